@@ -0,0 +1,254 @@
+//! Python bindings for the `zarrs_filter` pipeline runner.
+//!
+//! The `zarrs_tools` Python module exposes a [`Pipeline`] of filters, configured as in a `zarrs_filter` run configuration.
+
+use std::{ops::Range, path::PathBuf, sync::Mutex};
+
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use zarrs_tools::{
+    filter::{
+        read_pipeline, FilterCommand, FilterError, OutputExists, Pipeline as FilterPipeline,
+        PipelineObserver, PipelineOptions,
+    },
+    progress::{ProgressStage, ProgressStats},
+    storage::RemoteStorageArgs,
+    task::Task,
+};
+
+fn filter_error(err: FilterError) -> PyErr {
+    PyRuntimeError::new_err(err.to_string())
+}
+
+/// Convert `value` to a Python object through JSON.
+fn to_python<T: serde::Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let json =
+        serde_json::to_string(value).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+    Ok(py
+        .import_bound("json")?
+        .call_method1("loads", (json,))?
+        .unbind())
+}
+
+/// Convert a list of filters (dicts as in a run configuration) to filter commands through JSON.
+fn filter_commands(filters: &Bound<'_, PyAny>) -> PyResult<Vec<FilterCommand>> {
+    let json: String = filters
+        .py()
+        .import_bound("json")?
+        .call_method1("dumps", (filters,))?
+        .extract()?;
+    serde_json::from_str(&json).map_err(|err| PyValueError::new_err(err.to_string()))
+}
+
+/// Calls a Python progress callback with each progress event.
+///
+/// An exception raised by the callback is held until the run finishes, and then raised.
+struct ProgressCallback {
+    progress: Option<PyObject>,
+    error: Mutex<Option<PyErr>>,
+}
+
+impl PipelineObserver for ProgressCallback {
+    fn progress(&self, _filters: Range<usize>, stage: &ProgressStage, stats: ProgressStats) {
+        let Some(progress) = &self.progress else {
+            return;
+        };
+        Python::with_gil(|py| {
+            let result = to_python(py, &stage.event(&stats))
+                .and_then(|event| progress.call1(py, (event,)).map(|_| ()));
+            if let Err(err) = result {
+                self.error.lock().unwrap().get_or_insert(err);
+            }
+        });
+    }
+}
+
+/// A pipeline of filters, run in order with the outputs of earlier filters as the inputs of later filters.
+///
+/// `filters` is the path of a run configuration (YAML or JSON), or a list of filters as dicts, e.g.
+/// `[{"filter": "clamp", "input": "array.zarr", "output": "clamp.zarr", "min": 0, "max": 255}]`.
+/// The keyword arguments match the global options of `zarrs_filter`.
+#[pyclass(name = "Pipeline", module = "zarrs_tools")]
+struct Pipeline {
+    pipeline: FilterPipeline,
+}
+
+#[pymethods]
+impl Pipeline {
+    #[new]
+    #[pyo3(signature = (
+        filters,
+        *,
+        exists = "erase",
+        tmp = None,
+        chunk_limit = None,
+        fuse = false,
+        in_place = false,
+        run_async = false,
+        concurrent_chunks = None,
+        prefetch = None,
+        max_block_bytes = None,
+        adaptive = false,
+        deterministic = false,
+        resume = false,
+        task_index = None,
+        task_count = None,
+        worker = None,
+        cache_dir = None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        filters: &Bound<'_, PyAny>,
+        exists: &str,
+        tmp: Option<PathBuf>,
+        chunk_limit: Option<usize>,
+        fuse: bool,
+        in_place: bool,
+        run_async: bool,
+        concurrent_chunks: Option<usize>,
+        prefetch: Option<usize>,
+        max_block_bytes: Option<usize>,
+        adaptive: bool,
+        deterministic: bool,
+        resume: bool,
+        task_index: Option<usize>,
+        task_count: Option<usize>,
+        worker: Option<String>,
+        cache_dir: Option<PathBuf>,
+    ) -> PyResult<Self> {
+        let filter_commands = if let Ok(path) = filters.extract::<PathBuf>() {
+            read_pipeline(&path).map_err(filter_error)?
+        } else {
+            filter_commands(filters)?
+        };
+        let exists = match exists {
+            "erase" => OutputExists::Erase,
+            "exit" => OutputExists::Exit,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "invalid exists {exists}, expected erase or exit"
+                )))
+            }
+        };
+        let task = match (task_index, task_count) {
+            (Some(index), Some(count)) => Some(
+                Task::new(index, count).map_err(|err| PyValueError::new_err(err.to_string()))?,
+            ),
+            (None, None) => None,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "task_index and task_count must be set together",
+                ))
+            }
+        };
+        let mut remote = RemoteStorageArgs::default();
+        remote.cache_dir = cache_dir;
+        let options = PipelineOptions {
+            exists,
+            tmp,
+            chunk_limit,
+            fuse,
+            in_place,
+            run_async,
+            concurrent_chunks,
+            prefetch,
+            max_block_bytes,
+            adaptive,
+            deterministic,
+            resume,
+            task,
+            worker,
+            remote,
+        };
+        let pipeline = FilterPipeline::new(filter_commands, options).map_err(filter_error)?;
+        Ok(Self { pipeline })
+    }
+
+    /// The stages that are run, as `(start, end)` ranges of filter indices.
+    fn stages(&self) -> Vec<(usize, usize)> {
+        self.pipeline
+            .stages()
+            .into_iter()
+            .map(|stage| (stage.start, stage.end))
+            .collect()
+    }
+
+    /// Plan the memory per chunk, chunk limit, and output size of each filter without reading or writing any chunks.
+    ///
+    /// Returns a dict per filter.
+    fn dry_run<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let plans = py
+            .allow_threads(|| self.pipeline.dry_run())
+            .map_err(filter_error)?;
+        plans
+            .into_iter()
+            .map(|plan| {
+                let dict = PyDict::new_bound(py);
+                dict.set_item("name", plan.name)?;
+                dict.set_item("args", plan.args)?;
+                dict.set_item("encode", plan.encode)?;
+                dict.set_item("input", plan.input)?;
+                dict.set_item("input_data_type", plan.input_data_type.to_string())?;
+                dict.set_item("input_shape", plan.input_shape)?;
+                dict.set_item("output", plan.output)?;
+                dict.set_item("output_temporary", plan.output_temporary)?;
+                dict.set_item("output_data_type", plan.output_data_type.to_string())?;
+                dict.set_item("output_shape", plan.output_shape)?;
+                dict.set_item("memory_per_chunk", plan.memory_per_chunk)?;
+                dict.set_item("chunk_limit", plan.chunk_limit.ok())?;
+                dict.set_item("output_chunks", plan.output_chunks)?;
+                dict.set_item("output_size", plan.output_size)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// Verify that each of `task_count` tasks of an array job has written its chunks of every output, then remove their checkpoints.
+    fn finalize_tasks(&self, py: Python<'_>, task_count: usize) -> PyResult<()> {
+        py.allow_threads(|| self.pipeline.finalize_tasks(task_count))
+            .map_err(filter_error)
+    }
+
+    /// Hand out the chunks of the output to workers (pipelines created with `worker`) connecting to `address` in ranges of up to `batch` chunks, until every chunk has been written.
+    ///
+    /// The GIL is released while the workers are served.
+    #[pyo3(signature = (address, batch = 16))]
+    fn coordinate(&self, py: Python<'_>, address: &str, batch: u64) -> PyResult<()> {
+        py.allow_threads(|| self.pipeline.coordinate(address, batch))
+            .map_err(filter_error)
+    }
+
+    /// Run the pipeline, returning the timing and throughput report of each stage as a dict.
+    ///
+    /// `progress` is called with a dict of each progress event of a stage, as written by `zarrs_filter --progress-format json`.
+    /// The GIL is released while the pipeline runs.
+    #[pyo3(signature = (progress = None))]
+    fn run(&self, py: Python<'_>, progress: Option<PyObject>) -> PyResult<PyObject> {
+        let progress = ProgressCallback {
+            progress,
+            error: Mutex::new(None),
+        };
+        let reports = py.allow_threads(|| self.pipeline.run(&progress));
+        if let Some(err) = progress.error.into_inner().unwrap() {
+            return Err(err);
+        }
+        to_python(py, &reports.map_err(filter_error)?)
+    }
+}
+
+/// Set the process-wide memory limit in bytes shared by every filter, or remove it with `None`.
+#[pyfunction]
+fn set_memory_limit(memory_limit: Option<usize>) {
+    zarrs_tools::filter::set_memory_limit(memory_limit);
+}
+
+#[pymodule]
+#[pyo3(name = "zarrs_tools")]
+fn zarrs_tools_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Pipeline>()?;
+    m.add_function(wrap_pyfunction!(set_memory_limit, m)?)?;
+    Ok(())
+}
@@ -12,14 +12,18 @@ use zarrs::{
     },
     array_subset::ArraySubset,
     config::global_config,
-    storage::{store, AsyncReadableStorage},
+    storage::AsyncReadableStorage,
+};
+use zarrs_tools::{
+    storage::{async_preload_storage, async_storage_from_uri},
+    threads::{num_threads, tokio_runtime, ThreadArgs},
 };
 
 /// Benchmark zarrs read throughput with the async API.
 #[derive(Parser, Debug)]
 #[command(author, version)]
 struct Args {
-    /// The zarr array directory.
+    /// The path, URL, or object store URI (`s3://`, `gs://`, `az://`) of a zarr array.
     path: String,
 
     /// Number of concurrent chunks.
@@ -37,36 +41,31 @@ struct Args {
     /// If set, checksum validation in codecs (e.g. crc32c) is skipped.
     #[arg(long, default_value_t = false)]
     ignore_checksums: bool,
+
+    /// Load the entire store into memory before benchmarking.
+    ///
+    /// If set, codec decode throughput is measured without storage effects.
+    #[arg(long, default_value_t = false)]
+    preload: bool,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    args.threads.init()?;
+    tokio_runtime()?.block_on(run(args))
+}
 
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     zarrs::config::global_config_mut().set_validate_checksums(!args.ignore_checksums);
 
-    let storage: AsyncReadableStorage = if args.path.starts_with("http") {
-        // opendal
-        let builder = opendal::services::Http::default().endpoint(&args.path);
-        let operator = opendal::Operator::new(builder)?.finish();
-        Arc::new(store::AsyncOpendalStore::new(operator))
-
-        // object_store
-        // let options = object_store::ClientOptions::new().with_allow_http(true);
-        // let store = object_store::http::HttpBuilder::new()
-        //     .with_url(&args.path)
-        //     .with_client_options(options)
-        //     .build()?;
-        // Arc::new(store::AsyncObjectStore::new(store))
+    let storage = async_storage_from_uri(&args.path)?;
+    let storage: AsyncReadableStorage = if args.preload {
+        async_preload_storage(&*storage).await?
     } else {
-        // opendal
-        let builder = opendal::services::Fs::default().root(&args.path);
-        let operator = opendal::Operator::new(builder)?.finish();
-        Arc::new(store::AsyncOpendalStore::new(operator))
-
-        // object_store
-        // let store = object_store::local::LocalFileSystem::new_with_prefix(&args.path)?;
-        // Arc::new(store::AsyncObjectStore::new(store))
+        storage
     };
 
     let array = Arc::new(zarrs::array::Array::async_open(storage.clone(), "/").await?);
@@ -86,7 +85,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         // Calculate chunk/codec concurrency
         let chunk_representation =
             array.chunk_array_representation(&vec![0; array.chunk_grid().dimensionality()])?;
-        let concurrent_target = std::thread::available_parallelism().unwrap().get();
+        let concurrent_target = num_threads();
         let (chunk_concurrent_limit, codec_concurrent_target) =
             zarrs::array::concurrency::calc_concurrency_outer_inner(
                 concurrent_target,
@@ -12,19 +12,26 @@ use zarrs::{
     },
     array_subset::ArraySubset,
     config::global_config,
-    storage::{
-        storage_adapter::async_to_sync::{AsyncToSyncBlockOn, AsyncToSyncStorageAdapter},
-        store, AsyncReadableStorage, ReadableStorage,
-    },
+    storage::{storage_adapter::async_to_sync::AsyncToSyncStorageAdapter, ReadableStorage},
+};
+use zarrs_tools::{
+    storage::{async_storage_from_uri, preload_storage, RemoteStorageArgs, TokioBlockOn},
+    threads::{num_threads, tokio_runtime, ThreadArgs},
 };
 
 /// Benchmark zarrs read throughput with the sync API.
 #[derive(Parser, Debug)]
 #[command(author, version)]
 struct Args {
-    /// The path or URL of a zarr array.
+    /// The path, URL, or object store URI (`s3://`, `gs://`, `az://`) of a zarr array.
     path: String,
 
+    #[command(flatten)]
+    remote: RemoteStorageArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
+
     /// Number of concurrent chunks.
     #[arg(long)]
     concurrent_chunks: Option<usize>,
@@ -40,46 +47,28 @@ struct Args {
     /// If set, checksum validation in codecs (e.g. crc32c) is skipped.
     #[arg(long, default_value_t = false)]
     ignore_checksums: bool,
-}
-
-struct TokioBlockOn(tokio::runtime::Runtime);
 
-impl AsyncToSyncBlockOn for TokioBlockOn {
-    fn block_on<F: core::future::Future>(&self, future: F) -> F::Output {
-        self.0.block_on(future)
-    }
+    /// Load the entire store into memory before benchmarking.
+    ///
+    /// If set, codec decode throughput is measured without storage effects.
+    #[arg(long, default_value_t = false)]
+    preload: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    args.threads.init()?;
 
-    let storage: AsyncReadableStorage = if args.path.starts_with("http") {
-        // opendal
-        let builder = opendal::services::Http::default().endpoint(&args.path);
-        let operator = opendal::Operator::new(builder)?.finish();
-        Arc::new(store::AsyncOpendalStore::new(operator))
-
-        // object_store
-        // let options = object_store::ClientOptions::new().with_allow_http(true);
-        // let store = object_store::http::HttpBuilder::new()
-        //     .with_url(&args.path)
-        //     .with_client_options(options)
-        //     .build()?;
-        // Arc::new(store::AsyncObjectStore::new(store))
+    let storage = async_storage_from_uri(&args.path)?;
+
+    let block_on = TokioBlockOn(tokio_runtime()?);
+    let storage = Arc::new(AsyncToSyncStorageAdapter::new(storage, block_on));
+    let storage: ReadableStorage = if args.preload {
+        preload_storage(&*storage)?
     } else {
-        // opendal
-        let builder = opendal::services::Fs::default().root(&args.path);
-        let operator = opendal::Operator::new(builder)?.finish();
-        Arc::new(store::AsyncOpendalStore::new(operator))
-
-        // object_store
-        // let store = object_store::local::LocalFileSystem::new_with_prefix(&args.path)?;
-        // Arc::new(store::AsyncObjectStore::new(store))
+        args.remote.readable_storage(&args.path, storage)?
     };
 
-    let block_on = TokioBlockOn(tokio::runtime::Runtime::new()?);
-    let storage: ReadableStorage = Arc::new(AsyncToSyncStorageAdapter::new(storage, block_on));
-
     let array = zarrs::array::Array::open(storage.clone(), "/")?;
     // println!("{:#?}", array.metadata());
 
@@ -95,7 +84,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         let chunk_representation =
             array.chunk_array_representation(&vec![0; array.chunk_grid().dimensionality()])?;
-        let concurrent_target = std::thread::available_parallelism().unwrap().get();
+        let concurrent_target = num_threads();
         let (chunks_concurrent_limit, codec_concurrent_target) =
             zarrs::array::concurrency::calc_concurrency_outer_inner(
                 concurrent_target,
@@ -1,7 +1,4 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::SystemTime,
-};
+use std::{sync::Mutex, time::SystemTime};
 
 use clap::Parser;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -12,16 +9,27 @@ use zarrs::{
     },
     array_subset::ArraySubset,
     config::global_config,
-    storage::{store, ReadableStorage},
+    storage::ReadableStorage,
+};
+use zarrs_tools::{
+    filter::{element_size, AdaptiveConcurrency},
+    storage::{preload_storage, RemoteStorageArgs},
+    threads::{num_threads, ThreadArgs},
 };
 
 /// Benchmark zarrs read throughput with the sync API.
 #[derive(Parser, Debug)]
 #[command(author, version)]
 struct Args {
-    /// The path or URL of a zarr array.
+    /// The path, URL, or object store URI (`s3://`, `gs://`, `az://`) of a zarr array.
     path: String,
 
+    #[command(flatten)]
+    remote: RemoteStorageArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
+
     /// Number of concurrent chunks.
     #[arg(long)]
     concurrent_chunks: Option<usize>,
@@ -37,19 +45,30 @@ struct Args {
     /// If set, checksum validation in codecs (e.g. crc32c) is skipped.
     #[arg(long, default_value_t = false)]
     ignore_checksums: bool,
+
+    /// Load the entire store into memory before benchmarking.
+    ///
+    /// If set, codec decode throughput is measured without storage effects.
+    #[arg(long, default_value_t = false)]
+    preload: bool,
+
+    /// Adapt the number of concurrent chunks to the observed throughput.
+    ///
+    /// Starts with one chunk at a time, up to `concurrent_chunks` or the recommended concurrency.
+    #[arg(long, default_value_t = false)]
+    adaptive: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    args.threads.init()?;
 
-    // opendal
-    // let mut builder = opendal::services::Fs::default();
-    // builder.root(&args.path);
-    // let operator = opendal::Operator::new(builder)?.finish().blocking();
-    // let storage: ReadableStorage = Arc::new(store::OpendalStore::new(operator));
-
-    // Default filesystem store
-    let storage: ReadableStorage = Arc::new(store::FilesystemStore::new(args.path.clone())?);
+    let storage = args.remote.storage_from_uri(&args.path)?;
+    let storage: ReadableStorage = if args.preload {
+        preload_storage(&*storage)?
+    } else {
+        storage
+    };
 
     let array = zarrs::array::Array::open(storage.clone(), "/")?;
     // println!("{:#?}", array.metadata());
@@ -66,7 +85,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     } else {
         let chunk_representation =
             array.chunk_array_representation(&vec![0; array.chunk_grid().dimensionality()])?;
-        let concurrent_target = std::thread::available_parallelism().unwrap().get();
+        let concurrent_target = num_threads();
         let (chunks_concurrent_limit, codec_concurrent_target) =
             zarrs::array::concurrency::calc_concurrency_outer_inner(
                 concurrent_target,
@@ -93,19 +112,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let n_chunks = usize::try_from(chunks.shape().iter().product::<u64>()).unwrap();
         // NOTE: Could init memory per split with for_each_init and then reuse it with retrieve_chunk_into_array_view_opt.
         //       But that might be cheating against tensorstore.
-        rayon_iter_concurrent_limit::iter_concurrent_limit!(
-            chunks_concurrent_limit,
-            0..n_chunks,
-            for_each,
-            |chunk_index: usize| {
-                let chunk_indices = zarrs::array::unravel_index(chunk_index as u64, chunks.shape());
-                // println!("Chunk/shard: {:?}", chunk_indices);
-                let bytes = array
-                    .retrieve_chunk_opt(&chunk_indices, &codec_options)
-                    .unwrap();
-                *bytes_decoded.lock().unwrap() += bytes.size();
-            }
-        );
+        let retrieve_chunk = |chunk_index: usize| {
+            let chunk_indices = zarrs::array::unravel_index(chunk_index as u64, chunks.shape());
+            // println!("Chunk/shard: {:?}", chunk_indices);
+            let bytes = array
+                .retrieve_chunk_opt(&chunk_indices, &codec_options)
+                .unwrap();
+            let size = bytes.size();
+            *bytes_decoded.lock().unwrap() += size;
+            size
+        };
+        if args.adaptive {
+            let controller = AdaptiveConcurrency::new(
+                chunks_concurrent_limit,
+                chunk_representation.num_elements_usize() * element_size(&chunk_representation),
+            );
+            // Permits are acquired outside of the rayon threads, which may block on nested work
+            rayon::in_place_scope(|scope| {
+                for chunk_index in 0..n_chunks {
+                    let permit = controller.acquire();
+                    let retrieve_chunk = &retrieve_chunk;
+                    scope.spawn(move |_| permit.finish(retrieve_chunk(chunk_index) as u64));
+                }
+            });
+            println!("Adaptive concurrent chunks: {}", controller.concurrency());
+        } else {
+            rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                chunks_concurrent_limit,
+                0..n_chunks,
+                for_each,
+                |chunk_index: usize| {
+                    retrieve_chunk(chunk_index);
+                }
+            );
+        }
     }
     let bytes_decoded = bytes_decoded.into_inner()?;
     let duration = SystemTime::now().duration_since(start)?.as_secs_f32();
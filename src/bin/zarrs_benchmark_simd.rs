@@ -0,0 +1,124 @@
+use std::time::Instant;
+
+use clap::Parser;
+use rayon::{
+    iter::{IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+use zarrs_tools::{
+    filter::{simd_avx2, simd_clamp, simd_map, simd_min_max},
+    threads::ThreadArgs,
+};
+
+/// Benchmark the vectorised per-element kernels of zarrs_filter against plain rayon iterators.
+///
+/// Each kernel is run on `float32` and `uint16` elements, and the best of `repeats` runs is reported.
+/// The kernels rely on autovectorisation, so this measures what the compiler made of them on this CPU.
+#[derive(Parser)]
+#[command(author, version)]
+struct Args {
+    #[command(flatten)]
+    threads: ThreadArgs,
+
+    /// The number of elements.
+    #[arg(long, default_value_t = 1 << 26)]
+    elements: usize,
+
+    /// The number of runs of each kernel.
+    #[arg(long, default_value_t = 10)]
+    repeats: usize,
+}
+
+/// Returns the best duration in seconds of `repeats` runs of `kernel`.
+fn best_of(repeats: usize, mut kernel: impl FnMut()) -> f32 {
+    (0..repeats)
+        .map(|_| {
+            let start = Instant::now();
+            kernel();
+            start.elapsed().as_secs_f32()
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Print the throughput of the vectorised and the plain rayon `kernel` over `bytes`.
+fn print_throughput(kernel: &str, bytes: usize, simd: f32, rayon: f32) {
+    let throughput = |duration: f32| (/* GB */bytes as f32 * 1e-9) / duration;
+    println!(
+        "{kernel:<16} simd {:>8.2}GB/s  rayon {:>8.2}GB/s  speedup {:.2}x",
+        throughput(simd),
+        throughput(rayon),
+        rayon / simd
+    );
+}
+
+fn benchmark<T>(args: &Args, name: &str, input: &[T], min: T, max: T)
+where
+    T: Copy + Send + Sync + PartialOrd + num_traits::AsPrimitive<f64>,
+{
+    let bytes = std::mem::size_of_val(input);
+
+    let simd = best_of(args.repeats, || {
+        std::hint::black_box(simd_map(input, |value| value > min));
+    });
+    let rayon = best_of(args.repeats, || {
+        std::hint::black_box(
+            input
+                .par_iter()
+                .map(|value| *value > min)
+                .collect::<Vec<_>>(),
+        );
+    });
+    print_throughput(&format!("{name} map"), bytes, simd, rayon);
+
+    let mut elements = input.to_vec();
+    let simd = best_of(args.repeats, || {
+        elements.copy_from_slice(input);
+        simd_clamp(&mut elements, min, max);
+    });
+    let rayon = best_of(args.repeats, || {
+        elements.copy_from_slice(input);
+        elements
+            .par_iter_mut()
+            .for_each(|value| *value = num_traits::clamp(*value, min, max));
+    });
+    print_throughput(&format!("{name} clamp"), bytes, simd, rayon);
+
+    let simd = best_of(args.repeats, || {
+        std::hint::black_box(simd_min_max(input, |value| value.as_()));
+    });
+    let rayon = best_of(args.repeats, || {
+        std::hint::black_box(
+            input
+                .par_chunks(1 << 16)
+                .map(|block| {
+                    block
+                        .iter()
+                        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+                            let value: f64 = value.as_();
+                            (min.min(value), max.max(value))
+                        })
+                })
+                .reduce(
+                    || (f64::INFINITY, f64::NEG_INFINITY),
+                    |(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)),
+                ),
+        );
+    });
+    print_throughput(&format!("{name} min_max"), bytes, simd, rayon);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    args.threads.init()?;
+
+    println!("AVX2: {}", simd_avx2());
+    let input = (0..args.elements)
+        .map(|i| (i % 1000) as f32 - 500.0)
+        .collect::<Vec<_>>();
+    benchmark(&args, "float32", &input, -10.0, 10.0);
+    let input = (0..args.elements)
+        .map(|i| (i % 1000) as u16)
+        .collect::<Vec<_>>();
+    benchmark(&args, "uint16", &input, 10, 500);
+    Ok(())
+}
@@ -0,0 +1,509 @@
+use std::{
+    borrow::Cow,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime},
+};
+
+use clap::{Parser, ValueEnum};
+use futures::StreamExt;
+use half::{bf16, f16};
+use num_traits::AsPrimitive;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use zarrs::{
+    array::{
+        codec::{ArrayCodecTraits, CodecOptions, CodecOptionsBuilder},
+        concurrency::RecommendedConcurrency,
+        data_type::UnsupportedDataTypeError,
+        Array, ArrayBuilder, ArrayBytes, DataType,
+    },
+    array_subset::ArraySubset,
+    config::global_config,
+    metadata::Metadata,
+};
+use zarrs_tools::{
+    get_array_builder,
+    storage::{async_storage_from_uri, is_uri, storage_from_uri},
+    threads::{num_threads, tokio_runtime, ThreadArgs},
+    ZarrEncodingArgs,
+};
+
+/// The synthetic data written to each chunk.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    /// Every element is zero.
+    Zeros,
+    /// Random bytes, seeded by the chunk index so every chunk differs.
+    Random,
+    /// The sum of the indices of each element within the chunk.
+    Gradient,
+}
+
+/// Benchmark zarrs encode and write throughput with the sync or async API.
+///
+/// The data of a chunk is generated before benchmarking and written to every chunk, so only encoding and writing is measured.
+/// Random chunks differ, so they are generated while writing and their generation is included in the measurement.
+/// With `--round-trip`, the array is then read back and verified.
+#[derive(Parser)]
+#[command(author, version)]
+#[allow(rustdoc::bare_urls)]
+struct Args {
+    /// The path or URI of the output zarr array.
+    path: String,
+
+    #[command(flatten)]
+    encoding: ZarrEncodingArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
+
+    /// Zarr data type. See https://zarr-specs.readthedocs.io/en/latest/v3/core/v3.0.html#id11
+    ///
+    /// Examples:
+    ///   int8 int16 int32 int64
+    ///   uint8 uint16 uint32 uint64
+    ///   float32 float64 float16 bfloat16
+    #[arg(short, long, verbatim_doc_comment)]
+    data_type: String,
+
+    /// Array shape. A comma separated list of the sizes of each array dimension.
+    #[arg(short, long, required = true, value_delimiter = ',')]
+    array_shape: Vec<u64>,
+
+    /// The synthetic data written to each chunk.
+    #[arg(long, value_enum, default_value_t = Pattern::Random)]
+    pattern: Pattern,
+
+    /// Number of concurrent chunks.
+    #[arg(long)]
+    concurrent_chunks: Option<usize>,
+
+    /// Write chunks with the async API.
+    #[arg(long = "async", default_value_t = false)]
+    run_async: bool,
+
+    /// Read the array back and verify its chunks after writing it, reporting the read and verify throughput.
+    ///
+    /// The pages of a local array are flushed and evicted from the page cache before reading (Linux only), so the chunks are read from the storage.
+    /// The read time includes verifying the chunks, and the verify time is summed over threads.
+    #[arg(long, default_value_t = false)]
+    round_trip: bool,
+}
+
+/// Returns the bytes of chunk `chunk_index` of `chunk_shape` with elements of `data_type` following `pattern`.
+fn chunk_bytes(
+    pattern: Pattern,
+    data_type: &DataType,
+    chunk_shape: &[u64],
+    chunk_index: u64,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let data_type_size = data_type
+        .fixed_size()
+        .ok_or_else(|| UnsupportedDataTypeError::from(data_type.to_string()))?;
+    let chunk_subset = ArraySubset::new_with_shape(chunk_shape.to_vec());
+    let num_elements = chunk_subset.num_elements_usize();
+    match pattern {
+        Pattern::Zeros => Ok(vec![0; num_elements * data_type_size]),
+        Pattern::Random => {
+            let mut bytes = vec![0; num_elements * data_type_size];
+            ChaCha8Rng::seed_from_u64(chunk_index).fill_bytes(&mut bytes);
+            Ok(bytes)
+        }
+        Pattern::Gradient => {
+            let indices = chunk_subset.indices();
+            let values = indices
+                .into_iter()
+                .map(|indices| indices.iter().sum::<u64>());
+            macro_rules! gradient {
+                ([$( ( $data_type:ident, $t:ty ) ),* ]) => {
+                    match data_type {
+                        $(DataType::$data_type => Ok(values
+                            .flat_map(|value| AsPrimitive::<$t>::as_(value).to_ne_bytes())
+                            .collect()),)*
+                        _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+                    }
+                };
+            }
+            gradient!([
+                (Int8, i8),
+                (Int16, i16),
+                (Int32, i32),
+                (Int64, i64),
+                (UInt8, u8),
+                (UInt16, u16),
+                (UInt32, u32),
+                (UInt64, u64),
+                (Float16, f16),
+                (BFloat16, bf16),
+                (Float32, f32),
+                (Float64, f64)
+            ])
+        }
+    }
+}
+
+/// The data written to each chunk of the array.
+struct ChunkData {
+    pattern: Pattern,
+    data_type: DataType,
+    chunk_shape: Vec<u64>,
+    /// The number of bytes in a chunk.
+    len: usize,
+    /// The bytes of every chunk, if they do not depend on the chunk index.
+    bytes: Option<Vec<u8>>,
+}
+
+impl ChunkData {
+    fn new(
+        pattern: Pattern,
+        data_type: DataType,
+        chunk_shape: Vec<u64>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let bytes = chunk_bytes(pattern, &data_type, &chunk_shape, 0)?;
+        Ok(Self {
+            pattern,
+            data_type,
+            chunk_shape,
+            len: bytes.len(),
+            bytes: (pattern != Pattern::Random).then_some(bytes),
+        })
+    }
+
+    /// The bytes of chunk `chunk_index`.
+    fn bytes(&self, chunk_index: u64) -> Cow<'_, [u8]> {
+        self.bytes.as_deref().map_or_else(
+            || {
+                Cow::Owned(
+                    chunk_bytes(
+                        self.pattern,
+                        &self.data_type,
+                        &self.chunk_shape,
+                        chunk_index,
+                    )
+                    .expect("the data type is supported"),
+                )
+            },
+            Cow::Borrowed,
+        )
+    }
+
+    /// The number of bytes in a chunk.
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Returns the number of concurrent chunks and the codec options of writing the chunks of `array`.
+fn concurrency<TStorage: ?Sized>(
+    array: &Array<TStorage>,
+    concurrent_chunks: Option<usize>,
+) -> Result<(usize, CodecOptions), Box<dyn std::error::Error>> {
+    let num_chunks =
+        ArraySubset::new_with_shape(array.chunk_grid_shape().unwrap()).num_elements_usize();
+    let chunk_representation =
+        array.chunk_array_representation(&vec![0; array.chunk_grid().dimensionality()])?;
+    let (chunks_concurrent_limit, codec_concurrent_target) =
+        zarrs::array::concurrency::calc_concurrency_outer_inner(
+            num_threads(),
+            &if let Some(concurrent_chunks) = concurrent_chunks {
+                let concurrent_chunks = std::cmp::min(num_chunks, concurrent_chunks);
+                RecommendedConcurrency::new(concurrent_chunks..concurrent_chunks)
+            } else {
+                let concurrent_chunks =
+                    std::cmp::min(num_chunks, global_config().chunk_concurrent_minimum());
+                RecommendedConcurrency::new_minimum(concurrent_chunks)
+            },
+            &array
+                .codecs()
+                .recommended_concurrency(&chunk_representation)?,
+        );
+    let codec_options = CodecOptionsBuilder::new()
+        .concurrent_target(codec_concurrent_target)
+        .build();
+    Ok((chunks_concurrent_limit, codec_options))
+}
+
+/// Write `data` to every chunk of the array built by `array_builder` with the sync API.
+///
+/// Returns the number of bytes encoded.
+fn write_sync(
+    args: &Args,
+    array_builder: &ArrayBuilder,
+    data: &ChunkData,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let array = array_builder.build(storage_from_uri(&args.path)?, "/")?;
+    array.store_metadata()?;
+    let (chunks_concurrent_limit, codec_options) = concurrency(&array, args.concurrent_chunks)?;
+    let chunks = ArraySubset::new_with_shape(array.chunk_grid_shape().unwrap());
+    let n_chunks = chunks.num_elements_usize();
+    let store_chunk = |chunk_index: usize| {
+        let chunk_indices = zarrs::array::unravel_index(chunk_index as u64, chunks.shape());
+        array
+            .store_chunk_opt(
+                &chunk_indices,
+                &*data.bytes(chunk_index as u64),
+                &codec_options,
+            )
+            .unwrap();
+    };
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(
+        chunks_concurrent_limit,
+        0..n_chunks,
+        for_each,
+        store_chunk
+    );
+    Ok(n_chunks * data.len())
+}
+
+/// Write `data` to every chunk of the array built by `array_builder` with the async API.
+///
+/// Returns the number of bytes encoded.
+async fn write_async(
+    args: &Args,
+    array_builder: &ArrayBuilder,
+    data: Arc<ChunkData>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let array = Arc::new(array_builder.build(async_storage_from_uri(&args.path)?, "/")?);
+    array.async_store_metadata().await?;
+    let (chunks_concurrent_limit, codec_options) = concurrency(&*array, args.concurrent_chunks)?;
+    let chunks = ArraySubset::new_with_shape(array.chunk_grid_shape().unwrap());
+    let n_chunks = chunks.num_elements_usize();
+    let indices = chunks.indices();
+    let futures = indices
+        .into_iter()
+        .enumerate()
+        .map(|(chunk_index, chunk_indices)| {
+            let array = array.clone();
+            let codec_options = codec_options.clone();
+            let data = data.clone();
+            async move {
+                let bytes = data.bytes(chunk_index as u64);
+                array
+                    .async_store_chunk_opt(&chunk_indices, &*bytes, &codec_options)
+                    .await
+            }
+        })
+        .map(tokio::task::spawn);
+    let mut stream = futures::stream::iter(futures).buffer_unordered(chunks_concurrent_limit);
+    while let Some(item) = stream.next().await {
+        item??;
+    }
+    Ok(n_chunks * data.len())
+}
+
+/// The time spent verifying chunks, and the number of chunks that do not match the written data.
+#[derive(Default)]
+struct Verification {
+    nanos: AtomicU64,
+    mismatches: AtomicUsize,
+}
+
+impl Verification {
+    /// Verify that `chunk_bytes` read from a chunk match the `expected` bytes written to it.
+    fn verify(&self, expected: &[u8], chunk_bytes: ArrayBytes<'_>) {
+        let start = Instant::now();
+        let matches = chunk_bytes
+            .into_fixed()
+            .is_ok_and(|chunk_bytes| *chunk_bytes == *expected);
+        self.nanos.fetch_add(
+            u64::try_from(start.elapsed().as_nanos()).unwrap(),
+            Ordering::Relaxed,
+        );
+        if !matches {
+            self.mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Read every chunk of the array at `args.path` with the sync API, verifying that it matches `data`.
+///
+/// Returns the number of bytes decoded.
+fn read_sync(
+    args: &Args,
+    data: &ChunkData,
+    verification: &Verification,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let array = Array::open(storage_from_uri(&args.path)?, "/")?;
+    let (chunks_concurrent_limit, codec_options) = concurrency(&array, args.concurrent_chunks)?;
+    let chunks = ArraySubset::new_with_shape(array.chunk_grid_shape().unwrap());
+    let n_chunks = chunks.num_elements_usize();
+    let retrieve_chunk = |chunk_index: usize| {
+        let chunk_indices = zarrs::array::unravel_index(chunk_index as u64, chunks.shape());
+        let chunk_bytes = array
+            .retrieve_chunk_opt(&chunk_indices, &codec_options)
+            .unwrap();
+        verification.verify(&data.bytes(chunk_index as u64), chunk_bytes);
+    };
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(
+        chunks_concurrent_limit,
+        0..n_chunks,
+        for_each,
+        retrieve_chunk
+    );
+    Ok(n_chunks * data.len())
+}
+
+/// Read every chunk of the array at `args.path` with the async API, verifying that it matches `data`.
+///
+/// Returns the number of bytes decoded.
+async fn read_async(
+    args: &Args,
+    data: Arc<ChunkData>,
+    verification: Arc<Verification>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let array = Arc::new(Array::async_open(async_storage_from_uri(&args.path)?, "/").await?);
+    let (chunks_concurrent_limit, codec_options) = concurrency(&*array, args.concurrent_chunks)?;
+    let chunks = ArraySubset::new_with_shape(array.chunk_grid_shape().unwrap());
+    let n_chunks = chunks.num_elements_usize();
+    let indices = chunks.indices();
+    let futures = indices
+        .into_iter()
+        .enumerate()
+        .map(|(chunk_index, chunk_indices)| {
+            let array = array.clone();
+            let codec_options = codec_options.clone();
+            let data = data.clone();
+            let verification = verification.clone();
+            async move {
+                let chunk_bytes = array
+                    .async_retrieve_chunk_opt(&chunk_indices, &codec_options)
+                    .await?;
+                verification.verify(&data.bytes(chunk_index as u64), chunk_bytes);
+                Ok::<_, zarrs::array::ArrayError>(())
+            }
+        })
+        .map(tokio::task::spawn);
+    let mut stream = futures::stream::iter(futures).buffer_unordered(chunks_concurrent_limit);
+    while let Some(item) = stream.next().await {
+        item??;
+    }
+    Ok(n_chunks * data.len())
+}
+
+/// Flush the files under `path` and evict them from the page cache.
+#[cfg(target_os = "linux")]
+fn drop_caches(path: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            drop_caches(&entry?.path())?;
+        }
+    } else {
+        let file = std::fs::File::open(path)?;
+        file.sync_all()?;
+        // SAFETY: the file descriptor is valid while the file is open
+        let err = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if err != 0 {
+            return Err(std::io::Error::from_raw_os_error(err));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_caches(_path: &Path) -> std::io::Result<()> {
+    println!("The page cache is not dropped on this platform");
+    Ok(())
+}
+
+/// Print the duration and throughput of `bytes` `processed` (e.g. "Encoded") in `duration` seconds.
+fn print_throughput(processed: &str, path: &str, duration: f32, bytes: usize) {
+    println!(
+        "{processed} {path} in {:.2}ms ({:.2}MB {} @ {:.2}GB/s)",
+        duration * 1e3,
+        bytes as f32 / 1e6,
+        processed.to_lowercase(),
+        (/* GB */bytes as f32 * 1e-9) / duration,
+    );
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    args.threads.init()?;
+    run(&args)
+}
+
+/// Write the array, and read it back and verify it if `args.round_trip`.
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let data_type = DataType::from_metadata(&Metadata::new(&args.data_type))?;
+    let array_builder =
+        get_array_builder(&args.encoding, &args.array_shape, data_type.clone(), None);
+    let chunk_shape = array_builder
+        .chunk_grid
+        .chunk_shape(&vec![0; args.array_shape.len()], &args.array_shape)?
+        .expect("lowest indices should have a chunk shape");
+    let chunk_shape = chunk_shape
+        .iter()
+        .map(|size| size.get())
+        .collect::<Vec<_>>();
+    let data = Arc::new(ChunkData::new(args.pattern, data_type, chunk_shape)?);
+    let runtime = tokio_runtime()?;
+
+    let start = SystemTime::now();
+    let bytes_encoded = if args.run_async {
+        runtime.block_on(write_async(args, &array_builder, data.clone()))?
+    } else {
+        write_sync(args, &array_builder, &data)?
+    };
+    let duration = SystemTime::now().duration_since(start)?.as_secs_f32();
+    print_throughput("Encoded", &args.path, duration, bytes_encoded);
+
+    if args.round_trip {
+        if !is_uri(&args.path) {
+            drop_caches(Path::new(&args.path))?;
+        }
+        let verification = Arc::new(Verification::default());
+        let start = SystemTime::now();
+        let bytes_decoded = if args.run_async {
+            runtime.block_on(read_async(args, data.clone(), verification.clone()))?
+        } else {
+            read_sync(args, &data, &verification)?
+        };
+        let duration = SystemTime::now().duration_since(start)?.as_secs_f32();
+        print_throughput("Decoded", &args.path, duration, bytes_decoded);
+        let duration = verification.nanos.load(Ordering::Relaxed) as f32 * 1e-9;
+        print_throughput("Verified", &args.path, duration, bytes_decoded);
+        let mismatches = verification.mismatches.load(Ordering::Relaxed);
+        if mismatches > 0 {
+            return Err(format!("{mismatches} chunks do not match the written data").into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        for pattern in ["zeros", "random", "gradient"] {
+            for run_async in [false, true] {
+                let path = dir.path().join(format!("{pattern}_{run_async}.zarr"));
+                let path = path.to_str().unwrap();
+                let mut argv = vec![
+                    "zarrs_benchmark_write",
+                    path,
+                    "--data-type=uint16",
+                    "--array-shape=5,6",
+                    "--chunk-shape=2,4",
+                    "--fill-value=0",
+                    "--pattern",
+                    pattern,
+                    "--round-trip",
+                ];
+                if run_async {
+                    argv.push("--async");
+                }
+                run(&Args::try_parse_from(argv)?)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -3,7 +3,12 @@ use indicatif::{DecimalBytes, ProgressBar, ProgressStyle};
 use rayon::{iter::ParallelIterator, prelude::IntoParallelIterator};
 use rayon_iter_concurrent_limit::iter_concurrent_limit;
 use std::{io::Read, path::PathBuf, sync::atomic::AtomicUsize};
-use zarrs_tools::{get_array_builder, ZarrEncodingArgs};
+use zarrs_tools::{
+    get_array_builder,
+    storage::{storage_from_uri, Storage},
+    threads::{num_threads, ThreadArgs},
+    ZarrEncodingArgs,
+};
 
 use zarrs::{
     array::{
@@ -17,7 +22,7 @@ use zarrs::{
     array_subset::ArraySubset,
     config::global_config,
     metadata::Metadata,
-    storage::{store::FilesystemStore, ListableStorageTraits},
+    storage::ListableStorageTraits,
 };
 
 /// Convert an N-dimensional binary array from standard input to a Zarr V3 array.
@@ -32,6 +37,9 @@ struct Cli {
     #[command(flatten)]
     encoding: ZarrEncodingArgs,
 
+    #[command(flatten)]
+    threads: ThreadArgs,
+
     /// Number of concurrent chunk writers.
     #[arg(long)]
     concurrent_chunks: Option<usize>,
@@ -77,7 +85,7 @@ fn parse_endianness(endianness: &str) -> std::io::Result<Endianness> {
 }
 
 fn stdin_to_array(
-    array: &Array<FilesystemStore>,
+    array: &Array<Storage>,
     endianness: Option<Endianness>,
     concurrent_chunks: Option<usize>,
 ) -> usize {
@@ -107,7 +115,7 @@ fn stdin_to_array(
     let chunk_representation = array
         .chunk_array_representation(&vec![0; array.chunk_grid().dimensionality()])
         .unwrap();
-    let concurrent_target = std::thread::available_parallelism().unwrap().get();
+    let concurrent_target = num_threads();
     let (concurrent_chunks, codec_concurrent_target) =
         zarrs::array::concurrency::calc_concurrency_outer_inner(
             concurrent_target,
@@ -172,13 +180,14 @@ fn stdin_to_array(
 fn main() {
     // Parse and validate arguments
     let cli = Cli::parse();
+    cli.threads.init().unwrap();
 
     // Get data type
     let data_type = zarrs::array::DataType::from_metadata(&Metadata::new(&cli.data_type)).unwrap();
 
     // Create storage
     let path_out = cli.out.as_path();
-    let store = std::sync::Arc::new(FilesystemStore::new(path_out).unwrap());
+    let store = storage_from_uri(&path_out.to_string_lossy()).unwrap();
 
     // Create array
     let dimension_names = cli
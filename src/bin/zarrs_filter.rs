@@ -1,35 +1,24 @@
 use std::{
-    collections::HashMap,
     error::Error,
+    ops::Range,
     path::{Path, PathBuf},
-    sync::Arc,
 };
 
 use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use itertools::Itertools;
-use tempfile::TempDir;
-use zarrs::{
-    array::{Array, ArrayBuilder, ArrayCreateError},
-    storage::{store::FilesystemStore, StorageError, StorePrefix, WritableStorageTraits},
-};
 use zarrs_tools::{
     filter::{
-        FilterCommand, FilterCommandTraits, FilterError, FilterTraits, PathOrIdentifier,
-        PathOrTempPath,
+        parse_memory_size, read_pipeline, set_device, set_memory_limit, Device, FilterCommand,
+        FilterCommandTraits, OutputExists, Pipeline, PipelineObserver, PipelineOptions,
     },
-    progress::{ProgressCallback, ProgressStats},
-    ZarrReencodingArgs,
+    interrupt::{is_interrupted, EXIT_CODE_INTERRUPTED},
+    progress::{ProgressEventWriter, ProgressFormat, ProgressStage, ProgressStats, StageReport},
+    storage::RemoteStorageArgs,
+    task::TaskArgs,
+    threads::ThreadArgs,
 };
 
-#[derive(clap::ValueEnum, Debug, Clone)]
-enum OutputExists {
-    /// Erase the output
-    Erase,
-    /// Exit if the output already exists
-    Exit,
-}
-
 /// Apply simple image filters (transformations) to a Zarr V3 array.
 #[derive(Parser, Debug)]
 #[command(author, version)]
@@ -52,9 +41,138 @@ struct Cli {
     #[arg(long)]
     pub chunk_limit: Option<usize>,
 
-    /// Path to a JSON run configuration.
+    /// The maximum memory used by the chunks being processed, e.g. `32G` or `512M`.
+    ///
+    /// By default, this is 80% of the available memory.
+    /// Filters without a chunk limit derive it from this budget and their memory per chunk, and chunks in flight with `--async` are reduced to fit their buffers in it.
+    #[arg(long, value_parser = parse_memory_size)]
+    pub memory_limit: Option<usize>,
+
+    /// The device of compute-heavy filters (gaussian, unsharp, separable convolve).
+    ///
+    /// `auto` uses the GPU if one is available (requires the `gpu` feature), and the CPU otherwise.
+    /// All other filters run on the CPU, and `gpu` warns about each of them in the pipeline.
+    /// A chunk that cannot be processed on the GPU (e.g. it exceeds the GPU buffer size) falls back to the CPU.
+    #[arg(long, value_enum, default_value_t = Device::Auto)]
+    pub device: Device,
+
+    /// Fuse consecutive element-wise filters (e.g. reencode, cast, clamp, rescale).
+    ///
+    /// Each chunk is passed through all filters of a fused group in memory, so intermediate arrays are not written.
+    /// Only filters connected by a temporary intermediate array that is not used elsewhere are fused.
+    #[arg(long)]
+    pub fuse: bool,
+
+    /// Run filters with the same input and output in place.
+    ///
+    /// The output is written to the input array, rather than erasing it and creating a new array.
+    /// Only filters that read and write each chunk independently (e.g. clamp, replace_value) and do not change the array metadata can be run in place.
+    #[arg(long)]
+    pub in_place: bool,
+
+    /// Read and write chunks asynchronously.
+    ///
+    /// Element-wise filters (and fused groups) read and write chunks concurrently, overlapping the latency of remote stores.
+    /// Other filters are run synchronously.
+    #[arg(long = "async")]
+    pub run_async: bool,
+
+    /// The maximum number of chunks in flight with `--async`.
+    ///
+    /// By default, this is the chunk limit.
+    /// Chunks are processed on the CPUs independently of this, so it can be increased to hide the latency of remote stores.
+    #[arg(long, requires = "run_async")]
+    pub concurrent_chunks: Option<usize>,
+
+    /// Read ahead this many chunks in a pipeline of reads, processing, and writes.
+    ///
+    /// Element-wise filters (and fused groups) read the next chunks while the current chunks are processed and written.
+    /// Other filters, and filters run with `--async`, are unaffected.
+    #[arg(long)]
+    pub prefetch: Option<usize>,
+
+    /// The maximum size of a block that chunks of element-wise filters are processed in (e.g. 64M).
+    ///
+    /// Chunks larger than this are read and processed in blocks rather than whole, though the output chunk is still held in memory.
+    /// This does not apply with `--async` or `--prefetch`.
+    #[arg(long, value_parser = parse_memory_size)]
+    pub max_block_bytes: Option<usize>,
+
+    /// Adapt the number of chunks processed concurrently to the observed throughput and memory headroom.
+    ///
+    /// Element-wise filters (and fused groups) start with one chunk at a time, and the concurrency is increased while the throughput improves, up to the chunk limit.
+    /// This does not apply with `--async` or `--prefetch`.
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Process the chunks of each filter one at a time in order, and seed stochastic filters (e.g. noise, sample) without a `--seed` with 0.
+    ///
+    /// The outputs and checkpoints of a run are reproducible, though timings still vary.
+    #[arg(long, conflicts_with_all = ["chunk_limit", "run_async", "prefetch", "adaptive", "worker"])]
+    pub deterministic: bool,
+
+    /// Resume an interrupted run.
+    ///
+    /// Filters with an existing output are skipped.
+    /// Element-wise filters (and fused groups) with an output path record their completed chunks in a checkpoint (e.g. `<output>.checkpoint`), and only the remaining chunks are processed.
+    /// Other filters are rerun.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Hand out the chunks of the output to `--worker` processes connecting to this address (e.g. `0.0.0.0:7878`), then exit once every chunk has been written.
+    ///
+    /// The pipeline must have one element-wise filter (or fused group) with an output path.
+    /// The checkpoint of the output (e.g. `<output>.checkpoint`) is the completion ledger, so an interrupted coordinator can be resumed with `--resume`.
+    #[arg(long, conflicts_with = "worker")]
+    pub coordinator: Option<String>,
+
+    /// Process the chunks handed out by the `--coordinator` at this address (e.g. `node01:7878`).
+    ///
+    /// The output is shared by the workers, so it is not erased.
+    #[arg(long)]
+    pub worker: Option<String>,
+
+    /// The number of chunks handed out to a worker at a time by `--coordinator`.
+    #[arg(long, default_value_t = 16, requires = "coordinator")]
+    pub batch: u64,
+
+    /// Validate the pipeline and print the memory per chunk, chunk limit, and output size of each filter, then exit without reading or writing any chunks.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// The format of progress reporting.
+    ///
+    /// `json` writes newline-delimited progress events of each filter (stage, chunks done/total, bytes read/processed/written, ETA) to stderr or `--progress-file` rather than progress bars.
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    pub progress_format: ProgressFormat,
+
+    /// Write JSON progress events to this file rather than stderr.
+    #[arg(long)]
+    pub progress_file: Option<PathBuf>,
+
+    /// Print the wall time, read/process/write time, bytes moved, and throughput of each filter at the end of the run.
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Write the wall time, read/process/write time, bytes moved, and throughput of each filter to this JSON file.
+    #[arg(long)]
+    pub profile_json: Option<PathBuf>,
+
+    /// Path to a run configuration.
+    ///
+    /// A YAML (.yaml/.yml) or JSON file holding an array of filters.
+    /// The whole pipeline is validated before any filter is run.
     pub run_config: Option<PathBuf>,
 
+    #[command(flatten)]
+    remote: RemoteStorageArgs,
+
+    #[command(flatten)]
+    task: TaskArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
+
     #[command(subcommand)]
     filter: Option<FilterCommand>,
 }
@@ -71,72 +189,69 @@ fn bar_style_finish() -> ProgressStyle {
         .unwrap_or(ProgressStyle::default_bar())
 }
 
-fn load_array<P: Into<PathBuf>>(path: P) -> Result<Array<FilesystemStore>, ArrayCreateError> {
-    let store = FilesystemStore::new(path.into())
-        .map_err(|err| ArrayCreateError::StorageError(StorageError::Other(err.to_string())))?;
-    Array::open(store.into(), "/")
-}
-
-/// Removes array if it exists
-fn create_array<P: Into<PathBuf>>(
-    path: P,
-    builder: &ArrayBuilder,
-) -> Result<Array<FilesystemStore>, ArrayCreateError> {
-    let store = FilesystemStore::new(path.into())
-        .map_err(|err| ArrayCreateError::StorageError(StorageError::Other(err.to_string())))?;
-    store.erase_prefix(&StorePrefix::root()).unwrap();
-    builder.build(store.into(), "/")
+/// Reports the progress of a pipeline to a progress bar per filter, and optionally JSON progress events.
+struct Bars {
+    bars: Vec<ProgressBar>,
+    progress_events: Option<ProgressEventWriter>,
 }
 
-fn get_array_input_output(
-    filter: &dyn FilterTraits,
-    input: &std::path::Path,
-    output: &std::path::Path,
-    reencode: &ZarrReencodingArgs,
-) -> Result<(Array<FilesystemStore>, Array<FilesystemStore>), ArrayCreateError> {
-    let array_input = load_array(input)?;
-    let array_output = create_array(output, &filter.output_array_builder(&array_input, reencode))?;
-    Ok((array_input, array_output))
+impl Bars {
+    /// Finish the stage `filters` on the bar of its first filter, clearing the bars of the other filters.
+    fn finish(&self, filters: Range<usize>, prefix: String) {
+        for bar in &self.bars[filters.start + 1..filters.end] {
+            bar.finish_and_clear();
+        }
+        let bar = &self.bars[filters.start];
+        bar.set_style(bar_style_finish());
+        bar.set_prefix(prefix);
+        bar.abandon();
+    }
 }
 
-fn get_path(
-    path_or_id: &Option<PathOrIdentifier>,
-    tmp_dir: &std::path::Path,
-    id_to_path: &mut HashMap<String, Arc<TempDir>>,
-    last_output: &Option<std::path::PathBuf>,
-) -> std::io::Result<PathOrTempPath> {
-    if let Some(path_or_id) = path_or_id {
-        match path_or_id {
-            PathOrIdentifier::Identifier(id) => {
-                // Named temporary output
-                let entry = id_to_path.entry(id.clone()).or_insert_with(|| {
-                    tempfile::TempDir::with_prefix_in(id, tmp_dir)
-                        .unwrap()
-                        .into()
-                });
-                Ok(PathOrTempPath::TempPath(entry.clone()))
-            }
-            PathOrIdentifier::Path(path) => {
-                // Long lived output
-                Ok(PathOrTempPath::Path(path.clone()))
-            }
-        }
-    } else {
-        // Unnamed temporary
-        if let Some(last_output) = last_output {
-            Ok(PathOrTempPath::Path(last_output.clone()))
+impl PipelineObserver for Bars {
+    fn opened(&self, index: usize, description: &str) {
+        if self.bars.len() == 1 {
+            println!("{description}");
         } else {
-            Ok(PathOrTempPath::TempPath(
-                tempfile::TempDir::new_in(tmp_dir)?.into(),
-            ))
+            println!("{index} {description}");
         }
     }
+
+    fn skipped(&self, filters: Range<usize>, name: &str, output: &Path) {
+        self.finish(
+            filters,
+            format!("{name} {} (complete)", output.to_string_lossy()),
+        );
+    }
+
+    fn started(&self, filters: Range<usize>) {
+        self.bars[filters.start].reset();
+    }
+
+    fn progress(&self, filters: Range<usize>, stage: &ProgressStage, stats: ProgressStats) {
+        if let Some(progress_events) = &self.progress_events {
+            progress_events.write(&stage.event(&stats));
+        }
+        progress_callback(stats, &self.bars[filters.start]);
+    }
+
+    fn finished(&self, filters: Range<usize>, report: &StageReport, output: &Path) {
+        self.finish(
+            filters,
+            format!("{} {}", report.stage, output.to_string_lossy()),
+        );
+    }
 }
 
 fn main() -> std::process::ExitCode {
     if let Err(err) = run() {
-        println!("{}", err);
-        std::process::ExitCode::FAILURE
+        if is_interrupted() {
+            println!("Interrupted, resume with --resume");
+            std::process::ExitCode::from(EXIT_CODE_INTERRUPTED)
+        } else {
+            println!("{}", err);
+            std::process::ExitCode::FAILURE
+        }
     } else {
         std::process::ExitCode::SUCCESS
     }
@@ -167,25 +282,60 @@ fn progress_callback(stats: ProgressStats, bar: &ProgressBar) {
     }
 }
 
+/// Print the timing and throughput report of each filter (or fused group) of a run.
+fn print_profile(reports: &[StageReport]) {
+    println!(
+        "{:<24} {:>9} {:>9} {:>9} {:>9} {:>8} {:>11} {:>11} {:>11} {:>11}",
+        "stage",
+        "wall(s)",
+        "read(s)",
+        "proc(s)",
+        "write(s)",
+        "chunks",
+        "read(MB)",
+        "proc(MB)",
+        "write(MB)",
+        "MB/s"
+    );
+    for report in reports {
+        println!(
+            "{:<24} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>8} {:>11.2} {:>11.2} {:>11.2} {:>11.2}",
+            report.stage,
+            report.wall_s,
+            report.read_s,
+            report.process_s,
+            report.write_s,
+            report.chunks,
+            report.bytes_read as f64 / 1e6, // MB
+            report.bytes_processed as f64 / 1e6,
+            report.bytes_written as f64 / 1e6,
+            report.throughput_bytes_per_s / 1e6,
+        );
+    }
+}
+
 fn run() -> Result<(), Box<dyn Error>> {
     // Parse command line arguments
     let cli = Cli::parse();
 
+    cli.threads.init()?;
+    zarrs_tools::interrupt::set_handler()?;
+    set_memory_limit(cli.memory_limit);
+    set_device(cli.device)?;
+
     let start = std::time::Instant::now();
 
     let multi_progress = MultiProgress::new();
-
-    // Create temporary directory
-    let tmp_dir = if let Some(tmp) = cli.tmp {
-        PathOrTempPath::Path(tmp.clone())
+    let progress_events = if cli.progress_format == ProgressFormat::Json {
+        multi_progress.set_draw_target(ProgressDrawTarget::hidden());
+        Some(ProgressEventWriter::new(cli.progress_file.as_deref())?)
     } else {
-        PathOrTempPath::TempPath(tempfile::tempdir()?.into())
+        None
     };
 
     // Get the filters
-    let mut filter_commands: Vec<FilterCommand> = if let Some(config) = cli.run_config {
-        let config = std::fs::read_to_string(config)?;
-        serde_json::from_str(&config)?
+    let filter_commands: Vec<FilterCommand> = if let Some(config) = cli.run_config {
+        read_pipeline(&config)?
     } else if let Some(filter) = cli.filter {
         vec![filter]
     } else {
@@ -194,9 +344,77 @@ fn run() -> Result<(), Box<dyn Error>> {
             "no filters supplied",
         ))?
     };
+    if cli.device == Device::Gpu {
+        for filter in filter_commands
+            .iter()
+            .filter(|filter| !filter.supports_gpu())
+        {
+            eprintln!(
+                "Warning: {} is not supported on the GPU and runs on the CPU",
+                filter.name()
+            );
+        }
+    }
+
+    let task = cli.task.task()?;
+    let pipeline = Pipeline::new(
+        filter_commands,
+        PipelineOptions {
+            exists: cli.exists,
+            tmp: cli.tmp,
+            chunk_limit: cli.chunk_limit,
+            fuse: cli.fuse,
+            in_place: cli.in_place,
+            run_async: cli.run_async,
+            concurrent_chunks: cli.concurrent_chunks,
+            prefetch: cli.prefetch,
+            max_block_bytes: cli.max_block_bytes,
+            adaptive: cli.adaptive,
+            deterministic: cli.deterministic,
+            resume: cli.resume,
+            task,
+            worker: cli.worker,
+            remote: cli.remote,
+        },
+    )?;
+
+    if let Some(address) = &cli.coordinator {
+        pipeline.coordinate(address, cli.batch)?;
+        println!("Every chunk has been written by the workers of {address}");
+        return Ok(());
+    }
+
+    if cli.task.finalize_tasks {
+        let task_count = cli.task.task_count.unwrap_or_default();
+        pipeline.finalize_tasks(task_count)?;
+        println!("Finalized {task_count} tasks");
+        return Ok(());
+    }
+
+    if cli.dry_run {
+        let plans = pipeline.dry_run()?;
+        for (i, plan) in plans.iter().enumerate() {
+            if plans.len() == 1 {
+                println!("{plan}");
+            } else {
+                println!("{i} {plan}");
+            }
+        }
+        println!(
+            "Dry run, ~{:.2}MB uncompressed written to output paths",
+            plans
+                .iter()
+                .filter(|plan| !plan.output_temporary)
+                .map(|plan| plan.output_size)
+                .sum::<u64>() as f32
+                / 1e6 // MB
+        );
+        return Ok(());
+    }
 
     // Setup progress bars
-    let bars = filter_commands
+    let bars = pipeline
+        .filter_commands()
         .iter()
         .map(|filter| {
             let bar = multi_progress.add(ProgressBar::new(1));
@@ -206,172 +424,21 @@ fn run() -> Result<(), Box<dyn Error>> {
         })
         .collect_vec();
 
-    // Propagate global settings to filters
-    for filter in &mut filter_commands {
-        if let Some(global_chunk_limit) = cli.chunk_limit {
-            let filter_chunk_limit = filter.common_args_mut().chunk_limit_mut();
-            if filter_chunk_limit.is_none() {
-                *filter_chunk_limit = Some(global_chunk_limit);
-            }
-        }
-    }
+    // Run the filters
+    let reports = pipeline.run(&Bars {
+        bars,
+        progress_events,
+    })?;
 
-    // Get the input and output paths
-    let InputsOutputsExists {
-        input_paths,
-        output_paths,
-        exists,
-    } = get_input_output_paths(&filter_commands, tmp_dir.path())?;
-
-    // Handle an existing output
-    match cli.exists {
-        OutputExists::Exit => {
-            if exists.iter().any(|i| *i) {
-                Err(FilterError::Other("Output exists, exiting".to_string()))?;
-            }
-        }
-        OutputExists::Erase => {}
+    if cli.profile {
+        print_profile(&reports);
+    }
+    if let Some(profile_json) = &cli.profile_json {
+        std::fs::write(profile_json, serde_json::to_string_pretty(&reports)?)?;
     }
-
-    // Instantiate the filters
-    let filters: Vec<Box<dyn FilterTraits>> = filter_commands
-        .iter()
-        .map(|filter| filter.init())
-        .try_collect()?;
-
-    // Collect filters/input/outputs and check compatibility
-    let filter_input_output: Vec<_> = itertools::izip!(
-        &filter_commands,
-        &filters,
-        &input_paths,
-        &output_paths,
-        &exists
-    )
-    .enumerate()
-    .map(|(i, (filter_command, filter, input, output, exists))| {
-        let (array_input, array_output) = get_array_input_output(
-            filter,
-            input.path(),
-            output.path(),
-            filter_command.common_args().reencode(),
-        )?;
-        println!(
-            "{}{}\n\targs:   {}\n\tencode: {}\n\tinput:  {} {:?} {:?}\n\toutput: {} {:?} {:?}{}",
-            if filters.len() == 1 {
-                "".to_string()
-            } else {
-                format!("{i} ")
-            },
-            filter_command.name(),
-            filter_command.args_str(),
-            filter_command.reencode_str(),
-            array_input.data_type(),
-            array_input.shape(),
-            input.path(),
-            array_output.data_type(),
-            array_output.shape(),
-            output.path(),
-            if *exists { " (overwrite)" } else { "" },
-        );
-        array_output.store_metadata()?; // erased before filter run
-
-        filter.is_compatible(
-            &array_input.chunk_array_representation(&vec![0; array_input.dimensionality()])?,
-            &array_output.chunk_array_representation(&vec![0; array_output.dimensionality()])?,
-        )?;
-        Ok::<_, FilterError>((
-            filter_command.name(),
-            filter,
-            array_input,
-            array_output,
-            output.path(),
-        ))
-    })
-    .try_collect()?;
-
-    // Erase output metadata to imply indicating that filter has not run
-    filter_input_output
-        .iter()
-        .try_for_each(|(_, _, _, array_output, _)| array_output.erase_metadata())?;
-
-    // Run the filters
-    std::iter::zip(filter_input_output, bars).try_for_each(
-        |((_name, filter, array_input, mut array_output, output_path), bar)| {
-            bar.reset();
-
-            let progress_callback = |stats: ProgressStats| progress_callback(stats, &bar);
-            let progress_callback = ProgressCallback::new(&progress_callback);
-            // Run the filter
-            filter.apply(&array_input, &mut array_output, &progress_callback)?;
-
-            // Write metadata to indicate that filter is finished
-            array_output.store_metadata()?;
-
-            bar.set_style(bar_style_finish());
-            bar.set_prefix(format!(
-                "{} {}",
-                bar.prefix(),
-                output_path.to_string_lossy()
-            ));
-            bar.abandon();
-            Ok::<(), FilterError>(())
-        },
-    )?;
 
     let duration_s = start.elapsed().as_secs_f32();
     println!("Completed in {duration_s:.2}s");
 
     Ok(())
 }
-
-struct InputsOutputsExists {
-    input_paths: Vec<PathOrTempPath>,
-    output_paths: Vec<PathOrTempPath>,
-    exists: Vec<bool>,
-}
-
-fn get_input_output_paths(
-    filters: &[FilterCommand],
-    tmp_dir: &Path,
-) -> Result<InputsOutputsExists, FilterError> {
-    let mut id_to_path = HashMap::<String, Arc<TempDir>>::new();
-    let mut input_paths = Vec::<PathOrTempPath>::with_capacity(filters.len());
-    let mut output_paths = Vec::<PathOrTempPath>::with_capacity(filters.len());
-    let mut last_output: Option<std::path::PathBuf> = None;
-    let mut exists = Vec::<bool>::with_capacity(filters.len());
-    for filter in filters {
-        if let Some(PathOrIdentifier::Path(output_path)) = filter.io_args().output() {
-            exists.push(output_path.exists());
-        } else {
-            exists.push(false);
-        }
-
-        // println!("{filter:#?}");
-        let input_path = get_path(
-            filter.io_args().input(),
-            tmp_dir,
-            &mut id_to_path,
-            &last_output,
-        )?;
-        let output_path = get_path(filter.io_args().output(), tmp_dir, &mut id_to_path, &None)?;
-        if input_paths.is_empty() {
-            if let PathOrTempPath::TempPath(_) = input_path {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "the first filter must have a valid input path",
-                ))?
-            }
-        }
-        last_output = Some(output_path.path().to_path_buf());
-        // println!("{:?} -> {:?}", input_path.path(), output_path.path());
-
-        input_paths.push(input_path);
-        output_paths.push(output_path);
-    }
-
-    Ok(InputsOutputsExists {
-        input_paths,
-        output_paths,
-        exists,
-    })
-}
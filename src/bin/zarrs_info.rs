@@ -1,7 +1,6 @@
-use std::{error::Error, sync::Arc};
+use std::error::Error;
 
 use clap::{Parser, Subcommand};
-use rayon::current_num_threads;
 use serde::Serialize;
 use serde_json::Number;
 use zarrs::{
@@ -9,7 +8,10 @@ use zarrs::{
     group::{Group, GroupMetadataOptions},
     metadata::Metadata,
     node::{Node, NodeMetadata},
-    storage::store::FilesystemStore,
+};
+use zarrs_tools::{
+    storage::RemoteStorageArgs,
+    threads::{num_threads, ThreadArgs},
 };
 
 /// Get information about a Zarr V3 array as JSON.
@@ -18,16 +20,22 @@ use zarrs::{
 struct Cli {
     /// The maximum number of chunks concurrently processed.
     ///
-    /// Defaults to the RAYON_NUM_THREADS environment variable or the number of logical CPUs.
+    /// Defaults to the number of threads.
     /// Consider reducing this for images with large chunk sizes or on systems with low memory availability.
-    #[arg(long, default_value_t = current_num_threads())]
-    chunk_limit: usize,
+    #[arg(long)]
+    chunk_limit: Option<usize>,
 
     #[arg(long, default_value_t = false)]
     time: bool,
 
-    /// Path to zarr input array.
-    path: std::path::PathBuf,
+    /// Path to zarr input array, or a URI (e.g. `https://`, `s3://`, `webdav://`).
+    path: String,
+
+    #[command(flatten)]
+    remote: RemoteStorageArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
 
     #[command(subcommand)]
     command: InfoCommand,
@@ -86,10 +94,12 @@ fn array_metadata_options_v3() -> ArrayMetadataOptions {
 
 fn run() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
+    cli.threads.init()?;
+    let chunk_limit = cli.chunk_limit.unwrap_or_else(num_threads);
 
     let start = std::time::Instant::now();
 
-    let storage = Arc::new(FilesystemStore::new(&cli.path)?);
+    let storage = cli.remote.storage_from_uri(&cli.path)?;
 
     let node = Node::open(&storage, "/")?;
     if let NodeMetadata::Group(_) = node.metadata() {
@@ -173,7 +183,7 @@ fn run() -> Result<(), Box<dyn Error>> {
                 );
             }
             InfoCommand::Range => {
-                let (min, max) = zarrs_tools::info::calculate_range(&array, cli.chunk_limit)?;
+                let (min, max) = zarrs_tools::info::calculate_range(&array, chunk_limit)?;
                 #[derive(Serialize)]
                 struct MinMax {
                     min: Number,
@@ -187,7 +197,7 @@ fn run() -> Result<(), Box<dyn Error>> {
                     histogram_params.n_bins,
                     histogram_params.min,
                     histogram_params.max,
-                    cli.chunk_limit,
+                    chunk_limit,
                 )?;
                 #[derive(Serialize)]
                 struct Histogram {
@@ -7,15 +7,20 @@ use std::{
         Arc,
     },
 };
-use zarrs_tools::{get_array_builder, ZarrEncodingArgs};
+use zarrs_tools::{
+    get_array_builder,
+    storage::storage_from_uri,
+    threads::{num_threads, ThreadArgs},
+    ZarrEncodingArgs,
+};
 
 use zarrs::{
     array::{codec::CodecOptionsBuilder, Array, DimensionName},
     array_subset::ArraySubset,
     metadata::Metadata,
     storage::{
-        store::{FilesystemStore, MemoryStore},
-        ReadableWritableListableStorage, ReadableWritableStorageTraits, StorePrefix,
+        store::MemoryStore, ReadableWritableListableStorage, ReadableWritableStorageTraits,
+        StorePrefix,
     },
 };
 
@@ -26,6 +31,9 @@ struct Cli {
     #[command(flatten)]
     encoding: ZarrEncodingArgs,
 
+    #[command(flatten)]
+    threads: ThreadArgs,
+
     /// Validate written data.
     #[arg(long, default_value_t = false)]
     validate: bool,
@@ -73,7 +81,7 @@ fn ncfiles_to_array<TStore: ReadableWritableStorageTraits + ?Sized + 'static>(
 
     let bytes_read: AtomicUsize = 0.into();
 
-    let concurrent_target = std::thread::available_parallelism().unwrap().get();
+    let concurrent_target = num_threads();
     let codec_options = CodecOptionsBuilder::new()
         .concurrent_target(concurrent_target)
         .build();
@@ -181,6 +189,7 @@ fn nc_vartype_to_zarr_datatype(nc_vartype: netcdf::types::VariableType) -> Optio
 fn main() {
     // Parse and validate arguments
     let cli = Cli::parse();
+    cli.threads.init().unwrap();
     if let Some(shard_shape) = &cli.encoding.shard_shape {
         assert_eq!(cli.encoding.chunk_shape.len(), shard_shape.len());
     }
@@ -259,7 +268,7 @@ fn main() {
     let store: ReadableWritableListableStorage = if cli.memory_test {
         Arc::new(MemoryStore::default())
     } else {
-        Arc::new(FilesystemStore::new(path_out).unwrap())
+        storage_from_uri(&path_out.to_string_lossy()).unwrap()
     };
 
     // Create array
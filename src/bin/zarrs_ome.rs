@@ -3,6 +3,7 @@ use std::{
     hash::Hash,
     num::NonZeroU64,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use clap::Parser;
@@ -19,14 +20,16 @@ use zarrs::{
     array::{Array, ArrayCodecTraits, ArrayMetadata, ChunkRepresentation, Element, ElementOwned},
     array_subset::ArraySubset,
     group::{Group, GroupMetadata, GroupMetadataV3},
-    storage::{store::FilesystemStore, StorePrefix, WritableStorageTraits},
+    storage::{StorePrefix, WritableStorageTraits},
 };
 use zarrs_tools::{
     filter::{
         filters::{downsample::Downsample, gaussian::Gaussian},
-        ArraySubsetOverlap, FilterError, FilterTraits,
+        ArraySubsetOverlap, FilterError, FilterStorage, FilterTraits,
     },
     progress::{Progress, ProgressCallback, ProgressStats},
+    storage::{is_uri, is_zip_path, RemoteStorageArgs, ZipOutput},
+    threads::ThreadArgs,
     ZarrReEncodingChangeType, ZarrReencodingArgs,
 };
 
@@ -70,9 +73,15 @@ impl std::fmt::Display for OMEZarrVersion {
 struct Cli {
     /// The input array path.
     input: PathBuf,
-    /// The output group path.
+    /// The output group path, or a zip archive (`.zip`).
     output: PathBuf,
 
+    #[command(flatten)]
+    remote: RemoteStorageArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
+
     // The OME-Zarr version.
     #[arg(long, default_value_t = OMEZarrVersion::V0_5_dev)]
     version: OMEZarrVersion,
@@ -173,14 +182,14 @@ fn copy_dir(
 }
 
 fn apply_chunk_discrete<T>(
-    array_input: &Array<FilesystemStore>,
-    array_output: &Array<FilesystemStore>,
+    array_input: &Array<FilterStorage>,
+    array_output: &Array<FilterStorage>,
     chunk_indices: &[u64],
     downsample_filter: &Downsample,
     progress: &Progress,
 ) -> Result<(), FilterError>
 where
-    T: Element + ElementOwned + Copy + Send + Sync + Eq + PartialEq + Hash + AsPrimitive<T>,
+    T: Element + ElementOwned + Copy + Send + Sync + Ord + Hash + AsPrimitive<T>,
 {
     let output_subset = array_output.chunk_subset_bounded(chunk_indices).unwrap();
     let downsample_input_subset =
@@ -197,8 +206,8 @@ where
 }
 
 fn apply_chunk_continuous<T>(
-    array_input: &Array<FilesystemStore>,
-    array_output: &Array<FilesystemStore>,
+    array_input: &Array<FilterStorage>,
+    array_output: &Array<FilterStorage>,
     chunk_indices: &[u64],
     downsample_filter: &Downsample,
     progress: &Progress,
@@ -222,8 +231,8 @@ where
 }
 
 fn apply_chunk_continuous_gaussian<T>(
-    array_input: &Array<FilesystemStore>,
-    array_output: &Array<FilesystemStore>,
+    array_input: &Array<FilterStorage>,
+    array_output: &Array<FilterStorage>,
     chunk_indices: &[u64],
     downsample_filter: &Downsample,
     gaussian_filter: &Gaussian,
@@ -271,14 +280,25 @@ fn progress_callback(stats: ProgressStats, bar: &ProgressBar) {
 
 fn run() -> Result<(), Box<dyn Error>> {
     // Parse command line arguments
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    cli.threads.init()?;
 
     println!("Input {:?}", cli.input);
 
+    // Write a zip output to a staging directory
+    let output_exists = cli.output.exists();
+    let zip_out = if is_zip_path(&cli.output.to_string_lossy()) {
+        let zip_out = ZipOutput::new(&cli.output)?;
+        cli.output = zip_out.directory().to_path_buf();
+        Some(zip_out)
+    } else {
+        None
+    };
+
     let start = std::time::Instant::now();
 
-    let store_in = FilesystemStore::new(&cli.input)?;
-    let array_in = Array::open(store_in.into(), "/")?;
+    let store_in: Arc<FilterStorage> = cli.remote.storage_from_uri(&cli.input.to_string_lossy())?;
+    let array_in = Array::open(store_in, "/")?;
 
     let multi_progress = MultiProgress::new();
     let bars = (0..=cli.max_levels)
@@ -301,7 +321,7 @@ fn run() -> Result<(), Box<dyn Error>> {
     };
 
     // Create group
-    let store = std::sync::Arc::new(FilesystemStore::new(&cli.output)?);
+    let store = cli.remote.storage_from_uri(&cli.output.to_string_lossy())?;
     let mut group = Group::new_with_metadata(
         store.clone(),
         "/",
@@ -316,7 +336,7 @@ fn run() -> Result<(), Box<dyn Error>> {
     // Handle an existing output
     match cli.exists {
         OutputExists::Exit => {
-            if cli.output.exists() {
+            if output_exists {
                 Err(FilterError::Other("Output exists, exiting".to_string()))?;
             }
         }
@@ -333,20 +353,22 @@ fn run() -> Result<(), Box<dyn Error>> {
         let output_0_path = cli.output.join("0");
         let progress_callback = |stats: ProgressStats| progress_callback(stats, bar);
         let progress_callback = ProgressCallback::new(&progress_callback);
-        if let (ZarrReEncodingChangeType::None, ArrayMetadata::V3(_)) =
-            (cli.reencoding.change_type(), array_in.metadata())
+        let local = !is_uri(&cli.input.to_string_lossy()) && !is_uri(&cli.output.to_string_lossy());
+        if let (ZarrReEncodingChangeType::None, ArrayMetadata::V3(_), true) =
+            (cli.reencoding.change_type(), array_in.metadata(), local)
         {
-            // Copy full res input to output if it is Zarr V3 and does not need any changes
+            // Copy full res input to output if it is Zarr V3, local, and does not need any changes
             let dir_count = count_dir(&cli.input)?;
             let progress = Progress::new(dir_count, &progress_callback);
             copy_dir(&cli.input, &output_0_path, &progress)?;
         } else {
             // Reencode the input
             let reencode = zarrs_tools::filter::filters::reencode::Reencode::new(cli.chunk_limit);
-            let store_out = FilesystemStore::new(&cli.output)?;
+            let store_out: Arc<FilterStorage> =
+                cli.remote.storage_from_uri(&cli.output.to_string_lossy())?;
             let mut array_out = reencode
                 .output_array_builder(&array_in, &cli.reencoding)
-                .build(store_out.into(), "/0")?;
+                .build(store_out, "/0")?;
             reencode.apply(&array_in, &mut array_out, &progress_callback)?;
             array_out.store_metadata()?;
         }
@@ -354,7 +376,7 @@ fn run() -> Result<(), Box<dyn Error>> {
     }
 
     // Setup attributes
-    let store = std::sync::Arc::new(FilesystemStore::new(&cli.output)?);
+    let store = cli.remote.storage_from_uri(&cli.output.to_string_lossy())?;
     // store.erase_prefix(&StorePrefix::root()).unwrap();
     let mut array0 = Array::open(store.clone(), "/0")?;
     {
@@ -460,12 +482,14 @@ fn run() -> Result<(), Box<dyn Error>> {
         let progress_callback = ProgressCallback::new(&progress_callback);
 
         // Input
-        let store = FilesystemStore::new(&cli.output)?;
-        let array_input = Array::open(store.into(), &format!("/{}", i - 1))?;
+        let store: Arc<FilterStorage> =
+            cli.remote.storage_from_uri(&cli.output.to_string_lossy())?;
+        let array_input = Array::open(store, &format!("/{}", i - 1))?;
 
         // Filters
         let gaussian_filter = Gaussian::new(sigma.clone(), kernel_half_size.clone(), None);
-        let downsample_filter = Downsample::new(downsample_factor.clone(), cli.discrete, None);
+        let downsample_filter =
+            Downsample::new(downsample_factor.clone(), cli.discrete, false, None);
 
         // Setup reencoding (this is a bit hacky)
         let chunk_representation =
@@ -510,8 +534,9 @@ fn run() -> Result<(), Box<dyn Error>> {
 
         // Output
         let output_path = cli.output.join(i.to_string());
-        let output_store = FilesystemStore::new(&cli.output)?;
-        let array_output = output_builder.build(output_store.into(), &format!("/{}", i))?;
+        let output_store: Arc<FilterStorage> =
+            cli.remote.storage_from_uri(&cli.output.to_string_lossy())?;
+        let array_output = output_builder.build(output_store, &format!("/{}", i))?;
         bar.set_prefix(format!("{i} {:?}", array_output.shape()));
 
         // Scale factor (inverse of downsample factor, accounting for actual changes)
@@ -720,6 +745,11 @@ fn run() -> Result<(), Box<dyn Error>> {
     // Store metadata
     group.store_metadata()?;
 
+    if let Some(zip_out) = zip_out {
+        cli.output = zip_out.path().to_path_buf();
+        zip_out.finish()?;
+    }
+
     let duration_s = start.elapsed().as_secs_f32();
     println!("Output {:?} in {duration_s:.2}s", cli.output);
 
@@ -1,17 +1,23 @@
 use core::f32;
-use std::sync::Arc;
 
 use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use zarrs::storage::{
-    storage_adapter::async_to_sync::{AsyncToSyncBlockOn, AsyncToSyncStorageAdapter},
-    store::AsyncOpendalStore,
-    AsyncReadableListableStorage, ListableStorageTraits, ReadableListableStorage, StorePrefix,
-    WritableStorageTraits,
+    store::MemoryStore, ListableStorageTraits, StorePrefix, WritableStorageTraits,
 };
 use zarrs_tools::{
-    do_reencode, get_array_builder_reencode,
-    progress::{ProgressCallback, ProgressStats},
+    calculate_reencode_concurrency,
+    checkpoint::Checkpoint,
+    do_reencode,
+    filter::element_size,
+    get_array_builder_reencode,
+    interrupt::{is_interrupted, InterruptStorageAdapter, EXIT_CODE_INTERRUPTED},
+    progress::{
+        ProgressCallback, ProgressEventWriter, ProgressFormat, ProgressStage, ProgressStats,
+    },
+    storage::{is_zip_path, RemoteStorageArgs, ZipOutput},
+    task::{finalize_tasks, TaskArgs},
+    threads::ThreadArgs,
     ZarrReencodingArgs,
 };
 
@@ -22,10 +28,19 @@ struct Args {
     #[command(flatten)]
     encoding: ZarrReencodingArgs,
 
-    /// The zarr array input path or URL.
+    #[command(flatten)]
+    remote: RemoteStorageArgs,
+
+    #[command(flatten)]
+    task: TaskArgs,
+
+    #[command(flatten)]
+    threads: ThreadArgs,
+
+    /// The zarr array input path, URL, or object store URI (`s3://`, `gs://`, `az://`).
     path_in: String,
 
-    /// The zarr array output directory.
+    /// The zarr array output directory, object store URI, or zip archive (`.zip`).
     path_out: String,
 
     /// Number of concurrent chunks.
@@ -42,6 +57,27 @@ struct Args {
     #[arg(long, default_value_t = false)]
     validate: bool,
 
+    /// Resume an interrupted run.
+    ///
+    /// Chunks recorded in the checkpoint of the output (e.g. `<path_out>.checkpoint`) by the interrupted run are not reencoded again.
+    /// If there is no checkpoint, the output is reencoded from scratch.
+    #[arg(long, default_value_t = false)]
+    resume: bool,
+
+    /// Print the memory per chunk, chunk concurrency, and output size, then exit without reading or writing any chunks.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// The format of progress reporting.
+    ///
+    /// `json` writes newline-delimited progress events (stage, chunks done/total, bytes read/processed/written, ETA) to stderr or `--progress-file` rather than a progress bar.
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    progress_format: ProgressFormat,
+
+    /// Write JSON progress events to this file rather than stderr.
+    #[arg(long)]
+    progress_file: Option<std::path::PathBuf>,
+
     /// Print verbose information, such as the array header.
     #[arg(long, short, default_value_t = false)]
     verbose: bool,
@@ -84,46 +120,35 @@ fn progress_callback(stats: ProgressStats, bar: &ProgressBar) {
     }
 }
 
-struct TokioBlockOn(tokio::runtime::Runtime);
-
-impl AsyncToSyncBlockOn for TokioBlockOn {
-    fn block_on<F: core::future::Future>(&self, future: F) -> F::Output {
-        self.0.block_on(future)
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(_) if is_interrupted() => {
+            eprintln!("Interrupted, resume with --resume");
+            std::process::ExitCode::from(EXIT_CODE_INTERRUPTED)
+        }
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            std::process::ExitCode::FAILURE
+        }
     }
 }
 
-fn get_storage(path: &str) -> anyhow::Result<ReadableListableStorage> {
-    if path.starts_with("http://") || path.starts_with("https://") {
-        let builder = opendal::services::Http::default().endpoint(path);
-        let operator = opendal::Operator::new(builder)?.finish();
-        let storage: AsyncReadableListableStorage = Arc::new(AsyncOpendalStore::new(operator));
-        let block_on = TokioBlockOn(tokio::runtime::Runtime::new()?);
-        Ok(Arc::new(AsyncToSyncStorageAdapter::new(storage, block_on)))
-    // } else if path.starts_with("s3://") {
-    //     let endpoint = "";
-    //     let bucket = "";
-    //     let root = "";
-    //     let region = "auto";
-    //     let builder = opendal::services::S3::default()
-    //         .endpoint(&endpoint)
-    //         .region(&region)
-    //         .root(path)
-    //         .allow_anonymous()
-    //         .bucket(&bucket);
-    //     let operator = opendal::Operator::new(builder)?.finish();
-    //     Arc::new(AsyncOpendalStore::new(operator))
-    } else {
-        Ok(Arc::new(zarrs::storage::store::FilesystemStore::new(path)?))
-    }
-}
-
-fn main() -> anyhow::Result<()> {
+fn run() -> anyhow::Result<()> {
     let args = Args::parse();
+    args.threads.init()?;
+
+    zarrs_tools::interrupt::set_handler()?;
 
     zarrs::config::global_config_mut().set_validate_checksums(!args.ignore_checksums);
 
-    let storage_in = get_storage(&args.path_in)?;
-    let array_in = zarrs::array::Array::open(storage_in.clone(), "/").unwrap();
+    let storage_in = args.remote.storage_from_uri(&args.path_in)?;
+    // Reads fail after an interrupt, so no new chunks are reencoded
+    let array_in = zarrs::array::Array::open(
+        std::sync::Arc::new(InterruptStorageAdapter::new(storage_in.clone())),
+        "/",
+    )
+    .unwrap();
     if args.verbose {
         println!(
             "{}",
@@ -131,25 +156,160 @@ fn main() -> anyhow::Result<()> {
         );
     }
 
-    let bar = ProgressBar::new(0);
-    bar.set_style(bar_style_run());
-    let progress_callback = |stats: ProgressStats| progress_callback(stats, &bar);
-    let progress_callback = ProgressCallback::new(&progress_callback);
+    if args.dry_run {
+        let array_out = get_array_builder_reencode(&args.encoding, &array_in, None)
+            .build(std::sync::Arc::new(MemoryStore::new()), "/")?;
+        let chunk_representation =
+            array_out.chunk_array_representation(&vec![0; array_out.dimensionality()])?;
+        let memory_per_chunk =
+            chunk_representation.num_elements_usize() * element_size(&chunk_representation);
+        let (chunks_concurrent_limit, codec_concurrent_target) =
+            calculate_reencode_concurrency(&array_out, args.concurrent_chunks);
+        let num_chunks = array_out
+            .chunk_grid_shape()
+            .unwrap()
+            .iter()
+            .product::<u64>();
+        let size = array_out.shape().iter().product::<u64>() as f32
+            * element_size(&chunk_representation) as f32;
+        println!(
+            "Reencode {} to {} (dry run)\n\toutput: {} {:?}\n\tmemory per chunk: ~{:.2}MB\n\tconcurrent chunks: {} (codec concurrent target {})\n\toutput chunks: {}\n\toutput size: ~{:.2}MB uncompressed",
+            args.path_in,
+            args.path_out,
+            array_out.data_type(),
+            array_out.shape(),
+            memory_per_chunk as f32 / 1e6, // MB
+            chunks_concurrent_limit,
+            codec_concurrent_target,
+            num_chunks,
+            size / 1e6, // MB
+        );
+        return Ok(());
+    }
+
+    let task = args.task.task()?;
+    if args.task.finalize_tasks {
+        let array_out =
+            zarrs::array::Array::open(args.remote.storage_from_uri(&args.path_out)?, "/")?;
+        let task_count = args.task.task_count.unwrap_or_default();
+        finalize_tasks(
+            &args.path_out,
+            task_count,
+            &array_out.chunk_grid_shape().unwrap(),
+        )?;
+        println!("Finalized {task_count} tasks of {}", args.path_out);
+        return Ok(());
+    }
+
+    let zip_out = if is_zip_path(&args.path_out) {
+        Some(ZipOutput::new(&args.path_out)?)
+    } else {
+        None
+    };
+    let storage_out = if let Some(zip_out) = &zip_out {
+        args.remote
+            .storage_from_uri(&zip_out.directory().to_string_lossy())?
+    } else {
+        args.remote.storage_from_uri(&args.path_out)?
+    };
+
+    // Record completed chunks so an interrupted run can be resumed
+    // Zip archive outputs are staged in a temporary directory, so they cannot be resumed
+    let (checkpoint, resumed) = if zip_out.is_some() {
+        if args.resume {
+            anyhow::bail!("a zip archive output cannot be resumed");
+        }
+        if task.is_some() {
+            anyhow::bail!("a zip archive output cannot be written by the tasks of an array job");
+        }
+        (None, false)
+    } else if let Some(task) = task {
+        // The output is shared by the tasks, so it is not erased
+        let checkpoint_path = Checkpoint::task_manifest_path(&args.path_out, &task);
+        let checkpoint = if args.resume {
+            Checkpoint::resume(&checkpoint_path)?
+        } else {
+            None
+        };
+        let checkpoint = match checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => Checkpoint::create(&checkpoint_path)?,
+        };
+        (Some(checkpoint), true)
+    } else {
+        let checkpoint_path = Checkpoint::manifest_path(&args.path_out);
+        let checkpoint = if args.resume {
+            Checkpoint::resume(&checkpoint_path)?
+        } else {
+            None
+        };
+        if let Some(checkpoint) = checkpoint {
+            (Some(checkpoint), true)
+        } else {
+            (Some(Checkpoint::create(&checkpoint_path)?), false)
+        }
+    };
 
-    let storage_out =
-        Arc::new(zarrs::storage::store::FilesystemStore::new(args.path_out.clone()).unwrap());
-    storage_out.erase_prefix(&StorePrefix::root()).unwrap();
     let builder = get_array_builder_reencode(&args.encoding, &array_in, None);
     let array_out = builder.build(storage_out.clone(), "/").unwrap();
+    if resumed {
+        // The output of another task may not have been created yet
+        match zarrs::array::Array::open(storage_out.clone(), "/") {
+            Ok(array_out_resumed) if array_out_resumed.metadata() != array_out.metadata() => {
+                anyhow::bail!(
+                    "the output {} of the interrupted run or another task has different metadata, it cannot be resumed",
+                    args.path_out
+                );
+            }
+            Err(_) if task.is_some() => {}
+            result => {
+                result?;
+            }
+        }
+    } else {
+        storage_out.erase_prefix(&StorePrefix::root())?;
+    }
     array_out.store_metadata().unwrap();
+    let checkpoint = match (checkpoint, task) {
+        (Some(checkpoint), Some(task)) => {
+            Some(checkpoint.with_task(task, array_out.chunk_grid_shape().unwrap()))
+        }
+        (checkpoint, _) => checkpoint,
+    };
+
+    let bar = ProgressBar::new(0);
+    bar.set_style(bar_style_run());
+    let progress_events = if args.progress_format == ProgressFormat::Json {
+        bar.set_draw_target(ProgressDrawTarget::hidden());
+        Some(ProgressEventWriter::new(args.progress_file.as_deref())?)
+    } else {
+        None
+    };
+    let chunk_out = array_out.chunk_array_representation(&vec![0; array_out.dimensionality()])?;
+    let progress_stage = ProgressStage::new(
+        "reencode".to_string(),
+        (chunk_out.num_elements_usize() * element_size(&chunk_out)) as u64,
+    );
+    let progress_callback = |stats: ProgressStats| {
+        if let Some(progress_events) = &progress_events {
+            progress_events.write(&progress_stage.event(&stats));
+        }
+        progress_callback(stats, &bar);
+    };
+    let progress_callback = ProgressCallback::new(&progress_callback);
 
     let (duration, duration_read, duration_write, bytes_decoded) = do_reencode(
         &array_in,
         &array_out,
         args.validate,
         args.concurrent_chunks,
+        checkpoint.as_ref(),
         &progress_callback,
     )?;
+    // The checkpoint of a task is kept until every task has finished
+    if let (Some(checkpoint), None) = (checkpoint, task) {
+        checkpoint.finish()?;
+    }
     bar.set_style(bar_style_finish());
     bar.finish_and_clear();
     let size_in = storage_in
@@ -171,5 +331,9 @@ fn main() -> anyhow::Result<()> {
         size_out / 1e6, // MB
         bytes_decoded / 1e6, // MB
     );
+
+    if let Some(zip_out) = zip_out {
+        zip_out.finish()?;
+    }
     Ok(())
 }
@@ -0,0 +1,229 @@
+//! Checkpoints of the chunks written to an output, so an interrupted run can be resumed.
+//!
+//! A checkpoint is a sidecar manifest with the indices of a completed chunk on each line.
+//! It is created when a run starts, appended to as each chunk is written, and removed when the run finishes.
+//! The checkpoint of a [`Task`] of an array job is kept until every task has finished, see [`finalize_tasks`](crate::task::finalize_tasks).
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use zarrs::array::ravel_indices;
+
+use crate::{
+    storage::{is_uri, uri_cache_dir},
+    task::Task,
+};
+
+/// The completed chunks of an output.
+pub struct Checkpoint {
+    path: PathBuf,
+    completed: HashSet<Vec<u64>>,
+    file: Mutex<std::fs::File>,
+    /// The linearised indices and the chunk grid shape of the output, if only some chunks are processed.
+    chunks: Option<(Range<u64>, Vec<u64>)>,
+}
+
+impl Checkpoint {
+    /// The path of the checkpoint manifest of an output.
+    ///
+    /// This is `<output>.checkpoint` next to a local output, or a file in the current directory named after a remote output.
+    #[must_use]
+    pub fn manifest_path(output: &str) -> PathBuf {
+        let mut path = if is_uri(output) {
+            uri_cache_dir(Path::new("."), output).into_os_string()
+        } else {
+            output.trim_end_matches('/').into()
+        };
+        path.push(".checkpoint");
+        path.into()
+    }
+
+    /// The path of the checkpoint manifest of a task of an array job writing to an output.
+    ///
+    /// This is `<output>.task-<index>-of-<count>.checkpoint` next to a local output.
+    #[must_use]
+    pub fn task_manifest_path(output: &str, task: &Task) -> PathBuf {
+        let mut path = Self::manifest_path(output);
+        path.set_extension(format!(
+            "task-{}-of-{}.checkpoint",
+            task.index(),
+            task.count()
+        ));
+        path
+    }
+
+    /// Only process the chunks of `task`, of an output with a chunk grid of `chunk_grid_shape`.
+    ///
+    /// The chunks of other tasks are skipped, see [`Checkpoint::is_skipped`].
+    #[must_use]
+    pub fn with_task(self, task: Task, chunk_grid_shape: Vec<u64>) -> Self {
+        let chunks = task.chunks(chunk_grid_shape.iter().product());
+        self.with_chunks(chunks, chunk_grid_shape)
+    }
+
+    /// Only process the chunks with linearised indices in `chunks`, of an output with a chunk grid of `chunk_grid_shape`.
+    ///
+    /// Other chunks are skipped, see [`Checkpoint::is_skipped`].
+    #[must_use]
+    pub fn with_chunks(mut self, chunks: Range<u64>, chunk_grid_shape: Vec<u64>) -> Self {
+        self.chunks = Some((chunks, chunk_grid_shape));
+        self
+    }
+
+    /// Start a new checkpoint at `path`, discarding any chunks completed by a previous run.
+    ///
+    /// # Errors
+    /// Returns an error if the manifest cannot be created.
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            completed: HashSet::new(),
+            file: Mutex::new(std::fs::File::create(path)?),
+            chunks: None,
+        })
+    }
+
+    /// Resume the checkpoint at `path` with the chunks completed by a previous run, or [`None`] if it does not exist.
+    ///
+    /// # Errors
+    /// Returns an error if the manifest cannot be read or is malformed.
+    pub fn resume(path: &Path) -> std::io::Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        // A line without a newline was interrupted while being written, and is ignored
+        let lines = contents.split('\n');
+        let num_lines = lines.clone().count() - 1;
+        let completed = lines
+            .take(num_lines)
+            .map(|line| {
+                line.split(',')
+                    .filter(|index| !index.is_empty())
+                    .map(str::parse::<u64>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("invalid checkpoint {}: {err}", path.display()),
+                        )
+                    })
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+        // Remove the interrupted line, so it is not completed by the next line written
+        let file = std::fs::OpenOptions::new().append(true).open(path)?;
+        file.set_len(contents.rfind('\n').map_or(0, |end| end + 1) as u64)?;
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            completed,
+            file: Mutex::new(file),
+            chunks: None,
+        }))
+    }
+
+    /// Returns the number of chunks completed by a previous run.
+    #[must_use]
+    pub fn num_completed(&self) -> usize {
+        self.completed.len()
+    }
+
+    /// Returns true if the chunk at `chunk_indices` was completed by a previous run.
+    #[must_use]
+    pub fn is_complete(&self, chunk_indices: &[u64]) -> bool {
+        self.completed.contains(chunk_indices)
+    }
+
+    /// Returns true if the chunk at `chunk_indices` is skipped, since it was completed by a previous run or it is not processed (e.g. it is a chunk of another task).
+    #[must_use]
+    pub fn is_skipped(&self, chunk_indices: &[u64]) -> bool {
+        self.is_complete(chunk_indices)
+            || self
+                .chunks
+                .as_ref()
+                .is_some_and(|(chunks, chunk_grid_shape)| {
+                    !chunks.contains(&ravel_indices(chunk_indices, chunk_grid_shape))
+                })
+    }
+
+    /// Record that the chunk at `chunk_indices` has been written.
+    ///
+    /// # Errors
+    /// Returns an error if the manifest cannot be written.
+    pub fn complete(&self, chunk_indices: &[u64]) -> std::io::Result<()> {
+        let line = chunk_indices
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+            + "\n";
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+
+    /// Finish the checkpoint, removing its manifest.
+    ///
+    /// # Errors
+    /// Returns an error if the manifest cannot be removed.
+    pub fn finish(self) -> std::io::Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let manifest = path.path().join("array.zarr.checkpoint");
+        assert!(Checkpoint::resume(&manifest)?.is_none());
+
+        let checkpoint = Checkpoint::create(&manifest)?;
+        checkpoint.complete(&[0, 1])?;
+        checkpoint.complete(&[2, 3])?;
+        drop(checkpoint);
+
+        // An interrupted line is ignored
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&manifest)?
+            .write_all(b"4,")?;
+        let checkpoint = Checkpoint::resume(&manifest)?.unwrap();
+        assert_eq!(checkpoint.num_completed(), 2);
+        assert!(checkpoint.is_complete(&[2, 3]));
+        assert!(!checkpoint.is_complete(&[4, 5]));
+        checkpoint.complete(&[4, 5])?;
+        drop(checkpoint);
+
+        let checkpoint = Checkpoint::resume(&manifest)?.unwrap();
+        assert_eq!(checkpoint.num_completed(), 3);
+        checkpoint.finish()?;
+        assert!(!manifest.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_path() {
+        assert_eq!(
+            Checkpoint::manifest_path("data/array.zarr/"),
+            PathBuf::from("data/array.zarr.checkpoint")
+        );
+        assert_eq!(
+            Checkpoint::manifest_path("s3://bucket/array.zarr"),
+            PathBuf::from("./s3___bucket_array.zarr.checkpoint")
+        );
+        assert_eq!(
+            Checkpoint::task_manifest_path("data/array.zarr/", &Task::new(3, 8).unwrap()),
+            PathBuf::from("data/array.zarr.task-3-of-8.checkpoint")
+        );
+    }
+}
@@ -1,55 +1,311 @@
+//! Filters (transformations) of arrays, and the pipeline runner of `zarrs_filter`.
+//!
+//! A filter implements [`FilterTraits`], and is configured by a [`FilterCommand`] parsed from the command line or a run configuration (see [`read_pipeline`]).
+//! A [`Pipeline`] runs a sequence of filters, with the outputs of earlier filters as the inputs of later filters, and reports its progress to a [`PipelineObserver`].
+//!
+//! The arrays of a filter are in any [`FilterStorage`], and are opened from a local path or a remote URI with [`load_array`] and [`create_array`].
+
+mod adaptive_concurrency;
 mod array_subset_overlap;
+mod device;
 mod filter_arguments;
 mod filter_command;
 mod filter_common_arguments;
 mod filter_error;
 mod filter_input_output_arguments;
+mod filter_subset;
 mod filter_traits;
+mod fused_filter;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod interpolation;
 mod kernel;
+mod memory_limit;
 mod path_or_identifier;
 mod path_or_temp_path;
+mod pipeline;
+mod runner;
+mod simd;
+mod time_units;
 // mod chunk_cache;
 
 pub mod filters {
+    pub mod affine;
+    pub mod argmax;
+    pub mod arith;
+    pub mod binary_op;
+    pub mod cast;
     pub mod clamp;
+    pub mod comparison;
+    pub mod complex;
+    pub mod convolve;
     pub mod crop;
+    pub mod crop_nonzero;
+    pub mod cumsum;
+    pub mod derivative;
+    pub mod distance_transform;
     pub mod downsample;
     pub mod equal;
+    pub mod equalize_histogram;
+    pub mod expr;
+    pub mod external;
+    pub mod fft;
+    pub mod fillnan;
+    pub mod find_boundaries;
+    pub mod finite;
+    pub mod flip;
     pub mod gaussian;
     pub mod gradient_magnitude;
     pub mod guided_filter;
+    pub mod label;
+    pub mod local_variance;
+    pub mod magnitude;
+    pub mod mask;
+    pub mod median;
+    pub mod morphology;
+    pub mod noise;
+    pub mod normalize;
+    pub mod one_hot;
+    pub mod otsu;
+    pub mod pad;
+    pub mod project;
+    pub mod rank;
+    pub mod reduce;
     pub mod reencode;
+    pub mod remap;
     pub mod replace_value;
+    pub mod resample;
     pub mod rescale;
+    pub mod rescale_intensity;
+    pub mod roll;
+    pub mod sample;
+    pub mod skeletonize;
+    pub mod standardize;
+    pub mod stitch;
     pub mod summed_area_table;
+    pub mod tile;
+    pub mod transpose;
+    pub mod unsharp;
+    pub mod upsample;
+    pub mod watershed;
+    pub mod where_op;
 }
 
+pub use adaptive_concurrency::{AdaptiveConcurrency, AdaptivePermit};
 pub use array_subset_overlap::ArraySubsetOverlap;
+pub use device::{device, gpu_available, gpu_correlate_axes, set_device, Correlate1d, Device};
 pub use filter_arguments::FilterArguments;
 pub use filter_command::{FilterCommand, FilterCommandTraits};
 pub use filter_common_arguments::FilterCommonArguments;
 pub use filter_error::FilterError;
 pub use filter_input_output_arguments::FilterInputOutputArguments;
+pub use filter_subset::{copy_array_subset, FilterSubset};
 pub use filter_traits::FilterTraits;
+pub use fused_filter::{FusedFilter, FusedStage};
+pub use interpolation::{AxisTaps, Interpolation};
+pub use kernel::BorderMode;
+pub use memory_limit::{memory_budget, memory_limit, parse_memory_size, set_memory_limit};
 pub use path_or_identifier::PathOrIdentifier;
 pub use path_or_temp_path::PathOrTempPath;
+pub use pipeline::{read_pipeline, validate_pipeline};
+pub use runner::{
+    async_array, create_array, load_array, load_output_array, resume_array, FilterPlan,
+    OutputExists, Pipeline, PipelineObserver, PipelineOptions, DETERMINISTIC_SEED,
+};
+pub use simd::{simd_avx2, simd_clamp, simd_map, simd_min_max};
+pub use time_units::{resolve_time_value, TimeUnit, TimeUnits, NOT_A_TIME, UNITS_ATTRIBUTE};
 // pub use chunk_cache::{ChunkCache, retrieve_array_subset_ndarray_cached};
 
-use sysinfo::{MemoryRefreshKind, RefreshKind, System};
+use zarrs::{
+    array::{
+        codec::array_to_bytes::sharding, data_type::UnsupportedDataTypeError, Array, ArrayError,
+        ChunkRepresentation, ChunkShape, DataType, ElementOwned,
+    },
+    array_subset::ArraySubset,
+    storage::{AsyncReadableWritableListableStorageTraits, ReadableWritableStorageTraits},
+};
+
+use crate::progress::Progress;
+
+/// The storage of the arrays read and written by filters.
+///
+/// Filters are not tied to a store, so inputs and outputs can be in a filesystem, in memory, or remote.
+pub type FilterStorage = dyn ReadableWritableStorageTraits;
+
+/// The asynchronous storage of the arrays read and written by filters run with [`FusedFilter::apply_async`].
+pub type AsyncFilterStorage = dyn AsyncReadableWritableListableStorageTraits;
 
-/// Calculates the chunk limit based on the amount of available memory.
+/// The estimated average number of bytes in a variable sized element (e.g. a `string`).
+const VARIABLE_ELEMENT_BYTES_ESTIMATE: usize = 32;
+
+/// Calculates the chunk limit based on the [`memory_budget`].
 pub fn calculate_chunk_limit(memory_per_chunk: usize) -> Result<usize, FilterError> {
-    let system = System::new_with_specifics(
-        RefreshKind::new().with_memory(MemoryRefreshKind::new().with_ram()),
-    );
-    let available_memory = usize::try_from(system.available_memory()).unwrap();
-    let available_memory_target = available_memory * 8 / 10; // 80%
-    let chunk_limit = available_memory_target / memory_per_chunk;
+    let chunk_limit = memory_budget() / memory_per_chunk;
     if chunk_limit == 0 {
         Err(FilterError::Other(
-            "There is not enough available memory to process a single output chunk. Consider reducing the chunk shape (or shard shape if sharding)".to_string(),
+            "There is not enough available memory to process a single output chunk. Consider reducing the chunk shape (or shard shape if sharding) or increasing the memory limit".to_string(),
         ))
     } else {
         Ok(chunk_limit)
     }
 }
+
+/// Returns the (estimated) in-memory size of an element of a chunk.
+///
+/// Variable sized elements are held as a [`String`] or [`Vec<u8>`] with an estimated number of bytes.
+pub fn element_size(chunk_representation: &ChunkRepresentation) -> usize {
+    chunk_representation
+        .fixed_element_size()
+        .unwrap_or(core::mem::size_of::<String>() + VARIABLE_ELEMENT_BYTES_ESTIMATE)
+}
+
+/// Returns the inner chunk shape of an array encoded with the sharding codec, or [`None`] if it is not sharded.
+///
+/// The inner chunks of a shard can be decoded without decoding the whole shard.
+pub fn inner_chunk_shape<TStorage: ?Sized>(array: &Array<TStorage>) -> Option<ChunkShape> {
+    let codecs = array.codecs();
+    if !codecs.array_to_array_codecs().is_empty() {
+        // The inner chunks are not aligned with the array
+        return None;
+    }
+    let metadata = codecs.array_to_bytes_codec().create_metadata()?;
+    if metadata.name() != sharding::IDENTIFIER {
+        return None;
+    }
+    let configuration: sharding::ShardingCodecConfigurationV1 = metadata.to_configuration().ok()?;
+    Some(configuration.chunk_shape)
+}
+
+/// Retrieves the elements in an array subset and converts them to [`f64`].
+pub fn retrieve_array_subset_elements_f64(
+    array: &Array<FilterStorage>,
+    array_subset: &ArraySubset,
+) -> Result<Vec<f64>, FilterError> {
+    macro_rules! retrieve {
+        ([$( ( $data_type:ident, $t:ty ) ),* ]) => {
+            match array.data_type() {
+                $(DataType::$data_type => {
+                    let elements = array.retrieve_array_subset_elements::<$t>(array_subset)?;
+                    Ok(elements.into_iter().map(num_traits::AsPrimitive::<f64>::as_).collect())
+                } ,)*
+                data_type => Err(zarrs::array::data_type::UnsupportedDataTypeError::from(data_type.to_string()))?
+            }
+        };
+    }
+    retrieve!([
+        (Bool, u8),
+        (Int8, i8),
+        (Int16, i16),
+        (Int32, i32),
+        (Int64, i64),
+        (UInt8, u8),
+        (UInt16, u16),
+        (UInt32, u32),
+        (UInt64, u64),
+        (BFloat16, half::bf16),
+        (Float16, half::f16),
+        (Float32, f32),
+        (Float64, f64)
+    ])
+}
+
+/// Retrieves an array subset as an [`ndarray::ArrayD`] and converts the elements to [`f64`].
+///
+/// This is typically used to read the haloed input subset of an [`ArraySubsetOverlap`].
+pub fn retrieve_array_subset_ndarray_f64(
+    array: &Array<FilterStorage>,
+    array_subset: &ArraySubset,
+) -> Result<ndarray::ArrayD<f64>, FilterError> {
+    let elements = retrieve_array_subset_elements_f64(array, array_subset)?;
+    let shape = array_subset
+        .shape()
+        .iter()
+        .map(|s| usize::try_from(*s).unwrap())
+        .collect::<Vec<_>>();
+    Ok(ndarray::ArrayD::from_shape_vec(shape, elements).unwrap())
+}
+
+/// Retrieves the input elements of the output chunk at `chunk_indices`, extended by `halo` elements beyond each side of each axis.
+///
+/// The halo is truncated at the boundaries of `input`, see [`ArraySubsetOverlap::truncated_overlap`].
+/// Returns the output chunk subset, its overlap with the input, and the haloed input elements.
+///
+/// # Errors
+/// Returns an error if the output chunk is out of bounds or `input` cannot be read.
+pub fn retrieve_chunk_haloed_ndarray<T: ElementOwned>(
+    input: &Array<FilterStorage>,
+    output: &Array<FilterStorage>,
+    chunk_indices: &[u64],
+    halo: &[u64],
+    progress: &Progress,
+) -> Result<(ArraySubset, ArraySubsetOverlap, ndarray::ArrayD<T>), FilterError> {
+    let subset_output = output.chunk_subset_bounded(chunk_indices)?;
+    let subset_overlap = ArraySubsetOverlap::new(input.shape(), &subset_output, halo);
+    let input_array = progress
+        .read(|| input.retrieve_array_subset_ndarray::<T>(subset_overlap.subset_input()))?;
+    Ok((subset_output, subset_overlap, input_array))
+}
+
+/// Returns the number of elements of a chunk with `chunk_shape` extended by `halo` beyond each side of each axis.
+pub fn num_elements_haloed(chunk_shape: &[std::num::NonZeroU64], halo: &[u64]) -> usize {
+    usize::try_from(
+        std::iter::zip(chunk_shape, halo)
+            .map(|(shape, halo)| shape.get() + 2 * halo)
+            .product::<u64>(),
+    )
+    .unwrap()
+}
+
+/// Convert element bytes to a [`Vec`] of elements.
+pub fn elements_from_bytes<T: bytemuck::Pod>(bytes: &[u8]) -> Vec<T> {
+    bytes
+        .chunks_exact(core::mem::size_of::<T>())
+        .map(bytemuck::pod_read_unaligned)
+        .collect()
+}
+
+/// Convert elements to element bytes.
+pub fn elements_to_bytes<T: bytemuck::Pod>(elements: &[T]) -> Vec<u8> {
+    bytemuck::cast_slice(elements).to_vec()
+}
+
+/// Retrieves an array subset as an [`ndarray::ArrayD<u8>`] of element bytes, with a trailing axis of the element size.
+///
+/// This treats the elements of a fixed size data type (e.g. raw bits `r*`) as opaque byte blobs.
+pub fn retrieve_array_subset_ndarray_bytes(
+    array: &Array<FilterStorage>,
+    array_subset: &ArraySubset,
+) -> Result<ndarray::ArrayD<u8>, FilterError> {
+    let element_size = array
+        .data_type()
+        .fixed_size()
+        .ok_or_else(|| UnsupportedDataTypeError::from(array.data_type().to_string()))?;
+    let bytes = array
+        .retrieve_array_subset(array_subset)?
+        .into_fixed()
+        .map_err(ArrayError::from)?
+        .into_owned();
+    let shape = array_subset
+        .shape()
+        .iter()
+        .map(|s| usize::try_from(*s).unwrap())
+        .chain(std::iter::once(element_size))
+        .collect::<Vec<_>>();
+    Ok(ndarray::ArrayD::from_shape_vec(shape, bytes).unwrap())
+}
+
+/// Stores an [`ndarray::ArrayD<u8>`] of element bytes from [`retrieve_array_subset_ndarray_bytes`] at `subset_start`.
+pub fn store_array_subset_ndarray_bytes(
+    array: &Array<FilterStorage>,
+    subset_start: &[u64],
+    bytes: ndarray::ArrayD<u8>,
+) -> Result<(), FilterError> {
+    let (_, subset_shape) = bytes.shape().split_last().unwrap();
+    let array_subset = ArraySubset::new_with_start_shape(
+        subset_start.to_vec(),
+        subset_shape.iter().map(|&s| s as u64).collect(),
+    )
+    .unwrap();
+    let bytes = bytes.as_standard_layout().into_owned().into_raw_vec();
+    Ok(array.store_array_subset(&array_subset, bytes)?)
+}
@@ -0,0 +1,200 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::memory_budget;
+
+/// The minimum duration of a window that throughput is measured over.
+const WINDOW: Duration = Duration::from_millis(250);
+
+/// The relative change in throughput between windows that is considered an improvement.
+const IMPROVEMENT: f64 = 0.05;
+
+/// Adapts the number of chunks processed concurrently to the observed throughput and memory headroom.
+///
+/// The concurrency starts at one chunk and doubles while the throughput improves, then it is increased or decreased by one chunk at a time towards a higher throughput.
+/// It never exceeds the maximum concurrency (e.g. the static chunk limit from [`calculate_chunk_limit`](super::calculate_chunk_limit)), and it is decreased if the [`memory_budget`] cannot fit another chunk.
+///
+/// Each chunk is processed while holding an [`AdaptivePermit`] from [`AdaptiveConcurrency::acquire`].
+pub struct AdaptiveConcurrency {
+    max_concurrency: usize,
+    memory_per_chunk: usize,
+    state: Mutex<AdaptiveState>,
+    condvar: Condvar,
+}
+
+/// The state of an [`AdaptiveConcurrency`].
+#[derive(Debug)]
+struct AdaptiveState {
+    /// The number of chunks that can be processed concurrently.
+    concurrency: usize,
+    /// The number of chunks being processed.
+    active: usize,
+    /// Whether the concurrency is doubled while the throughput improves.
+    slow_start: bool,
+    /// The direction the concurrency is changed in after the slow start, +1 or -1.
+    direction: isize,
+    /// The throughput of the previous window in bytes per second.
+    throughput: Option<f64>,
+    window_start: Instant,
+    window_bytes: u64,
+    window_chunks: usize,
+}
+
+impl AdaptiveState {
+    /// Adjust the concurrency given the `throughput` of the last window and whether another chunk fits in the memory budget.
+    fn adjust(&mut self, throughput: f64, headroom: bool, max_concurrency: usize) {
+        let improved = match self.throughput {
+            Some(previous) => throughput > previous * (1.0 + IMPROVEMENT),
+            None => true,
+        };
+        let concurrency = if !headroom {
+            self.slow_start = false;
+            self.direction = -1;
+            self.concurrency.saturating_sub(1)
+        } else if self.slow_start {
+            if improved {
+                self.concurrency * 2
+            } else {
+                // Back off from a concurrency past the peak throughput
+                self.slow_start = false;
+                self.direction = -1;
+                self.concurrency - 1
+            }
+        } else {
+            if !improved {
+                self.direction = -self.direction;
+            }
+            self.concurrency.saturating_add_signed(self.direction)
+        };
+        self.concurrency = concurrency.clamp(1, max_concurrency.max(1));
+        self.throughput = Some(throughput);
+    }
+}
+
+/// A permit to process a chunk with an [`AdaptiveConcurrency`].
+///
+/// The chunk is released when the permit is dropped, and its bytes are recorded with [`AdaptivePermit::finish`].
+pub struct AdaptivePermit<'a> {
+    controller: &'a AdaptiveConcurrency,
+}
+
+impl AdaptivePermit<'_> {
+    /// Record that the chunk has been processed, with `bytes` (e.g. its decoded size) counted towards the throughput.
+    pub fn finish(self, bytes: u64) {
+        self.controller.finish(bytes);
+    }
+}
+
+impl Drop for AdaptivePermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.controller.state.lock().unwrap();
+        state.active -= 1;
+        drop(state);
+        self.controller.condvar.notify_one();
+    }
+}
+
+impl AdaptiveConcurrency {
+    /// Create a new adaptive concurrency controller with at most `max_concurrency` chunks of `memory_per_chunk` bytes processed concurrently.
+    #[must_use]
+    pub fn new(max_concurrency: usize, memory_per_chunk: usize) -> Self {
+        Self {
+            max_concurrency: max_concurrency.max(1),
+            memory_per_chunk,
+            state: Mutex::new(AdaptiveState {
+                concurrency: 1,
+                active: 0,
+                slow_start: true,
+                direction: 1,
+                throughput: None,
+                window_start: Instant::now(),
+                window_bytes: 0,
+                window_chunks: 0,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Returns the number of chunks that can currently be processed concurrently.
+    pub fn concurrency(&self) -> usize {
+        self.state.lock().unwrap().concurrency
+    }
+
+    /// Wait until a chunk can be processed, and return a permit to process it.
+    pub fn acquire(&self) -> AdaptivePermit<'_> {
+        let mut state = self.state.lock().unwrap();
+        while state.active >= state.concurrency {
+            state = self.condvar.wait(state).unwrap();
+        }
+        state.active += 1;
+        AdaptivePermit { controller: self }
+    }
+
+    fn finish(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.window_bytes += bytes;
+        state.window_chunks += 1;
+        let elapsed = state.window_start.elapsed();
+        // A window spans at least as many chunks as can be processed concurrently, so it reflects the concurrency
+        if elapsed < WINDOW || state.window_chunks < state.concurrency {
+            return;
+        }
+        let throughput = state.window_bytes as f64 / elapsed.as_secs_f64();
+        let headroom = memory_budget() >= self.memory_per_chunk;
+        let concurrency = state.concurrency;
+        state.adjust(throughput, headroom, self.max_concurrency);
+        state.window_start = Instant::now();
+        state.window_bytes = 0;
+        state.window_chunks = 0;
+        let increased = state.concurrency > concurrency;
+        drop(state);
+        if increased {
+            self.condvar.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptive_concurrency() {
+        let controller = AdaptiveConcurrency::new(8, 0);
+        let mut state = controller.state.lock().unwrap();
+
+        // Slow start while the throughput improves
+        state.adjust(100.0, true, 8);
+        assert_eq!(state.concurrency, 2);
+        state.adjust(200.0, true, 8);
+        assert_eq!(state.concurrency, 4);
+        state.adjust(400.0, true, 8);
+        assert_eq!(state.concurrency, 8);
+        state.adjust(1600.0, true, 8);
+        assert_eq!(state.concurrency, 8);
+
+        // Back off when the throughput stops improving
+        state.adjust(1600.0, true, 8);
+        assert_eq!(state.concurrency, 7);
+
+        // Then step towards a higher throughput
+        state.adjust(1800.0, true, 8);
+        assert_eq!(state.concurrency, 6);
+        state.adjust(1700.0, true, 8);
+        assert_eq!(state.concurrency, 7);
+
+        // Decrease without memory headroom
+        state.adjust(2000.0, false, 8);
+        assert_eq!(state.concurrency, 6);
+        drop(state);
+
+        // Permits are limited by the concurrency
+        let controller = AdaptiveConcurrency::new(8, 0);
+        let permit = controller.acquire();
+        assert_eq!(controller.state.lock().unwrap().active, 1);
+        permit.finish(1);
+        assert_eq!(controller.state.lock().unwrap().active, 0);
+    }
+}
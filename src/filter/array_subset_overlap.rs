@@ -36,10 +36,64 @@ impl ArraySubsetOverlap {
         }
     }
 
+    /// Create an overlap from `subset_src` and a (possibly asymmetric) input subset that contains it.
+    ///
+    /// # Panics
+    /// Panics if `subset_src_overlap` does not contain `subset_src`.
+    pub fn new_with_subset_input(
+        subset_src: &ArraySubset,
+        subset_src_overlap: ArraySubset,
+    ) -> Self {
+        assert!(itertools::izip!(
+            subset_src.start(),
+            subset_src.end_exc(),
+            subset_src_overlap.start(),
+            subset_src_overlap.end_exc()
+        )
+        .all(
+            |(start, end, overlap_start, overlap_end)| overlap_start <= start && end <= overlap_end
+        ));
+        let offset_dst = std::iter::zip(subset_src.start(), subset_src_overlap.start())
+            .map(|(start, overlap_start)| start - overlap_start)
+            .collect::<Vec<u64>>();
+        let subset_dst_in_src = unsafe {
+            ArraySubset::new_with_start_shape_unchecked(offset_dst, subset_src.shape().to_vec())
+        };
+
+        ArraySubsetOverlap {
+            subset_src_overlap,
+            subset_dst_in_src,
+        }
+    }
+
     pub fn subset_input(&self) -> &ArraySubset {
         &self.subset_src_overlap
     }
 
+    /// The output subset relative to the start of the [input subset](Self::subset_input).
+    pub fn subset_output_in_input(&self) -> &ArraySubset {
+        &self.subset_dst_in_src
+    }
+
+    /// The amount of `overlap` before and after each axis that is missing from the input subset due to the array boundaries.
+    ///
+    /// This is the padding required to extend the input subset to the full overlap.
+    pub fn truncated_overlap(&self, overlap: &[u64]) -> Vec<(usize, usize)> {
+        itertools::izip!(
+            overlap,
+            self.subset_dst_in_src.start(),
+            self.subset_dst_in_src.end_exc(),
+            self.subset_src_overlap.shape()
+        )
+        .map(|(overlap, start, end, shape)| {
+            (
+                usize::try_from(overlap - start).unwrap(),
+                usize::try_from(overlap - (shape - end)).unwrap(),
+            )
+        })
+        .collect()
+    }
+
     pub fn extract_subset<T: Clone>(&self, array: &ArrayD<T>) -> ArrayD<T> {
         let slices: Vec<SliceInfoElem> = std::iter::zip(
             self.subset_dst_in_src.start(),
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::FilterError;
+
+/// The device that compute-heavy filters (e.g. gaussian, convolve) dispatch their per-chunk kernels on.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Device {
+    /// The GPU if available, otherwise the CPU
+    Auto,
+    /// The CPU
+    #[default]
+    Cpu,
+    /// The GPU (requires the `gpu` feature)
+    Gpu,
+}
+
+/// The process-wide device.
+static DEVICE: AtomicU8 = AtomicU8::new(Device::Cpu as u8);
+
+/// Set the process-wide device of compute-heavy filters.
+///
+/// # Errors
+/// Returns [`FilterError::InvalidParameters`] if the device is [`Device::Gpu`] and no GPU is available.
+pub fn set_device(device: Device) -> Result<(), FilterError> {
+    if device == Device::Gpu && !gpu_available() {
+        return Err(FilterError::InvalidParameters(if cfg!(feature = "gpu") {
+            "no GPU is available".to_string()
+        } else {
+            "GPU support requires the gpu feature".to_string()
+        }));
+    }
+    DEVICE.store(device as u8, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns the process-wide device of compute-heavy filters.
+pub fn device() -> Device {
+    match DEVICE.load(Ordering::SeqCst) {
+        device if device == Device::Auto as u8 => Device::Auto,
+        device if device == Device::Gpu as u8 => Device::Gpu,
+        _ => Device::Cpu,
+    }
+}
+
+/// Returns true if a GPU is available.
+///
+/// The GPU is initialised on the first call.
+pub fn gpu_available() -> bool {
+    #[cfg(feature = "gpu")]
+    {
+        super::gpu::gpu_available()
+    }
+    #[cfg(not(feature = "gpu"))]
+    {
+        false
+    }
+}
+
+/// A 1D correlation along an axis, a pass of [`gpu_correlate_axes`].
+///
+/// An output element at index `k` along `axis` is the sum of `kernel[t] * input[k + t - offset]`, where input indices are clamped to the axis.
+#[derive(Debug, Clone)]
+pub struct Correlate1d {
+    /// The axis.
+    pub axis: usize,
+    /// The kernel weights.
+    pub kernel: Vec<f32>,
+    /// The offset of the kernel.
+    pub offset: usize,
+    /// The length of the output along the axis.
+    pub output_len: usize,
+}
+
+/// Apply the 1D correlations `passes` to `input` in order on the GPU.
+///
+/// Returns [`None`] if the [`device`] is the CPU or the GPU is unavailable or fails (e.g. the chunk exceeds its buffer size), so the caller falls back to the CPU.
+pub fn gpu_correlate_axes(
+    input: &ndarray::ArrayD<f32>,
+    passes: &[Correlate1d],
+) -> Option<ndarray::ArrayD<f32>> {
+    if device() == Device::Cpu || !gpu_available() {
+        return None;
+    }
+    #[cfg(feature = "gpu")]
+    {
+        super::gpu::correlate_axes(input, passes).ok()
+    }
+    #[cfg(not(feature = "gpu"))]
+    {
+        let _ = (input, passes);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_cpu() {
+        assert_eq!(device(), Device::Cpu);
+        let input = ndarray::ArrayD::<f32>::zeros(vec![4, 4]);
+        let passes = [Correlate1d {
+            axis: 0,
+            kernel: vec![1.0],
+            offset: 0,
+            output_len: 4,
+        }];
+        assert!(gpu_correlate_axes(&input, &passes).is_none());
+        if !gpu_available() {
+            assert!(set_device(Device::Gpu).is_err());
+        }
+    }
+}
@@ -1,8 +1,23 @@
-use super::{FilterCommonArguments, FilterError, FilterTraits};
+use super::{FilterCommonArguments, FilterError, FilterTraits, PathOrIdentifier};
 
 pub trait FilterArguments {
     fn name(&self) -> String;
 
+    /// Returns the paths or identifiers of any inputs in addition to the primary input.
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        vec![]
+    }
+
+    /// Returns true if the additional inputs must have the same shape as the primary input.
+    fn additional_inputs_match_input_shape(&self) -> bool {
+        true
+    }
+
+    /// Returns the random number generator seed of a stochastic filter, which is fixed in a deterministic run if unset.
+    fn seed_mut(&mut self) -> Option<&mut Option<u64>> {
+        None
+    }
+
     fn init(
         &self,
         common_args: &FilterCommonArguments,
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{
     filter_error::FilterError, filter_traits::FilterTraits, filters, FilterArguments,
-    FilterCommonArguments, FilterInputOutputArguments,
+    FilterCommonArguments, FilterInputOutputArguments, PathOrIdentifier,
 };
 
 #[derive(Debug, Clone, Parser, Deserialize)]
@@ -28,8 +28,11 @@ pub trait FilterCommandTraits {
         serde_json::to_string(&self.common_args().reencode()).unwrap()
     }
     fn io_args(&self) -> &FilterInputOutputArguments;
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier>;
+    fn additional_inputs_match_input_shape(&self) -> bool;
     fn common_args(&self) -> &FilterCommonArguments;
     fn common_args_mut(&mut self) -> &mut FilterCommonArguments;
+    fn seed_mut(&mut self) -> Option<&mut Option<u64>>;
     fn init(&self) -> Result<Box<dyn FilterTraits>, FilterError>;
 }
 
@@ -48,6 +51,14 @@ impl<TArgs: FilterArguments + Serialize + clap::Args> FilterCommandTraits
         &self.input_output
     }
 
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        self.args.additional_inputs()
+    }
+
+    fn additional_inputs_match_input_shape(&self) -> bool {
+        self.args.additional_inputs_match_input_shape()
+    }
+
     fn common_args(&self) -> &FilterCommonArguments {
         &self.common_args
     }
@@ -56,6 +67,10 @@ impl<TArgs: FilterArguments + Serialize + clap::Args> FilterCommandTraits
         &mut self.common_args
     }
 
+    fn seed_mut(&mut self) -> Option<&mut Option<u64>> {
+        self.args.seed_mut()
+    }
+
     fn init(&self) -> Result<Box<dyn FilterTraits>, FilterError> {
         self.args.init(&self.common_args)
     }
@@ -75,6 +90,18 @@ pub enum FilterCommand {
     Clamp(FilterCombinedArgs<filters::clamp::ClampArguments>),
     /// Return a binary image where the input is equal to some value.
     Equal(FilterCombinedArgs<filters::equal::EqualArguments>),
+    /// Return a binary image where the input is not equal to some value.
+    #[command(name = "notequal")]
+    #[serde(rename = "notequal")]
+    NotEqual(FilterCombinedArgs<filters::equal::NotEqualArguments>),
+    /// Return a binary image where the input is greater than some value.
+    Greater(FilterCombinedArgs<filters::comparison::GreaterArguments>),
+    /// Return a binary image where the input is greater than or equal to some value.
+    GreaterEqual(FilterCombinedArgs<filters::comparison::GreaterEqualArguments>),
+    /// Return a binary image where the input is less than some value.
+    Less(FilterCombinedArgs<filters::comparison::LessArguments>),
+    /// Return a binary image where the input is less than or equal to some value.
+    LessEqual(FilterCombinedArgs<filters::comparison::LessEqualArguments>),
     /// Downsample an image given a stride.
     Downsample(FilterCombinedArgs<filters::downsample::DownsampleArguments>),
     /// Compute the gradient magnitude (sobel).
@@ -87,4 +114,129 @@ pub enum FilterCommand {
     GuidedFilter(FilterCombinedArgs<filters::guided_filter::GuidedFilterArguments>),
     /// Replace a value with another value.
     ReplaceValue(FilterCombinedArgs<filters::replace_value::ReplaceValueArguments>),
+    /// Apply an arithmetic operation with a scalar operand.
+    Arith(FilterCombinedArgs<filters::arith::ArithArguments>),
+    /// Apply an element-wise binary operation between the input and another array.
+    BinaryOp(FilterCombinedArgs<filters::binary_op::BinaryOpArguments>),
+    /// Evaluate an expression for each element.
+    Expr(FilterCombinedArgs<filters::expr::ExprArguments>),
+    /// Cast to the output data type with configurable rounding, overflow, and NaN handling.
+    Cast(FilterCombinedArgs<filters::cast::CastArguments>),
+    /// Replace multiple values with replacements in one pass.
+    Remap(FilterCombinedArgs<filters::remap::RemapArguments>),
+    /// Replace NaN/Inf with a constant, the nearest finite value, or linear interpolation along an axis.
+    #[command(name = "fillnan")]
+    #[serde(rename = "fillnan")]
+    FillNan(FilterCombinedArgs<filters::fillnan::FillNanArguments>),
+    /// Rescale values to [0, 1] or a target range.
+    Normalize(FilterCombinedArgs<filters::normalize::NormalizeArguments>),
+    /// Standardize values to zero mean and unit variance.
+    Standardize(FilterCombinedArgs<filters::standardize::StandardizeArguments>),
+    /// Clip to lower/upper percentiles and linearly stretch to the output data type range.
+    RescaleIntensity(FilterCombinedArgs<filters::rescale_intensity::RescaleIntensityArguments>),
+    /// Histogram equalization, optionally contrast limited and adaptive (tiled).
+    EqualizeHistogram(FilterCombinedArgs<filters::equalize_histogram::EqualizeHistogramArguments>),
+    /// Apply a median filter.
+    Median(FilterCombinedArgs<filters::median::MedianArguments>),
+    /// Apply a rank (percentile) filter.
+    Rank(FilterCombinedArgs<filters::rank::RankArguments>),
+    /// Convolve with a separable 1D kernel or an ND kernel.
+    Convolve(FilterCombinedArgs<filters::convolve::ConvolveArguments>),
+    /// Compute a Sobel, Laplacian, or central difference derivative.
+    Derivative(FilterCombinedArgs<filters::derivative::DerivativeArguments>),
+    /// Sharpen with an unsharp mask.
+    Unsharp(FilterCombinedArgs<filters::unsharp::UnsharpArguments>),
+    /// Compute the local variance or standard deviation within a window.
+    LocalVariance(FilterCombinedArgs<filters::local_variance::LocalVarianceArguments>),
+    /// Apply a morphological erosion, dilation, opening, or closing.
+    Morphology(FilterCombinedArgs<filters::morphology::MorphologyArguments>),
+    /// Label connected components.
+    Label(FilterCombinedArgs<filters::label::LabelArguments>),
+    /// Compute the distance transform of the foreground.
+    DistanceTransform(FilterCombinedArgs<filters::distance_transform::DistanceTransformArguments>),
+    /// Apply a marker-based watershed.
+    Watershed(FilterCombinedArgs<filters::watershed::WatershedArguments>),
+    /// Binarize (or multi-class threshold) with Otsu's method.
+    Otsu(FilterCombinedArgs<filters::otsu::OtsuArguments>),
+    /// Skeletonize (thin) a binary input.
+    Skeletonize(FilterCombinedArgs<filters::skeletonize::SkeletonizeArguments>),
+    /// Mark the boundaries between labels.
+    FindBoundaries(FilterCombinedArgs<filters::find_boundaries::FindBoundariesArguments>),
+    /// One-hot encode labels into a channel axis.
+    OneHot(FilterCombinedArgs<filters::one_hot::OneHotArguments>),
+    /// Upsample an image by integer or rational factors with nearest, linear, or cubic interpolation.
+    Upsample(FilterCombinedArgs<filters::upsample::UpsampleArguments>),
+    /// Resample to a target shape or pixel size with anti-aliased interpolation.
+    Resample(FilterCombinedArgs<filters::resample::ResampleArguments>),
+    /// Resample through an affine transform (e.g. a registration) into a target space.
+    Affine(FilterCombinedArgs<filters::affine::AffineArguments>),
+    /// Permute the axes of an array, e.g. to convert between XYZC and CZYX layouts.
+    Transpose(FilterCombinedArgs<filters::transpose::TransposeArguments>),
+    /// Reverse the order of elements along selected axes.
+    Flip(FilterCombinedArgs<filters::flip::FlipArguments>),
+    /// Shift the elements of an array along selected axes, wrapping around at the ends.
+    Roll(FilterCombinedArgs<filters::roll::RollArguments>),
+    /// Pad an array along each axis with a constant, reflection, or edge replication.
+    Pad(FilterCombinedArgs<filters::pad::PadArguments>),
+    /// Crop an array to the bounding box of its non fill value elements.
+    CropNonzero(FilterCombinedArgs<filters::crop_nonzero::CropNonzeroArguments>),
+    /// Write the input where a mask is true and the fill value elsewhere.
+    Mask(FilterCombinedArgs<filters::mask::MaskArguments>),
+    /// Select between two arrays or scalars based on a condition.
+    Where(FilterCombinedArgs<filters::where_op::WhereArguments>),
+    /// Reduce an array along one or more axes with a sum, mean, minimum, maximum, or standard deviation.
+    Reduce(FilterCombinedArgs<filters::reduce::ReduceArguments>),
+    /// Find the index of the maximum (or minimum) along an axis.
+    Argmax(FilterCombinedArgs<filters::argmax::ArgmaxArguments>),
+    /// Project an array along an axis, e.g. a maximum intensity projection, optionally over a slab.
+    Project(FilterCombinedArgs<filters::project::ProjectArguments>),
+    /// Compute the cumulative sum along an axis.
+    Cumsum(FilterCombinedArgs<filters::cumsum::CumsumArguments>),
+    /// Combine the components along a channel axis into their Euclidean norm, or another per-element reduction.
+    Magnitude(FilterCombinedArgs<filters::magnitude::MagnitudeArguments>),
+    /// Compute the magnitude (absolute value) of a complex (or real) input.
+    Abs(FilterCombinedArgs<filters::complex::AbsArguments>),
+    /// Compute the phase (argument) of a complex input in radians.
+    Phase(FilterCombinedArgs<filters::complex::PhaseArguments>),
+    /// Extract the real part of a complex input.
+    Real(FilterCombinedArgs<filters::complex::RealArguments>),
+    /// Extract the imaginary part of a complex input.
+    Imag(FilterCombinedArgs<filters::complex::ImagArguments>),
+    /// Compute the (inverse) fast Fourier transform along selected axes.
+    Fft(FilterCombinedArgs<filters::fft::FftArguments>),
+    /// Add Gaussian, Poisson, or salt-and-pepper noise.
+    Noise(FilterCombinedArgs<filters::noise::NoiseArguments>),
+    /// Keep a random sample of the non-fill elements, setting the rest to the fill value.
+    Sample(FilterCombinedArgs<filters::sample::SampleArguments>),
+    /// Stitch the input and additional inputs at offsets from a layout file into one output.
+    Stitch(FilterCombinedArgs<filters::stitch::StitchArguments>),
+    /// Return a binary image where the input is finite (not NaN or infinite).
+    #[command(name = "isfinite")]
+    #[serde(rename = "isfinite")]
+    IsFinite(FilterCombinedArgs<filters::finite::IsFiniteArguments>),
+    /// Return a binary image where the input is NaN.
+    #[command(name = "isnan")]
+    #[serde(rename = "isnan")]
+    IsNan(FilterCombinedArgs<filters::finite::IsNanArguments>),
+    /// Return a binary image where the input is positive or negative infinity.
+    #[command(name = "isinf")]
+    #[serde(rename = "isinf")]
+    IsInf(FilterCombinedArgs<filters::finite::IsInfArguments>),
+    /// Repeat an array along selected axes.
+    Tile(FilterCombinedArgs<filters::tile::TileArguments>),
+    /// Run an external program on each chunk, exchanging the chunk elements over stdin and stdout.
+    External(FilterCombinedArgs<filters::external::ExternalArguments>),
+}
+
+impl FilterCommand {
+    /// Returns true if the filter dispatches its kernels on the [`device`](super::device).
+    ///
+    /// Only the separable kernels of `gaussian` (and `unsharp`, which is built on it) and `convolve` (with a 1D kernel) run on the GPU, and all other filters run on the CPU.
+    #[must_use]
+    pub fn supports_gpu(&self) -> bool {
+        matches!(
+            self,
+            FilterCommand::Gaussian(_) | FilterCommand::Unsharp(_) | FilterCommand::Convolve(_)
+        )
+    }
 }
@@ -3,6 +3,8 @@ use serde::Deserialize;
 
 use crate::ZarrReencodingArgs;
 
+use super::{FilterError, FilterSubset};
+
 #[derive(Debug, Clone, Parser, Deserialize)]
 pub struct FilterCommonArguments {
     /// Reencoding args.
@@ -13,6 +15,18 @@ pub struct FilterCommonArguments {
     /// Inherits the top level arg if left unset.
     #[arg(long)]
     chunk_limit: Option<usize>,
+    /// Restrict the filter to a region of interest with this offset, comma delimited.
+    #[arg(long, value_delimiter = ',', requires = "subset_shape")]
+    #[serde(default)]
+    subset_offset: Option<Vec<u64>>,
+    /// Restrict the filter to a region of interest with this shape, comma delimited.
+    #[arg(long, value_delimiter = ',', requires = "subset_offset")]
+    #[serde(default)]
+    subset_shape: Option<Vec<u64>>,
+    /// Write an output with the shape of the input where the region outside of the subset is the fill value, rather than a cropped output.
+    #[arg(long, requires = "subset_offset")]
+    #[serde(default)]
+    subset_fill: bool,
 }
 
 impl FilterCommonArguments {
@@ -27,4 +41,22 @@ impl FilterCommonArguments {
     pub fn chunk_limit_mut(&mut self) -> &mut Option<usize> {
         &mut self.chunk_limit
     }
+
+    /// Returns the region of interest the filter is restricted to, if set.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if only one of the subset offset and shape is set, or they differ in length.
+    pub fn subset(&self) -> Result<Option<FilterSubset>, FilterError> {
+        match (&self.subset_offset, &self.subset_shape) {
+            (Some(offset), Some(shape)) if offset.len() == shape.len() => Ok(Some(FilterSubset {
+                offset: offset.clone(),
+                shape: shape.clone(),
+                fill: self.subset_fill,
+            })),
+            (None, None) if !self.subset_fill => Ok(None),
+            _ => Err(FilterError::InvalidParameters(
+                "the subset offset and shape must be set together with the same length".to_string(),
+            )),
+        }
+    }
 }
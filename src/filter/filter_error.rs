@@ -19,6 +19,8 @@ pub enum FilterError {
     InvalidParameters(String),
     #[error(transparent)]
     JSONError(#[from] serde_json::Error),
+    #[error(transparent)]
+    YAMLError(#[from] serde_yaml::Error),
     #[error("Unsupported data type {_0}")]
     UnsupportedDataType(#[from] UnsupportedDataTypeError),
     #[error(transparent)]
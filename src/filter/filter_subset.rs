@@ -0,0 +1,233 @@
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use zarrs::{
+    array::{Array, ArrayBuilder, ArrayError},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    get_array_builder_reencode,
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+use super::{calculate_chunk_limit, element_size, ArraySubsetOverlap, FilterError, FilterStorage};
+
+/// A region of interest that a filter is restricted to.
+///
+/// The filter is applied to a cropped copy of its input, and writes either a cropped output or a same-shaped output where the region outside the subset keeps the fill value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterSubset {
+    /// The offset of the subset per axis.
+    pub offset: Vec<u64>,
+    /// The shape of the subset per axis.
+    pub shape: Vec<u64>,
+    /// Write a same-shaped output rather than a cropped output.
+    pub fill: bool,
+}
+
+impl FilterSubset {
+    /// Returns the subset of an array with `array_shape`.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if the dimensionality does not match or the subset exceeds the array shape.
+    pub fn array_subset(&self, array_shape: &[u64]) -> Result<ArraySubset, FilterError> {
+        if self.offset.len() != array_shape.len() || self.shape.len() != array_shape.len() {
+            return Err(FilterError::InvalidParameters(format!(
+                "subset offset {:?} and shape {:?} do not match the dimensionality of the input shape {array_shape:?}",
+                self.offset, self.shape
+            )));
+        }
+        let array_subset =
+            ArraySubset::new_with_start_shape(self.offset.clone(), self.shape.clone()).unwrap();
+        if !array_subset.inbounds(array_shape) {
+            return Err(FilterError::InvalidParameters(format!(
+                "subset offset {:?} and shape {:?} exceed the input shape {array_shape:?}",
+                self.offset, self.shape
+            )));
+        }
+        Ok(array_subset)
+    }
+
+    /// Returns the subset of an array with `array_shape` extended by the `halo` of a neighbourhood filter.
+    ///
+    /// The halo is truncated at the array boundaries.
+    /// The [output subset in the input](ArraySubsetOverlap::subset_output_in_input) is the region of the filtered input subset that is within the subset.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if the subset is invalid or the halo does not match the dimensionality.
+    pub fn overlap(
+        &self,
+        array_shape: &[u64],
+        halo: Option<&[u64]>,
+    ) -> Result<ArraySubsetOverlap, FilterError> {
+        let array_subset = self.array_subset(array_shape)?;
+        let halo = halo.map_or_else(|| vec![0; array_shape.len()], <[u64]>::to_vec);
+        if halo.len() != array_shape.len() {
+            return Err(FilterError::InvalidParameters(format!(
+                "halo {halo:?} does not match the dimensionality of the input shape {array_shape:?}"
+            )));
+        }
+        Ok(ArraySubsetOverlap::new(array_shape, &array_subset, &halo))
+    }
+
+    /// Returns an array builder for `array_subset` of `array`, with the same encoding.
+    #[must_use]
+    pub fn array_builder(array: &Array<FilterStorage>, array_subset: &ArraySubset) -> ArrayBuilder {
+        get_array_builder_reencode(
+            &ZarrReencodingArgs::default(),
+            array,
+            Some(array_subset.shape().to_vec()),
+        )
+    }
+}
+
+/// Copy the elements of `input` in `input_subset` to `output` in `output_subset`, which must have the same shape.
+///
+/// Each chunk of `output` intersecting `output_subset` is written once, so the subsets need not be aligned to chunks.
+/// The elements of `output` outside of `output_subset` are unchanged.
+///
+/// # Errors
+/// Returns an error if the subsets differ in shape, or `input` cannot be read or `output` cannot be written.
+pub fn copy_array_subset(
+    input: &Array<FilterStorage>,
+    input_subset: &ArraySubset,
+    output: &Array<FilterStorage>,
+    output_subset: &ArraySubset,
+    chunk_limit: Option<usize>,
+    progress_callback: &ProgressCallback,
+) -> Result<(), FilterError> {
+    if input_subset.shape() != output_subset.shape() {
+        return Err(FilterError::InvalidParameters(format!(
+            "cannot copy {input_subset:?} to {output_subset:?} with a different shape"
+        )));
+    }
+    let chunks = output
+        .chunks_in_array_subset(output_subset)
+        .map_err(ArrayError::from)?
+        .unwrap_or_else(|| ArraySubset::new_empty(output.dimensionality()));
+    let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+    let chunk_limit = if let Some(chunk_limit) = chunk_limit {
+        chunk_limit
+    } else {
+        let chunk_output = output.chunk_array_representation(&vec![0; output.dimensionality()])?;
+        calculate_chunk_limit(chunk_output.num_elements_usize() * element_size(&chunk_output))?
+    };
+
+    let indices = chunks.indices();
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(
+        chunk_limit,
+        indices,
+        try_for_each,
+        |chunk_indices: Vec<u64>| {
+            let chunk_subset = output.chunk_subset_bounded(&chunk_indices)?;
+            let subset_output = chunk_subset
+                .overlap(output_subset)
+                .map_err(ArrayError::from)?;
+            let start_input = itertools::izip!(
+                subset_output.start(),
+                output_subset.start(),
+                input_subset.start()
+            )
+            .map(|(start, output_start, input_start)| start - output_start + input_start)
+            .collect();
+            let subset_input =
+                ArraySubset::new_with_start_shape(start_input, subset_output.shape().to_vec())
+                    .unwrap();
+            let bytes = progress.read(|| input.retrieve_array_subset(&subset_input))?;
+            progress.write(|| output.store_array_subset(&subset_output, bytes))?;
+            progress.next();
+            Ok::<_, FilterError>(())
+        }
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, sync::Arc};
+
+    use zarrs::{array::DataType, storage::store::MemoryStore};
+
+    use crate::progress::ProgressStats;
+
+    use super::*;
+
+    #[test]
+    fn filter_subset() -> Result<(), Box<dyn Error>> {
+        let array = |shape: Vec<u64>| {
+            let store: Arc<FilterStorage> = Arc::new(MemoryStore::new());
+            ArrayBuilder::new(
+                shape,
+                DataType::UInt8,
+                vec![3, 3].try_into().unwrap(),
+                0u8.into(),
+            )
+            .build(store, "/")
+            .unwrap()
+        };
+        let input = array(vec![6, 6]);
+        input.store_array_subset_elements(
+            &ArraySubset::new_with_shape(vec![6, 6]),
+            &(0..36).collect::<Vec<u8>>(),
+        )?;
+
+        let subset = FilterSubset {
+            offset: vec![1, 2],
+            shape: vec![2, 3],
+            fill: true,
+        };
+        assert!(subset.array_subset(&[2, 4]).is_err());
+        assert!(subset.array_subset(&[6]).is_err());
+        let array_subset = subset.array_subset(input.shape())?;
+
+        // The halo is truncated at the array boundaries
+        let overlap = subset.overlap(input.shape(), Some(&[2, 4]))?;
+        assert_eq!(
+            overlap.subset_input(),
+            &ArraySubset::new_with_start_shape(vec![0, 0], vec![5, 6])?
+        );
+        assert_eq!(
+            overlap.subset_output_in_input(),
+            &ArraySubset::new_with_start_shape(vec![1, 2], vec![2, 3])?
+        );
+        assert!(subset.overlap(input.shape(), Some(&[1])).is_err());
+
+        let progress_callback = |_stats: ProgressStats| {};
+        let progress_callback = ProgressCallback::new(&progress_callback);
+
+        // Crop the subset
+        let cropped = array(vec![2, 3]);
+        copy_array_subset(
+            &input,
+            &array_subset,
+            &cropped,
+            &ArraySubset::new_with_shape(vec![2, 3]),
+            None,
+            &progress_callback,
+        )?;
+        assert_eq!(
+            cropped
+                .retrieve_array_subset_elements::<u8>(&ArraySubset::new_with_shape(vec![2, 3]))?,
+            vec![8, 9, 10, 14, 15, 16]
+        );
+
+        // Write the subset back into a same-shaped output
+        let output = array(vec![6, 6]);
+        copy_array_subset(
+            &cropped,
+            &ArraySubset::new_with_shape(vec![2, 3]),
+            &output,
+            &array_subset,
+            None,
+            &progress_callback,
+        )?;
+        let elements = output
+            .retrieve_array_subset_elements::<u8>(&ArraySubset::new_with_shape(vec![6, 6]))?;
+        assert_eq!(elements[8..11], [8, 9, 10]);
+        assert_eq!(elements[14..17], [14, 15, 16]);
+        assert_eq!(elements.iter().filter(|&&e| e != 0).count(), 6);
+        Ok(())
+    }
+}
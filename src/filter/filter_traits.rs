@@ -1,15 +1,12 @@
-use zarrs::{
-    array::{Array, ArrayBuilder, ArrayShape, ChunkRepresentation, DataType, FillValue},
-    storage::store::FilesystemStore,
-};
+use zarrs::array::{Array, ArrayBuilder, ArrayShape, ChunkRepresentation, DataType, FillValue};
 
 use crate::{
     convert_fill_value, get_array_builder_reencode, progress::ProgressCallback, ZarrReencodingArgs,
 };
 
-use super::filter_error::FilterError;
+use super::{filter_error::FilterError, FilterStorage};
 
-pub trait FilterTraits {
+pub trait FilterTraits: Send + Sync {
     /// Checks if the input and output are compatible.
     fn is_compatible(
         &self,
@@ -17,6 +14,34 @@ pub trait FilterTraits {
         chunk_output: &ChunkRepresentation,
     ) -> Result<(), FilterError>;
 
+    /// Checks if each of the inputs and the output are compatible.
+    ///
+    /// The first input is the primary input of the filter, and any others are its additional inputs.
+    /// The default implementation checks each input against the output with [`FilterTraits::is_compatible`].
+    fn is_compatible_inputs(
+        &self,
+        chunk_inputs: &[ChunkRepresentation],
+        chunk_output: &ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        chunk_inputs
+            .iter()
+            .try_for_each(|chunk_input| self.is_compatible(chunk_input, chunk_output))
+    }
+
+    /// Checks if the input arrays and output array are compatible with [`FilterTraits::is_compatible_inputs`].
+    fn is_compatible_arrays(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &Array<FilterStorage>,
+    ) -> Result<(), FilterError> {
+        let chunk_inputs = inputs
+            .iter()
+            .map(|input| input.chunk_array_representation(&vec![0; input.dimensionality()]))
+            .collect::<Result<Vec<_>, _>>()?;
+        let chunk_output = output.chunk_array_representation(&vec![0; output.dimensionality()])?;
+        self.is_compatible_inputs(&chunk_inputs, &chunk_output)
+    }
+
     /// Returns the memory overhead per chunk.
     ///
     /// This can be used to automatically constrain the number of concurrent chunks based on the amount of available memory.
@@ -28,7 +53,7 @@ pub trait FilterTraits {
 
     /// Returns an [`ArrayShape`] if the filter changes the array shape.
     #[allow(unused_variables)]
-    fn output_shape(&self, array_input: &Array<FilesystemStore>) -> Option<ArrayShape> {
+    fn output_shape(&self, array_input: &Array<FilterStorage>) -> Option<ArrayShape> {
         None
     }
 
@@ -36,16 +61,19 @@ pub trait FilterTraits {
     #[allow(unused_variables)]
     fn output_data_type(
         &self,
-        array_input: &Array<FilesystemStore>,
+        array_input: &Array<FilterStorage>,
     ) -> Option<(DataType, FillValue)> {
         None
     }
 
-    fn output_array_builder(
+    /// Returns the reencoding arguments of the output, with the data type and fill value resolved.
+    ///
+    /// An explicitly set data type takes precedence over [`FilterTraits::output_data_type`].
+    fn output_reencoding_args(
         &self,
-        array_input: &Array<FilesystemStore>,
+        array_input: &Array<FilterStorage>,
         reencoding_args: &ZarrReencodingArgs,
-    ) -> ArrayBuilder {
+    ) -> ZarrReencodingArgs {
         let mut reencoding_args = reencoding_args.clone();
 
         if let Some(data_type) = &reencoding_args.data_type {
@@ -67,8 +95,16 @@ pub trait FilterTraits {
             reencoding_args.fill_value = Some(data_type.metadata_fill_value(&fill_value));
         }
 
+        reencoding_args
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
         get_array_builder_reencode(
-            &reencoding_args,
+            &self.output_reencoding_args(array_input, reencoding_args),
             array_input,
             self.output_shape(array_input),
         )
@@ -76,24 +112,117 @@ pub trait FilterTraits {
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError>;
+
+    /// Returns true if the filter is element-wise and implements [`FilterTraits::apply_elementwise`].
+    ///
+    /// An element-wise filter maps each element independently of its position and neighbours, and does not change the array shape.
+    /// Consecutive element-wise filters can be fused into a single pass with a [`FusedFilter`](super::FusedFilter).
+    fn is_elementwise(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the filter can be run in place, with the same array as its input and output.
+    ///
+    /// This requires that each output chunk is only computed from the same input chunk, which is read before it is written.
+    /// Element-wise filters can be run in place by default.
+    fn supports_in_place(&self) -> bool {
+        self.is_elementwise()
+    }
+
+    /// Returns the halo of a neighbourhood filter, the number of input elements it reads beyond each side of an output chunk per axis.
+    ///
+    /// Filters with a halo read their input with [`retrieve_chunk_haloed_ndarray`](super::retrieve_chunk_haloed_ndarray), and the runner extends subsets of their input by the halo.
+    #[allow(unused_variables)]
+    fn halo(&self, dimensionality: usize) -> Option<Vec<u64>> {
+        None
+    }
+
+    /// Apply an element-wise filter to the element `bytes` of `input`, returning the element bytes of `output`.
+    ///
+    /// `input` and `output` only provide metadata (e.g. the data type and fill value), their elements are not accessed.
+    #[allow(unused_variables)]
+    fn apply_elementwise(
+        &self,
+        bytes: &[u8],
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+    ) -> Result<Vec<u8>, FilterError> {
+        Err(FilterError::Other(
+            "the filter is not element-wise".to_string(),
+        ))
+    }
+
+    /// Apply the filter to its inputs.
+    ///
+    /// The first input is the primary input of the filter, and any others are its additional inputs.
+    /// Filters with additional inputs must override this, the default implementation applies [`FilterTraits::apply`] to a single input.
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        match inputs {
+            [input] => self.apply(input, output, progress_callback),
+            _ => Err(FilterError::InvalidParameters(format!(
+                "the filter requires one input, got {}",
+                inputs.len()
+            ))),
+        }
+    }
 }
 
 impl<T: FilterTraits + ?Sized> FilterTraits for Box<T> {
     #[inline]
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
         // progress_callback: CB,
     ) -> Result<(), FilterError> {
         (**self).apply(input, output, progress_callback)
     }
 
+    #[inline]
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        (**self).apply_inputs(inputs, output, progress_callback)
+    }
+
+    #[inline]
+    fn is_elementwise(&self) -> bool {
+        (**self).is_elementwise()
+    }
+
+    #[inline]
+    fn supports_in_place(&self) -> bool {
+        (**self).supports_in_place()
+    }
+
+    #[inline]
+    fn halo(&self, dimensionality: usize) -> Option<Vec<u64>> {
+        (**self).halo(dimensionality)
+    }
+
+    #[inline]
+    fn apply_elementwise(
+        &self,
+        bytes: &[u8],
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+    ) -> Result<Vec<u8>, FilterError> {
+        (**self).apply_elementwise(bytes, input, output)
+    }
+
     #[inline]
     fn is_compatible(
         &self,
@@ -103,6 +232,24 @@ impl<T: FilterTraits + ?Sized> FilterTraits for Box<T> {
         (**self).is_compatible(chunk_input, chunk_output)
     }
 
+    #[inline]
+    fn is_compatible_inputs(
+        &self,
+        chunk_inputs: &[ChunkRepresentation],
+        chunk_output: &ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        (**self).is_compatible_inputs(chunk_inputs, chunk_output)
+    }
+
+    #[inline]
+    fn is_compatible_arrays(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &Array<FilterStorage>,
+    ) -> Result<(), FilterError> {
+        (**self).is_compatible_arrays(inputs, output)
+    }
+
     #[inline]
     fn memory_per_chunk(
         &self,
@@ -112,10 +259,19 @@ impl<T: FilterTraits + ?Sized> FilterTraits for Box<T> {
         (**self).memory_per_chunk(chunk_input, chunk_output)
     }
 
+    #[inline]
+    fn output_reencoding_args(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ZarrReencodingArgs {
+        (**self).output_reencoding_args(array_input, reencoding_args)
+    }
+
     #[inline]
     fn output_array_builder(
         &self,
-        array_input: &Array<FilesystemStore>,
+        array_input: &Array<FilterStorage>,
         reencoding_args: &ZarrReencodingArgs,
     ) -> ArrayBuilder {
         (**self).output_array_builder(array_input, reencoding_args)
@@ -124,13 +280,13 @@ impl<T: FilterTraits + ?Sized> FilterTraits for Box<T> {
     #[inline]
     fn output_data_type(
         &self,
-        array_input: &Array<FilesystemStore>,
+        array_input: &Array<FilterStorage>,
     ) -> Option<(DataType, FillValue)> {
         (**self).output_data_type(array_input)
     }
 
     #[inline]
-    fn output_shape(&self, array_input: &Array<FilesystemStore>) -> Option<ArrayShape> {
+    fn output_shape(&self, array_input: &Array<FilterStorage>) -> Option<ArrayShape> {
         (**self).output_shape(array_input)
     }
 }
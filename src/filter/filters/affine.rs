@@ -0,0 +1,605 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use ndarray::ArrayD;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, unravel_index, Array, DataType, Element, ElementOwned,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage, Interpolation,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct AffineArguments {
+    /// The affine matrix mapping output to input element coordinates, row-major and comma delimited.
+    ///
+    /// For an N dimensional array, this is an N x (N + 1) matrix with the translation in the last column,
+    /// or an (N + 1) x (N + 1) homogeneous matrix.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        allow_hyphen_values(true),
+        required_unless_present = "matrix_file",
+        conflicts_with = "matrix_file"
+    )]
+    #[serde(default)]
+    pub matrix: Option<Vec<f64>>,
+    /// Path to a JSON file holding the affine matrix as nested arrays, e.g. [[1, 0, 5], [0, 1, -2]].
+    #[arg(long)]
+    #[serde(default)]
+    pub matrix_file: Option<PathBuf>,
+    /// The matrix maps input to output coordinates (a forward transform), so it is inverted.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub inverse: bool,
+    /// The output shape, comma delimited. Defaults to the input shape.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub shape: Option<Vec<u64>>,
+    /// The interpolation method.
+    #[arg(long, value_enum, default_value_t = Interpolation::Linear)]
+    #[serde(default = "default_interpolation")]
+    pub interpolation: Interpolation,
+}
+
+fn default_interpolation() -> Interpolation {
+    Interpolation::Linear
+}
+
+impl FilterArguments for AffineArguments {
+    fn name(&self) -> String {
+        "affine".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let matrix = match (&self.matrix, &self.matrix_file) {
+            (Some(matrix), None) => matrix.clone(),
+            (None, Some(matrix_file)) => {
+                let matrix: Vec<Vec<f64>> =
+                    serde_json::from_str(&std::fs::read_to_string(matrix_file)?)?;
+                matrix.concat()
+            }
+            _ => {
+                return Err(FilterError::InvalidParameters(
+                    "affine requires exactly one of a matrix or a matrix file".to_string(),
+                ))
+            }
+        };
+        Ok(Box::new(Affine::new(
+            matrix,
+            self.inverse,
+            self.shape.clone(),
+            self.interpolation,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// An affine transform `x -> matrix * x + translation`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AffineTransform {
+    matrix: Vec<Vec<f64>>,
+    translation: Vec<f64>,
+}
+
+impl AffineTransform {
+    /// Create an affine transform from the row-major elements of an N x (N + 1) or (N + 1) x (N + 1) homogeneous matrix.
+    ///
+    /// # Errors
+    /// Returns an error if the number of elements does not match `dimensionality`, or the last row of a homogeneous matrix is not `[0, ..., 0, 1]`.
+    pub fn from_elements(elements: &[f64], dimensionality: usize) -> Result<Self, FilterError> {
+        let n = dimensionality;
+        let rows = if elements.len() == n * (n + 1) {
+            n
+        } else if elements.len() == (n + 1) * (n + 1) {
+            let last_row = &elements[n * (n + 1)..];
+            if last_row[..n].iter().any(|v| *v != 0.0) || last_row[n] != 1.0 {
+                return Err(FilterError::InvalidParameters(format!(
+                    "affine homogeneous matrix last row {last_row:?} must be [0, ..., 0, 1]"
+                )));
+            }
+            n
+        } else {
+            return Err(FilterError::InvalidParameters(format!(
+                "affine matrix has {} elements, expected {} or {} for dimensionality {n}",
+                elements.len(),
+                n * (n + 1),
+                (n + 1) * (n + 1)
+            )));
+        };
+        let (matrix, translation) = elements
+            .chunks_exact(n + 1)
+            .take(rows)
+            .map(|row| (row[..n].to_vec(), row[n]))
+            .unzip();
+        Ok(Self {
+            matrix,
+            translation,
+        })
+    }
+
+    /// Returns the inverse transform.
+    ///
+    /// # Errors
+    /// Returns an error if the matrix is singular.
+    pub fn inverse(&self) -> Result<Self, FilterError> {
+        // Gauss-Jordan elimination with partial pivoting
+        let n = self.translation.len();
+        let mut a = self.matrix.clone();
+        let mut inverse: Vec<Vec<f64>> = (0..n)
+            .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+            .collect();
+        for column in 0..n {
+            let pivot = (column..n)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < f64::EPSILON {
+                return Err(FilterError::InvalidParameters(
+                    "affine matrix is singular".to_string(),
+                ));
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let scale = a[column][column];
+            a[column].iter_mut().for_each(|v| *v /= scale);
+            inverse[column].iter_mut().for_each(|v| *v /= scale);
+            for row in 0..n {
+                if row != column {
+                    let factor = a[row][column];
+                    for j in 0..n {
+                        a[row][j] -= factor * a[column][j];
+                        inverse[row][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+        let translation = inverse
+            .iter()
+            .map(|row| {
+                -std::iter::zip(row, &self.translation)
+                    .map(|(a, b)| a * b)
+                    .sum::<f64>()
+            })
+            .collect();
+        Ok(Self {
+            matrix: inverse,
+            translation,
+        })
+    }
+
+    /// Transform the coordinate `x`.
+    pub fn apply(&self, x: &[f64]) -> Vec<f64> {
+        std::iter::zip(&self.matrix, &self.translation)
+            .map(|(row, translation)| {
+                std::iter::zip(row, x).map(|(a, x)| a * x).sum::<f64>() + translation
+            })
+            .collect()
+    }
+}
+
+/// Returns true if the coordinate `x` is within an array of shape `array_shape`, where integer coordinates are element centres.
+fn is_inside(x: &[f64], array_shape: &[u64]) -> bool {
+    std::iter::zip(x, array_shape).all(|(&x, &len)| x >= -0.5 && x < len as f64 - 0.5)
+}
+
+/// Sample the nearest element to the coordinate `x` of `input`, a region starting at `input_start` of an array of shape `array_shape`.
+///
+/// Returns [`None`] if `x` is outside of the array.
+pub fn sample_nearest<T: Copy>(
+    input: &ArrayD<T>,
+    input_start: &[u64],
+    array_shape: &[u64],
+    x: &[f64],
+) -> Option<T> {
+    if !is_inside(x, array_shape) {
+        return None;
+    }
+    let index: Vec<usize> = itertools::izip!(x, input_start, array_shape)
+        .map(|(&x, &start, &len)| {
+            let i = ((x + 0.5).floor().max(0.0) as u64).min(len - 1);
+            usize::try_from(i - start).unwrap()
+        })
+        .collect();
+    Some(input[index.as_slice()])
+}
+
+/// Interpolate `input`, a region starting at `input_start` of an array of shape `array_shape`, at the coordinate `x`.
+///
+/// Returns [`None`] if `x` is outside of the array.
+pub fn sample_interpolated(
+    input: &ArrayD<f64>,
+    input_start: &[u64],
+    array_shape: &[u64],
+    x: &[f64],
+    interpolation: Interpolation,
+) -> Option<f64> {
+    if !is_inside(x, array_shape) {
+        return None;
+    }
+    let taps: Vec<Vec<(u64, f64)>> = std::iter::zip(x, array_shape)
+        .map(|(&x, &len)| interpolation.taps(x, 1.0, len))
+        .collect();
+
+    // Sum over the tensor product of the taps of each axis
+    let n = x.len();
+    let mut counter = vec![0usize; n];
+    let mut index = vec![0usize; n];
+    let mut sum = 0.0;
+    loop {
+        let mut weight = 1.0;
+        for axis in 0..n {
+            let (i, w) = taps[axis][counter[axis]];
+            index[axis] = usize::try_from(i - input_start[axis]).unwrap();
+            weight *= w;
+        }
+        sum += weight * input[index.as_slice()];
+
+        let mut axis = n;
+        loop {
+            if axis == 0 {
+                return Some(sum);
+            }
+            axis -= 1;
+            counter[axis] += 1;
+            if counter[axis] < taps[axis].len() {
+                break;
+            }
+            counter[axis] = 0;
+        }
+    }
+}
+
+pub struct Affine {
+    matrix: Vec<f64>,
+    inverse: bool,
+    shape: Option<Vec<u64>>,
+    interpolation: Interpolation,
+    chunk_limit: Option<usize>,
+}
+
+impl Affine {
+    pub fn new(
+        matrix: Vec<f64>,
+        inverse: bool,
+        shape: Option<Vec<u64>>,
+        interpolation: Interpolation,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            matrix,
+            inverse,
+            shape,
+            interpolation,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the transform from output to input element coordinates.
+    ///
+    /// # Errors
+    /// Returns an error if the matrix is invalid for `dimensionality` or cannot be inverted.
+    pub fn transform(&self, dimensionality: usize) -> Result<AffineTransform, FilterError> {
+        let transform = AffineTransform::from_elements(&self.matrix, dimensionality)?;
+        if self.inverse {
+            transform.inverse()
+        } else {
+            Ok(transform)
+        }
+    }
+
+    /// Returns the input subset required to sample `subset_output`, or [`None`] if it maps outside of the input.
+    pub fn input_subset(
+        &self,
+        transform: &AffineTransform,
+        input_shape: &[u64],
+        subset_output: &ArraySubset,
+    ) -> Option<ArraySubset> {
+        // The bounding box of the transformed corners of the output subset
+        let n = input_shape.len();
+        let mut min = vec![f64::INFINITY; n];
+        let mut max = vec![f64::NEG_INFINITY; n];
+        for corner in 0..1usize << n {
+            let x: Vec<f64> =
+                itertools::izip!(0..n, subset_output.start(), subset_output.end_exc())
+                    .map(|(axis, &start, end)| {
+                        if (corner >> axis) & 1 == 0 {
+                            start as f64
+                        } else {
+                            (end - 1) as f64
+                        }
+                    })
+                    .collect();
+            for (axis, x) in transform.apply(&x).into_iter().enumerate() {
+                min[axis] = min[axis].min(x);
+                max[axis] = max[axis].max(x);
+            }
+        }
+
+        let radius = self.interpolation.radius().ceil();
+        let mut ranges = Vec::with_capacity(n);
+        for (min, max, &len) in itertools::izip!(min, max, input_shape) {
+            let start = (min.floor() - radius).max(0.0);
+            let end = (max.ceil() + radius + 1.0).min(len as f64);
+            if start >= end {
+                return None;
+            }
+            ranges.push(start as u64..end as u64);
+        }
+        Some(ArraySubset::new_with_ranges(&ranges))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        transform: &AffineTransform,
+        fill_value: TOut,
+        round: bool,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut> + AsPrimitive<f64>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let output_elements = if let Some(subset_input) =
+            self.input_subset(transform, input.shape(), &subset_output)
+        {
+            let input_array =
+                progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+            progress.process(|| {
+                let coordinates = |i: u64| {
+                    let index = unravel_index(i, subset_output.shape());
+                    let x: Vec<f64> = std::iter::zip(index, subset_output.start())
+                        .map(|(index, start)| (index + start) as f64)
+                        .collect();
+                    transform.apply(&x)
+                };
+                let num_elements = subset_output.num_elements();
+                if self.interpolation == Interpolation::Nearest {
+                    (0..num_elements)
+                        .into_par_iter()
+                        .map(|i| {
+                            sample_nearest(
+                                &input_array,
+                                subset_input.start(),
+                                input.shape(),
+                                &coordinates(i),
+                            )
+                            .map_or(fill_value, |v| v.as_())
+                        })
+                        .collect::<Vec<TOut>>()
+                } else {
+                    let input_array = input_array.mapv(|v| AsPrimitive::<f64>::as_(v));
+                    (0..num_elements)
+                        .into_par_iter()
+                        .map(|i| {
+                            sample_interpolated(
+                                &input_array,
+                                subset_input.start(),
+                                input.shape(),
+                                &coordinates(i),
+                                self.interpolation,
+                            )
+                            .map_or(fill_value, |v| if round { v.round() } else { v }.as_())
+                        })
+                        .collect::<Vec<TOut>>()
+                }
+            })
+        } else {
+            vec![fill_value; subset_output.num_elements_usize()]
+        };
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&subset_output, &output_elements)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Affine {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        self.transform(dimensionality)?;
+        if let Some(shape) = &self.shape {
+            if shape.len() != dimensionality {
+                return Err(FilterError::InvalidParameters(format!(
+                    "affine shape {shape:?} does not match the input dimensionality {dimensionality}"
+                )));
+            }
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // Assume the input region of an output chunk is up to twice the size of the output chunk along each axis
+        let num_input_elements = chunk_output
+            .shape()
+            .iter()
+            .map(|len| usize::try_from(len.get() * 2).unwrap())
+            .product::<usize>();
+        num_input_elements
+            * (chunk_input.fixed_element_size().unwrap() + core::mem::size_of::<f64>())
+            + chunk_output.num_elements_usize() * chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<Vec<u64>> {
+        self.shape
+            .clone()
+            .filter(|shape| shape.len() == input.dimensionality())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let transform = self.transform(input.dimensionality())?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => {
+                                let fill_value: $type_out = bytemuck::pod_read_unaligned(output.fill_value().as_ne_bytes());
+                                self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &transform, fill_value, round, &progress)
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn affine_transform() {
+        // Rotation by 90 degrees about the centre of a 3x3 array, with a homogeneous matrix
+        let transform =
+            AffineTransform::from_elements(&[0.0, 1.0, 0.0, -1.0, 0.0, 2.0, 0.0, 0.0, 1.0], 2)
+                .unwrap();
+        assert_eq!(transform.apply(&[0.0, 0.0]), vec![0.0, 2.0]);
+        let inverse = transform.inverse().unwrap();
+        assert_eq!(inverse.apply(&transform.apply(&[1.0, 2.0])), vec![1.0, 2.0]);
+        assert!(
+            AffineTransform::from_elements(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0], 2)
+                .unwrap()
+                .inverse()
+                .is_err()
+        );
+
+        let input = ndarray::array![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]].into_dyn();
+        let shape = [3, 3];
+        assert_eq!(
+            sample_nearest(&input, &[0, 0], &shape, &transform.apply(&[0.0, 0.0])),
+            Some(3.0)
+        );
+        assert_eq!(sample_nearest(&input, &[0, 0], &shape, &[-0.6, 0.0]), None);
+        // A region of the array
+        let region = input.slice(ndarray::s![1.., 1..]).to_owned().into_dyn();
+        assert_eq!(
+            sample_interpolated(&region, &[1, 1], &shape, &[1.5, 1.5], Interpolation::Linear),
+            Some(7.0)
+        );
+        assert_eq!(
+            sample_interpolated(&input, &[0, 0], &shape, &[2.0, 1.0], Interpolation::Cubic),
+            Some(8.0)
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use clap::Parser;
+use ndarray::ArrayD;
+use num_traits::AsPrimitive;
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+    },
+    slice::ParallelSlice,
+};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, ArrayShape, DataType, Element,
+        FillValue,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_elements_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+use super::reduce::{kept_axes, reduced_array_builder, reduced_input_subsets, reduced_shape};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ArgmaxArguments {
+    /// The axis to find the index of the maximum along.
+    pub axis: usize,
+    /// Find the index of the minimum rather than the maximum.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub min: bool,
+}
+
+impl FilterArguments for ArgmaxArguments {
+    fn name(&self) -> String {
+        "argmax".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Argmax::new(
+            self.axis,
+            self.min,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// The extreme value and its index found so far.
+#[derive(Debug, Clone, Copy)]
+pub struct ArgmaxState {
+    value: f64,
+    index: u64,
+}
+
+pub struct Argmax {
+    axis: usize,
+    min: bool,
+    chunk_limit: Option<usize>,
+}
+
+impl Argmax {
+    pub fn new(axis: usize, min: bool, chunk_limit: Option<usize>) -> Self {
+        Self {
+            axis,
+            min,
+            chunk_limit,
+        }
+    }
+
+    fn init(&self) -> ArgmaxState {
+        ArgmaxState {
+            value: if self.min {
+                f64::INFINITY
+            } else {
+                f64::NEG_INFINITY
+            },
+            index: 0,
+        }
+    }
+
+    /// Update `states` with `elements` of shape `shape`, which start at `start` along the axis.
+    ///
+    /// The first index of the extreme value is kept, and NaN is ignored.
+    pub fn argmax_elements(
+        &self,
+        states: &mut [ArgmaxState],
+        elements: Vec<f64>,
+        shape: &[usize],
+        start: u64,
+    ) {
+        // Move the axis last, so each output element is a contiguous run of elements
+        let order = kept_axes(shape.len(), &[self.axis])
+            .into_iter()
+            .chain([self.axis])
+            .collect::<Vec<_>>();
+        let elements = ArrayD::from_shape_vec(shape, elements)
+            .unwrap()
+            .permuted_axes(order);
+        let elements = elements.as_standard_layout();
+        let elements = elements.as_slice().unwrap();
+        if elements.is_empty() {
+            return;
+        }
+        states
+            .par_iter_mut()
+            .zip(elements.par_chunks(shape[self.axis]))
+            .for_each(|(state, elements)| {
+                for (index, &value) in (start..).zip(elements) {
+                    let better = if self.min {
+                        value < state.value
+                    } else {
+                        value > state.value
+                    };
+                    if better {
+                        *state = ArgmaxState { value, index };
+                    }
+                }
+            });
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        u64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let mut states = vec![self.init(); subset_output.num_elements_usize()];
+        let range = 0..input.shape()[self.axis];
+        for subset_input in reduced_input_subsets(
+            input,
+            &subset_output,
+            &[self.axis],
+            std::slice::from_ref(&range),
+        )? {
+            let elements =
+                progress.read(|| retrieve_array_subset_elements_f64(input, &subset_input))?;
+            progress.process(|| {
+                self.argmax_elements(
+                    &mut states,
+                    elements,
+                    &subset_input.shape_usize(),
+                    subset_input.start()[self.axis],
+                )
+            });
+        }
+
+        let elements_out = progress.process(|| {
+            states
+                .into_par_iter()
+                .map(|state| state.index.as_())
+                .collect::<Vec<TOut>>()
+        });
+
+        progress
+            .write(|| output.store_array_subset_elements::<TOut>(&subset_output, &elements_out))?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Argmax {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if self.axis >= dimensionality {
+            return Err(FilterError::InvalidParameters(format!(
+                "argmax axis {} is out of bounds for an input with dimensionality {dimensionality}",
+                self.axis
+            )));
+        }
+        match chunk_input.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // A slab of about an input chunk is held as f64 and copied to reorder its axes, and the extremes of the output chunk
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap() + 2 * core::mem::size_of::<f64>())
+            + chunk_output.num_elements_usize()
+                * (core::mem::size_of::<ArgmaxState>() + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        reduced_shape(input, &[self.axis])
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::UInt32, FillValue::from(0u32)))
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        reduced_array_builder(
+            array_input,
+            self.output_reencoding_args(array_input, reencoding_args),
+            &[self.axis],
+        )
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argmax_elements() {
+        // A 2x3 array along axis 1 in two slabs, ties keep the first index and NaN is ignored
+        let argmax = |min: bool| {
+            let argmax = Argmax::new(1, min, None);
+            let mut states = vec![argmax.init(); 2];
+            argmax.argmax_elements(&mut states, vec![1.0, 5.0, 2.0, 2.0], &[2, 2], 0);
+            argmax.argmax_elements(&mut states, vec![5.0, f64::NAN], &[2, 1], 2);
+            states.iter().map(|state| state.index).collect::<Vec<_>>()
+        };
+        assert_eq!(argmax(false), vec![1, 0]);
+        assert_eq!(argmax(true), vec![0, 0]);
+    }
+}
@@ -0,0 +1,561 @@
+use clap::Parser;
+use num_traits::{AsPrimitive, Bounded, FromPrimitive, ToPrimitive};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits, simd_map,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArithmeticOperator {
+    /// Add the operand.
+    Add,
+    /// Subtract the operand.
+    Sub,
+    /// Multiply by the operand.
+    Mul,
+    /// Divide by the operand.
+    Div,
+}
+
+impl ArithmeticOperator {
+    #[inline]
+    pub fn apply(&self, value: f64, operand: f64) -> f64 {
+        match self {
+            Self::Add => value + operand,
+            Self::Sub => value - operand,
+            Self::Mul => value * operand,
+            Self::Div => value / operand,
+        }
+    }
+
+    /// Apply the operator to integers, with `overflow` behaviour on overflow of an [`i128`].
+    ///
+    /// Returns [`None`] on overflow with [`ArithmeticOverflow::Error`].
+    #[inline]
+    fn apply_integer(
+        &self,
+        value: i128,
+        operand: i128,
+        overflow: ArithmeticOverflow,
+    ) -> Option<i128> {
+        match overflow {
+            ArithmeticOverflow::Wrap => Some(match self {
+                Self::Add => value.wrapping_add(operand),
+                Self::Sub => value.wrapping_sub(operand),
+                Self::Mul => value.wrapping_mul(operand),
+                Self::Div => value.wrapping_div(operand),
+            }),
+            ArithmeticOverflow::Saturate => Some(match self {
+                Self::Add => value.saturating_add(operand),
+                Self::Sub => value.saturating_sub(operand),
+                Self::Mul => value.saturating_mul(operand),
+                Self::Div => value.saturating_div(operand),
+            }),
+            ArithmeticOverflow::Error => match self {
+                Self::Add => value.checked_add(operand),
+                Self::Sub => value.checked_sub(operand),
+                Self::Mul => value.checked_mul(operand),
+                Self::Div => value.checked_div(operand),
+            },
+        }
+    }
+}
+
+/// Returns true if `T` is a primitive integer type.
+fn is_integer<T: 'static>() -> bool {
+    use std::any::TypeId;
+    [
+        TypeId::of::<i8>(),
+        TypeId::of::<i16>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<i64>(),
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+    ]
+    .contains(&TypeId::of::<T>())
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArithmeticOverflow {
+    /// Wrap around on integer overflow.
+    Wrap,
+    /// Saturate to the minimum/maximum of the output data type.
+    #[default]
+    Saturate,
+    /// Fail if a result is not representable by the output data type.
+    Error,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ArithArguments {
+    /// The arithmetic operator.
+    #[arg(value_enum)]
+    pub operator: ArithmeticOperator,
+    /// The scalar operand.
+    #[arg(allow_hyphen_values(true))]
+    pub operand: f64,
+    /// Behaviour if a result overflows the output data type.
+    #[arg(long, value_enum, default_value_t = ArithmeticOverflow::Saturate)]
+    #[serde(default)]
+    pub overflow: ArithmeticOverflow,
+}
+
+impl FilterArguments for ArithArguments {
+    fn name(&self) -> String {
+        "arith".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Arith::new(
+            self.operator,
+            self.operand,
+            self.overflow,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Arith {
+    operator: ArithmeticOperator,
+    operand: f64,
+    overflow: ArithmeticOverflow,
+    chunk_limit: Option<usize>,
+}
+
+impl Arith {
+    pub fn new(
+        operator: ArithmeticOperator,
+        operand: f64,
+        overflow: ArithmeticOverflow,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            operator,
+            operand,
+            overflow,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<f64> + ToPrimitive,
+        TOut: Element + Send + Sync + Copy + FromPrimitive + Bounded + 'static,
+        f64: AsPrimitive<TOut>,
+        u64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements_in =
+            progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_output_subset))?;
+
+        let elements_out = progress.process(|| self.apply_elements(&elements_in))?;
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Apply the operator to `elements_in`.
+    ///
+    /// If the input and output are integers and the operand is an integer, the operator is applied with integer arithmetic.
+    /// Otherwise, it is applied in [`f64`] and the result is converted to the output data type.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::Other`] if a result overflows the output data type with [`ArithmeticOverflow::Error`].
+    pub fn apply_elements<TIn, TOut>(&self, elements_in: &[TIn]) -> Result<Vec<TOut>, FilterError>
+    where
+        TIn: Send + Sync + AsPrimitive<f64> + ToPrimitive + 'static,
+        TOut: Send + Sync + Copy + FromPrimitive + Bounded + 'static,
+        f64: AsPrimitive<TOut>,
+        u64: AsPrimitive<TOut>,
+    {
+        if is_integer::<TIn>() && is_integer::<TOut>() {
+            if let Some(operand) = self.integer_operand() {
+                return self.apply_elements_integer(elements_in, operand);
+            }
+        }
+
+        if self.overflow == ArithmeticOverflow::Saturate {
+            // The operator is matched outside of the vectorised kernel
+            let operand = self.operand;
+            return Ok(match self.operator {
+                ArithmeticOperator::Add => apply_saturate(elements_in, |value| value + operand),
+                ArithmeticOperator::Sub => apply_saturate(elements_in, |value| value - operand),
+                ArithmeticOperator::Mul => apply_saturate(elements_in, |value| value * operand),
+                ArithmeticOperator::Div => apply_saturate(elements_in, |value| value / operand),
+            });
+        }
+
+        elements_in
+            .par_iter()
+            .map(|value| {
+                let value_f64: f64 = value.as_();
+                let result = self.operator.apply(value_f64, self.operand);
+                match self.overflow {
+                    ArithmeticOverflow::Saturate => Ok(result.as_()),
+                    ArithmeticOverflow::Wrap => {
+                        if TOut::from_f64(result).is_some() {
+                            Ok(result.as_())
+                        } else {
+                            // Out of range of an integer output, so wrap via two's complement
+                            Ok((result as i128 as u64).as_())
+                        }
+                    }
+                    ArithmeticOverflow::Error => TOut::from_f64(result).ok_or_else(|| {
+                        FilterError::Other(format!(
+                            "arithmetic overflow: {result} is not representable by the output data type"
+                        ))
+                    }),
+                }
+            })
+            .collect::<Result<Vec<TOut>, FilterError>>()
+    }
+
+    /// Returns the operand as an integer if it is integral and the operator is not a division by zero.
+    fn integer_operand(&self) -> Option<i128> {
+        let operand = self.operand;
+        let is_integral = operand.fract() == 0.0 && operand.abs() <= u64::MAX as f64;
+        let is_division_by_zero = self.operator == ArithmeticOperator::Div && operand == 0.0;
+        (is_integral && !is_division_by_zero).then_some(operand as i128)
+    }
+
+    /// Apply the operator to integer `elements_in` with an integer `operand`.
+    ///
+    /// Division truncates towards zero.
+    fn apply_elements_integer<TIn, TOut>(
+        &self,
+        elements_in: &[TIn],
+        operand: i128,
+    ) -> Result<Vec<TOut>, FilterError>
+    where
+        TIn: Send + Sync + ToPrimitive,
+        TOut: Send + Sync + Copy + FromPrimitive + Bounded + 'static,
+        u64: AsPrimitive<TOut>,
+    {
+        let overflow_error = |value: &dyn std::fmt::Display| {
+            FilterError::Other(format!(
+                "arithmetic overflow: {value} {:?} {operand} is not representable by the output data type",
+                self.operator
+            ))
+        };
+        elements_in
+            .par_iter()
+            .map(|value| {
+                let value = value.to_i128().unwrap();
+                let result = self
+                    .operator
+                    .apply_integer(value, operand, self.overflow)
+                    .ok_or_else(|| overflow_error(&value))?;
+                match self.overflow {
+                    ArithmeticOverflow::Saturate => {
+                        Ok(TOut::from_i128(result).unwrap_or_else(|| {
+                            if result < 0 {
+                                TOut::min_value()
+                            } else {
+                                TOut::max_value()
+                            }
+                        }))
+                    }
+                    // Truncate to the least significant bits, which wraps any integer output
+                    ArithmeticOverflow::Wrap => Ok((result as u64).as_()),
+                    ArithmeticOverflow::Error => {
+                        TOut::from_i128(result).ok_or_else(|| overflow_error(&value))
+                    }
+                }
+            })
+            .collect::<Result<Vec<TOut>, FilterError>>()
+    }
+}
+
+/// Apply `operator` to each element, saturating to the output data type.
+fn apply_saturate<TIn, TOut>(elements_in: &[TIn], operator: impl Fn(f64) -> f64 + Sync) -> Vec<TOut>
+where
+    TIn: Sync + AsPrimitive<f64>,
+    TOut: Send + Copy + 'static,
+    f64: AsPrimitive<TOut>,
+{
+    simd_map(elements_in, |value| operator(value.as_()).as_())
+}
+
+impl FilterTraits for Arith {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn supports_in_place(&self) -> bool {
+        true
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$t_in, $t_out>(&input, &output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                            match output.data_type() {
+                                $(
+                                    DataType::$data_type => {
+                                        apply_input!($type_out, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{create_test_array, run_test_filter};
+
+    use super::*;
+
+    #[test]
+    fn arith_overflow() -> Result<(), FilterError> {
+        let elements: Vec<u8> = vec![0, 100, 200, 250];
+
+        let saturate = Arith::new(
+            ArithmeticOperator::Add,
+            10.0,
+            ArithmeticOverflow::Saturate,
+            None,
+        );
+        assert_eq!(
+            saturate.apply_elements::<u8, u8>(&elements)?,
+            vec![10, 110, 210, 255]
+        );
+
+        let wrap = Arith::new(
+            ArithmeticOperator::Add,
+            10.0,
+            ArithmeticOverflow::Wrap,
+            None,
+        );
+        assert_eq!(
+            wrap.apply_elements::<u8, u8>(&elements)?,
+            vec![10, 110, 210, 4]
+        );
+
+        let error = Arith::new(
+            ArithmeticOperator::Add,
+            10.0,
+            ArithmeticOverflow::Error,
+            None,
+        );
+        assert!(error.apply_elements::<u8, u8>(&elements).is_err());
+
+        let div = Arith::new(
+            ArithmeticOperator::Div,
+            4.0,
+            ArithmeticOverflow::Error,
+            None,
+        );
+        assert_eq!(
+            div.apply_elements::<u8, f32>(&elements)?,
+            vec![0.0, 25.0, 50.0, 62.5]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn arith_integer_64() -> Result<(), FilterError> {
+        use ArithmeticOperator::{Add, Div, Mul};
+        use ArithmeticOverflow::{Error, Saturate, Wrap};
+        let arith = |operator, operand, overflow| Arith::new(operator, operand, overflow, None);
+
+        // Values that are not exactly representable by f64
+        let elements: Vec<u64> = vec![(1 << 53) + 1, u64::MAX - 1, u64::MAX];
+        assert_eq!(
+            arith(Add, 1.0, Saturate).apply_elements::<u64, u64>(&elements)?,
+            vec![(1 << 53) + 2, u64::MAX, u64::MAX]
+        );
+        assert_eq!(
+            arith(Add, 1.0, Wrap).apply_elements::<u64, u64>(&elements)?,
+            vec![(1 << 53) + 2, u64::MAX, 0]
+        );
+        assert!(arith(Add, 1.0, Error)
+            .apply_elements::<u64, u64>(&elements)
+            .is_err());
+        assert_eq!(
+            arith(Div, 2.0, Error).apply_elements::<u64, u64>(&elements)?,
+            vec![(1 << 52), u64::MAX / 2, u64::MAX / 2]
+        );
+        assert_eq!(
+            arith(Add, 0.0, Saturate).apply_elements::<u64, i64>(&elements)?,
+            vec![(1 << 53) + 1, i64::MAX, i64::MAX]
+        );
+        assert_eq!(
+            arith(Add, 0.0, Wrap).apply_elements::<u64, i64>(&elements)?,
+            vec![(1 << 53) + 1, -2, -1]
+        );
+
+        let elements: Vec<i64> = vec![-(1 << 53) - 1, i64::MIN, i64::MAX];
+        assert_eq!(
+            arith(Mul, -1.0, Saturate).apply_elements::<i64, i64>(&elements)?,
+            vec![(1 << 53) + 1, i64::MAX, -i64::MAX]
+        );
+        assert_eq!(
+            arith(Mul, -1.0, Wrap).apply_elements::<i64, i64>(&elements)?,
+            vec![(1 << 53) + 1, i64::MIN, -i64::MAX]
+        );
+        assert!(arith(Mul, -1.0, Error)
+            .apply_elements::<i64, i64>(&elements)
+            .is_err());
+        assert_eq!(
+            arith(Mul, 2.0, Wrap).apply_elements::<i64, u64>(&elements)?,
+            vec![(-(1i64 << 54) - 2) as u64, 0, u64::MAX - 1]
+        );
+
+        // A non-integral operand is applied in f64, and wraps an unsigned output
+        assert_eq!(
+            arith(Mul, 1.5, Wrap).apply_elements::<u64, u64>(&[u64::MAX])?,
+            vec![1 << 63]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn arith_bool() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![2],
+                DataType::Bool,
+                vec![2].try_into().unwrap(),
+                false.into(),
+            ),
+            &[false, true],
+        )?;
+
+        // Bool is not supported
+        let args = "operator: add\noperand: 1";
+        assert!(run_test_filter("arith", &input, &output, args).is_err());
+        let args = "operator: add\noperand: 1\ndata_type: uint8";
+        assert!(run_test_filter("arith", &input, &output, args).is_err());
+        Ok(())
+    }
+}
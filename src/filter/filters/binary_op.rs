@@ -0,0 +1,422 @@
+use clap::Parser;
+use num_traits::{AsPrimitive, Bounded, FromPrimitive};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        path_or_identifier::parse_path_or_identifier, retrieve_array_subset_elements_f64,
+        FilterArguments, FilterCommonArguments, FilterStorage, PathOrIdentifier,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BinaryOperator {
+    /// a + b
+    Add,
+    /// a - b
+    Sub,
+    /// a * b
+    Mul,
+    /// a / b
+    Div,
+    /// min(a, b)
+    Min,
+    /// max(a, b)
+    Max,
+}
+
+impl BinaryOperator {
+    #[inline]
+    pub fn apply(&self, a: f64, b: f64) -> f64 {
+        match self {
+            Self::Add => a + b,
+            Self::Sub => a - b,
+            Self::Mul => a * b,
+            Self::Div => a / b,
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+
+    /// Apply the operator to integers, saturating on overflow of an [`i128`].
+    ///
+    /// Returns [`None`] for a division by zero.
+    #[inline]
+    fn apply_integer(&self, a: i128, b: i128) -> Option<i128> {
+        match self {
+            Self::Add => Some(a.saturating_add(b)),
+            Self::Sub => Some(a.saturating_sub(b)),
+            Self::Mul => Some(a.saturating_mul(b)),
+            Self::Div => a.checked_div(b),
+            Self::Min => Some(a.min(b)),
+            Self::Max => Some(a.max(b)),
+        }
+    }
+}
+
+/// Returns true if `data_type` is an integer data type.
+fn is_integer(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+    )
+}
+
+/// Retrieves an array subset of an integer array and converts the elements to [`i128`].
+fn retrieve_array_subset_elements_i128(
+    array: &Array<FilterStorage>,
+    array_subset: &ArraySubset,
+) -> Result<Vec<i128>, FilterError> {
+    macro_rules! retrieve {
+        ([$( ( $data_type:ident, $t:ty ) ),* ]) => {
+            match array.data_type() {
+                $(DataType::$data_type => {
+                    let elements = array.retrieve_array_subset_elements::<$t>(array_subset)?;
+                    Ok(elements.into_iter().map(i128::from).collect())
+                } ,)*
+                data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?
+            }
+        };
+    }
+    retrieve!([
+        (Int8, i8),
+        (Int16, i16),
+        (Int32, i32),
+        (Int64, i64),
+        (UInt8, u8),
+        (UInt16, u16),
+        (UInt32, u32),
+        (UInt64, u64)
+    ])
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct BinaryOpArguments {
+    /// The binary operator. The input is the first operand (a).
+    #[arg(value_enum)]
+    pub operator: BinaryOperator,
+    /// Path to the zarr array holding the second operand (b).
+    ///
+    /// Must have the same shape as the input.
+    #[arg(value_parser = parse_path_or_identifier)]
+    pub other: PathOrIdentifier,
+}
+
+impl FilterArguments for BinaryOpArguments {
+    fn name(&self) -> String {
+        "binary_op".to_string()
+    }
+
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        vec![self.other.clone()]
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(BinaryOp::new(
+            self.operator,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct BinaryOp {
+    operator: BinaryOperator,
+    chunk_limit: Option<usize>,
+}
+
+impl BinaryOp {
+    pub fn new(operator: BinaryOperator, chunk_limit: Option<usize>) -> Self {
+        Self {
+            operator,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input_a: &Array<FilterStorage>,
+        input_b: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + FromPrimitive + Bounded + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        if [input_a, input_b, output]
+            .iter()
+            .all(|array| is_integer(array.data_type()))
+        {
+            let (elements_a, elements_b) = progress.read(|| {
+                Ok::<_, FilterError>((
+                    retrieve_array_subset_elements_i128(input_a, &input_output_subset)?,
+                    retrieve_array_subset_elements_i128(input_b, &input_output_subset)?,
+                ))
+            })?;
+            let elements_out =
+                progress.process(|| self.apply_elements_integer::<TOut>(&elements_a, &elements_b));
+            drop(elements_a);
+            drop(elements_b);
+            progress.write(|| {
+                output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+            })?;
+            progress.next();
+            return Ok(());
+        }
+
+        let (elements_a, elements_b) = progress.read(|| {
+            Ok::<_, FilterError>((
+                retrieve_array_subset_elements_f64(input_a, &input_output_subset)?,
+                retrieve_array_subset_elements_f64(input_b, &input_output_subset)?,
+            ))
+        })?;
+
+        let elements_out =
+            progress.process(|| self.apply_elements::<TOut>(&elements_a, &elements_b));
+        drop(elements_a);
+        drop(elements_b);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    pub fn apply_elements<TOut>(&self, elements_a: &[f64], elements_b: &[f64]) -> Vec<TOut>
+    where
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        elements_a
+            .into_par_iter()
+            .zip(elements_b)
+            .map(|(a, b)| self.operator.apply(*a, *b).as_())
+            .collect::<Vec<TOut>>()
+    }
+
+    /// Apply the operator to integer elements with exact integer arithmetic.
+    ///
+    /// Results saturate to the output data type.
+    /// A division by zero is applied in [`f64`], like [`apply_elements`](BinaryOp::apply_elements).
+    pub fn apply_elements_integer<TOut>(
+        &self,
+        elements_a: &[i128],
+        elements_b: &[i128],
+    ) -> Vec<TOut>
+    where
+        TOut: Send + Sync + Copy + FromPrimitive + Bounded + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        elements_a
+            .into_par_iter()
+            .zip(elements_b)
+            .map(|(&a, &b)| match self.operator.apply_integer(a, b) {
+                Some(result) => TOut::from_i128(result).unwrap_or_else(|| {
+                    if result < 0 {
+                        TOut::min_value()
+                    } else {
+                        TOut::max_value()
+                    }
+                }),
+                None => self.operator.apply(a as f64, b as f64).as_(),
+            })
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for BinaryOp {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // Both inputs are held as f64 or i128 during processing
+        2 * (chunk_input.fixed_element_size().unwrap() + core::mem::size_of::<i128>())
+            + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        self.apply_inputs(&[input], output, progress_callback)
+    }
+
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let [input, input_b] = *inputs else {
+            return Err(FilterError::InvalidParameters(
+                "binary_op requires two inputs".to_string(),
+            ));
+        };
+        assert_eq!(output.shape(), input.shape());
+        assert_eq!(input_b.shape(), input.shape());
+        self.is_compatible_arrays(inputs, output)?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, input_b, &output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn binary_op() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let other = dir.path().join("other.zarr");
+        let output = dir.path().join("output.zarr");
+        let builder = ArrayBuilder::new(
+            vec![4],
+            DataType::UInt8,
+            vec![2].try_into().unwrap(),
+            0u8.into(),
+        );
+        create_test_array(&input, &builder, &[0u8, 100, 200, 5])?;
+        create_test_array(&other, &builder, &[0u8, 0, 100, 10])?;
+
+        let binary_op = |operator: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let args = format!("operator: {operator}\nother: {other:?}");
+            retrieve_test_elements(&run_test_filter("binary_op", &input, &output, &args)?)
+        };
+
+        // Out of range results saturate, and NaN (0 / 0) is zero
+        assert_eq!(binary_op("add")?, vec![0, 100, 255, 15]);
+        assert_eq!(binary_op("sub")?, vec![0, 100, 100, 0]);
+        assert_eq!(binary_op("mul")?, vec![0, 0, 255, 50]);
+        assert_eq!(binary_op("div")?, vec![0, 255, 2, 0]);
+        assert_eq!(binary_op("min")?, vec![0, 0, 100, 5]);
+        assert_eq!(binary_op("max")?, vec![0, 100, 200, 10]);
+
+        // Integers are not rounded to f64
+        let builder = ArrayBuilder::new(
+            vec![2],
+            DataType::Int64,
+            vec![2].try_into().unwrap(),
+            0i64.into(),
+        );
+        create_test_array(&input, &builder, &[(1i64 << 53) + 1, i64::MIN])?;
+        create_test_array(&other, &builder, &[2i64, -1])?;
+        let args = format!("operator: add\nother: {other:?}");
+        let elements: Vec<i64> =
+            retrieve_test_elements(&run_test_filter("binary_op", &input, &output, &args)?)?;
+        assert_eq!(elements, vec![(1 << 53) + 3, i64::MIN]);
+
+        // Bool is not supported
+        let builder = ArrayBuilder::new(
+            vec![2],
+            DataType::Bool,
+            vec![2].try_into().unwrap(),
+            false.into(),
+        );
+        create_test_array(&input, &builder, &[false, true])?;
+        create_test_array(&other, &builder, &[true, true])?;
+        let args = format!("operator: max\nother: {other:?}");
+        assert!(run_test_filter("binary_op", &input, &output, &args).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+use clap::Parser;
+use num_traits::{AsPrimitive, NumCast, ToPrimitive};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, elements_from_bytes, elements_to_bytes, filter_error::FilterError,
+        filter_traits::FilterTraits, simd_map, simd_min_max, FilterArguments,
+        FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CastRounding {
+    /// Round to the nearest integer, with halfway cases away from zero.
+    #[default]
+    Nearest,
+    /// Round to the nearest integer, with halfway cases to even.
+    NearestEven,
+    /// Round towards negative infinity.
+    Floor,
+    /// Round towards positive infinity.
+    Ceil,
+    /// Round towards zero.
+    Trunc,
+}
+
+impl CastRounding {
+    #[inline]
+    pub fn apply(&self, value: f64) -> f64 {
+        match self {
+            Self::Nearest => value.round(),
+            Self::NearestEven => {
+                if (value - value.trunc()).abs() == 0.5 {
+                    2.0 * (value / 2.0).round()
+                } else {
+                    value.round()
+                }
+            }
+            Self::Floor => value.floor(),
+            Self::Ceil => value.ceil(),
+            Self::Trunc => value.trunc(),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CastOverflow {
+    /// Saturate to the minimum/maximum of the output data type.
+    #[default]
+    Saturate,
+    /// Fail if a value is not representable by the output data type.
+    Error,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CastNan {
+    /// Replace NaN with the fill value of the output.
+    #[default]
+    FillValue,
+    /// Replace NaN with zero.
+    Zero,
+    /// Fail if a NaN is encountered.
+    Error,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct CastArguments {
+    /// Rounding mode for float to integer casts.
+    #[arg(long, value_enum, default_value_t = CastRounding::Nearest)]
+    #[serde(default)]
+    pub rounding: CastRounding,
+    /// Behaviour if a value is outside the range of the output data type.
+    #[arg(long, value_enum, default_value_t = CastOverflow::Saturate)]
+    #[serde(default)]
+    pub overflow: CastOverflow,
+    /// Behaviour for NaN in float to integer casts.
+    #[arg(long, value_enum, default_value_t = CastNan::FillValue)]
+    #[serde(default)]
+    pub nan: CastNan,
+}
+
+impl FilterArguments for CastArguments {
+    fn name(&self) -> String {
+        "cast".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Cast::new(
+            self.rounding,
+            self.overflow,
+            self.nan,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Cast {
+    rounding: CastRounding,
+    overflow: CastOverflow,
+    nan: CastNan,
+    chunk_limit: Option<usize>,
+}
+
+impl Cast {
+    pub fn new(
+        rounding: CastRounding,
+        overflow: CastOverflow,
+        nan: CastNan,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            rounding,
+            overflow,
+            nan,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        fill_value: TOut,
+        round: bool,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + ToPrimitive + AsPrimitive<f64> + 'static,
+        TOut: Element + Send + Sync + Copy + NumCast + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements_in =
+            progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_output_subset))?;
+
+        let elements_out =
+            progress.process(|| self.apply_elements(&elements_in, fill_value, round))?;
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Cast `elements_in` to `TOut`.
+    ///
+    /// Values are only rounded if `round` is true, which should be the case for integer outputs.
+    pub fn apply_elements<TIn, TOut>(
+        &self,
+        elements_in: &[TIn],
+        fill_value: TOut,
+        round: bool,
+    ) -> Result<Vec<TOut>, FilterError>
+    where
+        TIn: Send + Sync + Copy + ToPrimitive + AsPrimitive<f64> + 'static,
+        TOut: Send + Sync + Copy + NumCast + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let rounded = |value: f64| {
+            if round {
+                self.rounding.apply(value)
+            } else {
+                value
+            }
+        };
+
+        // Vectorised if every (rounded) element is exactly representable by f64 and in the range of the output, which is checked in a first pass
+        // 64-bit integers are not, since they are rounded by the conversion to f64 before they could be checked
+        const MAX_EXACT: f64 = (1u64 << f64::MANTISSA_DIGITS) as f64;
+        let is_integer_64 = std::any::TypeId::of::<TIn>() == std::any::TypeId::of::<i64>()
+            || std::any::TypeId::of::<TIn>() == std::any::TypeId::of::<u64>();
+        if let Some((min, max)) =
+            simd_min_max(elements_in, |value| rounded(value.as_())).filter(|_| !is_integer_64)
+        {
+            if min > -MAX_EXACT
+                && max < MAX_EXACT
+                && <TOut as NumCast>::from(min).is_some()
+                && <TOut as NumCast>::from(max).is_some()
+            {
+                return Ok(simd_map(elements_in, |value| {
+                    <TOut as NumCast>::from(rounded(value.as_())).unwrap_or(fill_value)
+                }));
+            }
+        }
+
+        elements_in
+            .par_iter()
+            .map(|value| {
+                let value_f64: f64 = value.as_();
+                if value_f64.is_nan() {
+                    if let Some(value) = <TOut as NumCast>::from(value_f64) {
+                        // Float output
+                        return Ok(value);
+                    }
+                    return match self.nan {
+                        CastNan::FillValue => Ok(fill_value),
+                        CastNan::Zero => Ok(0.0.as_()),
+                        CastNan::Error => Err(FilterError::Other(
+                            "cannot cast NaN to the output data type".to_string(),
+                        )),
+                    };
+                }
+
+                let (value_f64, cast) = if value_f64.fract() == 0.0 {
+                    // Integral values are cast directly to avoid a loss of precision through f64
+                    (value_f64, <TOut as NumCast>::from(*value))
+                } else {
+                    let value_f64 = rounded(value_f64);
+                    (value_f64, <TOut as NumCast>::from(value_f64))
+                };
+                match (cast, self.overflow) {
+                    (Some(cast), _) => Ok(cast),
+                    (None, CastOverflow::Saturate) => Ok(value_f64.as_()),
+                    (None, CastOverflow::Error) => Err(FilterError::Other(format!(
+                        "cast overflow: {value_f64} is not representable by the output data type"
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<TOut>, FilterError>>()
+    }
+}
+
+impl FilterTraits for Cast {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn is_elementwise(&self) -> bool {
+        true
+    }
+
+    fn apply_elementwise(
+        &self,
+        bytes: &[u8],
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+    ) -> Result<Vec<u8>, FilterError> {
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+        macro_rules! apply_output {
+            ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                match output.data_type() {
+                    $(DataType::$data_type_out => {
+                        let fill_value = <$type_out>::from_ne_bytes(output.fill_value().as_ne_bytes().try_into().unwrap());
+                        let elements_in = elements_from_bytes::<$type_in>(bytes);
+                        let elements_out = self.apply_elements::<$type_in, $type_out>(&elements_in, fill_value, round)?;
+                        Ok(elements_to_bytes(&elements_out))
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        macro_rules! apply_input {
+            ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                match input.data_type() {
+                    $(DataType::$data_type_in => {
+                        apply_output!($type_in, [
+                            (Int8, i8),
+                            (Int16, i16),
+                            (Int32, i32),
+                            (Int64, i64),
+                            (UInt8, u8),
+                            (UInt16, u16),
+                            (UInt32, u32),
+                            (UInt64, u64),
+                            (BFloat16, half::bf16),
+                            (Float16, half::f16),
+                            (Float32, f32),
+                            (Float64, f64)
+                        ])
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        apply_input!([
+            (Int8, i8),
+            (Int16, i16),
+            (Int32, i32),
+            (Int64, i64),
+            (UInt8, u8),
+            (UInt16, u16),
+            (UInt32, u32),
+            (UInt64, u64),
+            (BFloat16, half::bf16),
+            (Float16, half::f16),
+            (Float32, f32),
+            (Float64, f64)
+        ])
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type => {
+                                let fill_value = <$t_out>::from_ne_bytes(output.fill_value().as_ne_bytes().try_into().unwrap());
+                                self.apply_chunk::<$t_in, $t_out>(&input, &output, &chunk_indices, fill_value, round, &progress)
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                            match output.data_type() {
+                                $(
+                                    DataType::$data_type => {
+                                        apply_input!($type_out, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cast_integer_64() -> Result<(), FilterError> {
+        let cast = Cast::new(
+            CastRounding::Nearest,
+            CastOverflow::Saturate,
+            CastNan::FillValue,
+            None,
+        );
+        // 2^53 + 1 is not representable by f64
+        let elements = [(1u64 << 53) + 1, u64::MAX, 1];
+        assert_eq!(
+            cast.apply_elements::<u64, u64>(&elements, 0, true)?,
+            elements
+        );
+        assert_eq!(
+            cast.apply_elements::<u64, i64>(&elements, 0, true)?,
+            [(1i64 << 53) + 1, i64::MAX, 1]
+        );
+        let elements = [-(1i64 << 53) - 1, i64::MIN, -1];
+        assert_eq!(
+            cast.apply_elements::<i64, i64>(&elements, 0, true)?,
+            elements
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cast_float() -> Result<(), FilterError> {
+        let cast = Cast::new(
+            CastRounding::Nearest,
+            CastOverflow::Saturate,
+            CastNan::FillValue,
+            None,
+        );
+        assert_eq!(
+            cast.apply_elements::<f32, u8>(&[-1.0, 1.5, 254.4, 300.0, f32::NAN], 7, true)?,
+            [0, 2, 254, 255, 7]
+        );
+        let error = Cast::new(
+            CastRounding::Trunc,
+            CastOverflow::Error,
+            CastNan::Error,
+            None,
+        );
+        assert!(error.apply_elements::<f32, u8>(&[1.9], 0, true)? == [1]);
+        assert!(error.apply_elements::<f32, u8>(&[256.0], 0, true).is_err());
+        assert!(error
+            .apply_elements::<f32, u8>(&[f32::NAN], 0, true)
+            .is_err());
+        Ok(())
+    }
+}
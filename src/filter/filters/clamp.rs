@@ -1,27 +1,51 @@
 use clap::Parser;
 use num_traits::AsPrimitive;
-use rayon::iter::{IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use zarrs::{
-    array::{data_type::UnsupportedDataTypeError, Array, DataType},
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, FillValueMetadata},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
-        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        FilterArguments, FilterCommonArguments,
+        calculate_chunk_limit, elements_from_bytes, elements_to_bytes, filter_error::FilterError,
+        filter_traits::FilterTraits, resolve_time_value, simd_clamp, FilterArguments,
+        FilterCommonArguments, FilterStorage,
     },
+    parse_fill_value,
     progress::{Progress, ProgressCallback},
 };
 
 #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
 pub struct ClampArguments {
-    /// Minimum.
-    pub min: f64,
-    /// Maximum.
-    pub max: f64,
+    /// Minimum (lower bound).
+    ///
+    /// Examples:
+    ///   int/uint/float: 0
+    ///   datetime: '"2020-01-01T00:00:00"' (int64 with a datetime units attribute)
+    ///   timedelta: '"90 s"' (int64 with a timedelta units attribute)
+    #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
+    pub min: FillValueMetadata,
+    /// Maximum (upper bound).
+    #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
+    pub max: FillValueMetadata,
+}
+
+/// Convert a numeric bound to `T`, preserving the precision of integer bounds.
+fn bound_as<T>(bound: &FillValueMetadata) -> Option<T>
+where
+    T: Copy + 'static,
+    i64: AsPrimitive<T>,
+    u64: AsPrimitive<T>,
+    f64: AsPrimitive<T>,
+{
+    match bound {
+        FillValueMetadata::Int(int) => Some(int.as_()),
+        FillValueMetadata::UInt(uint) => Some(uint.as_()),
+        FillValueMetadata::Float(_) => bound.try_as_float::<f64>().map(|float| float.as_()),
+        _ => None,
+    }
 }
 
 impl FilterArguments for ClampArguments {
@@ -33,22 +57,30 @@ impl FilterArguments for ClampArguments {
         &self,
         common_args: &FilterCommonArguments,
     ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        // Datetime/timedelta bounds are validated once the units of the input are known
+        if let (Some(min), Some(max)) = (bound_as::<f64>(&self.min), bound_as::<f64>(&self.max)) {
+            if min.is_nan() || max.is_nan() || min > max {
+                return Err(FilterError::InvalidParameters(format!(
+                    "clamp minimum {min} must be less than or equal to the maximum {max} (and neither may be NaN)"
+                )));
+            }
+        }
         Ok(Box::new(Clamp::new(
-            self.min,
-            self.max,
+            self.min.clone(),
+            self.max.clone(),
             *common_args.chunk_limit(),
         )))
     }
 }
 
 pub struct Clamp {
-    min: f64,
-    max: f64,
+    min: FillValueMetadata,
+    max: FillValueMetadata,
     chunk_limit: Option<usize>,
 }
 
 impl Clamp {
-    pub fn new(min: f64, max: f64, chunk_limit: Option<usize>) -> Self {
+    pub fn new(min: FillValueMetadata, max: FillValueMetadata, chunk_limit: Option<usize>) -> Self {
         Self {
             min,
             max,
@@ -56,16 +88,43 @@ impl Clamp {
         }
     }
 
-    pub fn apply_elements_inplace<T>(&self, elements: &mut [T]) -> Result<(), FilterError>
+    /// Resolve the bounds for an input array, converting datetime/timedelta bounds to the units of the input.
+    fn bounds<T>(&self, input: &Array<FilterStorage>) -> Result<(T, T), FilterError>
     where
-        T: bytemuck::Pod + Copy + Send + Sync + PartialOrd,
+        T: Copy + PartialOrd + 'static,
+        i64: AsPrimitive<T>,
+        u64: AsPrimitive<T>,
         f64: AsPrimitive<T>,
     {
-        let min: T = self.min.as_();
-        let max: T = self.max.as_();
-        elements
-            .par_iter_mut()
-            .for_each(|value| *value = num_traits::clamp(*value, min, max));
+        let bound = |value: &FillValueMetadata| {
+            bound_as::<T>(&resolve_time_value(input, value)?).ok_or_else(|| {
+                FilterError::InvalidParameters(format!(
+                    "clamp bound {value} is not compatible with input data type {}",
+                    input.data_type()
+                ))
+            })
+        };
+        let (min, max) = (bound(&self.min)?, bound(&self.max)?);
+        if min <= max {
+            Ok((min, max))
+        } else {
+            Err(FilterError::InvalidParameters(format!(
+                "clamp minimum {} must be less than or equal to the maximum {}",
+                self.min, self.max
+            )))
+        }
+    }
+
+    pub fn apply_elements_inplace<T>(
+        &self,
+        elements: &mut [T],
+        min: T,
+        max: T,
+    ) -> Result<(), FilterError>
+    where
+        T: bytemuck::Pod + Copy + Send + Sync + PartialOrd,
+    {
+        simd_clamp(elements, min, max);
         Ok(())
     }
 }
@@ -105,10 +164,75 @@ impl FilterTraits for Clamp {
         chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
     }
 
+    fn is_elementwise(&self) -> bool {
+        true
+    }
+
+    fn apply_elementwise(
+        &self,
+        bytes: &[u8],
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+    ) -> Result<Vec<u8>, FilterError> {
+        macro_rules! apply_output {
+            ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                match output.data_type() {
+                    $(DataType::$data_type_out => {
+                        let (min, max) = self.bounds::<$type_in>(input)?;
+                        let mut elements = elements_from_bytes::<$type_in>(bytes);
+                        self.apply_elements_inplace::<$type_in>(&mut elements, min, max)?;
+                        let elements_out = elements.into_iter().map(|v| v.as_()).collect::<Vec<$type_out>>();
+                        Ok(elements_to_bytes(&elements_out))
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        macro_rules! apply_input {
+            ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                match input.data_type() {
+                    $(DataType::$data_type_in => {
+                        apply_output!($type_in, [
+                            (Bool, u8),
+                            (Int8, i8),
+                            (Int16, i16),
+                            (Int32, i32),
+                            (Int64, i64),
+                            (UInt8, u8),
+                            (UInt16, u16),
+                            (UInt32, u32),
+                            (UInt64, u64),
+                            (BFloat16, half::bf16),
+                            (Float16, half::f16),
+                            (Float32, f32),
+                            (Float64, f64)
+                        ])
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        apply_input!([
+            (Bool, u8),
+            (Int8, i8),
+            (Int16, i16),
+            (Int32, i32),
+            (Int64, i64),
+            (UInt8, u8),
+            (UInt16, u16),
+            (UInt32, u32),
+            (UInt64, u64),
+            (BFloat16, half::bf16),
+            (Float16, half::f16),
+            (Float32, f32),
+            (Float64, f64)
+        ])
+    }
+
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
@@ -136,9 +260,10 @@ impl FilterTraits for Clamp {
                         match input.data_type() {
                             $(DataType::$data_type_in => {
                                 let input_output_subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+                                let (min, max) = self.bounds::<$t_in>(input)?;
                                 let mut elements_in =
                                     progress.read(|| input.retrieve_array_subset_elements::<$t_in>(&input_output_subset))?;
-                                progress.process(|| self.apply_elements_inplace::<$t_in>(&mut elements_in))?;
+                                progress.process(|| self.apply_elements_inplace::<$t_in>(&mut elements_in, min, max))?;
 
                                 // macro_rules! apply_input_inner {
                                 //     ($t_in, $t_in) => {{
@@ -0,0 +1,392 @@
+use clap::Parser;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, DataType, Element, ElementOwned,
+        FillValue, FillValueMetadata,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    get_array_builder_reencode, parse_fill_value,
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+use crate::filter::{
+    calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+    resolve_time_value, simd_map, FilterArguments, FilterCommonArguments, FilterStorage,
+    UNITS_ATTRIBUTE,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComparisonOperator {
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl ComparisonOperator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Greater => "greater",
+            Self::GreaterEqual => "greater_equal",
+            Self::Less => "less",
+            Self::LessEqual => "less_equal",
+        }
+    }
+
+    #[inline]
+    pub fn compare<T: PartialOrd>(&self, lhs: &T, rhs: &T) -> bool {
+        match self {
+            Self::Greater => lhs > rhs,
+            Self::GreaterEqual => lhs >= rhs,
+            Self::Less => lhs < rhs,
+            Self::LessEqual => lhs <= rhs,
+        }
+    }
+}
+
+macro_rules! comparison_arguments {
+    ( $name:ident, $operator:ident, $doc:literal ) => {
+        #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+        pub struct $name {
+            #[doc = $doc]
+            ///
+            /// The value must be compatible with the data type.
+            ///
+            /// Examples:
+            ///   int/uint: 0
+            ///   float: 0.0 "NaN" "Infinity" "-Infinity"
+            ///   datetime: '"2020-01-01T00:00:00"' (int64 with a datetime units attribute)
+            ///   timedelta: '"90 s"' (int64 with a timedelta units attribute)
+            #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
+            pub value: FillValueMetadata,
+        }
+
+        impl FilterArguments for $name {
+            fn name(&self) -> String {
+                ComparisonOperator::$operator.name().to_string()
+            }
+
+            fn init(
+                &self,
+                common_args: &FilterCommonArguments,
+            ) -> Result<Box<dyn FilterTraits>, FilterError> {
+                Ok(Box::new(Comparison::new(
+                    ComparisonOperator::$operator,
+                    self.value.clone(),
+                    *common_args.chunk_limit(),
+                )))
+            }
+        }
+    };
+}
+
+comparison_arguments!(
+    GreaterArguments,
+    Greater,
+    "The value that the input must be greater than."
+);
+comparison_arguments!(
+    GreaterEqualArguments,
+    GreaterEqual,
+    "The value that the input must be greater than or equal to."
+);
+comparison_arguments!(
+    LessArguments,
+    Less,
+    "The value that the input must be less than."
+);
+comparison_arguments!(
+    LessEqualArguments,
+    LessEqual,
+    "The value that the input must be less than or equal to."
+);
+
+pub struct Comparison {
+    operator: ComparisonOperator,
+    value: FillValueMetadata,
+    chunk_limit: Option<usize>,
+}
+
+impl Comparison {
+    pub fn new(
+        operator: ComparisonOperator,
+        value: FillValueMetadata,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            operator,
+            value,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_elements<TIn, TOut>(
+        &self,
+        input_elements: &[TIn],
+        value: &TIn,
+    ) -> Result<Vec<TOut>, FilterError>
+    where
+        TIn: ElementOwned + Copy + Send + Sync + PartialOrd,
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        let value = *value;
+        let output_elements = match self.operator {
+            ComparisonOperator::Greater => simd_map(input_elements, |e| TOut::from(e > value)),
+            ComparisonOperator::GreaterEqual => {
+                simd_map(input_elements, |e| TOut::from(e >= value))
+            }
+            ComparisonOperator::Less => simd_map(input_elements, |e| TOut::from(e < value)),
+            ComparisonOperator::LessEqual => simd_map(input_elements, |e| TOut::from(e <= value)),
+        };
+        Ok(output_elements)
+    }
+}
+
+impl FilterTraits for Comparison {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            _ => Err(UnsupportedDataTypeError::from(
+                chunk_input.data_type().to_string(),
+            ))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Bool | DataType::UInt8 => {}
+            _ => Err(UnsupportedDataTypeError::from(
+                chunk_output.data_type().to_string(),
+            ))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::Bool, FillValue::from(false)))
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        // The output is a mask, so the datetime/timedelta units of the input do not apply
+        let mut array_builder = get_array_builder_reencode(
+            &self.output_reencoding_args(array_input, reencoding_args),
+            array_input,
+            self.output_shape(array_input),
+        );
+        array_builder.attributes.remove(UNITS_ATTRIBUTE);
+        array_builder
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let value = input
+            .data_type()
+            .fill_value_from_metadata(&resolve_time_value(input, &self.value)?)
+            .map_err(|_| {
+                FilterError::InvalidParameters(format!(
+                    "value {:?} is not compatible with input data type {}",
+                    self.value,
+                    input.data_type()
+                ))
+            })?;
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                let input_output_subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type_in:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type_in => {
+                                let input_elements =
+                                    progress.read(|| input.retrieve_array_subset_elements::<$t_in>(&input_output_subset))?;
+
+                                let value = <$t_in>::from_array_bytes(input.data_type(), value.as_ne_bytes().into())?[0];
+                                let output_elements =
+                                    progress.process(|| self.apply_elements::<$t_in, $t_out>(&input_elements, &value))?;
+                                drop(input_elements);
+
+                                progress.write(|| {
+                                    output.store_array_subset_elements::<$t_out>(&input_output_subset, &output_elements)
+                                })?;
+
+                                progress.next();
+                                Ok(())
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                            match output.data_type() {
+                                $(
+                                    DataType::$data_type_out => {
+                                        apply_input!($type_out, [
+                                            (Bool, bool),
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_output!([(Bool, bool), (UInt8, u8)])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn comparison() -> Result<(), FilterError> {
+        let elements: Vec<f32> = vec![-f32::INFINITY, 0.0, 1.0, 2.0, f32::NAN];
+        let value = FillValueMetadata::Int(1);
+        let compare = |operator| {
+            Comparison::new(operator, value.clone(), None)
+                .apply_elements::<f32, bool>(&elements, &1.0)
+        };
+
+        // A NaN input is never selected
+        assert_eq!(
+            compare(ComparisonOperator::Greater)?,
+            vec![false, false, false, true, false]
+        );
+        assert_eq!(
+            compare(ComparisonOperator::GreaterEqual)?,
+            vec![false, false, true, true, false]
+        );
+        assert_eq!(
+            compare(ComparisonOperator::Less)?,
+            vec![true, true, false, false, false]
+        );
+        assert_eq!(
+            compare(ComparisonOperator::LessEqual)?,
+            vec![true, true, true, false, false]
+        );
+
+        // A NaN value selects nothing
+        let nan = Comparison::new(ComparisonOperator::LessEqual, value, None)
+            .apply_elements::<f32, u8>(&elements, &f32::NAN)?;
+        assert_eq!(nan, vec![0; 5]);
+
+        // Integers at the limits of the data type
+        let elements: Vec<i64> = vec![i64::MIN, -1, 0, i64::MAX];
+        assert_eq!(
+            Comparison::new(
+                ComparisonOperator::Greater,
+                FillValueMetadata::Int(i64::MIN),
+                None
+            )
+            .apply_elements::<i64, u8>(&elements, &i64::MIN)?,
+            vec![0, 1, 1, 1]
+        );
+        assert_eq!(
+            Comparison::new(
+                ComparisonOperator::Less,
+                FillValueMetadata::Int(i64::MAX),
+                None
+            )
+            .apply_elements::<i64, u8>(&elements, &i64::MAX)?,
+            vec![1, 1, 1, 0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn comparison_bool() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        let elements = [false, true, true, false];
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4],
+                DataType::Bool,
+                vec![2].try_into().unwrap(),
+                false.into(),
+            ),
+            &elements,
+        )?;
+
+        let compare = |filter: &str| -> Result<Vec<bool>, Box<dyn std::error::Error>> {
+            retrieve_test_elements(&run_test_filter(filter, &input, &output, "value: false")?)
+        };
+        assert_eq!(compare("greater")?, elements);
+        assert_eq!(compare("less_equal")?, elements.map(|e| !e));
+        Ok(())
+    }
+}
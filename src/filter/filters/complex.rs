@@ -0,0 +1,365 @@
+use clap::Parser;
+use num_complex::{Complex32, Complex64};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplexPart {
+    /// The magnitude (absolute value).
+    Abs,
+    /// The phase (argument) in radians, in the range [-pi, pi].
+    Phase,
+    /// The real part.
+    Real,
+    /// The imaginary part.
+    Imag,
+}
+
+impl ComplexPart {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Abs => "abs",
+            Self::Phase => "phase",
+            Self::Real => "real",
+            Self::Imag => "imag",
+        }
+    }
+
+    #[inline]
+    pub fn apply(&self, value: Complex64) -> f64 {
+        match self {
+            Self::Abs => value.norm(),
+            Self::Phase => value.arg(),
+            Self::Real => value.re,
+            Self::Imag => value.im,
+        }
+    }
+}
+
+/// An element that can be interpreted as a complex number.
+///
+/// Real elements have a zero imaginary part.
+pub trait ToComplex64: Copy {
+    fn to_complex64(self) -> Complex64;
+}
+
+macro_rules! impl_to_complex64_real {
+    ( $( $type:ty ),* ) => {
+        $(
+            impl ToComplex64 for $type {
+                #[inline]
+                fn to_complex64(self) -> Complex64 {
+                    Complex64::new(self.as_(), 0.0)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_complex64_real!(
+    u8,
+    i8,
+    i16,
+    i32,
+    i64,
+    u16,
+    u32,
+    u64,
+    half::bf16,
+    half::f16,
+    f32,
+    f64
+);
+
+impl ToComplex64 for Complex32 {
+    #[inline]
+    fn to_complex64(self) -> Complex64 {
+        Complex64::new(self.re.into(), self.im.into())
+    }
+}
+
+impl ToComplex64 for Complex64 {
+    #[inline]
+    fn to_complex64(self) -> Complex64 {
+        self
+    }
+}
+
+macro_rules! complex_arguments {
+    ( $name:ident, $part:ident ) => {
+        #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+        pub struct $name {}
+
+        impl FilterArguments for $name {
+            fn name(&self) -> String {
+                ComplexPart::$part.name().to_string()
+            }
+
+            fn init(
+                &self,
+                common_args: &FilterCommonArguments,
+            ) -> Result<Box<dyn FilterTraits>, FilterError> {
+                Ok(Box::new(Complex::new(
+                    ComplexPart::$part,
+                    *common_args.chunk_limit(),
+                )))
+            }
+        }
+    };
+}
+
+complex_arguments!(AbsArguments, Abs);
+complex_arguments!(PhaseArguments, Phase);
+complex_arguments!(RealArguments, Real);
+complex_arguments!(ImagArguments, Imag);
+
+pub struct Complex {
+    part: ComplexPart,
+    chunk_limit: Option<usize>,
+}
+
+impl Complex {
+    pub fn new(part: ComplexPart, chunk_limit: Option<usize>) -> Self {
+        Self { part, chunk_limit }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + ToComplex64,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements =
+            progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_output_subset))?;
+
+        let elements_out = progress.process(|| self.apply_elements::<TIn, TOut>(&elements));
+        drop(elements);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    pub fn apply_elements<TIn, TOut>(&self, elements: &[TIn]) -> Vec<TOut>
+    where
+        TIn: Send + Sync + ToComplex64,
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        elements
+            .par_iter()
+            .map(|value| self.part.apply(value.to_complex64()).as_())
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for Complex {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16
+            | DataType::Complex64
+            | DataType::Complex128 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize() * chunk_input.fixed_element_size().unwrap()
+            + chunk_output.num_elements_usize() * chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        // The parts of a complex number are real numbers of the same precision
+        match input.data_type() {
+            DataType::Complex64 => Some((DataType::Float32, FillValue::from(0.0f32))),
+            DataType::Complex128 => Some((DataType::Float64, FillValue::from(0.0f64))),
+            _ => None,
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64),
+                    (Complex64, Complex32),
+                    (Complex128, Complex64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complex_elements() {
+        let elements = [
+            Complex32::new(3.0, 4.0),
+            Complex32::new(-1.0, 0.0),
+            Complex32::new(0.0, -2.0),
+        ];
+        assert_eq!(
+            Complex::new(ComplexPart::Abs, None).apply_elements::<Complex32, f32>(&elements),
+            vec![5.0, 1.0, 2.0]
+        );
+        assert_eq!(
+            Complex::new(ComplexPart::Phase, None).apply_elements::<Complex32, f64>(&elements),
+            vec![
+                4.0f64.atan2(3.0),
+                std::f64::consts::PI,
+                -std::f64::consts::FRAC_PI_2
+            ]
+        );
+        assert_eq!(
+            Complex::new(ComplexPart::Real, None).apply_elements::<Complex32, i16>(&elements),
+            vec![3, -1, 0]
+        );
+        assert_eq!(
+            Complex::new(ComplexPart::Imag, None).apply_elements::<Complex32, f32>(&elements),
+            vec![4.0, 0.0, -2.0]
+        );
+        // Real elements have a zero imaginary part
+        assert_eq!(
+            Complex::new(ComplexPart::Abs, None).apply_elements::<i8, u8>(&[-3, 2]),
+            vec![3, 2]
+        );
+    }
+}
@@ -0,0 +1,446 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use itertools::Itertools;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        gpu_correlate_axes,
+        kernel::{convolve_1d_valid, convolve_nd_valid, pad_ndarray},
+        num_elements_haloed, retrieve_chunk_haloed_ndarray, BorderMode, Correlate1d,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ConvolveArguments {
+    /// A 1D kernel applied separably along each axis in `axes`, comma delimited. Must have an odd length.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        allow_hyphen_values(true),
+        required_unless_present = "kernel_file",
+        conflicts_with = "kernel_file"
+    )]
+    #[serde(default)]
+    pub kernel: Option<Vec<f32>>,
+    /// Axes to apply the 1D kernel along, comma delimited. Defaults to all axes.
+    #[arg(long, value_delimiter = ',', requires = "kernel")]
+    #[serde(default)]
+    pub axes: Option<Vec<usize>>,
+    /// Path to a JSON file holding an ND kernel as nested arrays, e.g. [[0, 1, 0], [1, -4, 1], [0, 1, 0]].
+    ///
+    /// The kernel must have the dimensionality of the input and an odd length along each axis.
+    #[arg(long)]
+    #[serde(default)]
+    pub kernel_file: Option<PathBuf>,
+    /// Behaviour of the kernel beyond the boundaries of the input.
+    #[arg(long, value_enum, default_value_t = BorderMode::Nearest)]
+    #[serde(default)]
+    pub border: BorderMode,
+    /// The value beyond the boundaries of the input with the constant border mode.
+    #[arg(long, allow_hyphen_values(true), default_value_t = 0.0)]
+    #[serde(default)]
+    pub cval: f32,
+}
+
+impl FilterArguments for ConvolveArguments {
+    fn name(&self) -> String {
+        "convolve".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let kernel = match (&self.kernel, &self.kernel_file) {
+            (Some(kernel), None) => {
+                if kernel.len() % 2 == 0 {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "convolve kernel length {} must be odd",
+                        kernel.len()
+                    )));
+                }
+                ConvolveKernel::Separable {
+                    kernel: kernel.clone(),
+                    axes: self.axes.clone(),
+                }
+            }
+            (None, Some(kernel_file)) => ConvolveKernel::Nd(read_kernel_file(kernel_file)?),
+            _ => {
+                return Err(FilterError::InvalidParameters(
+                    "convolve requires exactly one of a kernel or a kernel file".to_string(),
+                ))
+            }
+        };
+        Ok(Box::new(Convolve::new(
+            kernel,
+            self.border,
+            self.cval,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Read an ND kernel from a JSON file holding nested arrays.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, is not a rectangular nested array of numbers, or has an even length along an axis.
+pub fn read_kernel_file(path: &std::path::Path) -> Result<ndarray::ArrayD<f32>, FilterError> {
+    parse_kernel_json(&std::fs::read_to_string(path)?)
+}
+
+fn parse_kernel_json(contents: &str) -> Result<ndarray::ArrayD<f32>, FilterError> {
+    fn flatten(
+        value: &serde_json::Value,
+        depth: usize,
+        shape: &mut Vec<usize>,
+        elements: &mut Vec<f32>,
+    ) -> Option<()> {
+        match value {
+            serde_json::Value::Array(values) => {
+                if depth == shape.len() {
+                    shape.push(values.len());
+                } else if shape[depth] != values.len() {
+                    return None;
+                }
+                values
+                    .iter()
+                    .try_for_each(|value| flatten(value, depth + 1, shape, elements))
+            }
+            serde_json::Value::Number(number) if depth == shape.len() => {
+                elements.push(number.as_f64()? as f32);
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    let json: serde_json::Value = serde_json::from_str(contents)?;
+    let mut shape = vec![];
+    let mut elements = vec![];
+    let kernel = flatten(&json, 0, &mut shape, &mut elements)
+        .and_then(|()| ndarray::ArrayD::from_shape_vec(shape, elements).ok())
+        .filter(|kernel| kernel.ndim() > 0)
+        .ok_or_else(|| {
+            FilterError::InvalidParameters(
+                "convolve kernel file must hold a rectangular nested array of numbers".to_string(),
+            )
+        })?;
+    if kernel.shape().iter().any(|len| len % 2 == 0) {
+        return Err(FilterError::InvalidParameters(format!(
+            "convolve kernel shape {:?} must be odd along each axis",
+            kernel.shape()
+        )));
+    }
+    Ok(kernel)
+}
+
+pub enum ConvolveKernel {
+    /// A 1D kernel applied separably along each axis in `axes`, or all axes if [`None`].
+    Separable {
+        kernel: Vec<f32>,
+        axes: Option<Vec<usize>>,
+    },
+    /// An ND kernel.
+    Nd(ndarray::ArrayD<f32>),
+}
+
+pub struct Convolve {
+    kernel: ConvolveKernel,
+    border: BorderMode,
+    cval: f32,
+    chunk_limit: Option<usize>,
+}
+
+impl Convolve {
+    pub fn new(
+        kernel: ConvolveKernel,
+        border: BorderMode,
+        cval: f32,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            kernel,
+            border,
+            cval,
+            chunk_limit,
+        }
+    }
+
+    /// Returns true if the kernel applies along `axis`.
+    fn applies_along(&self, axis: usize) -> bool {
+        match &self.kernel {
+            ConvolveKernel::Separable { axes, .. } => {
+                axes.as_ref().map_or(true, |axes| axes.contains(&axis))
+            }
+            ConvolveKernel::Nd(kernel) => kernel.shape()[axis] > 1,
+        }
+    }
+
+    /// The kernel half size along each axis of an input with `dimensionality`.
+    pub fn kernel_half_size(&self, dimensionality: usize) -> Vec<u64> {
+        (0..dimensionality)
+            .map(|axis| match &self.kernel {
+                ConvolveKernel::Separable { kernel, .. } if self.applies_along(axis) => {
+                    kernel.len() as u64 / 2
+                }
+                ConvolveKernel::Separable { .. } => 0,
+                ConvolveKernel::Nd(kernel) => kernel.shape()[axis] as u64 / 2,
+            })
+            .collect()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<f32>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f32: AsPrimitive<TOut>,
+    {
+        let kernel_half_size = self.kernel_half_size(input.dimensionality());
+        let (subset_output, subset_overlap, input_array) = retrieve_chunk_haloed_ndarray::<TIn>(
+            input,
+            output,
+            chunk_indices,
+            &kernel_half_size,
+            progress,
+        )?;
+
+        let output_array = progress.process(|| {
+            let input_array = input_array.mapv(|x| x.as_());
+
+            // Pad where the overlap is truncated at the boundaries of the input
+            let pad = subset_overlap.truncated_overlap(&kernel_half_size);
+            let input_array = pad_ndarray(&input_array, &pad, self.border, self.cval);
+
+            let output_array = match &self.kernel {
+                ConvolveKernel::Separable { kernel, .. } => {
+                    let axes = (0..input_array.ndim())
+                        .filter(|axis| self.applies_along(*axis))
+                        .collect_vec();
+                    // A convolution is a correlation with the reversed kernel
+                    let passes = axes
+                        .iter()
+                        .map(|&axis| Correlate1d {
+                            axis,
+                            kernel: kernel.iter().rev().copied().collect(),
+                            offset: 0,
+                            output_len: input_array.shape()[axis] + 1 - kernel.len(),
+                        })
+                        .collect_vec();
+                    gpu_correlate_axes(&input_array, &passes).unwrap_or_else(|| {
+                        axes.into_iter().fold(input_array, |array, axis| {
+                            convolve_1d_valid(axis, kernel, &array)
+                        })
+                    })
+                }
+                ConvolveKernel::Nd(kernel) => convolve_nd_valid(kernel, &input_array),
+            };
+            output_array.mapv(|x| x.as_())
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Convolve {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        match &self.kernel {
+            ConvolveKernel::Separable {
+                axes: Some(axes), ..
+            } if axes.iter().any(|axis| *axis >= dimensionality) => {
+                return Err(FilterError::InvalidParameters(format!(
+                    "convolve axes {axes:?} are out of bounds for an input with dimensionality {dimensionality}"
+                )));
+            }
+            ConvolveKernel::Nd(kernel) if kernel.ndim() != dimensionality => {
+                return Err(FilterError::InvalidParameters(format!(
+                    "convolve kernel dimensionality {} does not match the input dimensionality {dimensionality}",
+                    kernel.ndim()
+                )));
+            }
+            _ => {}
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn halo(&self, dimensionality: usize) -> Option<Vec<u64>> {
+        Some(self.kernel_half_size(dimensionality))
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let kernel_half_size = self.kernel_half_size(chunk_input.dimensionality());
+        let num_input_elements = num_elements_haloed(chunk_input.shape(), &kernel_half_size);
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_input_elements
+            * (chunk_input.data_type().fixed_size().unwrap() + core::mem::size_of::<f32>() * 3)
+            + num_output_elements
+                * (core::mem::size_of::<f32>() + chunk_output.data_type().fixed_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                            match input.data_type() {
+                                $(
+                                    DataType::$data_type_in => {
+                                        apply_output!($type_in, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_input!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::kernel::pad_ndarray;
+
+    #[test]
+    fn convolve_borders() -> Result<(), FilterError> {
+        let input = ndarray::array![1.0f32, 2.0, 3.0].into_dyn();
+        let pad = [(2, 2)];
+        assert_eq!(
+            pad_ndarray(&input, &pad, BorderMode::Nearest, 0.0),
+            ndarray::array![1.0, 1.0, 1.0, 2.0, 3.0, 3.0, 3.0].into_dyn()
+        );
+        assert_eq!(
+            pad_ndarray(&input, &pad, BorderMode::Reflect, 0.0),
+            ndarray::array![2.0, 1.0, 1.0, 2.0, 3.0, 3.0, 2.0].into_dyn()
+        );
+        assert_eq!(
+            pad_ndarray(&input, &pad, BorderMode::Constant, 9.0),
+            ndarray::array![9.0, 9.0, 1.0, 2.0, 3.0, 9.0, 9.0].into_dyn()
+        );
+
+        // Convolution flips the kernel
+        let padded = pad_ndarray(&input, &[(1, 1)], BorderMode::Constant, 0.0);
+        assert_eq!(
+            convolve_1d_valid(0, &[1.0, 0.0, -1.0], &padded),
+            ndarray::array![2.0, 2.0, -2.0].into_dyn()
+        );
+        let kernel = parse_kernel_json("[[1, 0, -1]]")?;
+        let padded = padded.insert_axis(ndarray::Axis(0));
+        assert_eq!(
+            convolve_nd_valid(&kernel, &padded),
+            ndarray::array![[2.0, 2.0, -2.0]].into_dyn()
+        );
+        assert!(parse_kernel_json("[[1, 0], [1]]").is_err());
+        assert!(parse_kernel_json("[1, 0]").is_err());
+        Ok(())
+    }
+}
@@ -3,27 +3,82 @@ use num_traits::AsPrimitive;
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use zarrs::{
-    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, DataType, Element, ElementOwned,
+    },
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
         calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        FilterArguments, FilterCommonArguments,
+        FilterArguments, FilterCommonArguments, FilterStorage,
     },
+    get_array_builder_reencode,
     progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
 };
 
+use super::resample::PIXEL_SIZE_ATTRIBUTE;
+
+/// The name of the attribute holding the physical position of the first element of each axis.
+pub const ORIGIN_ATTRIBUTE: &str = "origin";
+
+/// Rounding of a physical crop region to element indices.
+///
+/// Element `i` of an axis spans `origin + i * spacing` to `origin + (i + 1) * spacing`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CropRounding {
+    /// Round the start and end of the region to the nearest element boundary.
+    #[default]
+    Nearest,
+    /// Include every element that overlaps the region.
+    Outer,
+    /// Include only the elements entirely within the region.
+    Inner,
+}
+
 #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
 pub struct CropArguments {
     /// Crop offset, comma delimited.
-    #[arg(required = true, value_delimiter = ',')]
-    pub offset: Vec<u64>,
+    #[arg(required = true, value_delimiter = ',', allow_hyphen_values(true))]
+    pub offset: Vec<f64>,
     /// Crop shape, comma delimited.
     #[arg(required = true, value_delimiter = ',')]
-    pub shape: Vec<u64>,
+    pub shape: Vec<f64>,
+    /// Interpret the offset and shape in physical units rather than elements.
+    ///
+    /// The physical position of element `i` is `origin + i * spacing`.
+    /// The output `origin` attribute is set to the physical position of the first cropped element.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub physical: bool,
+    /// The pixel size of the input per axis for a physical crop, comma delimited.
+    ///
+    /// Defaults to the `pixel_size` attribute of the input if present, otherwise 1.
+    /// For OME-Zarr, this is the `scale` coordinate transformation of the dataset.
+    #[arg(long, value_delimiter = ',', requires = "physical")]
+    #[serde(default)]
+    pub spacing: Option<Vec<f64>>,
+    /// The physical position of the first element of the input per axis for a physical crop, comma delimited.
+    ///
+    /// Defaults to the `origin` attribute of the input if present, otherwise 0.
+    /// For OME-Zarr, this is the `translation` coordinate transformation of the dataset.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        allow_hyphen_values(true),
+        requires = "physical"
+    )]
+    #[serde(default)]
+    pub origin: Option<Vec<f64>>,
+    /// Rounding of a physical crop to element indices.
+    ///
+    /// The cropped region is clamped to the bounds of the input.
+    #[arg(long, value_enum, default_value_t = CropRounding::Nearest)]
+    #[serde(default)]
+    pub rounding: CropRounding,
 }
 
 impl FilterArguments for CropArguments {
@@ -35,38 +90,170 @@ impl FilterArguments for CropArguments {
         &self,
         common_args: &FilterCommonArguments,
     ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if self.physical {
+            return Ok(Box::new(Crop::new_physical(
+                PhysicalRegion {
+                    offset: self.offset.clone(),
+                    shape: self.shape.clone(),
+                    spacing: self.spacing.clone(),
+                    origin: self.origin.clone(),
+                    rounding: self.rounding,
+                },
+                *common_args.chunk_limit(),
+            )));
+        }
+        let to_index = |values: &[f64]| {
+            values
+                .iter()
+                .map(|&v| {
+                    if v >= 0.0 && v.fract() == 0.0 {
+                        Ok(v as u64)
+                    } else {
+                        Err(FilterError::InvalidParameters(format!(
+                            "crop offset and shape must be non-negative integers without --physical, got {v}"
+                        )))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
         Ok(Box::new(Crop::new(
-            self.offset.clone(),
-            self.shape.clone(),
+            to_index(&self.offset)?,
+            to_index(&self.shape)?,
             *common_args.chunk_limit(),
         )))
     }
 }
 
+/// A crop region in physical units.
+#[derive(Debug, Clone)]
+pub struct PhysicalRegion {
+    pub offset: Vec<f64>,
+    pub shape: Vec<f64>,
+    pub spacing: Option<Vec<f64>>,
+    pub origin: Option<Vec<f64>>,
+    pub rounding: CropRounding,
+}
+
+/// Returns the numeric array attribute `name` of `input` if it has an element per axis.
+fn attribute_per_axis(input: &Array<FilterStorage>, name: &str) -> Option<Vec<f64>> {
+    input
+        .attributes()
+        .get(name)
+        .and_then(|value| serde_json::from_value::<Vec<f64>>(value.clone()).ok())
+        .filter(|value| value.len() == input.dimensionality())
+}
+
+impl PhysicalRegion {
+    /// The pixel size and origin of `input`, from the explicit values, the input attributes, or 1 and 0.
+    pub fn spacing_origin(&self, input: &Array<FilterStorage>) -> (Vec<f64>, Vec<f64>) {
+        let spacing = self
+            .spacing
+            .clone()
+            .or_else(|| attribute_per_axis(input, PIXEL_SIZE_ATTRIBUTE))
+            .unwrap_or_else(|| vec![1.0; input.dimensionality()]);
+        let origin = self
+            .origin
+            .clone()
+            .or_else(|| attribute_per_axis(input, ORIGIN_ATTRIBUTE))
+            .unwrap_or_else(|| vec![0.0; input.dimensionality()]);
+        (spacing, origin)
+    }
+
+    /// Returns the offset and shape in elements of the region within an array of shape `array_shape`.
+    pub fn to_index(
+        &self,
+        array_shape: &[u64],
+        spacing: &[f64],
+        origin: &[f64],
+    ) -> Result<(Vec<u64>, Vec<u64>), FilterError> {
+        let dimensionality = array_shape.len();
+        if [
+            self.offset.len(),
+            self.shape.len(),
+            spacing.len(),
+            origin.len(),
+        ]
+        .iter()
+        .any(|&len| len != dimensionality)
+        {
+            return Err(FilterError::InvalidParameters(format!(
+                "crop offset {:?}, shape {:?}, spacing {spacing:?}, and origin {origin:?} must have the dimensionality of the input ({dimensionality})",
+                self.offset, self.shape
+            )));
+        }
+        if let Some(s) = spacing.iter().find(|s| !(s.is_finite() && **s > 0.0)) {
+            return Err(FilterError::InvalidParameters(format!(
+                "crop spacing {s} must be positive"
+            )));
+        }
+
+        type Round = fn(f64) -> f64;
+        let (round_start, round_end): (Round, Round) = match self.rounding {
+            CropRounding::Nearest => (f64::round, f64::round),
+            CropRounding::Outer => (f64::floor, f64::ceil),
+            CropRounding::Inner => (f64::ceil, f64::floor),
+        };
+        Ok(
+            itertools::izip!(&self.offset, &self.shape, spacing, origin, array_shape)
+                .map(|(offset, shape, spacing, origin, &len)| {
+                    let start = round_start((offset - origin) / spacing).clamp(0.0, len as f64);
+                    let end =
+                        round_end((offset + shape - origin) / spacing).clamp(start, len as f64);
+                    (start as u64, (end - start) as u64)
+                })
+                .unzip(),
+        )
+    }
+}
+
+enum CropRegion {
+    Index { offset: Vec<u64>, shape: Vec<u64> },
+    Physical(PhysicalRegion),
+}
+
 pub struct Crop {
-    offset: Vec<u64>,
-    shape: Vec<u64>,
+    region: CropRegion,
     chunk_limit: Option<usize>,
 }
 
 impl Crop {
     pub fn new(offset: Vec<u64>, shape: Vec<u64>, chunk_limit: Option<usize>) -> Self {
         Self {
-            offset,
-            shape,
+            region: CropRegion::Index { offset, shape },
             chunk_limit,
         }
     }
 
+    pub fn new_physical(region: PhysicalRegion, chunk_limit: Option<usize>) -> Self {
+        Self {
+            region: CropRegion::Physical(region),
+            chunk_limit,
+        }
+    }
+
+    /// Returns the offset and shape of the crop in elements.
+    pub fn offset_shape(
+        &self,
+        input: &Array<FilterStorage>,
+    ) -> Result<(Vec<u64>, Vec<u64>), FilterError> {
+        match &self.region {
+            CropRegion::Index { offset, shape } => Ok((offset.clone(), shape.clone())),
+            CropRegion::Physical(region) => {
+                let (spacing, origin) = region.spacing_origin(input);
+                region.to_index(input.shape(), &spacing, &origin)
+            }
+        }
+    }
+
     // Determine the input and output subset
     fn get_input_output_subset(
-        &self,
-        output: &Array<FilesystemStore>,
+        output: &Array<FilterStorage>,
+        offset: &[u64],
         chunk_indices: &[u64],
     ) -> (ArraySubset, ArraySubset) {
         let output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
         let input_subset = ArraySubset::new_with_start_shape(
-            std::iter::zip(output_subset.start(), self.offset.clone())
+            std::iter::zip(output_subset.start(), offset)
                 .map(|(s, o)| s + o)
                 .collect::<Vec<_>>(),
             output_subset.shape().to_vec(),
@@ -77,12 +264,14 @@ impl Crop {
 
     pub fn apply_chunk(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        offset: &[u64],
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError> {
-        let (input_subset, output_subset) = self.get_input_output_subset(output, chunk_indices);
+        let (input_subset, output_subset) =
+            Self::get_input_output_subset(output, offset, chunk_indices);
         let output_bytes = progress.read(|| input.retrieve_array_subset(&input_subset))?;
         progress.write(|| output.store_array_subset(&output_subset, output_bytes))?;
         progress.next();
@@ -91,8 +280,9 @@ impl Crop {
 
     pub fn apply_chunk_convert<TIn, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        offset: &[u64],
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
@@ -100,7 +290,8 @@ impl Crop {
         TIn: ElementOwned + Send + Sync + AsPrimitive<TOut>,
         TOut: Element + Send + Sync + Copy + 'static,
     {
-        let (input_subset, output_subset) = self.get_input_output_subset(output, chunk_indices);
+        let (input_subset, output_subset) =
+            Self::get_input_output_subset(output, offset, chunk_indices);
 
         let input_elements =
             progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_subset))?;
@@ -143,6 +334,9 @@ impl FilterTraits for Crop {
                 | DataType::Float32
                 | DataType::Float64
                 | DataType::BFloat16 => {}
+                // Complex and raw bits elements are copied without conversion
+                DataType::Complex64 | DataType::Complex128 | DataType::RawBits(_)
+                    if chunk_input.data_type() == chunk_output.data_type() => {}
                 _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
             };
         }
@@ -157,17 +351,63 @@ impl FilterTraits for Crop {
         chunk_output.fixed_element_size().unwrap()
     }
 
-    fn output_shape(&self, _input: &Array<FilesystemStore>) -> Option<Vec<u64>> {
-        Some(self.shape.clone())
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<Vec<u64>> {
+        self.offset_shape(input).ok().map(|(_, shape)| shape)
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        let mut builder = get_array_builder_reencode(
+            &self.output_reencoding_args(array_input, reencoding_args),
+            array_input,
+            self.output_shape(array_input),
+        );
+
+        // Preserve physical coordinates by moving the origin to the first cropped element
+        let spacing_origin = match &self.region {
+            CropRegion::Index { .. } => {
+                attribute_per_axis(array_input, ORIGIN_ATTRIBUTE).map(|origin| {
+                    let spacing = attribute_per_axis(array_input, PIXEL_SIZE_ATTRIBUTE)
+                        .unwrap_or_else(|| vec![1.0; array_input.dimensionality()]);
+                    (spacing, origin)
+                })
+            }
+            CropRegion::Physical(region) => Some(region.spacing_origin(array_input)),
+        };
+        if let (Some((spacing, origin)), Ok((offset, _))) =
+            (spacing_origin, self.offset_shape(array_input))
+        {
+            let origin: Vec<f64> = itertools::izip!(&offset, &spacing, &origin)
+                .map(|(&offset, spacing, origin)| origin + offset as f64 * spacing)
+                .collect();
+            builder
+                .attributes
+                .insert(ORIGIN_ATTRIBUTE.to_string(), origin.into());
+        }
+
+        builder
     }
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
-        assert_eq!(output.shape(), self.shape);
+        let (offset, shape) = self.offset_shape(input)?;
+        assert_eq!(output.shape(), shape);
+        if let Some((axis, _)) = itertools::izip!(&offset, &shape, input.shape())
+            .enumerate()
+            .find(|(_, (offset, shape, len))| *offset + *shape > **len)
+        {
+            return Err(FilterError::InvalidParameters(format!(
+                "crop offset {offset:?} and shape {shape:?} exceed the input shape {:?} along axis {axis}",
+                input.shape()
+            )));
+        }
 
         let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
         let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
@@ -188,12 +428,12 @@ impl FilterTraits for Crop {
             try_for_each,
             |chunk_indices: Vec<u64>| {
                 if input.data_type() == output.data_type() {
-                    self.apply_chunk(input, output, &chunk_indices, &progress)
+                    self.apply_chunk(input, output, &offset, &chunk_indices, &progress)
                 } else {
                     macro_rules! apply_output {
                         ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
                             match output.data_type() {
-                                $(DataType::$data_type_out => { self.apply_chunk_convert::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                                $(DataType::$data_type_out => { self.apply_chunk_convert::<$type_in, $type_out>(input, output, &offset, &chunk_indices, &progress) } ,)*
                                 _ => panic!()
                             }
                         };
@@ -246,3 +486,36 @@ impl FilterTraits for Crop {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_physical_rounding() {
+        let region = |rounding| PhysicalRegion {
+            offset: vec![1.4, -5.0],
+            shape: vec![2.4, 100.0],
+            spacing: None,
+            origin: None,
+            rounding,
+        };
+        // The first axis spans elements 2.8..7.6, the second is clamped to the input
+        let to_index = |rounding| region(rounding).to_index(&[20, 10], &[0.5, 1.0], &[0.0, 0.0]);
+        assert_eq!(
+            to_index(CropRounding::Nearest).unwrap(),
+            (vec![3, 0], vec![5, 10])
+        );
+        assert_eq!(
+            to_index(CropRounding::Outer).unwrap(),
+            (vec![2, 0], vec![6, 10])
+        );
+        assert_eq!(
+            to_index(CropRounding::Inner).unwrap(),
+            (vec![3, 0], vec![4, 10])
+        );
+        assert!(region(CropRounding::Nearest)
+            .to_index(&[20], &[1.0], &[0.0])
+            .is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use clap::Parser;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+use super::crop::Crop;
+
+/// The output attribute holding the offset of the crop in the input.
+pub const CROP_OFFSET_ATTRIBUTE: &str = "crop_offset";
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct CropNonzeroArguments {}
+
+impl FilterArguments for CropNonzeroArguments {
+    fn name(&self) -> String {
+        "crop_nonzero".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(CropNonzero::new(*common_args.chunk_limit())))
+    }
+}
+
+/// A bounding box as an inclusive start and exclusive end.
+type BoundingBox = (Vec<u64>, Vec<u64>);
+
+/// Returns the bounding box of the elements of `bytes` not equal to `fill_value`.
+///
+/// `bytes` has shape `chunk_shape`, but only elements within `bounded_shape` are considered.
+pub fn nonzero_bounding_box(
+    bytes: &[u8],
+    chunk_shape: &[u64],
+    bounded_shape: &[u64],
+    fill_value: &[u8],
+) -> Option<BoundingBox> {
+    let mut bounding_box: Option<BoundingBox> = None;
+    let mut indices = vec![0; chunk_shape.len()];
+    for element in bytes.chunks_exact(fill_value.len()) {
+        if element != fill_value && std::iter::zip(&indices, bounded_shape).all(|(i, len)| i < len)
+        {
+            if let Some((start, end)) = &mut bounding_box {
+                for (i, start, end) in itertools::izip!(&indices, start, end) {
+                    *start = std::cmp::min(*start, *i);
+                    *end = std::cmp::max(*end, i + 1);
+                }
+            } else {
+                bounding_box = Some((indices.clone(), indices.iter().map(|i| i + 1).collect()));
+            }
+        }
+
+        // Increment the indices in C order
+        for (i, len) in std::iter::zip(&mut indices, chunk_shape).rev() {
+            *i += 1;
+            if *i < *len {
+                break;
+            }
+            *i = 0;
+        }
+    }
+    bounding_box
+}
+
+fn merge_bounding_box(a: Option<BoundingBox>, b: Option<BoundingBox>) -> Option<BoundingBox> {
+    match (a, b) {
+        (Some((start_a, end_a)), Some((start_b, end_b))) => Some((
+            std::iter::zip(start_a, start_b)
+                .map(|(a, b)| std::cmp::min(a, b))
+                .collect(),
+            std::iter::zip(end_a, end_b)
+                .map(|(a, b)| std::cmp::max(a, b))
+                .collect(),
+        )),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Calculate the bounding box of the elements of `input` not equal to its fill value.
+///
+/// Returns [`None`] if every element is the fill value.
+pub fn calculate_nonzero_bounding_box(
+    input: &Array<FilterStorage>,
+    chunk_limit: usize,
+    progress: &Progress,
+) -> Result<Option<BoundingBox>, FilterError> {
+    let chunks = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+    let indices = chunks.indices();
+    let chunk_bounding_box = |chunk_indices: Vec<u64>| {
+        // Chunks that do not exist are entirely the fill value
+        let Some(bytes) = progress.read(|| input.retrieve_chunk_if_exists(&chunk_indices))? else {
+            progress.next();
+            return Ok(None);
+        };
+        let bytes = bytes.into_fixed().unwrap();
+        let chunk_subset = input.chunk_subset(&chunk_indices).unwrap();
+        let chunk_subset_bounded = input.chunk_subset_bounded(&chunk_indices).unwrap();
+        let bounding_box = progress.process(|| {
+            nonzero_bounding_box(
+                &bytes,
+                chunk_subset.shape(),
+                chunk_subset_bounded.shape(),
+                input.fill_value().as_ne_bytes(),
+            )
+        });
+        progress.next();
+
+        // Offset by the chunk origin
+        Ok::<_, FilterError>(bounding_box.map(|(start, end)| {
+            (
+                std::iter::zip(start, chunk_subset.start())
+                    .map(|(i, origin)| i + origin)
+                    .collect(),
+                std::iter::zip(end, chunk_subset.start())
+                    .map(|(i, origin)| i + origin)
+                    .collect(),
+            )
+        }))
+    };
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(
+        chunk_limit,
+        indices,
+        map,
+        chunk_bounding_box
+    )
+    .try_reduce(|| None, |a, b| Ok(merge_bounding_box(a, b)))
+}
+
+pub struct CropNonzero {
+    chunk_limit: Option<usize>,
+}
+
+impl CropNonzero {
+    pub fn new(chunk_limit: Option<usize>) -> Self {
+        Self { chunk_limit }
+    }
+}
+
+impl FilterTraits for CropNonzero {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The bounding box pass holds an input chunk, the crop pass an output chunk
+        std::cmp::max(
+            chunk_input.num_elements_usize() * chunk_input.fixed_element_size().unwrap(),
+            chunk_output.num_elements_usize()
+                * (chunk_input.fixed_element_size().unwrap()
+                    + chunk_output.fixed_element_size().unwrap()),
+        )
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let chunks_input = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        // First pass: find the bounding box of the non fill value elements
+        let bounding_box = {
+            let progress = Progress::new(chunks_input.num_elements_usize(), progress_callback);
+            calculate_nonzero_bounding_box(input, chunk_limit, &progress)?
+        };
+        let (offset, shape): (Vec<u64>, Vec<u64>) = if let Some((start, end)) = bounding_box {
+            let shape = std::iter::zip(&start, end).map(|(s, e)| e - s).collect();
+            (start, shape)
+        } else {
+            (
+                vec![0; input.dimensionality()],
+                vec![0; input.dimensionality()],
+            )
+        };
+
+        // Second pass: crop to the bounding box
+        output.set_shape(shape.clone());
+        output
+            .attributes_mut()
+            .insert(CROP_OFFSET_ATTRIBUTE.to_string(), offset.clone().into());
+        Crop::new(offset, shape, Some(chunk_limit)).apply(input, output, progress_callback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_nonzero_bounding_box() {
+        #[rustfmt::skip]
+        let bytes: Vec<u8> = vec![
+            0, 0, 0, 0,
+            0, 0, 1, 0,
+            0, 2, 0, 0,
+            0, 0, 0, 3,
+        ];
+        assert_eq!(
+            nonzero_bounding_box(&bytes, &[4, 4], &[4, 4], &[0]),
+            Some((vec![1, 1], vec![4, 4]))
+        );
+        // Elements beyond the bounds of the array are ignored
+        assert_eq!(
+            nonzero_bounding_box(&bytes, &[4, 4], &[3, 3], &[0]),
+            Some((vec![1, 1], vec![3, 3]))
+        );
+        assert_eq!(nonzero_bounding_box(&bytes, &[4, 4], &[1, 4], &[0]), None);
+
+        assert_eq!(
+            merge_bounding_box(
+                Some((vec![1, 1], vec![3, 3])),
+                Some((vec![0, 2], vec![2, 4]))
+            ),
+            Some((vec![0, 1], vec![3, 4]))
+        );
+        assert_eq!(
+            merge_bounding_box(None, Some((vec![0, 2], vec![2, 4]))),
+            Some((vec![0, 2], vec![2, 4]))
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::ops::AddAssign;
+
+use clap::Parser;
+use num_traits::{AsPrimitive, Zero};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct CumsumArguments {
+    /// The axis to compute the cumulative sum along.
+    pub axis: usize,
+}
+
+impl FilterArguments for CumsumArguments {
+    fn name(&self) -> String {
+        "cumsum".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Cumsum::new(self.axis, *common_args.chunk_limit())))
+    }
+}
+
+pub struct Cumsum {
+    axis: usize,
+    chunk_limit: Option<usize>,
+}
+
+impl Cumsum {
+    pub fn new(axis: usize, chunk_limit: Option<usize>) -> Self {
+        Self { axis, chunk_limit }
+    }
+
+    /// Compute the cumulative sum of a column of chunks along the axis, starting at the chunk at `chunk_start`.
+    ///
+    /// The chunks of a column are processed in order, carrying the sum of the previous chunks into the next.
+    pub fn apply_column<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_start: &[u64],
+        num_chunks: u64,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Zero + AddAssign + Copy + 'static,
+    {
+        let mut carry: Option<ndarray::ArrayD<TOut>> = None;
+        for i in 0..num_chunks {
+            let mut chunk_indices = chunk_start.to_vec();
+            chunk_indices[self.axis] = i;
+            let subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+
+            let input_array =
+                progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset))?;
+
+            let output_array = progress.process(|| {
+                let mut output_array = input_array.map(|v| v.as_());
+                let carry = carry.get_or_insert_with(|| {
+                    let mut carry_shape = output_array.shape().to_vec();
+                    carry_shape[self.axis] = 1;
+                    ndarray::ArrayD::<TOut>::zeros(carry_shape)
+                });
+                itertools::izip!(
+                    carry.lanes_mut(ndarray::Axis(self.axis)),
+                    output_array.lanes_mut(ndarray::Axis(self.axis))
+                )
+                .for_each(|(mut carry, mut lane)| {
+                    carry[0] = lane.iter_mut().fold(carry[0], |acc, element| {
+                        *element += acc;
+                        *element
+                    });
+                });
+                output_array
+            });
+
+            progress.write(|| {
+                output.store_array_subset_ndarray::<TOut, _>(subset.start(), output_array)
+            })?;
+            progress.next();
+        }
+
+        Ok(())
+    }
+}
+
+impl FilterTraits for Cumsum {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if self.axis >= dimensionality {
+            return Err(FilterError::InvalidParameters(format!(
+                "cumsum axis {} is out of bounds for an input with dimensionality {dimensionality}",
+                self.axis
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input and output of a chunk, the carry is a single slice of the output chunk
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let progress = Progress::new(
+            usize::try_from(chunk_grid_shape.iter().product::<u64>()).unwrap(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        // The scan is sequential along the axis and parallel over the columns of chunks along the other axes
+        let mut columns_shape = chunk_grid_shape.clone();
+        columns_shape[self.axis] = 1;
+        let columns = ArraySubset::new_with_shape(columns_shape);
+        let num_chunks = chunk_grid_shape[self.axis];
+
+        let indices = columns.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_start: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_column::<$type_in, $type_out>(input, output, &chunk_start, num_chunks, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::ProgressStats;
+
+    use super::*;
+    use std::{error::Error, sync::Arc};
+    use zarrs::{array::ArrayBuilder, storage::store::FilesystemStore};
+
+    #[test]
+    fn cumsum() -> Result<(), Box<dyn Error>> {
+        let path = tempfile::TempDir::new()?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
+        let array = ArrayBuilder::new(
+            vec![5, 3],
+            DataType::UInt8,
+            vec![2, 2].try_into()?,
+            0u8.into(),
+        )
+        .build(store, "/")?;
+        let array_subset = ArraySubset::new_with_shape(array.shape().to_vec());
+        let elements_in: ndarray::ArrayD<u8> =
+            ndarray::array![[1, 2, 3], [4, 5, 6], [7, 8, 9], [10, 11, 12], [13, 14, 15],]
+                .into_dyn();
+        array.store_array_subset_ndarray(array_subset.start(), elements_in)?;
+
+        let path = tempfile::TempDir::new()?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
+        let mut array_output = array
+            .builder()
+            .data_type(DataType::UInt16)
+            .fill_value(0u16.into())
+            .build(store, "/")?;
+        let progress_callback = |_stats: ProgressStats| {};
+        Cumsum::new(0, None).apply(
+            &array,
+            &mut array_output,
+            &ProgressCallback::new(&progress_callback),
+        )?;
+        let elements = array_output.retrieve_array_subset_ndarray::<u16>(&array_subset)?;
+
+        // The sums carry across the chunk boundaries along axis 0
+        let elements_ref: ndarray::ArrayD<u16> = ndarray::array![
+            [1, 2, 3],
+            [5, 7, 9],
+            [12, 15, 18],
+            [22, 26, 30],
+            [35, 40, 45],
+        ]
+        .into_dyn();
+        assert_eq!(elements, elements_ref);
+
+        Ok(())
+    }
+}
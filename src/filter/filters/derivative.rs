@@ -0,0 +1,342 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::gradient_magnitude::GradientMagnitude,
+        kernel::{apply_1d_difference_operator, apply_1d_kernel, apply_sobel_operator},
+        ArraySubsetOverlap, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DerivativeOperator {
+    /// The Sobel gradient magnitude, or the Sobel derivative along `axis` if set.
+    Sobel,
+    /// The Laplacian (sum of second differences along each axis).
+    Laplacian,
+    /// The central difference along `axis`.
+    Difference,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct DerivativeArguments {
+    /// The derivative operator.
+    #[arg(value_enum)]
+    pub operator: DerivativeOperator,
+    /// The axis of a per-axis derivative.
+    ///
+    /// Required for the difference operator. Write each axis to a separate output to get all components.
+    #[arg(long)]
+    #[serde(default)]
+    pub axis: Option<usize>,
+}
+
+impl FilterArguments for DerivativeArguments {
+    fn name(&self) -> String {
+        "derivative".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        match (self.operator, self.axis) {
+            (DerivativeOperator::Difference, None) => Err(FilterError::InvalidParameters(
+                "the difference operator requires an axis".to_string(),
+            )),
+            (DerivativeOperator::Laplacian, Some(_)) => Err(FilterError::InvalidParameters(
+                "the laplacian operator does not support an axis".to_string(),
+            )),
+            _ => Ok(Box::new(Derivative::new(
+                self.operator,
+                self.axis,
+                *common_args.chunk_limit(),
+            ))),
+        }
+    }
+}
+
+pub struct Derivative {
+    operator: DerivativeOperator,
+    axis: Option<usize>,
+    chunk_limit: Option<usize>,
+}
+
+impl Derivative {
+    pub fn new(
+        operator: DerivativeOperator,
+        axis: Option<usize>,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            operator,
+            axis,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + AsPrimitive<f32>,
+        TOut: Element + Copy + 'static,
+        f32: AsPrimitive<TOut>,
+    {
+        // Determine the input and output subset
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(
+            input.shape(),
+            &subset_output,
+            &vec![1; input.dimensionality()],
+        );
+
+        let input_array = progress
+            .read(|| input.retrieve_array_subset_ndarray::<TIn>(subset_overlap.subset_input()))?;
+
+        let output_array = progress.process(|| {
+            let input_array_f32 = input_array.map(|x| x.as_());
+            let output_array = self.apply_ndarray(&input_array_f32);
+            let output_array = subset_overlap.extract_subset(&output_array);
+            output_array.map(|x| x.as_())
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    pub fn apply_ndarray(&self, input: &ndarray::ArrayD<f32>) -> ndarray::ArrayD<f32> {
+        match (self.operator, self.axis) {
+            (DerivativeOperator::Sobel, None) => GradientMagnitude::new(None).apply_ndarray(input),
+            (DerivativeOperator::Sobel, Some(axis)) => apply_sobel_operator(axis, input),
+            (DerivativeOperator::Difference, Some(axis)) => {
+                let mut output = ndarray::ArrayD::<f32>::zeros(input.shape());
+                apply_1d_difference_operator(axis, input, &mut output);
+                output
+            }
+            (DerivativeOperator::Laplacian, _) => {
+                let kernel = ndarray::array![1.0, -2.0, 1.0];
+                let mut laplacian = ndarray::ArrayD::<f32>::zeros(input.shape());
+                let mut second_difference = ndarray::ArrayD::<f32>::zeros(input.shape());
+                for axis in 0..input.ndim() {
+                    apply_1d_kernel(axis, &kernel, input, &mut second_difference);
+                    laplacian += &second_difference;
+                }
+                laplacian
+            }
+            (DerivativeOperator::Difference, None) => unreachable!(),
+        }
+    }
+}
+
+impl FilterTraits for Derivative {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        if let Some(axis) = self.axis {
+            if axis >= chunk_input.dimensionality() {
+                return Err(FilterError::InvalidParameters(format!(
+                    "derivative axis {axis} is out of bounds for an input with dimensionality {}",
+                    chunk_input.dimensionality()
+                )));
+            }
+        }
+        match chunk_input.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = usize::try_from(
+            chunk_input
+                .shape()
+                .iter()
+                .map(|s| s.get() + 2)
+                .product::<u64>(),
+        )
+        .unwrap();
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_input_elements
+            * (chunk_input.data_type().fixed_size().unwrap() + core::mem::size_of::<f32>() * 4)
+            + num_output_elements
+                * (core::mem::size_of::<f32>() + chunk_output.data_type().fixed_size().unwrap())
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        match input.data_type() {
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16 => None,
+            _ => Some((DataType::Float32, FillValue::from(0.0f32))),
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                            match input.data_type() {
+                                $(
+                                    DataType::$data_type_in => {
+                                        apply_output!($type_in, [
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_input!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn derivative() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &(0..16).map(|i| i * 17).collect::<Vec<u8>>(),
+        )?;
+
+        let derivative = |args: &str| -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+            retrieve_test_elements(&run_test_filter("derivative", &input, &output, args)?)
+        };
+        let edges = |step: f32| [step, 0.0, 0.0, -step];
+
+        // Integer inputs are differentiated as float, and neighbours beyond the border are clamped to the edge
+        assert_eq!(
+            derivative("operator: difference\naxis: 1")?,
+            [8.5, 17.0, 17.0, 8.5].repeat(4)
+        );
+        assert_eq!(
+            derivative("operator: difference\naxis: 0")?,
+            [34.0, 68.0, 68.0, 34.0]
+                .iter()
+                .flat_map(|&d| [d; 4])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            derivative("operator: laplacian")?,
+            edges(68.0)
+                .iter()
+                .flat_map(|a| edges(17.0).map(|b| a + b))
+                .collect::<Vec<_>>()
+        );
+
+        assert!(derivative("operator: difference").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use clap::Parser;
+use ndarray::{ArrayD, Axis};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, ElementOwned, FillValue},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_ndarray_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistanceMetric {
+    /// The Euclidean distance.
+    #[default]
+    Euclidean,
+    /// The sum of the absolute differences along each axis (L1).
+    Cityblock,
+    /// The maximum of the absolute differences along each axis (L∞).
+    Chessboard,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct DistanceTransformArguments {
+    /// The distance metric.
+    #[arg(long, value_enum, default_value_t = DistanceMetric::Euclidean)]
+    #[serde(default)]
+    pub metric: DistanceMetric,
+}
+
+impl FilterArguments for DistanceTransformArguments {
+    fn name(&self) -> String {
+        "distance_transform".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(DistanceTransform::new(
+            self.metric,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// The squared Euclidean distance transform of a sampled function `f` (Felzenszwalb and Huttenlocher, 2012).
+///
+/// `v` and `z` are buffers for the lower envelope of parabolas.
+fn squared_euclidean_1d(f: &mut [f64], v: &mut Vec<usize>, z: &mut Vec<f64>) {
+    v.clear();
+    z.clear();
+    for q in (0..f.len()).filter(|&q| f[q].is_finite()) {
+        let qf = q as f64;
+        let mut s = f64::NEG_INFINITY;
+        while let Some(&p) = v.last() {
+            let pf = p as f64;
+            s = ((f[q] + qf * qf) - (f[p] + pf * pf)) / (2.0 * qf - 2.0 * pf);
+            if s <= *z.last().unwrap() {
+                v.pop();
+                z.pop();
+            } else {
+                break;
+            }
+        }
+        if v.is_empty() {
+            s = f64::NEG_INFINITY;
+        }
+        v.push(q);
+        z.push(s);
+    }
+    if v.is_empty() {
+        return;
+    }
+    let envelope = v.iter().map(|&p| f[p]).collect::<Vec<_>>();
+    let mut k = 0;
+    for (q, f_q) in f.iter_mut().enumerate() {
+        let qf = q as f64;
+        while k + 1 < v.len() && z[k + 1] < qf {
+            k += 1;
+        }
+        let d = qf - v[k] as f64;
+        *f_q = d * d + envelope[k];
+    }
+}
+
+/// The cityblock distance transform of a sampled function `f`.
+fn cityblock_1d(f: &mut [f64]) {
+    for q in 1..f.len() {
+        f[q] = f[q].min(f[q - 1] + 1.0);
+    }
+    for q in (0..f.len().saturating_sub(1)).rev() {
+        f[q] = f[q].min(f[q + 1] + 1.0);
+    }
+}
+
+/// The chessboard distance transform of a sampled function `f`.
+fn chessboard_1d(f: &[f64], d: &mut [f64]) {
+    for (q, d_q) in d.iter_mut().enumerate() {
+        let mut best = f[q];
+        let mut k = 1;
+        while (k as f64) < best && (k <= q || q + k < f.len()) {
+            if k <= q {
+                best = best.min(f[q - k].max(k as f64));
+            }
+            if q + k < f.len() {
+                best = best.min(f[q + k].max(k as f64));
+            }
+            k += 1;
+        }
+        *d_q = best;
+    }
+}
+
+pub struct DistanceTransform {
+    metric: DistanceMetric,
+    chunk_limit: Option<usize>,
+}
+
+impl DistanceTransform {
+    pub fn new(metric: DistanceMetric, chunk_limit: Option<usize>) -> Self {
+        Self {
+            metric,
+            chunk_limit,
+        }
+    }
+
+    /// Initialise the transform from the foreground (non-zero, non-NaN) elements of an input.
+    fn initialise(input: &ArrayD<f64>) -> ArrayD<f64> {
+        input.mapv(|x| {
+            if x != 0.0 && !x.is_nan() {
+                f64::INFINITY
+            } else {
+                0.0
+            }
+        })
+    }
+
+    /// Apply the transform along an `axis` of `array`.
+    ///
+    /// Euclidean distances are squared until [`finalise`](Self::finalise).
+    fn transform_axis(&self, array: &mut ArrayD<f64>, axis: usize) {
+        ndarray::Zip::from(array.lanes_mut(Axis(axis))).par_for_each(|mut lane| {
+            let mut f = lane.to_vec();
+            match self.metric {
+                DistanceMetric::Euclidean => {
+                    squared_euclidean_1d(&mut f, &mut Vec::new(), &mut Vec::new());
+                }
+                DistanceMetric::Cityblock => cityblock_1d(&mut f),
+                DistanceMetric::Chessboard => {
+                    let g = f.clone();
+                    chessboard_1d(&g, &mut f);
+                }
+            }
+            lane.assign(&ndarray::ArrayView1::from(&f));
+        });
+    }
+
+    fn finalise(&self, array: &mut ArrayD<f64>) {
+        if self.metric == DistanceMetric::Euclidean {
+            array.mapv_inplace(f64::sqrt);
+        }
+    }
+
+    /// Returns the distance from each foreground (non-zero) element of `input` to the nearest background (zero) element.
+    ///
+    /// The distance is infinite if there are no background elements.
+    pub fn apply_ndarray(&self, input: &ArrayD<f64>) -> ArrayD<f64> {
+        let mut array = Self::initialise(input);
+        for axis in 0..array.ndim() {
+            self.transform_axis(&mut array, axis);
+        }
+        self.finalise(&mut array);
+        array
+    }
+
+    /// Apply the transform along `axis` to a `subset` spanning the full extent of that axis.
+    ///
+    /// The input is read for the first axis, and the output holds the transform of preceding axes for subsequent axes.
+    pub fn apply_subset<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        subset: &ArraySubset,
+        axis: usize,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: ElementOwned + Copy + 'static + AsPrimitive<f64>,
+        f64: AsPrimitive<TOut>,
+    {
+        let mut array = if axis == 0 {
+            let input_array = progress.read(|| retrieve_array_subset_ndarray_f64(input, subset))?;
+            progress.process(|| Self::initialise(&input_array))
+        } else {
+            let output_array =
+                progress.read(|| output.retrieve_array_subset_ndarray::<TOut>(subset))?;
+            progress.process(|| output_array.mapv(|x| x.as_()))
+        };
+
+        let output_array = progress.process(|| {
+            self.transform_axis(&mut array, axis);
+            if axis + 1 == output.dimensionality() {
+                self.finalise(&mut array);
+            }
+            array.mapv(|x| x.as_())
+        });
+        drop(array);
+
+        progress
+            .write(|| output.store_array_subset_ndarray::<TOut, _>(subset.start(), output_array))?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for DistanceTransform {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Float32 | DataType::Float64 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input
+                .data_type()
+                .fixed_size()
+                .unwrap()
+                .max(chunk_output.data_type().fixed_size().unwrap())
+                + core::mem::size_of::<f64>() * 2
+                + chunk_output.data_type().fixed_size().unwrap())
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::Float32, FillValue::from(0.0f32)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        // Each axis is transformed in subsets spanning the full extent of the axis (a column of chunks)
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let columns_per_axis = (0..output.dimensionality())
+            .map(|axis| {
+                let mut columns_shape = chunk_grid_shape.clone();
+                columns_shape[axis] = columns_shape[axis].min(1);
+                ArraySubset::new_with_shape(columns_shape)
+            })
+            .collect::<Vec<_>>();
+        let progress = Progress::new(
+            columns_per_axis
+                .iter()
+                .map(ArraySubset::num_elements_usize)
+                .sum(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            let chunks_per_column = usize::try_from(*chunk_grid_shape.iter().max().unwrap_or(&1))
+                .unwrap()
+                .max(1);
+            calculate_chunk_limit(
+                self.memory_per_chunk(
+                    &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                    &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                ) * chunks_per_column,
+            )?
+        };
+
+        for (axis, columns) in columns_per_axis.iter().enumerate() {
+            let indices = columns.indices();
+            rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                chunk_limit,
+                indices,
+                try_for_each,
+                |chunk_indices: Vec<u64>| {
+                    let chunk_subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+                    let mut start = chunk_subset.start().to_vec();
+                    let mut shape = chunk_subset.shape().to_vec();
+                    start[axis] = 0;
+                    shape[axis] = output.shape()[axis];
+                    let subset = ArraySubset::new_with_start_shape(start, shape).unwrap();
+                    match output.data_type() {
+                        DataType::Float32 => {
+                            self.apply_subset::<f32>(input, output, &subset, axis, &progress)
+                        }
+                        DataType::Float64 => {
+                            self.apply_subset::<f64>(input, output, &subset, axis, &progress)
+                        }
+                        _ => panic!(),
+                    }
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_transform_metrics() {
+        let mut input = ArrayD::<f64>::ones(vec![5, 6]);
+        input[[1, 1]] = 0.0;
+        let distance = |metric| DistanceTransform::new(metric, None).apply_ndarray(&input);
+
+        let euclidean = distance(DistanceMetric::Euclidean);
+        assert_eq!(euclidean[[1, 1]], 0.0);
+        assert_eq!(euclidean[[1, 4]], 3.0);
+        assert_eq!(euclidean[[4, 5]], 5.0);
+        assert_eq!(euclidean[[3, 3]], 8.0f64.sqrt());
+        assert_eq!(distance(DistanceMetric::Cityblock)[[4, 5]], 7.0);
+        assert_eq!(distance(DistanceMetric::Chessboard)[[4, 5]], 4.0);
+
+        let euclidean = DistanceTransform::new(DistanceMetric::Euclidean, None)
+            .apply_ndarray(&input.mapv(|_| 1.0));
+        assert!(euclidean.iter().all(|d| d.is_infinite()));
+    }
+}
@@ -1,19 +1,20 @@
 use std::collections::HashMap;
 
 use clap::Parser;
+use ndarray::ArrayD;
 use num_traits::{AsPrimitive, FromPrimitive};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use zarrs::{
-    array::{data_type::UnsupportedDataTypeError, Array, DataType},
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, ElementOwned},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
         calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        FilterArguments, FilterCommonArguments,
+        filters::gaussian::create_sampled_gaussian_kernel, kernel::apply_1d_kernel,
+        ArraySubsetOverlap, FilterArguments, FilterCommonArguments, FilterStorage,
     },
     progress::{Progress, ProgressCallback},
 };
@@ -25,8 +26,19 @@ pub struct DownsampleArguments {
     pub stride: Vec<u64>,
     #[serde(default)]
     /// Perform majority filtering (mode downsampling).
+    ///
+    /// Each output element is the most frequent input value in its window, or the smallest of the most frequent values if tied.
+    /// Use this for label arrays, where the mean would produce invalid labels.
+    /// Floating point inputs are always mean downsampled.
     #[arg(long, default_value_t = false)]
     pub discrete: bool,
+    #[serde(default)]
+    /// Apply a Gaussian prefilter matched to the stride of each axis before mean downsampling.
+    ///
+    /// The prefilter sigma of an axis is 2 x stride / 6, and axes with a stride of 1 are not filtered.
+    /// This suppresses aliasing artifacts of continuous intensity data, such as when building image pyramids.
+    #[arg(long, default_value_t = false, conflicts_with = "discrete")]
+    pub antialias: bool,
 }
 
 impl FilterArguments for DownsampleArguments {
@@ -41,6 +53,7 @@ impl FilterArguments for DownsampleArguments {
         Ok(Box::new(Downsample::new(
             self.stride.clone(),
             self.discrete,
+            self.antialias,
             *common_args.chunk_limit(),
         )))
     }
@@ -49,18 +62,93 @@ impl FilterArguments for DownsampleArguments {
 pub struct Downsample {
     stride: Vec<u64>,
     discrete: bool,
+    antialias_kernel: Option<Vec<ndarray::Array1<f32>>>,
     chunk_limit: Option<usize>,
 }
 
 impl Downsample {
-    pub fn new(stride: Vec<u64>, discrete: bool, chunk_limit: Option<usize>) -> Self {
+    pub fn new(
+        stride: Vec<u64>,
+        discrete: bool,
+        antialias: bool,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        let antialias_kernel = antialias.then(|| {
+            stride
+                .iter()
+                .map(|&stride| {
+                    let sigma = if stride > 1 {
+                        2.0 * stride as f32 / 6.0
+                    } else {
+                        0.0
+                    };
+                    let kernel_half_size = (sigma * 4.0).ceil() as u64;
+                    let kernel = create_sampled_gaussian_kernel(sigma, kernel_half_size);
+                    let sum = kernel.sum();
+                    kernel / sum
+                })
+                .collect()
+        });
         Self {
             stride,
             discrete,
+            antialias_kernel,
             chunk_limit,
         }
     }
 
+    /// The half size of the anti-aliasing prefilter kernel for each axis, zero if not anti-aliasing.
+    pub fn antialias_kernel_half_size(&self) -> Vec<u64> {
+        if let Some(antialias_kernel) = &self.antialias_kernel {
+            antialias_kernel
+                .iter()
+                .map(|kernel| (kernel.len() / 2) as u64)
+                .collect()
+        } else {
+            vec![0; self.stride.len()]
+        }
+    }
+
+    /// Apply the anti-aliasing prefilter to `input`, if enabled.
+    pub fn apply_ndarray_antialias(&self, mut input: ArrayD<f32>) -> ArrayD<f32> {
+        if let Some(antialias_kernel) = &self.antialias_kernel {
+            let mut filtered = ArrayD::<f32>::zeros(input.shape());
+            for (dim, kernel) in antialias_kernel.iter().enumerate() {
+                if kernel.len() > 1 {
+                    apply_1d_kernel(dim, kernel, &input, &mut filtered);
+                    std::mem::swap(&mut input, &mut filtered);
+                }
+            }
+        }
+        input
+    }
+
+    /// Retrieve `input_subset` of `input` with the anti-aliasing prefilter applied.
+    ///
+    /// The prefilter is applied to the input subset expanded by the kernel half size, so the result is independent of the chunking.
+    pub fn retrieve_antialiased<T>(
+        &self,
+        input: &Array<FilterStorage>,
+        input_subset: &ArraySubset,
+        progress: &Progress,
+    ) -> Result<ArrayD<f32>, FilterError>
+    where
+        T: ElementOwned + Send + Sync + AsPrimitive<f32>,
+    {
+        let subset_overlap = ArraySubsetOverlap::new(
+            input.shape(),
+            input_subset,
+            &self.antialias_kernel_half_size(),
+        );
+        let input_array = progress
+            .read(|| input.retrieve_array_subset_ndarray::<T>(subset_overlap.subset_input()))?;
+        Ok(progress.process(|| {
+            let input_array = input_array.mapv(|x| x.as_());
+            let filtered = self.apply_ndarray_antialias(input_array);
+            subset_overlap.extract_subset(&filtered)
+        }))
+    }
+
     pub fn input_subset(&self, input_shape: &[u64], output_subset: &ArraySubset) -> ArraySubset {
         let input_start = std::iter::zip(output_subset.start(), &self.stride)
             .map(|(start, stride)| start * stride);
@@ -102,7 +190,7 @@ impl Downsample {
         progress: &Progress,
     ) -> ndarray::ArrayD<TOut>
     where
-        TIn: Copy + Send + Sync + PartialEq + Eq + core::hash::Hash + AsPrimitive<TOut>,
+        TIn: Copy + Send + Sync + Ord + core::hash::Hash + AsPrimitive<TOut>,
         TOut: Copy + Send + Sync + 'static,
     {
         progress.process(|| {
@@ -114,7 +202,12 @@ impl Downsample {
                 for element in &chunk {
                     *map.entry(*element).or_insert(0) += 1;
                 }
-                map.iter().max_by(|a, b| a.1.cmp(b.1)).unwrap().0.as_()
+                // Break ties by the smallest value, so the output is deterministic
+                map.iter()
+                    .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                    .unwrap()
+                    .0
+                    .as_()
             })
         })
     }
@@ -149,17 +242,35 @@ impl FilterTraits for Downsample {
 
     fn memory_per_chunk(
         &self,
-        _chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_input: &zarrs::array::ChunkRepresentation,
         chunk_output: &zarrs::array::ChunkRepresentation,
     ) -> usize {
-        debug_assert_eq!(_chunk_input.data_type(), chunk_output.data_type());
-        let input = chunk_output.fixed_element_size().unwrap()
-            * usize::try_from(self.stride.iter().product::<u64>()).unwrap();
-        let output = chunk_output.fixed_element_size().unwrap();
-        input + output
+        if self.antialias_kernel.is_some() {
+            let num_input_elements = usize::try_from(
+                itertools::izip!(
+                    chunk_output.shape(),
+                    &self.stride,
+                    self.antialias_kernel_half_size()
+                )
+                .map(|(shape, stride, kernel_half_size)| {
+                    shape.get() * stride + kernel_half_size * 2
+                })
+                .product::<u64>(),
+            )
+            .unwrap();
+            let num_output_elements = chunk_output.num_elements_usize();
+            num_input_elements
+                * (chunk_input.fixed_element_size().unwrap() + core::mem::size_of::<f32>() * 2)
+                + num_output_elements * chunk_output.fixed_element_size().unwrap()
+        } else {
+            let input = chunk_input.fixed_element_size().unwrap()
+                * usize::try_from(self.stride.iter().product::<u64>()).unwrap();
+            let output = chunk_output.fixed_element_size().unwrap();
+            input + output
+        }
     }
 
-    fn output_shape(&self, input: &Array<FilesystemStore>) -> Option<Vec<u64>> {
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<Vec<u64>> {
         Some(
             std::iter::zip(input.shape(), &self.stride)
                 .map(|(shape, stride)| std::cmp::max(shape / stride, 1))
@@ -169,8 +280,8 @@ impl FilterTraits for Downsample {
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), self.output_shape(input).unwrap());
@@ -199,12 +310,22 @@ impl FilterTraits for Downsample {
 
                 macro_rules! downsample {
                     ( $t_in:ty, $t_out:ty ) => {{
-                        let input_array = progress
-                            .read(|| input.retrieve_array_subset_ndarray::<$t_in>(&input_subset))?;
-                        let output_array = if self.discrete {
-                            self.apply_ndarray_discrete(input_array, &progress)
-                        } else {
+                        let output_array = if self.antialias_kernel.is_some() {
+                            let input_array = self.retrieve_antialiased::<$t_in>(
+                                input,
+                                &input_subset,
+                                &progress,
+                            )?;
                             self.apply_ndarray_continuous(input_array, &progress)
+                        } else {
+                            let input_array = progress.read(|| {
+                                input.retrieve_array_subset_ndarray::<$t_in>(&input_subset)
+                            })?;
+                            if self.discrete {
+                                self.apply_ndarray_discrete(input_array, &progress)
+                            } else {
+                                self.apply_ndarray_continuous(input_array, &progress)
+                            }
                         };
                         progress.write(|| {
                             output.store_array_subset_ndarray::<$t_out, _>(
@@ -216,9 +337,19 @@ impl FilterTraits for Downsample {
                 }
                 macro_rules! downsample_continuous_only {
                     ( $t_in:ty, $t_out:ty ) => {{
-                        let input_array = progress
-                            .read(|| input.retrieve_array_subset_ndarray::<$t_in>(&input_subset))?;
-                        let output_array = self.apply_ndarray_continuous(input_array, &progress);
+                        let output_array = if self.antialias_kernel.is_some() {
+                            let input_array = self.retrieve_antialiased::<$t_in>(
+                                input,
+                                &input_subset,
+                                &progress,
+                            )?;
+                            self.apply_ndarray_continuous(input_array, &progress)
+                        } else {
+                            let input_array = progress.read(|| {
+                                input.retrieve_array_subset_ndarray::<$t_in>(&input_subset)
+                            })?;
+                            self.apply_ndarray_continuous(input_array, &progress)
+                        };
                         progress.write(|| {
                             output.store_array_subset_ndarray::<$t_out, _>(
                                 output_subset.start(),
@@ -285,3 +416,54 @@ impl FilterTraits for Downsample {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::ProgressCallback;
+
+    #[test]
+    fn downsample_discrete_ties() {
+        let callback = |_| {};
+        let progress_callback = ProgressCallback::new(&callback);
+        let progress = Progress::new(1, &progress_callback);
+
+        let downsample = Downsample::new(vec![2, 2], true, false, None);
+        let input =
+            ndarray::array![[7u32, 3, 5, 5], [3, 7, 5, 9], [1, 2, 4, 4], [3, 4, 8, 8]].into_dyn();
+        for _ in 0..8 {
+            let output: ndarray::ArrayD<u32> =
+                downsample.apply_ndarray_discrete(input.clone(), &progress);
+            assert_eq!(output, ndarray::array![[3, 5], [1, 4]].into_dyn());
+        }
+    }
+
+    #[test]
+    fn downsample_antialias() {
+        let callback = |_| {};
+        let progress_callback = ProgressCallback::new(&callback);
+        let progress = Progress::new(1, &progress_callback);
+
+        let downsample = Downsample::new(vec![2, 1], false, true, None);
+        assert_eq!(downsample.antialias_kernel_half_size(), vec![3, 0]);
+
+        // A constant input is preserved
+        let input = ArrayD::<f32>::from_elem(vec![8, 2], 5.0);
+        let output: ArrayD<f32> = downsample
+            .apply_ndarray_continuous(downsample.apply_ndarray_antialias(input), &progress);
+        approx::assert_abs_diff_eq!(
+            output,
+            ArrayD::<f32>::from_elem(vec![4, 2], 5.0),
+            epsilon = 1e-5
+        );
+
+        // An alternating input is attenuated towards its mean
+        let input =
+            ndarray::Array::from_shape_fn((8, 1), |(i, _)| (i / 2 % 2) as f32 * 2.0).into_dyn();
+        let plain: ArrayD<f32> = downsample.apply_ndarray_continuous(input.clone(), &progress);
+        let antialiased: ArrayD<f32> = downsample
+            .apply_ndarray_continuous(downsample.apply_ndarray_antialias(input), &progress);
+        let deviation = |array: &ArrayD<f32>| array.iter().map(|v| (v - 1.0).abs()).sum::<f32>();
+        assert!(deviation(&antialiased) < deviation(&plain));
+    }
+}
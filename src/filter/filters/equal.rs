@@ -1,14 +1,15 @@
 use clap::Parser;
-use num_traits::AsPrimitive;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
 use serde::{Deserialize, Serialize};
 use zarrs::{
     array::{
-        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
-        FillValueMetadata,
+        data_type::UnsupportedDataTypeError, Array, ArrayError, DataType, Element, ElementOwned,
+        FillValue, FillValueMetadata,
     },
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
@@ -17,48 +18,72 @@ use crate::{
 };
 
 use crate::filter::{
-    calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits, FilterArguments,
-    FilterCommonArguments,
+    calculate_chunk_limit, element_size, filter_error::FilterError, filter_traits::FilterTraits,
+    FilterArguments, FilterCommonArguments, FilterStorage,
 };
 
-#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
-pub struct EqualArguments {
-    /// The value to compare against.
-    ///
-    /// The value must be compatible with the data type.
-    ///
-    /// Examples:
-    ///   int/uint: 0
-    ///   float: 0.0 "NaN" "Infinity" "-Infinity"
-    ///   r*: "[0, 255]"
-    #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
-    pub value: FillValueMetadata,
-}
+macro_rules! equal_arguments {
+    ( $name:ident, $filter_name:literal, $not_equal:literal, $doc:literal ) => {
+        #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+        pub struct $name {
+            #[doc = $doc]
+            ///
+            /// The value must be compatible with the data type.
+            ///
+            /// Examples:
+            ///   int/uint: 0
+            ///   float: 0.0 "NaN" "Infinity" "-Infinity"
+            ///   r*: "[0, 255]"
+            ///   string: '"cat"'
+            #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
+            pub value: FillValueMetadata,
+        }
 
-impl FilterArguments for EqualArguments {
-    fn name(&self) -> String {
-        "equal".to_string()
-    }
+        impl FilterArguments for $name {
+            fn name(&self) -> String {
+                $filter_name.to_string()
+            }
 
-    fn init(
-        &self,
-        common_args: &FilterCommonArguments,
-    ) -> Result<Box<dyn FilterTraits>, FilterError> {
-        Ok(Box::new(Equal::new(
-            self.value.clone(),
-            *common_args.chunk_limit(),
-        )))
-    }
+            fn init(
+                &self,
+                common_args: &FilterCommonArguments,
+            ) -> Result<Box<dyn FilterTraits>, FilterError> {
+                Ok(Box::new(Equal::new(
+                    self.value.clone(),
+                    $not_equal,
+                    *common_args.chunk_limit(),
+                )))
+            }
+        }
+    };
 }
 
+equal_arguments!(
+    EqualArguments,
+    "equal",
+    false,
+    "The value to compare against."
+);
+equal_arguments!(
+    NotEqualArguments,
+    "notequal",
+    true,
+    "The value that the input must not be equal to."
+);
+
 pub struct Equal {
     value: FillValueMetadata,
+    not_equal: bool,
     chunk_limit: Option<usize>,
 }
 
 impl Equal {
-    pub fn new(value: FillValueMetadata, chunk_limit: Option<usize>) -> Self {
-        Self { value, chunk_limit }
+    pub fn new(value: FillValueMetadata, not_equal: bool, chunk_limit: Option<usize>) -> Self {
+        Self {
+            value,
+            not_equal,
+            chunk_limit,
+        }
     }
 
     pub fn apply_elements<TIn, TOut>(
@@ -67,16 +92,26 @@ impl Equal {
         equal: &TIn,
     ) -> Result<Vec<TOut>, FilterError>
     where
-        TIn: ElementOwned + Copy + Send + Sync + PartialEq,
-        TOut: Element + Send + Sync + Copy + 'static,
-        bool: AsPrimitive<TOut>,
+        TIn: ElementOwned + Send + Sync + PartialEq,
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
     {
         let output_elements = input_elements
             .into_par_iter()
-            .map(|value| (value == equal).as_())
+            .map(|value| TOut::from((value == equal) != self.not_equal))
             .collect::<Vec<TOut>>();
         Ok(output_elements)
     }
+
+    /// Compare elements of a fixed size data type as opaque byte blobs (e.g. raw bits).
+    pub fn apply_elements_bytes<TOut>(&self, input_bytes: &[u8], equal: &[u8]) -> Vec<TOut>
+    where
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        input_bytes
+            .par_chunks_exact(equal.len())
+            .map(|value| TOut::from((value == equal) != self.not_equal))
+            .collect::<Vec<TOut>>()
+    }
 }
 
 impl FilterTraits for Equal {
@@ -98,7 +133,9 @@ impl FilterTraits for Equal {
             | DataType::Float16
             | DataType::Float32
             | DataType::Float64
-            | DataType::BFloat16 => {}
+            | DataType::BFloat16
+            | DataType::String
+            | DataType::RawBits(_) => {}
             _ => Err(UnsupportedDataTypeError::from(
                 chunk_input.data_type().to_string(),
             ))?,
@@ -117,17 +154,17 @@ impl FilterTraits for Equal {
         chunk_input: &zarrs::array::ChunkRepresentation,
         chunk_output: &zarrs::array::ChunkRepresentation,
     ) -> usize {
-        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+        element_size(chunk_input) + element_size(chunk_output)
     }
 
-    fn output_data_type(&self, _input: &Array<FilesystemStore>) -> Option<(DataType, FillValue)> {
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
         Some((DataType::Bool, FillValue::from(false)))
     }
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
@@ -159,6 +196,43 @@ impl FilterTraits for Equal {
                 macro_rules! apply_input {
                     ( $t_out:ty, [$( ( $data_type_in:ident, $t_in:ty ) ),* ]) => {
                         match input.data_type() {
+                            DataType::RawBits(_) => {
+                                let input_bytes = progress
+                                    .read(|| input.retrieve_array_subset(&input_output_subset))?
+                                    .into_fixed()
+                                    .map_err(ArrayError::from)?;
+
+                                let output_elements = progress.process(|| {
+                                    self.apply_elements_bytes::<$t_out>(&input_bytes, value.as_ne_bytes())
+                                });
+                                drop(input_bytes);
+
+                                progress.write(|| {
+                                    output.store_array_subset_elements::<$t_out>(&input_output_subset, &output_elements)
+                                })?;
+
+                                progress.next();
+                                Ok(())
+                            }
+                            DataType::String => {
+                                let input_elements =
+                                    progress.read(|| input.retrieve_array_subset_elements::<String>(&input_output_subset))?;
+
+                                let output_elements =
+                                    progress.process(|| {
+                                        let value = String::from_utf8(value.as_ne_bytes().to_vec())
+                                            .map_err(|err| FilterError::InvalidParameters(err.to_string()))?;
+                                        self.apply_elements::<String, $t_out>(&input_elements, &value)
+                                    })?;
+                                drop(input_elements);
+
+                                progress.write(|| {
+                                    output.store_array_subset_elements::<$t_out>(&input_output_subset, &output_elements)
+                                })?;
+
+                                progress.next();
+                                Ok(())
+                            }
                             $(DataType::$data_type_in => {
                                 let input_elements =
                                     progress.read(|| input.retrieve_array_subset_elements::<$t_in>(&input_output_subset))?;
@@ -207,10 +281,7 @@ impl FilterTraits for Equal {
                             }
                         };
                     }
-                apply_output!([
-                    (Bool, u8), // bool != bytemuck::Pod, but apply_chunk only stores 0 or 1, so can store as u8
-                    (UInt8, u8)
-                ])
+                apply_output!([(Bool, bool), (UInt8, u8)])
             }
         )
     }
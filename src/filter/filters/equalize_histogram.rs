@@ -0,0 +1,642 @@
+use clap::Parser;
+use ndarray::Dimension;
+use num_traits::AsPrimitive;
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    },
+    slice::ParallelSlice,
+};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::{
+            normalize::{
+                calculate_min_max, calculate_min_max_per_slice, check_per_slice_axis,
+                slice_run_index, slice_run_length,
+            },
+            rescale_intensity::{
+                calculate_histogram, calculate_histogram_per_slice, data_type_range, histogram_bin,
+            },
+        },
+        retrieve_array_subset_elements_f64, retrieve_array_subset_ndarray_f64, ArraySubsetOverlap,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct EqualizeHistogramArguments {
+    /// The number of histogram bins.
+    #[arg(long, default_value_t = 256)]
+    #[serde(default = "default_bins")]
+    pub bins: usize,
+    /// Tile shape per axis for adaptive equalization, comma delimited.
+    ///
+    /// Each tile is equalized independently, and outputs are interpolated between neighbouring tiles.
+    /// A tile size of 1 equalizes each index along an axis independently (e.g. 2D slices of a 3D array).
+    /// A tile size of 0 spans the entire axis.
+    /// The input is equalized globally if unset.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub tile_shape: Option<Vec<u64>>,
+    /// Limit contrast by clipping histogram bins to this fraction of the tile (or input) elements, e.g. 0.01.
+    ///
+    /// Clipped counts are redistributed uniformly over all bins.
+    #[arg(long)]
+    #[serde(default)]
+    pub clip_limit: Option<f64>,
+    /// Equalize each index along this axis independently (e.g. per channel or timepoint).
+    ///
+    /// For adaptive equalization, use a tile size of 1 along the axis instead.
+    #[arg(long, conflicts_with = "tile_shape")]
+    #[serde(default)]
+    pub per_slice_axis: Option<usize>,
+}
+
+fn default_bins() -> usize {
+    256
+}
+
+impl FilterArguments for EqualizeHistogramArguments {
+    fn name(&self) -> String {
+        "equalize_histogram".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if self.bins == 0 {
+            return Err(FilterError::InvalidParameters(
+                "equalize_histogram bins must be greater than zero".to_string(),
+            ));
+        }
+        if let Some(clip_limit) = self.clip_limit {
+            if !(clip_limit > 0.0 && clip_limit <= 1.0) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "equalize_histogram clip limit {clip_limit} must be in the range (0, 1]"
+                )));
+            }
+        }
+        if self.tile_shape.is_some() && self.per_slice_axis.is_some() {
+            return Err(FilterError::InvalidParameters(
+                "equalize_histogram tile shape and per slice axis cannot be combined, use a tile size of 1 along the axis instead".to_string(),
+            ));
+        }
+        Ok(Box::new(EqualizeHistogram::new(
+            self.bins,
+            self.tile_shape.clone(),
+            self.clip_limit,
+            self.per_slice_axis,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the equalization mapping of a histogram, which is its normalised cumulative distribution.
+///
+/// If `clip_limit` is set, bins are first clipped to `clip_limit` times the total count (at least 1) and the excess is redistributed uniformly.
+pub fn equalization_mapping(histogram: &[u64], clip_limit: Option<f64>) -> Vec<f64> {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return vec![0.0; histogram.len()];
+    }
+    let mut histogram = histogram
+        .iter()
+        .map(|&count| count as f64)
+        .collect::<Vec<_>>();
+    if let Some(clip_limit) = clip_limit {
+        let limit = (clip_limit * total as f64).max(1.0);
+        let excess: f64 = histogram
+            .iter_mut()
+            .map(|count| {
+                let excess = (*count - limit).max(0.0);
+                *count -= excess;
+                excess
+            })
+            .sum();
+        let redistributed = excess / histogram.len() as f64;
+        histogram
+            .iter_mut()
+            .for_each(|count| *count += redistributed);
+    }
+    let mut cumulative = 0.0;
+    histogram
+        .iter()
+        .map(|count| {
+            cumulative += count;
+            cumulative / total as f64
+        })
+        .collect()
+}
+
+/// The neighbouring tiles of an element along an axis and the interpolation weight of the upper tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TileCoordinate {
+    lower: u64,
+    upper: u64,
+    weight: f64,
+}
+
+impl TileCoordinate {
+    fn new(index: u64, tile_size: u64, num_tiles: u64) -> Self {
+        let position = ((index as f64 + 0.5) / tile_size as f64 - 0.5)
+            .clamp(0.0, num_tiles.saturating_sub(1) as f64);
+        let lower = position.floor() as u64;
+        Self {
+            lower,
+            upper: (lower + 1).min(num_tiles - 1),
+            weight: position - lower as f64,
+        }
+    }
+}
+
+pub struct EqualizeHistogram {
+    bins: usize,
+    tile_shape: Option<Vec<u64>>,
+    clip_limit: Option<f64>,
+    per_slice_axis: Option<usize>,
+    chunk_limit: Option<usize>,
+}
+
+impl EqualizeHistogram {
+    pub fn new(
+        bins: usize,
+        tile_shape: Option<Vec<u64>>,
+        clip_limit: Option<f64>,
+        per_slice_axis: Option<usize>,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            bins,
+            tile_shape,
+            clip_limit,
+            per_slice_axis,
+            chunk_limit,
+        }
+    }
+
+    /// Resolve the tile shape for `shape`, replacing sizes of 0 with the axis length.
+    fn resolve_tile_shape(&self, shape: &[u64]) -> Result<Option<Vec<u64>>, FilterError> {
+        let Some(tile_shape) = &self.tile_shape else {
+            return Ok(None);
+        };
+        if tile_shape.len() != shape.len() {
+            return Err(FilterError::InvalidParameters(format!(
+                "equalize_histogram tile shape {tile_shape:?} does not match the input dimensionality {}",
+                shape.len()
+            )));
+        }
+        Ok(Some(
+            std::iter::zip(tile_shape, shape)
+                .map(|(&tile_size, &size)| {
+                    if tile_size == 0 {
+                        size.max(1)
+                    } else {
+                        tile_size.min(size.max(1))
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    /// Map `value` through the equalization `mapping` to `out_range`.
+    #[inline]
+    fn map_value(
+        &self,
+        value: f64,
+        min_max: (f64, f64),
+        out_range: (f64, f64),
+        mapping: impl Fn(usize) -> f64,
+    ) -> f64 {
+        if value.is_finite() {
+            let (out_min, out_max) = out_range;
+            out_min + mapping(histogram_bin(value, min_max, self.bins)) * (out_max - out_min)
+        } else {
+            value
+        }
+    }
+
+    /// Equalize a chunk with a single (global) mapping, or a mapping per index along the per slice axis.
+    fn equalize_chunk_global(
+        &self,
+        input: &Array<FilterStorage>,
+        subset: &ArraySubset,
+        min_max: &[(f64, f64)],
+        out_range: (f64, f64),
+        mappings: &[Vec<f64>],
+        progress: &Progress,
+    ) -> Result<Vec<f64>, FilterError> {
+        let elements_in = progress.read(|| retrieve_array_subset_elements_f64(input, subset))?;
+        Ok(progress.process(|| {
+            if let Some(axis) = self.per_slice_axis {
+                elements_in
+                    .par_chunks(slice_run_length(subset, axis))
+                    .enumerate()
+                    .flat_map_iter(|(run, elements)| {
+                        let slice = slice_run_index(subset, axis, run);
+                        let (min_max, mapping) = (min_max[slice], &mappings[slice]);
+                        elements.iter().map(move |&value| {
+                            self.map_value(value, min_max, out_range, |bin| mapping[bin])
+                        })
+                    })
+                    .collect()
+            } else {
+                let (min_max, mapping) = (min_max[0], &mappings[0]);
+                elements_in
+                    .par_iter()
+                    .map(|&value| self.map_value(value, min_max, out_range, |bin| mapping[bin]))
+                    .collect()
+            }
+        }))
+    }
+
+    /// Equalize a chunk with the interpolated mappings of its neighbouring tiles.
+    ///
+    /// The input is read with a halo covering all tiles that contribute to the chunk.
+    fn equalize_chunk_tiled(
+        &self,
+        input: &Array<FilterStorage>,
+        subset: &ArraySubset,
+        tile_shape: &[u64],
+        min_max: (f64, f64),
+        out_range: (f64, f64),
+        progress: &Progress,
+    ) -> Result<Vec<f64>, FilterError> {
+        let shape = input.shape();
+        let num_tiles = std::iter::zip(shape, tile_shape)
+            .map(|(size, tile_size)| size.div_ceil(*tile_size).max(1))
+            .collect::<Vec<_>>();
+        let tile_coordinate =
+            |axis: usize, index: u64| TileCoordinate::new(index, tile_shape[axis], num_tiles[axis]);
+
+        // The range of tiles contributing to the chunk
+        let tiles_start = (0..shape.len())
+            .map(|axis| tile_coordinate(axis, subset.start()[axis]).lower)
+            .collect::<Vec<_>>();
+        let tiles_end = (0..shape.len())
+            .map(|axis| tile_coordinate(axis, subset.end_exc()[axis] - 1).upper + 1)
+            .collect::<Vec<_>>();
+        let tiles_local_shape = std::iter::zip(&tiles_start, &tiles_end)
+            .map(|(start, end)| usize::try_from(end - start).unwrap())
+            .collect::<Vec<_>>();
+        let subset_input = ArraySubset::new_with_start_end_exc(
+            std::iter::zip(&tiles_start, tile_shape)
+                .map(|(tile, tile_size)| tile * tile_size)
+                .collect(),
+            itertools::izip!(&tiles_end, tile_shape, shape)
+                .map(|(tile, tile_size, size)| (tile * tile_size).min(*size))
+                .collect(),
+        )
+        .unwrap();
+        let subset_overlap = ArraySubsetOverlap::new_with_subset_input(subset, subset_input);
+
+        let input_array = progress
+            .read(|| retrieve_array_subset_ndarray_f64(input, subset_overlap.subset_input()))?;
+
+        Ok(progress.process(|| {
+            // Tile index (local to the chunk) of an element in the input array
+            let tile_local_index = |indices: &[usize]| {
+                itertools::izip!(
+                    indices,
+                    subset_overlap.subset_input().start(),
+                    tile_shape,
+                    &tiles_start,
+                    &tiles_local_shape
+                )
+                .fold(0, |acc, (&index, start, tile_size, tile_start, tiles)| {
+                    acc * tiles
+                        + usize::try_from((index as u64 + start) / tile_size - tile_start).unwrap()
+                })
+            };
+
+            // Per-tile mappings
+            let num_tiles_local = tiles_local_shape.iter().product::<usize>();
+            let mut histograms = vec![vec![0u64; self.bins]; num_tiles_local];
+            for (indices, value) in input_array.indexed_iter() {
+                if value.is_finite() {
+                    histograms[tile_local_index(indices.slice())]
+                        [histogram_bin(*value, min_max, self.bins)] += 1;
+                }
+            }
+            let mappings = histograms
+                .into_par_iter()
+                .map(|histogram| equalization_mapping(&histogram, self.clip_limit))
+                .collect::<Vec<_>>();
+
+            // Interpolate the mappings of neighbouring tiles
+            let chunk_array = subset_overlap.extract_subset(&input_array);
+            let dimensionality = shape.len();
+            chunk_array
+                .indexed_iter()
+                .map(|(indices, &value)| {
+                    let coordinates = (0..dimensionality)
+                        .map(|axis| {
+                            tile_coordinate(axis, indices[axis] as u64 + subset.start()[axis])
+                        })
+                        .collect::<Vec<_>>();
+                    self.map_value(value, min_max, out_range, |bin| {
+                        let mut mapped = 0.0;
+                        'corners: for corner in 0..(1usize << dimensionality) {
+                            let mut weight = 1.0;
+                            let mut tile = 0;
+                            for (axis, coordinate) in coordinates.iter().enumerate() {
+                                let (tile_axis, weight_axis) = if corner >> axis & 1 == 1 {
+                                    (coordinate.upper, coordinate.weight)
+                                } else {
+                                    (coordinate.lower, 1.0 - coordinate.weight)
+                                };
+                                if weight_axis == 0.0 {
+                                    continue 'corners;
+                                }
+                                weight *= weight_axis;
+                                tile = tile * tiles_local_shape[axis]
+                                    + usize::try_from(tile_axis - tiles_start[axis]).unwrap();
+                            }
+                            mapped += weight * mappings[tile][bin];
+                        }
+                        mapped
+                    })
+                })
+                .collect()
+        }))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        tile_shape: Option<&[u64]>,
+        min_max: &[(f64, f64)],
+        out_range: (f64, f64),
+        mappings: Option<&[Vec<f64>]>,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let elements = match (tile_shape, mappings) {
+            (Some(tile_shape), _) => self.equalize_chunk_tiled(
+                input,
+                &input_output_subset,
+                tile_shape,
+                min_max[0],
+                out_range,
+                progress,
+            )?,
+            (None, Some(mappings)) => self.equalize_chunk_global(
+                input,
+                &input_output_subset,
+                min_max,
+                out_range,
+                mappings,
+                progress,
+            )?,
+            (None, None) => unreachable!(),
+        };
+
+        let elements_out = progress.process(|| {
+            elements
+                .into_par_iter()
+                .map(|value| if round { value.round() } else { value }.as_())
+                .collect::<Vec<TOut>>()
+        });
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for EqualizeHistogram {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        check_per_slice_axis(
+            "equalize_histogram",
+            self.per_slice_axis,
+            chunk_input.dimensionality(),
+        )?;
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // Tiled equalization reads up to one tile beyond each side of a chunk
+        let num_elements_input = if let Some(tile_shape) = &self.tile_shape {
+            std::iter::zip(chunk_input.shape(), tile_shape)
+                .map(|(chunk_size, tile_size)| {
+                    usize::try_from(chunk_size.get() + 2 * tile_size).unwrap()
+                })
+                .product::<usize>()
+        } else {
+            chunk_input.num_elements_usize()
+        };
+        // A histogram per slice of the chunk is accumulated if computing per slice statistics
+        let num_histograms = self
+            .per_slice_axis
+            .and_then(|axis| chunk_input.shape().get(axis))
+            .map_or(1, |size| usize::try_from(size.get()).unwrap());
+        num_elements_input * core::mem::size_of::<f64>()
+            + chunk_input.num_elements_usize()
+                * (chunk_input.fixed_element_size().unwrap()
+                    + core::mem::size_of::<f64>()
+                    + chunk_output.fixed_element_size().unwrap())
+            + num_histograms * self.bins * core::mem::size_of::<u64>()
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let tile_shape = self.resolve_tile_shape(input.shape())?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let chunks_input = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+        let num_statistics_passes = if tile_shape.is_some() { 1 } else { 2 };
+        let progress = Progress::new(
+            num_statistics_passes * chunks_input.num_elements_usize() + chunks.num_elements_usize(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let min_max = if let Some(axis) = self.per_slice_axis {
+            calculate_min_max_per_slice(input, axis, chunk_limit, &progress)?
+                .into_iter()
+                .map(|min_max| min_max.unwrap_or((0.0, 0.0)))
+                .collect::<Vec<_>>()
+        } else {
+            vec![calculate_min_max(input, chunk_limit, &progress)?.unwrap_or((0.0, 0.0))]
+        };
+        let mappings = match (&tile_shape, self.per_slice_axis) {
+            (Some(_), _) => None,
+            (None, Some(axis)) => Some(
+                calculate_histogram_per_slice(
+                    input,
+                    axis,
+                    &min_max,
+                    self.bins,
+                    chunk_limit,
+                    &progress,
+                )?
+                .iter()
+                .map(|histogram| equalization_mapping(histogram, self.clip_limit))
+                .collect::<Vec<_>>(),
+            ),
+            (None, None) => {
+                let histogram =
+                    calculate_histogram(input, min_max[0], self.bins, chunk_limit, &progress)?;
+                Some(vec![equalization_mapping(&histogram, self.clip_limit)])
+            }
+        };
+        let out_range = data_type_range(output.data_type());
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, tile_shape.as_deref(), &min_max, out_range, mappings.as_deref(), &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn equalize_histogram() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        let equalize_histogram = |bins: usize| {
+            run_test_filter(
+                "equalize_histogram",
+                &input,
+                &output,
+                &format!("bins: {bins}"),
+            )
+        };
+
+        // Non-finite values are excluded from the histogram and passed through
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![8],
+                DataType::Float32,
+                vec![3].try_into().unwrap(),
+                0.0f32.into(),
+            ),
+            &[f32::NAN, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0, f32::INFINITY],
+        )?;
+        let elements = retrieve_test_elements::<f32>(&equalize_histogram(6)?)?;
+        assert!(elements[0].is_nan());
+        assert_eq!(
+            elements[1..7],
+            [1, 2, 3, 4, 5, 6].map(|i| (f64::from(i) / 6.0) as f32)
+        );
+        assert_eq!(elements[7], f32::INFINITY);
+
+        // The maximum maps to the top of the integer range without overflowing
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4],
+                DataType::UInt8,
+                vec![3].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &[0u8, 255, 255, 255],
+        )?;
+        let elements = retrieve_test_elements::<u8>(&equalize_histogram(2)?)?;
+        assert_eq!(elements, vec![64, 255, 255, 255]);
+        Ok(())
+    }
+}
@@ -0,0 +1,613 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ExprArguments {
+    /// The expression to evaluate for each element.
+    ///
+    /// The input element is `a`. Evaluation is in 64-bit floating point and the result is cast (saturating) to the output data type.
+    ///
+    /// Operators: + - * / % ^ (power), unary -, parentheses.
+    /// Constants: pi, e, nan, inf.
+    /// Functions: abs, sqrt, exp, ln, log10, log2, sin, cos, tan, floor, ceil, round, trunc, sign, pow(x, y), min(x, y), max(x, y), clip(x, lo, hi).
+    ///
+    /// Example: "clip(a * 0.5 + 10, 0, 255)"
+    #[arg(allow_hyphen_values(true))]
+    pub expression: String,
+}
+
+impl FilterArguments for ExprArguments {
+    fn name(&self) -> String {
+        "expr".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Expr::new(
+            &self.expression,
+            *common_args.chunk_limit(),
+        )?))
+    }
+}
+
+/// A compiled expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Constant(f64),
+    Input,
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Function(Function, Vec<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Log2,
+    Sin,
+    Cos,
+    Tan,
+    Floor,
+    Ceil,
+    Round,
+    Trunc,
+    Sign,
+    Pow,
+    Min,
+    Max,
+    Clip,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Self::Abs,
+            "sqrt" => Self::Sqrt,
+            "exp" => Self::Exp,
+            "ln" => Self::Ln,
+            "log10" => Self::Log10,
+            "log2" => Self::Log2,
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "round" => Self::Round,
+            "trunc" => Self::Trunc,
+            "sign" => Self::Sign,
+            "pow" => Self::Pow,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "clip" => Self::Clip,
+            _ => return None,
+        })
+    }
+
+    fn num_args(&self) -> usize {
+        match self {
+            Self::Pow | Self::Min | Self::Max => 2,
+            Self::Clip => 3,
+            _ => 1,
+        }
+    }
+
+    fn apply(&self, args: &[f64]) -> f64 {
+        match self {
+            Self::Abs => args[0].abs(),
+            Self::Sqrt => args[0].sqrt(),
+            Self::Exp => args[0].exp(),
+            Self::Ln => args[0].ln(),
+            Self::Log10 => args[0].log10(),
+            Self::Log2 => args[0].log2(),
+            Self::Sin => args[0].sin(),
+            Self::Cos => args[0].cos(),
+            Self::Tan => args[0].tan(),
+            Self::Floor => args[0].floor(),
+            Self::Ceil => args[0].ceil(),
+            Self::Round => args[0].round(),
+            Self::Trunc => args[0].trunc(),
+            Self::Sign => {
+                if args[0].is_nan() || args[0] == 0.0 {
+                    args[0]
+                } else {
+                    args[0].signum()
+                }
+            }
+            Self::Pow => args[0].powf(args[1]),
+            Self::Min => args[0].min(args[1]),
+            Self::Max => args[0].max(args[1]),
+            Self::Clip => args[0].max(args[1]).min(args[2]),
+        }
+    }
+}
+
+impl Expression {
+    /// Parse an expression.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if the expression is malformed.
+    pub fn parse(expression: &str) -> Result<Self, FilterError> {
+        let mut parser = ExpressionParser {
+            chars: expression.chars().collect(),
+            position: 0,
+        };
+        let parsed = parser.parse_expression()?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(parsed)
+    }
+
+    /// Evaluate the expression for input element `a`.
+    #[inline]
+    pub fn evaluate(&self, a: f64) -> f64 {
+        match self {
+            Self::Constant(value) => *value,
+            Self::Input => a,
+            Self::Negate(expression) => -expression.evaluate(a),
+            Self::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(a);
+                let rhs = rhs.evaluate(a);
+                match op {
+                    Operator::Add => lhs + rhs,
+                    Operator::Sub => lhs - rhs,
+                    Operator::Mul => lhs * rhs,
+                    Operator::Div => lhs / rhs,
+                    Operator::Rem => lhs % rhs,
+                    Operator::Pow => lhs.powf(rhs),
+                }
+            }
+            Self::Function(function, args) => {
+                let mut values = [0.0; 3];
+                for (value, arg) in values.iter_mut().zip(args) {
+                    *value = arg.evaluate(a);
+                }
+                function.apply(&values[..args.len()])
+            }
+        }
+    }
+}
+
+/// A recursive descent parser for [`Expression`].
+///
+/// Grammar (lowest to highest precedence):
+///   expression := term (('+' | '-') term)*
+///   term       := unary (('*' | '/' | '%') unary)*
+///   unary      := '-' unary | power
+///   power      := primary ('^' unary)?
+///   primary    := number | identifier | identifier '(' expression (',' expression)* ')' | '(' expression ')'
+struct ExpressionParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl ExpressionParser {
+    fn error(&self, message: &str) -> FilterError {
+        FilterError::InvalidParameters(format!(
+            "invalid expression: {message} at position {}",
+            self.position
+        ))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.position).copied()
+    }
+
+    fn consume(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, FilterError> {
+        let mut lhs = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Sub,
+                _ => return Ok(lhs),
+            };
+            self.position += 1;
+            let rhs = self.parse_term()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expression, FilterError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Operator::Mul,
+                Some('/') => Operator::Div,
+                Some('%') => Operator::Rem,
+                _ => return Ok(lhs),
+            };
+            self.position += 1;
+            let rhs = self.parse_unary()?;
+            lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, FilterError> {
+        if self.consume('-') {
+            Ok(Expression::Negate(Box::new(self.parse_unary()?)))
+        } else if self.consume('+') {
+            self.parse_unary()
+        } else {
+            self.parse_power()
+        }
+    }
+
+    fn parse_power(&mut self) -> Result<Expression, FilterError> {
+        let base = self.parse_primary()?;
+        if self.consume('^') {
+            // Right associative
+            let exponent = self.parse_unary()?;
+            Ok(Expression::Binary(
+                Operator::Pow,
+                Box::new(base),
+                Box::new(exponent),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, FilterError> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                let expression = self.parse_expression()?;
+                if !self.consume(')') {
+                    return Err(self.error("expected ')'"));
+                }
+                Ok(expression)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.parse_number(),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self.parse_identifier(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Expression, FilterError> {
+        let start = self.position;
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+        {
+            self.position += 1;
+        }
+        // Exponent
+        if matches!(self.chars.get(self.position), Some('e' | 'E')) {
+            let mantissa_end = self.position;
+            self.position += 1;
+            if matches!(self.chars.get(self.position), Some('+' | '-')) {
+                self.position += 1;
+            }
+            if self
+                .chars
+                .get(self.position)
+                .is_some_and(|c| c.is_ascii_digit())
+            {
+                while self
+                    .chars
+                    .get(self.position)
+                    .is_some_and(|c| c.is_ascii_digit())
+                {
+                    self.position += 1;
+                }
+            } else {
+                self.position = mantissa_end;
+            }
+        }
+        let number: String = self.chars[start..self.position].iter().collect();
+        number
+            .parse::<f64>()
+            .map(Expression::Constant)
+            .map_err(|_| {
+                self.position = start;
+                self.error("invalid number")
+            })
+    }
+
+    fn parse_identifier(&mut self) -> Result<Expression, FilterError> {
+        let start = self.position;
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+        {
+            self.position += 1;
+        }
+        let identifier: String = self.chars[start..self.position].iter().collect();
+
+        if self.consume('(') {
+            let Some(function) = Function::from_name(&identifier) else {
+                self.position = start;
+                return Err(self.error(&format!("unknown function {identifier}")));
+            };
+            let mut args = vec![self.parse_expression()?];
+            while self.consume(',') {
+                args.push(self.parse_expression()?);
+            }
+            if !self.consume(')') {
+                return Err(self.error("expected ')'"));
+            }
+            if args.len() != function.num_args() {
+                self.position = start;
+                return Err(self.error(&format!(
+                    "{identifier} expects {} argument(s), got {}",
+                    function.num_args(),
+                    args.len()
+                )));
+            }
+            Ok(Expression::Function(function, args))
+        } else {
+            match identifier.as_str() {
+                "a" => Ok(Expression::Input),
+                "pi" => Ok(Expression::Constant(std::f64::consts::PI)),
+                "e" => Ok(Expression::Constant(std::f64::consts::E)),
+                "nan" => Ok(Expression::Constant(f64::NAN)),
+                "inf" => Ok(Expression::Constant(f64::INFINITY)),
+                _ => {
+                    self.position = start;
+                    Err(self.error(&format!("unknown variable {identifier}")))
+                }
+            }
+        }
+    }
+}
+
+pub struct Expr {
+    expression: Expression,
+    chunk_limit: Option<usize>,
+}
+
+impl Expr {
+    /// Create a new expression filter.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if `expression` is malformed.
+    pub fn new(expression: &str, chunk_limit: Option<usize>) -> Result<Self, FilterError> {
+        Ok(Self {
+            expression: Expression::parse(expression)?,
+            chunk_limit,
+        })
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<f64>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements_in =
+            progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_output_subset))?;
+
+        let elements_out = progress.process(|| self.apply_elements(&elements_in));
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    pub fn apply_elements<TIn, TOut>(&self, elements_in: &[TIn]) -> Vec<TOut>
+    where
+        TIn: Send + Sync + AsPrimitive<f64>,
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        elements_in
+            .par_iter()
+            .map(|value| self.expression.evaluate(value.as_()).as_())
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for Expr {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn supports_in_place(&self) -> bool {
+        true
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$t_in, $t_out>(&input, &output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                            match output.data_type() {
+                                $(
+                                    DataType::$data_type => {
+                                        apply_input!($type_out, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expr_parse_evaluate() -> Result<(), FilterError> {
+        let expression = Expression::parse("clip(a * 0.5 + 10, 0, 255)")?;
+        assert_eq!(expression.evaluate(4.0), 12.0);
+        assert_eq!(expression.evaluate(-100.0), 0.0);
+        assert_eq!(expression.evaluate(1000.0), 255.0);
+
+        assert_eq!(Expression::parse("-2^2")?.evaluate(0.0), -4.0);
+        assert_eq!(Expression::parse("2^3^2")?.evaluate(0.0), 512.0);
+        assert_eq!(Expression::parse("(1 + a) * 2 - 3 % 2")?.evaluate(1.0), 3.0);
+        assert_eq!(Expression::parse("1e2 + max(a, 1.5)")?.evaluate(1.0), 101.5);
+
+        assert!(Expression::parse("a +").is_err());
+        assert!(Expression::parse("b").is_err());
+        assert!(Expression::parse("foo(a)").is_err());
+        assert!(Expression::parse("min(a)").is_err());
+        assert!(Expression::parse("(a").is_err());
+
+        let expr = Expr::new("a * 2", None)?;
+        assert_eq!(
+            expr.apply_elements::<u8, u8>(&[1, 100, 200]),
+            vec![2, 200, 255]
+        );
+        Ok(())
+    }
+}
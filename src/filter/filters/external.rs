@@ -0,0 +1,284 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use itertools::Itertools;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, ArrayError},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ExternalArguments {
+    /// The program run on each chunk.
+    ///
+    /// The decoded elements of an input chunk are written to its stdin, and the decoded elements of the output chunk are read from its stdout.
+    /// The chunk is described by the environment variables
+    ///   ZARRS_FILTER_DATA_TYPE_IN, ZARRS_FILTER_DATA_TYPE_OUT: the input and output data types (e.g. uint8, float32)
+    ///   ZARRS_FILTER_ARRAY_SHAPE: the shape of the array, comma delimited
+    ///   ZARRS_FILTER_CHUNK_START, ZARRS_FILTER_CHUNK_SHAPE: the start and shape of the chunk, comma delimited
+    ///   ZARRS_FILTER_ENDIAN: the byte order of the elements (little or big)
+    #[arg(verbatim_doc_comment)]
+    pub command: String,
+    /// Arguments of the program.
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl FilterArguments for ExternalArguments {
+    fn name(&self) -> String {
+        "external".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(External::new(
+            self.command.clone(),
+            self.args.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// A filter run by an external program on each chunk, exchanging the decoded chunk elements over stdin and stdout.
+pub struct External {
+    command: String,
+    args: Vec<String>,
+    chunk_limit: Option<usize>,
+}
+
+impl External {
+    pub fn new(command: String, args: Vec<String>, chunk_limit: Option<usize>) -> Self {
+        Self {
+            command,
+            args,
+            chunk_limit,
+        }
+    }
+
+    /// Run the program on the decoded `bytes` of `input` in `subset`, returning the decoded bytes of `output` in `subset`.
+    ///
+    /// # Errors
+    /// Returns an error if the program cannot be run, exits unsuccessfully, or writes the wrong number of bytes.
+    pub fn apply_chunk(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        subset: &ArraySubset,
+        bytes: &[u8],
+    ) -> Result<Vec<u8>, FilterError> {
+        let join = |values: &[u64]| values.iter().join(",");
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .env("ZARRS_FILTER_DATA_TYPE_IN", input.data_type().to_string())
+            .env("ZARRS_FILTER_DATA_TYPE_OUT", output.data_type().to_string())
+            .env("ZARRS_FILTER_ARRAY_SHAPE", join(input.shape()))
+            .env("ZARRS_FILTER_CHUNK_START", join(subset.start()))
+            .env("ZARRS_FILTER_CHUNK_SHAPE", join(subset.shape()))
+            .env(
+                "ZARRS_FILTER_ENDIAN",
+                if cfg!(target_endian = "big") {
+                    "big"
+                } else {
+                    "little"
+                },
+            )
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| FilterError::Other(format!("failed to run {}: {err}", self.command)))?;
+
+        // Write stdin while stdout is read, so a program streaming its output does not deadlock
+        let mut stdin = child.stdin.take().unwrap();
+        let (written, result) = std::thread::scope(|scope| {
+            let writer = scope.spawn(move || stdin.write_all(bytes));
+            let result = child.wait_with_output();
+            (writer.join().unwrap(), result)
+        });
+        let result = result?;
+        if !result.status.success() {
+            return Err(FilterError::Other(format!(
+                "{} failed ({}) on the chunk at {:?}: {}",
+                self.command,
+                result.status,
+                subset.start(),
+                String::from_utf8_lossy(&result.stderr).trim()
+            )));
+        }
+        // A program need not read all of its input
+        match written {
+            Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+
+        let expected = subset.num_elements_usize() * output.data_type().fixed_size().unwrap();
+        if result.stdout.len() != expected {
+            return Err(FilterError::Other(format!(
+                "{} wrote {} bytes for the chunk at {:?}, expected {expected}",
+                self.command,
+                result.stdout.len(),
+                subset.start()
+            )));
+        }
+        Ok(result.stdout)
+    }
+}
+
+impl FilterTraits for External {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            if data_type.fixed_size().is_none() {
+                Err(UnsupportedDataTypeError::from(data_type.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                let subset = output.chunk_subset_bounded(&chunk_indices)?;
+                let input_bytes = progress
+                    .read(|| input.retrieve_array_subset(&subset))?
+                    .into_fixed()
+                    .map_err(ArrayError::from)?;
+                let output_bytes =
+                    progress.process(|| self.apply_chunk(input, output, &subset, &input_bytes))?;
+                drop(input_bytes);
+                progress.write(|| output.store_array_subset(&subset, output_bytes))?;
+                progress.next();
+                Ok::<_, FilterError>(())
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use std::sync::Arc;
+
+    use zarrs::{
+        array::{ArrayBuilder, DataType},
+        storage::store::MemoryStore,
+    };
+
+    use crate::progress::ProgressStats;
+
+    use super::*;
+
+    #[test]
+    fn external() -> Result<(), Box<dyn std::error::Error>> {
+        let store: Arc<FilterStorage> = Arc::new(MemoryStore::new());
+        let array = |path: &str| {
+            ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 3].try_into().unwrap(),
+                0u8.into(),
+            )
+            .build(store.clone(), path)
+            .unwrap()
+        };
+        let input = array("/input");
+        input.store_array_subset_elements(
+            &ArraySubset::new_with_shape(vec![4, 4]),
+            &(0..16).collect::<Vec<u8>>(),
+        )?;
+        let mut output = array("/output");
+
+        let progress_callback = |_stats: ProgressStats| {};
+        let progress_callback = ProgressCallback::new(&progress_callback);
+
+        // An identity filter
+        External::new("cat".to_string(), vec![], None).apply(
+            &input,
+            &mut output,
+            &progress_callback,
+        )?;
+        assert_eq!(
+            output
+                .retrieve_array_subset_elements::<u8>(&ArraySubset::new_with_shape(vec![4, 4]))?,
+            (0..16).collect::<Vec<u8>>()
+        );
+
+        // The chunk is described by the environment
+        let chunk_shape = External::new(
+            "sh".to_string(),
+            vec![
+                "-c".to_string(),
+                "printf %s $ZARRS_FILTER_CHUNK_SHAPE".to_string(),
+            ],
+            None,
+        );
+        let subset = ArraySubset::new_with_start_shape(vec![1, 3], vec![3, 1])?;
+        assert_eq!(
+            chunk_shape.apply_chunk(&input, &output, &subset, &[0, 0, 0])?,
+            b"3,1"
+        );
+        assert!(chunk_shape
+            .apply(&input, &mut output, &progress_callback)
+            .is_err());
+
+        // A failing program
+        assert!(External::new("false".to_string(), vec![], None)
+            .apply(&input, &mut output, &progress_callback)
+            .is_err());
+        Ok(())
+    }
+}
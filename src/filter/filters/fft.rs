@@ -0,0 +1,374 @@
+use std::sync::Arc;
+
+use clap::Parser;
+use num_complex::{Complex32, Complex64};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+use super::complex::ToComplex64;
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct FftArguments {
+    /// The axes to transform along, comma delimited. Defaults to all axes.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub axes: Option<Vec<usize>>,
+    /// Compute the inverse transform, normalised by the number of elements transformed.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub inverse: bool,
+}
+
+impl FilterArguments for FftArguments {
+    fn name(&self) -> String {
+        "fft".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Fft::new(
+            self.axes.clone(),
+            self.inverse,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// A complex element that can be created from a [`Complex64`].
+pub trait FromComplex64: Copy {
+    fn from_complex64(value: Complex64) -> Self;
+}
+
+impl FromComplex64 for Complex32 {
+    #[inline]
+    fn from_complex64(value: Complex64) -> Self {
+        Complex32::new(value.re as f32, value.im as f32)
+    }
+}
+
+impl FromComplex64 for Complex64 {
+    #[inline]
+    fn from_complex64(value: Complex64) -> Self {
+        value
+    }
+}
+
+pub struct Fft {
+    axes: Option<Vec<usize>>,
+    inverse: bool,
+    chunk_limit: Option<usize>,
+}
+
+impl Fft {
+    pub fn new(axes: Option<Vec<usize>>, inverse: bool, chunk_limit: Option<usize>) -> Self {
+        Self {
+            axes,
+            inverse,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the axes to transform along.
+    fn axes(&self, dimensionality: usize) -> Vec<usize> {
+        self.axes
+            .clone()
+            .unwrap_or_else(|| (0..dimensionality).collect())
+    }
+
+    /// Transform each lane of `array` along `axis` in place.
+    ///
+    /// Lanes are copied to a contiguous buffer, so the axis need not be the innermost.
+    pub fn apply_ndarray(
+        array: &mut ndarray::ArrayD<Complex64>,
+        axis: usize,
+        fft: &Arc<dyn rustfft::Fft<f64>>,
+        inverse: bool,
+    ) {
+        let len = array.shape()[axis];
+        let scale = if inverse { 1.0 / len as f64 } else { 1.0 };
+        let mut buffer = vec![Complex64::default(); len];
+        let mut scratch = vec![Complex64::default(); fft.get_inplace_scratch_len()];
+        for mut lane in array.lanes_mut(ndarray::Axis(axis)) {
+            std::iter::zip(&mut buffer, lane.iter()).for_each(|(b, v)| *b = *v);
+            fft.process_with_scratch(&mut buffer, &mut scratch);
+            std::iter::zip(lane.iter_mut(), &buffer).for_each(|(v, b)| *v = *b * scale);
+        }
+    }
+
+    /// Transform a column of chunks spanning the full extent of `axis`, starting at the chunk at `chunk_start`.
+    ///
+    /// The column is read from `source`, which is the input for the first axis and the output thereafter.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_column<TIn, TOut>(
+        &self,
+        source: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_start: &[u64],
+        axis: usize,
+        fft: &Arc<dyn rustfft::Fft<f64>>,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + ToComplex64,
+        TOut: Element + Send + Sync + FromComplex64 + 'static,
+    {
+        let chunk_subset = output.chunk_subset_bounded(chunk_start).unwrap();
+        let mut ranges = std::iter::zip(chunk_subset.start(), chunk_subset.shape())
+            .map(|(&start, &size)| start..start + size)
+            .collect::<Vec<_>>();
+        ranges[axis] = 0..output.shape()[axis];
+        let subset = ArraySubset::new_with_ranges(&ranges);
+
+        let array = progress.read(|| source.retrieve_array_subset_ndarray::<TIn>(&subset))?;
+
+        let array = progress.process(|| {
+            let mut array = array.map(|v| v.to_complex64());
+            Self::apply_ndarray(&mut array, axis, fft, self.inverse);
+            array.map(|v| TOut::from_complex64(*v))
+        });
+
+        progress.write(|| output.store_array_subset_ndarray::<TOut, _>(subset.start(), array))?;
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Fft {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        let axes = self.axes(dimensionality);
+        if axes.is_empty() {
+            return Err(FilterError::InvalidParameters(
+                "fft requires at least one axis".to_string(),
+            ));
+        }
+        for (i, axis) in axes.iter().enumerate() {
+            if *axis >= dimensionality {
+                return Err(FilterError::InvalidParameters(format!(
+                    "fft axis {axis} is out of bounds for an input with dimensionality {dimensionality}"
+                )));
+            } else if axes[..i].contains(axis) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "fft axis {axis} is repeated"
+                )));
+            }
+        }
+        match chunk_input.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16
+            | DataType::Complex64
+            | DataType::Complex128 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Complex64 | DataType::Complex128 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input, the output, and the transformed chunk in double precision
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap()
+                + core::mem::size_of::<Complex64>())
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        match input.data_type() {
+            DataType::Complex64 | DataType::Complex128 => None,
+            DataType::Float16 | DataType::BFloat16 | DataType::Float32 => Some((
+                DataType::Complex64,
+                FillValue::from(Complex32::new(0.0, 0.0)),
+            )),
+            _ => Some((
+                DataType::Complex128,
+                FillValue::from(Complex64::new(0.0, 0.0)),
+            )),
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+        let output = &*output;
+
+        let axes = self.axes(output.dimensionality());
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let columns_shape = |axis: usize| {
+            let mut columns_shape = chunk_grid_shape.clone();
+            columns_shape[axis] = 1;
+            columns_shape
+        };
+        let progress = Progress::new(
+            axes.iter()
+                .map(|axis| usize::try_from(columns_shape(*axis).iter().product::<u64>()).unwrap())
+                .sum(),
+            progress_callback,
+        );
+
+        let memory_per_chunk = self.memory_per_chunk(
+            &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+        );
+
+        // Each axis is transformed in turn over columns of chunks spanning the full extent of the axis
+        let mut planner = FftPlanner::<f64>::new();
+        for (i, &axis) in axes.iter().enumerate() {
+            let source = if i == 0 { input } else { output };
+            let fft = if self.inverse {
+                planner.plan_fft_inverse(usize::try_from(output.shape()[axis]).unwrap())
+            } else {
+                planner.plan_fft_forward(usize::try_from(output.shape()[axis]).unwrap())
+            };
+
+            let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+                chunk_limit
+            } else {
+                calculate_chunk_limit(
+                    memory_per_chunk * usize::try_from(chunk_grid_shape[axis]).unwrap(),
+                )?
+            };
+
+            let columns = ArraySubset::new_with_shape(columns_shape(axis));
+            let indices = columns.indices();
+            rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                chunk_limit,
+                indices,
+                try_for_each,
+                |chunk_start: Vec<u64>| {
+                    macro_rules! apply_output {
+                        ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                            match output.data_type() {
+                                $(DataType::$data_type_out => { self.apply_column::<$type_in, $type_out>(source, output, &chunk_start, axis, &fft, &progress) } ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                    macro_rules! apply_input {
+                        ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                            match source.data_type() {
+                                $(
+                                    DataType::$data_type_in => {
+                                        apply_output!($type_in, [
+                                            (Complex64, Complex32),
+                                            (Complex128, Complex64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                    apply_input!([
+                        (Bool, u8),
+                        (Int8, i8),
+                        (Int16, i16),
+                        (Int32, i32),
+                        (Int64, i64),
+                        (UInt8, u8),
+                        (UInt16, u16),
+                        (UInt32, u32),
+                        (UInt64, u64),
+                        (BFloat16, half::bf16),
+                        (Float16, half::f16),
+                        (Float32, f32),
+                        (Float64, f64),
+                        (Complex64, Complex32),
+                        (Complex128, Complex64)
+                    ])
+                }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_abs_diff_eq;
+
+    use super::*;
+
+    #[test]
+    fn fft_ndarray() {
+        let elements: ndarray::ArrayD<Complex64> =
+            ndarray::array![[1.0, 2.0, 3.0, 4.0], [0.0, 1.0, 0.0, -1.0]]
+                .map(|v| Complex64::new(*v, 0.0))
+                .into_dyn();
+        let mut planner = FftPlanner::<f64>::new();
+
+        let mut array = elements.clone();
+        Fft::apply_ndarray(&mut array, 1, &planner.plan_fft_forward(4), false);
+        let array_ref = ndarray::array![
+            [
+                Complex64::new(10.0, 0.0),
+                Complex64::new(-2.0, 2.0),
+                Complex64::new(-2.0, 0.0),
+                Complex64::new(-2.0, -2.0)
+            ],
+            [
+                Complex64::new(0.0, 0.0),
+                Complex64::new(0.0, -2.0),
+                Complex64::new(0.0, 0.0),
+                Complex64::new(0.0, 2.0)
+            ]
+        ]
+        .into_dyn();
+        for (v, v_ref) in std::iter::zip(&array, &array_ref) {
+            assert_abs_diff_eq!(v.re, v_ref.re, epsilon = 1e-12);
+            assert_abs_diff_eq!(v.im, v_ref.im, epsilon = 1e-12);
+        }
+
+        // The inverse along both axes recovers the input from its transform along both axes
+        Fft::apply_ndarray(&mut array, 0, &planner.plan_fft_forward(2), false);
+        Fft::apply_ndarray(&mut array, 0, &planner.plan_fft_inverse(2), true);
+        Fft::apply_ndarray(&mut array, 1, &planner.plan_fft_inverse(4), true);
+        for (v, v_ref) in std::iter::zip(&array, &elements) {
+            assert_abs_diff_eq!(v.re, v_ref.re, epsilon = 1e-12);
+            assert_abs_diff_eq!(v.im, v_ref.im, epsilon = 1e-12);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use clap::Parser;
+use itertools::Itertools;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillNanMode {
+    /// Replace with a constant value.
+    Constant,
+    /// Replace with the nearest finite value along the axis.
+    Nearest,
+    /// Linearly interpolate between the adjacent finite values along the axis.
+    ///
+    /// Values beyond the first/last finite value along the axis take the nearest finite value.
+    Linear,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct FillNanArguments {
+    /// The fill mode.
+    #[arg(value_enum)]
+    pub mode: FillNanMode,
+    /// The replacement value for the constant mode.
+    #[arg(long, allow_hyphen_values(true), default_value_t = 0.0)]
+    #[serde(default)]
+    pub value: f64,
+    /// The axis for the nearest and linear modes.
+    #[arg(long, default_value_t = 0)]
+    #[serde(default)]
+    pub axis: usize,
+}
+
+impl FilterArguments for FillNanArguments {
+    fn name(&self) -> String {
+        "fillnan".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(FillNan::new(
+            self.mode,
+            self.value,
+            self.axis,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct FillNan {
+    mode: FillNanMode,
+    value: f64,
+    axis: usize,
+    chunk_limit: Option<usize>,
+}
+
+impl FillNan {
+    pub fn new(mode: FillNanMode, value: f64, axis: usize, chunk_limit: Option<usize>) -> Self {
+        Self {
+            mode,
+            value,
+            axis,
+            chunk_limit,
+        }
+    }
+
+    /// Returns true if the filter operates on entire lanes along the axis rather than individual chunks.
+    fn lanes(&self) -> bool {
+        self.mode != FillNanMode::Constant
+    }
+
+    pub fn apply_subset<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        subset: &ArraySubset,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<f64>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_array = progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(subset))?;
+
+        let output_array = progress.process(|| {
+            let mut array = input_array.mapv(|x| x.as_());
+            if self.lanes() {
+                array
+                    .lanes_mut(ndarray::Axis(self.axis))
+                    .into_iter()
+                    .for_each(|mut lane| {
+                        let mut values = lane.to_vec();
+                        match self.mode {
+                            FillNanMode::Nearest => fill_lane_nearest(&mut values),
+                            FillNanMode::Linear => fill_lane_linear(&mut values),
+                            FillNanMode::Constant => unreachable!(),
+                        }
+                        lane.iter_mut()
+                            .zip(values)
+                            .for_each(|(element, value)| *element = value);
+                    });
+            } else {
+                array.mapv_inplace(|x| if x.is_finite() { x } else { self.value });
+            }
+            array.mapv(|x| x.as_())
+        });
+        drop(input_array);
+
+        progress
+            .write(|| output.store_array_subset_ndarray::<TOut, _>(subset.start(), output_array))?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+/// Returns the indices of the previous and next finite values for each element of `lane`.
+fn finite_neighbours(lane: &[f64]) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+    let mut previous = Vec::with_capacity(lane.len());
+    let mut last = None;
+    for (i, value) in lane.iter().enumerate() {
+        if value.is_finite() {
+            last = Some(i);
+        }
+        previous.push(last);
+    }
+    let mut next = vec![None; lane.len()];
+    let mut last = None;
+    for (i, value) in lane.iter().enumerate().rev() {
+        if value.is_finite() {
+            last = Some(i);
+        }
+        next[i] = last;
+    }
+    (previous, next)
+}
+
+/// Replace non-finite values in `lane` with the nearest finite value.
+///
+/// Ties are resolved to the preceding value.
+pub fn fill_lane_nearest(lane: &mut [f64]) {
+    let (previous, next) = finite_neighbours(lane);
+    for i in 0..lane.len() {
+        if !lane[i].is_finite() {
+            let nearest = match (previous[i], next[i]) {
+                (Some(p), Some(n)) => Some(if i - p <= n - i { p } else { n }),
+                (p, n) => p.or(n),
+            };
+            if let Some(nearest) = nearest {
+                lane[i] = lane[nearest];
+            }
+        }
+    }
+}
+
+/// Replace non-finite values in `lane` by linear interpolation of the adjacent finite values.
+pub fn fill_lane_linear(lane: &mut [f64]) {
+    let (previous, next) = finite_neighbours(lane);
+    for i in 0..lane.len() {
+        if !lane[i].is_finite() {
+            lane[i] = match (previous[i], next[i]) {
+                (Some(p), Some(n)) => {
+                    let t = (i - p) as f64 / (n - p) as f64;
+                    lane[p] + t * (lane[n] - lane[p])
+                }
+                (Some(p), None) => lane[p],
+                (None, Some(n)) => lane[n],
+                (None, None) => lane[i],
+            };
+        }
+    }
+}
+
+impl FilterTraits for FillNan {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16 => {}
+            _ => Err(UnsupportedDataTypeError::from(
+                chunk_input.data_type().to_string(),
+            ))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            _ => Err(UnsupportedDataTypeError::from(
+                chunk_output.data_type().to_string(),
+            ))?,
+        };
+        if self.lanes() && self.axis >= chunk_input.dimensionality() {
+            return Err(FilterError::InvalidParameters(format!(
+                "axis {} is out of bounds for an array with {} dimensions",
+                self.axis,
+                chunk_input.dimensionality()
+            )));
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        // The nearest and linear modes process entire columns of chunks along the axis
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let chunks_per_subset = if self.lanes() {
+            chunk_grid_shape[self.axis]
+        } else {
+            1
+        };
+        let chunk_grid_shape_subsets = chunk_grid_shape
+            .iter()
+            .enumerate()
+            .map(|(i, dim_i)| {
+                if self.lanes() && i == self.axis {
+                    1
+                } else {
+                    *dim_i
+                }
+            })
+            .collect_vec();
+        let subsets = ArraySubset::new_with_shape(chunk_grid_shape_subsets);
+        let progress = Progress::new(subsets.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(
+                self.memory_per_chunk(
+                    &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                    &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                ) * usize::try_from(chunks_per_subset).unwrap(),
+            )?
+        };
+
+        let indices = subsets.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                let subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+                let subset = if self.lanes() {
+                    let mut start = subset.start().to_vec();
+                    let mut shape = subset.shape().to_vec();
+                    start[self.axis] = 0;
+                    shape[self.axis] = output.shape()[self.axis];
+                    ArraySubset::new_with_start_shape(start, shape).unwrap()
+                } else {
+                    subset
+                };
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type => { self.apply_subset::<$t_in, $t_out>(&input, &output, &subset, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                            match output.data_type() {
+                                $(
+                                    DataType::$data_type => {
+                                        apply_input!($type_out, [
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fillnan_lanes() {
+        let lane = [f64::NAN, 1.0, f64::NAN, f64::NAN, 4.0, f64::INFINITY];
+
+        let mut nearest = lane;
+        fill_lane_nearest(&mut nearest);
+        assert_eq!(nearest, [1.0, 1.0, 1.0, 4.0, 4.0, 4.0]);
+
+        let mut linear = lane;
+        fill_lane_linear(&mut linear);
+        assert_eq!(linear, [1.0, 1.0, 2.0, 3.0, 4.0, 4.0]);
+
+        let mut empty = [f64::NAN; 3];
+        fill_lane_linear(&mut empty);
+        assert!(empty.iter().all(|x| x.is_nan()));
+    }
+}
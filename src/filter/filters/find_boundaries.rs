@@ -0,0 +1,321 @@
+use clap::Parser;
+use ndarray::{ArrayD, Dimension};
+use num_traits::NumCast;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::label::{neighbour_offsets, offset_index, Connectivity},
+        ArraySubsetOverlap, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundaryMode {
+    /// Elements with a neighbour of a different label, on both sides of the boundary.
+    #[default]
+    Thick,
+    /// Boundary elements inside of labelled (non-background) regions.
+    Inner,
+    /// Boundary elements in the background, and on the higher label side where labels touch.
+    Outer,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct FindBoundariesArguments {
+    /// The boundary mode.
+    #[arg(long, value_enum, default_value_t = BoundaryMode::Thick)]
+    #[serde(default)]
+    pub mode: BoundaryMode,
+    /// The connectivity of neighbouring elements.
+    #[arg(long, value_enum, default_value_t = Connectivity::Face)]
+    #[serde(default)]
+    pub connectivity: Connectivity,
+    /// The background label.
+    #[arg(long, default_value_t = 0, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub background: i64,
+}
+
+impl FilterArguments for FindBoundariesArguments {
+    fn name(&self) -> String {
+        "find_boundaries".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(FindBoundaries::new(
+            self.mode,
+            self.connectivity,
+            self.background,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct FindBoundaries {
+    mode: BoundaryMode,
+    connectivity: Connectivity,
+    background: i64,
+    chunk_limit: Option<usize>,
+}
+
+impl FindBoundaries {
+    pub fn new(
+        mode: BoundaryMode,
+        connectivity: Connectivity,
+        background: i64,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            mode,
+            connectivity,
+            background,
+            chunk_limit,
+        }
+    }
+
+    /// Mark the label boundaries of `input`.
+    ///
+    /// Neighbours outside of `input` are ignored.
+    pub fn apply_ndarray<T>(&self, input: &ArrayD<T>, background: T) -> ArrayD<bool>
+    where
+        T: Copy + PartialOrd + Send + Sync,
+    {
+        let shape = input.shape();
+        let offsets = neighbour_offsets(input.ndim(), self.connectivity);
+        let boundaries = input.indexed_iter().map(|(index, &label)| {
+            let index = index.slice();
+            let mut differs = false;
+            let mut higher = false;
+            for offset in &offsets {
+                if let Some(neighbour) = offset_index(index, offset, shape) {
+                    let neighbour = input[neighbour.as_slice()];
+                    differs |= neighbour != label;
+                    higher |= neighbour > label;
+                }
+            }
+            match self.mode {
+                BoundaryMode::Thick => differs,
+                BoundaryMode::Inner => differs && label != background,
+                BoundaryMode::Outer => differs && (label == background || higher),
+            }
+        });
+        ArrayD::from_shape_vec(shape, boundaries.collect()).unwrap()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Copy + PartialOrd + NumCast + Send + Sync,
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        let background = <TIn as NumCast>::from(self.background).ok_or_else(|| {
+            FilterError::InvalidParameters(format!(
+                "find_boundaries background {} is not representable by the input data type {}",
+                self.background,
+                input.data_type()
+            ))
+        })?;
+
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(
+            input.shape(),
+            &subset_output,
+            &vec![1; input.dimensionality()],
+        );
+
+        let input_array = progress
+            .read(|| input.retrieve_array_subset_ndarray::<TIn>(subset_overlap.subset_input()))?;
+
+        let output_array = progress.process(|| {
+            let boundaries = self.apply_ndarray(&input_array, background);
+            subset_overlap
+                .extract_subset(&boundaries)
+                .mapv(|boundary| TOut::from(boundary))
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for FindBoundaries {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Bool | DataType::UInt8 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = chunk_input
+            .shape()
+            .iter()
+            .map(|s| usize::try_from(s.get() + 2).unwrap())
+            .product::<usize>();
+        num_input_elements
+            * (chunk_input.fixed_element_size().unwrap() + core::mem::size_of::<bool>())
+            + chunk_input.num_elements_usize() * chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::Bool, FillValue::from(false)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type_in:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type_in => {
+                                self.apply_chunk::<$t_in, $t_out>(input, &output, &chunk_indices, &progress)
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(
+                                DataType::$data_type_out => {
+                                    apply_input!($type_out, [
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([(Bool, bool), (UInt8, u8)])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn find_boundaries() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        // Labels that are not distinguishable as f64, touching across a chunk boundary
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt64,
+                vec![2, 2].try_into().unwrap(),
+                0u64.into(),
+            ),
+            &[u64::MAX - 1, u64::MAX - 1, u64::MAX, u64::MAX].repeat(4),
+        )?;
+
+        let find_boundaries = |args: &str| -> Result<Vec<bool>, Box<dyn std::error::Error>> {
+            retrieve_test_elements(&run_test_filter("find_boundaries", &input, &output, args)?)
+        };
+
+        assert_eq!(
+            find_boundaries("mode: thick")?,
+            [false, true, true, false].repeat(4)
+        );
+        assert_eq!(
+            find_boundaries("mode: inner")?,
+            [false, true, true, false].repeat(4)
+        );
+        assert_eq!(
+            find_boundaries("mode: outer")?,
+            [false, true, false, false].repeat(4)
+        );
+
+        // The background must be representable by the input data type
+        assert!(find_boundaries("background: -1").is_err());
+        Ok(())
+    }
+}
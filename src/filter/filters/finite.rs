@@ -0,0 +1,254 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::progress::{Progress, ProgressCallback};
+
+use crate::filter::{
+    calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits, FilterArguments,
+    FilterCommonArguments, FilterStorage,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FiniteTest {
+    IsFinite,
+    IsNan,
+    IsInf,
+}
+
+impl FiniteTest {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::IsFinite => "isfinite",
+            Self::IsNan => "isnan",
+            Self::IsInf => "isinf",
+        }
+    }
+
+    #[inline]
+    pub fn test(&self, value: f64) -> bool {
+        match self {
+            Self::IsFinite => value.is_finite(),
+            Self::IsNan => value.is_nan(),
+            Self::IsInf => value.is_infinite(),
+        }
+    }
+}
+
+macro_rules! finite_arguments {
+    ( $name:ident, $test:ident ) => {
+        #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+        pub struct $name {}
+
+        impl FilterArguments for $name {
+            fn name(&self) -> String {
+                FiniteTest::$test.name().to_string()
+            }
+
+            fn init(
+                &self,
+                common_args: &FilterCommonArguments,
+            ) -> Result<Box<dyn FilterTraits>, FilterError> {
+                Ok(Box::new(Finite::new(
+                    FiniteTest::$test,
+                    *common_args.chunk_limit(),
+                )))
+            }
+        }
+    };
+}
+
+finite_arguments!(IsFiniteArguments, IsFinite);
+finite_arguments!(IsNanArguments, IsNan);
+finite_arguments!(IsInfArguments, IsInf);
+
+pub struct Finite {
+    test: FiniteTest,
+    chunk_limit: Option<usize>,
+}
+
+impl Finite {
+    pub fn new(test: FiniteTest, chunk_limit: Option<usize>) -> Self {
+        Self { test, chunk_limit }
+    }
+
+    pub fn apply_elements<TIn, TOut>(&self, input_elements: &[TIn]) -> Vec<TOut>
+    where
+        TIn: Copy + Send + Sync + AsPrimitive<f64>,
+        TOut: Send + Sync + Copy + From<bool> + 'static,
+    {
+        input_elements
+            .par_iter()
+            .map(|value| TOut::from(self.test.test(value.as_())))
+            .collect::<Vec<TOut>>()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Copy + Send + Sync + AsPrimitive<f64>,
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let input_elements =
+            progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_output_subset))?;
+
+        let output_elements =
+            progress.process(|| self.apply_elements::<TIn, TOut>(&input_elements));
+        drop(input_elements);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &output_elements)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Finite {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            _ => Err(UnsupportedDataTypeError::from(
+                chunk_input.data_type().to_string(),
+            ))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Bool | DataType::UInt8 => {}
+            _ => Err(UnsupportedDataTypeError::from(
+                chunk_output.data_type().to_string(),
+            ))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::Bool, FillValue::from(false)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_input {
+                    ( $type_out:ty, [$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type_in => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(
+                                DataType::$data_type_out => {
+                                    apply_input!($type_out, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([(Bool, bool), (UInt8, u8)])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finite_elements() {
+        let elements = [1.0f32, f32::NAN, f32::INFINITY, f32::NEG_INFINITY, -0.0];
+        assert_eq!(
+            Finite::new(FiniteTest::IsFinite, None).apply_elements::<f32, bool>(&elements),
+            vec![true, false, false, false, true]
+        );
+        assert_eq!(
+            Finite::new(FiniteTest::IsNan, None).apply_elements::<f32, u8>(&elements),
+            vec![0, 1, 0, 0, 0]
+        );
+        assert_eq!(
+            Finite::new(FiniteTest::IsInf, None).apply_elements::<f32, bool>(&elements),
+            vec![false, false, true, true, false]
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use clap::Parser;
+use ndarray::{ArrayD, Axis};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct FlipArguments {
+    /// The axes to reverse, comma delimited.
+    #[arg(required = true, value_delimiter = ',')]
+    pub axes: Vec<usize>,
+}
+
+impl FilterArguments for FlipArguments {
+    fn name(&self) -> String {
+        "flip".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Flip::new(
+            self.axes.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Flip {
+    axes: Vec<usize>,
+    chunk_limit: Option<usize>,
+}
+
+impl Flip {
+    pub fn new(axes: Vec<usize>, chunk_limit: Option<usize>) -> Self {
+        Self { axes, chunk_limit }
+    }
+
+    /// Returns the input subset mirroring `subset_output` along `axes` in an array of shape `array_shape`.
+    pub fn input_subset(
+        subset_output: &ArraySubset,
+        array_shape: &[u64],
+        axes: &[usize],
+    ) -> ArraySubset {
+        let start = itertools::izip!(
+            0..,
+            subset_output.start(),
+            subset_output.end_exc(),
+            array_shape
+        )
+        .map(|(axis, &start, end, &len)| {
+            if axes.contains(&axis) {
+                len - end
+            } else {
+                start
+            }
+        })
+        .collect::<Vec<_>>();
+        ArraySubset::new_with_start_shape(start, subset_output.shape().to_vec()).unwrap()
+    }
+
+    /// Reverse `input` along `axes`, returning an array in standard layout.
+    pub fn apply_ndarray<TIn, TOut>(mut input: ArrayD<TIn>, axes: &[usize]) -> ArrayD<TOut>
+    where
+        TIn: Copy + AsPrimitive<TOut>,
+        TOut: Copy + 'static,
+    {
+        for &axis in axes {
+            input.invert_axis(Axis(axis));
+        }
+        // Iterate in logical order, so the output is in standard layout
+        ArrayD::from_shape_vec(input.shape(), input.iter().map(|v| v.as_()).collect()).unwrap()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Copy + 'static,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_input = Self::input_subset(&subset_output, input.shape(), &self.axes);
+
+        let input_array =
+            progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+
+        let output_array =
+            progress.process(|| Self::apply_ndarray::<TIn, TOut>(input_array, &self.axes));
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Flip {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        for (i, axis) in self.axes.iter().enumerate() {
+            if *axis >= dimensionality {
+                return Err(FilterError::InvalidParameters(format!(
+                    "flip axis {axis} is out of bounds for an input with dimensionality {dimensionality}"
+                )));
+            } else if self.axes[..i].contains(axis) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "flip axis {axis} is repeated"
+                )));
+            }
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input region of an output chunk is the mirrored output chunk
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flip_axes() {
+        // The last chunk of a bounded chunk grid maps to the start of the input
+        let subset_output = ArraySubset::new_with_ranges(&[8..10, 4..8]);
+        assert_eq!(
+            Flip::input_subset(&subset_output, &[10, 8], &[0]),
+            ArraySubset::new_with_ranges(&[0..2, 4..8])
+        );
+        assert_eq!(
+            Flip::input_subset(&subset_output, &[10, 8], &[0, 1]),
+            ArraySubset::new_with_ranges(&[0..2, 0..4])
+        );
+
+        let input = ndarray::array![[1u8, 2, 3], [4, 5, 6]].into_dyn();
+        let output = Flip::apply_ndarray::<u8, i16>(input.clone(), &[1]);
+        assert_eq!(output, ndarray::array![[3i16, 2, 1], [6, 5, 4]].into_dyn());
+        assert!(output.is_standard_layout());
+        let output = Flip::apply_ndarray::<u8, u8>(input, &[0, 1]);
+        assert_eq!(output, ndarray::array![[6u8, 5, 4], [3, 2, 1]].into_dyn());
+    }
+}
@@ -7,13 +7,14 @@ use serde::{Deserialize, Serialize};
 use zarrs::{
     array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
         calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        kernel::apply_1d_kernel, ArraySubsetOverlap, FilterArguments, FilterCommonArguments,
+        gpu_correlate_axes, kernel::apply_1d_kernel, num_elements_haloed,
+        retrieve_chunk_haloed_ndarray, Correlate1d, FilterArguments, FilterCommonArguments,
+        FilterStorage,
     },
     progress::{Progress, ProgressCallback},
 };
@@ -71,8 +72,8 @@ impl Gaussian {
 
     pub fn apply_chunk<TIn, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
@@ -81,12 +82,13 @@ impl Gaussian {
         TOut: Element + Send + Sync + Copy + 'static,
         f32: AsPrimitive<TOut>,
     {
-        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
-        let subset_overlap =
-            ArraySubsetOverlap::new(input.shape(), &subset_output, &self.kernel_half_size);
-
-        let input_array = progress
-            .read(|| input.retrieve_array_subset_ndarray::<TIn>(subset_overlap.subset_input()))?;
+        let (subset_output, subset_overlap, input_array) = retrieve_chunk_haloed_ndarray::<TIn>(
+            input,
+            output,
+            chunk_indices,
+            &self.kernel_half_size,
+            progress,
+        )?;
 
         let output_array = progress.process(|| {
             let input_array = input_array.mapv(|x| x.as_()); // par?
@@ -107,6 +109,18 @@ impl Gaussian {
     }
 
     pub fn apply_ndarray(&self, mut input: ndarray::ArrayD<f32>) -> ndarray::ArrayD<f32> {
+        let passes = (0..input.ndim())
+            .map(|dim| Correlate1d {
+                axis: dim,
+                kernel: self.kernel[dim].to_vec(),
+                offset: self.kernel[dim].len() / 2,
+                output_len: input.shape()[dim],
+            })
+            .collect_vec();
+        if let Some(gaussian) = gpu_correlate_axes(&input, &passes) {
+            return gaussian;
+        }
+
         let mut gaussian = ArrayD::<f32>::zeros(input.shape());
         for dim in 0..input.ndim() {
             apply_1d_kernel(dim, &self.kernel[dim], &input, &mut gaussian);
@@ -145,20 +159,16 @@ impl FilterTraits for Gaussian {
         Ok(())
     }
 
+    fn halo(&self, _dimensionality: usize) -> Option<Vec<u64>> {
+        Some(self.kernel_half_size.clone())
+    }
+
     fn memory_per_chunk(
         &self,
         chunk_input: &zarrs::array::ChunkRepresentation,
         chunk_output: &zarrs::array::ChunkRepresentation,
     ) -> usize {
-        let num_input_elements = usize::try_from(
-            chunk_output
-                .shape()
-                .iter()
-                .zip(&self.kernel_half_size)
-                .map(|(s, kernel_half_size)| s.get() + kernel_half_size * 2)
-                .product::<u64>(),
-        )
-        .unwrap();
+        let num_input_elements = num_elements_haloed(chunk_output.shape(), &self.kernel_half_size);
         let num_output_elements = chunk_output.num_elements_usize();
         num_input_elements
             * (chunk_input.data_type().fixed_size().unwrap() + core::mem::size_of::<f32>() * 2)
@@ -168,8 +178,8 @@ impl FilterTraits for Gaussian {
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
@@ -248,7 +258,10 @@ impl FilterTraits for Gaussian {
     }
 }
 
-fn create_sampled_gaussian_kernel(sigma: f32, kernel_half_size: u64) -> ndarray::Array1<f32> {
+pub(crate) fn create_sampled_gaussian_kernel(
+    sigma: f32,
+    kernel_half_size: u64,
+) -> ndarray::Array1<f32> {
     if sigma == 0.0 {
         ndarray::Array1::<f32>::from_vec(vec![1.0])
     } else {
@@ -270,13 +283,13 @@ mod tests {
     use crate::progress::ProgressStats;
 
     use super::*;
-    use std::error::Error;
+    use std::{error::Error, sync::Arc};
     use zarrs::{array::ArrayBuilder, array_subset::ArraySubset, storage::store::FilesystemStore};
 
     #[test]
     fn gaussian() -> Result<(), Box<dyn Error>> {
         let path = tempfile::TempDir::new()?;
-        let store = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let array = ArrayBuilder::new(
             vec![4, 4],
             DataType::Float32,
@@ -296,7 +309,7 @@ mod tests {
         println!("{elements:?}");
 
         let path = tempfile::TempDir::new()?;
-        let store: FilesystemStore = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let mut array_output = array.builder().build(store.into(), "/")?;
         let progress_callback = |_stats: ProgressStats| {};
         Gaussian::new(vec![1.0; 2], vec![3; 2], None).apply(
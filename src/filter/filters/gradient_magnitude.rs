@@ -6,7 +6,6 @@ use serde::{Deserialize, Serialize};
 use zarrs::{
     array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
@@ -15,10 +14,8 @@ use crate::{
 };
 
 use crate::filter::{
-    filter_error::FilterError,
-    filter_traits::FilterTraits,
-    kernel::{apply_1d_difference_operator, apply_1d_triangle_filter},
-    FilterArguments, FilterCommonArguments,
+    filter_error::FilterError, filter_traits::FilterTraits, kernel::apply_sobel_operator,
+    FilterArguments, FilterCommonArguments, FilterStorage,
 };
 
 #[derive(Debug, Clone, Parser, Serialize, Deserialize, Default)]
@@ -48,8 +45,8 @@ impl GradientMagnitude {
 
     pub fn apply_chunk<TIn, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
@@ -88,25 +85,12 @@ impl GradientMagnitude {
     }
 
     pub fn apply_ndarray(&self, input: &ndarray::ArrayD<f32>) -> ndarray::ArrayD<f32> {
-        let mut staging_in = ArrayD::<f32>::zeros(input.shape());
-        let mut staging_out = ArrayD::<f32>::zeros(input.shape());
         let mut gradient_magnitude = ArrayD::<f32>::zeros(input.shape());
 
         for axis in 0..input.ndim() {
-            staging_in.assign(input);
-            for i in 0..input.ndim() {
-                if i == axis {
-                    apply_1d_difference_operator(i, &staging_in, &mut staging_out);
-                } else {
-                    apply_1d_triangle_filter(i, &staging_in, &mut staging_out);
-                }
-                if i != input.ndim() - 1 {
-                    std::mem::swap(&mut staging_in, &mut staging_out);
-                }
-            }
-
+            let sobel = apply_sobel_operator(axis, input);
             ndarray::Zip::from(&mut gradient_magnitude)
-                .and(&staging_out)
+                .and(&sobel)
                 .par_for_each(|g, &s| *g += s * s);
         }
         gradient_magnitude.map_inplace(|x| *x = x.sqrt());
@@ -164,8 +148,8 @@ impl FilterTraits for GradientMagnitude {
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
@@ -247,13 +231,13 @@ mod tests {
     use crate::progress::ProgressStats;
 
     use super::*;
-    use std::error::Error;
+    use std::{error::Error, sync::Arc};
     use zarrs::{array::ArrayBuilder, array_subset::ArraySubset, storage::store::FilesystemStore};
 
     #[test]
     fn gradients() -> Result<(), Box<dyn Error>> {
         let path = tempfile::TempDir::new()?;
-        let store = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let array = ArrayBuilder::new(
             vec![4, 4],
             DataType::Float32,
@@ -273,7 +257,7 @@ mod tests {
         println!("{elements:?}");
 
         let path = tempfile::TempDir::new()?;
-        let store: FilesystemStore = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let mut array_output = array.builder().build(store.into(), "/")?;
         let progress_callback = |_stats: ProgressStats| {};
         GradientMagnitude::new(None).apply(
@@ -7,14 +7,15 @@ use serde::{Deserialize, Serialize};
 use zarrs::{
     array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::filter::filters::summed_area_table::{summed_area_table, summed_area_table_mean};
 use crate::{
     filter::{
         calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        ArraySubsetOverlap, FilterArguments, FilterCommonArguments,
+        path_or_identifier::parse_path_or_identifier, retrieve_array_subset_ndarray_f64,
+        ArraySubsetOverlap, FilterArguments, FilterCommonArguments, FilterStorage,
+        PathOrIdentifier,
     },
     progress::{Progress, ProgressCallback},
 };
@@ -27,6 +28,12 @@ pub struct GuidedFilterArguments {
     /// Guided filter "radius".
     #[arg(required = true)]
     radius: u8,
+    /// Path to a zarr array to use as guidance.
+    ///
+    /// Must have the same shape as the input. The input guides itself if omitted.
+    #[arg(long, value_parser = parse_path_or_identifier)]
+    #[serde(default)]
+    guidance: Option<PathOrIdentifier>,
 }
 
 impl FilterArguments for GuidedFilterArguments {
@@ -34,6 +41,10 @@ impl FilterArguments for GuidedFilterArguments {
         "guided_filter".to_string()
     }
 
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        self.guidance.iter().cloned().collect()
+    }
+
     fn init(
         &self,
         common_args: &FilterCommonArguments,
@@ -71,8 +82,9 @@ impl GuidedFilter {
 
     pub fn apply_chunk<TIn, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        guidance: Option<&Array<FilterStorage>>,
+        output: &Array<FilterStorage>,
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
@@ -89,12 +101,24 @@ impl GuidedFilter {
             &vec![(self.radius * 2) as u64; input.dimensionality()],
         );
 
-        let input_array = progress
-            .read(|| input.retrieve_array_subset_ndarray::<TIn>(subset_overlap.subset_input()))?;
+        let (input_array, guidance_array) = progress.read(|| {
+            let input_array =
+                input.retrieve_array_subset_ndarray::<TIn>(subset_overlap.subset_input())?;
+            let guidance_array = guidance
+                .map(|guidance| {
+                    retrieve_array_subset_ndarray_f64(guidance, subset_overlap.subset_input())
+                })
+                .transpose()?;
+            Ok::<_, FilterError>((input_array, guidance_array))
+        })?;
 
         let output_array = progress.process(|| {
             let input_array = input_array.mapv(|x| x.as_()); // par?
-            let output_array = self.apply_ndarray(input_array);
+            let output_array = if let Some(guidance_array) = guidance_array {
+                self.apply_ndarray_guided(guidance_array.mapv(|x| x as f32), input_array)
+            } else {
+                self.apply_ndarray(input_array)
+            };
             let output_array = subset_overlap.extract_subset(&output_array);
             Ok::<_, FilterError>(output_array.mapv(|x| x.as_())) // par?
         })?;
@@ -159,6 +183,46 @@ impl GuidedFilter {
         ndarray::ArrayD::from_shape_vec(sat.shape(), v_i).unwrap()
     }
 
+    /// Apply the guided filter to `p` with a separate guidance `i` of the same shape.
+    pub fn apply_ndarray_guided(
+        &self,
+        i: ndarray::ArrayD<f32>,
+        p: ndarray::ArrayD<f32>,
+    ) -> ndarray::ArrayD<f32> {
+        assert_eq!(i.shape(), p.shape());
+        let mut sat = ndarray::ArrayD::<f64>::zeros(i.shape());
+        let mut mean = |v: &ndarray::ArrayD<f32>| {
+            summed_area_table(v, &mut sat);
+            self.sat_to_mean(&sat)
+        };
+
+        let mean_i = mean(&i);
+        let mean_p = mean(&p);
+        let mean_ip = mean(&ndarray::Zip::from(&i).and(&p).par_map_collect(|i, p| i * p));
+        let mean_ii = mean(&i.mapv(|i| i * i));
+        drop(p);
+
+        let mut a = mean_ip;
+        let mut b = mean_p;
+        ndarray::par_azip!((a in &mut a, b in &mut b, &mean_i in &mean_i, &mean_ii in &mean_ii) {
+            let cov_ip = *a - mean_i * *b;
+            let var_i = mean_ii - mean_i * mean_i;
+            *a = cov_ip / (var_i + self.epsilon);
+            *b -= *a * mean_i;
+        });
+        drop(mean_i);
+        drop(mean_ii);
+
+        let mean_a = mean(&a);
+        drop(a);
+        let mean_b = mean(&b);
+        drop(b);
+        ndarray::Zip::from(&i)
+            .and(&mean_a)
+            .and(&mean_b)
+            .par_map_collect(|i, a, b| a * i + b)
+    }
+
     fn get_block(&self, indices: &[u64], shape: &[usize]) -> (Vec<usize>, Vec<usize>) {
         let p0: Vec<usize> = std::iter::zip(indices, shape)
             .map(|(indices, shape)| {
@@ -193,50 +257,12 @@ impl GuidedFilter {
             .collect();
         ndarray::ArrayD::from_shape_vec(sat.shape(), mean).unwrap()
     }
-}
 
-impl FilterTraits for GuidedFilter {
-    fn is_compatible(
+    fn apply_guided(
         &self,
-        chunk_input: &zarrs::array::ChunkRepresentation,
-        chunk_output: &zarrs::array::ChunkRepresentation,
-    ) -> Result<(), FilterError> {
-        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
-            match data_type {
-                DataType::Bool
-                | DataType::Int8
-                | DataType::Int16
-                | DataType::Int32
-                | DataType::Int64
-                | DataType::UInt8
-                | DataType::UInt16
-                | DataType::UInt32
-                | DataType::UInt64
-                | DataType::Float16
-                | DataType::Float32
-                | DataType::Float64
-                | DataType::BFloat16 => {}
-                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
-            };
-        }
-        Ok(())
-    }
-
-    fn memory_per_chunk(
-        &self,
-        chunk_input: &zarrs::array::ChunkRepresentation,
-        chunk_output: &zarrs::array::ChunkRepresentation,
-    ) -> usize {
-        chunk_input.fixed_element_size().unwrap()
-            + chunk_output.fixed_element_size().unwrap()
-            + chunk_output.num_elements_usize()
-                * (core::mem::size_of::<f64>() + core::mem::size_of::<f32>() * 2)
-    }
-
-    fn apply(
-        &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        guidance: Option<&Array<FilterStorage>>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
@@ -262,7 +288,7 @@ impl FilterTraits for GuidedFilter {
                 macro_rules! apply_output {
                     ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
                         match output.data_type() {
-                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(&input, &output, &chunk_indices, &progress) } ,)*
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(&input, guidance, &output, &chunk_indices, &progress) } ,)*
                             _ => panic!()
                         }
                     };
@@ -315,18 +341,91 @@ impl FilterTraits for GuidedFilter {
     }
 }
 
+impl FilterTraits for GuidedFilter {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap()
+            + chunk_output.fixed_element_size().unwrap()
+            + chunk_output.num_elements_usize()
+                * (core::mem::size_of::<f64>() + core::mem::size_of::<f32>() * 4)
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        self.apply_guided(input, None, output, progress_callback)
+    }
+
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        match *inputs {
+            [input] => self.apply_guided(input, None, output, progress_callback),
+            [input, guidance] => {
+                if guidance.shape() != input.shape() {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "guidance shape {:?} does not match input shape {:?}",
+                        guidance.shape(),
+                        input.shape()
+                    )));
+                }
+                self.is_compatible_arrays(inputs, output)?;
+                self.apply_guided(input, Some(guidance), output, progress_callback)
+            }
+            _ => Err(FilterError::InvalidParameters(
+                "guided_filter requires an input and an optional guidance input".to_string(),
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::progress::ProgressStats;
 
     use super::*;
-    use std::error::Error;
+    use std::{error::Error, sync::Arc};
     use zarrs::{array::ArrayBuilder, array_subset::ArraySubset, storage::store::FilesystemStore};
 
     #[test]
     fn guided_filter() -> Result<(), Box<dyn Error>> {
         let path = tempfile::TempDir::new()?;
-        let store = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let array = ArrayBuilder::new(
             vec![4, 4],
             DataType::Float32,
@@ -346,7 +445,7 @@ mod tests {
         println!("{elements:?}");
 
         let path = tempfile::TempDir::new()?;
-        let store: FilesystemStore = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let mut array_output = array.builder().build(store.into(), "/")?;
         let progress_callback = |_stats: ProgressStats| {};
         GuidedFilter::new(1.0, 2, None).apply(
@@ -368,4 +467,30 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn guided_filter_guidance() {
+        let p: ndarray::ArrayD<f32> = ndarray::array![
+            [0.0, 1.0, 2.0, 3.0],
+            [1.0, 2.0, 3.0, 4.0],
+            [2.0, 3.0, 4.0, 5.0],
+            [3.0, 4.0, 5.0, 6.0]
+        ]
+        .into_dyn();
+        let filter = GuidedFilter::new(1e-6, 1, None);
+
+        // The input is preserved when it is a linear function of the guidance
+        let i = p.mapv(|p| 2.0 * p + 1.0);
+        let q = filter.apply_ndarray_guided(i, p.clone());
+        approx::assert_abs_diff_eq!(q, p, epsilon = 1e-2);
+
+        // Constant guidance reduces to a double box filter
+        let i = ndarray::ArrayD::<f32>::from_elem(p.shape(), 1.0);
+        let q = filter.apply_ndarray_guided(i, p.clone());
+        let mut sat = ndarray::ArrayD::<f64>::zeros(p.shape());
+        summed_area_table(&p, &mut sat);
+        let mean_p = filter.sat_to_mean(&sat);
+        summed_area_table(&mean_p, &mut sat);
+        approx::assert_abs_diff_eq!(q, filter.sat_to_mean(&sat), epsilon = 1e-5);
+    }
 }
@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+
+use clap::Parser;
+use ndarray::{ArrayD, Dimension};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, ElementOwned, FillValue},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_ndarray_f64, ArraySubsetOverlap, FilterArguments,
+        FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connectivity {
+    /// Elements sharing a face are connected.
+    #[default]
+    Face,
+    /// Elements sharing a face, edge, or corner are connected.
+    Full,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct LabelArguments {
+    /// The connectivity of foreground (non-zero) elements.
+    #[arg(long, value_enum, default_value_t = Connectivity::Face)]
+    #[serde(default)]
+    pub connectivity: Connectivity,
+}
+
+impl FilterArguments for LabelArguments {
+    fn name(&self) -> String {
+        "label".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Label::new(
+            self.connectivity,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// A disjoint-set forest where the root of a set is its smallest element.
+struct UnionFind(Vec<usize>);
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self((0..len).collect())
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.0[i] != i {
+            self.0[i] = self.0[self.0[i]];
+            i = self.0[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a < b {
+            self.0[b] = a;
+        } else {
+            self.0[a] = b;
+        }
+    }
+
+    /// Returns consecutive labels from 0 for each element, ordered by the root of each set, and the number of sets.
+    fn labels(&mut self) -> (Vec<usize>, usize) {
+        let mut labels = vec![0; self.0.len()];
+        let mut num_labels = 0;
+        for i in 0..self.0.len() {
+            let root = self.find(i);
+            if root == i {
+                labels[i] = num_labels;
+                num_labels += 1;
+            } else {
+                labels[i] = labels[root];
+            }
+        }
+        (labels, num_labels)
+    }
+}
+
+/// Returns the offsets of the neighbours of an element for a `connectivity`.
+pub fn neighbour_offsets(dimensionality: usize, connectivity: Connectivity) -> Vec<Vec<isize>> {
+    ndarray::indices(vec![3; dimensionality])
+        .into_iter()
+        .map(|offset| {
+            offset
+                .slice()
+                .iter()
+                .map(|&o| o as isize - 1)
+                .collect::<Vec<_>>()
+        })
+        .filter(|offset| {
+            let num_nonzero = offset.iter().filter(|&&o| o != 0).count();
+            num_nonzero > 0 && (connectivity == Connectivity::Full || num_nonzero == 1)
+        })
+        .collect()
+}
+
+/// Returns the neighbour offsets preceding an element in C order for a `connectivity`.
+fn preceding_neighbour_offsets(
+    dimensionality: usize,
+    connectivity: Connectivity,
+) -> Vec<Vec<isize>> {
+    neighbour_offsets(dimensionality, connectivity)
+        .into_iter()
+        .filter(|offset| offset.iter().find(|&&o| o != 0) == Some(&-1))
+        .collect()
+}
+
+/// Returns the index of `index` + `offset` if it is within `shape`.
+pub fn offset_index(index: &[usize], offset: &[isize], shape: &[usize]) -> Option<Vec<usize>> {
+    itertools::izip!(index, offset, shape)
+        .map(|(&i, &o, &len)| {
+            let i = i.checked_add_signed(o)?;
+            (i < len).then_some(i)
+        })
+        .collect()
+}
+
+/// Returns the C order linear index of `index` within `shape`.
+pub fn ravel_index(index: &[usize], shape: &[usize]) -> usize {
+    std::iter::zip(index, shape).fold(0, |acc, (i, len)| acc * len + i)
+}
+
+/// Label the connected components of `foreground` from 1 in C order of their first element, where 0 is the background.
+///
+/// Returns the labels and the number of components.
+pub fn label_ndarray(foreground: &ArrayD<bool>, connectivity: Connectivity) -> (ArrayD<u64>, u64) {
+    let shape = foreground.shape();
+    let offsets = preceding_neighbour_offsets(foreground.ndim(), connectivity);
+    let foreground = foreground.as_standard_layout();
+    let foreground = foreground.as_slice().unwrap();
+
+    let mut union_find = UnionFind::new(foreground.len());
+    for (i, index) in ndarray::indices(shape).into_iter().enumerate() {
+        if foreground[i] {
+            for offset in &offsets {
+                if let Some(neighbour) = offset_index(index.slice(), offset, shape) {
+                    let j = ravel_index(&neighbour, shape);
+                    if foreground[j] {
+                        union_find.union(i, j);
+                    }
+                }
+            }
+        }
+    }
+
+    // Background elements are their own roots, so relabel the foreground roots consecutively
+    let mut labels = vec![0u64; foreground.len()];
+    let mut num_labels = 0;
+    for i in 0..foreground.len() {
+        if foreground[i] {
+            let root = union_find.find(i);
+            if root == i {
+                num_labels += 1;
+                labels[i] = num_labels;
+            } else {
+                labels[i] = labels[root];
+            }
+        }
+    }
+    (ArrayD::from_shape_vec(shape, labels).unwrap(), num_labels)
+}
+
+pub struct Label {
+    connectivity: Connectivity,
+    chunk_limit: Option<usize>,
+}
+
+impl Label {
+    pub fn new(connectivity: Connectivity, chunk_limit: Option<usize>) -> Self {
+        Self {
+            connectivity,
+            chunk_limit,
+        }
+    }
+
+    /// Label the components of a chunk independently of other chunks, returning the number of components.
+    pub fn apply_chunk_local<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<u64, FilterError>
+    where
+        TOut: ElementOwned + Copy + 'static,
+        u64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let input_array =
+            progress.read(|| retrieve_array_subset_ndarray_f64(input, &subset_output))?;
+
+        let (output_array, num_labels) = progress.process(|| {
+            let foreground = input_array.mapv(|x| x != 0.0 && !x.is_nan());
+            let (labels, num_labels) = label_ndarray(&foreground, self.connectivity);
+            (labels.mapv(|x| x.as_()), num_labels)
+        });
+        drop(input_array);
+        if num_labels > max_label(output.data_type()) {
+            return Err(FilterError::Other(format!(
+                "the number of labels in chunk {chunk_indices:?} exceeds the maximum of the output data type {}",
+                output.data_type()
+            )));
+        }
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(num_labels)
+    }
+
+    /// Returns the equivalences between global labels of connected elements on either side of the borders of a chunk.
+    pub fn chunk_equivalences<TOut>(
+        &self,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        offsets: &HashMap<Vec<u64>, u64>,
+        progress: &Progress,
+    ) -> Result<Vec<(u64, u64)>, FilterError>
+    where
+        TOut: ElementOwned + Copy + AsPrimitive<u64>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(
+            output.shape(),
+            &subset_output,
+            &vec![1; output.dimensionality()],
+        );
+        let subset_input = subset_overlap.subset_input();
+        let labels =
+            progress.read(|| output.retrieve_array_subset_ndarray::<TOut>(subset_input))?;
+
+        let equivalences = progress.process(|| {
+            let shape = labels.shape();
+            let neighbour_offsets =
+                preceding_neighbour_offsets(output.dimensionality(), self.connectivity);
+            let chunk_start = std::iter::zip(subset_output.start(), subset_input.start())
+                .map(|(output, input)| usize::try_from(output - input).unwrap())
+                .collect::<Vec<_>>();
+            let chunk_end = std::iter::zip(&chunk_start, subset_output.shape())
+                .map(|(start, len)| start + usize::try_from(*len).unwrap())
+                .collect::<Vec<_>>();
+            let in_chunk = |index: &[usize]| {
+                itertools::izip!(index, &chunk_start, &chunk_end)
+                    .all(|(i, start, end)| start <= i && i < end)
+            };
+            let global_label = |index: &[usize], label: u64| {
+                let array_index = std::iter::zip(index, subset_input.start())
+                    .map(|(&i, start)| i as u64 + start)
+                    .collect::<Vec<_>>();
+                let chunk_indices = output
+                    .chunk_grid()
+                    .chunk_indices(&array_index, output.shape())
+                    .unwrap()
+                    .unwrap();
+                offsets[&chunk_indices] + label - 1
+            };
+
+            let mut equivalences = Vec::new();
+            for index in ndarray::indices(subset_output.shape_usize()) {
+                let index = std::iter::zip(index.slice(), &chunk_start)
+                    .map(|(i, start)| i + start)
+                    .collect::<Vec<_>>();
+                let on_border = itertools::izip!(&index, &chunk_start, &chunk_end)
+                    .any(|(&i, &start, &end)| i == start || i + 1 == end);
+                let label: u64 = labels[index.as_slice()].as_();
+                if !on_border || label == 0 {
+                    continue;
+                }
+                for offset in &neighbour_offsets {
+                    let Some(neighbour) = offset_index(&index, offset, shape) else {
+                        continue;
+                    };
+                    let neighbour_label: u64 = labels[neighbour.as_slice()].as_();
+                    if neighbour_label != 0 && !in_chunk(&neighbour) {
+                        equivalences.push((
+                            global_label(&index, label),
+                            global_label(&neighbour, neighbour_label),
+                        ));
+                    }
+                }
+            }
+            equivalences
+        });
+
+        progress.next();
+        Ok(equivalences)
+    }
+
+    /// Replace the local labels of a chunk with final labels.
+    pub fn apply_chunk_relabel<TOut>(
+        &self,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        labels: &[usize],
+        offset: u64,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: ElementOwned + Copy + 'static + AsPrimitive<u64>,
+        u64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let output_array =
+            progress.read(|| output.retrieve_array_subset_ndarray::<TOut>(&subset_output))?;
+
+        let output_array = progress.process(|| {
+            output_array.mapv(|label| {
+                let label: u64 = label.as_();
+                if label == 0 {
+                    0u64.as_()
+                } else {
+                    let label = labels[usize::try_from(offset + label - 1).unwrap()] as u64 + 1;
+                    label.as_()
+                }
+            })
+        });
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    fn apply_typed<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError>
+    where
+        TOut: ElementOwned + Copy + Send + Sync + 'static + AsPrimitive<u64>,
+        u64: AsPrimitive<TOut>,
+    {
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize() * 3, progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        // Label each chunk independently
+        let output_ref = &*output;
+        let indices = chunks.indices();
+        let mut num_labels_chunks: Vec<(Vec<u64>, u64)> =
+            rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                chunk_limit,
+                indices,
+                map,
+                |chunk_indices: Vec<u64>| {
+                    let num_labels = self.apply_chunk_local::<TOut>(
+                        input,
+                        output_ref,
+                        &chunk_indices,
+                        &progress,
+                    )?;
+                    Ok::<_, FilterError>((chunk_indices, num_labels))
+                }
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        num_labels_chunks.sort_unstable();
+        let mut num_labels_local = 0;
+        let offsets: HashMap<Vec<u64>, u64> = num_labels_chunks
+            .into_iter()
+            .map(|(chunk_indices, num_labels)| {
+                let offset = num_labels_local;
+                num_labels_local += num_labels;
+                (chunk_indices, offset)
+            })
+            .collect();
+
+        // Merge labels connected across chunk borders
+        let indices = chunks.indices();
+        let equivalences = rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            map,
+            |chunk_indices: Vec<u64>| {
+                self.chunk_equivalences::<TOut>(output_ref, &chunk_indices, &offsets, &progress)
+            }
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+        let mut union_find = UnionFind::new(usize::try_from(num_labels_local).unwrap());
+        for (a, b) in equivalences.into_iter().flatten() {
+            union_find.union(usize::try_from(a).unwrap(), usize::try_from(b).unwrap());
+        }
+        let (labels, num_labels) = union_find.labels();
+        drop(union_find);
+        if num_labels as u64 > max_label(output.data_type()) {
+            return Err(FilterError::Other(format!(
+                "the number of labels {num_labels} exceeds the maximum of the output data type {}",
+                output.data_type()
+            )));
+        }
+
+        // Write the final labels
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                self.apply_chunk_relabel::<TOut>(
+                    output_ref,
+                    &chunk_indices,
+                    &labels,
+                    offsets[&chunk_indices],
+                    &progress,
+                )
+            }
+        )?;
+
+        output
+            .attributes_mut()
+            .insert("num_labels".to_string(), num_labels.into());
+        Ok(())
+    }
+}
+
+/// The maximum label of an output data type.
+fn max_label(data_type: &DataType) -> u64 {
+    match data_type {
+        DataType::UInt32 => u64::from(u32::MAX),
+        _ => u64::MAX,
+    }
+}
+
+impl FilterTraits for Label {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::UInt32 | DataType::UInt64 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = usize::try_from(
+            chunk_input
+                .shape()
+                .iter()
+                .map(|s| s.get() + 2)
+                .product::<u64>(),
+        )
+        .unwrap();
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_output_elements
+            * (chunk_input.data_type().fixed_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + core::mem::size_of::<bool>()
+                + core::mem::size_of::<usize>()
+                + core::mem::size_of::<u64>() * 2)
+            + num_input_elements * chunk_output.data_type().fixed_size().unwrap()
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::UInt32, FillValue::from(0u32)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+        match output.data_type() {
+            DataType::UInt32 => self.apply_typed::<u32>(input, output, progress_callback),
+            DataType::UInt64 => self.apply_typed::<u64>(input, output, progress_callback),
+            _ => panic!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_connectivity() {
+        let foreground = ndarray::array![
+            [true, false, true],
+            [false, true, false],
+            [true, true, false]
+        ]
+        .into_dyn();
+        let (labels, num_labels) = label_ndarray(&foreground, Connectivity::Face);
+        assert_eq!(num_labels, 3);
+        assert_eq!(
+            labels,
+            ndarray::array![[1, 0, 2], [0, 3, 0], [3, 3, 0]].into_dyn()
+        );
+        let (labels, num_labels) = label_ndarray(&foreground, Connectivity::Full);
+        assert_eq!(num_labels, 1);
+        assert!(labels.iter().all(|&label| label <= 1));
+    }
+}
@@ -0,0 +1,288 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, FillValue},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        kernel::{convolve_1d_valid, pad_ndarray},
+        retrieve_array_subset_ndarray_f64, ArraySubsetOverlap, BorderMode, FilterArguments,
+        FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct LocalVarianceArguments {
+    /// Window radius per axis, comma delimited. The window is 2 x radius + 1.
+    #[arg(required = true, value_delimiter = ',')]
+    pub radius: Vec<u64>,
+    /// Output the local standard deviation rather than the local variance.
+    #[arg(long)]
+    #[serde(default)]
+    pub std: bool,
+}
+
+impl FilterArguments for LocalVarianceArguments {
+    fn name(&self) -> String {
+        "local_variance".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(LocalVariance::new(
+            self.radius.clone(),
+            self.std,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct LocalVariance {
+    radius: Vec<u64>,
+    std: bool,
+    chunk_limit: Option<usize>,
+}
+
+impl LocalVariance {
+    pub fn new(radius: Vec<u64>, std: bool, chunk_limit: Option<usize>) -> Self {
+        Self {
+            radius,
+            std,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the (population) variance, or standard deviation, of the finite values in the window of `radius` about each element.
+    ///
+    /// `pad` is the padding required for full windows at the boundaries of `input`, windows are truncated at the padding.
+    /// The output is shorter than `input` plus `pad` by 2 x `radius` along each axis.
+    /// The output is NaN where a window has no finite values.
+    pub fn apply_ndarray(
+        &self,
+        input: &ndarray::ArrayD<f64>,
+        pad: &[(usize, usize)],
+    ) -> ndarray::ArrayD<f64> {
+        // Shift by the mean to reduce cancellation when computing the variance
+        let (sum, count) = input
+            .iter()
+            .filter(|value| value.is_finite())
+            .fold((0.0, 0usize), |(sum, count), value| {
+                (sum + value, count + 1)
+            });
+        let shift = if count > 0 { sum / count as f64 } else { 0.0 };
+        let values = input.mapv(|value| {
+            if value.is_finite() {
+                value - shift
+            } else {
+                0.0
+            }
+        });
+        let squares = values.mapv(|value| value * value);
+        let counts = input.mapv(|value| if value.is_finite() { 1.0 } else { 0.0 });
+
+        let box_sum = |array: ndarray::ArrayD<f64>| {
+            let array = pad_ndarray(&array, pad, BorderMode::Constant, 0.0);
+            self.radius
+                .iter()
+                .enumerate()
+                .fold(array, |array, (axis, radius)| {
+                    let kernel = vec![1.0; usize::try_from(2 * radius + 1).unwrap()];
+                    convolve_1d_valid(axis, &kernel, &array)
+                })
+        };
+        let sums = box_sum(values);
+        let sums_squares = box_sum(squares);
+        let counts = box_sum(counts);
+
+        ndarray::Zip::from(&sums)
+            .and(&sums_squares)
+            .and(&counts)
+            .map_collect(|&sum, &sum_squares, &count| {
+                if count > 0.0 {
+                    let variance = ((sum_squares - sum * sum / count) / count).max(0.0);
+                    if self.std {
+                        variance.sqrt()
+                    } else {
+                        variance
+                    }
+                } else {
+                    f64::NAN
+                }
+            })
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(input.shape(), &subset_output, &self.radius);
+
+        let input_array = progress
+            .read(|| retrieve_array_subset_ndarray_f64(input, subset_overlap.subset_input()))?;
+
+        let output_array = progress.process(|| {
+            let output_array = self.apply_ndarray(
+                &input_array,
+                &subset_overlap.truncated_overlap(&self.radius),
+            );
+            output_array.mapv(|x| x.as_())
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for LocalVariance {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        if self.radius.len() != chunk_input.dimensionality() {
+            return Err(FilterError::InvalidParameters(format!(
+                "local_variance radius {:?} does not match the input dimensionality {}",
+                self.radius,
+                chunk_input.dimensionality()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = usize::try_from(
+            std::iter::zip(chunk_input.shape(), &self.radius)
+                .map(|(s, r)| s.get() + 2 * r)
+                .product::<u64>(),
+        )
+        .unwrap();
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_input_elements
+            * (chunk_input.data_type().fixed_size().unwrap() + core::mem::size_of::<f64>() * 6)
+            + num_output_elements
+                * (core::mem::size_of::<f64>() + chunk_output.data_type().fixed_size().unwrap())
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        match input.data_type() {
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16 => None,
+            _ => Some((DataType::Float32, FillValue::from(0.0f32))),
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_variance_window() {
+        let input = ndarray::array![[1.0, 2.0, 3.0], [4.0, f64::NAN, 6.0]].into_dyn();
+        let local_variance = LocalVariance::new(vec![0, 1], false, None);
+        let output = local_variance.apply_ndarray(&input, &[(0, 0), (1, 1)]);
+        // Windows truncated at the boundaries, NaN excluded
+        assert_eq!(output[[0, 0]], 0.25);
+        assert!((output[[0, 1]] - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(output[[1, 1]], 1.0);
+        let local_std = LocalVariance::new(vec![0, 0], true, None);
+        let output = local_std.apply_ndarray(&input, &[(0, 0), (0, 0)]);
+        assert_eq!(output[[0, 0]], 0.0);
+        assert!(output[[1, 1]].is_nan());
+    }
+}
@@ -0,0 +1,40 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::filter::{
+    filter_error::FilterError, filter_traits::FilterTraits, FilterArguments, FilterCommonArguments,
+};
+
+use super::reduce::{Reduce, ReduceOperator};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct MagnitudeArguments {
+    /// The channel axis holding the components, e.g. the axis of a 3-component gradient.
+    pub axis: usize,
+    /// The reduction over the components, the Euclidean norm by default.
+    #[arg(long, value_enum, default_value_t = ReduceOperator::Norm)]
+    #[serde(default = "default_operator")]
+    pub operator: ReduceOperator,
+}
+
+fn default_operator() -> ReduceOperator {
+    ReduceOperator::Norm
+}
+
+impl FilterArguments for MagnitudeArguments {
+    fn name(&self) -> String {
+        "magnitude".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        // The magnitude is a reduction over the channel axis
+        Ok(Box::new(Reduce::new(
+            self.operator,
+            vec![self.axis],
+            *common_args.chunk_limit(),
+        )))
+    }
+}
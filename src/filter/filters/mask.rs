@@ -0,0 +1,295 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        path_or_identifier::parse_path_or_identifier, FilterArguments, FilterCommonArguments,
+        FilterStorage, PathOrIdentifier,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct MaskArguments {
+    /// Path to the zarr array holding the mask.
+    ///
+    /// Must be a bool or uint8 array with the same shape as the input. Non-zero elements are true.
+    #[arg(value_parser = parse_path_or_identifier)]
+    pub mask: PathOrIdentifier,
+    /// Write the input where the mask is false rather than true.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub invert: bool,
+}
+
+impl FilterArguments for MaskArguments {
+    fn name(&self) -> String {
+        "mask".to_string()
+    }
+
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        vec![self.mask.clone()]
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Mask::new(self.invert, *common_args.chunk_limit())))
+    }
+}
+
+pub struct Mask {
+    invert: bool,
+    chunk_limit: Option<usize>,
+}
+
+impl Mask {
+    pub fn new(invert: bool, chunk_limit: Option<usize>) -> Self {
+        Self {
+            invert,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        mask: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        fill_value: TOut,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Copy + 'static,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let (elements, elements_mask) = progress.read(|| {
+            Ok::<_, FilterError>((
+                input.retrieve_array_subset_elements::<TIn>(&input_output_subset)?,
+                mask.retrieve_array_subset_elements::<u8>(&input_output_subset)?,
+            ))
+        })?;
+
+        let elements_out = progress
+            .process(|| self.apply_elements::<TIn, TOut>(&elements, &elements_mask, fill_value));
+        drop(elements);
+        drop(elements_mask);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    pub fn apply_elements<TIn, TOut>(
+        &self,
+        elements: &[TIn],
+        elements_mask: &[u8],
+        fill_value: TOut,
+    ) -> Vec<TOut>
+    where
+        TIn: Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Send + Sync + Copy + 'static,
+    {
+        elements
+            .into_par_iter()
+            .zip(elements_mask)
+            .map(|(value, mask)| {
+                if (*mask != 0) != self.invert {
+                    value.as_()
+                } else {
+                    fill_value
+                }
+            })
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for Mask {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input, mask, and output of a chunk
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<u8>()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn is_compatible_inputs(
+        &self,
+        chunk_inputs: &[zarrs::array::ChunkRepresentation],
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let [chunk_input, chunk_mask] = chunk_inputs else {
+            return Err(FilterError::InvalidParameters(
+                "mask requires an input and a mask".to_string(),
+            ));
+        };
+        self.is_compatible(chunk_input, chunk_output)?;
+        match chunk_mask.data_type() {
+            DataType::Bool | DataType::UInt8 => Ok(()),
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()).into()),
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        self.apply_inputs(&[input], output, progress_callback)
+    }
+
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let [input, mask] = *inputs else {
+            return Err(FilterError::InvalidParameters(
+                "mask requires an input and a mask".to_string(),
+            ));
+        };
+        assert_eq!(output.shape(), input.shape());
+        assert_eq!(mask.shape(), input.shape());
+        self.is_compatible_arrays(inputs, output)?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => {
+                                let fill_value: $type_out = bytemuck::pod_read_unaligned(output.fill_value().as_ne_bytes());
+                                self.apply_chunk::<$type_in, $type_out>(input, mask, output, &chunk_indices, fill_value, &progress)
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_elements() {
+        let elements = [1.5f32, 2.5, 3.5, 4.5];
+        let elements_mask = [1u8, 0, 255, 0];
+        assert_eq!(
+            Mask::new(false, None).apply_elements::<f32, f32>(&elements, &elements_mask, -1.0),
+            vec![1.5, -1.0, 3.5, -1.0]
+        );
+        assert_eq!(
+            Mask::new(true, None).apply_elements::<f32, i16>(&elements, &elements_mask, 0),
+            vec![0, 2, 0, 4]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::filter::{
+    filter_error::FilterError, filter_traits::FilterTraits, filters::rank::Rank, FilterArguments,
+    FilterCommonArguments,
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize, Default)]
+pub struct MedianArguments {
+    /// Window radius per axis, comma delimited. The window is 2 x radius + 1.
+    #[arg(required = true, value_delimiter = ',')]
+    pub radius: Vec<u64>,
+}
+
+impl FilterArguments for MedianArguments {
+    fn name(&self) -> String {
+        "median".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Rank::new(
+            50.0,
+            self.radius.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
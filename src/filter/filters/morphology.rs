@@ -0,0 +1,411 @@
+use clap::Parser;
+use ndarray::{ArrayD, Axis, Dimension, IxDyn, SliceInfo, SliceInfoElem};
+use num_traits::{AsPrimitive, Bounded};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        kernel::pad_ndarray, num_elements_haloed, retrieve_chunk_haloed_ndarray, BorderMode,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MorphologyOperation {
+    /// The minimum over the structuring element.
+    Erode,
+    /// The maximum over the structuring element.
+    Dilate,
+    /// Erosion followed by dilation.
+    Open,
+    /// Dilation followed by erosion.
+    Close,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StructuringElement {
+    /// A box (hyperrectangle).
+    #[default]
+    Box,
+    /// A ball (ellipsoid).
+    Ball,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct MorphologyArguments {
+    /// The morphological operation.
+    #[arg(value_enum)]
+    pub operation: MorphologyOperation,
+    /// Structuring element radius per axis, comma delimited. The structuring element is 2 x radius + 1.
+    #[arg(required = true, value_delimiter = ',')]
+    pub radius: Vec<u64>,
+    /// The shape of the structuring element.
+    #[arg(long, value_enum, default_value_t = StructuringElement::Box)]
+    #[serde(default)]
+    pub structuring_element: StructuringElement,
+}
+
+impl FilterArguments for MorphologyArguments {
+    fn name(&self) -> String {
+        "morphology".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Morphology::new(
+            self.operation,
+            self.radius.clone(),
+            self.structuring_element,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the minimum (`erode`) or maximum (not `erode`) over a structuring element about each element of `input`, without padding.
+///
+/// The output is shorter than the input by 2 x `radius` along each axis.
+pub fn erode_dilate_valid<T>(
+    input: &ArrayD<T>,
+    radius: &[usize],
+    structuring_element: StructuringElement,
+    erode: bool,
+) -> ArrayD<T>
+where
+    T: Copy + PartialOrd + Send + Sync,
+{
+    let select = |a: &mut T, b: &T| {
+        if (erode && *b < *a) || (!erode && *b > *a) {
+            *a = *b;
+        }
+    };
+    match structuring_element {
+        StructuringElement::Box => {
+            let mut array = input.clone();
+            for (axis, &radius) in radius.iter().enumerate() {
+                let len = array.len_of(Axis(axis)) - 2 * radius;
+                let mut output = array.slice_axis(Axis(axis), (0..len).into()).to_owned();
+                for offset in 1..=2 * radius {
+                    ndarray::Zip::from(&mut output)
+                        .and(&array.slice_axis(Axis(axis), (offset..offset + len).into()))
+                        .par_for_each(select);
+                }
+                array = output;
+            }
+            array
+        }
+        StructuringElement::Ball => {
+            let shape = std::iter::zip(input.shape(), radius)
+                .map(|(len, radius)| len - 2 * radius)
+                .collect::<Vec<_>>();
+            let slice = |offset: &[usize]| {
+                let slices = std::iter::zip(offset, &shape)
+                    .map(|(&offset, &len)| SliceInfoElem::from(offset..offset + len))
+                    .collect::<Vec<_>>();
+                input.slice(SliceInfo::<_, IxDyn, IxDyn>::try_from(slices).unwrap())
+            };
+            let mut output = slice(radius).to_owned();
+            let window_shape = radius.iter().map(|r| 2 * r + 1).collect::<Vec<_>>();
+            for offset in ndarray::indices(window_shape) {
+                let in_ball = std::iter::zip(offset.slice(), radius)
+                    .map(|(&offset, &radius)| {
+                        if radius == 0 {
+                            0.0
+                        } else {
+                            ((offset as f64 - radius as f64) / radius as f64).powi(2)
+                        }
+                    })
+                    .sum::<f64>()
+                    <= 1.0;
+                if in_ball {
+                    ndarray::Zip::from(&mut output)
+                        .and(&slice(offset.slice()))
+                        .par_for_each(select);
+                }
+            }
+            output
+        }
+    }
+}
+
+pub struct Morphology {
+    operation: MorphologyOperation,
+    radius: Vec<u64>,
+    structuring_element: StructuringElement,
+    chunk_limit: Option<usize>,
+}
+
+impl Morphology {
+    pub fn new(
+        operation: MorphologyOperation,
+        radius: Vec<u64>,
+        structuring_element: StructuringElement,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            operation,
+            radius,
+            structuring_element,
+            chunk_limit,
+        }
+    }
+
+    /// The erode (true) / dilate (false) passes of the operation.
+    fn passes(&self) -> &'static [bool] {
+        match self.operation {
+            MorphologyOperation::Erode => &[true],
+            MorphologyOperation::Dilate => &[false],
+            MorphologyOperation::Open => &[true, false],
+            MorphologyOperation::Close => &[false, true],
+        }
+    }
+
+    /// The overlap required for all passes of the operation.
+    fn overlap(&self) -> Vec<u64> {
+        let passes = self.passes().len() as u64;
+        self.radius.iter().map(|radius| radius * passes).collect()
+    }
+
+    /// Apply the operation to a haloed `input`, where `pad` is the overlap missing due to the array boundaries.
+    ///
+    /// Elements beyond the array boundaries do not contribute to the output.
+    pub fn apply_ndarray<T>(&self, input: &ArrayD<T>, pad: &[(usize, usize)]) -> ArrayD<T>
+    where
+        T: Copy + PartialOrd + Bounded + Send + Sync,
+    {
+        let radius = self
+            .radius
+            .iter()
+            .map(|radius| usize::try_from(*radius).unwrap())
+            .collect::<Vec<_>>();
+        let identity = |erode: bool| {
+            if erode {
+                T::max_value()
+            } else {
+                T::min_value()
+            }
+        };
+        let passes = self.passes();
+        let mut array = pad_ndarray(input, pad, BorderMode::Constant, identity(passes[0]));
+        for (i, &erode) in passes.iter().enumerate() {
+            if i > 0 {
+                // Reset elements beyond the array boundaries for the next pass
+                for (axis, ((before, after), radius)) in std::iter::zip(pad, &radius).enumerate() {
+                    let len = array.len_of(Axis(axis));
+                    let before = before.saturating_sub(radius * i);
+                    let after = after.saturating_sub(radius * i);
+                    array
+                        .slice_axis_mut(Axis(axis), (0..before).into())
+                        .fill(identity(erode));
+                    array
+                        .slice_axis_mut(Axis(axis), (len - after..len).into())
+                        .fill(identity(erode));
+                }
+            }
+            array = erode_dilate_valid(&array, &radius, self.structuring_element, erode);
+        }
+        array
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Copy + PartialOrd + Bounded + Send + Sync + AsPrimitive<TOut>,
+        TOut: Element + Copy + 'static,
+    {
+        let overlap = self.overlap();
+        let (subset_output, subset_overlap, input_array) =
+            retrieve_chunk_haloed_ndarray::<TIn>(input, output, chunk_indices, &overlap, progress)?;
+
+        let output_array = progress.process(|| {
+            self.apply_ndarray(&input_array, &subset_overlap.truncated_overlap(&overlap))
+                .mapv(|x| x.as_())
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Morphology {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        if self.radius.len() != chunk_input.dimensionality() {
+            return Err(FilterError::InvalidParameters(format!(
+                "morphology radius {:?} does not match the input dimensionality {}",
+                self.radius,
+                chunk_input.dimensionality()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn halo(&self, _dimensionality: usize) -> Option<Vec<u64>> {
+        Some(self.overlap())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = num_elements_haloed(chunk_input.shape(), &self.overlap());
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_input_elements * chunk_input.data_type().fixed_size().unwrap() * 3
+            + num_output_elements * chunk_output.data_type().fixed_size().unwrap()
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                            match input.data_type() {
+                                $(
+                                    DataType::$data_type_in => {
+                                        apply_output!($type_in, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_input!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn morphology_operations() {
+        let input = ndarray::array![0u8, 5, 0, 0, 3, 3, 3, 0].into_dyn();
+        let morphology = |operation| {
+            Morphology::new(operation, vec![1], StructuringElement::Box, None)
+                .apply_ndarray(&input, &[(2, 2)])
+        };
+        // Windows are truncated at the array boundaries
+        assert_eq!(
+            morphology(MorphologyOperation::Open).as_slice().unwrap(),
+            &[0, 0, 0, 0, 3, 3, 3, 0]
+        );
+        assert_eq!(
+            morphology(MorphologyOperation::Close).as_slice().unwrap(),
+            &[5, 5, 3, 3, 3, 3, 3, 3]
+        );
+
+        let input =
+            ndarray::array![[0.0f32, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]].into_dyn();
+        let dilated = Morphology::new(
+            MorphologyOperation::Dilate,
+            vec![1, 1],
+            StructuringElement::Ball,
+            None,
+        )
+        .apply_ndarray(&input, &[(1, 1), (1, 1)]);
+        assert_eq!(
+            dilated,
+            ndarray::array![[0.0f32, 1.0, 0.0], [1.0, 1.0, 1.0], [0.0, 1.0, 0.0]].into_dyn()
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Normal, Poisson};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        filters::rescale_intensity::data_type_range, retrieve_array_subset_elements_f64,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseType {
+    /// Add zero mean Gaussian noise with a standard deviation of `--sigma`.
+    Gaussian,
+    /// Replace each value with a sample of a Poisson distribution with the value as its mean (shot noise).
+    Poisson,
+    /// Replace a proportion `--amount` of values with `--salt` or `--pepper`.
+    SaltAndPepper,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct NoiseArguments {
+    /// The type of noise.
+    #[arg(value_enum)]
+    pub noise: NoiseType,
+    /// The standard deviation of Gaussian noise.
+    #[arg(long, default_value_t = 1.0)]
+    #[serde(default = "default_sigma")]
+    pub sigma: f64,
+    /// The proportion of values replaced by salt-and-pepper noise, in the range [0, 1].
+    #[arg(long, default_value_t = 0.05)]
+    #[serde(default = "default_amount")]
+    pub amount: f64,
+    /// The salt value. Defaults to the maximum of integer output data types, or 1 otherwise.
+    #[arg(long, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub salt: Option<f64>,
+    /// The pepper value. Defaults to the minimum of integer output data types, or 0 otherwise.
+    #[arg(long, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub pepper: Option<f64>,
+    /// The random number generator seed. Defaults to a random seed, or 0 with `--deterministic`.
+    ///
+    /// The noise is reproducible for the same seed and output chunk grid.
+    #[arg(long)]
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+fn default_sigma() -> f64 {
+    1.0
+}
+
+fn default_amount() -> f64 {
+    0.05
+}
+
+impl FilterArguments for NoiseArguments {
+    fn name(&self) -> String {
+        "noise".to_string()
+    }
+
+    fn seed_mut(&mut self) -> Option<&mut Option<u64>> {
+        Some(&mut self.seed)
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let model = match self.noise {
+            NoiseType::Gaussian => {
+                if !(self.sigma.is_finite() && self.sigma >= 0.0) {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "noise sigma {} must be finite and non-negative",
+                        self.sigma
+                    )));
+                }
+                NoiseModel::Gaussian { sigma: self.sigma }
+            }
+            NoiseType::Poisson => NoiseModel::Poisson,
+            NoiseType::SaltAndPepper => {
+                if !(0.0..=1.0).contains(&self.amount) {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "noise amount {} must be in the range [0, 1]",
+                        self.amount
+                    )));
+                }
+                NoiseModel::SaltAndPepper {
+                    amount: self.amount,
+                    salt: self.salt,
+                    pepper: self.pepper,
+                }
+            }
+        };
+        Ok(Box::new(Noise::new(
+            model,
+            self.seed.unwrap_or_else(rand::random),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// A noise model and its parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseModel {
+    Gaussian {
+        sigma: f64,
+    },
+    Poisson,
+    SaltAndPepper {
+        amount: f64,
+        salt: Option<f64>,
+        pepper: Option<f64>,
+    },
+}
+
+pub struct Noise {
+    model: NoiseModel,
+    seed: u64,
+    chunk_limit: Option<usize>,
+}
+
+impl Noise {
+    pub fn new(model: NoiseModel, seed: u64, chunk_limit: Option<usize>) -> Self {
+        Self {
+            model,
+            seed,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the random number generator of a chunk.
+    ///
+    /// Each chunk draws from its own stream, so the noise does not depend on the order chunks are processed.
+    fn chunk_rng(&self, chunk_indices: &[u64], chunk_grid_shape: &[u64]) -> ChaCha8Rng {
+        let stream = std::iter::zip(chunk_indices, chunk_grid_shape)
+            .fold(0, |stream, (index, shape)| stream * shape + index);
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(stream);
+        rng
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        chunk_grid_shape: &[u64],
+        salt_pepper: (f64, f64),
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let elements_in =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &input_output_subset))?;
+
+        let elements_out = progress.process(|| {
+            let mut rng = self.chunk_rng(chunk_indices, chunk_grid_shape);
+            self.apply_elements::<TOut>(&elements_in, &mut rng, salt_pepper, round)
+        });
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Apply noise to `elements_in`, drawing from `rng`.
+    ///
+    /// `salt_pepper` holds the salt and pepper values of salt-and-pepper noise.
+    /// Values are rounded if `round` is true, which should be the case for integer outputs.
+    pub fn apply_elements<TOut>(
+        &self,
+        elements_in: &[f64],
+        rng: &mut impl Rng,
+        salt_pepper: (f64, f64),
+        round: bool,
+    ) -> Vec<TOut>
+    where
+        TOut: Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let apply_round = |value: f64| -> TOut {
+            if round {
+                value.round().as_()
+            } else {
+                value.as_()
+            }
+        };
+        match self.model {
+            NoiseModel::Gaussian { sigma } => {
+                let normal = Normal::new(0.0, sigma).unwrap();
+                elements_in
+                    .iter()
+                    .map(|value| apply_round(value + normal.sample(rng)))
+                    .collect()
+            }
+            NoiseModel::Poisson => elements_in
+                .iter()
+                .map(|&value| {
+                    // A Poisson distribution with a mean of zero (or less) is degenerate
+                    match Poisson::new(value) {
+                        Ok(poisson) if value.is_finite() => apply_round(poisson.sample(rng)),
+                        _ => apply_round(value),
+                    }
+                })
+                .collect(),
+            NoiseModel::SaltAndPepper { amount, .. } => {
+                let (salt, pepper) = salt_pepper;
+                elements_in
+                    .iter()
+                    .map(|&value| {
+                        if rng.gen_bool(amount) {
+                            apply_round(if rng.gen() { salt } else { pepper })
+                        } else {
+                            apply_round(value)
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FilterTraits for Noise {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let chunks = ArraySubset::new_with_shape(chunk_grid_shape.clone());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let salt_pepper = if let NoiseModel::SaltAndPepper { salt, pepper, .. } = self.model {
+            let (min, max) = data_type_range(output.data_type());
+            (salt.unwrap_or(max), pepper.unwrap_or(min))
+        } else {
+            (0.0, 0.0)
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, &chunk_grid_shape, salt_pepper, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_elements() {
+        let elements = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let noise = |model: NoiseModel, stream: u64| {
+            let noise = Noise::new(model, 42, None);
+            let mut rng = noise.chunk_rng(&[stream], &[2]);
+            noise.apply_elements::<f64>(&elements, &mut rng, (10.0, -10.0), false)
+        };
+
+        // The noise is reproducible for a seed and differs between chunks
+        let gaussian = NoiseModel::Gaussian { sigma: 1.0 };
+        assert_eq!(noise(gaussian, 0), noise(gaussian, 0));
+        assert_ne!(noise(gaussian, 0), noise(gaussian, 1));
+        assert_eq!(
+            noise(NoiseModel::Gaussian { sigma: 0.0 }, 0),
+            elements.to_vec()
+        );
+
+        let salt_and_pepper = |amount: f64| NoiseModel::SaltAndPepper {
+            amount,
+            salt: None,
+            pepper: None,
+        };
+        assert_eq!(noise(salt_and_pepper(0.0), 0), elements.to_vec());
+        assert!(noise(salt_and_pepper(1.0), 0)
+            .iter()
+            .all(|value| *value == 10.0 || *value == -10.0));
+
+        assert!(noise(NoiseModel::Poisson, 0)
+            .iter()
+            .all(|value| *value >= 0.0 && value.fract() == 0.0));
+    }
+}
@@ -0,0 +1,487 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    },
+    slice::ParallelSlice,
+};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, FillValue},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_elements_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct NormalizeArguments {
+    /// The input value mapped to the target minimum.
+    ///
+    /// Computed from the input if unset.
+    #[arg(long, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// The input value mapped to the target maximum.
+    ///
+    /// Computed from the input if unset.
+    #[arg(long, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Compute unset minimum/maximum statistics per chunk rather than over the entire input.
+    #[arg(long, conflicts_with = "per_slice_axis")]
+    #[serde(default)]
+    pub per_chunk: bool,
+    /// Compute unset minimum/maximum statistics independently for each index along this axis (e.g. per channel or timepoint).
+    #[arg(long)]
+    #[serde(default)]
+    pub per_slice_axis: Option<usize>,
+    /// The target minimum.
+    #[arg(long, allow_hyphen_values(true), default_value_t = 0.0)]
+    #[serde(default)]
+    pub target_min: f64,
+    /// The target maximum.
+    #[arg(long, allow_hyphen_values(true), default_value_t = 1.0)]
+    #[serde(default = "default_target_max")]
+    pub target_max: f64,
+}
+
+fn default_target_max() -> f64 {
+    1.0
+}
+
+impl FilterArguments for NormalizeArguments {
+    fn name(&self) -> String {
+        "normalize".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if self.per_chunk && self.per_slice_axis.is_some() {
+            return Err(FilterError::InvalidParameters(
+                "normalize per chunk and per slice statistics cannot be combined".to_string(),
+            ));
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min.is_nan() || max.is_nan() || min > max {
+                return Err(FilterError::InvalidParameters(format!(
+                    "normalize minimum {min} must be less than or equal to maximum {max}"
+                )));
+            }
+        }
+        Ok(Box::new(Normalize::new(
+            self.min,
+            self.max,
+            self.per_chunk,
+            self.per_slice_axis,
+            (self.target_min, self.target_max),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Normalize {
+    min: Option<f64>,
+    max: Option<f64>,
+    per_chunk: bool,
+    per_slice_axis: Option<usize>,
+    target: (f64, f64),
+    chunk_limit: Option<usize>,
+}
+
+/// Returns the minimum and maximum finite value of `elements`, or [`None`] if there are no finite values.
+pub fn finite_min_max(elements: &[f64]) -> Option<(f64, f64)> {
+    elements
+        .par_iter()
+        .filter(|value| value.is_finite())
+        .fold(
+            || None,
+            |acc: Option<(f64, f64)>, &value| match acc {
+                Some((min, max)) => Some((min.min(value), max.max(value))),
+                None => Some((value, value)),
+            },
+        )
+        .reduce(|| None, merge_min_max)
+}
+
+/// Merges two results of [`finite_min_max`].
+pub fn merge_min_max(a: Option<(f64, f64)>, b: Option<(f64, f64)>) -> Option<(f64, f64)> {
+    match (a, b) {
+        (Some((amin, amax)), Some((bmin, bmax))) => Some((amin.min(bmin), amax.max(bmax))),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Computes the minimum and maximum finite value of `input`, or [`None`] if there are no finite values.
+///
+/// # Errors
+/// Returns an error if the input cannot be read.
+pub fn calculate_min_max(
+    input: &Array<FilterStorage>,
+    chunk_limit: usize,
+    progress: &Progress,
+) -> Result<Option<(f64, f64)>, FilterError> {
+    let chunks = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+    let indices = chunks.indices();
+    let chunk_min_max = |chunk_indices: Vec<u64>| {
+        let chunk_subset = input.chunk_subset_bounded(&chunk_indices).unwrap();
+        let elements =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &chunk_subset))?;
+        let min_max = progress.process(|| finite_min_max(&elements));
+        progress.next();
+        Ok::<_, FilterError>(min_max)
+    };
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(chunk_limit, indices, map, chunk_min_max)
+        .try_reduce(|| None, |a, b| Ok(merge_min_max(a, b)))
+}
+
+/// Returns the number of consecutive elements of `subset` (in C order) sharing an index along `axis`.
+///
+/// The elements of a subset are runs of this length, and run `i` has the index [`slice_run_index`] along `axis`.
+pub fn slice_run_length(subset: &ArraySubset, axis: usize) -> usize {
+    usize::try_from(subset.shape()[axis + 1..].iter().product::<u64>())
+        .unwrap()
+        .max(1)
+}
+
+/// Returns the index along `axis` of run `run` of the elements of `subset`.
+pub fn slice_run_index(subset: &ArraySubset, axis: usize, run: usize) -> usize {
+    usize::try_from(subset.start()[axis] + run as u64 % subset.shape()[axis]).unwrap()
+}
+
+/// Checks that a per slice axis is within the dimensionality of the input of filter `name`.
+///
+/// # Errors
+/// Returns [`FilterError::InvalidParameters`] if the axis is out of bounds.
+pub fn check_per_slice_axis(
+    name: &str,
+    per_slice_axis: Option<usize>,
+    dimensionality: usize,
+) -> Result<(), FilterError> {
+    match per_slice_axis {
+        Some(axis) if axis >= dimensionality => Err(FilterError::InvalidParameters(format!(
+            "{name} per slice axis {axis} is out of bounds for an input with dimensionality {dimensionality}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Computes the minimum and maximum finite value of each index along `axis` of `input`, or [`None`] if there are no finite values.
+///
+/// # Errors
+/// Returns an error if the input cannot be read.
+pub fn calculate_min_max_per_slice(
+    input: &Array<FilterStorage>,
+    axis: usize,
+    chunk_limit: usize,
+    progress: &Progress,
+) -> Result<Vec<Option<(f64, f64)>>, FilterError> {
+    let num_slices = usize::try_from(input.shape()[axis]).unwrap();
+    let chunks = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+    let indices = chunks.indices();
+    let chunk_min_max = |chunk_indices: Vec<u64>| {
+        let chunk_subset = input.chunk_subset_bounded(&chunk_indices).unwrap();
+        let elements =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &chunk_subset))?;
+        let min_max = progress.process(|| {
+            let mut min_max = vec![None; num_slices];
+            elements
+                .chunks(slice_run_length(&chunk_subset, axis))
+                .enumerate()
+                .for_each(|(run, elements)| {
+                    let slice = slice_run_index(&chunk_subset, axis, run);
+                    min_max[slice] = merge_min_max(min_max[slice], finite_min_max(elements));
+                });
+            min_max
+        });
+        progress.next();
+        Ok::<_, FilterError>(min_max)
+    };
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(chunk_limit, indices, map, chunk_min_max)
+        .try_reduce(
+            || vec![None; num_slices],
+            |a, b| {
+                Ok(std::iter::zip(a, b)
+                    .map(|(a, b)| merge_min_max(a, b))
+                    .collect())
+            },
+        )
+}
+
+impl Normalize {
+    pub fn new(
+        min: Option<f64>,
+        max: Option<f64>,
+        per_chunk: bool,
+        per_slice_axis: Option<usize>,
+        target: (f64, f64),
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            min,
+            max,
+            per_chunk,
+            per_slice_axis,
+            target,
+            chunk_limit,
+        }
+    }
+
+    /// Returns true if statistics must be computed over the entire input before normalizing.
+    fn global_statistics(&self) -> bool {
+        !self.per_chunk && (self.min.is_none() || self.max.is_none())
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        min_max: &[Option<(f64, f64)>],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements_in =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &input_output_subset))?;
+
+        let elements_out = progress.process(|| {
+            let resolve = |statistics: Option<(f64, f64)>| match (self.min, self.max) {
+                (Some(min), Some(max)) => Some((min, max)),
+                (min, max) => statistics.map(|(statistics_min, statistics_max)| {
+                    (min.unwrap_or(statistics_min), max.unwrap_or(statistics_max))
+                }),
+            };
+            if let Some(axis) = self.per_slice_axis {
+                // Each run of elements sharing an index along the axis has its own statistics
+                elements_in
+                    .par_chunks(slice_run_length(&input_output_subset, axis))
+                    .enumerate()
+                    .flat_map_iter(|(run, elements)| {
+                        let slice = slice_run_index(&input_output_subset, axis, run);
+                        let statistics = min_max.get(slice).copied().flatten();
+                        self.apply_elements::<TOut>(elements, resolve(statistics))
+                    })
+                    .collect()
+            } else {
+                let statistics = if self.per_chunk {
+                    finite_min_max(&elements_in)
+                } else {
+                    min_max.first().copied().flatten()
+                };
+                self.apply_elements::<TOut>(&elements_in, resolve(statistics))
+            }
+        });
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Normalize `elements_in` from `min_max` to the target range.
+    ///
+    /// All elements are mapped to the target minimum if `min_max` is [`None`] or the minimum equals the maximum.
+    pub fn apply_elements<TOut>(
+        &self,
+        elements_in: &[f64],
+        min_max: Option<(f64, f64)>,
+    ) -> Vec<TOut>
+    where
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let (target_min, target_max) = self.target;
+        let scale = match min_max {
+            Some((min, max)) if max > min => (target_max - target_min) / (max - min),
+            _ => 0.0,
+        };
+        let min = min_max.map_or(0.0, |(min, _)| min);
+        elements_in
+            .par_iter()
+            .map(|value| (target_min + (value - min) * scale).as_())
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for Normalize {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        check_per_slice_axis(
+            "normalize",
+            self.per_slice_axis,
+            chunk_input.dimensionality(),
+        )?;
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        match input.data_type() {
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16 => None,
+            _ => Some((DataType::Float32, FillValue::from(0.0f32))),
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let num_statistics_chunks = if self.global_statistics() {
+            ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap()).num_elements_usize()
+        } else {
+            0
+        };
+        let progress = Progress::new(
+            num_statistics_chunks + chunks.num_elements_usize(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let min_max = match (self.global_statistics(), self.per_slice_axis) {
+            (true, Some(axis)) => calculate_min_max_per_slice(input, axis, chunk_limit, &progress)?,
+            (true, None) => vec![calculate_min_max(input, chunk_limit, &progress)?],
+            (false, _) => vec![],
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, &min_max, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn normalize() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![2, 4],
+                DataType::Float32,
+                vec![1, 2].try_into().unwrap(),
+                0.0f32.into(),
+            ),
+            &[-f32::INFINITY, 0.0, 2.0, 4.0, 10.0, 10.0, 10.0, f32::NAN],
+        )?;
+
+        let normalize = |args: &str| -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+            retrieve_test_elements(&run_test_filter("normalize", &input, &output, args)?)
+        };
+        let equal = |a: &[f32], b: &[f32]| {
+            a.len() == b.len()
+                && std::iter::zip(a, b).all(|(a, b)| a == b || (a.is_nan() && b.is_nan()))
+        };
+
+        // Statistics ignore non-finite values, which are passed through
+        assert!(equal(
+            &normalize("target_max: 1")?,
+            &[-f32::INFINITY, 0.0, 0.2, 0.4, 1.0, 1.0, 1.0, f32::NAN]
+        ));
+
+        // A constant slice maps to the target minimum
+        assert!(equal(
+            &normalize("per_slice_axis: 0")?,
+            &[-f32::INFINITY, 0.0, 0.5, 1.0, 0.0, 0.0, 0.0, f32::NAN]
+        ));
+        Ok(())
+    }
+}
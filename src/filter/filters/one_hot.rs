@@ -0,0 +1,319 @@
+use clap::Parser;
+use ndarray::{ArrayD, Axis};
+use num_traits::NumCast;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        codec::Codec, data_type::UnsupportedDataTypeError, Array, ArrayBuilder, ArrayShape,
+        DataType, Element, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+    metadata::Metadata,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    get_array_builder_reencode,
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct OneHotArguments {
+    /// The class labels, comma delimited. Each class is a channel of the output in the order given.
+    #[arg(required = true, value_delimiter = ',')]
+    pub classes: Vec<i64>,
+    /// The position of the channel axis in the output.
+    #[arg(long, default_value_t = 0)]
+    #[serde(default)]
+    pub channel_axis: usize,
+}
+
+impl FilterArguments for OneHotArguments {
+    fn name(&self) -> String {
+        "one_hot".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if self.classes.is_empty() {
+            return Err(FilterError::InvalidParameters(
+                "one_hot requires at least one class".to_string(),
+            ));
+        }
+        Ok(Box::new(OneHot::new(
+            self.classes.clone(),
+            self.channel_axis,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct OneHot {
+    classes: Vec<i64>,
+    channel_axis: usize,
+    chunk_limit: Option<usize>,
+}
+
+impl OneHot {
+    pub fn new(classes: Vec<i64>, channel_axis: usize, chunk_limit: Option<usize>) -> Self {
+        Self {
+            classes,
+            channel_axis,
+            chunk_limit,
+        }
+    }
+
+    /// One-hot encode `input` for `classes`, with a channel axis at `channel_axis`.
+    pub fn apply_ndarray<TIn, TOut>(
+        input: &ArrayD<TIn>,
+        classes: &[TIn],
+        channel_axis: usize,
+    ) -> ArrayD<TOut>
+    where
+        TIn: Copy + PartialEq,
+        TOut: Copy + From<bool>,
+    {
+        let channels = classes
+            .iter()
+            .map(|class| input.mapv(|value| TOut::from(value == *class)))
+            .collect::<Vec<_>>();
+        let channels = channels.iter().map(ArrayD::view).collect::<Vec<_>>();
+        ndarray::stack(Axis(channel_axis), &channels).unwrap()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Copy + PartialEq + NumCast + Send + Sync,
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        let classes = self
+            .classes
+            .iter()
+            .map(|class| {
+                <TIn as NumCast>::from(*class).ok_or_else(|| {
+                    FilterError::InvalidParameters(format!(
+                        "one_hot class {class} is not representable by the input data type {}",
+                        input.data_type()
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The output chunk may hold a subset of the classes, the input subset excludes the channel axis
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let mut start = subset_output.start().to_vec();
+        let mut shape = subset_output.shape().to_vec();
+        let classes_start = usize::try_from(start.remove(self.channel_axis)).unwrap();
+        let classes_end = classes_start + usize::try_from(shape.remove(self.channel_axis)).unwrap();
+        let subset_input = ArraySubset::new_with_start_shape(start, shape).unwrap();
+
+        let input_array =
+            progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+
+        let output_array = progress.process(|| {
+            Self::apply_ndarray::<TIn, TOut>(
+                &input_array,
+                &classes[classes_start..classes_end],
+                self.channel_axis,
+            )
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for OneHot {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if self.channel_axis > dimensionality {
+            return Err(FilterError::InvalidParameters(format!(
+                "one_hot channel axis {} is out of bounds for an input with dimensionality {dimensionality}",
+                self.channel_axis
+            )));
+        }
+        match chunk_input.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Bool | DataType::UInt8 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize() * chunk_input.fixed_element_size().unwrap()
+            + 2 * chunk_output.num_elements_usize() * chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        let mut shape = input.shape().to_vec();
+        shape.insert(self.channel_axis, self.classes.len() as u64);
+        Some(shape)
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::Bool, FillValue::from(false)))
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        let mut builder = get_array_builder_reencode(
+            &self.output_reencoding_args(array_input, reencoding_args),
+            array_input,
+            self.output_shape(array_input),
+        );
+        let num_classes = self.classes.len() as u64;
+
+        // Insert the channel axis into the chunk grid, with all classes in one chunk
+        let mut chunk_shape = builder
+            .chunk_grid
+            .chunk_shape_u64(&vec![0; array_input.dimensionality()], array_input.shape())
+            .unwrap()
+            .unwrap();
+        chunk_shape.insert(self.channel_axis, num_classes);
+        builder.chunk_grid(chunk_shape.try_into().unwrap());
+
+        // Insert the channel axis into the inner chunk shape of a sharded output
+        if let Some(metadata) = builder.array_to_bytes_codec.create_metadata() {
+            if metadata.name() == "sharding_indexed" {
+                let mut configuration = metadata.configuration().unwrap().clone();
+                let mut inner_chunk_shape: Vec<u64> =
+                    serde_json::from_value(configuration["chunk_shape"].clone()).unwrap();
+                inner_chunk_shape.insert(self.channel_axis, num_classes);
+                configuration.insert("chunk_shape".to_string(), inner_chunk_shape.into());
+                let metadata = Metadata::new_with_configuration(metadata.name(), configuration);
+                match Codec::from_metadata(&metadata).unwrap() {
+                    Codec::ArrayToBytes(codec) => builder.array_to_bytes_codec(codec),
+                    _ => unreachable!(),
+                };
+            }
+        }
+
+        if let Some(mut dimension_names) = builder.dimension_names.take() {
+            dimension_names.insert(self.channel_axis, "channel".into());
+            builder.dimension_names(Some(dimension_names));
+        }
+
+        builder
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_input {
+                    ( $t_out:ty, [$( ( $data_type_in:ident, $t_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(DataType::$data_type_in => {
+                                self.apply_chunk::<$t_in, $t_out>(input, &output, &chunk_indices, &progress)
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_output {
+                    ([$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(
+                                DataType::$data_type_out => {
+                                    apply_input!($type_out, [
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([(Bool, bool), (UInt8, u8)])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_hot_channels() {
+        let input = ndarray::array![[0u32, 3], [7, 3]].into_dyn();
+        let first = OneHot::apply_ndarray::<u32, u8>(&input, &[3, 7], 0);
+        assert_eq!(first.shape(), &[2, 2, 2]);
+        assert_eq!(
+            first,
+            ndarray::array![[[0, 1], [0, 1]], [[0, 0], [1, 0]]].into_dyn()
+        );
+        let last = OneHot::apply_ndarray::<u32, bool>(&input, &[3, 7], 2);
+        assert_eq!(last.shape(), &[2, 2, 2]);
+        assert!(last[[0, 1, 0]] && last[[1, 0, 1]] && !last[[0, 0, 0]]);
+    }
+}
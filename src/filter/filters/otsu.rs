@@ -0,0 +1,355 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, FillValue},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::{
+            normalize::calculate_min_max,
+            rescale_intensity::{calculate_histogram, data_type_range, histogram_bin},
+        },
+        retrieve_array_subset_elements_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct OtsuArguments {
+    /// The number of classes.
+    ///
+    /// 2 classes gives a binary output, more classes uses multi-Otsu thresholding.
+    #[arg(long, default_value_t = 2)]
+    #[serde(default = "default_classes")]
+    pub classes: usize,
+    /// The number of histogram bins used to find the thresholds.
+    ///
+    /// The cost of the threshold search grows quadratically with the number of bins.
+    #[arg(long, default_value_t = 256)]
+    #[serde(default = "default_bins")]
+    pub bins: usize,
+}
+
+fn default_classes() -> usize {
+    2
+}
+
+fn default_bins() -> usize {
+    256
+}
+
+impl FilterArguments for OtsuArguments {
+    fn name(&self) -> String {
+        "otsu".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if self.classes < 2 {
+            return Err(FilterError::InvalidParameters(format!(
+                "otsu classes must be at least 2, got {}",
+                self.classes
+            )));
+        }
+        if self.bins < self.classes {
+            return Err(FilterError::InvalidParameters(format!(
+                "otsu bins must be at least the number of classes ({}), got {}",
+                self.classes, self.bins
+            )));
+        }
+        Ok(Box::new(Otsu::new(
+            self.classes,
+            self.bins,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the bin edges separating `classes` classes of a histogram that maximise the between-class variance.
+///
+/// Class `i` holds bins `edges[i - 1]..edges[i]`, with implicit edges at `0` and `histogram.len()`.
+/// The search is exhaustive using dynamic programming. If there are ties, the lowest edges are chosen.
+pub fn otsu_edges(histogram: &[u64], classes: usize) -> Vec<usize> {
+    let bins = histogram.len();
+    assert!(classes >= 1 && classes <= bins);
+
+    // Cumulative counts and sums of bin indices
+    let mut counts = vec![0.0f64; bins + 1];
+    let mut sums = vec![0.0f64; bins + 1];
+    for (bin, &count) in histogram.iter().enumerate() {
+        counts[bin + 1] = counts[bin] + count as f64;
+        sums[bin + 1] = sums[bin] + (bin as f64) * count as f64;
+    }
+    // Maximising the between-class variance is equivalent to maximising the sum of w_k * mu_k^2
+    let cost = |start: usize, end: usize| {
+        let count = counts[end] - counts[start];
+        if count > 0.0 {
+            let sum = sums[end] - sums[start];
+            sum * sum / count
+        } else {
+            0.0
+        }
+    };
+
+    // best[c][j]: best score of splitting bins 0..j into c + 1 classes
+    // start[c][j]: the first bin of the last class of that split
+    let mut best = vec![vec![f64::NEG_INFINITY; bins + 1]; classes];
+    let mut start = vec![vec![0usize; bins + 1]; classes];
+    for (end, score) in best[0].iter_mut().enumerate().skip(1) {
+        *score = cost(0, end);
+    }
+    for class in 1..classes {
+        for end in class + 1..=bins {
+            for split in class..end {
+                let score = best[class - 1][split] + cost(split, end);
+                if score > best[class][end] {
+                    best[class][end] = score;
+                    start[class][end] = split;
+                }
+            }
+        }
+    }
+
+    let mut edges = vec![0; classes - 1];
+    let mut end = bins;
+    for class in (1..classes).rev() {
+        end = start[class][end];
+        edges[class - 1] = end;
+    }
+    edges
+}
+
+/// Returns the class of a histogram bin given the class `edges` from [`otsu_edges`].
+#[inline]
+pub fn otsu_class(bin: usize, edges: &[usize]) -> usize {
+    edges.partition_point(|&edge| edge <= bin)
+}
+
+pub struct Otsu {
+    classes: usize,
+    bins: usize,
+    chunk_limit: Option<usize>,
+}
+
+impl Otsu {
+    pub fn new(classes: usize, bins: usize, chunk_limit: Option<usize>) -> Self {
+        Self {
+            classes,
+            bins,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        min_max: (f64, f64),
+        edges: &[usize],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements_in =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &input_output_subset))?;
+
+        let elements_out = progress
+            .process(|| Self::apply_elements::<TOut>(&elements_in, min_max, self.bins, edges));
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Assign each element of `elements_in` to its class, where class edges are bins of a histogram spanning `min_max`.
+    ///
+    /// NaN values are assigned to the first class.
+    pub fn apply_elements<TOut>(
+        elements_in: &[f64],
+        min_max: (f64, f64),
+        bins: usize,
+        edges: &[usize],
+    ) -> Vec<TOut>
+    where
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        elements_in
+            .par_iter()
+            .map(|&value| {
+                let class = if value.is_nan() {
+                    0
+                } else {
+                    otsu_class(histogram_bin(value, min_max, bins), edges)
+                };
+                (class as f64).as_()
+            })
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for Otsu {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        let is_float = matches!(
+            chunk_output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+        if !is_float && data_type_range(chunk_output.data_type()).1 < (self.classes - 1) as f64 {
+            return Err(FilterError::InvalidParameters(format!(
+                "otsu output data type {} cannot represent {} classes",
+                chunk_output.data_type(),
+                self.classes
+            )));
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + chunk_output.fixed_element_size().unwrap())
+            + self.bins * core::mem::size_of::<u64>()
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::UInt8, FillValue::from(0u8)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let chunks_input = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+        let progress = Progress::new(
+            2 * chunks_input.num_elements_usize() + chunks.num_elements_usize(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let min_max = calculate_min_max(input, chunk_limit, &progress)?.unwrap_or((0.0, 0.0));
+        let histogram = calculate_histogram(input, min_max, self.bins, chunk_limit, &progress)?;
+        let edges = otsu_edges(&histogram, self.classes);
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, min_max, &edges, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        let (min, max) = min_max;
+        let bin_width = (max - min) / self.bins as f64;
+        let thresholds: Vec<f64> = edges
+            .iter()
+            .map(|&edge| min + edge as f64 * bin_width)
+            .collect();
+        output
+            .attributes_mut()
+            .insert("thresholds".to_string(), thresholds.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otsu_thresholds() {
+        // Two modes separated by empty bins, ties pick the lowest edge
+        let histogram = [5, 5, 0, 0, 0, 0, 5, 5];
+        assert_eq!(otsu_edges(&histogram, 2), vec![2]);
+
+        let histogram = [4, 4, 0, 1, 4, 0, 4, 4];
+        assert_eq!(otsu_edges(&histogram, 3), vec![2, 5]);
+        assert_eq!(otsu_class(0, &[2, 5]), 0);
+        assert_eq!(otsu_class(2, &[2, 5]), 1);
+        assert_eq!(otsu_class(4, &[2, 5]), 1);
+        assert_eq!(otsu_class(5, &[2, 5]), 2);
+
+        let elements = [0.0, 1.9, 2.0, 7.9, 8.0, f64::NAN];
+        let out = Otsu::apply_elements::<u8>(&elements, (0.0, 8.0), 8, &[2]);
+        assert_eq!(out, vec![0, 0, 1, 1, 1, 0]);
+    }
+}
@@ -0,0 +1,532 @@
+use std::ops::Range;
+
+use clap::Parser;
+use ndarray::{ArrayD, Axis, Slice};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, ArrayShape, DataType, Element,
+        ElementOwned,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_ndarray_bytes, store_array_subset_ndarray_bytes, FilterArguments,
+        FilterCommonArguments, FilterStorage,
+    },
+    get_array_builder_reencode,
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PadMode {
+    /// Fill with the output fill value.
+    #[default]
+    Constant,
+    /// Reflect about the edge, excluding the edge element (e.g. 3 2 | 1 2 3 | 2 1).
+    Reflect,
+    /// Replicate the edge element (e.g. 1 1 | 1 2 3 | 3 3).
+    Edge,
+}
+
+impl PadMode {
+    /// Returns the input index of `index` along an axis of length `len`, or [`None`] if it is filled with a constant.
+    pub fn input_index(&self, index: i64, len: u64) -> Option<u64> {
+        let len = len as i64;
+        if (0..len).contains(&index) {
+            return Some(index as u64);
+        }
+        match self {
+            Self::Constant => None,
+            Self::Reflect => {
+                if len == 1 {
+                    Some(0)
+                } else {
+                    // Reflections repeat if the padding is wider than the input
+                    let period = 2 * (len - 1);
+                    let index = index.rem_euclid(period);
+                    Some(if index < len { index } else { period - index } as u64)
+                }
+            }
+            Self::Edge => Some(index.clamp(0, len - 1) as u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct PadArguments {
+    /// The number of elements to add before the start of each axis, comma delimited.
+    #[arg(required = true, value_delimiter = ',')]
+    pub before: Vec<u64>,
+    /// The number of elements to add after the end of each axis, comma delimited.
+    #[arg(required = true, value_delimiter = ',')]
+    pub after: Vec<u64>,
+    /// How to fill the padded regions.
+    ///
+    /// Constant padding uses the output fill value, which can be set with --fill-value.
+    #[arg(long, value_enum, default_value_t = PadMode::Constant)]
+    #[serde(default)]
+    pub mode: PadMode,
+}
+
+impl FilterArguments for PadArguments {
+    fn name(&self) -> String {
+        "pad".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Pad::new(
+            self.before.clone(),
+            self.after.clone(),
+            self.mode,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// The input elements of an output chunk along one axis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PadAxis {
+    /// The output chunk positions read from the input, the remainder are constant.
+    pub output: Range<usize>,
+    /// The input region read.
+    pub input: Range<u64>,
+    /// The index into the input region of each position in `output`.
+    pub indices: Vec<usize>,
+}
+
+pub struct Pad {
+    before: Vec<u64>,
+    after: Vec<u64>,
+    mode: PadMode,
+    chunk_limit: Option<usize>,
+}
+
+impl Pad {
+    pub fn new(
+        before: Vec<u64>,
+        after: Vec<u64>,
+        mode: PadMode,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            before,
+            after,
+            mode,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the input elements of the output range `start..start + len` along an axis of length `axis_len` padded by `before`.
+    ///
+    /// Returns [`None`] if the range is entirely constant.
+    pub fn pad_axis(
+        mode: PadMode,
+        start: u64,
+        len: u64,
+        axis_len: u64,
+        before: u64,
+    ) -> Option<PadAxis> {
+        let input_indices = (start..start + len)
+            .map(|index| mode.input_index(index as i64 - before as i64, axis_len))
+            .collect::<Vec<_>>();
+        // Positions read from the input are contiguous, since constant padding is only at the ends
+        let output_start = input_indices.iter().position(Option::is_some)?;
+        let output_end = input_indices.iter().rposition(Option::is_some).unwrap() + 1;
+        let input_indices = input_indices[output_start..output_end]
+            .iter()
+            .map(|index| index.unwrap())
+            .collect::<Vec<_>>();
+        let input_start = *input_indices.iter().min().unwrap();
+        let input_end = *input_indices.iter().max().unwrap() + 1;
+        Some(PadAxis {
+            output: output_start..output_end,
+            input: input_start..input_end,
+            indices: input_indices
+                .iter()
+                .map(|index| usize::try_from(index - input_start).unwrap())
+                .collect(),
+        })
+    }
+
+    /// Returns the [`PadAxis`] of each axis of an output chunk, or [`None`] if the chunk is entirely constant.
+    fn chunk_pad_axes(
+        &self,
+        input: &Array<FilterStorage>,
+        subset_output: &ArraySubset,
+    ) -> Option<Vec<PadAxis>> {
+        itertools::izip!(
+            subset_output.start(),
+            subset_output.shape(),
+            input.shape(),
+            &self.before
+        )
+        .map(|(&start, &len, &axis_len, &before)| {
+            Self::pad_axis(self.mode, start, len, axis_len, before)
+        })
+        .collect::<Option<Vec<_>>>()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        fill_value: TOut,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Copy + 'static,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let output_shape = subset_output
+            .shape()
+            .iter()
+            .map(|&len| usize::try_from(len).unwrap())
+            .collect::<Vec<_>>();
+        let mut output_array = ArrayD::<TOut>::from_elem(output_shape, fill_value);
+
+        // Only read the input if some of the output chunk is not constant
+        if let Some(pad_axes) = self.chunk_pad_axes(input, &subset_output) {
+            let subset_input = ArraySubset::new_with_ranges(
+                &pad_axes
+                    .iter()
+                    .map(|pad_axis| pad_axis.input.clone())
+                    .collect::<Vec<_>>(),
+            );
+            let input_array =
+                progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+            progress.process(|| {
+                let input_array = pad_axes.iter().enumerate().fold(
+                    input_array,
+                    |input_array, (axis, pad_axis)| {
+                        input_array.select(Axis(axis), &pad_axis.indices)
+                    },
+                );
+                let mut output_view = output_array.slice_each_axis_mut(|axis| {
+                    Slice::from(pad_axes[axis.axis.index()].output.clone())
+                });
+                output_view.zip_mut_with(&input_array, |output, input| *output = input.as_());
+            });
+        }
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Pad a chunk of a data type without arithmetic (e.g. raw bits), copying elements as opaque byte blobs.
+    pub fn apply_chunk_bytes(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError> {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let fill_value = output.fill_value().as_ne_bytes();
+        let output_shape = subset_output
+            .shape()
+            .iter()
+            .map(|&len| usize::try_from(len).unwrap())
+            .chain(std::iter::once(fill_value.len()))
+            .collect::<Vec<_>>();
+        let mut output_array = ndarray::ArrayView1::from(fill_value)
+            .broadcast(output_shape)
+            .unwrap()
+            .to_owned();
+
+        // Only read the input if some of the output chunk is not constant
+        if let Some(pad_axes) = self.chunk_pad_axes(input, &subset_output) {
+            let subset_input = ArraySubset::new_with_ranges(
+                &pad_axes
+                    .iter()
+                    .map(|pad_axis| pad_axis.input.clone())
+                    .collect::<Vec<_>>(),
+            );
+            let input_array =
+                progress.read(|| retrieve_array_subset_ndarray_bytes(input, &subset_input))?;
+            progress.process(|| {
+                // The trailing axis of element bytes is not padded
+                let input_array = pad_axes.iter().enumerate().fold(
+                    input_array,
+                    |input_array, (axis, pad_axis)| {
+                        input_array.select(Axis(axis), &pad_axis.indices)
+                    },
+                );
+                let mut output_view = output_array.slice_each_axis_mut(|axis| {
+                    pad_axes
+                        .get(axis.axis.index())
+                        .map_or(Slice::from(..), |pad_axis| {
+                            Slice::from(pad_axis.output.clone())
+                        })
+                });
+                output_view.assign(&input_array);
+            });
+        }
+
+        progress.write(|| {
+            store_array_subset_ndarray_bytes(output, subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Pad {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if self.before.len() != dimensionality || self.after.len() != dimensionality {
+            return Err(FilterError::InvalidParameters(format!(
+                "pad before {:?} and after {:?} must have the dimensionality of the input ({dimensionality})",
+                self.before, self.after
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                // Raw bits elements are copied without conversion
+                DataType::RawBits(_) if chunk_input.data_type() == chunk_output.data_type() => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input region of an output chunk is no larger than the output chunk, but is copied when reflected or replicated
+        chunk_output.num_elements_usize()
+            * (2 * chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        Some(
+            itertools::izip!(input.shape(), &self.before, &self.after)
+                .map(|(len, before, after)| before + len + after)
+                .collect(),
+        )
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        let output_shape = self.output_shape(array_input).unwrap();
+        let mut reencoding_args = self.output_reencoding_args(array_input, reencoding_args);
+
+        // Resolve zero sized dimensions of an explicit chunk or shard shape against the padded shape
+        for shape in [
+            &mut reencoding_args.chunk_shape,
+            &mut reencoding_args.shard_shape,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            std::iter::zip(shape.iter_mut(), &output_shape)
+                .filter(|(s, _)| **s == 0)
+                .for_each(|(s, &len)| *s = len);
+        }
+
+        get_array_builder_reencode(&reencoding_args, array_input, Some(output_shape))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+        if self.mode != PadMode::Constant && input.shape().contains(&0) {
+            return Err(FilterError::InvalidParameters(format!(
+                "pad mode {:?} cannot pad an empty input with shape {:?}",
+                self.mode,
+                input.shape()
+            )));
+        }
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                if let DataType::RawBits(_) = input.data_type() {
+                    return self.apply_chunk_bytes(input, output, &chunk_indices, &progress);
+                }
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => {
+                                let fill_value: $type_out = bytemuck::pod_read_unaligned(output.fill_value().as_ne_bytes());
+                                self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, fill_value, &progress)
+                            } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pad_modes() {
+        let input_indices = |mode: PadMode| {
+            (-4..7)
+                .map(|index| mode.input_index(index, 3))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            input_indices(PadMode::Constant),
+            [
+                None,
+                None,
+                None,
+                None,
+                Some(0),
+                Some(1),
+                Some(2),
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(
+            input_indices(PadMode::Reflect),
+            [0, 1, 2, 1, 0, 1, 2, 1, 0, 1, 2].map(Some)
+        );
+        assert_eq!(
+            input_indices(PadMode::Edge),
+            [0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2].map(Some)
+        );
+    }
+
+    #[test]
+    fn pad_axis() {
+        // An output chunk straddling the start of an axis of length 10 padded by 2
+        assert_eq!(
+            Pad::pad_axis(PadMode::Constant, 0, 4, 10, 2),
+            Some(PadAxis {
+                output: 2..4,
+                input: 0..2,
+                indices: vec![0, 1],
+            })
+        );
+        assert_eq!(
+            Pad::pad_axis(PadMode::Reflect, 0, 4, 10, 2),
+            Some(PadAxis {
+                output: 0..4,
+                input: 0..3,
+                indices: vec![2, 1, 0, 1],
+            })
+        );
+        assert_eq!(
+            Pad::pad_axis(PadMode::Edge, 10, 4, 10, 2),
+            Some(PadAxis {
+                output: 0..4,
+                input: 8..10,
+                indices: vec![0, 1, 1, 1],
+            })
+        );
+        // An output chunk entirely within the padding
+        assert_eq!(Pad::pad_axis(PadMode::Constant, 12, 4, 10, 2), None);
+    }
+}
@@ -0,0 +1,49 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::filter::{
+    filter_error::FilterError, filter_traits::FilterTraits, FilterArguments, FilterCommonArguments,
+};
+
+use super::reduce::{Reduce, ReduceOperator};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ProjectArguments {
+    /// The axis to project along.
+    pub axis: usize,
+    /// The projection, e.g. max for a maximum intensity projection (MIP) or min for a minimum intensity projection (MinIP).
+    #[arg(long, value_enum, default_value_t = ReduceOperator::Max)]
+    #[serde(default = "default_projection")]
+    pub projection: ReduceOperator,
+    /// The start of the projected range along the axis (inclusive), for a slab projection.
+    #[arg(long, default_value_t = 0)]
+    #[serde(default)]
+    pub start: u64,
+    /// The end of the projected range along the axis (exclusive), for a slab projection. Defaults to the length of the axis.
+    #[arg(long)]
+    #[serde(default)]
+    pub end: Option<u64>,
+}
+
+fn default_projection() -> ReduceOperator {
+    ReduceOperator::Max
+}
+
+impl FilterArguments for ProjectArguments {
+    fn name(&self) -> String {
+        "project".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        // A projection is a reduction along a range of a single axis
+        Ok(Box::new(Reduce::new_with_ranges(
+            self.projection,
+            vec![self.axis],
+            vec![(self.start, self.end)],
+            *common_args.chunk_limit(),
+        )))
+    }
+}
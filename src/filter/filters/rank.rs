@@ -0,0 +1,331 @@
+use clap::Parser;
+use ndarray::{ArrayD, IxDyn, SliceInfo, SliceInfoElem};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        num_elements_haloed, retrieve_chunk_haloed_ndarray, FilterArguments, FilterCommonArguments,
+        FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize, Default)]
+pub struct RankArguments {
+    /// The percentile of each window, in the range [0, 100].
+    ///
+    /// 0 is a minimum filter, 50 is a median filter, and 100 is a maximum filter.
+    pub percentile: f64,
+    /// Window radius per axis, comma delimited. The window is 2 x radius + 1.
+    #[arg(required = true, value_delimiter = ',')]
+    pub radius: Vec<u64>,
+}
+
+impl FilterArguments for RankArguments {
+    fn name(&self) -> String {
+        "rank".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if !(0.0..=100.0).contains(&self.percentile) {
+            return Err(FilterError::InvalidParameters(format!(
+                "rank percentile {} must be in the range [0, 100]",
+                self.percentile
+            )));
+        }
+        Ok(Box::new(Rank::new(
+            self.percentile,
+            self.radius.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the value at `percentile` (in the range [0, 100]) of the window of `radius` about each element of `subset` in `input`.
+///
+/// The nearest rank is used and windows are truncated at the boundaries of `input`.
+/// NaN values are excluded from windows, and the output is NaN if the window contains only NaN values.
+pub fn rank_ndarray<T>(
+    input: &ArrayD<T>,
+    radius: &[u64],
+    subset: &ArraySubset,
+    percentile: f64,
+) -> ArrayD<T>
+where
+    T: Copy + PartialOrd + Send + Sync,
+{
+    let shape = subset
+        .shape()
+        .iter()
+        .map(|s| usize::try_from(*s).unwrap())
+        .collect::<Vec<_>>();
+    let start = subset
+        .start()
+        .iter()
+        .map(|s| usize::try_from(*s).unwrap())
+        .collect::<Vec<_>>();
+    let radius = radius
+        .iter()
+        .map(|r| usize::try_from(*r).unwrap())
+        .collect::<Vec<_>>();
+    let window_size = radius.iter().map(|r| 2 * r + 1).product::<usize>();
+
+    let elements = (0..shape.iter().product::<usize>())
+        .into_par_iter()
+        .map_init(
+            || Vec::<T>::with_capacity(window_size),
+            |window, index| {
+                // Unravel the index and determine the window bounds in the input
+                let mut remainder = index;
+                let mut center = vec![0; shape.len()];
+                for axis in (0..shape.len()).rev() {
+                    center[axis] = start[axis] + remainder % shape[axis];
+                    remainder /= shape[axis];
+                }
+                let slices = itertools::izip!(&center, &radius, input.shape())
+                    .map(|(&c, &r, &len)| {
+                        SliceInfoElem::from(c.saturating_sub(r)..(c + r + 1).min(len))
+                    })
+                    .collect::<Vec<_>>();
+
+                window.clear();
+                #[allow(clippy::eq_op)]
+                window.extend(
+                    input
+                        .slice(SliceInfo::<_, IxDyn, IxDyn>::try_from(slices).unwrap())
+                        .iter()
+                        .filter(|value| value == value),
+                );
+                if window.is_empty() {
+                    input[IxDyn(&center)]
+                } else {
+                    let rank = ((window.len() - 1) as f64 * percentile / 100.0).round() as usize;
+                    *window
+                        .select_nth_unstable_by(rank, |a, b| a.partial_cmp(b).unwrap())
+                        .1
+                }
+            },
+        )
+        .collect::<Vec<T>>();
+    ArrayD::from_shape_vec(shape, elements).unwrap()
+}
+
+pub struct Rank {
+    percentile: f64,
+    radius: Vec<u64>,
+    chunk_limit: Option<usize>,
+}
+
+impl Rank {
+    pub fn new(percentile: f64, radius: Vec<u64>, chunk_limit: Option<usize>) -> Self {
+        Self {
+            percentile,
+            radius,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Copy + PartialOrd + Send + Sync + AsPrimitive<TOut>,
+        TOut: Element + Copy + 'static,
+    {
+        let (subset_output, subset_overlap, input_array) = retrieve_chunk_haloed_ndarray::<TIn>(
+            input,
+            output,
+            chunk_indices,
+            &self.radius,
+            progress,
+        )?;
+
+        let output_array = progress.process(|| {
+            rank_ndarray(
+                &input_array,
+                &self.radius,
+                subset_overlap.subset_output_in_input(),
+                self.percentile,
+            )
+            .map(|x| x.as_())
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Rank {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        if self.radius.len() != chunk_input.dimensionality() {
+            return Err(FilterError::InvalidParameters(format!(
+                "rank radius {:?} does not match the input dimensionality {}",
+                self.radius,
+                chunk_input.dimensionality()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn halo(&self, _dimensionality: usize) -> Option<Vec<u64>> {
+        Some(self.radius.clone())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = num_elements_haloed(chunk_input.shape(), &self.radius);
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_input_elements * chunk_input.data_type().fixed_size().unwrap()
+            + num_output_elements
+                * (chunk_input.data_type().fixed_size().unwrap()
+                    + chunk_output.data_type().fixed_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                            match input.data_type() {
+                                $(
+                                    DataType::$data_type_in => {
+                                        apply_output!($type_in, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_input!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_window() {
+        let input =
+            ndarray::array![[1.0, 9.0, 2.0], [8.0, f32::NAN, 3.0], [7.0, 4.0, 6.0]].into_dyn();
+        let subset = ArraySubset::new_with_shape(vec![3, 3]);
+        let output = rank_ndarray(&input, &[1, 1], &subset, 50.0);
+        // Corner window [1, 9, 8] (NaN excluded)
+        assert_eq!(output[[0, 0]], 8.0);
+        // Full window excluding NaN: [1, 9, 2, 8, 3, 7, 4, 6], nearest rank 4 (of 0..8) is 6
+        assert_eq!(output[[1, 1]], 6.0);
+        let output = rank_ndarray(&input, &[0, 0], &subset, 50.0);
+        assert!(output[[1, 1]].is_nan());
+        let output = rank_ndarray(
+            &input,
+            &[1, 1],
+            &ArraySubset::new_with_ranges(&[2..3, 0..1]),
+            0.0,
+        );
+        assert_eq!(output, ndarray::array![[4.0]].into_dyn());
+        let output = rank_ndarray(&input, &[1, 1], &subset, 100.0);
+        assert_eq!(output[[2, 2]], 6.0);
+    }
+}
@@ -0,0 +1,650 @@
+use std::ops::Range;
+
+use clap::Parser;
+use itertools::Itertools;
+use ndarray::ArrayD;
+use num_traits::AsPrimitive;
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+    },
+    slice::ParallelSlice,
+};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        codec::Codec, data_type::UnsupportedDataTypeError, Array, ArrayBuilder, ArrayShape,
+        DataType, Element, FillValue,
+    },
+    array_subset::ArraySubset,
+    metadata::Metadata,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_elements_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    get_array_builder_reencode,
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+use super::{resample::PIXEL_SIZE_ATTRIBUTE, transpose::permute};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReduceOperator {
+    /// The sum.
+    Sum,
+    /// The mean.
+    Mean,
+    /// The minimum.
+    Min,
+    /// The maximum.
+    Max,
+    /// The population standard deviation.
+    Std,
+    /// The Euclidean (L2) norm.
+    Norm,
+}
+
+/// The partial reduction of the elements reduced into an output element.
+#[derive(Debug, Clone, Copy)]
+pub struct ReduceState {
+    count: u64,
+    a: f64,
+    b: f64,
+}
+
+impl ReduceOperator {
+    pub fn init(&self) -> ReduceState {
+        let a = match self {
+            Self::Sum | Self::Mean | Self::Std | Self::Norm => 0.0,
+            Self::Min => f64::INFINITY,
+            Self::Max => f64::NEG_INFINITY,
+        };
+        ReduceState {
+            count: 0,
+            a,
+            b: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn push(&self, state: &mut ReduceState, value: f64) {
+        state.count += 1;
+        match self {
+            Self::Sum | Self::Mean => state.a += value,
+            Self::Min => state.a = state.a.min(value),
+            Self::Max => state.a = state.a.max(value),
+            Self::Std => {
+                // Welford's online algorithm, a is the mean and b the sum of squared differences from the mean
+                let delta = value - state.a;
+                state.a += delta / state.count as f64;
+                state.b += delta * (value - state.a);
+            }
+            Self::Norm => state.a += value * value,
+        }
+    }
+
+    pub fn finish(&self, state: &ReduceState) -> f64 {
+        match self {
+            Self::Sum | Self::Min | Self::Max => state.a,
+            Self::Mean => state.a / state.count as f64,
+            Self::Std => (state.b / state.count as f64).sqrt(),
+            Self::Norm => state.a.sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ReduceArguments {
+    /// The reduction.
+    #[arg(value_enum)]
+    pub operator: ReduceOperator,
+    /// The axes to reduce, comma delimited.
+    #[arg(required = true, value_delimiter = ',')]
+    pub axes: Vec<usize>,
+}
+
+impl FilterArguments for ReduceArguments {
+    fn name(&self) -> String {
+        "reduce".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Reduce::new(
+            self.operator,
+            self.axes.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the axes of an array with `dimensionality` that are not in `axes`.
+pub fn kept_axes(dimensionality: usize, axes: &[usize]) -> Vec<usize> {
+    (0..dimensionality)
+        .filter(|axis| !axes.contains(axis))
+        .collect()
+}
+
+/// Restrict the chunk grid, inner chunk shape, dimension names, and pixel size of `builder` to `kept_axes`.
+///
+/// Shapes with the dimensionality of the input (`dimensionality`) are restricted, shapes explicitly set for the output are unchanged.
+pub fn select_axes_array_builder(
+    builder: &mut ArrayBuilder,
+    array_input: &Array<FilterStorage>,
+    reencoding_args: &ZarrReencodingArgs,
+    kept_axes: &[usize],
+) {
+    let dimensionality = array_input.dimensionality();
+    if builder.chunk_grid.dimensionality() == dimensionality {
+        let chunk_shape = builder
+            .chunk_grid
+            .chunk_shape_u64(&vec![0; dimensionality], array_input.shape())
+            .unwrap()
+            .unwrap();
+        builder.chunk_grid(permute(&chunk_shape, kept_axes).try_into().unwrap());
+    }
+
+    // Restrict the inner chunk shape of a sharded output
+    if let Some(metadata) = builder.array_to_bytes_codec.create_metadata() {
+        if metadata.name() == "sharding_indexed" {
+            let mut configuration = metadata.configuration().unwrap().clone();
+            let inner_chunk_shape: Vec<u64> =
+                serde_json::from_value(configuration["chunk_shape"].clone()).unwrap();
+            if inner_chunk_shape.len() == dimensionality {
+                configuration.insert(
+                    "chunk_shape".to_string(),
+                    permute(&inner_chunk_shape, kept_axes).into(),
+                );
+                let metadata = Metadata::new_with_configuration(metadata.name(), configuration);
+                match Codec::from_metadata(&metadata).unwrap() {
+                    Codec::ArrayToBytes(codec) => builder.array_to_bytes_codec(codec),
+                    _ => unreachable!(),
+                };
+            }
+        }
+    }
+
+    if reencoding_args.dimension_names.is_none() {
+        if let Some(dimension_names) = builder.dimension_names.take() {
+            builder.dimension_names(Some(permute(&dimension_names, kept_axes)));
+        }
+    }
+
+    if let Some(pixel_size) = builder
+        .attributes
+        .get(PIXEL_SIZE_ATTRIBUTE)
+        .and_then(|pixel_size| serde_json::from_value::<Vec<f64>>(pixel_size.clone()).ok())
+        .filter(|pixel_size| pixel_size.len() == dimensionality)
+    {
+        builder.attributes.insert(
+            PIXEL_SIZE_ATTRIBUTE.to_string(),
+            permute(&pixel_size, kept_axes).into(),
+        );
+    }
+}
+
+/// Returns the shape of `input` with `axes` removed, or [`None`] if an axis is out of bounds.
+pub fn reduced_shape(input: &Array<FilterStorage>, axes: &[usize]) -> Option<ArrayShape> {
+    if axes.iter().all(|&axis| axis < input.dimensionality()) {
+        Some(permute(
+            input.shape(),
+            &kept_axes(input.dimensionality(), axes),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Returns the builder of an output with `axes` of `array_input` removed.
+///
+/// `reencoding_args` are the resolved output reencoding arguments, explicit chunk and shard shapes are in the output axes.
+pub fn reduced_array_builder(
+    array_input: &Array<FilterStorage>,
+    mut reencoding_args: ZarrReencodingArgs,
+    axes: &[usize],
+) -> ArrayBuilder {
+    let output_shape = reduced_shape(array_input, axes);
+
+    // Resolve zero sized dimensions of an explicit chunk or shard shape against the output shape
+    if let Some(output_shape) = &output_shape {
+        for shape in [
+            &mut reencoding_args.chunk_shape,
+            &mut reencoding_args.shard_shape,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            std::iter::zip(shape.iter_mut(), output_shape)
+                .filter(|(s, _)| **s == 0)
+                .for_each(|(s, &len)| *s = len);
+        }
+    }
+
+    let mut builder =
+        get_array_builder_reencode(&reencoding_args, array_input, output_shape.clone());
+    if output_shape.is_some() {
+        select_axes_array_builder(
+            &mut builder,
+            array_input,
+            &reencoding_args,
+            &kept_axes(array_input.dimensionality(), axes),
+        );
+    }
+    builder
+}
+
+/// Returns the input subsets reduced into `subset_output`, an output subset with `axes` of `input` removed.
+///
+/// Each reduced axis spans the corresponding range of `ranges`.
+/// The reduced axes are split into slabs aligned with the input chunks, so each subset is about the size of an input chunk.
+pub fn reduced_input_subsets(
+    input: &Array<FilterStorage>,
+    subset_output: &ArraySubset,
+    axes: &[usize],
+    ranges: &[Range<u64>],
+) -> Result<Vec<ArraySubset>, FilterError> {
+    let dimensionality = input.dimensionality();
+    let chunk_shape_input = input.chunk_shape(&vec![0; dimensionality])?;
+    let slabs = std::iter::zip(axes, ranges)
+        .map(|(&axis, range)| {
+            let chunk_len = chunk_shape_input[axis].get();
+            (range.start / chunk_len..range.end.div_ceil(chunk_len))
+                .map(|i| {
+                    std::cmp::max(i * chunk_len, range.start)
+                        ..std::cmp::min((i + 1) * chunk_len, range.end)
+                })
+                .collect::<Vec<_>>()
+        })
+        .multi_cartesian_product();
+
+    let mut ranges = vec![0..0; dimensionality];
+    for (axis, start, end) in itertools::izip!(
+        kept_axes(dimensionality, axes),
+        subset_output.start(),
+        subset_output.end_exc()
+    ) {
+        ranges[axis] = *start..end;
+    }
+    Ok(slabs
+        .map(|slab| {
+            for (axis, range) in std::iter::zip(axes, slab) {
+                ranges[*axis] = range;
+            }
+            ArraySubset::new_with_ranges(&ranges)
+        })
+        .collect())
+}
+
+pub struct Reduce {
+    operator: ReduceOperator,
+    axes: Vec<usize>,
+    ranges: Vec<(u64, Option<u64>)>,
+    chunk_limit: Option<usize>,
+}
+
+impl Reduce {
+    pub fn new(operator: ReduceOperator, axes: Vec<usize>, chunk_limit: Option<usize>) -> Self {
+        let ranges = vec![(0, None); axes.len()];
+        Self::new_with_ranges(operator, axes, ranges, chunk_limit)
+    }
+
+    /// Create a reduction over the start and (optional) end of each axis in `axes`, the end defaults to the axis length.
+    pub fn new_with_ranges(
+        operator: ReduceOperator,
+        axes: Vec<usize>,
+        ranges: Vec<(u64, Option<u64>)>,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            operator,
+            axes,
+            ranges,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the range of each reduced axis of `input`.
+    pub fn reduced_ranges(&self, input: &Array<FilterStorage>) -> Vec<Range<u64>> {
+        std::iter::zip(&self.axes, &self.ranges)
+            .map(|(&axis, &(start, end))| start..end.unwrap_or(input.shape()[axis]))
+            .collect()
+    }
+
+    /// Checks that the range of each reduced axis is non-empty and within the shape of `input`.
+    fn check_ranges(&self, input: &Array<FilterStorage>) -> Result<(), FilterError> {
+        for (axis, range) in std::iter::zip(&self.axes, self.reduced_ranges(input)) {
+            if range.is_empty() || range.end > input.shape()[*axis] {
+                return Err(FilterError::InvalidParameters(format!(
+                    "reduce range {range:?} of axis {axis} is empty or out of bounds for an input with shape {:?}",
+                    input.shape()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reduce `elements` with shape `shape` into `states`, which has an element per element of the kept axes.
+    pub fn reduce_elements(&self, states: &mut [ReduceState], elements: Vec<f64>, shape: &[usize]) {
+        // Move the reduced axes last, so each output element reduces a contiguous run of elements
+        let order = kept_axes(shape.len(), &self.axes)
+            .into_iter()
+            .chain(self.axes.iter().copied())
+            .collect::<Vec<_>>();
+        let elements = ArrayD::from_shape_vec(shape, elements)
+            .unwrap()
+            .permuted_axes(order);
+        let elements = elements.as_standard_layout();
+        let elements = elements.as_slice().unwrap();
+        if elements.is_empty() {
+            return;
+        }
+        let chunk_len = elements.len() / states.len();
+        states
+            .par_iter_mut()
+            .zip(elements.par_chunks(chunk_len))
+            .for_each(|(state, elements)| {
+                for element in elements {
+                    self.operator.push(state, *element);
+                }
+            });
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let mut states = vec![self.operator.init(); subset_output.num_elements_usize()];
+        let ranges = self.reduced_ranges(input);
+        for subset_input in reduced_input_subsets(input, &subset_output, &self.axes, &ranges)? {
+            let elements =
+                progress.read(|| retrieve_array_subset_elements_f64(input, &subset_input))?;
+            progress.process(|| {
+                self.reduce_elements(&mut states, elements, &subset_input.shape_usize())
+            });
+        }
+
+        let elements_out = progress.process(|| {
+            states
+                .into_par_iter()
+                .map(|state| self.operator.finish(&state).as_())
+                .collect::<Vec<TOut>>()
+        });
+
+        progress
+            .write(|| output.store_array_subset_elements::<TOut>(&subset_output, &elements_out))?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Reduce {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if self.axes.is_empty() {
+            return Err(FilterError::InvalidParameters(
+                "reduce requires at least one axis".to_string(),
+            ));
+        }
+        for (i, axis) in self.axes.iter().enumerate() {
+            if *axis >= dimensionality {
+                return Err(FilterError::InvalidParameters(format!(
+                    "reduce axis {axis} is out of bounds for an input with dimensionality {dimensionality}"
+                )));
+            } else if self.axes[..i].contains(axis) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "reduce axis {axis} is repeated"
+                )));
+            }
+        }
+        if self.ranges.len() != self.axes.len() {
+            return Err(FilterError::InvalidParameters(format!(
+                "reduce has {} ranges for {} axes",
+                self.ranges.len(),
+                self.axes.len()
+            )));
+        }
+        for (axis, &(start, end)) in std::iter::zip(&self.axes, &self.ranges) {
+            if end.is_some_and(|end| end <= start) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "reduce range {start}..{} of axis {axis} is empty",
+                    end.unwrap()
+                )));
+            }
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn is_compatible_arrays(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &Array<FilterStorage>,
+    ) -> Result<(), FilterError> {
+        for input in inputs {
+            let chunk_input = input.chunk_array_representation(&vec![0; input.dimensionality()])?;
+            let chunk_output =
+                output.chunk_array_representation(&vec![0; output.dimensionality()])?;
+            self.is_compatible(&chunk_input, &chunk_output)?;
+            self.check_ranges(input)?;
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // A slab of about an input chunk is held as f64 and copied to reorder its axes, and the partial reduction of the output chunk
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap() + 2 * core::mem::size_of::<f64>())
+            + chunk_output.num_elements_usize()
+                * (core::mem::size_of::<ReduceState>() + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        reduced_shape(input, &self.axes)
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        match (self.operator, input.data_type()) {
+            (ReduceOperator::Sum | ReduceOperator::Min | ReduceOperator::Max, _)
+            | (_, DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16) => {
+                None
+            }
+            _ => Some((DataType::Float32, FillValue::from(0.0f32))),
+        }
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        reduced_array_builder(
+            array_input,
+            self.output_reencoding_args(array_input, reencoding_args),
+            &self.axes,
+        )
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+        self.check_ranges(input)?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reduce_operators() {
+        let reduce = |operator: ReduceOperator, values: &[f64]| {
+            let mut state = operator.init();
+            values
+                .iter()
+                .for_each(|value| operator.push(&mut state, *value));
+            operator.finish(&state)
+        };
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(reduce(ReduceOperator::Sum, &values), 40.0);
+        assert_eq!(reduce(ReduceOperator::Mean, &values), 5.0);
+        assert_eq!(reduce(ReduceOperator::Min, &values), 2.0);
+        assert_eq!(reduce(ReduceOperator::Max, &values), 9.0);
+        assert_eq!(reduce(ReduceOperator::Std, &values), 2.0);
+        assert_eq!(reduce(ReduceOperator::Norm, &[3.0, 4.0]), 5.0);
+    }
+
+    #[test]
+    fn reduce_elements() {
+        // Reduce axes 0 and 2 of a 2x3x2 array in two slabs along axis 0
+        let elements = (0..12).map(f64::from).collect::<Vec<_>>();
+        let reduce = Reduce::new(ReduceOperator::Sum, vec![0, 2], None);
+        let mut states = vec![ReduceOperator::Sum.init(); 3];
+        reduce.reduce_elements(&mut states, elements[..6].to_vec(), &[1, 3, 2]);
+        reduce.reduce_elements(&mut states, elements[6..].to_vec(), &[1, 3, 2]);
+        let sums = states
+            .iter()
+            .map(|state| ReduceOperator::Sum.finish(state))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sums,
+            vec![
+                0.0 + 1.0 + 6.0 + 7.0,
+                2.0 + 3.0 + 8.0 + 9.0,
+                4.0 + 5.0 + 10.0 + 11.0
+            ]
+        );
+        assert_eq!(kept_axes(3, &[0, 2]), vec![1]);
+    }
+
+    #[test]
+    fn reduce_ranges() -> Result<(), Box<dyn std::error::Error>> {
+        let store: std::sync::Arc<FilterStorage> =
+            std::sync::Arc::new(zarrs::storage::store::MemoryStore::new());
+        let array = |shape: Vec<u64>, chunk_shape: Vec<u64>, path: &str| {
+            ArrayBuilder::new(
+                shape,
+                DataType::UInt8,
+                chunk_shape.try_into().unwrap(),
+                0u8.into(),
+            )
+            .build(store.clone(), path)
+            .unwrap()
+        };
+        let input = array(vec![4, 2], vec![2, 2], "/input");
+        let output = array(vec![2], vec![2], "/output");
+        let project = |start, end| {
+            Reduce::new_with_ranges(ReduceOperator::Max, vec![0], vec![(start, end)], None)
+        };
+
+        project(1, Some(3)).is_compatible_arrays(&[&input], &output)?;
+        project(1, None).is_compatible_arrays(&[&input], &output)?;
+
+        // An empty range is rejected without the input shape
+        let chunk_input = input.chunk_array_representation(&[0, 0])?;
+        let chunk_output = output.chunk_array_representation(&[0])?;
+        assert!(project(2, Some(2))
+            .is_compatible(&chunk_input, &chunk_output)
+            .is_err());
+
+        // A range beyond the input shape is rejected with the input array
+        assert!(project(2, Some(5))
+            .is_compatible_arrays(&[&input], &output)
+            .is_err());
+        assert!(project(4, None)
+            .is_compatible_arrays(&[&input], &output)
+            .is_err());
+        Ok(())
+    }
+}
@@ -5,13 +5,12 @@ use serde::{Deserialize, Serialize};
 use zarrs::{
     array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
-        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        FilterArguments, FilterCommonArguments,
+        calculate_chunk_limit, elements_from_bytes, elements_to_bytes, filter_error::FilterError,
+        filter_traits::FilterTraits, FilterArguments, FilterCommonArguments, FilterStorage,
     },
     progress::{Progress, ProgressCallback},
 };
@@ -43,8 +42,8 @@ impl Reencode {
 
     pub fn apply_chunk(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError> {
@@ -57,8 +56,8 @@ impl Reencode {
 
     pub fn apply_chunk_convert<TIn, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
@@ -109,6 +108,9 @@ impl FilterTraits for Reencode {
                 | DataType::Float32
                 | DataType::Float64
                 | DataType::BFloat16 => {}
+                // Complex elements are copied without conversion
+                DataType::Complex64 | DataType::Complex128
+                    if chunk_input.data_type() == chunk_output.data_type() => {}
                 _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
             };
         }
@@ -123,10 +125,78 @@ impl FilterTraits for Reencode {
         chunk_output.fixed_element_size().unwrap()
     }
 
+    fn is_elementwise(&self) -> bool {
+        true
+    }
+
+    fn apply_elementwise(
+        &self,
+        bytes: &[u8],
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+    ) -> Result<Vec<u8>, FilterError> {
+        if output.data_type() == input.data_type() {
+            return Ok(bytes.to_vec());
+        }
+        macro_rules! apply_output {
+            ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                match output.data_type() {
+                    $(DataType::$data_type_out => {
+                        let elements_out = elements_from_bytes::<$type_in>(bytes)
+                            .into_iter()
+                            .map(|input| input.as_())
+                            .collect::<Vec<$type_out>>();
+                        Ok(elements_to_bytes(&elements_out))
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        macro_rules! apply_input {
+            ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                match input.data_type() {
+                    $(DataType::$data_type_in => {
+                        apply_output!($type_in, [
+                            (Bool, u8),
+                            (Int8, i8),
+                            (Int16, i16),
+                            (Int32, i32),
+                            (Int64, i64),
+                            (UInt8, u8),
+                            (UInt16, u16),
+                            (UInt32, u32),
+                            (UInt64, u64),
+                            (BFloat16, half::bf16),
+                            (Float16, half::f16),
+                            (Float32, f32),
+                            (Float64, f64)
+                        ])
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        apply_input!([
+            (Bool, u8),
+            (Int8, i8),
+            (Int16, i16),
+            (Int32, i32),
+            (Int64, i64),
+            (UInt8, u8),
+            (UInt16, u16),
+            (UInt32, u32),
+            (UInt64, u64),
+            (BFloat16, half::bf16),
+            (Float16, half::f16),
+            (Float32, f32),
+            (Float64, f64)
+        ])
+    }
+
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(input.shape(), output.shape());
@@ -0,0 +1,420 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, FillValueMetadata},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    parse_fill_value,
+    progress::{Progress, ProgressCallback},
+};
+
+use crate::filter::{
+    calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits, FilterArguments,
+    FilterCommonArguments, FilterStorage,
+};
+
+/// A (value, replacement) pair.
+pub type RemapPair = (FillValueMetadata, FillValueMetadata);
+
+fn parse_remap_pair(pair: &str) -> std::io::Result<RemapPair> {
+    let (value, replace) = pair.split_once('=').ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("expected a pair of the form value=replacement, got {pair}"),
+        )
+    })?;
+    Ok((
+        parse_fill_value(value.trim())?,
+        parse_fill_value(replace.trim())?,
+    ))
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemapUnmapped {
+    /// Pass through unmapped values, subject to the conversion to the output data type.
+    #[default]
+    Keep,
+    /// Replace unmapped values with the output fill value.
+    Fill,
+    /// Fail if any value is unmapped.
+    Error,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct RemapArguments {
+    /// Pairs of the form value=replacement.
+    ///
+    /// Values must be compatible with the input data type, and replacements with the output data type.
+    /// Precede pairs starting with a negative value by --.
+    ///
+    /// Examples:
+    ///   int/uint: 7=1 9=2 -- -1=0
+    ///   float: 0.5=1.0 '"NaN"=0.0'
+    #[arg(value_parser = parse_remap_pair, verbatim_doc_comment)]
+    #[serde(default)]
+    pub pairs: Vec<RemapPair>,
+    /// Path to a mapping file with additional pairs.
+    ///
+    /// A JSON file (.json) holding an object ({"7": 1, "9": 2}) or an array of pairs ([[7, 1], [9, 2]]).
+    /// Otherwise a CSV file with value,replacement per line. A non-numeric header line is skipped.
+    #[arg(long, verbatim_doc_comment)]
+    #[serde(default)]
+    pub map: Option<PathBuf>,
+    /// How to handle values that are not remapped.
+    #[arg(long, value_enum, default_value_t = RemapUnmapped::Keep)]
+    #[serde(default)]
+    pub unmapped: RemapUnmapped,
+}
+
+impl FilterArguments for RemapArguments {
+    fn name(&self) -> String {
+        "remap".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let mut pairs = self.pairs.clone();
+        if let Some(map) = &self.map {
+            pairs.extend(read_remap_file(map)?);
+        }
+        if pairs.is_empty() {
+            return Err(FilterError::InvalidParameters(
+                "remap requires at least one pair".to_string(),
+            ));
+        }
+        Ok(Box::new(Remap::new(
+            pairs,
+            self.unmapped,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Read remap pairs from a JSON or CSV file.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or is malformed.
+pub fn read_remap_file(path: &std::path::Path) -> Result<Vec<RemapPair>, FilterError> {
+    let contents = std::fs::read_to_string(path)?;
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        parse_remap_json(&contents)
+    } else {
+        parse_remap_csv(&contents)
+    }
+}
+
+fn parse_remap_json(contents: &str) -> Result<Vec<RemapPair>, FilterError> {
+    let json: serde_json::Value = serde_json::from_str(contents)?;
+    match json {
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .map(|(value, replace)| {
+                let value = parse_fill_value(&value).or_else(|_| {
+                    // Allow unquoted strings as keys, e.g. "NaN"
+                    serde_json::from_value(serde_json::Value::String(value))
+                })?;
+                Ok((value, serde_json::from_value(replace)?))
+            })
+            .collect(),
+        serde_json::Value::Array(_) => Ok(serde_json::from_value(json)?),
+        _ => Err(FilterError::InvalidParameters(
+            "remap file must hold a JSON object or an array of pairs".to_string(),
+        )),
+    }
+}
+
+fn parse_remap_csv(contents: &str) -> Result<Vec<RemapPair>, FilterError> {
+    let mut pairs = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pair = line.replacen(',', "=", 1);
+        match parse_remap_pair(&pair) {
+            Ok(pair) => pairs.push(pair),
+            Err(_) if pairs.is_empty() && line_number == 0 => {
+                // Header
+            }
+            Err(err) => Err(FilterError::InvalidParameters(format!(
+                "remap file line {}: {err}",
+                line_number + 1
+            )))?,
+        }
+    }
+    Ok(pairs)
+}
+
+pub struct Remap {
+    pairs: Vec<RemapPair>,
+    unmapped: RemapUnmapped,
+    chunk_limit: Option<usize>,
+}
+
+/// Returns the bit pattern of an element as a hashable key.
+fn element_key<T: bytemuck::Pod>(element: &T) -> u64 {
+    let mut key = [0u8; 8];
+    let bytes = bytemuck::bytes_of(element);
+    key[..bytes.len()].copy_from_slice(bytes);
+    u64::from_ne_bytes(key)
+}
+
+impl Remap {
+    pub fn new(pairs: Vec<RemapPair>, unmapped: RemapUnmapped, chunk_limit: Option<usize>) -> Self {
+        Self {
+            pairs,
+            unmapped,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the remapping for `TIn` to `TOut`, keyed by the bit pattern of the value.
+    ///
+    /// # Errors
+    /// Returns an error if a value or replacement is incompatible with the input or output data type, or a value is repeated.
+    pub fn mapping<TIn, TOut>(
+        &self,
+        data_type_in: &DataType,
+        data_type_out: &DataType,
+    ) -> Result<HashMap<u64, TOut>, FilterError>
+    where
+        TIn: bytemuck::Pod,
+        TOut: bytemuck::Pod,
+    {
+        let mut mapping = HashMap::with_capacity(self.pairs.len());
+        for (value, replace) in &self.pairs {
+            let value_in = data_type_in.fill_value_from_metadata(value)?;
+            let replace_out = data_type_out.fill_value_from_metadata(replace)?;
+            let value_in: TIn = bytemuck::pod_read_unaligned(value_in.as_ne_bytes());
+            let replace_out: TOut = bytemuck::pod_read_unaligned(replace_out.as_ne_bytes());
+            if mapping
+                .insert(element_key(&value_in), replace_out)
+                .is_some()
+            {
+                return Err(FilterError::InvalidParameters(format!(
+                    "remap value {value:?} is repeated"
+                )));
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Remap `input_elements`, with unmapped values handled according to the unmapped mode.
+    ///
+    /// # Errors
+    /// Returns an error if a value is unmapped and the unmapped mode is [`RemapUnmapped::Error`].
+    pub fn apply_elements<TIn, TOut>(
+        &self,
+        input_elements: &[TIn],
+        mapping: &HashMap<u64, TOut>,
+        fill_value: TOut,
+    ) -> Result<Vec<TOut>, FilterError>
+    where
+        TIn: bytemuck::Pod + Copy + Send + Sync + AsPrimitive<TOut> + std::fmt::Debug,
+        TOut: bytemuck::Pod + Send + Sync,
+    {
+        input_elements
+            .into_par_iter()
+            .map(|v_in| match mapping.get(&element_key(v_in)) {
+                Some(v_out) => Ok(*v_out),
+                None => match self.unmapped {
+                    RemapUnmapped::Keep => Ok(v_in.as_()),
+                    RemapUnmapped::Fill => Ok(fill_value),
+                    RemapUnmapped::Error => Err(FilterError::InvalidParameters(format!(
+                        "remap value {v_in:?} is not mapped"
+                    ))),
+                },
+            })
+            .collect::<Result<Vec<TOut>, _>>()
+    }
+}
+
+impl FilterTraits for Remap {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+    }
+
+    fn supports_in_place(&self) -> bool {
+        true
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        macro_rules! apply_input {
+            ( $t_out:ty, [$( ( $data_type_in:ident, $t_in:ty ) ),* ]) => {
+                match input.data_type() {
+                    $(DataType::$data_type_in => {
+                        let mapping = self.mapping::<$t_in, $t_out>(input.data_type(), output.data_type())?;
+                        let fill_value: $t_out = bytemuck::pod_read_unaligned(output.fill_value().as_ne_bytes());
+                        let indices = chunks.indices();
+                        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                            chunk_limit,
+                            indices,
+                            try_for_each,
+                            |chunk_indices: Vec<u64>| {
+                                let input_output_subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+                                let input_elements =
+                                    progress.read(|| input.retrieve_array_subset_elements::<$t_in>(&input_output_subset))?;
+
+                                let output_elements =
+                                    progress.process(|| self.apply_elements::<$t_in, $t_out>(&input_elements, &mapping, fill_value))?;
+                                drop(input_elements);
+
+                                progress.write(|| {
+                                    output.store_array_subset_elements::<$t_out>(&input_output_subset, &output_elements)
+                                })?;
+
+                                progress.next();
+                                Ok::<_, FilterError>(())
+                            }
+                        )
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        macro_rules! apply_output {
+            ([$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                    match output.data_type() {
+                        $(
+                            DataType::$data_type_out => {
+                                apply_input!($type_out, [
+                                    (Int8, i8),
+                                    (Int16, i16),
+                                    (Int32, i32),
+                                    (Int64, i64),
+                                    (UInt8, u8),
+                                    (UInt16, u16),
+                                    (UInt32, u32),
+                                    (UInt64, u64),
+                                    (BFloat16, half::bf16),
+                                    (Float16, half::f16),
+                                    (Float32, f32),
+                                    (Float64, f64)
+                                ]
+                            )}
+                        ,)*
+                        _ => panic!()
+                    }
+                };
+            }
+        apply_output!([
+            (Int8, i8),
+            (Int16, i16),
+            (Int32, i32),
+            (Int64, i64),
+            (UInt8, u8),
+            (UInt16, u16),
+            (UInt32, u32),
+            (UInt64, u64),
+            (BFloat16, half::bf16),
+            (Float16, half::f16),
+            (Float32, f32),
+            (Float64, f64)
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remap_pairs() -> Result<(), FilterError> {
+        let csv = parse_remap_csv("from,to\n7,1\n\n9, 2\n")?;
+        let json_object = parse_remap_json(r#"{"7": 1, "9": 2}"#)?;
+        let json_array = parse_remap_json("[[7, 1], [9, 2]]")?;
+        assert_eq!(csv.len(), 2);
+        assert_eq!(csv, json_array);
+        assert_eq!(json_object, json_array);
+        assert!(parse_remap_csv("7,1\n9\n").is_err());
+
+        let remap = Remap::new(csv.clone(), RemapUnmapped::Keep, None);
+        let mapping = remap.mapping::<u16, u8>(&DataType::UInt16, &DataType::UInt8)?;
+        assert_eq!(
+            remap.apply_elements::<u16, u8>(&[7, 8, 9, 7], &mapping, 0)?,
+            vec![1, 8, 2, 1]
+        );
+        let remap_fill = Remap::new(csv.clone(), RemapUnmapped::Fill, None);
+        assert_eq!(
+            remap_fill.apply_elements::<u16, u8>(&[7, 8, 9, 7], &mapping, 255)?,
+            vec![1, 255, 2, 1]
+        );
+        let remap_error = Remap::new(csv, RemapUnmapped::Error, None);
+        assert!(remap_error
+            .apply_elements::<u16, u8>(&[7, 8, 9, 7], &mapping, 0)
+            .is_err());
+        assert!(remap_error
+            .apply_elements::<u16, u8>(&[7, 9], &mapping, 0)
+            .is_ok());
+
+        let repeated = Remap::new(
+            parse_remap_json("[[7, 1], [7, 2]]")?,
+            RemapUnmapped::Keep,
+            None,
+        );
+        assert!(repeated
+            .mapping::<u16, u8>(&DataType::UInt16, &DataType::UInt8)
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
 use clap::Parser;
 use num_traits::AsPrimitive;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
 use serde::{Deserialize, Serialize};
 use zarrs::{
-    array::{data_type::UnsupportedDataTypeError, Array, DataType, FillValueMetadata},
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayError, DataType, FillValue,
+        FillValueMetadata,
+    },
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
@@ -14,8 +19,8 @@ use crate::{
 };
 
 use crate::filter::{
-    calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits, FilterArguments,
-    FilterCommonArguments,
+    calculate_chunk_limit, element_size, filter_error::FilterError, filter_traits::FilterTraits,
+    resolve_time_value, FilterArguments, FilterCommonArguments, FilterStorage,
 };
 
 #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
@@ -28,6 +33,9 @@ pub struct ReplaceValueArguments {
     ///   int/uint: 0
     ///   float: 0.0 "NaN" "Infinity" "-Infinity"
     ///   r*: "[0, 255]"
+    ///   datetime: '"2020-01-01T00:00:00"' '"NaT"' (int64 with a datetime units attribute)
+    ///   timedelta: '"90 s"' (int64 with a timedelta units attribute)
+    ///   string: '"cat"'
     #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
     pub value: FillValueMetadata,
     /// The replacement value.
@@ -38,6 +46,9 @@ pub struct ReplaceValueArguments {
     ///   int/uint: 0
     ///   float: 0.0 "NaN" "Infinity" "-Infinity"
     ///   r*: "[0, 255]"
+    ///   datetime: '"2020-01-01T00:00:00"' '"NaT"' (int64 with a datetime units attribute)
+    ///   timedelta: '"90 s"' (int64 with a timedelta units attribute)
+    ///   string: '"cat"'
     #[arg(allow_hyphen_values(true), value_parser = parse_fill_value)]
     pub replace: FillValueMetadata,
 }
@@ -94,6 +105,32 @@ impl ReplaceValue {
             .collect::<Vec<TOut>>();
         Ok(output_elements)
     }
+
+    pub fn apply_elements_string(
+        &self,
+        input_elements: Vec<String>,
+        value: &str,
+        replace: &str,
+    ) -> Vec<String> {
+        input_elements
+            .into_par_iter()
+            .map(|v_in| {
+                if v_in == value {
+                    replace.to_string()
+                } else {
+                    v_in
+                }
+            })
+            .collect()
+    }
+
+    /// Replace elements of a fixed size data type as opaque byte blobs (e.g. raw bits) in place.
+    pub fn apply_elements_bytes_inplace(&self, bytes: &mut [u8], value: &[u8], replace: &[u8]) {
+        bytes
+            .par_chunks_exact_mut(value.len())
+            .filter(|v_in| *v_in == value)
+            .for_each(|v_in| v_in.copy_from_slice(replace));
+    }
 }
 
 impl FilterTraits for ReplaceValue {
@@ -102,6 +139,18 @@ impl FilterTraits for ReplaceValue {
         chunk_input: &zarrs::array::ChunkRepresentation,
         chunk_output: &zarrs::array::ChunkRepresentation,
     ) -> Result<(), FilterError> {
+        // Strings and raw bits are not converted, so the input and output data types must match
+        let unconverted =
+            |data_type: &DataType| matches!(data_type, DataType::String | DataType::RawBits(_));
+        if (unconverted(chunk_input.data_type()) || unconverted(chunk_output.data_type()))
+            && chunk_input.data_type() != chunk_output.data_type()
+        {
+            return Err(FilterError::InvalidParameters(format!(
+                "replace_value input data type {} and output data type {} must match for string or raw bits data types",
+                chunk_input.data_type(),
+                chunk_output.data_type()
+            )));
+        }
         for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
             match data_type {
                 DataType::Bool
@@ -116,7 +165,9 @@ impl FilterTraits for ReplaceValue {
                 | DataType::Float16
                 | DataType::Float32
                 | DataType::Float64
-                | DataType::BFloat16 => {}
+                | DataType::BFloat16
+                | DataType::String
+                | DataType::RawBits(_) => {}
                 _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
             };
         }
@@ -128,13 +179,17 @@ impl FilterTraits for ReplaceValue {
         chunk_input: &zarrs::array::ChunkRepresentation,
         chunk_output: &zarrs::array::ChunkRepresentation,
     ) -> usize {
-        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+        element_size(chunk_input) + element_size(chunk_output)
+    }
+
+    fn supports_in_place(&self) -> bool {
+        true
     }
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
@@ -144,11 +199,11 @@ impl FilterTraits for ReplaceValue {
 
         let value = input
             .data_type()
-            .fill_value_from_metadata(&self.value)
+            .fill_value_from_metadata(&resolve_time_value(input, &self.value)?)
             .expect("value not compatible with input image");
         let replace = output
             .data_type()
-            .fill_value_from_metadata(&self.replace)
+            .fill_value_from_metadata(&resolve_time_value(output, &self.replace)?)
             .expect("replace not compatible with output image");
 
         let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
@@ -196,6 +251,45 @@ impl FilterTraits for ReplaceValue {
                 macro_rules! apply_output {
                     ([$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
                             match output.data_type() {
+                                DataType::RawBits(_) => {
+                                    let mut bytes = progress
+                                        .read(|| input.retrieve_array_subset(&input_output_subset))?
+                                        .into_fixed()
+                                        .map_err(ArrayError::from)?
+                                        .into_owned();
+
+                                    progress.process(|| {
+                                        self.apply_elements_bytes_inplace(&mut bytes, value.as_ne_bytes(), replace.as_ne_bytes())
+                                    });
+
+                                    progress.write(|| {
+                                        output.store_array_subset(&input_output_subset, bytes)
+                                    })?;
+
+                                    progress.next();
+                                    Ok(())
+                                }
+                                DataType::String => {
+                                    let input_elements =
+                                        progress.read(|| input.retrieve_array_subset_elements::<String>(&input_output_subset))?;
+
+                                    let output_elements =
+                                        progress.process(|| {
+                                            let as_str = |value: &FillValue| {
+                                                std::str::from_utf8(value.as_ne_bytes())
+                                                    .map(str::to_string)
+                                                    .map_err(|err| FilterError::InvalidParameters(err.to_string()))
+                                            };
+                                            Ok::<_, FilterError>(self.apply_elements_string(input_elements, &as_str(&value)?, &as_str(&replace)?))
+                                        })?;
+
+                                    progress.write(|| {
+                                        output.store_array_subset_elements::<String>(&input_output_subset, &output_elements)
+                                    })?;
+
+                                    progress.next();
+                                    Ok(())
+                                }
                                 $(
                                     DataType::$data_type_out => {
                                         apply_input!($type_out, [
@@ -0,0 +1,467 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, DataType, Element, ElementOwned,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        interpolation::retrieve_resampled, AxisTaps, FilterArguments, FilterCommonArguments,
+        FilterStorage, Interpolation,
+    },
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+/// The name of the attribute holding the physical pixel size of each axis.
+pub const PIXEL_SIZE_ATTRIBUTE: &str = "pixel_size";
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct ResampleArguments {
+    /// The target shape, comma delimited.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        required_unless_present = "spacing",
+        conflicts_with = "spacing"
+    )]
+    #[serde(default)]
+    pub shape: Option<Vec<u64>>,
+    /// The target pixel size (spacing) per axis, comma delimited.
+    ///
+    /// The output shape is the physical extent of the input divided by the spacing, rounded to the nearest integer.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub spacing: Option<Vec<f64>>,
+    /// The pixel size of the input per axis, comma delimited.
+    ///
+    /// Defaults to the `pixel_size` attribute of the input if present, otherwise 1.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub input_spacing: Option<Vec<f64>>,
+    /// The interpolation method.
+    ///
+    /// Linear and cubic interpolation are anti-aliased along axes that are reduced.
+    #[arg(long, value_enum, default_value_t = Interpolation::Linear)]
+    #[serde(default = "default_interpolation")]
+    pub interpolation: Interpolation,
+}
+
+fn default_interpolation() -> Interpolation {
+    Interpolation::Linear
+}
+
+impl FilterArguments for ResampleArguments {
+    fn name(&self) -> String {
+        "resample".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let target = match (&self.shape, &self.spacing) {
+            (Some(shape), None) => {
+                if shape.contains(&0) {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "resample shape {shape:?} must be non-zero"
+                    )));
+                }
+                ResampleTarget::Shape(shape.clone())
+            }
+            (None, Some(spacing)) => ResampleTarget::Spacing(spacing.clone()),
+            _ => {
+                return Err(FilterError::InvalidParameters(
+                    "resample requires exactly one of a shape or a spacing".to_string(),
+                ))
+            }
+        };
+        for spacing in [self.spacing.as_ref(), self.input_spacing.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if spacing.iter().any(|s| !(s.is_finite() && *s > 0.0)) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "resample spacing {spacing:?} must be positive"
+                )));
+            }
+        }
+        Ok(Box::new(Resample::new(
+            target,
+            self.input_spacing.clone(),
+            self.interpolation,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// The target grid of a [`Resample`].
+#[derive(Debug, Clone)]
+pub enum ResampleTarget {
+    /// A target shape, the physical extent of the input is preserved.
+    Shape(Vec<u64>),
+    /// A target pixel size.
+    Spacing(Vec<f64>),
+}
+
+pub struct Resample {
+    target: ResampleTarget,
+    input_spacing: Option<Vec<f64>>,
+    interpolation: Interpolation,
+    chunk_limit: Option<usize>,
+}
+
+impl Resample {
+    pub fn new(
+        target: ResampleTarget,
+        input_spacing: Option<Vec<f64>>,
+        interpolation: Interpolation,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            target,
+            input_spacing,
+            interpolation,
+            chunk_limit,
+        }
+    }
+
+    /// The pixel size of `input`.
+    ///
+    /// This is the explicit input spacing, the `pixel_size` attribute of `input` if valid, or 1.
+    pub fn input_spacing(&self, input: &Array<FilterStorage>) -> Vec<f64> {
+        self.input_spacing
+            .clone()
+            .or_else(|| {
+                input
+                    .attributes()
+                    .get(PIXEL_SIZE_ATTRIBUTE)
+                    .and_then(|pixel_size| {
+                        serde_json::from_value::<Vec<f64>>(pixel_size.clone()).ok()
+                    })
+                    .filter(|pixel_size| {
+                        pixel_size.len() == input.dimensionality()
+                            && pixel_size.iter().all(|s| s.is_finite() && *s > 0.0)
+                    })
+            })
+            .unwrap_or_else(|| vec![1.0; input.dimensionality()])
+    }
+
+    /// Returns the output shape and the scale (input elements per output element) of each axis.
+    ///
+    /// Returns [`None`] if the target does not match the dimensionality of `input`.
+    pub fn output_shape_scale(&self, input: &Array<FilterStorage>) -> Option<(Vec<u64>, Vec<f64>)> {
+        let input_spacing = self.input_spacing(input);
+        if input_spacing.len() != input.dimensionality() {
+            return None;
+        }
+        match &self.target {
+            ResampleTarget::Shape(shape) => {
+                if shape.len() != input.dimensionality() {
+                    return None;
+                }
+                let scale = std::iter::zip(input.shape(), shape)
+                    .map(|(&input_len, &output_len)| input_len as f64 / output_len as f64)
+                    .collect();
+                Some((shape.clone(), scale))
+            }
+            ResampleTarget::Spacing(spacing) => {
+                if spacing.len() != input.dimensionality() {
+                    return None;
+                }
+                let shape = itertools::izip!(input.shape(), &input_spacing, spacing)
+                    .map(|(&len, input_spacing, spacing)| {
+                        std::cmp::max((len as f64 * input_spacing / spacing).round() as u64, 1)
+                    })
+                    .collect();
+                let scale = std::iter::zip(spacing, &input_spacing)
+                    .map(|(spacing, input_spacing)| spacing / input_spacing)
+                    .collect();
+                Some((shape, scale))
+            }
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        scale: &[f64],
+        round: bool,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<TOut> + AsPrimitive<f64>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let axis_taps = itertools::izip!(
+            subset_output.start(),
+            subset_output.end_exc(),
+            scale,
+            input.shape()
+        )
+        .map(|(&start, end, &scale, &len)| {
+            AxisTaps::new(self.interpolation, start..end, scale, 0.0, len, true)
+        })
+        .collect::<Vec<_>>();
+
+        let output_array = retrieve_resampled::<TIn, TOut>(
+            input,
+            &axis_taps,
+            self.interpolation,
+            round,
+            progress,
+        )?;
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    fn memory_per_chunk_scaled(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+        scale: &[f64],
+    ) -> usize {
+        // The input subset grows with the scale when reducing, and interpolation holds two f64 arrays of up to the input subset size
+        let num_input_elements = std::iter::zip(chunk_output.shape(), scale)
+            .map(|(len, &scale)| {
+                let scale = scale.max(1.0);
+                (len.get() as f64 * scale + 2.0 * self.interpolation.radius() * scale).ceil()
+                    as usize
+                    + 1
+            })
+            .product::<usize>();
+        num_input_elements
+            * (chunk_input.fixed_element_size().unwrap() + core::mem::size_of::<f64>() * 2)
+            + chunk_output.num_elements_usize() * chunk_output.fixed_element_size().unwrap()
+    }
+}
+
+impl FilterTraits for Resample {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        let target_dimensionality = match &self.target {
+            ResampleTarget::Shape(shape) => shape.len(),
+            ResampleTarget::Spacing(spacing) => spacing.len(),
+        };
+        if target_dimensionality != dimensionality
+            || self
+                .input_spacing
+                .as_ref()
+                .is_some_and(|input_spacing| input_spacing.len() != dimensionality)
+        {
+            return Err(FilterError::InvalidParameters(format!(
+                "resample target and input spacing must match the input dimensionality {dimensionality}"
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        self.memory_per_chunk_scaled(
+            chunk_input,
+            chunk_output,
+            &vec![1.0; chunk_output.dimensionality()],
+        )
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<Vec<u64>> {
+        self.output_shape_scale(input).map(|(shape, _scale)| shape)
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        let mut builder = crate::get_array_builder_reencode(
+            &self.output_reencoding_args(array_input, reencoding_args),
+            array_input,
+            self.output_shape(array_input),
+        );
+        if let Some((_shape, scale)) = self.output_shape_scale(array_input) {
+            let pixel_size: Vec<f64> = std::iter::zip(self.input_spacing(array_input), scale)
+                .map(|(input_spacing, scale)| input_spacing * scale)
+                .collect();
+            builder
+                .attributes
+                .insert(PIXEL_SIZE_ATTRIBUTE.to_string(), pixel_size.into());
+        }
+        builder
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let (shape, scale) = self.output_shape_scale(input).unwrap();
+        assert_eq!(output.shape(), shape);
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk_scaled(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &scale,
+            ))?
+        };
+
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &scale, round, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn resample() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![8],
+                DataType::UInt8,
+                vec![4].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &[0u8, 255].repeat(4),
+        )?;
+
+        let resample = |args: &str| run_test_filter("resample", &input, &output, args);
+
+        // Anti-aliasing averages neighbours as f64 without overflowing, and clamps at the edges
+        for args in ["shape: [4]", "spacing: [1.0]\ninput_spacing: [0.5]"] {
+            let array = resample(args)?;
+            assert_eq!(array.shape(), &[4]);
+            assert_eq!(
+                retrieve_test_elements::<u8>(&array)?,
+                vec![96, 128, 128, 159]
+            );
+        }
+        assert_eq!(
+            resample("shape: [4]")?
+                .attributes()
+                .get(PIXEL_SIZE_ATTRIBUTE),
+            Some(&serde_json::json!([2.0]))
+        );
+
+        assert!(resample("shape: [0]").is_err());
+        Ok(())
+    }
+}
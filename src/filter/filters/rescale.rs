@@ -5,13 +5,12 @@ use serde::{Deserialize, Serialize};
 use zarrs::{
     array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
-        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        FilterArguments, FilterCommonArguments,
+        calculate_chunk_limit, elements_from_bytes, elements_to_bytes, filter_error::FilterError,
+        filter_traits::FilterTraits, FilterArguments, FilterCommonArguments, FilterStorage,
     },
     progress::{Progress, ProgressCallback},
 };
@@ -67,8 +66,8 @@ impl Rescale {
 
     pub fn apply_chunk<TIn, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
         chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
@@ -83,19 +82,7 @@ impl Rescale {
         let elements_in =
             progress.read(|| input.retrieve_array_subset_elements::<TIn>(&input_output_subset))?;
 
-        let elements_out = if self.add_first {
-            progress.process(|| {
-                elements_in
-                    .iter()
-                    .map(|value| {
-                        let value_f64: f64 = value.as_();
-                        ((value_f64 + self.add) * self.multiply).as_()
-                    })
-                    .collect::<Vec<TOut>>()
-            })
-        } else {
-            progress.process(|| self.apply_elements(&elements_in))
-        };
+        let elements_out = progress.process(|| self.apply_elements(&elements_in));
         drop(elements_in);
 
         progress.write(|| {
@@ -116,7 +103,11 @@ impl Rescale {
             .par_iter()
             .map(|value| {
                 let value_f64: f64 = value.as_();
-                value_f64.mul_add(self.multiply, self.add).as_()
+                if self.add_first {
+                    ((value_f64 + self.add) * self.multiply).as_()
+                } else {
+                    value_f64.mul_add(self.multiply, self.add).as_()
+                }
             })
             .collect::<Vec<TOut>>()
     }
@@ -157,10 +148,72 @@ impl FilterTraits for Rescale {
         chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
     }
 
+    fn is_elementwise(&self) -> bool {
+        true
+    }
+
+    fn apply_elementwise(
+        &self,
+        bytes: &[u8],
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+    ) -> Result<Vec<u8>, FilterError> {
+        macro_rules! apply_output {
+            ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                match output.data_type() {
+                    $(DataType::$data_type_out => {
+                        let elements_in = elements_from_bytes::<$type_in>(bytes);
+                        Ok(elements_to_bytes(&self.apply_elements::<$type_in, $type_out>(&elements_in)))
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        macro_rules! apply_input {
+            ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                match input.data_type() {
+                    $(DataType::$data_type_in => {
+                        apply_output!($type_in, [
+                            (Bool, u8),
+                            (Int8, i8),
+                            (Int16, i16),
+                            (Int32, i32),
+                            (Int64, i64),
+                            (UInt8, u8),
+                            (UInt16, u16),
+                            (UInt32, u32),
+                            (UInt64, u64),
+                            (BFloat16, half::bf16),
+                            (Float16, half::f16),
+                            (Float32, f32),
+                            (Float64, f64)
+                        ])
+                    } ,)*
+                    _ => panic!()
+                }
+            };
+        }
+        apply_input!([
+            (Bool, u8),
+            (Int8, i8),
+            (Int16, i16),
+            (Int32, i32),
+            (Int64, i64),
+            (UInt8, u8),
+            (UInt16, u16),
+            (UInt32, u32),
+            (UInt64, u64),
+            (BFloat16, half::bf16),
+            (Float16, half::f16),
+            (Float32, f32),
+            (Float64, f64)
+        ])
+    }
+
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
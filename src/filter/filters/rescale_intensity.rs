@@ -0,0 +1,525 @@
+use std::sync::Mutex;
+
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    },
+    slice::ParallelSlice,
+};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::normalize::{
+            calculate_min_max, calculate_min_max_per_slice, check_per_slice_axis, slice_run_index,
+            slice_run_length,
+        },
+        retrieve_array_subset_elements_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct RescaleIntensityArguments {
+    /// The lower percentile to clip to, in the range [0, 100].
+    #[arg(long, default_value_t = 2.0)]
+    #[serde(default = "default_low")]
+    pub low: f64,
+    /// The upper percentile to clip to, in the range [0, 100].
+    #[arg(long, default_value_t = 98.0)]
+    #[serde(default = "default_high")]
+    pub high: f64,
+    /// The number of histogram bins used to estimate the percentiles.
+    #[arg(long, default_value_t = 65536)]
+    #[serde(default = "default_bins")]
+    pub bins: usize,
+    /// The output value of the lower percentile.
+    ///
+    /// Defaults to the minimum of the output data type, or 0 for floating point outputs.
+    #[arg(long, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub out_min: Option<f64>,
+    /// The output value of the upper percentile.
+    ///
+    /// Defaults to the maximum of the output data type, or 1 for floating point outputs.
+    #[arg(long, allow_hyphen_values(true))]
+    #[serde(default)]
+    pub out_max: Option<f64>,
+    /// Compute the percentiles independently for each index along this axis (e.g. per channel or timepoint).
+    ///
+    /// A histogram is held in memory for each index along the axis.
+    #[arg(long)]
+    #[serde(default)]
+    pub per_slice_axis: Option<usize>,
+}
+
+fn default_low() -> f64 {
+    2.0
+}
+
+fn default_high() -> f64 {
+    98.0
+}
+
+fn default_bins() -> usize {
+    65536
+}
+
+impl FilterArguments for RescaleIntensityArguments {
+    fn name(&self) -> String {
+        "rescale_intensity".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if !(0.0..=100.0).contains(&self.low)
+            || !(0.0..=100.0).contains(&self.high)
+            || self.low >= self.high
+        {
+            return Err(FilterError::InvalidParameters(format!(
+                "rescale_intensity percentiles must satisfy 0 <= low < high <= 100, got low {} and high {}",
+                self.low, self.high
+            )));
+        }
+        if self.bins == 0 {
+            return Err(FilterError::InvalidParameters(
+                "rescale_intensity bins must be greater than zero".to_string(),
+            ));
+        }
+        Ok(Box::new(RescaleIntensity::new(
+            (self.low, self.high),
+            self.bins,
+            (self.out_min, self.out_max),
+            self.per_slice_axis,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the value at percentile `percentile` of a histogram with uniform bins spanning `min_max`.
+///
+/// The value is linearly interpolated within the bin containing the percentile.
+pub fn histogram_percentile(histogram: &[u64], min_max: (f64, f64), percentile: f64) -> f64 {
+    let (min, max) = min_max;
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return min;
+    }
+    let bin_width = (max - min) / histogram.len() as f64;
+    let target = percentile / 100.0 * total as f64;
+    let mut cumulative = 0.0;
+    for (bin, &count) in histogram.iter().enumerate() {
+        let count = count as f64;
+        if count > 0.0 && cumulative + count >= target {
+            let fraction = ((target - cumulative) / count).clamp(0.0, 1.0);
+            return min + (bin as f64 + fraction) * bin_width;
+        }
+        cumulative += count;
+    }
+    max
+}
+
+/// Returns the default output range of a data type.
+///
+/// This is the range of integer data types, or [0, 1] otherwise.
+pub fn data_type_range(data_type: &DataType) -> (f64, f64) {
+    match data_type {
+        DataType::Int8 => (i8::MIN as f64, i8::MAX as f64),
+        DataType::Int16 => (i16::MIN as f64, i16::MAX as f64),
+        DataType::Int32 => (i32::MIN as f64, i32::MAX as f64),
+        DataType::Int64 => (i64::MIN as f64, i64::MAX as f64),
+        DataType::UInt8 => (0.0, u8::MAX as f64),
+        DataType::UInt16 => (0.0, u16::MAX as f64),
+        DataType::UInt32 => (0.0, u32::MAX as f64),
+        DataType::UInt64 => (0.0, u64::MAX as f64),
+        _ => (0.0, 1.0),
+    }
+}
+
+/// Returns the bin of `value` in a histogram of `bins` uniform bins spanning `min_max`.
+///
+/// Values outside of `min_max` are assigned to the first or last bin.
+#[inline]
+pub fn histogram_bin(value: f64, min_max: (f64, f64), bins: usize) -> usize {
+    let (min, max) = min_max;
+    if max > min {
+        let norm = (value - min) / (max - min);
+        ((norm * bins as f64).max(0.0).floor() as usize).min(bins - 1)
+    } else {
+        0
+    }
+}
+
+/// Computes a histogram of the finite values of `input` with `bins` uniform bins spanning `min_max`.
+///
+/// # Errors
+/// Returns an error if the input cannot be read.
+pub fn calculate_histogram(
+    input: &Array<FilterStorage>,
+    min_max: (f64, f64),
+    bins: usize,
+    chunk_limit: usize,
+    progress: &Progress,
+) -> Result<Vec<u64>, FilterError> {
+    let chunks = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+    let indices = chunks.indices();
+    let chunk_histogram = |chunk_indices: Vec<u64>| {
+        let chunk_subset = input.chunk_subset_bounded(&chunk_indices).unwrap();
+        let elements =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &chunk_subset))?;
+        let histogram = progress.process(|| {
+            let mut histogram = vec![0u64; bins];
+            elements
+                .iter()
+                .filter(|value| value.is_finite())
+                .for_each(|value| histogram[histogram_bin(*value, min_max, bins)] += 1);
+            histogram
+        });
+        progress.next();
+        Ok::<_, FilterError>(histogram)
+    };
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(chunk_limit, indices, map, chunk_histogram)
+        .try_reduce(
+            || vec![0u64; bins],
+            |a, b| Ok(std::iter::zip(a, b).map(|(a, b)| a + b).collect::<Vec<_>>()),
+        )
+}
+
+/// Computes a histogram of the finite values of each index along `axis` of `input` with `bins` uniform bins spanning the corresponding `min_max`.
+///
+/// # Errors
+/// Returns an error if the input cannot be read.
+pub fn calculate_histogram_per_slice(
+    input: &Array<FilterStorage>,
+    axis: usize,
+    min_max: &[(f64, f64)],
+    bins: usize,
+    chunk_limit: usize,
+    progress: &Progress,
+) -> Result<Vec<Vec<u64>>, FilterError> {
+    let histograms = Mutex::new(vec![vec![0u64; bins]; min_max.len()]);
+    let chunks = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+    let indices = chunks.indices();
+    rayon_iter_concurrent_limit::iter_concurrent_limit!(
+        chunk_limit,
+        indices,
+        try_for_each,
+        |chunk_indices: Vec<u64>| {
+            let chunk_subset = input.chunk_subset_bounded(&chunk_indices).unwrap();
+            let elements =
+                progress.read(|| retrieve_array_subset_elements_f64(input, &chunk_subset))?;
+            progress.process(|| {
+                // Only the slices intersecting the chunk are accumulated locally
+                let slice_start = usize::try_from(chunk_subset.start()[axis]).unwrap();
+                let num_slices = usize::try_from(chunk_subset.shape()[axis]).unwrap();
+                let mut chunk_histograms = vec![vec![0u64; bins]; num_slices];
+                elements
+                    .chunks(slice_run_length(&chunk_subset, axis))
+                    .enumerate()
+                    .for_each(|(run, elements)| {
+                        let slice = slice_run_index(&chunk_subset, axis, run);
+                        let histogram = &mut chunk_histograms[slice - slice_start];
+                        elements
+                            .iter()
+                            .filter(|value| value.is_finite())
+                            .for_each(|value| {
+                                histogram[histogram_bin(*value, min_max[slice], bins)] += 1;
+                            });
+                    });
+                let mut histograms = histograms.lock().unwrap();
+                std::iter::zip(&mut histograms[slice_start..], chunk_histograms).for_each(
+                    |(histogram, chunk_histogram)| {
+                        std::iter::zip(histogram, chunk_histogram).for_each(|(a, b)| *a += b);
+                    },
+                );
+            });
+            progress.next();
+            Ok::<_, FilterError>(())
+        }
+    )?;
+    Ok(histograms.into_inner().unwrap())
+}
+
+pub struct RescaleIntensity {
+    percentiles: (f64, f64),
+    bins: usize,
+    out_range: (Option<f64>, Option<f64>),
+    per_slice_axis: Option<usize>,
+    chunk_limit: Option<usize>,
+}
+
+impl RescaleIntensity {
+    pub fn new(
+        percentiles: (f64, f64),
+        bins: usize,
+        out_range: (Option<f64>, Option<f64>),
+        per_slice_axis: Option<usize>,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            percentiles,
+            bins,
+            out_range,
+            per_slice_axis,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        in_ranges: &[(f64, f64)],
+        out_range: (f64, f64),
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let elements_in =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &input_output_subset))?;
+
+        let elements_out = progress.process(|| {
+            if let Some(axis) = self.per_slice_axis {
+                // Each run of elements sharing an index along the axis has its own input range
+                elements_in
+                    .par_chunks(slice_run_length(&input_output_subset, axis))
+                    .enumerate()
+                    .flat_map_iter(|(run, elements)| {
+                        let slice = slice_run_index(&input_output_subset, axis, run);
+                        Self::apply_elements::<TOut>(elements, in_ranges[slice], out_range, round)
+                    })
+                    .collect()
+            } else {
+                Self::apply_elements::<TOut>(&elements_in, in_ranges[0], out_range, round)
+            }
+        });
+        drop(elements_in);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Clip `elements_in` to `in_range` and linearly stretch to `out_range`.
+    ///
+    /// Values are rounded if `round` is true, which should be the case for integer outputs.
+    /// Non-finite values are passed through, subject to the conversion to `TOut`.
+    pub fn apply_elements<TOut>(
+        elements_in: &[f64],
+        in_range: (f64, f64),
+        out_range: (f64, f64),
+        round: bool,
+    ) -> Vec<TOut>
+    where
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let (in_min, in_max) = in_range;
+        let (out_min, out_max) = out_range;
+        let scale = if in_max > in_min {
+            (out_max - out_min) / (in_max - in_min)
+        } else {
+            0.0
+        };
+        elements_in
+            .par_iter()
+            .map(|&value| {
+                let value = if value.is_finite() {
+                    out_min + (value.clamp(in_min, in_max) - in_min) * scale
+                } else {
+                    value
+                };
+                if round {
+                    value.round().as_()
+                } else {
+                    value.as_()
+                }
+            })
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for RescaleIntensity {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        check_per_slice_axis(
+            "rescale_intensity",
+            self.per_slice_axis,
+            chunk_input.dimensionality(),
+        )?;
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // A histogram per slice of the chunk is accumulated if computing per slice statistics
+        let num_histograms = self
+            .per_slice_axis
+            .and_then(|axis| chunk_input.shape().get(axis))
+            .map_or(1, |size| usize::try_from(size.get()).unwrap());
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + chunk_output.fixed_element_size().unwrap())
+            + num_histograms * self.bins * core::mem::size_of::<u64>()
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let chunks_input = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+        let progress = Progress::new(
+            2 * chunks_input.num_elements_usize() + chunks.num_elements_usize(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let (min_max, histograms) = if let Some(axis) = self.per_slice_axis {
+            let min_max = calculate_min_max_per_slice(input, axis, chunk_limit, &progress)?
+                .into_iter()
+                .map(|min_max| min_max.unwrap_or((0.0, 0.0)))
+                .collect::<Vec<_>>();
+            let histograms = calculate_histogram_per_slice(
+                input,
+                axis,
+                &min_max,
+                self.bins,
+                chunk_limit,
+                &progress,
+            )?;
+            (min_max, histograms)
+        } else {
+            let min_max = calculate_min_max(input, chunk_limit, &progress)?.unwrap_or((0.0, 0.0));
+            let histogram = calculate_histogram(input, min_max, self.bins, chunk_limit, &progress)?;
+            (vec![min_max], vec![histogram])
+        };
+        let in_ranges = std::iter::zip(&histograms, &min_max)
+            .map(|(histogram, &min_max)| {
+                (
+                    histogram_percentile(histogram, min_max, self.percentiles.0),
+                    histogram_percentile(histogram, min_max, self.percentiles.1),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let data_type_range = data_type_range(output.data_type());
+        let out_range = (
+            self.out_range.0.unwrap_or(data_type_range.0),
+            self.out_range.1.unwrap_or(data_type_range.1),
+        );
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, &in_ranges, out_range, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rescale_intensity_percentiles() {
+        // Values 0..100, one per bin
+        let histogram = vec![1u64; 100];
+        let min_max = (0.0, 100.0);
+        assert_eq!(histogram_percentile(&histogram, min_max, 0.0), 0.0);
+        assert_eq!(histogram_percentile(&histogram, min_max, 2.0), 2.0);
+        assert_eq!(histogram_percentile(&histogram, min_max, 98.0), 98.0);
+        assert_eq!(histogram_percentile(&histogram, min_max, 100.0), 100.0);
+        assert_eq!(histogram_percentile(&[0; 4], min_max, 50.0), 0.0);
+
+        let elements = [0.0, 2.0, 50.0, 98.0, 100.0, f64::NAN];
+        let out =
+            RescaleIntensity::apply_elements::<u8>(&elements, (2.0, 98.0), (0.0, 255.0), true);
+        assert_eq!(&out[..5], &[0, 0, 128, 255, 255]);
+    }
+}
@@ -0,0 +1,359 @@
+use clap::Parser;
+use itertools::Itertools;
+use ndarray::{ArrayD, Slice};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct RollArguments {
+    /// The number of elements to shift by along each axis, comma delimited. Elements shifted beyond the end wrap around to the start.
+    ///
+    /// Negative shifts move elements towards the start.
+    #[arg(required = true, value_delimiter = ',', allow_hyphen_values(true))]
+    pub shift: Vec<i64>,
+    /// The axes to shift along, comma delimited. Defaults to all axes.
+    ///
+    /// Must have the same length as the shift if specified.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub axes: Option<Vec<usize>>,
+}
+
+impl FilterArguments for RollArguments {
+    fn name(&self) -> String {
+        "roll".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Roll::new(
+            self.shift.clone(),
+            self.axes.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// A contiguous run of elements along one axis, read from `input_start` and written at `offset` in the output chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollSegment {
+    pub input_start: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+pub struct Roll {
+    shift: Vec<i64>,
+    axes: Option<Vec<usize>>,
+    chunk_limit: Option<usize>,
+}
+
+impl Roll {
+    pub fn new(shift: Vec<i64>, axes: Option<Vec<usize>>, chunk_limit: Option<usize>) -> Self {
+        Self {
+            shift,
+            axes,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the shift along each of the `dimensionality` axes.
+    fn shift_per_axis(&self, dimensionality: usize) -> Vec<i64> {
+        if let Some(axes) = &self.axes {
+            let mut shift = vec![0; dimensionality];
+            for (&axis, &axis_shift) in std::iter::zip(axes, &self.shift) {
+                shift[axis] += axis_shift;
+            }
+            shift
+        } else {
+            self.shift.clone()
+        }
+    }
+
+    /// Returns the input segments of the output range `start..start + len` along an axis of length `axis_len` shifted by `shift`.
+    ///
+    /// There are two segments if the range wraps around the end of the input.
+    pub fn segments(start: u64, len: u64, axis_len: u64, shift: i64) -> Vec<RollSegment> {
+        let input_start = (start as i64 - shift).rem_euclid(axis_len as i64) as u64;
+        if input_start + len <= axis_len {
+            vec![RollSegment {
+                input_start,
+                offset: 0,
+                len,
+            }]
+        } else {
+            let len_first = axis_len - input_start;
+            vec![
+                RollSegment {
+                    input_start,
+                    offset: 0,
+                    len: len_first,
+                },
+                RollSegment {
+                    input_start: 0,
+                    offset: len_first,
+                    len: len - len_first,
+                },
+            ]
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Copy + Default + 'static,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let shift = self.shift_per_axis(input.dimensionality());
+
+        // Each axis of the output chunk reads one or two input segments, so there are up to 2^N input regions
+        let segments = itertools::izip!(
+            subset_output.start(),
+            subset_output.shape(),
+            input.shape(),
+            &shift
+        )
+        .map(|(&start, &len, &axis_len, &shift)| Self::segments(start, len, axis_len, shift))
+        .collect::<Vec<_>>();
+
+        let output_shape = subset_output
+            .shape()
+            .iter()
+            .map(|&len| usize::try_from(len).unwrap())
+            .collect::<Vec<_>>();
+        let mut output_array = ArrayD::<TOut>::default(output_shape);
+        for region in segments.into_iter().multi_cartesian_product() {
+            let subset_input = ArraySubset::new_with_start_shape(
+                region.iter().map(|segment| segment.input_start).collect(),
+                region.iter().map(|segment| segment.len).collect(),
+            )
+            .unwrap();
+            let input_array =
+                progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+            progress.process(|| {
+                let mut output_view = output_array.slice_each_axis_mut(|axis| {
+                    let segment = &region[axis.axis.index()];
+                    let offset = usize::try_from(segment.offset).unwrap();
+                    let len = usize::try_from(segment.len).unwrap();
+                    Slice::from(offset..offset + len)
+                });
+                output_view.zip_mut_with(&input_array, |output, input| *output = input.as_());
+            });
+        }
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Roll {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if let Some(axes) = &self.axes {
+            if axes.len() != self.shift.len() {
+                return Err(FilterError::InvalidParameters(format!(
+                    "roll has {} axes but {} shifts",
+                    axes.len(),
+                    self.shift.len()
+                )));
+            }
+            if let Some(axis) = axes.iter().find(|&&axis| axis >= dimensionality) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "roll axis {axis} is out of bounds for an input with dimensionality {dimensionality}"
+                )));
+            }
+        } else if self.shift.len() != dimensionality {
+            return Err(FilterError::InvalidParameters(format!(
+                "roll has {} shifts but the input has dimensionality {dimensionality}, specify --axes to shift a subset of axes",
+                self.shift.len()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input regions of an output chunk together have the same number of elements as the output chunk
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roll_segments() {
+        // An output range wrapping around the end of the input reads two segments
+        assert_eq!(
+            Roll::segments(0, 4, 10, 2),
+            vec![
+                RollSegment {
+                    input_start: 8,
+                    offset: 0,
+                    len: 2
+                },
+                RollSegment {
+                    input_start: 0,
+                    offset: 2,
+                    len: 2
+                }
+            ]
+        );
+        // Otherwise, a single segment is read
+        assert_eq!(
+            Roll::segments(4, 4, 10, 2),
+            vec![RollSegment {
+                input_start: 2,
+                offset: 0,
+                len: 4
+            }]
+        );
+        // Negative shifts and shifts larger than the axis wrap around
+        assert_eq!(Roll::segments(4, 4, 10, -12), Roll::segments(4, 4, 10, 8));
+        assert_eq!(
+            Roll::segments(8, 2, 10, -3),
+            vec![RollSegment {
+                input_start: 1,
+                offset: 0,
+                len: 2
+            }]
+        );
+
+        let roll = Roll::new(vec![3, -1], Some(vec![2, 0]), None);
+        assert_eq!(roll.shift_per_axis(3), vec![-1, 0, 3]);
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use clap::Parser;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Hypergeometric};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, ArrayError},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleMode {
+    /// Keep each non-fill element with a probability of `amount`.
+    Probability,
+    /// Keep a fraction `amount` of the non-fill elements, chosen uniformly at random.
+    Fraction,
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct SampleArguments {
+    /// The sampling mode.
+    #[arg(value_enum)]
+    pub mode: SampleMode,
+    /// The probability or fraction of non-fill elements to keep, in the range [0, 1].
+    pub amount: f64,
+    /// The random number generator seed. Defaults to a random seed, or 0 with `--deterministic`.
+    ///
+    /// The sample is reproducible for the same seed and output chunk grid.
+    #[arg(long)]
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl FilterArguments for SampleArguments {
+    fn name(&self) -> String {
+        "sample".to_string()
+    }
+
+    fn seed_mut(&mut self) -> Option<&mut Option<u64>> {
+        Some(&mut self.seed)
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if !(0.0..=1.0).contains(&self.amount) {
+            return Err(FilterError::InvalidParameters(format!(
+                "sample amount {} must be in the range [0, 1]",
+                self.amount
+            )));
+        }
+        Ok(Box::new(Sample::new(
+            self.mode,
+            self.amount,
+            self.seed.unwrap_or_else(rand::random),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// How the non-fill elements of a chunk are sampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkSample {
+    /// Keep each non-fill element with a probability.
+    Probability(f64),
+    /// Keep a number of non-fill elements.
+    Count(u64),
+}
+
+/// Keep a sample of the non-fill elements of `bytes`, setting the rest to `fill_value_out`.
+///
+/// Elements equal to `fill_value_in` are always set to `fill_value_out`.
+/// A [`ChunkSample::Count`] must not exceed the number of non-fill elements.
+pub fn sample_elements(
+    bytes: &mut [u8],
+    fill_value_in: &[u8],
+    fill_value_out: &[u8],
+    sample: ChunkSample,
+    rng: &mut impl Rng,
+) {
+    let element_size = fill_value_out.len();
+    match sample {
+        ChunkSample::Probability(probability) => {
+            for element in bytes.chunks_exact_mut(element_size) {
+                if element == fill_value_in || !rng.gen_bool(probability) {
+                    element.copy_from_slice(fill_value_out);
+                }
+            }
+        }
+        ChunkSample::Count(count) => {
+            let non_fill: Vec<usize> = bytes
+                .chunks_exact(element_size)
+                .enumerate()
+                .filter(|(_, element)| *element != fill_value_in)
+                .map(|(i, _)| i)
+                .collect();
+            let count = usize::try_from(count).unwrap();
+            let mut keep = vec![false; bytes.len() / element_size];
+            for i in rand::seq::index::sample(rng, non_fill.len(), count) {
+                keep[non_fill[i]] = true;
+            }
+            for (element, keep) in std::iter::zip(bytes.chunks_exact_mut(element_size), keep) {
+                if !keep {
+                    element.copy_from_slice(fill_value_out);
+                }
+            }
+        }
+    }
+}
+
+/// Distribute `count` samples over chunks with `non_fill` elements each, drawing from `rng`.
+///
+/// Every subset of `count` elements is equally likely, so the number of samples of each chunk is drawn from a hypergeometric distribution conditioned on the preceding chunks.
+pub fn chunk_sample_counts(non_fill: &[u64], count: u64, rng: &mut impl Rng) -> Vec<u64> {
+    let mut remaining_elements: u64 = non_fill.iter().sum();
+    let mut remaining_count = count;
+    non_fill
+        .iter()
+        .map(|&non_fill| {
+            let chunk_count = if remaining_count == 0 || non_fill == 0 {
+                0
+            } else if non_fill == remaining_elements {
+                remaining_count
+            } else {
+                Hypergeometric::new(remaining_elements, non_fill, remaining_count)
+                    .unwrap()
+                    .sample(rng)
+            };
+            remaining_elements -= non_fill;
+            remaining_count -= chunk_count;
+            chunk_count
+        })
+        .collect()
+}
+
+pub struct Sample {
+    mode: SampleMode,
+    amount: f64,
+    seed: u64,
+    chunk_limit: Option<usize>,
+}
+
+impl Sample {
+    pub fn new(mode: SampleMode, amount: f64, seed: u64, chunk_limit: Option<usize>) -> Self {
+        Self {
+            mode,
+            amount,
+            seed,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the random number generator of a stream.
+    ///
+    /// Each chunk draws from its own stream (its linearised index), so the sample does not depend on the order chunks are processed.
+    fn stream_rng(&self, stream: u64) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(stream);
+        rng
+    }
+
+    fn chunk_stream(chunk_indices: &[u64], chunk_grid_shape: &[u64]) -> u64 {
+        std::iter::zip(chunk_indices, chunk_grid_shape)
+            .fold(0, |stream, (index, shape)| stream * shape + index)
+    }
+
+    /// Count the non-fill elements of `input` in each chunk of the output chunk grid.
+    fn count_non_fill(
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunks: &ArraySubset,
+        chunk_limit: usize,
+        progress: &Progress,
+    ) -> Result<Vec<u64>, FilterError> {
+        let fill_value = input.fill_value().as_ne_bytes();
+        let indices = chunks.indices();
+        let mut non_fill: Vec<(Vec<u64>, u64)> =
+            rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                chunk_limit,
+                indices,
+                map,
+                |chunk_indices: Vec<u64>| {
+                    let subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+                    let bytes = progress.read(|| {
+                        Ok::<_, FilterError>(
+                            input
+                                .retrieve_array_subset(&subset)?
+                                .into_fixed()
+                                .map_err(ArrayError::from)?
+                                .into_owned(),
+                        )
+                    })?;
+                    let count = progress.process(|| {
+                        bytes
+                            .chunks_exact(fill_value.len())
+                            .filter(|element| *element != fill_value)
+                            .count() as u64
+                    });
+                    progress.next();
+                    Ok::<_, FilterError>((chunk_indices, count))
+                }
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        non_fill.sort_unstable();
+        Ok(non_fill.into_iter().map(|(_, count)| count).collect())
+    }
+
+    pub fn apply_chunk(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        chunk_grid_shape: &[u64],
+        sample: ChunkSample,
+        progress: &Progress,
+    ) -> Result<(), FilterError> {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let mut bytes = progress.read(|| {
+            Ok::<_, FilterError>(
+                input
+                    .retrieve_array_subset(&input_output_subset)?
+                    .into_fixed()
+                    .map_err(ArrayError::from)?
+                    .into_owned(),
+            )
+        })?;
+
+        progress.process(|| {
+            let mut rng = self.stream_rng(Self::chunk_stream(chunk_indices, chunk_grid_shape));
+            sample_elements(
+                &mut bytes,
+                input.fill_value().as_ne_bytes(),
+                output.fill_value().as_ne_bytes(),
+                sample,
+                &mut rng,
+            );
+        });
+
+        progress.write(|| output.store_array_subset(&input_output_subset, bytes))?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Sample {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            if data_type.fixed_size().is_none() {
+                Err(UnsupportedDataTypeError::from(data_type.to_string()))?;
+            }
+        }
+        if chunk_input.data_type() != chunk_output.data_type() {
+            return Err(FilterError::InvalidParameters(format!(
+                "sample requires the output data type {} to match the input data type {}",
+                chunk_output.data_type(),
+                chunk_input.data_type()
+            )));
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        _chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + match self.mode {
+                    SampleMode::Probability => 0,
+                    SampleMode::Fraction => {
+                        core::mem::size_of::<usize>() + core::mem::size_of::<bool>()
+                    }
+                })
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+        self.is_compatible(
+            &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+        )?;
+
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let chunks = ArraySubset::new_with_shape(chunk_grid_shape.clone());
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        // First pass (fraction): count the non-fill elements of each chunk and distribute the samples over chunks
+        let chunk_counts: HashMap<Vec<u64>, u64> = match self.mode {
+            SampleMode::Probability => HashMap::new(),
+            SampleMode::Fraction => {
+                let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+                let non_fill =
+                    Self::count_non_fill(input, output, &chunks, chunk_limit, &progress)?;
+                let count = (self.amount * non_fill.iter().sum::<u64>() as f64).round() as u64;
+                // The chunk streams are 0..num_chunks, so the distribution draws from the next stream
+                let mut rng = self.stream_rng(chunks.num_elements());
+                std::iter::zip(
+                    &chunks.indices(),
+                    chunk_sample_counts(&non_fill, count, &mut rng),
+                )
+                .collect()
+            }
+        };
+
+        // Second pass: sample each chunk
+        let output_ref = &*output;
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                let sample = match self.mode {
+                    SampleMode::Probability => ChunkSample::Probability(self.amount),
+                    SampleMode::Fraction => ChunkSample::Count(chunk_counts[&chunk_indices]),
+                };
+                self.apply_chunk(
+                    input,
+                    output_ref,
+                    &chunk_indices,
+                    &chunk_grid_shape,
+                    sample,
+                    &progress,
+                )
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_elements_count() {
+        let elements: Vec<u8> = vec![0, 1, 2, 0, 3, 4, 0, 5];
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+
+        let mut bytes = elements.clone();
+        sample_elements(&mut bytes, &[0], &[255], ChunkSample::Count(3), &mut rng);
+        assert_eq!(bytes.iter().filter(|b| **b != 255).count(), 3);
+        assert!(std::iter::zip(&bytes, &elements).all(|(b, e)| *b == 255 || (b == e && *e != 0)));
+
+        let mut bytes = elements.clone();
+        sample_elements(
+            &mut bytes,
+            &[0],
+            &[255],
+            ChunkSample::Probability(1.0),
+            &mut rng,
+        );
+        assert_eq!(bytes, vec![255, 1, 2, 255, 3, 4, 255, 5]);
+
+        let mut bytes = elements.clone();
+        sample_elements(
+            &mut bytes,
+            &[0],
+            &[0],
+            ChunkSample::Probability(0.0),
+            &mut rng,
+        );
+        assert_eq!(bytes, vec![0; 8]);
+    }
+
+    #[test]
+    fn sample_chunk_counts() {
+        let mut rng = ChaCha8Rng::seed_from_u64(42);
+        let non_fill = [10, 0, 5, 20, 1];
+        for count in [0, 1, 17, 36] {
+            let counts = chunk_sample_counts(&non_fill, count, &mut rng);
+            assert_eq!(counts.iter().sum::<u64>(), count);
+            assert!(std::iter::zip(&counts, &non_fill).all(|(c, n)| c <= n));
+        }
+    }
+}
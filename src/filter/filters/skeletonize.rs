@@ -0,0 +1,588 @@
+use std::sync::Arc;
+
+use clap::Parser;
+use ndarray::{ArrayD, Dimension};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, DataType, Element, FillValue,
+    },
+    array_subset::ArraySubset,
+    storage::store::FilesystemStore,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::label::{neighbour_offsets, offset_index, Connectivity},
+        retrieve_array_subset_ndarray_f64, ArraySubsetOverlap, FilterArguments,
+        FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct SkeletonizeArguments {
+    /// The axes to thin over, comma delimited. Must be 2 or 3 axes. Defaults to all axes.
+    ///
+    /// Thinning over 2 axes of a 3D array is applied independently to each slice.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub axes: Option<Vec<usize>>,
+}
+
+impl FilterArguments for SkeletonizeArguments {
+    fn name(&self) -> String {
+        "skeletonize".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if let Some(axes) = &self.axes {
+            let mut axes_unique = axes.clone();
+            axes_unique.sort_unstable();
+            axes_unique.dedup();
+            if axes_unique.len() != axes.len() || !(2..=3).contains(&axes.len()) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "skeletonize axes must be 2 or 3 distinct axes, got {axes:?}"
+                )));
+            }
+        }
+        Ok(Box::new(Skeletonize::new(
+            self.axes.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Element states during thinning.
+const BACKGROUND: u8 = 0;
+const FOREGROUND: u8 = 1;
+const CANDIDATE: u8 = 2;
+
+/// The neighbourhood of an element over the thinning axes.
+struct Neighbourhood {
+    offsets: Vec<Vec<isize>>,
+    full_adjacent: Vec<Vec<usize>>,
+    face_adjacent: Vec<Vec<usize>>,
+    face: Vec<bool>,
+    background: Vec<bool>,
+}
+
+impl Neighbourhood {
+    fn new(dimensionality: usize, axes: &[usize]) -> Self {
+        let offsets_local = neighbour_offsets(axes.len(), Connectivity::Full);
+        let offsets = offsets_local
+            .iter()
+            .map(|offset_local| {
+                let mut offset = vec![0; dimensionality];
+                for (axis, o) in std::iter::zip(axes, offset_local) {
+                    offset[*axis] = *o;
+                }
+                offset
+            })
+            .collect();
+        let num_nonzero = |offset: &[isize]| offset.iter().filter(|&&o| o != 0).count();
+        let adjacent = |is_adjacent: &dyn Fn(&[isize]) -> bool| {
+            offsets_local
+                .iter()
+                .map(|a| {
+                    offsets_local
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, b)| {
+                            let difference = std::iter::zip(a, *b)
+                                .map(|(a, b)| a - b)
+                                .collect::<Vec<_>>();
+                            is_adjacent(&difference)
+                        })
+                        .map(|(j, _)| j)
+                        .collect()
+                })
+                .collect()
+        };
+        Self {
+            full_adjacent: adjacent(&|d| num_nonzero(d) > 0 && d.iter().all(|d| d.abs() <= 1)),
+            face_adjacent: adjacent(&|d| d.iter().map(|d| d.abs()).sum::<isize>() == 1),
+            face: offsets_local.iter().map(|o| num_nonzero(o) == 1).collect(),
+            background: offsets_local.iter().map(|o| num_nonzero(o) <= 2).collect(),
+            offsets,
+        }
+    }
+
+    /// Returns the foreground state of the neighbours of `index`, treating elements outside of `state` as background.
+    fn foreground(&self, state: &ArrayD<u8>, index: &[usize]) -> Vec<bool> {
+        self.offsets
+            .iter()
+            .map(|offset| {
+                offset_index(index, offset, state.shape())
+                    .is_some_and(|neighbour| state[neighbour.as_slice()] != BACKGROUND)
+            })
+            .collect()
+    }
+
+    /// Returns the number of components of `members` under `adjacent` which include a `seeds` element.
+    fn num_components(members: &[bool], adjacent: &[Vec<usize>], seeds: &[bool]) -> usize {
+        let mut visited = vec![false; members.len()];
+        let mut num_components = 0;
+        for start in 0..members.len() {
+            if !members[start] || visited[start] {
+                continue;
+            }
+            let mut seeded = false;
+            let mut stack = vec![start];
+            visited[start] = true;
+            while let Some(i) = stack.pop() {
+                seeded |= seeds[i];
+                for &j in &adjacent[i] {
+                    if members[j] && !visited[j] {
+                        visited[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+            num_components += usize::from(seeded);
+        }
+        num_components
+    }
+
+    /// Returns true if removing an element with `foreground` neighbours preserves topology.
+    ///
+    /// The foreground is fully connected and the background is face connected.
+    fn is_simple(&self, foreground: &[bool]) -> bool {
+        let all = vec![true; foreground.len()];
+        let background = std::iter::zip(foreground, &self.background)
+            .map(|(foreground, background)| !foreground && *background)
+            .collect::<Vec<_>>();
+        Self::num_components(foreground, &self.full_adjacent, &all) == 1
+            && Self::num_components(&background, &self.face_adjacent, &self.face) == 1
+    }
+}
+
+pub struct Skeletonize {
+    axes: Option<Vec<usize>>,
+    chunk_limit: Option<usize>,
+}
+
+impl Skeletonize {
+    pub fn new(axes: Option<Vec<usize>>, chunk_limit: Option<usize>) -> Self {
+        Self { axes, chunk_limit }
+    }
+
+    fn axes(&self, dimensionality: usize) -> Vec<usize> {
+        self.axes
+            .clone()
+            .unwrap_or_else(|| (0..dimensionality).collect())
+    }
+
+    /// Mark the removable foreground elements on the border facing `direction`. Returns true if any were marked.
+    ///
+    /// Only elements where `updatable` is true are marked. End points are preserved.
+    fn mark_candidates(
+        neighbourhood: &Neighbourhood,
+        state: &mut ArrayD<u8>,
+        direction: &[isize],
+        updatable: impl Fn(&[usize]) -> bool,
+    ) -> bool {
+        let candidates = ndarray::indices_of(state)
+            .into_iter()
+            .filter(|index| {
+                let index = index.slice();
+                if state[index] != FOREGROUND || !updatable(index) {
+                    return false;
+                }
+                let border = offset_index(index, direction, state.shape())
+                    .map_or(true, |neighbour| state[neighbour.as_slice()] == BACKGROUND);
+                if !border {
+                    return false;
+                }
+                let foreground = neighbourhood.foreground(state, index);
+                foreground.iter().filter(|f| **f).count() != 1
+                    && neighbourhood.is_simple(&foreground)
+            })
+            .collect::<Vec<_>>();
+        for index in &candidates {
+            state[index] = CANDIDATE;
+        }
+        !candidates.is_empty()
+    }
+
+    /// Sequentially remove the marked elements that are still simple. Returns true if any were removed.
+    ///
+    /// Only elements where `updatable` is true are removed.
+    fn remove_candidates(
+        neighbourhood: &Neighbourhood,
+        state: &mut ArrayD<u8>,
+        updatable: impl Fn(&[usize]) -> bool,
+    ) -> bool {
+        let mut removed = false;
+        for index in ndarray::indices_of(state) {
+            let index = index.slice();
+            if state[index] != CANDIDATE || !updatable(index) {
+                continue;
+            }
+            if neighbourhood.is_simple(&neighbourhood.foreground(state, index)) {
+                state[index] = BACKGROUND;
+                removed = true;
+            } else {
+                state[index] = FOREGROUND;
+            }
+        }
+        removed
+    }
+
+    /// Skeletonize `input` over `axes` in memory.
+    pub fn apply_ndarray(input: &ArrayD<bool>, axes: &[usize]) -> ArrayD<bool> {
+        let neighbourhood = Neighbourhood::new(input.ndim(), axes);
+        let mut state = input.mapv(u8::from);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for direction in Self::directions(input.ndim(), axes) {
+                if Self::mark_candidates(&neighbourhood, &mut state, &direction, |_| true) {
+                    changed |= Self::remove_candidates(&neighbourhood, &mut state, |_| true);
+                }
+            }
+        }
+        state.mapv(|state| state != BACKGROUND)
+    }
+
+    /// Returns the border directions visited in each thinning iteration.
+    fn directions(dimensionality: usize, axes: &[usize]) -> Vec<Vec<isize>> {
+        axes.iter()
+            .flat_map(|axis| {
+                [-1, 1].map(|sign| {
+                    let mut direction = vec![0; dimensionality];
+                    direction[*axis] = sign;
+                    direction
+                })
+            })
+            .collect()
+    }
+
+    fn initialise_chunk(
+        input: &Array<FilterStorage>,
+        state: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError> {
+        let subset = state.chunk_subset_bounded(chunk_indices).unwrap();
+        let input_array = progress.read(|| retrieve_array_subset_ndarray_f64(input, &subset))?;
+        let state_array = progress
+            .process(|| input_array.mapv(|value| u8::from(value != 0.0 && !value.is_nan())));
+        drop(input_array);
+        progress
+            .write(|| state.store_array_subset_ndarray::<u8, _>(subset.start(), state_array))?;
+        progress.next();
+        Ok(())
+    }
+
+    /// Update the thinning state of a chunk with `update`, reading a halo of 1 along `axes`. Returns true if `update` returns true.
+    fn update_chunk(
+        state: &Array<FilterStorage>,
+        axes: &[usize],
+        chunk_indices: &[u64],
+        progress: &Progress,
+        update: impl FnOnce(&mut ArrayD<u8>, &dyn Fn(&[usize]) -> bool) -> bool,
+    ) -> Result<bool, FilterError> {
+        let subset_output = state.chunk_subset_bounded(chunk_indices).unwrap();
+        let mut overlap = vec![0; state.dimensionality()];
+        for axis in axes {
+            overlap[*axis] = 1;
+        }
+        let subset_overlap = ArraySubsetOverlap::new(state.shape(), &subset_output, &overlap);
+
+        let mut state_array = progress
+            .read(|| state.retrieve_array_subset_ndarray::<u8>(subset_overlap.subset_input()))?;
+
+        let updated = progress.process(|| {
+            let subset_chunk = subset_overlap.subset_output_in_input();
+            update(&mut state_array, &|index| {
+                subset_chunk.contains(&index.iter().map(|i| *i as u64).collect::<Vec<_>>())
+            })
+        });
+
+        if updated {
+            progress.write(|| {
+                state.store_array_subset_ndarray::<u8, _>(
+                    subset_output.start(),
+                    subset_overlap.extract_subset(&state_array),
+                )
+            })?;
+        }
+
+        progress.next();
+        Ok(updated)
+    }
+
+    fn finalise_chunk<TOut>(
+        state: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        let subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let state_array = progress.read(|| state.retrieve_array_subset_ndarray::<u8>(&subset))?;
+        let output_array =
+            progress.process(|| state_array.mapv(|state| TOut::from(state != BACKGROUND)));
+        drop(state_array);
+        progress
+            .write(|| output.store_array_subset_ndarray::<TOut, _>(subset.start(), output_array))?;
+        progress.next();
+        Ok(())
+    }
+
+    fn apply_typed<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + From<bool> + 'static,
+    {
+        let dimensionality = output.dimensionality();
+        let axes = self.axes(dimensionality);
+        let neighbourhood = Neighbourhood::new(dimensionality, &axes);
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; dimensionality])?,
+                &output.chunk_array_representation(&vec![0; dimensionality])?,
+            ))?
+        };
+
+        // The thinning state is held in a temporary array
+        let directory = tempfile::TempDir::new()?;
+        let store: Arc<FilterStorage> = Arc::new(
+            FilesystemStore::new(directory.path().join("state.zarr"))
+                .map_err(|err| FilterError::Other(err.to_string()))?,
+        );
+        let state = ArrayBuilder::new(
+            output.shape().to_vec(),
+            DataType::UInt8,
+            output.chunk_grid().clone(),
+            FillValue::from(BACKGROUND),
+        )
+        .build(store, "/")?;
+        state.store_metadata()?;
+
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                Self::initialise_chunk(input, &state, &chunk_indices, &progress)
+            }
+        )?;
+
+        // Thin from each border direction until no elements are removed.
+        // Candidates are marked concurrently, then removed sequentially within each chunk.
+        // Chunks are grouped by the parity of their indices so that neighbouring chunks are never updated concurrently.
+        let num_parities = 1usize << dimensionality;
+        let chunk_parity = |chunk_indices: &[u64]| {
+            chunk_indices
+                .iter()
+                .enumerate()
+                .map(|(axis, i)| ((i % 2) as usize) << axis)
+                .sum::<usize>()
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for direction in Self::directions(dimensionality, &axes) {
+                let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+                let indices = chunks.indices();
+                let marked = rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                    chunk_limit,
+                    indices,
+                    map,
+                    |chunk_indices: Vec<u64>| {
+                        let marked = Self::update_chunk(
+                            &state,
+                            &axes,
+                            &chunk_indices,
+                            &progress,
+                            |state_array, updatable| {
+                                Self::mark_candidates(
+                                    &neighbourhood,
+                                    state_array,
+                                    &direction,
+                                    updatable,
+                                )
+                            },
+                        )?;
+                        Ok::<_, FilterError>(marked.then_some(chunk_indices))
+                    }
+                )
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+
+                for parity in 0..num_parities {
+                    let chunks_parity = marked
+                        .iter()
+                        .filter(|chunk_indices| chunk_parity(chunk_indices) == parity)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if chunks_parity.is_empty() {
+                        continue;
+                    }
+                    let progress = Progress::new(chunks_parity.len(), progress_callback);
+                    let removed = rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                        chunk_limit,
+                        chunks_parity,
+                        map,
+                        |chunk_indices: Vec<u64>| {
+                            let mut removed = false;
+                            Self::update_chunk(
+                                &state,
+                                &axes,
+                                &chunk_indices,
+                                &progress,
+                                |state_array, updatable| {
+                                    removed = Self::remove_candidates(
+                                        &neighbourhood,
+                                        state_array,
+                                        updatable,
+                                    );
+                                    // Unremoved candidates are reset, so the chunk always changes
+                                    true
+                                },
+                            )?;
+                            Ok::<_, FilterError>(removed)
+                        }
+                    )
+                    .collect::<Result<Vec<_>, _>>()?;
+                    changed |= removed.into_iter().any(|removed| removed);
+                }
+            }
+        }
+
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                Self::finalise_chunk::<TOut>(&state, output, &chunk_indices, &progress)
+            }
+        )
+    }
+}
+
+impl FilterTraits for Skeletonize {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        let axes = self.axes(dimensionality);
+        if axes.iter().any(|axis| *axis >= dimensionality) {
+            return Err(FilterError::InvalidParameters(format!(
+                "skeletonize axes {axes:?} are out of bounds for an input with dimensionality {dimensionality}"
+            )));
+        }
+        if !(2..=3).contains(&axes.len()) {
+            return Err(FilterError::InvalidParameters(format!(
+                "skeletonize requires 2 or 3 axes, set axes for an input with dimensionality {dimensionality}"
+            )));
+        }
+        match chunk_input.data_type() {
+            DataType::Bool
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::Bool | DataType::UInt8 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + 2 * core::mem::size_of::<u8>()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::Bool, FillValue::from(false)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+        match output.data_type() {
+            DataType::Bool => self.apply_typed::<bool>(input, output, progress_callback),
+            DataType::UInt8 => self.apply_typed::<u8>(input, output, progress_callback),
+            _ => panic!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::filters::label::label_ndarray;
+
+    #[test]
+    fn skeletonize_centerline() {
+        // A 3 element thick bar thins to its centerline, keeping its end points
+        let mut input = ArrayD::from_elem(vec![5, 9], false);
+        input.slice_mut(ndarray::s![1..4, 1..8]).fill(true);
+        let skeleton = Skeletonize::apply_ndarray(&input, &[0, 1]);
+        assert_eq!(skeleton.iter().filter(|s| **s).count(), 7);
+        assert!(skeleton.slice(ndarray::s![2, 1..8]).iter().all(|s| *s));
+
+        // A square with a hole thins to a loop around the hole
+        let mut input = ArrayD::from_elem(vec![7, 7], true);
+        input[[3, 3]] = false;
+        let skeleton = Skeletonize::apply_ndarray(&input, &[0, 1]);
+        let (_, num_background) = label_ndarray(&skeleton.mapv(|s| !s), Connectivity::Face);
+        assert_eq!(num_background, 2);
+
+        // Thinning over the last 2 axes of a 3D array is slice-wise
+        let mut input = ArrayD::from_elem(vec![2, 5, 9], false);
+        input.slice_mut(ndarray::s![.., 1..4, 1..8]).fill(true);
+        let skeleton = Skeletonize::apply_ndarray(&input, &[1, 2]);
+        assert_eq!(skeleton.iter().filter(|s| **s).count(), 14);
+    }
+}
@@ -0,0 +1,427 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, FillValue},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        retrieve_array_subset_elements_f64, FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct StandardizeArguments {
+    /// Standardize each index along this axis independently (e.g. per channel).
+    ///
+    /// Either an axis index or a dimension name.
+    #[arg(long)]
+    #[serde(default)]
+    pub axis: Option<String>,
+}
+
+impl FilterArguments for StandardizeArguments {
+    fn name(&self) -> String {
+        "standardize".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Standardize::new(
+            self.axis.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Running count, mean, and sum of squared differences from the mean of a sequence of values.
+///
+/// Uses Welford's algorithm, and the parallel variant of Chan et al. to merge.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Moments {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    #[inline]
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    #[must_use]
+    pub fn merge(self, other: Self) -> Self {
+        if self.count == 0 {
+            return other;
+        } else if other.count == 0 {
+            return self;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        Self {
+            count,
+            mean: self.mean + delta * other.count as f64 / count as f64,
+            m2: self.m2
+                + other.m2
+                + delta * delta * (self.count as f64 * other.count as f64) / count as f64,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The population variance.
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    /// The population standard deviation.
+    pub fn std(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// Resolve an axis given as an index or a dimension name of `array`.
+///
+/// # Errors
+/// Returns [`FilterError::InvalidParameters`] if `axis` is out of bounds or does not match a dimension name.
+pub fn resolve_axis(axis: &str, array: &Array<FilterStorage>) -> Result<usize, FilterError> {
+    let index = if let Ok(index) = axis.parse::<usize>() {
+        Some(index)
+    } else {
+        array
+            .dimension_names()
+            .as_ref()
+            .and_then(|dimension_names| {
+                dimension_names
+                    .iter()
+                    .position(|dimension_name| dimension_name.as_str() == Some(axis))
+            })
+    };
+    match index {
+        Some(index) if index < array.dimensionality() => Ok(index),
+        _ => Err(FilterError::InvalidParameters(format!(
+            "axis {axis} is not an axis index or dimension name of the input"
+        ))),
+    }
+}
+
+pub struct Standardize {
+    axis: Option<String>,
+    chunk_limit: Option<usize>,
+}
+
+impl Standardize {
+    pub fn new(axis: Option<String>, chunk_limit: Option<usize>) -> Self {
+        Self { axis, chunk_limit }
+    }
+
+    /// Compute the [`Moments`] of the finite values of a chunk, for each index along `axis` if set.
+    fn chunk_moments(
+        &self,
+        input: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        axis: Option<usize>,
+        progress: &Progress,
+    ) -> Result<Vec<Moments>, FilterError> {
+        let chunk_subset = input.chunk_subset_bounded(chunk_indices).unwrap();
+        let elements =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &chunk_subset))?;
+        let moments = progress.process(|| {
+            let mut moments = vec![Moments::default(); Self::num_moments(input, axis)];
+            if let Some(axis) = axis {
+                let shape = chunk_subset
+                    .shape()
+                    .iter()
+                    .map(|s| usize::try_from(*s).unwrap())
+                    .collect::<Vec<_>>();
+                let elements = ndarray::ArrayViewD::from_shape(shape, &elements).unwrap();
+                let offset = usize::try_from(chunk_subset.start()[axis]).unwrap();
+                for (i, subview) in elements.axis_iter(ndarray::Axis(axis)).enumerate() {
+                    let moments = &mut moments[offset + i];
+                    subview
+                        .iter()
+                        .filter(|value| value.is_finite())
+                        .for_each(|value| moments.push(*value));
+                }
+            } else {
+                elements
+                    .iter()
+                    .filter(|value| value.is_finite())
+                    .for_each(|value| moments[0].push(*value));
+            }
+            moments
+        });
+        progress.next();
+        Ok(moments)
+    }
+
+    fn num_moments(input: &Array<FilterStorage>, axis: Option<usize>) -> usize {
+        axis.map_or(1, |axis| usize::try_from(input.shape()[axis]).unwrap())
+    }
+
+    /// Compute the [`Moments`] of the finite values of `input`, for each index along `axis` if set.
+    ///
+    /// # Errors
+    /// Returns an error if the input cannot be read.
+    pub fn calculate_moments(
+        &self,
+        input: &Array<FilterStorage>,
+        axis: Option<usize>,
+        chunk_limit: usize,
+        progress: &Progress,
+    ) -> Result<Vec<Moments>, FilterError> {
+        let chunks = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+        let indices = chunks.indices();
+        let num_moments = Self::num_moments(input, axis);
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            map,
+            |chunk_indices: Vec<u64>| self.chunk_moments(input, &chunk_indices, axis, progress)
+        )
+        .try_reduce(
+            || vec![Moments::default(); num_moments],
+            |a, b| {
+                Ok(std::iter::zip(a, b)
+                    .map(|(a, b)| a.merge(b))
+                    .collect::<Vec<_>>())
+            },
+        )
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        axis: Option<usize>,
+        moments: &[Moments],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let elements_in =
+            progress.read(|| retrieve_array_subset_elements_f64(input, &input_output_subset))?;
+
+        let elements_out = progress.process(|| {
+            let standardize = |value: f64, moments: &Moments| {
+                let std = moments.std();
+                if std > 0.0 {
+                    (value - moments.mean()) / std
+                } else {
+                    value - moments.mean()
+                }
+            };
+            if let Some(axis) = axis {
+                let shape = input_output_subset
+                    .shape()
+                    .iter()
+                    .map(|s| usize::try_from(*s).unwrap())
+                    .collect::<Vec<_>>();
+                let offset = usize::try_from(input_output_subset.start()[axis]).unwrap();
+                let mut elements = ndarray::ArrayD::from_shape_vec(shape, elements_in).unwrap();
+                for (i, mut subview) in elements.axis_iter_mut(ndarray::Axis(axis)).enumerate() {
+                    let moments = &moments[offset + i];
+                    subview.mapv_inplace(|value| standardize(value, moments));
+                }
+                elements
+                    .iter()
+                    .map(|value| value.as_())
+                    .collect::<Vec<TOut>>()
+            } else {
+                elements_in
+                    .into_par_iter()
+                    .map(|value| standardize(value, &moments[0]).as_())
+                    .collect::<Vec<TOut>>()
+            }
+        });
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Standardize {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        match input.data_type() {
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16 => None,
+            _ => Some((DataType::Float32, FillValue::from(0.0f32))),
+        }
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let axis = self
+            .axis
+            .as_ref()
+            .map(|axis| resolve_axis(axis, input))
+            .transpose()?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let chunks_input = ArraySubset::new_with_shape(input.chunk_grid_shape().unwrap());
+        let progress = Progress::new(
+            chunks_input.num_elements_usize() + chunks.num_elements_usize(),
+            progress_callback,
+        );
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let moments = self.calculate_moments(input, axis, chunk_limit, &progress)?;
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, &output, &chunk_indices, axis, &moments, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn standardize() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        let mut builder = ArrayBuilder::new(
+            vec![2, 4],
+            DataType::Float32,
+            vec![1, 2].try_into().unwrap(),
+            0.0f32.into(),
+        );
+        builder.dimension_names(Some(["c", "x"]));
+        create_test_array(
+            &input,
+            &builder,
+            &[1.0f32, 3.0, f32::NAN, f32::INFINITY, 5.0, 5.0, 5.0, 5.0],
+        )?;
+
+        let standardize = |axis: &str| -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+            let args = format!("axis: \"{axis}\"");
+            retrieve_test_elements(&run_test_filter("standardize", &input, &output, &args)?)
+        };
+
+        // Moments ignore non-finite values, which are passed through, and a constant channel is only centred
+        let elements = standardize("c")?;
+        assert_eq!(elements[..2], [-1.0, 1.0]);
+        assert!(elements[2].is_nan());
+        assert_eq!(elements[3..], [f32::INFINITY, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(standardize("0")?[..2], [-1.0, 1.0]);
+
+        assert!(standardize("z").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,519 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayIndices, ArrayShape, DataType, Element,
+        ElementOwned,
+    },
+    array_subset::ArraySubset,
+    storage::store::FilesystemStore,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage, PathOrIdentifier,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StitchBlend {
+    /// Later inputs overwrite earlier inputs.
+    #[default]
+    Overwrite,
+    /// The mean of the overlapping inputs.
+    Average,
+    /// The maximum of the overlapping inputs.
+    Max,
+}
+
+/// An additional input of a [`StitchLayout`] and its offset in the output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StitchInput {
+    pub path: PathOrIdentifier,
+    pub offset: ArrayIndices,
+}
+
+/// The layout of the inputs of the stitch filter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StitchLayout {
+    /// The offset of the primary input in the output. Defaults to the origin.
+    #[serde(default)]
+    pub offset: Option<ArrayIndices>,
+    /// The additional inputs, in the order they are stitched after the primary input.
+    pub inputs: Vec<StitchInput>,
+    /// The output shape. Defaults to the bounding box of the inputs.
+    #[serde(default)]
+    pub shape: Option<ArrayShape>,
+}
+
+fn parse_stitch_layout(path: &str) -> std::io::Result<StitchLayout> {
+    let layout = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&layout)?)
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct StitchArguments {
+    /// Path to a JSON layout file.
+    ///
+    /// The layout holds the additional inputs and their offsets in the output, and optionally the offset of the primary input and the output shape:
+    ///   {"offset": [0, 0], "inputs": [{"path": "tile_1.zarr", "offset": [0, 512]}], "shape": [512, 1024]}
+    /// The output shape must be specified if an input does not exist before the filter runs (e.g. a temporary output of a run configuration).
+    /// The layout is given inline rather than as a path in a run configuration.
+    #[arg(value_parser = parse_stitch_layout, verbatim_doc_comment)]
+    pub layout: StitchLayout,
+    /// How overlapping inputs are blended.
+    #[arg(long, value_enum, default_value_t = StitchBlend::Overwrite)]
+    #[serde(default)]
+    pub blend: StitchBlend,
+}
+
+impl FilterArguments for StitchArguments {
+    fn name(&self) -> String {
+        "stitch".to_string()
+    }
+
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        self.layout
+            .inputs
+            .iter()
+            .map(|input| input.path.clone())
+            .collect()
+    }
+
+    fn additional_inputs_match_input_shape(&self) -> bool {
+        false
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        // The shapes of the additional inputs are needed to infer the output shape
+        let input_shapes = if self.layout.shape.is_some() {
+            None
+        } else {
+            Some(
+                self.layout
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        let PathOrIdentifier::Path(path) = &input.path else {
+                            return Err(FilterError::InvalidParameters(
+                                "stitch requires a layout shape with temporary inputs".to_string(),
+                            ));
+                        };
+                        let store = FilesystemStore::new(path)
+                            .map_err(|err| FilterError::Other(err.to_string()))?;
+                        Ok(Array::open(store.into(), "/")?.shape().to_vec())
+                    })
+                    .collect::<Result<Vec<_>, FilterError>>()?,
+            )
+        };
+        Ok(Box::new(Stitch::new(
+            self.layout.clone(),
+            input_shapes,
+            self.blend,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns the overlap of `subset` with an input of `shape` at `offset`, or [`None`] if they are disjoint.
+fn stitch_overlap(subset: &ArraySubset, offset: &[u64], shape: &[u64]) -> Option<ArraySubset> {
+    let ranges = itertools::izip!(subset.start(), subset.shape(), offset, shape)
+        .map(|(start, size, offset, shape)| {
+            let overlap_start = std::cmp::max(*start, *offset);
+            let overlap_end = std::cmp::min(start + size, offset + shape);
+            (overlap_start < overlap_end).then_some(overlap_start..overlap_end)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(ArraySubset::new_with_ranges(&ranges))
+}
+
+/// A region of an input, relative to an output subset, with its elements and fill value.
+pub type StitchRegion<T> = (ArraySubset, Vec<T>, T);
+
+/// Blend input `regions` into an output subset of `shape`.
+///
+/// Input elements equal to the fill value of their input are not blended, and output elements without any input are `fill_value`.
+/// Averages are rounded if `round` is true, which should be the case for integer outputs.
+pub fn stitch_elements<TIn, TOut>(
+    regions: &[StitchRegion<TIn>],
+    shape: &[u64],
+    blend: StitchBlend,
+    fill_value: TOut,
+    round: bool,
+) -> Vec<TOut>
+where
+    TIn: Copy + PartialEq + AsPrimitive<TOut> + AsPrimitive<f64>,
+    TOut: Copy + PartialOrd + 'static,
+    f64: AsPrimitive<TOut>,
+{
+    let num_elements = usize::try_from(shape.iter().product::<u64>()).unwrap();
+    let mut elements = vec![fill_value; num_elements];
+    let region_elements = regions.iter().flat_map(|(subset, elements_in, fill_in)| {
+        let indices = subset.linearised_indices(shape).unwrap();
+        std::iter::zip(indices.iter().collect::<Vec<_>>(), elements_in)
+            .filter(move |(_, element)| *element != fill_in)
+            .map(|(index, element)| (usize::try_from(index).unwrap(), *element))
+    });
+    match blend {
+        StitchBlend::Overwrite | StitchBlend::Max => {
+            let mut written = vec![false; num_elements];
+            for (index, element) in region_elements {
+                let element: TOut = element.as_();
+                if blend == StitchBlend::Overwrite || !written[index] || element > elements[index] {
+                    elements[index] = element;
+                    written[index] = true;
+                }
+            }
+        }
+        StitchBlend::Average => {
+            let mut sum = vec![0.0f64; num_elements];
+            let mut count = vec![0u32; num_elements];
+            for (index, element) in region_elements {
+                sum[index] += AsPrimitive::<f64>::as_(element);
+                count[index] += 1;
+            }
+            for (element, sum, count) in itertools::izip!(&mut elements, sum, count) {
+                if count > 0 {
+                    let mean = sum / f64::from(count);
+                    *element = if round {
+                        mean.round().as_()
+                    } else {
+                        mean.as_()
+                    };
+                }
+            }
+        }
+    }
+    elements
+}
+
+pub struct Stitch {
+    layout: StitchLayout,
+    input_shapes: Option<Vec<ArrayShape>>,
+    blend: StitchBlend,
+    chunk_limit: Option<usize>,
+}
+
+impl Stitch {
+    /// Create a new stitch filter.
+    ///
+    /// `input_shapes` are the shapes of the additional inputs of `layout`, and are required if it does not have a shape.
+    pub fn new(
+        layout: StitchLayout,
+        input_shapes: Option<Vec<ArrayShape>>,
+        blend: StitchBlend,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            layout,
+            input_shapes,
+            blend,
+            chunk_limit,
+        }
+    }
+
+    fn input_offset(&self, input: &Array<FilterStorage>) -> ArrayIndices {
+        self.layout
+            .offset
+            .clone()
+            .unwrap_or_else(|| vec![0; input.dimensionality()])
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        inputs: &[(&Array<FilterStorage>, ArrayIndices)],
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + bytemuck::Pod + PartialEq + AsPrimitive<TOut> + AsPrimitive<f64>,
+        TOut: Element + bytemuck::Pod + PartialOrd + Send + Sync,
+        f64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let regions = progress.read(|| {
+            inputs
+                .iter()
+                .filter_map(|(input, offset)| {
+                    stitch_overlap(&subset_output, offset, input.shape())
+                        .map(|overlap| (input, offset, overlap))
+                })
+                .map(|(input, offset, overlap)| {
+                    let elements = input.retrieve_array_subset_elements::<TIn>(
+                        &overlap.relative_to(offset).unwrap(),
+                    )?;
+                    let fill_value: TIn =
+                        bytemuck::pod_read_unaligned(input.fill_value().as_ne_bytes());
+                    Ok::<_, FilterError>((
+                        overlap.relative_to(subset_output.start()).unwrap(),
+                        elements,
+                        fill_value,
+                    ))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        let elements = progress.process(|| {
+            let fill_value: TOut = bytemuck::pod_read_unaligned(output.fill_value().as_ne_bytes());
+            stitch_elements(
+                &regions,
+                subset_output.shape(),
+                self.blend,
+                fill_value,
+                round,
+            )
+        });
+        drop(regions);
+
+        progress.write(|| output.store_array_subset_elements::<TOut>(&subset_output, &elements))?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Stitch {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // Each input may overlap the entire output chunk
+        chunk_output.num_elements_usize()
+            * ((self.layout.inputs.len() + 1) * chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap()
+                + match self.blend {
+                    StitchBlend::Overwrite | StitchBlend::Max => core::mem::size_of::<bool>(),
+                    StitchBlend::Average => {
+                        core::mem::size_of::<f64>() + core::mem::size_of::<u32>()
+                    }
+                })
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        if let Some(shape) = &self.layout.shape {
+            return Some(shape.clone());
+        }
+        let input_shapes = self.input_shapes.as_ref()?;
+        let mut shape = std::iter::zip(self.input_offset(input), input.shape())
+            .map(|(offset, shape)| offset + shape)
+            .collect::<Vec<_>>();
+        for (stitch_input, input_shape) in std::iter::zip(&self.layout.inputs, input_shapes) {
+            for (shape, offset, input_shape) in
+                itertools::izip!(&mut shape, &stitch_input.offset, input_shape)
+            {
+                *shape = std::cmp::max(*shape, offset + input_shape);
+            }
+        }
+        Some(shape)
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        self.apply_inputs(&[input], output, progress_callback)
+    }
+
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let Some((&input, additional_inputs)) = inputs.split_first() else {
+            return Err(FilterError::InvalidParameters(
+                "stitch requires an input".to_string(),
+            ));
+        };
+        if additional_inputs.len() != self.layout.inputs.len() {
+            return Err(FilterError::InvalidParameters(format!(
+                "stitch requires {} additional inputs, got {}",
+                self.layout.inputs.len(),
+                additional_inputs.len()
+            )));
+        }
+        let inputs: Vec<(&Array<FilterStorage>, ArrayIndices)> =
+            std::iter::once((input, self.input_offset(input)))
+                .chain(
+                    std::iter::zip(additional_inputs, &self.layout.inputs)
+                        .map(|(array, stitch_input)| (*array, stitch_input.offset.clone())),
+                )
+                .collect();
+        for (array, offset) in &inputs {
+            if array.dimensionality() != output.dimensionality()
+                || offset.len() != output.dimensionality()
+            {
+                return Err(FilterError::InvalidParameters(format!(
+                    "stitch input shape {:?} and offset {:?} do not match the output dimensionality {}",
+                    array.shape(),
+                    offset,
+                    output.dimensionality()
+                )));
+            }
+            if array.data_type() != input.data_type() {
+                return Err(FilterError::InvalidParameters(format!(
+                    "stitch input data type {} does not match the primary input data type {}",
+                    array.data_type(),
+                    input.data_type()
+                )));
+            }
+        }
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let output = &*output;
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(&inputs, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stitch_overlaps() {
+        let subset = ArraySubset::new_with_ranges(&[2..6, 0..4]);
+        assert_eq!(
+            stitch_overlap(&subset, &[4, 3], &[10, 10]),
+            Some(ArraySubset::new_with_ranges(&[4..6, 3..4]))
+        );
+        assert_eq!(stitch_overlap(&subset, &[6, 0], &[10, 10]), None);
+        assert_eq!(stitch_overlap(&subset, &[0, 0], &[2, 10]), None);
+    }
+
+    #[test]
+    fn stitch_blend() {
+        // Two 2x2 inputs overlapping in the middle column of a 2x3 output, the second with a fill value element
+        let regions: Vec<StitchRegion<u8>> = vec![
+            (
+                ArraySubset::new_with_ranges(&[0..2, 0..2]),
+                vec![1, 2, 3, 4],
+                0,
+            ),
+            (
+                ArraySubset::new_with_ranges(&[0..2, 1..3]),
+                vec![9, 5, 0, 7],
+                0,
+            ),
+        ];
+        let stitch = |blend| stitch_elements::<u8, u8>(&regions, &[2, 3], blend, 255, true);
+        assert_eq!(stitch(StitchBlend::Overwrite), vec![1, 9, 5, 3, 4, 7]);
+        assert_eq!(stitch(StitchBlend::Max), vec![1, 9, 5, 3, 4, 7]);
+        assert_eq!(stitch(StitchBlend::Average), vec![1, 6, 5, 3, 4, 7]);
+
+        let regions: Vec<StitchRegion<u8>> =
+            vec![(ArraySubset::new_with_ranges(&[0..1, 1..2]), vec![3], 0)];
+        assert_eq!(
+            stitch_elements::<u8, f32>(&regions, &[2, 2], StitchBlend::Average, -1.0, false),
+            vec![-1.0, 3.0, -1.0, -1.0]
+        );
+    }
+}
@@ -1,26 +1,109 @@
-use std::ops::AddAssign;
-
 use clap::Parser;
-use itertools::Itertools;
-use num_traits::{AsPrimitive, Zero};
+use ndarray::{ArrayD, Axis};
+use num_traits::{AsPrimitive, CheckedAdd, CheckedSub, NumCast, Zero};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use zarrs::{
-    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array::{
+        data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned, FillValue,
+    },
     array_subset::ArraySubset,
-    storage::store::FilesystemStore,
 };
 
 use crate::{
     filter::{
         calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
-        FilterArguments, FilterCommonArguments,
+        FilterArguments, FilterCommonArguments, FilterStorage,
     },
     progress::{Progress, ProgressCallback},
 };
 
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummedAreaTableAccumulator {
+    /// Accumulate in a 64-bit signed integer.
+    Int64,
+    /// Accumulate in a 64-bit unsigned integer.
+    #[value(name = "uint64")]
+    #[serde(rename = "uint64")]
+    UInt64,
+    /// Accumulate in a 64-bit float.
+    Float64,
+}
+
+impl SummedAreaTableAccumulator {
+    /// The default accumulator for an input data type.
+    pub fn default_for(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Bool
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64 => Self::UInt64,
+            DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => Self::Int64,
+            _ => Self::Float64,
+        }
+    }
+
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Self::Int64 => DataType::Int64,
+            Self::UInt64 => DataType::UInt64,
+            Self::Float64 => DataType::Float64,
+        }
+    }
+
+    pub fn fill_value(&self) -> FillValue {
+        match self {
+            Self::Int64 => FillValue::from(0i64),
+            Self::UInt64 => FillValue::from(0u64),
+            Self::Float64 => FillValue::from(0.0f64),
+        }
+    }
+}
+
+/// An accumulator of a summed area table, which checks for overflow if it is an integer.
+pub trait Accumulator: Copy + Send + Sync + Zero + NumCast + 'static {
+    fn add_checked(self, rhs: Self) -> Option<Self>;
+    fn sub_checked(self, rhs: Self) -> Option<Self>;
+}
+
+macro_rules! impl_accumulator_integer {
+    ($t:ty) => {
+        impl Accumulator for $t {
+            fn add_checked(self, rhs: Self) -> Option<Self> {
+                CheckedAdd::checked_add(&self, &rhs)
+            }
+            fn sub_checked(self, rhs: Self) -> Option<Self> {
+                CheckedSub::checked_sub(&self, &rhs)
+            }
+        }
+    };
+}
+
+impl_accumulator_integer!(i64);
+impl_accumulator_integer!(u64);
+
+impl Accumulator for f64 {
+    fn add_checked(self, rhs: Self) -> Option<Self> {
+        Some(self + rhs)
+    }
+    fn sub_checked(self, rhs: Self) -> Option<Self> {
+        Some(self - rhs)
+    }
+}
+
 #[derive(Debug, Clone, Parser, Serialize, Deserialize)]
-pub struct SummedAreaTableArguments {}
+pub struct SummedAreaTableArguments {
+    /// The accumulator data type.
+    ///
+    /// Defaults to uint64 for bool/unsigned integer inputs, int64 for signed integer inputs, and float64 otherwise.
+    /// The output data type defaults to the accumulator data type.
+    /// Integer overflow of the accumulator or the output data type is an error.
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    pub accumulator: Option<SummedAreaTableAccumulator>,
+}
 
 impl FilterArguments for SummedAreaTableArguments {
     fn name(&self) -> String {
@@ -31,77 +114,150 @@ impl FilterArguments for SummedAreaTableArguments {
         &self,
         common_args: &FilterCommonArguments,
     ) -> Result<Box<dyn FilterTraits>, FilterError> {
-        Ok(Box::new(SummedAreaTable::new(*common_args.chunk_limit())))
+        Ok(Box::new(SummedAreaTable::new(
+            self.accumulator,
+            *common_args.chunk_limit(),
+        )))
     }
 }
 
 pub struct SummedAreaTable {
+    accumulator: Option<SummedAreaTableAccumulator>,
     chunk_limit: Option<usize>,
 }
 
+fn overflow_error() -> FilterError {
+    FilterError::Other(
+        "summed area table overflow: use a wider --accumulator or output data type".to_string(),
+    )
+}
+
 impl SummedAreaTable {
-    pub fn new(chunk_limit: Option<usize>) -> Self {
-        Self { chunk_limit }
+    pub fn new(
+        accumulator: Option<SummedAreaTableAccumulator>,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            accumulator,
+            chunk_limit,
+        }
+    }
+
+    fn accumulator(&self, input: &Array<FilterStorage>) -> SummedAreaTableAccumulator {
+        self.accumulator
+            .unwrap_or_else(|| SummedAreaTableAccumulator::default_for(input.data_type()))
     }
 
-    pub fn apply_dim<TIn, TOut>(
+    /// Groups the chunks of a chunk grid into wavefronts of chunks with the same sum of chunk indices.
+    ///
+    /// The chunks of a wavefront only depend on chunks in prior wavefronts, so they can be processed in parallel.
+    pub fn wavefronts(chunk_grid_shape: &[u64]) -> Vec<Vec<Vec<u64>>> {
+        let num_wavefronts = chunk_grid_shape
+            .iter()
+            .map(|len| len.saturating_sub(1))
+            .sum::<u64>()
+            + 1;
+        let mut wavefronts = vec![vec![]; usize::try_from(num_wavefronts).unwrap()];
+        for chunk_indices in &ArraySubset::new_with_shape(chunk_grid_shape.to_vec()).indices() {
+            let wavefront = usize::try_from(chunk_indices.iter().sum::<u64>()).unwrap();
+            wavefronts[wavefront].push(chunk_indices);
+        }
+        wavefronts
+    }
+
+    /// Compute the summed area table of a chunk.
+    ///
+    /// The local summed area table of the chunk is offset by the summed area table on the faces, edges, corners, etc. preceding the chunk,
+    /// which are read from the output by inclusion–exclusion.
+    pub fn apply_chunk<TIn, TAcc, TOut>(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &Array<FilesystemStore>,
-        chunk_start_dim: &[u64],
-        chunk_grid_shape: &[u64],
-        dim: usize,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
         progress: &Progress,
     ) -> Result<(), FilterError>
     where
-        TIn: ElementOwned + Send + Sync,
-        TOut: Element + ElementOwned + Send + Sync + Zero + AddAssign + Copy + 'static,
-        TIn: AsPrimitive<TOut>,
+        TIn: ElementOwned + Send + Sync + AsPrimitive<TAcc>,
+        TAcc: Accumulator,
+        TOut: Element + ElementOwned + Send + Sync + Copy + NumCast + AsPrimitive<TAcc>,
     {
-        let dimensionality = chunk_start_dim.len();
-        let chunk_shape =
-            zarrs::array::chunk_shape_to_array_shape(&output.chunk_shape(chunk_start_dim)?);
-        let mut last_shape = chunk_shape
-            .iter()
-            .map(|i| usize::try_from(*i).unwrap())
-            .collect::<Vec<_>>();
-        last_shape[dim] = 1;
-        let mut sum_last = ndarray::ArrayD::<TOut>::zeros(last_shape);
-        for i in 0..chunk_grid_shape[dim] {
-            let chunk_indices = chunk_start_dim
-                .iter()
-                .enumerate()
-                .map(|(dim_i, indices)| if dim_i == dim { i } else { *indices })
-                .collect::<Vec<_>>();
-            let mut chunk = progress.read(|| {
-                let chunk_subset = output.chunk_subset(&chunk_indices)?;
-                if dim == dimensionality - 1 {
-                    input
-                        .retrieve_array_subset_ndarray::<TIn>(&chunk_subset)
-                        .map(|array| array.map(|v| v.as_()))
-                } else {
-                    output.retrieve_chunk_ndarray::<TOut>(&chunk_indices)
+        let subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let dimensionality = subset.dimensionality();
+
+        let input_array = progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset))?;
+        let mut sat: ArrayD<TAcc> = progress.process(|| input_array.map(|v| v.as_()));
+        drop(input_array);
+
+        // Local summed area table
+        progress.process(|| {
+            for axis in 0..dimensionality {
+                for mut lane in sat.lanes_mut(Axis(axis)) {
+                    let mut acc = TAcc::zero();
+                    for element in &mut lane {
+                        acc = acc.add_checked(*element).ok_or_else(overflow_error)?;
+                        *element = acc;
+                    }
                 }
-            })?;
+            }
+            Ok::<_, FilterError>(())
+        })?;
 
+        // Offset by the preceding summed area table, adding odd subsets of axes before subtracting even subsets so an unsigned accumulator does not underflow
+        let mut subsets = (1..1usize << dimensionality)
+            .filter(|axes| {
+                (0..dimensionality).all(|axis| axes & (1 << axis) == 0 || subset.start()[axis] > 0)
+            })
+            .collect::<Vec<_>>();
+        subsets.sort_by_key(|axes| axes.count_ones() % 2 == 0);
+        for axes in subsets {
+            let (start, shape): (Vec<u64>, Vec<u64>) =
+                itertools::izip!(0..dimensionality, subset.start(), subset.shape())
+                    .map(|(axis, &start, &shape)| {
+                        if axes & (1 << axis) == 0 {
+                            (start, shape)
+                        } else {
+                            (start - 1, 1)
+                        }
+                    })
+                    .unzip();
+            let boundary_subset = ArraySubset::new_with_start_shape(start, shape).unwrap();
+            let boundary =
+                progress.read(|| output.retrieve_array_subset_ndarray::<TOut>(&boundary_subset))?;
             progress.process(|| {
-                itertools::izip!(
-                    sum_last.lanes_mut(ndarray::Axis(dim)),
-                    chunk.lanes_mut(ndarray::Axis(dim))
-                )
-                // .par_bridge() // Faster off?
-                .for_each(|(mut sum_last, mut lane)| {
-                    sum_last[0] = lane.iter_mut().fold(sum_last[0], |acc, element| {
-                        *element += acc;
-                        *element
-                    });
-                });
-            });
-
-            progress.write(|| output.store_chunk_ndarray(&chunk_indices, chunk))?;
-            progress.next();
+                let boundary = boundary.broadcast(sat.raw_dim()).unwrap();
+                let add = axes.count_ones() % 2 == 1;
+                ndarray::Zip::from(&mut sat)
+                    .and(&boundary)
+                    .fold_while(Ok(()), |_, element, boundary| {
+                        let boundary: TAcc = boundary.as_();
+                        let value = if add {
+                            element.add_checked(boundary)
+                        } else {
+                            element.sub_checked(boundary)
+                        };
+                        match value {
+                            Some(value) => {
+                                *element = value;
+                                ndarray::FoldWhile::Continue(Ok(()))
+                            }
+                            None => ndarray::FoldWhile::Done(Err(overflow_error())),
+                        }
+                    })
+                    .into_inner()
+            })?;
         }
 
+        let output_array = progress.process(|| {
+            sat.iter()
+                .map(|value| <TOut as NumCast>::from(*value).ok_or_else(overflow_error))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|elements| ArrayD::from_shape_vec(sat.raw_dim(), elements).unwrap())
+        })?;
+        drop(sat);
+
+        progress
+            .write(|| output.store_array_subset_ndarray::<TOut, _>(subset.start(), output_array))?;
+        progress.next();
         Ok(())
     }
 }
@@ -138,25 +294,31 @@ impl FilterTraits for SummedAreaTable {
         chunk_input: &zarrs::array::ChunkRepresentation,
         chunk_output: &zarrs::array::ChunkRepresentation,
     ) -> usize {
-        chunk_input.fixed_element_size().unwrap() + chunk_output.fixed_element_size().unwrap()
+        // The input, the accumulator, a preceding boundary, and the output
+        chunk_input.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<u64>()
+                + 2 * chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_data_type(&self, input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        let accumulator = self.accumulator(input);
+        Some((accumulator.data_type(), accumulator.fill_value()))
     }
 
     fn apply(
         &self,
-        input: &Array<FilesystemStore>,
-        output: &mut Array<FilesystemStore>,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
         progress_callback: &ProgressCallback,
     ) -> Result<(), FilterError> {
         assert_eq!(output.shape(), input.shape());
 
-        let progress = {
-            Progress::new(
-                usize::try_from(output.chunk_grid_shape().unwrap().iter().product::<u64>())
-                    .unwrap()
-                    * output.dimensionality(),
-                progress_callback,
-            )
-        };
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let progress = Progress::new(
+            usize::try_from(chunk_grid_shape.iter().product::<u64>()).unwrap(),
+            progress_callback,
+        );
 
         let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
             chunk_limit
@@ -167,69 +329,95 @@ impl FilterTraits for SummedAreaTable {
             ))?
         };
 
-        let dimensionality = output.chunk_grid().dimensionality();
-        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
-        for dim in (0..dimensionality).rev() {
-            let chunk_grid_shape_dim = chunk_grid_shape
-                .iter()
-                .enumerate()
-                .map(|(i, dim_i)| if i == dim { 1 } else { *dim_i })
-                .collect_vec();
-            let chunks_dim = ArraySubset::new_with_shape(chunk_grid_shape_dim);
-            let indices = chunks_dim.indices();
+        let accumulator = self.accumulator(input);
+        for wavefront in Self::wavefronts(&chunk_grid_shape) {
             rayon_iter_concurrent_limit::iter_concurrent_limit!(
                 chunk_limit,
-                indices,
+                wavefront,
                 try_for_each,
-                |chunk_start_dim: Vec<u64>| {
-                    macro_rules! sat {
-                        ( $t_in:ty, $t_out:ty) => {{
-                            self.apply_dim::<$t_in, $t_out>(
-                                input,
-                                output,
-                                &chunk_start_dim,
-                                &chunk_grid_shape,
-                                dim,
-                                &progress,
-                            )?;
-                        }};
-                    }
-
+                |chunk_indices: Vec<u64>| {
                     macro_rules! apply_output {
-                        ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        ( $type_in:ty, $type_acc:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
                             match output.data_type() {
-                                $(DataType::$data_type_out => { sat!($type_in, $type_out) } ,)*
+                                $(DataType::$data_type_out => {
+                                    self.apply_chunk::<$type_in, $type_acc, $type_out>(input, output, &chunk_indices, &progress)
+                                } ,)*
                                 _ => panic!()
                             }
                         };
                     }
+                    macro_rules! apply_accumulator {
+                        ( $type_in:ty ) => {
+                            match accumulator {
+                                SummedAreaTableAccumulator::Int64 => apply_output!(
+                                    $type_in,
+                                    i64,
+                                    [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                ),
+                                SummedAreaTableAccumulator::UInt64 => apply_output!(
+                                    $type_in,
+                                    u64,
+                                    [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                ),
+                                SummedAreaTableAccumulator::Float64 => apply_output!(
+                                    $type_in,
+                                    f64,
+                                    [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                ),
+                            }
+                        };
+                    }
                     macro_rules! apply_input {
-                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
                             match input.data_type() {
-                                $(
-                                    DataType::$data_type_in => {
-                                        apply_output!($type_in, [
-                                            (Bool, u8), // pointless
-                                            (Int8, i8),
-                                            (Int16, i16),
-                                            (Int32, i32),
-                                            (Int64, i64),
-                                            (UInt8, u8),
-                                            (UInt16, u16),
-                                            (UInt32, u32),
-                                            (UInt64, u64),
-                                            (BFloat16, half::bf16),
-                                            (Float16, half::f16),
-                                            (Float32, f32),
-                                            (Float64, f64)
-                                        ]
-                                    )}
-                                ,)*
+                                $(DataType::$data_type_in => { apply_accumulator!($type_in) } ,)*
                                 _ => panic!()
                             }
                         };
                     }
-                    Some(apply_input!([
+                    apply_input!([
                         (Bool, u8),
                         (Int8, i8),
                         (Int16, i16),
@@ -243,8 +431,7 @@ impl FilterTraits for SummedAreaTable {
                         (Float16, half::f16),
                         (Float32, f32),
                         (Float64, f64)
-                    ]));
-                    Ok::<_, FilterError>(())
+                    ])
                 }
             )?;
         }
@@ -258,13 +445,13 @@ mod tests {
     use crate::progress::ProgressStats;
 
     use super::*;
-    use std::error::Error;
+    use std::{error::Error, sync::Arc};
     use zarrs::{array::ArrayBuilder, array_subset::ArraySubset, storage::store::FilesystemStore};
 
     #[test]
     fn summed_area_table() -> Result<(), Box<dyn Error>> {
         let path = tempfile::TempDir::new()?;
-        let store = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let array = ArrayBuilder::new(
             vec![6, 6],
             DataType::UInt8,
@@ -288,14 +475,14 @@ mod tests {
         println!("{elements:?}");
 
         let path = tempfile::TempDir::new()?;
-        let store: FilesystemStore = FilesystemStore::new(path.path())?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
         let mut array_output = array
             .builder()
             .data_type(DataType::UInt16)
             .fill_value(0u16.into())
             .build(store.into(), "/")?;
         let progress_callback = |_stats: ProgressStats| {};
-        SummedAreaTable::new(None).apply(
+        SummedAreaTable::new(None, None).apply(
             &array,
             &mut array_output,
             &ProgressCallback::new(&progress_callback),
@@ -314,8 +501,76 @@ mod tests {
         .into_dyn();
         approx::assert_abs_diff_eq!(elements, elements_ref);
 
+        // The sums overflow a uint8 output
+        let path = tempfile::TempDir::new()?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
+        let mut array_output = array.builder().build(store, "/")?;
+        assert!(SummedAreaTable::new(None, None)
+            .apply(
+                &array,
+                &mut array_output,
+                &ProgressCallback::new(&progress_callback),
+            )
+            .is_err());
+
         Ok(())
     }
+
+    #[test]
+    fn summed_area_table_3d() -> Result<(), Box<dyn Error>> {
+        let path = tempfile::TempDir::new()?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
+        let array = ArrayBuilder::new(
+            vec![5, 4, 3],
+            DataType::Int16,
+            vec![2, 3, 2].try_into()?,
+            0i16.into(),
+        )
+        .build(store, "/")?;
+        let array_subset = ArraySubset::new_with_shape(array.shape().to_vec());
+        let elements_in = ndarray::ArrayD::from_shape_fn(vec![5, 4, 3], |i| {
+            (i[0] * 7 + i[1] * 3) as i16 - i[2] as i16 * 5
+        });
+        array.store_array_subset_ndarray(array_subset.start(), elements_in.clone())?;
+
+        let path = tempfile::TempDir::new()?;
+        let store: Arc<FilterStorage> = Arc::new(FilesystemStore::new(path.path())?);
+        let filter = SummedAreaTable::new(None, None);
+        let (data_type, fill_value) = filter.output_data_type(&array).unwrap();
+        assert_eq!(data_type, DataType::Int64);
+        let mut array_output = array
+            .builder()
+            .data_type(data_type)
+            .fill_value(fill_value)
+            .build(store, "/")?;
+        let progress_callback = |_stats: ProgressStats| {};
+        filter.apply(
+            &array,
+            &mut array_output,
+            &ProgressCallback::new(&progress_callback),
+        )?;
+        let elements = array_output.retrieve_array_subset_ndarray::<i64>(&array_subset)?;
+
+        let elements_ref =
+            summed_area_table_inplace(elements_in.mapv(<f64 as From<_>>::from)).mapv(|v| v as i64);
+        assert_eq!(elements, elements_ref);
+
+        Ok(())
+    }
+
+    #[test]
+    fn summed_area_table_wavefronts() {
+        let wavefronts = SummedAreaTable::wavefronts(&[2, 3]);
+        assert_eq!(
+            wavefronts,
+            vec![
+                vec![vec![0, 0]],
+                vec![vec![0, 1], vec![1, 0]],
+                vec![vec![0, 2], vec![1, 1]],
+                vec![vec![1, 2]],
+            ]
+        );
+    }
 }
 
 /// Computes the summed area table on a single ndarray. Not suitable for computing on an entire zarr array.
@@ -0,0 +1,379 @@
+use clap::Parser;
+use itertools::Itertools;
+use ndarray::{ArrayD, Axis, Slice};
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayShape, DataType, Element, ElementOwned,
+    },
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+use super::pad::PadAxis;
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct TileArguments {
+    /// The number of repetitions along each axis, comma delimited.
+    #[arg(required = true, value_delimiter = ',')]
+    pub reps: Vec<u64>,
+    /// The axes to repeat along, comma delimited. Defaults to all axes.
+    ///
+    /// Must have the same length as the repetitions if specified.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub axes: Option<Vec<usize>>,
+}
+
+impl FilterArguments for TileArguments {
+    fn name(&self) -> String {
+        "tile".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        if self.reps.contains(&0) {
+            return Err(FilterError::InvalidParameters(format!(
+                "tile repetitions {:?} must be positive",
+                self.reps
+            )));
+        }
+        Ok(Box::new(Tile::new(
+            self.reps.clone(),
+            self.axes.clone(),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Tile {
+    reps: Vec<u64>,
+    axes: Option<Vec<usize>>,
+    chunk_limit: Option<usize>,
+}
+
+impl Tile {
+    pub fn new(reps: Vec<u64>, axes: Option<Vec<usize>>, chunk_limit: Option<usize>) -> Self {
+        Self {
+            reps,
+            axes,
+            chunk_limit,
+        }
+    }
+
+    /// Returns the repetitions along each of the `dimensionality` axes.
+    fn reps_per_axis(&self, dimensionality: usize) -> Vec<u64> {
+        if let Some(axes) = &self.axes {
+            let mut reps = vec![1; dimensionality];
+            for (&axis, &axis_reps) in std::iter::zip(axes, &self.reps) {
+                reps[axis] *= axis_reps;
+            }
+            reps
+        } else {
+            self.reps.clone()
+        }
+    }
+
+    /// Returns the input segments of the output range `start..start + len` along an axis of length `axis_len`.
+    ///
+    /// A range shorter than the axis reads one or two segments, depending on whether it wraps around the end of the input.
+    /// Otherwise, the whole axis is read once and repeated.
+    pub fn tile_axis(start: u64, len: u64, axis_len: u64) -> Vec<PadAxis> {
+        let len_usize = usize::try_from(len).unwrap();
+        let input_start = start % axis_len;
+        if len >= axis_len {
+            vec![PadAxis {
+                output: 0..len_usize,
+                input: 0..axis_len,
+                indices: (start..start + len)
+                    .map(|index| usize::try_from(index % axis_len).unwrap())
+                    .collect(),
+            }]
+        } else if input_start + len <= axis_len {
+            vec![PadAxis {
+                output: 0..len_usize,
+                input: input_start..input_start + len,
+                indices: (0..len_usize).collect(),
+            }]
+        } else {
+            let len_first = axis_len - input_start;
+            let len_first_usize = usize::try_from(len_first).unwrap();
+            vec![
+                PadAxis {
+                    output: 0..len_first_usize,
+                    input: input_start..axis_len,
+                    indices: (0..len_first_usize).collect(),
+                },
+                PadAxis {
+                    output: len_first_usize..len_usize,
+                    input: 0..len - len_first,
+                    indices: (0..len_usize - len_first_usize).collect(),
+                },
+            ]
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Copy + Default + 'static,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let tile_axes =
+            itertools::izip!(subset_output.start(), subset_output.shape(), input.shape())
+                .map(|(&start, &len, &axis_len)| Self::tile_axis(start, len, axis_len))
+                .collect::<Vec<_>>();
+
+        let output_shape = subset_output
+            .shape()
+            .iter()
+            .map(|&len| usize::try_from(len).unwrap())
+            .collect::<Vec<_>>();
+        let mut output_array = ArrayD::<TOut>::default(output_shape);
+        for region in tile_axes.into_iter().multi_cartesian_product() {
+            let subset_input = ArraySubset::new_with_ranges(
+                &region
+                    .iter()
+                    .map(|tile_axis| tile_axis.input.clone())
+                    .collect::<Vec<_>>(),
+            );
+            let input_array =
+                progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+            progress.process(|| {
+                // Only axes spanning more than one repetition need their input reordered or repeated
+                let input_array = region.iter().enumerate().fold(
+                    input_array,
+                    |input_array, (axis, tile_axis)| {
+                        let is_identity =
+                            (0..input_array.shape()[axis]).eq(tile_axis.indices.iter().copied());
+                        if is_identity {
+                            input_array
+                        } else {
+                            input_array.select(Axis(axis), &tile_axis.indices)
+                        }
+                    },
+                );
+                let mut output_view = output_array.slice_each_axis_mut(|axis| {
+                    Slice::from(region[axis.axis.index()].output.clone())
+                });
+                output_view.zip_mut_with(&input_array, |output, input| *output = input.as_());
+            });
+        }
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Tile {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        if let Some(axes) = &self.axes {
+            if axes.len() != self.reps.len() {
+                return Err(FilterError::InvalidParameters(format!(
+                    "tile has {} axes but {} repetitions",
+                    axes.len(),
+                    self.reps.len()
+                )));
+            }
+            if let Some(axis) = axes.iter().find(|&&axis| axis >= dimensionality) {
+                return Err(FilterError::InvalidParameters(format!(
+                    "tile axis {axis} is out of bounds for an input with dimensionality {dimensionality}"
+                )));
+            }
+        } else if self.reps.len() != dimensionality {
+            return Err(FilterError::InvalidParameters(format!(
+                "tile has {} repetitions but the input has dimensionality {dimensionality}, specify --axes to repeat a subset of axes",
+                self.reps.len()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input regions of an output chunk are no larger than the output chunk, but are copied when repeated
+        chunk_output.num_elements_usize()
+            * (2 * chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        Some(
+            std::iter::zip(input.shape(), self.reps_per_axis(input.dimensionality()))
+                .map(|(len, reps)| len * reps)
+                .collect(),
+        )
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_axis() {
+        // An output chunk within one repetition of an axis of length 5
+        assert_eq!(
+            Tile::tile_axis(6, 3, 5),
+            vec![PadAxis {
+                output: 0..3,
+                input: 1..4,
+                indices: vec![0, 1, 2],
+            }]
+        );
+        // An output chunk wrapping around the end of the input
+        assert_eq!(
+            Tile::tile_axis(3, 4, 5),
+            vec![
+                PadAxis {
+                    output: 0..2,
+                    input: 3..5,
+                    indices: vec![0, 1],
+                },
+                PadAxis {
+                    output: 2..4,
+                    input: 0..2,
+                    indices: vec![0, 1],
+                }
+            ]
+        );
+        // An output chunk spanning more than one repetition
+        assert_eq!(
+            Tile::tile_axis(4, 6, 3),
+            vec![PadAxis {
+                output: 0..6,
+                input: 0..3,
+                indices: vec![1, 2, 0, 1, 2, 0],
+            }]
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use clap::Parser;
+use ndarray::ArrayD;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        codec::Codec, data_type::UnsupportedDataTypeError, Array, ArrayBuilder, ArrayShape,
+        DataType, Element, ElementOwned,
+    },
+    array_subset::ArraySubset,
+    metadata::Metadata,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        FilterArguments, FilterCommonArguments, FilterStorage,
+    },
+    get_array_builder_reencode,
+    progress::{Progress, ProgressCallback},
+    ZarrReencodingArgs,
+};
+
+use super::resample::PIXEL_SIZE_ATTRIBUTE;
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct TransposeArguments {
+    /// The input axis of each output axis, comma delimited. E.g. 3,2,1,0 reverses the axes of a 4D array.
+    #[arg(required = true, value_delimiter = ',')]
+    pub axes: Vec<usize>,
+    /// Keep the chunk grid of the input rather than permuting it to match the new axis order.
+    ///
+    /// An explicit chunk or shard shape is always in the output axis order.
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub keep_chunk_grid: bool,
+}
+
+impl FilterArguments for TransposeArguments {
+    fn name(&self) -> String {
+        "transpose".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Transpose::new(
+            self.axes.clone(),
+            self.keep_chunk_grid,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// Returns `values` reordered such that element `i` is `values[axes[i]]`.
+pub fn permute<T: Clone>(values: &[T], axes: &[usize]) -> Vec<T> {
+    axes.iter().map(|&axis| values[axis].clone()).collect()
+}
+
+/// Returns the permutation reversing `axes`.
+pub fn inverse_permutation(axes: &[usize]) -> Vec<usize> {
+    let mut inverse = vec![0; axes.len()];
+    for (i, &axis) in axes.iter().enumerate() {
+        inverse[axis] = i;
+    }
+    inverse
+}
+
+pub struct Transpose {
+    axes: Vec<usize>,
+    keep_chunk_grid: bool,
+    chunk_limit: Option<usize>,
+}
+
+impl Transpose {
+    pub fn new(axes: Vec<usize>, keep_chunk_grid: bool, chunk_limit: Option<usize>) -> Self {
+        Self {
+            axes,
+            keep_chunk_grid,
+            chunk_limit,
+        }
+    }
+
+    /// Permute the axes of `input`, returning an array in standard layout.
+    pub fn apply_ndarray<TIn, TOut>(input: ArrayD<TIn>, axes: &[usize]) -> ArrayD<TOut>
+    where
+        TIn: Copy + AsPrimitive<TOut>,
+        TOut: Copy + 'static,
+    {
+        let input = input.permuted_axes(axes);
+        // Iterate in logical order, so the output is in standard layout
+        ArrayD::from_shape_vec(input.shape(), input.iter().map(|v| v.as_()).collect()).unwrap()
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + Copy + AsPrimitive<TOut>,
+        TOut: Element + Send + Sync + Copy + 'static,
+    {
+        // The input subset is the output chunk subset with its axes put back in the input order
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let inverse = inverse_permutation(&self.axes);
+        let subset_input = ArraySubset::new_with_start_shape(
+            permute(subset_output.start(), &inverse),
+            permute(subset_output.shape(), &inverse),
+        )
+        .unwrap();
+
+        let input_array =
+            progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+
+        let output_array =
+            progress.process(|| Self::apply_ndarray::<TIn, TOut>(input_array, &self.axes));
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Transpose {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        let dimensionality = chunk_input.dimensionality();
+        let mut axes = self.axes.clone();
+        axes.sort_unstable();
+        if axes != (0..dimensionality).collect::<Vec<_>>() {
+            return Err(FilterError::InvalidParameters(format!(
+                "transpose axes {:?} are not a permutation of the axes of an input with dimensionality {dimensionality}",
+                self.axes
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input region of an output chunk has the same number of elements as the output chunk
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<ArrayShape> {
+        if self.axes.len() == input.dimensionality() {
+            Some(permute(input.shape(), &self.axes))
+        } else {
+            None
+        }
+    }
+
+    fn output_array_builder(
+        &self,
+        array_input: &Array<FilterStorage>,
+        reencoding_args: &ZarrReencodingArgs,
+    ) -> ArrayBuilder {
+        let output_shape = self.output_shape(array_input);
+        let mut reencoding_args = self.output_reencoding_args(array_input, reencoding_args);
+
+        // Resolve zero sized dimensions of an explicit chunk or shard shape against the output shape
+        let explicit_chunk_grid =
+            reencoding_args.chunk_shape.is_some() || reencoding_args.shard_shape.is_some();
+        if let Some(output_shape) = &output_shape {
+            for shape in [
+                &mut reencoding_args.chunk_shape,
+                &mut reencoding_args.shard_shape,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                std::iter::zip(shape.iter_mut(), output_shape)
+                    .filter(|(s, _)| **s == 0)
+                    .for_each(|(s, &len)| *s = len);
+            }
+        }
+
+        let mut builder =
+            get_array_builder_reencode(&reencoding_args, array_input, output_shape.clone());
+        if output_shape.is_none() {
+            return builder;
+        }
+
+        if !self.keep_chunk_grid && !explicit_chunk_grid {
+            let chunk_shape = builder
+                .chunk_grid
+                .chunk_shape_u64(&vec![0; array_input.dimensionality()], array_input.shape())
+                .unwrap()
+                .unwrap();
+            builder.chunk_grid(permute(&chunk_shape, &self.axes).try_into().unwrap());
+
+            // Permute the inner chunk shape of a sharded output
+            if let Some(metadata) = builder.array_to_bytes_codec.create_metadata() {
+                if metadata.name() == "sharding_indexed" {
+                    let mut configuration = metadata.configuration().unwrap().clone();
+                    let inner_chunk_shape: Vec<u64> =
+                        serde_json::from_value(configuration["chunk_shape"].clone()).unwrap();
+                    configuration.insert(
+                        "chunk_shape".to_string(),
+                        permute(&inner_chunk_shape, &self.axes).into(),
+                    );
+                    let metadata = Metadata::new_with_configuration(metadata.name(), configuration);
+                    match Codec::from_metadata(&metadata).unwrap() {
+                        Codec::ArrayToBytes(codec) => builder.array_to_bytes_codec(codec),
+                        _ => unreachable!(),
+                    };
+                }
+            }
+        }
+
+        if reencoding_args.dimension_names.is_none() {
+            if let Some(dimension_names) = builder.dimension_names.take() {
+                builder.dimension_names(Some(permute(&dimension_names, &self.axes)));
+            }
+        }
+
+        if let Some(pixel_size) = builder
+            .attributes
+            .get(PIXEL_SIZE_ATTRIBUTE)
+            .and_then(|pixel_size| serde_json::from_value::<Vec<f64>>(pixel_size.clone()).ok())
+            .filter(|pixel_size| pixel_size.len() == self.axes.len())
+        {
+            builder.attributes.insert(
+                PIXEL_SIZE_ATTRIBUTE.to_string(),
+                permute(&pixel_size, &self.axes).into(),
+            );
+        }
+
+        builder
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; output.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transpose_axes() {
+        let axes = [2, 0, 1];
+        assert_eq!(permute(&[4, 5, 6], &axes), vec![6, 4, 5]);
+        assert_eq!(inverse_permutation(&axes), vec![1, 2, 0]);
+        assert_eq!(
+            permute(&permute(&[4, 5, 6], &axes), &inverse_permutation(&axes)),
+            vec![4, 5, 6]
+        );
+
+        let input = ndarray::Array::from_shape_vec((2, 3, 4), (0u16..24).collect())
+            .unwrap()
+            .into_dyn();
+        let output = Transpose::apply_ndarray::<u16, f32>(input.clone(), &axes);
+        assert_eq!(output.shape(), &[4, 2, 3]);
+        assert!(output.is_standard_layout());
+        assert_eq!(output[[3, 1, 2]], f32::from(input[[1, 2, 3]]));
+    }
+}
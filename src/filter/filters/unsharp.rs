@@ -0,0 +1,302 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        filters::gaussian::Gaussian, ArraySubsetOverlap, FilterArguments, FilterCommonArguments,
+        FilterStorage,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct UnsharpArguments {
+    /// Gaussian kernel sigma per axis, comma delimited.
+    #[arg(required = true, value_delimiter = ',')]
+    pub sigma: Vec<f32>,
+    /// The amount of sharpening.
+    #[arg(long, allow_hyphen_values(true), default_value_t = 1.0)]
+    #[serde(default = "default_amount")]
+    pub amount: f32,
+    /// Gaussian kernel half size per axis, comma delimited. Kernel is 2 x half size + 1.
+    ///
+    /// Defaults to ceil(3 x sigma).
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub kernel_half_size: Option<Vec<u64>>,
+}
+
+fn default_amount() -> f32 {
+    1.0
+}
+
+impl FilterArguments for UnsharpArguments {
+    fn name(&self) -> String {
+        "unsharp".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let kernel_half_size = self.kernel_half_size.clone().unwrap_or_else(|| {
+            self.sigma
+                .iter()
+                .map(|sigma| (3.0 * sigma).ceil() as u64)
+                .collect()
+        });
+        if kernel_half_size.len() != self.sigma.len() {
+            return Err(FilterError::InvalidParameters(format!(
+                "unsharp sigma {:?} and kernel half size {kernel_half_size:?} must have the same length",
+                self.sigma
+            )));
+        }
+        Ok(Box::new(Unsharp::new(
+            self.sigma.clone(),
+            kernel_half_size,
+            self.amount,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Unsharp {
+    gaussian: Gaussian,
+    amount: f32,
+    chunk_limit: Option<usize>,
+}
+
+impl Unsharp {
+    pub fn new(
+        sigma: Vec<f32>,
+        kernel_half_size: Vec<u64>,
+        amount: f32,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            gaussian: Gaussian::new(sigma, kernel_half_size, None),
+            amount,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<f32>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f32: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(
+            input.shape(),
+            &subset_output,
+            self.gaussian.kernel_half_size(),
+        );
+
+        let input_array = progress
+            .read(|| input.retrieve_array_subset_ndarray::<TIn>(subset_overlap.subset_input()))?;
+
+        let output_array = progress.process(|| {
+            let input_array = input_array.mapv(|x| x.as_());
+            let blurred = self.gaussian.apply_ndarray(input_array.clone());
+            let input_array = subset_overlap.extract_subset(&input_array);
+            let blurred = subset_overlap.extract_subset(&blurred);
+            ndarray::Zip::from(&input_array)
+                .and(&blurred)
+                .map_collect(|&x, &blurred| (x + self.amount * (x - blurred)).as_())
+        });
+        drop(input_array);
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Unsharp {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        if self.gaussian.kernel_half_size().len() != chunk_input.dimensionality() {
+            return Err(FilterError::InvalidParameters(format!(
+                "unsharp sigma has length {} but the input has dimensionality {}",
+                self.gaussian.kernel_half_size().len(),
+                chunk_input.dimensionality()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = usize::try_from(
+            std::iter::zip(chunk_input.shape(), self.gaussian.kernel_half_size())
+                .map(|(s, h)| s.get() + 2 * h)
+                .product::<u64>(),
+        )
+        .unwrap();
+        let num_output_elements = chunk_input.num_elements_usize();
+        num_input_elements
+            * (chunk_input.data_type().fixed_size().unwrap() + core::mem::size_of::<f32>() * 3)
+            + num_output_elements
+                * (core::mem::size_of::<f32>() * 2 + chunk_output.data_type().fixed_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), input.shape());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                            match input.data_type() {
+                                $(
+                                    DataType::$data_type_in => {
+                                        apply_output!($type_in, [
+                                            (Int8, i8),
+                                            (Int16, i16),
+                                            (Int32, i32),
+                                            (Int64, i64),
+                                            (UInt8, u8),
+                                            (UInt16, u16),
+                                            (UInt32, u32),
+                                            (UInt64, u64),
+                                            (BFloat16, half::bf16),
+                                            (Float16, half::f16),
+                                            (Float32, f32),
+                                            (Float64, f64)
+                                        ]
+                                    )}
+                                ,)*
+                                _ => panic!()
+                            }
+                        };
+                    }
+                apply_input!([
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn unsharp() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        let step = [0u8, 0, 0, 0, 255, 255, 255, 255];
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![8],
+                DataType::UInt8,
+                vec![2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &step,
+        )?;
+
+        let unsharp = |args: &str| {
+            run_test_filter("unsharp", &input, &output, &format!("sigma: [1.0]\n{args}"))
+        };
+
+        // The overshoot either side of an edge saturates rather than wrapping
+        let elements = retrieve_test_elements::<u8>(&unsharp("amount: 1.0")?)?;
+        assert_eq!(elements, step);
+
+        let elements = retrieve_test_elements::<f32>(&unsharp("data_type: float32")?)?;
+        assert_eq!(elements[0], 0.0);
+        assert!(elements[3] < 0.0);
+        assert!(elements[4] > 255.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+use std::{fmt::Display, str::FromStr};
+
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        interpolation::retrieve_resampled, AxisTaps, FilterArguments, FilterCommonArguments,
+        FilterStorage, Interpolation,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+/// A rational upsampling factor, such as `2` or `3/2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UpsampleFactor {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl UpsampleFactor {
+    /// The input length per output element.
+    pub fn scale(&self) -> f64 {
+        self.denominator as f64 / self.numerator as f64
+    }
+
+    /// The upsampled length of an axis of length `len`, rounded down.
+    pub fn upsampled_len(&self, len: u64) -> u64 {
+        std::cmp::max(len * self.numerator / self.denominator, 1)
+    }
+}
+
+impl FromStr for UpsampleFactor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (numerator, denominator) = s.split_once('/').unwrap_or((s, "1"));
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid upsample factor {s}"))
+        };
+        let (numerator, denominator) = (parse(numerator)?, parse(denominator)?);
+        if denominator == 0 || numerator < denominator {
+            Err(format!("upsample factor {s} must be at least 1"))
+        } else {
+            Ok(Self {
+                numerator,
+                denominator,
+            })
+        }
+    }
+}
+
+impl TryFrom<String> for UpsampleFactor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for UpsampleFactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+impl From<UpsampleFactor> for String {
+    fn from(value: UpsampleFactor) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct UpsampleArguments {
+    /// Upsample factor per axis, comma delimited.
+    ///
+    /// A factor is an integer or a rational (e.g. 3/2) that is at least 1.
+    /// The output shape is the input shape multiplied by the factor, rounded down.
+    #[arg(required = true, value_delimiter = ',')]
+    pub factor: Vec<UpsampleFactor>,
+    /// The interpolation method.
+    ///
+    /// Nearest neighbour copies input values, so use it for labels and masks.
+    #[arg(long, value_enum, default_value_t = Interpolation::Nearest)]
+    #[serde(default)]
+    pub interpolation: Interpolation,
+}
+
+impl FilterArguments for UpsampleArguments {
+    fn name(&self) -> String {
+        "upsample".to_string()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Upsample::new(
+            self.factor.clone(),
+            self.interpolation,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+pub struct Upsample {
+    factor: Vec<UpsampleFactor>,
+    interpolation: Interpolation,
+    chunk_limit: Option<usize>,
+}
+
+impl Upsample {
+    pub fn new(
+        factor: Vec<UpsampleFactor>,
+        interpolation: Interpolation,
+        chunk_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            factor,
+            interpolation,
+            chunk_limit,
+        }
+    }
+
+    pub fn apply_chunk<TIn, TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        round: bool,
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TIn: ElementOwned + Send + Sync + AsPrimitive<TOut> + AsPrimitive<f64>,
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let axis_taps = itertools::izip!(
+            subset_output.start(),
+            subset_output.end_exc(),
+            &self.factor,
+            input.shape()
+        )
+        .map(|(&start, end, factor, &len)| {
+            AxisTaps::new(
+                self.interpolation,
+                start..end,
+                factor.scale(),
+                0.0,
+                len,
+                false,
+            )
+        })
+        .collect::<Vec<_>>();
+        let output_array = retrieve_resampled::<TIn, TOut>(
+            input,
+            &axis_taps,
+            self.interpolation,
+            round,
+            progress,
+        )?;
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset_output.start(), output_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+}
+
+impl FilterTraits for Upsample {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        if self.factor.len() != chunk_input.dimensionality() {
+            return Err(FilterError::InvalidParameters(format!(
+                "upsample has {} factors, but the input has dimensionality {}",
+                self.factor.len(),
+                chunk_input.dimensionality()
+            )));
+        }
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The input subset is at most the output chunk size, and interpolation holds two f64 arrays of up to the output size
+        chunk_output.num_elements_usize()
+            * (chunk_input.fixed_element_size().unwrap()
+                + core::mem::size_of::<f64>() * 2
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn output_shape(&self, input: &Array<FilterStorage>) -> Option<Vec<u64>> {
+        Some(
+            std::iter::zip(input.shape(), &self.factor)
+                .map(|(&len, factor)| factor.upsampled_len(len))
+                .collect(),
+        )
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        assert_eq!(output.shape(), self.output_shape(input).unwrap());
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let round = !matches!(
+            output.data_type(),
+            DataType::Float16 | DataType::Float32 | DataType::Float64 | DataType::BFloat16
+        );
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ( $type_in:ty, [$( ( $data_type_out:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type_out => { self.apply_chunk::<$type_in, $type_out>(input, output, &chunk_indices, round, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                macro_rules! apply_input {
+                    ([$( ( $data_type_in:ident, $type_in:ty ) ),* ]) => {
+                        match input.data_type() {
+                            $(
+                                DataType::$data_type_in => {
+                                    apply_output!($type_in, [
+                                        (Bool, u8),
+                                        (Int8, i8),
+                                        (Int16, i16),
+                                        (Int32, i32),
+                                        (Int64, i64),
+                                        (UInt8, u8),
+                                        (UInt16, u16),
+                                        (UInt32, u32),
+                                        (UInt64, u64),
+                                        (BFloat16, half::bf16),
+                                        (Float16, half::f16),
+                                        (Float32, f32),
+                                        (Float64, f64)
+                                    ]
+                                )}
+                            ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_input!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zarrs::array::ArrayBuilder;
+
+    use crate::filter::runner::tests::{
+        create_test_array, retrieve_test_elements, run_test_filter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn upsample() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        let upsample = |interpolation: &str| {
+            let args = format!("factor: [\"2\"]\ninterpolation: {interpolation}");
+            run_test_filter("upsample", &input, &output, &args)
+        };
+
+        // Nearest neighbour copies values that are not representable by f64
+        let elements = [(1u64 << 53) + 1, u64::MAX];
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![2],
+                DataType::UInt64,
+                vec![1].try_into().unwrap(),
+                0u64.into(),
+            ),
+            &elements,
+        )?;
+        assert_eq!(
+            retrieve_test_elements::<u64>(&upsample("nearest")?)?,
+            vec![elements[0], elements[0], elements[1], elements[1]]
+        );
+
+        // Cubic overshoot at an edge saturates rather than wrapping
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4],
+                DataType::UInt8,
+                vec![2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &[0u8, 0, 255, 255],
+        )?;
+        assert_eq!(
+            retrieve_test_elements::<u8>(&upsample("cubic")?)?,
+            vec![0, 0, 0, 52, 203, 255, 255, 255]
+        );
+        assert_eq!(
+            retrieve_test_elements::<u8>(&upsample("linear")?)?,
+            vec![0, 0, 0, 64, 191, 255, 255, 255]
+        );
+        Ok(())
+    }
+}
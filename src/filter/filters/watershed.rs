@@ -0,0 +1,699 @@
+use std::{cmp::Reverse, collections::BinaryHeap, sync::Arc};
+
+use clap::Parser;
+use ndarray::ArrayD;
+use num_traits::AsPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{
+        data_type::UnsupportedDataTypeError, Array, ArrayBuilder, DataType, ElementOwned, FillValue,
+    },
+    array_subset::ArraySubset,
+    storage::store::FilesystemStore,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit,
+        filter_error::FilterError,
+        filter_traits::FilterTraits,
+        filters::label::{neighbour_offsets, offset_index, ravel_index, Connectivity},
+        path_or_identifier::parse_path_or_identifier,
+        retrieve_array_subset_ndarray_f64, ArraySubsetOverlap, FilterArguments,
+        FilterCommonArguments, FilterStorage, PathOrIdentifier,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct WatershedArguments {
+    /// Path to the zarr array holding the markers.
+    ///
+    /// Non-zero markers are flooded over the input intensity, zero elements are unlabelled.
+    /// Must have the same shape as the input.
+    #[arg(value_parser = parse_path_or_identifier)]
+    pub markers: PathOrIdentifier,
+    /// The connectivity of flooding.
+    #[arg(long, value_enum, default_value_t = Connectivity::Face)]
+    #[serde(default)]
+    pub connectivity: Connectivity,
+}
+
+impl FilterArguments for WatershedArguments {
+    fn name(&self) -> String {
+        "watershed".to_string()
+    }
+
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        vec![self.markers.clone()]
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        Ok(Box::new(Watershed::new(
+            self.connectivity,
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// The cost of flooding an element: the minimum over paths from a marker of the maximum intensity along the path, then the number of steps since reaching that intensity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cost {
+    level: f64,
+    steps: u64,
+}
+
+impl Cost {
+    const UNREACHED: Self = Self {
+        level: f64::INFINITY,
+        steps: u64::MAX,
+    };
+
+    /// Extend a path to an element with `intensity`.
+    fn extend(&self, intensity: f64) -> Self {
+        if intensity > self.level {
+            Self {
+                level: intensity,
+                steps: 0,
+            }
+        } else {
+            Self {
+                level: self.level,
+                steps: self.steps + 1,
+            }
+        }
+    }
+}
+
+impl Eq for Cost {}
+
+impl PartialOrd for Cost {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Cost {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.level
+            .total_cmp(&other.level)
+            .then(self.steps.cmp(&other.steps))
+    }
+}
+
+/// The flooding cost of each element, stored in temporary arrays between passes.
+struct WatershedCosts {
+    level: Array<FilterStorage>,
+    steps: Array<FilterStorage>,
+    _directory: tempfile::TempDir,
+}
+
+impl WatershedCosts {
+    fn new(output: &Array<FilterStorage>) -> Result<Self, FilterError> {
+        let directory = tempfile::TempDir::new()?;
+        let create_array = |name: &str, data_type: DataType, fill_value: FillValue| {
+            let store: Arc<FilterStorage> = Arc::new(
+                FilesystemStore::new(directory.path().join(name))
+                    .map_err(|err| FilterError::Other(err.to_string()))?,
+            );
+            let array = ArrayBuilder::new(
+                output.shape().to_vec(),
+                data_type,
+                output.chunk_grid().clone(),
+                fill_value,
+            )
+            .build(store, "/")?;
+            array.store_metadata()?;
+            Ok::<_, FilterError>(array)
+        };
+        Ok(Self {
+            level: create_array(
+                "level.zarr",
+                DataType::Float64,
+                FillValue::from(Cost::UNREACHED.level),
+            )?,
+            steps: create_array(
+                "steps.zarr",
+                DataType::UInt64,
+                FillValue::from(Cost::UNREACHED.steps),
+            )?,
+            _directory: directory,
+        })
+    }
+
+    fn retrieve(&self, subset: &ArraySubset) -> Result<ArrayD<Cost>, FilterError> {
+        let level = self.level.retrieve_array_subset_ndarray::<f64>(subset)?;
+        let steps = self.steps.retrieve_array_subset_ndarray::<u64>(subset)?;
+        Ok(ndarray::Zip::from(&level)
+            .and(&steps)
+            .map_collect(|&level, &steps| Cost { level, steps }))
+    }
+
+    fn store(&self, start: &[u64], costs: &ArrayD<Cost>) -> Result<(), FilterError> {
+        self.level
+            .store_array_subset_ndarray::<f64, _>(start, costs.mapv(|cost| cost.level))?;
+        self.steps
+            .store_array_subset_ndarray::<u64, _>(start, costs.mapv(|cost| cost.steps))?;
+        Ok(())
+    }
+}
+
+/// Returns the index within `shape` of the C order linear index `i`.
+fn unravel_index(mut i: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for (axis, len) in shape.iter().enumerate().rev() {
+        index[axis] = i % len;
+        i /= len;
+    }
+    index
+}
+
+pub struct Watershed {
+    connectivity: Connectivity,
+    chunk_limit: Option<usize>,
+}
+
+impl Watershed {
+    pub fn new(connectivity: Connectivity, chunk_limit: Option<usize>) -> Self {
+        Self {
+            connectivity,
+            chunk_limit,
+        }
+    }
+
+    /// Flood the `costs` of elements over `intensity` from the reached elements.
+    ///
+    /// Only elements where `floodable` is true are updated. Returns true if any element changed.
+    fn flood(
+        &self,
+        intensity: &ArrayD<f64>,
+        costs: &mut ArrayD<Cost>,
+        floodable: impl Fn(&[usize]) -> bool,
+    ) -> bool {
+        let shape = intensity.shape().to_vec();
+        let offsets = neighbour_offsets(shape.len(), self.connectivity);
+        let intensity = intensity.as_slice().unwrap();
+        let costs = costs.as_slice_mut().unwrap();
+
+        let mut queue: BinaryHeap<_> = costs
+            .iter()
+            .enumerate()
+            .filter(|(_, cost)| **cost != Cost::UNREACHED)
+            .map(|(i, cost)| Reverse((*cost, i)))
+            .collect();
+        let mut changed = false;
+        while let Some(Reverse((cost, i))) = queue.pop() {
+            if cost != costs[i] {
+                continue;
+            }
+            let index = unravel_index(i, &shape);
+            for offset in &offsets {
+                let Some(neighbour) = offset_index(&index, offset, &shape) else {
+                    continue;
+                };
+                let j = ravel_index(&neighbour, &shape);
+                if intensity[j].is_nan() || !floodable(&neighbour) {
+                    continue;
+                }
+                let cost = cost.extend(intensity[j]);
+                if cost < costs[j] {
+                    costs[j] = cost;
+                    queue.push(Reverse((cost, j)));
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Label each flooded element with the smallest label of the neighbours it is flooded from.
+    ///
+    /// Only elements where `assignable` is true are updated. Returns true if any element changed.
+    fn assign_labels(
+        &self,
+        intensity: &ArrayD<f64>,
+        costs: &ArrayD<Cost>,
+        labels: &mut ArrayD<u64>,
+        assignable: impl Fn(&[usize]) -> bool,
+    ) -> bool {
+        let shape = intensity.shape().to_vec();
+        let offsets = neighbour_offsets(shape.len(), self.connectivity);
+        let intensity = intensity.as_slice().unwrap();
+        let costs = costs.as_slice().unwrap();
+        let labels = labels.as_slice_mut().unwrap();
+
+        // The neighbours an element is flooded from have a lower cost, so they are labelled first
+        let mut order = (0..costs.len())
+            .filter(|&i| costs[i] != Cost::UNREACHED)
+            .collect::<Vec<_>>();
+        order.sort_unstable_by_key(|&i| costs[i]);
+
+        let mut changed = false;
+        for i in order {
+            let index = unravel_index(i, &shape);
+            if !assignable(&index) {
+                continue;
+            }
+            let label = offsets
+                .iter()
+                .filter_map(|offset| offset_index(&index, offset, &shape))
+                .map(|neighbour| ravel_index(&neighbour, &shape))
+                .filter(|&j| labels[j] != 0 && costs[j].extend(intensity[i]) == costs[i])
+                .map(|j| labels[j])
+                .min();
+            if let Some(label) = label {
+                if labels[i] == 0 || label < labels[i] {
+                    labels[i] = label;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Returns the flooding costs and labels of the markers.
+    fn initialise_ndarray(
+        intensity: &ArrayD<f64>,
+        markers: &ArrayD<u64>,
+    ) -> (ArrayD<Cost>, ArrayD<u64>) {
+        let costs =
+            ndarray::Zip::from(intensity)
+                .and(markers)
+                .map_collect(|&intensity, &marker| {
+                    if marker == 0 || intensity.is_nan() {
+                        Cost::UNREACHED
+                    } else {
+                        Cost {
+                            level: intensity,
+                            steps: 0,
+                        }
+                    }
+                });
+        let labels = ndarray::Zip::from(&costs)
+            .and(markers)
+            .map_collect(
+                |&cost, &marker| {
+                    if cost == Cost::UNREACHED {
+                        0
+                    } else {
+                        marker
+                    }
+                },
+            );
+        (costs, labels)
+    }
+
+    /// Returns the labels of a marker-based watershed of `intensity`.
+    ///
+    /// Elements are flooded from the markers with the lowest cost paths, and ties are resolved by the smallest label.
+    pub fn apply_ndarray(&self, intensity: &ArrayD<f64>, markers: &ArrayD<u64>) -> ArrayD<u64> {
+        let intensity = intensity.as_standard_layout().into_owned();
+        let (mut costs, mut labels) = Self::initialise_ndarray(&intensity, markers);
+        self.flood(&intensity, &mut costs, |_| true);
+        self.assign_labels(&intensity, &costs, &mut labels, |index| markers[index] == 0);
+        labels
+    }
+
+    /// Write the markers of a chunk to the output and initialise the flooding costs.
+    fn initialise_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        markers: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        costs: &WatershedCosts,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: ElementOwned + Copy + 'static,
+        u64: AsPrimitive<TOut>,
+    {
+        let subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let (intensity, markers) = progress.read(|| {
+            Ok::<_, FilterError>((
+                retrieve_array_subset_ndarray_f64(input, &subset)?,
+                retrieve_array_subset_ndarray_f64(markers, &subset)?,
+            ))
+        })?;
+
+        let (costs_array, labels) = progress.process(|| {
+            let (costs_array, labels) =
+                Self::initialise_ndarray(&intensity, &markers.mapv(|marker| marker as u64));
+            (costs_array, labels.mapv(|label| label.as_()))
+        });
+
+        progress.write(|| {
+            output.store_array_subset_ndarray::<TOut, _>(subset.start(), labels)?;
+            costs.store(subset.start(), &costs_array)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Flood the costs of a chunk from the reached elements in the chunk and its neighbours. Returns true if the chunk changed.
+    fn flood_chunk(
+        &self,
+        input: &Array<FilterStorage>,
+        costs: &WatershedCosts,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<bool, FilterError> {
+        let subset_output = costs.level.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(
+            input.shape(),
+            &subset_output,
+            &vec![1; input.dimensionality()],
+        );
+        let subset_input = subset_overlap.subset_input();
+
+        let (intensity, mut costs_array) = progress.read(|| {
+            Ok::<_, FilterError>((
+                retrieve_array_subset_ndarray_f64(input, subset_input)?,
+                costs.retrieve(subset_input)?,
+            ))
+        })?;
+
+        let changed = progress.process(|| {
+            let subset_chunk = subset_overlap.subset_output_in_input();
+            self.flood(&intensity, &mut costs_array, |index| {
+                subset_chunk.contains(&index.iter().map(|i| *i as u64).collect::<Vec<_>>())
+            })
+        });
+        drop(intensity);
+
+        if changed {
+            progress.write(|| {
+                costs.store(
+                    subset_output.start(),
+                    &subset_overlap.extract_subset(&costs_array),
+                )
+            })?;
+        }
+
+        progress.next();
+        Ok(changed)
+    }
+
+    /// Label the flooded elements of a chunk from the labelled elements in the chunk and its neighbours. Returns true if the chunk changed.
+    fn label_chunk<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        markers: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        costs: &WatershedCosts,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<bool, FilterError>
+    where
+        TOut: ElementOwned + Copy + 'static + AsPrimitive<u64>,
+        u64: AsPrimitive<TOut>,
+    {
+        let subset_output = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let subset_overlap = ArraySubsetOverlap::new(
+            output.shape(),
+            &subset_output,
+            &vec![1; output.dimensionality()],
+        );
+        let subset_input = subset_overlap.subset_input();
+
+        let (intensity, markers, labels, costs_array) = progress.read(|| {
+            Ok::<_, FilterError>((
+                retrieve_array_subset_ndarray_f64(input, subset_input)?,
+                retrieve_array_subset_ndarray_f64(markers, subset_input)?,
+                output.retrieve_array_subset_ndarray::<TOut>(subset_input)?,
+                costs.retrieve(subset_input)?,
+            ))
+        })?;
+
+        let labels = progress.process(|| {
+            let mut labels = labels.mapv(|label| label.as_());
+            let subset_chunk = subset_overlap.subset_output_in_input();
+            let changed = self.assign_labels(&intensity, &costs_array, &mut labels, |index| {
+                markers[index] == 0.0
+                    && subset_chunk.contains(&index.iter().map(|i| *i as u64).collect::<Vec<_>>())
+            });
+            changed.then(|| subset_overlap.extract_subset(&labels))
+        });
+        drop((intensity, markers, costs_array));
+
+        let changed = labels.is_some();
+        if let Some(labels) = labels {
+            progress.write(|| {
+                output.store_array_subset_ndarray::<TOut, _>(
+                    subset_output.start(),
+                    labels.mapv(|label| label.as_()),
+                )
+            })?;
+        }
+
+        progress.next();
+        Ok(changed)
+    }
+
+    /// Update chunks with `update_chunk` until no chunk changes, revisiting the neighbours of changed chunks.
+    ///
+    /// Chunks are grouped by the parity of their indices so that neighbouring chunks are never updated concurrently.
+    fn update_until_unchanged(
+        chunk_grid_shape: &[u64],
+        chunk_limit: usize,
+        progress_callback: &ProgressCallback,
+        update_chunk: impl Fn(&[u64], &Progress) -> Result<bool, FilterError> + Send + Sync,
+    ) -> Result<(), FilterError> {
+        let chunks = ArraySubset::new_with_shape(chunk_grid_shape.to_vec());
+        let chunk_grid_shape = chunk_grid_shape
+            .iter()
+            .map(|s| usize::try_from(*s).unwrap())
+            .collect::<Vec<_>>();
+        let chunk_offsets = neighbour_offsets(chunk_grid_shape.len(), Connectivity::Full);
+        let num_parities = 1usize << chunk_grid_shape.len();
+
+        let mut dirty = vec![true; chunks.num_elements_usize()];
+        while dirty.iter().any(|dirty| *dirty) {
+            for parity in 0..num_parities {
+                let chunks_parity = chunks
+                    .indices()
+                    .into_iter()
+                    .filter(|chunk_indices| {
+                        let chunk_indices = chunk_indices
+                            .iter()
+                            .map(|i| usize::try_from(*i).unwrap())
+                            .collect::<Vec<_>>();
+                        let chunk_parity = chunk_indices
+                            .iter()
+                            .enumerate()
+                            .map(|(axis, i)| (i % 2) << axis)
+                            .sum::<usize>();
+                        chunk_parity == parity
+                            && std::mem::take(
+                                &mut dirty[ravel_index(&chunk_indices, &chunk_grid_shape)],
+                            )
+                    })
+                    .collect::<Vec<_>>();
+                if chunks_parity.is_empty() {
+                    continue;
+                }
+
+                let progress = Progress::new(chunks_parity.len(), progress_callback);
+                let changed = rayon_iter_concurrent_limit::iter_concurrent_limit!(
+                    chunk_limit,
+                    chunks_parity,
+                    map,
+                    |chunk_indices: Vec<u64>| {
+                        let changed = update_chunk(&chunk_indices, &progress)?;
+                        Ok::<_, FilterError>((chunk_indices, changed))
+                    }
+                )
+                .collect::<Result<Vec<_>, _>>()?;
+
+                for (chunk_indices, _) in changed.into_iter().filter(|(_, changed)| *changed) {
+                    let chunk_indices = chunk_indices
+                        .iter()
+                        .map(|i| usize::try_from(*i).unwrap())
+                        .collect::<Vec<_>>();
+                    for offset in &chunk_offsets {
+                        if let Some(neighbour) =
+                            offset_index(&chunk_indices, offset, &chunk_grid_shape)
+                        {
+                            dirty[ravel_index(&neighbour, &chunk_grid_shape)] = true;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_typed<TOut>(
+        &self,
+        input: &Array<FilterStorage>,
+        markers: &Array<FilterStorage>,
+        output: &Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError>
+    where
+        TOut: ElementOwned + Copy + Send + Sync + 'static + AsPrimitive<u64>,
+        u64: AsPrimitive<TOut>,
+    {
+        let chunk_grid_shape = output.chunk_grid_shape().unwrap();
+        let chunks = ArraySubset::new_with_shape(chunk_grid_shape.clone());
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let costs = WatershedCosts::new(output)?;
+
+        // Initialise the output with the markers
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                self.initialise_chunk::<TOut>(
+                    input,
+                    markers,
+                    output,
+                    &costs,
+                    &chunk_indices,
+                    &progress,
+                )
+            }
+        )?;
+
+        // Flood the costs, then label elements from the neighbours they are flooded from
+        Self::update_until_unchanged(
+            &chunk_grid_shape,
+            chunk_limit,
+            progress_callback,
+            |chunk_indices, progress| self.flood_chunk(input, &costs, chunk_indices, progress),
+        )?;
+        Self::update_until_unchanged(
+            &chunk_grid_shape,
+            chunk_limit,
+            progress_callback,
+            |chunk_indices, progress| {
+                self.label_chunk::<TOut>(input, markers, output, &costs, chunk_indices, progress)
+            },
+        )
+    }
+}
+
+impl FilterTraits for Watershed {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        match chunk_input.data_type() {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float16
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::BFloat16 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        match chunk_output.data_type() {
+            DataType::UInt32 | DataType::UInt64 => {}
+            data_type => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+        };
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        let num_input_elements = usize::try_from(
+            chunk_input
+                .shape()
+                .iter()
+                .map(|s| s.get() + 2)
+                .product::<u64>(),
+        )
+        .unwrap();
+        num_input_elements
+            * (chunk_input.data_type().fixed_size().unwrap()
+                + chunk_output.data_type().fixed_size().unwrap()
+                + core::mem::size_of::<f64>() * 3
+                + core::mem::size_of::<u64>() * 2
+                + core::mem::size_of::<Cost>() * 2
+                + core::mem::size_of::<(Cost, usize)>())
+    }
+
+    fn output_data_type(&self, _input: &Array<FilterStorage>) -> Option<(DataType, FillValue)> {
+        Some((DataType::UInt32, FillValue::from(0u32)))
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        self.apply_inputs(&[input], output, progress_callback)
+    }
+
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let [input, markers] = *inputs else {
+            return Err(FilterError::InvalidParameters(
+                "watershed requires an input and a markers input".to_string(),
+            ));
+        };
+        assert_eq!(output.shape(), input.shape());
+        assert_eq!(markers.shape(), input.shape());
+        self.is_compatible_arrays(inputs, output)?;
+
+        match output.data_type() {
+            DataType::UInt32 => self.apply_typed::<u32>(input, markers, output, progress_callback),
+            DataType::UInt64 => self.apply_typed::<u64>(input, markers, output, progress_callback),
+            _ => panic!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watershed_ridge() {
+        let intensity = ndarray::array![
+            [0.0, 1.0, 5.0, 1.0, 0.0],
+            [0.0, 2.0, 5.0, 2.0, 0.0],
+            [0.0, 9.0, 5.0, 4.0, 0.0]
+        ]
+        .into_dyn();
+        let markers = ndarray::array![[1, 0, 0, 0, 2], [0, 0, 0, 0, 0], [0, 0, 0, 0, 0]].into_dyn();
+        let labels = Watershed::new(Connectivity::Face, None).apply_ndarray(&intensity, &markers);
+        // The ridge is assigned to the smaller label on ties, unless it is only reached at its level from one side
+        assert_eq!(
+            labels,
+            ndarray::array![[1, 1, 1, 2, 2], [1, 1, 1, 2, 2], [1, 1, 2, 2, 2]].into_dyn()
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use clap::Parser;
+use num_traits::AsPrimitive;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{data_type::UnsupportedDataTypeError, Array, DataType, Element},
+    array_subset::ArraySubset,
+};
+
+use crate::{
+    filter::{
+        calculate_chunk_limit, filter_error::FilterError, filter_traits::FilterTraits,
+        path_or_identifier::parse_path_or_identifier, retrieve_array_subset_elements_f64,
+        FilterArguments, FilterCommonArguments, FilterStorage, PathOrIdentifier,
+    },
+    progress::{Progress, ProgressCallback},
+};
+
+/// An operand of the where filter, either a scalar or an array.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WhereOperand {
+    Scalar(f64),
+    Array(PathOrIdentifier),
+}
+
+fn parse_where_operand(operand: &str) -> std::io::Result<WhereOperand> {
+    if let Ok(scalar) = operand.parse::<f64>() {
+        Ok(WhereOperand::Scalar(scalar))
+    } else {
+        Ok(WhereOperand::Array(parse_path_or_identifier(operand)?))
+    }
+}
+
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
+pub struct WhereArguments {
+    /// The value where the input (condition) is non-zero. A scalar or a path to a zarr array.
+    ///
+    /// An array must have the same shape as the input.
+    #[arg(allow_hyphen_values(true), value_parser = parse_where_operand)]
+    pub a: WhereOperand,
+    /// The value where the input (condition) is zero. A scalar or a path to a zarr array.
+    ///
+    /// An array must have the same shape as the input.
+    #[arg(allow_hyphen_values(true), value_parser = parse_where_operand)]
+    pub b: WhereOperand,
+}
+
+impl FilterArguments for WhereArguments {
+    fn name(&self) -> String {
+        "where".to_string()
+    }
+
+    fn additional_inputs(&self) -> Vec<PathOrIdentifier> {
+        [&self.a, &self.b]
+            .into_iter()
+            .filter_map(|operand| match operand {
+                WhereOperand::Scalar(_) => None,
+                WhereOperand::Array(path) => Some(path.clone()),
+            })
+            .collect()
+    }
+
+    fn init(
+        &self,
+        common_args: &FilterCommonArguments,
+    ) -> Result<Box<dyn FilterTraits>, FilterError> {
+        let scalar = |operand: &WhereOperand| match operand {
+            WhereOperand::Scalar(scalar) => Some(*scalar),
+            WhereOperand::Array(_) => None,
+        };
+        Ok(Box::new(Where::new(
+            scalar(&self.a),
+            scalar(&self.b),
+            *common_args.chunk_limit(),
+        )))
+    }
+}
+
+/// The elements of an operand of a chunk.
+enum OperandElements {
+    Scalar(f64),
+    Array(Vec<f64>),
+}
+
+impl OperandElements {
+    #[inline]
+    fn get(&self, index: usize) -> f64 {
+        match self {
+            Self::Scalar(scalar) => *scalar,
+            Self::Array(elements) => elements[index],
+        }
+    }
+}
+
+pub struct Where {
+    scalar_a: Option<f64>,
+    scalar_b: Option<f64>,
+    chunk_limit: Option<usize>,
+}
+
+impl Where {
+    /// Create a new where filter, `scalar_a` and `scalar_b` are [`None`] if the operand is an array.
+    pub fn new(scalar_a: Option<f64>, scalar_b: Option<f64>, chunk_limit: Option<usize>) -> Self {
+        Self {
+            scalar_a,
+            scalar_b,
+            chunk_limit,
+        }
+    }
+
+    fn retrieve_operand(
+        scalar: Option<f64>,
+        array: Option<&Array<FilterStorage>>,
+        array_subset: &ArraySubset,
+    ) -> Result<OperandElements, FilterError> {
+        match (scalar, array) {
+            (Some(scalar), _) => Ok(OperandElements::Scalar(scalar)),
+            (None, Some(array)) => Ok(OperandElements::Array(retrieve_array_subset_elements_f64(
+                array,
+                array_subset,
+            )?)),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    pub fn apply_chunk<TOut>(
+        &self,
+        condition: &Array<FilterStorage>,
+        input_a: Option<&Array<FilterStorage>>,
+        input_b: Option<&Array<FilterStorage>>,
+        output: &Array<FilterStorage>,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError>
+    where
+        TOut: Element + Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+
+        let (elements_condition, elements_a, elements_b) = progress.read(|| {
+            Ok::<_, FilterError>((
+                retrieve_array_subset_elements_f64(condition, &input_output_subset)?,
+                Self::retrieve_operand(self.scalar_a, input_a, &input_output_subset)?,
+                Self::retrieve_operand(self.scalar_b, input_b, &input_output_subset)?,
+            ))
+        })?;
+
+        let elements_out = progress.process(|| {
+            Self::apply_elements::<TOut>(&elements_condition, &elements_a, &elements_b)
+        });
+        drop(elements_condition);
+        drop(elements_a);
+        drop(elements_b);
+
+        progress.write(|| {
+            output.store_array_subset_elements::<TOut>(&input_output_subset, &elements_out)
+        })?;
+
+        progress.next();
+        Ok(())
+    }
+
+    fn apply_elements<TOut>(
+        elements_condition: &[f64],
+        elements_a: &OperandElements,
+        elements_b: &OperandElements,
+    ) -> Vec<TOut>
+    where
+        TOut: Send + Sync + Copy + 'static,
+        f64: AsPrimitive<TOut>,
+    {
+        elements_condition
+            .into_par_iter()
+            .enumerate()
+            .map(|(i, condition)| {
+                if *condition != 0.0 {
+                    elements_a.get(i).as_()
+                } else {
+                    elements_b.get(i).as_()
+                }
+            })
+            .collect::<Vec<TOut>>()
+    }
+}
+
+impl FilterTraits for Where {
+    fn is_compatible(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> Result<(), FilterError> {
+        for data_type in [chunk_input.data_type(), chunk_output.data_type()] {
+            match data_type {
+                DataType::Bool
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::BFloat16 => {}
+                _ => Err(UnsupportedDataTypeError::from(data_type.to_string()))?,
+            };
+        }
+        Ok(())
+    }
+
+    fn memory_per_chunk(
+        &self,
+        chunk_input: &zarrs::array::ChunkRepresentation,
+        chunk_output: &zarrs::array::ChunkRepresentation,
+    ) -> usize {
+        // The condition and up to two array operands are held as f64 during processing
+        chunk_output.num_elements_usize()
+            * (3 * (chunk_input.fixed_element_size().unwrap() + core::mem::size_of::<f64>())
+                + chunk_output.fixed_element_size().unwrap())
+    }
+
+    fn apply(
+        &self,
+        input: &Array<FilterStorage>,
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        self.apply_inputs(&[input], output, progress_callback)
+    }
+
+    fn apply_inputs(
+        &self,
+        inputs: &[&Array<FilterStorage>],
+        output: &mut Array<FilterStorage>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let Some((&input, additional_inputs)) = inputs.split_first() else {
+            return Err(FilterError::InvalidParameters(
+                "where requires a condition input".to_string(),
+            ));
+        };
+
+        // Array operands are the additional inputs in order
+        let mut additional_inputs = additional_inputs.iter().copied();
+        let input_a = self
+            .scalar_a
+            .is_none()
+            .then(|| additional_inputs.next())
+            .flatten();
+        let input_b = self
+            .scalar_b
+            .is_none()
+            .then(|| additional_inputs.next())
+            .flatten();
+        if (self.scalar_a.is_none() && input_a.is_none())
+            || (self.scalar_b.is_none() && input_b.is_none())
+            || additional_inputs.next().is_some()
+        {
+            return Err(FilterError::InvalidParameters(
+                "where requires an additional input for each array operand".to_string(),
+            ));
+        }
+        assert_eq!(output.shape(), input.shape());
+        for input_operand in input_a.iter().chain(input_b.iter()) {
+            assert_eq!(input_operand.shape(), input.shape());
+        }
+        self.is_compatible_arrays(inputs, output)?;
+
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let chunk_limit = if let Some(chunk_limit) = self.chunk_limit {
+            chunk_limit
+        } else {
+            calculate_chunk_limit(self.memory_per_chunk(
+                &input.chunk_array_representation(&vec![0; input.dimensionality()])?,
+                &output.chunk_array_representation(&vec![0; input.dimensionality()])?,
+            ))?
+        };
+
+        let indices = chunks.indices();
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| {
+                macro_rules! apply_output {
+                    ([$( ( $data_type:ident, $type_out:ty ) ),* ]) => {
+                        match output.data_type() {
+                            $(DataType::$data_type => { self.apply_chunk::<$type_out>(input, input_a, input_b, &output, &chunk_indices, &progress) } ,)*
+                            _ => panic!()
+                        }
+                    };
+                }
+                apply_output!([
+                    (Bool, u8),
+                    (Int8, i8),
+                    (Int16, i16),
+                    (Int32, i32),
+                    (Int64, i64),
+                    (UInt8, u8),
+                    (UInt16, u16),
+                    (UInt32, u32),
+                    (UInt64, u64),
+                    (BFloat16, half::bf16),
+                    (Float16, half::f16),
+                    (Float32, f32),
+                    (Float64, f64)
+                ])
+            }
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn where_elements() {
+        let condition = [1.0, 0.0, -2.0, 0.0];
+        let a = OperandElements::Array(vec![10.0, 20.0, 30.0, 40.0]);
+        let b = OperandElements::Scalar(-1.0);
+        assert_eq!(
+            Where::apply_elements::<f32>(&condition, &a, &b),
+            vec![10.0, -1.0, 30.0, -1.0]
+        );
+        assert_eq!(
+            Where::apply_elements::<i16>(&condition, &b, &a),
+            vec![-1, 20, -1, 40]
+        );
+
+        let WhereOperand::Scalar(scalar) = parse_where_operand("-1.5").unwrap() else {
+            panic!()
+        };
+        assert_eq!(scalar, -1.5);
+        assert!(matches!(
+            parse_where_operand("array.zarr").unwrap(),
+            WhereOperand::Array(PathOrIdentifier::Path(_))
+        ));
+    }
+}
@@ -0,0 +1,1158 @@
+use std::{
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use futures::{StreamExt, TryStreamExt};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use zarrs::{
+    array::{Array, ArrayError, ChunkRepresentation, ChunkShape},
+    array_subset::{
+        iterators::{Indices, IndicesIterator},
+        ArraySubset,
+    },
+};
+
+use crate::{
+    checkpoint::Checkpoint,
+    interrupt::is_interrupted,
+    progress::{Progress, ProgressCallback},
+};
+
+use super::{
+    calculate_chunk_limit, element_size, inner_chunk_shape, memory_budget, memory_limit,
+    AdaptiveConcurrency, AsyncFilterStorage, FilterError, FilterStorage, FilterTraits,
+};
+
+/// The chunk indices, subset, and input element bytes of a chunk read by [`FusedFilter::apply_prefetch`].
+type ReadChunk = (Vec<u64>, ArraySubset, Vec<u8>);
+
+/// The chunk indices, subset, and output element bytes (if written) of a chunk processed by [`FusedFilter::apply_prefetch`].
+type ProcessedChunk = (Vec<u64>, ArraySubset, Option<Vec<u8>>);
+
+/// An element-wise filter of a [`FusedFilter`] with its input and output.
+///
+/// The output of a stage is the input of the next stage, but only the output of the last stage is written.
+pub struct FusedStage<'a> {
+    pub filter: &'a dyn FilterTraits,
+    pub input: &'a Array<FilterStorage>,
+    pub output: &'a Array<FilterStorage>,
+}
+
+/// The output of a chunk where every input element is the fill value.
+enum FillValueChunk {
+    /// Every output element is the fill value, so the chunk is not written.
+    Skip,
+    /// The output element bytes, which are all the same element.
+    Constant(Vec<u8>),
+}
+
+/// A chain of element-wise filters applied in a single pass.
+///
+/// Each chunk is read from the input of the first stage, passed through every stage in memory, and written to the output of the last stage.
+/// The intermediate arrays are never read or written.
+///
+/// The stages are not applied to chunks where every input element is the fill value (e.g. missing chunks).
+/// Their output is precomputed from the fill value, and they are not written if it is the output fill value.
+pub struct FusedFilter<'a> {
+    stages: Vec<FusedStage<'a>>,
+    chunk_limit: Option<usize>,
+    checkpoint: Option<&'a Checkpoint>,
+    /// The output element of an input fill value element, if the stages can be applied to it.
+    fill_value_output: Option<Vec<u8>>,
+    /// The shape of the blocks that chunks are processed in, e.g. the inner chunks of a sharded output or input.
+    block_shape: Option<ChunkShape>,
+    /// Adapt the number of chunks processed concurrently with an [`AdaptiveConcurrency`].
+    adaptive: bool,
+}
+
+impl<'a> FusedFilter<'a> {
+    /// Create a new fused filter.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if there are no stages, a stage is not element-wise, or the stages do not chain.
+    pub fn new(
+        stages: Vec<FusedStage<'a>>,
+        chunk_limit: Option<usize>,
+    ) -> Result<Self, FilterError> {
+        let Some(first) = stages.first() else {
+            return Err(FilterError::InvalidParameters(
+                "a fused filter requires at least one stage".to_string(),
+            ));
+        };
+        for stage in &stages {
+            if !stage.filter.is_elementwise() {
+                return Err(FilterError::InvalidParameters(
+                    "only element-wise filters can be fused".to_string(),
+                ));
+            }
+            if stage.input.shape() != first.input.shape()
+                || stage.output.shape() != first.input.shape()
+            {
+                return Err(FilterError::InvalidParameters(
+                    "the stages of a fused filter must have the same shape".to_string(),
+                ));
+            }
+        }
+        for (stage, next) in std::iter::zip(&stages, stages.iter().skip(1)) {
+            if stage.output.data_type() != next.input.data_type()
+                || stage.output.fill_value() != next.input.fill_value()
+            {
+                return Err(FilterError::InvalidParameters(
+                    "the output of each stage of a fused filter must be the input of the next stage"
+                        .to_string(),
+                ));
+            }
+        }
+        let fill_value_output = stages
+            .iter()
+            .try_fold(
+                first.input.fill_value().as_ne_bytes().to_vec(),
+                |bytes, stage| {
+                    stage
+                        .filter
+                        .apply_elementwise(&bytes, stage.input, stage.output)
+                },
+            )
+            .ok();
+        let output = stages.last().unwrap().output;
+        let block_shape = inner_chunk_shape(output)
+            .or_else(|| inner_chunk_shape(first.input))
+            .filter(|inner_chunk_shape| inner_chunk_shape.len() == output.dimensionality());
+        let mut fused_filter = Self {
+            stages,
+            chunk_limit,
+            checkpoint: None,
+            fill_value_output,
+            block_shape: None,
+            adaptive: false,
+        };
+        fused_filter.set_block_shape(block_shape)?;
+        Ok(fused_filter)
+    }
+
+    /// Process chunks in blocks of at most `max_block_bytes` (of the largest element size of the stages), rather than whole chunks.
+    ///
+    /// This bounds the memory of the stages for arrays with huge chunks, though the output chunk is still held in memory since chunks are encoded whole.
+    ///
+    /// # Errors
+    /// Returns an error if the chunk shape of the output cannot be determined.
+    pub fn with_max_block_bytes(
+        mut self,
+        max_block_bytes: Option<usize>,
+    ) -> Result<Self, FilterError> {
+        let Some(max_block_bytes) = max_block_bytes else {
+            return Ok(self);
+        };
+        let output = self.stages.last().unwrap().output;
+        let shape = match &self.block_shape {
+            Some(block_shape) => block_shape.clone(),
+            None => output.chunk_shape(&vec![0; output.dimensionality()])?,
+        };
+        let element_size = self
+            .stages
+            .iter()
+            .flat_map(|stage| {
+                [
+                    stage.input.fill_value().size(),
+                    stage.output.fill_value().size(),
+                ]
+            })
+            .max()
+            .unwrap();
+        self.set_block_shape(Some(block_shape(&shape, element_size, max_block_bytes)))?;
+        Ok(self)
+    }
+
+    /// Set the block shape, which is unset if a block is a whole chunk.
+    fn set_block_shape(&mut self, block_shape: Option<ChunkShape>) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        let chunk_shape = output.chunk_shape(&vec![0; output.dimensionality()])?;
+        self.block_shape = block_shape.filter(|block_shape| {
+            std::iter::zip(block_shape.iter(), chunk_shape.iter())
+                .any(|(block, chunk)| block < chunk)
+        });
+        Ok(())
+    }
+
+    /// Adapt the number of chunks processed concurrently to the observed throughput and memory headroom with [`FusedFilter::apply`].
+    ///
+    /// The chunk limit is the maximum concurrency.
+    #[must_use]
+    pub fn with_adaptive_concurrency(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// Skip the chunks completed by a previous run in `checkpoint` (or of other tasks of an array job), and record each written chunk in it.
+    #[must_use]
+    pub fn with_checkpoint(mut self, checkpoint: Option<&'a Checkpoint>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Returns true if the chunk at `chunk_indices` is skipped, see [`Checkpoint::is_skipped`].
+    fn is_skipped(&self, chunk_indices: &[u64]) -> bool {
+        self.checkpoint
+            .is_some_and(|checkpoint| checkpoint.is_skipped(chunk_indices))
+    }
+
+    /// Record that the chunk at `chunk_indices` has been written.
+    fn complete(&self, chunk_indices: &[u64]) -> Result<(), FilterError> {
+        if let Some(checkpoint) = self.checkpoint {
+            checkpoint.complete(chunk_indices)?;
+        }
+        Ok(())
+    }
+
+    pub fn stages(&self) -> &[FusedStage<'a>] {
+        &self.stages
+    }
+
+    /// Pass the element bytes of a chunk through every stage.
+    fn apply_stages(&self, bytes: Vec<u8>, progress: &Progress) -> Result<Vec<u8>, FilterError> {
+        progress.process(|| {
+            self.stages
+                .iter()
+                .enumerate()
+                .try_fold(bytes, |bytes, (i, stage)| {
+                    progress.process_step(i, || {
+                        stage
+                            .filter
+                            .apply_elementwise(&bytes, stage.input, stage.output)
+                    })
+                })
+        })
+    }
+
+    /// Returns the output of a chunk with the element `bytes` if every element is the input fill value.
+    fn fill_value_chunk(&self, bytes: &[u8]) -> Option<FillValueChunk> {
+        let input = self.stages.first().unwrap().input;
+        let output = self.stages.last().unwrap().output;
+        let fill_value_output = self.fill_value_output.as_ref()?;
+        if !input.fill_value().equals_all(bytes) {
+            None
+        } else if output.fill_value().equals_all(fill_value_output) {
+            Some(FillValueChunk::Skip)
+        } else {
+            let num_elements = bytes.len() / input.fill_value().size();
+            Some(FillValueChunk::Constant(
+                fill_value_output.repeat(num_elements),
+            ))
+        }
+    }
+
+    /// Returns the memory per chunk of the largest stage.
+    ///
+    /// Each chunk passes through the stages in turn, so only one stage of a chunk holds memory at a time.
+    /// With `blocks`, the stages hold a block at a time, in addition to the output chunk.
+    fn memory_per_chunk(&self, blocks: bool) -> Result<usize, FilterError> {
+        let block_shape = self.block_shape.as_ref().filter(|_| blocks);
+        let chunk_representation = |array: &Array<FilterStorage>| {
+            let chunk_representation =
+                array.chunk_array_representation(&vec![0; array.dimensionality()])?;
+            Ok::<_, FilterError>(if let Some(block_shape) = block_shape {
+                ChunkRepresentation::new(
+                    block_shape.to_vec(),
+                    chunk_representation.data_type().clone(),
+                    chunk_representation.fill_value().clone(),
+                )
+                .unwrap()
+            } else {
+                chunk_representation
+            })
+        };
+        let mut memory_per_chunk = 0;
+        for stage in &self.stages {
+            let chunk_input = chunk_representation(stage.input)?;
+            let chunk_output = chunk_representation(stage.output)?;
+            memory_per_chunk = std::cmp::max(
+                memory_per_chunk,
+                stage.filter.memory_per_chunk(&chunk_input, &chunk_output),
+            );
+        }
+        if block_shape.is_some() {
+            let output = self.stages.last().unwrap().output;
+            let chunk_output =
+                output.chunk_array_representation(&vec![0; output.dimensionality()])?;
+            memory_per_chunk += chunk_output.num_elements_usize() * element_size(&chunk_output);
+        }
+        Ok(memory_per_chunk)
+    }
+
+    /// Returns the memory of a chunk in flight waiting to be processed or written, its input and output elements.
+    fn memory_per_chunk_buffer(&self) -> Result<usize, FilterError> {
+        let input = self.stages.first().unwrap().input;
+        let output = self.stages.last().unwrap().output;
+        let chunk_input = input.chunk_array_representation(&vec![0; input.dimensionality()])?;
+        let chunk_output = output.chunk_array_representation(&vec![0; output.dimensionality()])?;
+        Ok(
+            chunk_input.num_elements_usize() * element_size(&chunk_input)
+                + chunk_output.num_elements_usize() * element_size(&chunk_output),
+        )
+    }
+
+    /// Returns the chunk limit, or a limit based on the memory per chunk of the stages and the memory budget if unset.
+    fn chunk_limit(&self, blocks: bool) -> Result<usize, FilterError> {
+        if let Some(chunk_limit) = self.chunk_limit {
+            return Ok(chunk_limit);
+        }
+        calculate_chunk_limit(self.memory_per_chunk(blocks)?)
+    }
+
+    /// Returns the number of chunks in flight with [`FusedFilter::apply_async`].
+    ///
+    /// This is `concurrent_chunks` if set, otherwise the chunk limit.
+    /// With a memory limit, the memory budget is divided between the chunks being processed and the buffers of the other chunks in flight.
+    fn concurrent_chunks(&self, concurrent_chunks: Option<usize>) -> Result<usize, FilterError> {
+        let chunk_limit = self.chunk_limit(false)?;
+        let Some(concurrent_chunks) = concurrent_chunks else {
+            return Ok(chunk_limit);
+        };
+        if memory_limit().is_none() {
+            return Ok(concurrent_chunks);
+        }
+        let chunks_processing = chunk_limit
+            .min(rayon::current_num_threads())
+            .min(concurrent_chunks);
+        let chunks_buffered = memory_budget()
+            .saturating_sub(chunks_processing * self.memory_per_chunk(false)?)
+            / self.memory_per_chunk_buffer()?.max(1);
+        Ok(concurrent_chunks
+            .min(chunks_processing + chunks_buffered)
+            .max(1))
+    }
+
+    pub fn apply_chunk(
+        &self,
+        chunk_indices: &[u64],
+        progress: &Progress,
+    ) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        if self.is_skipped(chunk_indices) {
+            progress.next();
+            return Ok(());
+        }
+        let input_output_subset = output.chunk_subset_bounded(chunk_indices).unwrap();
+        let bytes = if let Some(block_shape) = &self.block_shape {
+            self.apply_blocks(&input_output_subset, block_shape, progress)?
+        } else {
+            let bytes = self.read_chunk(&input_output_subset, progress)?;
+            self.process_chunk(bytes, progress)?
+        };
+        self.write_chunk(chunk_indices, &input_output_subset, bytes, progress)
+    }
+
+    /// Read and process the chunk in `subset` a block at a time, returning [`None`] if the chunk is not written.
+    ///
+    /// The blocks of the input are partially decoded where supported (e.g. the inner chunks of a shard), so only the output chunk and a block of each stage are held in memory.
+    fn apply_blocks(
+        &self,
+        subset: &ArraySubset,
+        block_shape: &[NonZeroU64],
+        progress: &Progress,
+    ) -> Result<Option<Vec<u8>>, FilterError> {
+        let output = self.stages.last().unwrap().output;
+        let element_size = output.fill_value().size();
+        let mut bytes = output
+            .fill_value()
+            .as_ne_bytes()
+            .repeat(subset.num_elements_usize());
+        let mut written = false;
+        let blocks = subset.chunks(block_shape).map_err(ArrayError::from)?;
+        for (_, block_subset) in &blocks {
+            let block_subset = subset.overlap(&block_subset).map_err(ArrayError::from)?;
+            let block_bytes = self.read_chunk(&block_subset, progress)?;
+            let Some(block_bytes) = self.process_chunk(block_bytes, progress)? else {
+                // Every output element is the fill value
+                continue;
+            };
+            written = true;
+            let mut offset = 0;
+            let block_subset_relative = block_subset
+                .relative_to(subset.start())
+                .map_err(ArrayError::from)?;
+            for (index, num_elements) in &block_subset_relative
+                .contiguous_linearised_indices(subset.shape())
+                .unwrap()
+            {
+                let index = usize::try_from(index).unwrap() * element_size;
+                let length = usize::try_from(num_elements).unwrap() * element_size;
+                bytes[index..index + length].copy_from_slice(&block_bytes[offset..offset + length]);
+                offset += length;
+            }
+        }
+        Ok(written.then_some(bytes))
+    }
+
+    /// Read the element bytes of the input of the first stage in `subset`.
+    fn read_chunk(
+        &self,
+        subset: &ArraySubset,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, FilterError> {
+        let input = self.stages.first().unwrap().input;
+        progress.read(|| {
+            Ok::<_, FilterError>(
+                input
+                    .retrieve_array_subset(subset)?
+                    .into_fixed()
+                    .map_err(ArrayError::from)?
+                    .into_owned(),
+            )
+        })
+    }
+
+    /// Pass the element bytes of a chunk through every stage, returning [`None`] if the chunk is not written.
+    fn process_chunk(
+        &self,
+        bytes: Vec<u8>,
+        progress: &Progress,
+    ) -> Result<Option<Vec<u8>>, FilterError> {
+        Ok(match self.fill_value_chunk(&bytes) {
+            Some(FillValueChunk::Skip) => None,
+            Some(FillValueChunk::Constant(bytes)) => Some(bytes),
+            None => Some(self.apply_stages(bytes, progress)?),
+        })
+    }
+
+    /// Write the element bytes of the output of the last stage in `subset`, and record the chunk as complete.
+    fn write_chunk(
+        &self,
+        chunk_indices: &[u64],
+        subset: &ArraySubset,
+        bytes: Option<Vec<u8>>,
+        progress: &Progress,
+    ) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        if let Some(bytes) = bytes {
+            progress.write(|| output.store_array_subset(subset, bytes))?;
+        }
+        self.complete(chunk_indices)?;
+
+        progress.next();
+        Ok(())
+    }
+
+    /// Apply the fused filter, writing the output of the last stage.
+    ///
+    /// # Errors
+    /// Returns an error if a stage fails or the input or output cannot be read or written.
+    pub fn apply(&self, progress_callback: &ProgressCallback) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+
+        let blocks = self.block_shape.is_some();
+        let chunk_limit = self.chunk_limit(blocks)?;
+
+        let indices = chunks.indices();
+        if self.adaptive {
+            let controller = AdaptiveConcurrency::new(chunk_limit, self.memory_per_chunk(blocks)?);
+            return self.apply_adaptive(&indices, &controller, &progress);
+        }
+        rayon_iter_concurrent_limit::iter_concurrent_limit!(
+            chunk_limit,
+            indices,
+            try_for_each,
+            |chunk_indices: Vec<u64>| self.apply_chunk(&chunk_indices, &progress)
+        )?;
+
+        Ok(())
+    }
+
+    /// Apply the fused filter to the chunks at `indices`, with the number of chunks processed concurrently adapted by `controller`.
+    ///
+    /// Permits are acquired by the calling thread before a chunk is spawned, so rayon threads waiting on nested work never block on a permit.
+    fn apply_adaptive(
+        &self,
+        indices: &Indices,
+        controller: &AdaptiveConcurrency,
+        progress: &Progress,
+    ) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        let element_size = output.fill_value().size() as u64;
+        let error = Mutex::new(None);
+        rayon::in_place_scope(|scope| {
+            for chunk_indices in indices {
+                let permit = controller.acquire();
+                if error.lock().unwrap().is_some() {
+                    break;
+                }
+                let bytes = output
+                    .chunk_subset_bounded(&chunk_indices)
+                    .unwrap()
+                    .num_elements()
+                    * element_size;
+                let error = &error;
+                scope.spawn(move |_| match self.apply_chunk(&chunk_indices, progress) {
+                    Ok(()) => permit.finish(bytes),
+                    Err(err) => {
+                        error.lock().unwrap().get_or_insert(err);
+                    }
+                });
+            }
+        });
+        error.into_inner().unwrap().map_or(Ok(()), Err)
+    }
+
+    /// Apply the fused filter with a read-ahead pipeline, writing the output of the last stage.
+    ///
+    /// Chunks are read, processed, and written by separate threads connected by bounded channels.
+    /// Up to `prefetch` chunks are read ahead of the chunks being processed, and up to `prefetch` processed chunks wait to be written, so reads and writes overlap processing.
+    /// With a memory limit (see [`set_memory_limit`](super::set_memory_limit)), `prefetch` is reduced so the buffered chunks fit in the memory budget.
+    ///
+    /// # Errors
+    /// Returns an error if a stage fails or the input or output cannot be read or written.
+    pub fn apply_prefetch(
+        &self,
+        prefetch: usize,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+        let threads = self
+            .chunk_limit(false)?
+            .min(rayon::current_num_threads())
+            .max(1);
+        let prefetch = self.prefetch(prefetch, threads)?;
+
+        let indices = chunks.indices();
+        let indices = Mutex::new(indices.iter());
+        let failed = AtomicBool::new(false);
+        let (read_sender, read_receiver) = crossbeam_channel::bounded(prefetch);
+        let (write_sender, write_receiver) = crossbeam_channel::bounded(prefetch);
+
+        let (indices, failed, progress) = (&indices, &failed, &progress);
+        std::thread::scope(|scope| {
+            let mut handles = vec![];
+            for _ in 0..threads {
+                let read_sender = read_sender.clone();
+                handles.push(scope.spawn(move || {
+                    self.read_ahead(indices, &read_sender, progress, failed)
+                        .map_err(|err| set_failed(failed, err))
+                }));
+                let read_receiver = read_receiver.clone();
+                let write_sender = write_sender.clone();
+                handles.push(scope.spawn(move || {
+                    self.process_ahead(&read_receiver, &write_sender, progress, failed)
+                        .map_err(|err| set_failed(failed, err))
+                }));
+                let write_receiver = write_receiver.clone();
+                handles.push(scope.spawn(move || {
+                    self.write_behind(&write_receiver, progress, failed)
+                        .map_err(|err| set_failed(failed, err))
+                }));
+            }
+            // Each stage stops once the threads of the previous stage have stopped
+            drop((read_sender, read_receiver, write_sender, write_receiver));
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<()>, FilterError>>()
+        })?;
+
+        Ok(())
+    }
+
+    /// Returns the number of chunks read ahead with [`FusedFilter::apply_prefetch`].
+    ///
+    /// With a memory limit, the memory budget is divided between the chunks being processed by `threads` and the buffered chunks.
+    fn prefetch(&self, prefetch: usize, threads: usize) -> Result<usize, FilterError> {
+        if memory_limit().is_none() {
+            return Ok(prefetch.max(1));
+        }
+        // Each thread reading or writing holds a buffer
+        let chunks_buffered = memory_budget()
+            .saturating_sub(threads * self.memory_per_chunk(false)?)
+            / self.memory_per_chunk_buffer()?.max(1);
+        Ok((chunks_buffered.saturating_sub(2 * threads) / 2)
+            .min(prefetch)
+            .max(1))
+    }
+
+    /// Read chunks into `sender` until every chunk has been read or a thread fails.
+    fn read_ahead(
+        &self,
+        indices: &Mutex<IndicesIterator<'_>>,
+        sender: &Sender<ReadChunk>,
+        progress: &Progress,
+        failed: &AtomicBool,
+    ) -> Result<(), FilterError> {
+        let output = self.stages.last().unwrap().output;
+        while !failed.load(Ordering::Relaxed) {
+            let Some(chunk_indices) = indices.lock().unwrap().next() else {
+                break;
+            };
+            if self.is_skipped(&chunk_indices) {
+                progress.next();
+                continue;
+            }
+            let input_output_subset = output.chunk_subset_bounded(&chunk_indices).unwrap();
+            let bytes = self.read_chunk(&input_output_subset, progress)?;
+            if sender
+                .send((chunk_indices, input_output_subset, bytes))
+                .is_err()
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Process the chunks from `receiver` into `sender` until every chunk has been read or a thread fails.
+    fn process_ahead(
+        &self,
+        receiver: &Receiver<ReadChunk>,
+        sender: &Sender<ProcessedChunk>,
+        progress: &Progress,
+        failed: &AtomicBool,
+    ) -> Result<(), FilterError> {
+        for (chunk_indices, input_output_subset, bytes) in receiver {
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            let bytes = self.process_chunk(bytes, progress)?;
+            if sender
+                .send((chunk_indices, input_output_subset, bytes))
+                .is_err()
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the chunks from `receiver` until every chunk has been processed or a thread fails.
+    fn write_behind(
+        &self,
+        receiver: &Receiver<ProcessedChunk>,
+        progress: &Progress,
+        failed: &AtomicBool,
+    ) -> Result<(), FilterError> {
+        for (chunk_indices, input_output_subset, bytes) in receiver {
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            self.write_chunk(&chunk_indices, &input_output_subset, bytes, progress)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the fused filter with asynchronous reads and writes, writing the output of the last stage.
+    ///
+    /// `input` and `output` are asynchronous views of the input of the first stage and the output of the last stage.
+    /// Up to `concurrent_chunks` chunks (the chunk limit by default) are in flight, so the latency of reads and writes to remote stores overlaps.
+    /// With a memory limit (see [`set_memory_limit`](super::set_memory_limit)), the chunks in flight are reduced so their buffers fit in the memory budget.
+    /// Chunks are processed on the rayon thread pool, independently of the number of chunks in flight.
+    ///
+    /// # Errors
+    /// Returns an error if a stage fails or the input or output cannot be read or written.
+    pub fn apply_async(
+        &self,
+        input: &Array<AsyncFilterStorage>,
+        output: &Array<AsyncFilterStorage>,
+        concurrent_chunks: Option<usize>,
+        runtime: &tokio::runtime::Runtime,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let chunks = ArraySubset::new_with_shape(output.chunk_grid_shape().unwrap());
+        let progress = Progress::new(chunks.num_elements_usize(), progress_callback);
+        let concurrent_chunks = self.concurrent_chunks(concurrent_chunks)?;
+
+        let progress = &progress;
+        rayon::in_place_scope(|scope| {
+            runtime.block_on(
+                futures::stream::iter(&chunks.indices())
+                    .map(|chunk_indices| async move {
+                        if self.is_skipped(&chunk_indices) {
+                            progress.next();
+                            return Ok(());
+                        }
+                        if is_interrupted() {
+                            return Err(FilterError::Other("interrupted".to_string()));
+                        }
+                        let input_output_subset =
+                            output.chunk_subset_bounded(&chunk_indices).unwrap();
+                        let bytes = progress
+                            .read_async(async {
+                                Ok::<_, FilterError>(
+                                    input
+                                        .async_retrieve_array_subset(&input_output_subset)
+                                        .await?
+                                        .into_fixed()
+                                        .map_err(ArrayError::from)?
+                                        .into_owned(),
+                                )
+                            })
+                            .await?;
+
+                        let bytes = match self.fill_value_chunk(&bytes) {
+                            Some(FillValueChunk::Skip) => None,
+                            Some(FillValueChunk::Constant(bytes)) => Some(bytes),
+                            None => {
+                                let (sender, receiver) = futures::channel::oneshot::channel();
+                                scope.spawn(move |_| {
+                                    let _ = sender.send(self.apply_stages(bytes, progress));
+                                });
+                                Some(receiver.await.map_err(|_| {
+                                    FilterError::Other(
+                                        "a fused filter stage was cancelled".to_string(),
+                                    )
+                                })??)
+                            }
+                        };
+
+                        if let Some(bytes) = bytes {
+                            progress
+                                .write_async(
+                                    output.async_store_array_subset(&input_output_subset, bytes),
+                                )
+                                .await?;
+                        }
+                        self.complete(&chunk_indices)?;
+
+                        progress.next();
+                        Ok::<_, FilterError>(())
+                    })
+                    .buffer_unordered(concurrent_chunks)
+                    .try_collect::<()>(),
+            )
+        })
+    }
+}
+
+/// Returns the largest block shape within `shape` with at most `max_block_bytes` of elements with `element_size`.
+///
+/// The outermost dimensions are split first, so a block is contiguous in a C order chunk where possible.
+fn block_shape(shape: &[NonZeroU64], element_size: usize, max_block_bytes: usize) -> ChunkShape {
+    let max_elements = (max_block_bytes / element_size.max(1)).max(1) as u64;
+    let mut block_shape = shape.to_vec();
+    let mut num_elements = shape.iter().map(|s| s.get()).product::<u64>();
+    for dim in &mut block_shape {
+        if num_elements <= max_elements {
+            break;
+        }
+        let num_elements_inner = num_elements / dim.get();
+        *dim = NonZeroU64::new((max_elements / num_elements_inner).clamp(1, dim.get())).unwrap();
+        num_elements = num_elements_inner * dim.get();
+    }
+    block_shape.into()
+}
+
+/// Flag that a thread of [`FusedFilter::apply_prefetch`] failed with `err`, so the other threads stop.
+fn set_failed(failed: &AtomicBool, err: FilterError) -> FilterError {
+    failed.store(true, Ordering::Relaxed);
+    err
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        filter::filters::{
+            cast::{Cast, CastNan, CastOverflow, CastRounding},
+            clamp::Clamp,
+            rescale::Rescale,
+        },
+        progress::ProgressStats,
+        storage::async_storage_from_uri,
+    };
+
+    use super::*;
+    use std::{error::Error, sync::Arc};
+    use zarrs::{
+        array::{
+            codec::array_to_bytes::sharding::ShardingCodecBuilder, ArrayBuilder, DataType,
+            FillValueMetadata,
+        },
+        storage::store::FilesystemStore,
+    };
+
+    #[test]
+    fn fused_filter() -> Result<(), Box<dyn Error>> {
+        let path = tempfile::TempDir::new()?;
+        let array = |name: &str, data_type: DataType, fill_value: zarrs::array::FillValue| {
+            let store: Arc<FilterStorage> =
+                Arc::new(FilesystemStore::new(path.path().join(name)).unwrap());
+            ArrayBuilder::new(
+                vec![4, 4],
+                data_type,
+                vec![2, 2].try_into().unwrap(),
+                fill_value,
+            )
+            .build(store, "/")
+            .unwrap()
+        };
+        let array_input = array("input.zarr", DataType::Float32, 0.0f32.into());
+        let array_rescale = array("rescale.zarr", DataType::Float32, 0.0f32.into());
+        let array_clamp = array("clamp.zarr", DataType::Float32, 0.0f32.into());
+        let array_output = array("output.zarr", DataType::UInt8, 0u8.into());
+
+        let array_subset = ArraySubset::new_with_shape(array_input.shape().to_vec());
+        array_input.store_array_subset_elements(
+            &array_subset,
+            &(0..16).map(|u| u as f32).collect::<Vec<f32>>(),
+        )?;
+
+        let rescale = Rescale::new(2.0, -3.0, false, None);
+        let clamp = Clamp::new(FillValueMetadata::Int(0), FillValueMetadata::Int(20), None);
+        let cast = Cast::new(
+            CastRounding::Nearest,
+            CastOverflow::Saturate,
+            CastNan::FillValue,
+            None,
+        );
+        let fused_filter = FusedFilter::new(
+            vec![
+                FusedStage {
+                    filter: &rescale,
+                    input: &array_input,
+                    output: &array_rescale,
+                },
+                FusedStage {
+                    filter: &clamp,
+                    input: &array_rescale,
+                    output: &array_clamp,
+                },
+                FusedStage {
+                    filter: &cast,
+                    input: &array_clamp,
+                    output: &array_output,
+                },
+            ],
+            None,
+        )?;
+        let progress_callback = |_stats: ProgressStats| {};
+        fused_filter.apply(&ProgressCallback::new(&progress_callback))?;
+
+        let elements = array_output.retrieve_array_subset_elements::<u8>(&array_subset)?;
+        let elements_ref = (0..16)
+            .map(|u| (2 * u - 3).clamp(0, 20) as u8)
+            .collect::<Vec<u8>>();
+        assert_eq!(elements, elements_ref);
+
+        // The intermediate arrays are not written
+        assert!(array_rescale
+            .retrieve_array_subset_elements::<f32>(&array_subset)?
+            .iter()
+            .all(|value| *value == 0.0));
+
+        // Stages must chain
+        assert!(FusedFilter::new(
+            vec![
+                FusedStage {
+                    filter: &rescale,
+                    input: &array_input,
+                    output: &array_rescale,
+                },
+                FusedStage {
+                    filter: &cast,
+                    input: &array_output,
+                    output: &array_output,
+                },
+            ],
+            None,
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fused_filter_fill_value() -> Result<(), Box<dyn Error>> {
+        let path = tempfile::TempDir::new()?;
+        let array = |name: &str| {
+            let store: Arc<FilterStorage> =
+                Arc::new(FilesystemStore::new(path.path().join(name)).unwrap());
+            ArrayBuilder::new(
+                vec![4, 4],
+                DataType::Float32,
+                vec![2, 2].try_into().unwrap(),
+                0.0f32.into(),
+            )
+            .build(store, "/")
+            .unwrap()
+        };
+        let array_input = array("input.zarr");
+        let array_rescale = array("rescale.zarr");
+        let array_clamp = array("clamp.zarr");
+        array_input.store_chunk_elements::<f32>(&[0, 0], &[1.0, 2.0, 3.0, 4.0])?;
+
+        let rescale = Rescale::new(2.0, 1.0, false, None);
+        let clamp = Clamp::new(FillValueMetadata::Int(0), FillValueMetadata::Int(2), None);
+        let progress_callback = |_stats: ProgressStats| {};
+        let progress_callback = ProgressCallback::new(&progress_callback);
+        let apply = |filter: &dyn FilterTraits, output: &Array<FilterStorage>| {
+            FusedFilter::new(
+                vec![FusedStage {
+                    filter,
+                    input: &array_input,
+                    output,
+                }],
+                None,
+            )?
+            .apply(&progress_callback)
+        };
+
+        // Chunks of the fill value are written with the precomputed output
+        apply(&rescale, &array_rescale)?;
+        let elements = array_rescale
+            .retrieve_array_subset_elements::<f32>(&ArraySubset::new_with_shape(vec![4, 4]))?;
+        assert_eq!(elements[..2], [3.0, 5.0]);
+        assert_eq!(elements[4..6], [7.0, 9.0]);
+        assert_eq!(elements.iter().filter(|&&e| e == 1.0).count(), 12);
+
+        // Chunks with an output of the fill value are not written
+        apply(&clamp, &array_clamp)?;
+        assert!(array_clamp.retrieve_chunk_if_exists(&[0, 0])?.is_some());
+        assert!(array_clamp.retrieve_chunk_if_exists(&[1, 1])?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn fused_filter_async() -> Result<(), Box<dyn Error>> {
+        let path = tempfile::TempDir::new()?;
+        let array = |name: &str| {
+            let store: Arc<FilterStorage> =
+                Arc::new(FilesystemStore::new(path.path().join(name)).unwrap());
+            ArrayBuilder::new(
+                vec![4, 4],
+                DataType::Float32,
+                vec![3, 3].try_into().unwrap(),
+                0.0f32.into(),
+            )
+            .build(store, "/")
+            .unwrap()
+        };
+        let async_array = |name: &str, array: &Array<FilterStorage>| {
+            let store = async_storage_from_uri(&path.path().join(name).to_string_lossy()).unwrap();
+            Array::new_with_metadata(store, "/", array.metadata().clone()).unwrap()
+        };
+        let array_input = array("input.zarr");
+        let array_output = array("output.zarr");
+
+        let array_subset = ArraySubset::new_with_shape(array_input.shape().to_vec());
+        array_input.store_array_subset_elements(
+            &array_subset,
+            &(0..16).map(|u| u as f32).collect::<Vec<f32>>(),
+        )?;
+
+        let rescale = Rescale::new(2.0, -3.0, false, None);
+        let fused_filter = FusedFilter::new(
+            vec![FusedStage {
+                filter: &rescale,
+                input: &array_input,
+                output: &array_output,
+            }],
+            None,
+        )?;
+        let progress_callback = |_stats: ProgressStats| {};
+        fused_filter.apply_async(
+            &async_array("input.zarr", &array_input),
+            &async_array("output.zarr", &array_output),
+            Some(2),
+            &crate::threads::tokio_runtime()?,
+            &ProgressCallback::new(&progress_callback),
+        )?;
+
+        let elements = array_output.retrieve_array_subset_elements::<f32>(&array_subset)?;
+        let elements_ref = (0..16).map(|u| (2 * u - 3) as f32).collect::<Vec<f32>>();
+        assert_eq!(elements, elements_ref);
+        Ok(())
+    }
+
+    #[test]
+    fn fused_filter_prefetch() -> Result<(), Box<dyn Error>> {
+        let store: Arc<FilterStorage> = Arc::new(zarrs::storage::store::MemoryStore::new());
+        let array = |path: &str| {
+            ArrayBuilder::new(
+                vec![10, 10],
+                DataType::Float32,
+                vec![3, 3].try_into().unwrap(),
+                0.0f32.into(),
+            )
+            .build(store.clone(), path)
+            .unwrap()
+        };
+        let array_input = array("/input");
+        let array_output = array("/output");
+
+        let array_subset = ArraySubset::new_with_shape(array_input.shape().to_vec());
+        array_input.store_array_subset_elements(
+            &array_subset,
+            &(0..100).map(|u| u as f32).collect::<Vec<f32>>(),
+        )?;
+
+        let rescale = Rescale::new(2.0, -3.0, false, None);
+        let progress_callback = |_stats: ProgressStats| {};
+        let progress_callback = ProgressCallback::new(&progress_callback);
+        for (chunk_limit, prefetch) in [(None, 1), (Some(1), 1), (Some(2), 4)] {
+            array_output.erase_chunks(&ArraySubset::new_with_shape(vec![4, 4]))?;
+            FusedFilter::new(
+                vec![FusedStage {
+                    filter: &rescale,
+                    input: &array_input,
+                    output: &array_output,
+                }],
+                chunk_limit,
+            )?
+            .apply_prefetch(prefetch, &progress_callback)?;
+
+            let elements = array_output.retrieve_array_subset_elements::<f32>(&array_subset)?;
+            let elements_ref = (0..100).map(|u| (2 * u - 3) as f32).collect::<Vec<f32>>();
+            assert_eq!(elements, elements_ref);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fused_filter_adaptive() -> Result<(), Box<dyn Error>> {
+        let store: Arc<FilterStorage> = Arc::new(zarrs::storage::store::MemoryStore::new());
+        let array = |path: &str| {
+            ArrayBuilder::new(
+                vec![10, 10],
+                DataType::Float32,
+                vec![3, 3].try_into().unwrap(),
+                0.0f32.into(),
+            )
+            .build(store.clone(), path)
+            .unwrap()
+        };
+        let array_input = array("/input");
+        let array_output = array("/output");
+
+        let array_subset = ArraySubset::new_with_shape(array_input.shape().to_vec());
+        array_input.store_array_subset_elements(
+            &array_subset,
+            &(0..100).map(|u| u as f32).collect::<Vec<f32>>(),
+        )?;
+
+        let rescale = Rescale::new(2.0, -3.0, false, None);
+        let progress_callback = |_stats: ProgressStats| {};
+        let progress_callback = ProgressCallback::new(&progress_callback);
+        for chunk_limit in [None, Some(1), Some(4)] {
+            array_output.erase_chunks(&ArraySubset::new_with_shape(vec![4, 4]))?;
+            FusedFilter::new(
+                vec![FusedStage {
+                    filter: &rescale,
+                    input: &array_input,
+                    output: &array_output,
+                }],
+                chunk_limit,
+            )?
+            .with_adaptive_concurrency(true)
+            .apply(&progress_callback)?;
+
+            let elements = array_output.retrieve_array_subset_elements::<f32>(&array_subset)?;
+            let elements_ref = (0..100).map(|u| (2 * u - 3) as f32).collect::<Vec<f32>>();
+            assert_eq!(elements, elements_ref);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fused_filter_inner_chunks() -> Result<(), Box<dyn Error>> {
+        let store: Arc<FilterStorage> = Arc::new(zarrs::storage::store::MemoryStore::new());
+        let array = |path: &str, sharded: bool| {
+            let mut builder = ArrayBuilder::new(
+                vec![6, 6],
+                DataType::Float32,
+                vec![4, 4].try_into().unwrap(),
+                0.0f32.into(),
+            );
+            if sharded {
+                builder.array_to_bytes_codec(Box::new(
+                    ShardingCodecBuilder::new(vec![2, 2].try_into().unwrap()).build(),
+                ));
+            }
+            builder.build(store.clone(), path).unwrap()
+        };
+        let array_input = array("/input", true);
+        array_input.store_array_subset_elements(
+            &ArraySubset::new_with_start_shape(vec![0, 0], vec![3, 6])?,
+            &(0..18).map(|u| u as f32).collect::<Vec<f32>>(),
+        )?;
+
+        let clamp = Clamp::new(FillValueMetadata::Int(0), FillValueMetadata::Int(10), None);
+        let progress_callback = |_stats: ProgressStats| {};
+        let progress_callback = ProgressCallback::new(&progress_callback);
+        for sharded in [true, false] {
+            let array_output = array(&format!("/output_{sharded}"), sharded);
+            let fused_filter = FusedFilter::new(
+                vec![FusedStage {
+                    filter: &clamp,
+                    input: &array_input,
+                    output: &array_output,
+                }],
+                None,
+            )?;
+            assert_eq!(
+                fused_filter.block_shape,
+                Some(vec![2, 2].try_into().unwrap())
+            );
+            fused_filter.apply(&progress_callback)?;
+
+            let elements = array_output
+                .retrieve_array_subset_elements::<f32>(&ArraySubset::new_with_shape(vec![6, 6]))?;
+            let elements_ref = (0..36)
+                .map(|u| if u < 18 { (u as f32).min(10.0) } else { 0.0 })
+                .collect::<Vec<f32>>();
+            assert_eq!(elements, elements_ref);
+            // Shards of the fill value are not written
+            assert!(array_output.retrieve_chunk_if_exists(&[1, 1])?.is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fused_filter_max_block_bytes() -> Result<(), Box<dyn Error>> {
+        let shape = |shape: &[u64]| -> ChunkShape { shape.to_vec().try_into().unwrap() };
+        assert_eq!(block_shape(&shape(&[6, 6]), 4, 48), shape(&[2, 6]));
+        assert_eq!(block_shape(&shape(&[6, 6]), 4, 8), shape(&[1, 2]));
+        assert_eq!(block_shape(&shape(&[6, 6]), 4, 1), shape(&[1, 1]));
+        assert_eq!(block_shape(&shape(&[6, 6]), 4, 1024), shape(&[6, 6]));
+
+        let store: Arc<FilterStorage> = Arc::new(zarrs::storage::store::MemoryStore::new());
+        let array = |path: &str| {
+            ArrayBuilder::new(
+                vec![6, 6],
+                DataType::Float32,
+                vec![6, 6].try_into().unwrap(),
+                0.0f32.into(),
+            )
+            .build(store.clone(), path)
+            .unwrap()
+        };
+        let array_input = array("/input");
+        let array_output = array("/output");
+        let array_subset = ArraySubset::new_with_shape(vec![6, 6]);
+        array_input.store_array_subset_elements(
+            &array_subset,
+            &(0..36).map(|u| u as f32).collect::<Vec<f32>>(),
+        )?;
+
+        let rescale = Rescale::new(2.0, -3.0, false, None);
+        let fused_filter = FusedFilter::new(
+            vec![FusedStage {
+                filter: &rescale,
+                input: &array_input,
+                output: &array_output,
+            }],
+            None,
+        )?
+        .with_max_block_bytes(Some(40))?;
+        assert_eq!(fused_filter.block_shape, Some(shape(&[1, 6])));
+        let progress_callback = |_stats: ProgressStats| {};
+        fused_filter.apply(&ProgressCallback::new(&progress_callback))?;
+
+        let elements = array_output.retrieve_array_subset_elements::<f32>(&array_subset)?;
+        let elements_ref = (0..36).map(|u| (2 * u - 3) as f32).collect::<Vec<f32>>();
+        assert_eq!(elements, elements_ref);
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Per-chunk kernels dispatched on the GPU with [wgpu](https://wgpu.rs).
+
+use std::sync::{Mutex, OnceLock};
+
+use wgpu::util::DeviceExt;
+
+use super::{device::Correlate1d, FilterError};
+
+const WORKGROUP_SIZE: u32 = 64;
+const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
+
+const CORRELATE_1D_SHADER: &str = r#"
+struct Params {
+    outer: u32,
+    input_len: u32,
+    output_len: u32,
+    inner: u32,
+    kernel_len: u32,
+    offset: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> kernel: array<f32>;
+@group(0) @binding(2) var<storage, read> input: array<f32>;
+@group(0) @binding(3) var<storage, read_write> output: array<f32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let index = id.x + id.y * num_workgroups.x * 64u;
+    if (index >= params.outer * params.output_len * params.inner) {
+        return;
+    }
+    let r = index % params.inner;
+    let k = (index / params.inner) % params.output_len;
+    let o = index / (params.inner * params.output_len);
+    let base = o * params.input_len * params.inner + r;
+    var sum = 0.0;
+    for (var t = 0u; t < params.kernel_len; t = t + 1u) {
+        let i = clamp(i32(k + t) - i32(params.offset), 0, i32(params.input_len) - 1);
+        sum = sum + kernel[t] * input[base + u32(i) * params.inner];
+    }
+    output[index] = sum;
+}
+"#;
+
+/// The parameters of a pass of the 1D correlation shader.
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    outer: u32,
+    input_len: u32,
+    output_len: u32,
+    inner: u32,
+    kernel_len: u32,
+    offset: u32,
+    _pad: [u32; 2],
+}
+
+struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    correlate_1d: wgpu::ComputePipeline,
+    max_buffer_size: u64,
+    /// Chunks are dispatched one at a time, so error scopes are not interleaved and the GPU memory is not oversubscribed.
+    lock: Mutex<()>,
+}
+
+static CONTEXT: OnceLock<Option<GpuContext>> = OnceLock::new();
+
+impl GpuContext {
+    fn new() -> Option<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        }))?;
+        let limits = adapter.limits();
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("zarrs_tools"),
+                required_features: wgpu::Features::empty(),
+                required_limits: limits.clone(),
+            },
+            None,
+        ))
+        .ok()?;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("correlate_1d"),
+            source: wgpu::ShaderSource::Wgsl(CORRELATE_1D_SHADER.into()),
+        });
+        let correlate_1d = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("correlate_1d"),
+            layout: None,
+            module: &shader,
+            entry_point: "main",
+            compilation_options: Default::default(),
+        });
+        Some(Self {
+            device,
+            queue,
+            correlate_1d,
+            max_buffer_size: u64::from(limits.max_storage_buffer_binding_size)
+                .min(limits.max_buffer_size),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn get() -> Option<&'static Self> {
+        CONTEXT.get_or_init(Self::new).as_ref()
+    }
+}
+
+/// Returns true if a GPU adapter and device can be created.
+pub(crate) fn gpu_available() -> bool {
+    GpuContext::get().is_some()
+}
+
+fn gpu_error(message: impl std::fmt::Display) -> FilterError {
+    FilterError::Other(format!("GPU: {message}"))
+}
+
+/// Apply the 1D correlations `passes` to `input` in order on the GPU.
+///
+/// The input is uploaded once, and only the output of the last pass is read back.
+///
+/// # Errors
+/// Returns an error if no GPU is available, a pass is invalid, a buffer exceeds the GPU limits, or the GPU fails.
+pub(crate) fn correlate_axes(
+    input: &ndarray::ArrayD<f32>,
+    passes: &[Correlate1d],
+) -> Result<ndarray::ArrayD<f32>, FilterError> {
+    let context = GpuContext::get().ok_or_else(|| gpu_error("no GPU is available"))?;
+    let mut shape = input.shape().to_vec();
+    let mut max_len = input.len();
+    for pass in passes {
+        if pass.axis >= shape.len() || pass.kernel.is_empty() || pass.output_len == 0 {
+            return Err(gpu_error(format!("invalid pass {pass:?}")));
+        }
+        shape[pass.axis] = pass.output_len;
+        max_len = max_len.max(shape.iter().product());
+    }
+    if passes.is_empty() || input.is_empty() {
+        return Ok(input.clone());
+    }
+    if (max_len * core::mem::size_of::<f32>()) as u64 > context.max_buffer_size {
+        return Err(gpu_error("the chunk exceeds the maximum buffer size"));
+    }
+
+    let _lock = context.lock.lock().unwrap();
+    let device = &context.device;
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let input_standard = input.as_standard_layout();
+    let mut buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("input"),
+        contents: bytemuck::cast_slice(input_standard.as_slice().unwrap()),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    let mut shape = input.shape().to_vec();
+    for pass in passes {
+        let params = Params {
+            outer: shape[..pass.axis].iter().product::<usize>() as u32,
+            input_len: shape[pass.axis] as u32,
+            output_len: pass.output_len as u32,
+            inner: shape[pass.axis + 1..].iter().product::<usize>() as u32,
+            kernel_len: pass.kernel.len() as u32,
+            offset: pass.offset as u32,
+            _pad: [0; 2],
+        };
+        shape[pass.axis] = pass.output_len;
+        let len = shape.iter().product::<usize>();
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let kernel_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("kernel"),
+            contents: bytemuck::cast_slice(&pass.kernel),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size: (len * core::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &context.correlate_1d.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: kernel_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: output_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let workgroups = (len as u32).div_ceil(WORKGROUP_SIZE);
+        let workgroups_x = workgroups.min(MAX_WORKGROUPS_PER_DIMENSION);
+        let workgroups_y = workgroups.div_ceil(workgroups_x);
+        {
+            let mut compute_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            compute_pass.set_pipeline(&context.correlate_1d);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroups_x, workgroups_y, 1);
+        }
+        buffer = output_buffer;
+    }
+    let size = buffer.size();
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("staging"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    encoder.copy_buffer_to_buffer(&buffer, 0, &staging, 0, size);
+    context.queue.submit(Some(encoder.finish()));
+
+    let validation_error = pollster::block_on(device.pop_error_scope());
+    let out_of_memory_error = pollster::block_on(device.pop_error_scope());
+    if let Some(err) = validation_error.or(out_of_memory_error) {
+        return Err(gpu_error(err));
+    }
+
+    let slice = staging.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().map_err(gpu_error)?.map_err(gpu_error)?;
+    let elements = bytemuck::cast_slice::<u8, f32>(&slice.get_mapped_range()).to_vec();
+    staging.unmap();
+    Ok(ndarray::ArrayD::from_shape_vec(shape, elements).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::kernel::{apply_1d_kernel, convolve_1d_valid};
+
+    use super::*;
+
+    #[test]
+    fn gpu_correlate_axes() -> Result<(), FilterError> {
+        if !gpu_available() {
+            return Ok(());
+        }
+        let input = ndarray::ArrayD::<f32>::from_shape_fn(vec![5, 6, 7], |indices| {
+            (indices[0] * 42 + indices[1] * 7 + indices[2]) as f32
+        });
+
+        // Same length, clamped to the nearest element
+        let kernel = ndarray::Array1::from_vec(vec![0.25, 0.5, 0.25]);
+        let mut expected = ndarray::ArrayD::<f32>::zeros(input.shape());
+        apply_1d_kernel(1, &kernel, &input, &mut expected);
+        let output = correlate_axes(
+            &input,
+            &[Correlate1d {
+                axis: 1,
+                kernel: kernel.to_vec(),
+                offset: 1,
+                output_len: 6,
+            }],
+        )?;
+        approx::assert_abs_diff_eq!(output, expected, epsilon = 1e-3);
+
+        // Valid, the kernel is reversed since a convolution flips the kernel
+        let kernel = [1.0, 2.0, -1.0];
+        let expected = convolve_1d_valid(2, &kernel, &convolve_1d_valid(0, &kernel, &input));
+        let pass = |axis: usize, output_len: usize| Correlate1d {
+            axis,
+            kernel: kernel.iter().rev().copied().collect(),
+            offset: 0,
+            output_len,
+        };
+        let output = correlate_axes(&input, &[pass(0, 3), pass(2, 5)])?;
+        approx::assert_abs_diff_eq!(output, expected, epsilon = 1e-3);
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use std::ops::Range;
+
+use ndarray::{ArrayD, Axis};
+use num_traits::AsPrimitive;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use zarrs::{
+    array::{Array, ElementOwned},
+    array_subset::ArraySubset,
+};
+
+use crate::progress::Progress;
+
+use super::{FilterError, FilterStorage};
+
+/// An interpolation method.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// Nearest neighbour. Values are copied from the input, suitable for labels and masks.
+    #[default]
+    Nearest,
+    /// Linear interpolation.
+    Linear,
+    /// Cubic convolution interpolation (Catmull-Rom).
+    Cubic,
+}
+
+impl Interpolation {
+    /// The radius of the support of the interpolation kernel.
+    pub fn radius(&self) -> f64 {
+        match self {
+            Self::Nearest => 0.5,
+            Self::Linear => 1.0,
+            Self::Cubic => 2.0,
+        }
+    }
+
+    /// Evaluate the interpolation kernel at a distance `x` from a sample.
+    pub fn weight(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Self::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Linear => (1.0 - x).max(0.0),
+            Self::Cubic => {
+                // Keys cubic convolution kernel with a = -0.5
+                const A: f64 = -0.5;
+                if x < 1.0 {
+                    ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((A * x - 5.0 * A) * x + 8.0 * A) * x - 4.0 * A
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// Returns the input indices and weights sampled at input coordinate `x` along an axis of length `input_len`.
+    ///
+    /// Integer coordinates are element centres.
+    /// The kernel is stretched by `kernel_scale` (>= 1) to anti-alias when reducing.
+    /// Samples outside of the axis are clamped to the nearest edge element, and the weights are normalised.
+    pub fn taps(&self, x: f64, kernel_scale: f64, input_len: u64) -> Vec<(u64, f64)> {
+        let clamp = |i: f64| (i.max(0.0) as u64).min(input_len - 1);
+        if *self == Self::Nearest {
+            return vec![(clamp((x + 0.5).floor()), 1.0)];
+        }
+        let radius = self.radius() * kernel_scale;
+        let start = (x - radius).ceil() as i64;
+        let end = (x + radius).floor() as i64;
+        let mut taps: Vec<(u64, f64)> =
+            Vec::with_capacity(usize::try_from(end - start + 1).unwrap());
+        for i in start..=end {
+            let weight = self.weight((i as f64 - x) / kernel_scale);
+            if weight != 0.0 {
+                let index = clamp(i as f64);
+                match taps.last_mut() {
+                    Some((last, last_weight)) if *last == index => *last_weight += weight,
+                    _ => taps.push((index, weight)),
+                }
+            }
+        }
+        let sum: f64 = taps.iter().map(|(_, weight)| weight).sum();
+        if sum != 0.0 {
+            taps.iter_mut().for_each(|(_, weight)| *weight /= sum);
+        }
+        taps
+    }
+}
+
+/// The input indices and weights of each output element in a range along an axis.
+pub struct AxisTaps {
+    taps: Vec<Vec<(u64, f64)>>,
+}
+
+impl AxisTaps {
+    /// Create the taps of the elements in `output_range` of an output axis.
+    ///
+    /// Output element `o` samples input coordinate `(o + 0.5) * scale - 0.5 + offset`, so element edges align if `offset` is zero.
+    /// If `antialias` is true and `scale` is greater than one (a reduction), the kernel is stretched by `scale`.
+    pub fn new(
+        interpolation: Interpolation,
+        output_range: Range<u64>,
+        scale: f64,
+        offset: f64,
+        input_len: u64,
+        antialias: bool,
+    ) -> Self {
+        let kernel_scale = if antialias { scale.max(1.0) } else { 1.0 };
+        let taps = output_range
+            .map(|o| {
+                let x = (o as f64 + 0.5) * scale - 0.5 + offset;
+                interpolation.taps(x, kernel_scale, input_len)
+            })
+            .collect();
+        Self { taps }
+    }
+
+    /// The range of input indices referenced by the taps.
+    pub fn input_range(&self) -> Range<u64> {
+        let indices = self.taps.iter().flatten().map(|(index, _)| *index);
+        let start = indices.clone().min().unwrap_or_default();
+        let end = indices.max().map_or(start, |end| end + 1);
+        start..end
+    }
+
+    /// The input indices of the output elements, relative to `input_start`, assuming nearest neighbour taps.
+    pub fn nearest_indices(&self, input_start: u64) -> Vec<usize> {
+        self.taps
+            .iter()
+            .map(|taps| usize::try_from(taps[0].0 - input_start).unwrap())
+            .collect()
+    }
+
+    /// Resample `input` along `axis`, where `input` starts at `input_start` along that axis.
+    pub fn resample(&self, input: &ArrayD<f64>, axis: usize, input_start: u64) -> ArrayD<f64> {
+        let mut shape = input.shape().to_vec();
+        shape[axis] = self.taps.len();
+        let mut output = ArrayD::<f64>::zeros(shape);
+        output
+            .axis_iter_mut(Axis(axis))
+            .into_par_iter()
+            .zip(&self.taps)
+            .for_each(|(mut output, taps)| {
+                for (index, weight) in taps {
+                    let index = usize::try_from(index - input_start).unwrap();
+                    output.scaled_add(*weight, &input.index_axis(Axis(axis), index));
+                }
+            });
+        output
+    }
+}
+
+/// Retrieve the region of `input` referenced by `axis_taps` and resample it with `interpolation`.
+///
+/// Nearest neighbour interpolation copies input values, otherwise values are interpolated as [`f64`] and rounded if `round` is true.
+pub fn retrieve_resampled<TIn, TOut>(
+    input: &Array<FilterStorage>,
+    axis_taps: &[AxisTaps],
+    interpolation: Interpolation,
+    round: bool,
+    progress: &Progress,
+) -> Result<ArrayD<TOut>, FilterError>
+where
+    TIn: ElementOwned + Send + Sync + AsPrimitive<TOut> + AsPrimitive<f64>,
+    TOut: Copy + Send + Sync + 'static,
+    f64: AsPrimitive<TOut>,
+{
+    let input_ranges = axis_taps
+        .iter()
+        .map(AxisTaps::input_range)
+        .collect::<Vec<_>>();
+    let subset_input = ArraySubset::new_with_ranges(&input_ranges);
+
+    let input_array =
+        progress.read(|| input.retrieve_array_subset_ndarray::<TIn>(&subset_input))?;
+
+    Ok(progress.process(|| {
+        if interpolation == Interpolation::Nearest {
+            let mut array = input_array;
+            for (axis, (taps, range)) in std::iter::zip(axis_taps, &input_ranges).enumerate() {
+                array = array.select(Axis(axis), &taps.nearest_indices(range.start));
+            }
+            array.mapv(|v| v.as_())
+        } else {
+            let mut array = input_array.mapv(|v| AsPrimitive::<f64>::as_(v));
+            for (axis, (taps, range)) in std::iter::zip(axis_taps, &input_ranges).enumerate() {
+                array = taps.resample(&array, axis, range.start);
+            }
+            if round {
+                array.mapv(|v| v.round().as_())
+            } else {
+                array.mapv(|v| v.as_())
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolation_taps() {
+        // Upsampling by 2, linear
+        let taps = AxisTaps::new(Interpolation::Linear, 0..4, 0.5, 0.0, 2, false);
+        assert_eq!(taps.input_range(), 0..2);
+        let output = taps.resample(&ndarray::array![0.0, 4.0].into_dyn(), 0, 0);
+        assert_eq!(output, ndarray::array![0.0, 1.0, 3.0, 4.0].into_dyn());
+
+        // Upsampling by 2, nearest
+        let taps = AxisTaps::new(Interpolation::Nearest, 2..6, 0.5, 0.0, 3, false);
+        assert_eq!(taps.input_range(), 1..3);
+        assert_eq!(taps.nearest_indices(1), vec![0, 0, 1, 1]);
+
+        // Cubic interpolates samples exactly
+        for x in 0..4 {
+            let taps = Interpolation::Cubic.taps(x as f64, 1.0, 4);
+            assert_eq!(taps, vec![(x, 1.0)]);
+        }
+
+        // Anti-aliased reduction by 2 averages neighbours
+        let taps = AxisTaps::new(Interpolation::Linear, 1..3, 2.0, 0.0, 8, true);
+        assert_eq!(taps.input_range(), 1..7);
+        let input = ndarray::Array::from_shape_fn(6, |i| ((i + 1) % 2) as f64 * 4.0).into_dyn();
+        let output = taps.resample(&input, 0, 1);
+        assert_eq!(output, ndarray::array![2.0, 2.0].into_dyn());
+    }
+}
@@ -1,3 +1,4 @@
+use ndarray::Dimension;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use zarrs::array::{ravel_indices, unravel_index, UnsafeCellSlice};
 
@@ -128,3 +129,147 @@ pub fn apply_1d_difference_operator(
         })
     });
 }
+
+/// Apply the Sobel operator along `axis`: a difference operator along `axis` and a triangle filter along all other axes.
+pub fn apply_sobel_operator(axis: usize, input: &ndarray::ArrayD<f32>) -> ndarray::ArrayD<f32> {
+    let mut staging_in = input.clone();
+    let mut staging_out = ndarray::ArrayD::<f32>::zeros(input.shape());
+    for i in 0..input.ndim() {
+        if i == axis {
+            apply_1d_difference_operator(i, &staging_in, &mut staging_out);
+        } else {
+            apply_1d_triangle_filter(i, &staging_in, &mut staging_out);
+        }
+        if i != input.ndim() - 1 {
+            std::mem::swap(&mut staging_in, &mut staging_out);
+        }
+    }
+    staging_out
+}
+
+/// The behaviour of a kernel beyond the boundaries of an array.
+#[derive(
+    clap::ValueEnum,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BorderMode {
+    /// Reflect about the edge of the array (d c b a | a b c d | d c b a).
+    Reflect,
+    /// Use a constant value (a a a a | a b c d | a a a a).
+    Constant,
+    /// Extend the nearest element (a a a a | a b c d | d d d d).
+    #[default]
+    Nearest,
+}
+
+/// Map an index outside of `0..len` to an index inside it according to `mode`.
+///
+/// Returns [`None`] for [`BorderMode::Constant`].
+fn border_index(index: isize, len: usize, mode: BorderMode) -> Option<usize> {
+    let len = len as isize;
+    if (0..len).contains(&index) {
+        return Some(index as usize);
+    }
+    match mode {
+        BorderMode::Constant => None,
+        BorderMode::Nearest => Some(index.clamp(0, len - 1) as usize),
+        BorderMode::Reflect => {
+            let period = 2 * len;
+            let index = index.rem_euclid(period);
+            Some(if index < len {
+                index
+            } else {
+                period - 1 - index
+            } as usize)
+        }
+    }
+}
+
+/// Pad `input` by `pad` elements before and after each axis according to `mode`.
+///
+/// `constant` is the padding value for [`BorderMode::Constant`].
+pub fn pad_ndarray<T: Copy>(
+    input: &ndarray::ArrayD<T>,
+    pad: &[(usize, usize)],
+    mode: BorderMode,
+    constant: T,
+) -> ndarray::ArrayD<T> {
+    if pad
+        .iter()
+        .all(|(before, after)| *before == 0 && *after == 0)
+    {
+        return input.clone();
+    }
+    let shape = std::iter::zip(input.shape(), pad)
+        .map(|(len, (before, after))| before + len + after)
+        .collect::<Vec<_>>();
+    ndarray::ArrayD::<T>::from_shape_fn(shape, |indices| {
+        itertools::izip!(indices.slice(), input.shape(), pad)
+            .map(|(&index, &len, (before, _))| {
+                border_index(index as isize - *before as isize, len, mode)
+            })
+            .collect::<Option<Vec<_>>>()
+            .map_or(constant, |indices| input[ndarray::IxDyn(&indices)])
+    })
+}
+
+/// Convolve `input` with an odd length 1D `kernel` along `axis`, without padding.
+///
+/// The output is shorter than the input by the kernel length - 1 along `axis`.
+pub fn convolve_1d_valid<T: ndarray::LinalgScalar>(
+    axis: usize,
+    kernel: &[T],
+    input: &ndarray::ArrayD<T>,
+) -> ndarray::ArrayD<T> {
+    assert!(kernel.len() % 2 == 1);
+    let axis = ndarray::Axis(axis);
+    let len = input.len_of(axis) + 1 - kernel.len();
+    let mut shape = input.shape().to_vec();
+    shape[axis.index()] = len;
+    let mut output = ndarray::ArrayD::<T>::zeros(shape);
+    for (i, weight) in kernel.iter().rev().enumerate() {
+        output.scaled_add(*weight, &input.slice_axis(axis, (i..i + len).into()));
+    }
+    output
+}
+
+/// Convolve `input` with an ND `kernel` with odd length axes, without padding.
+///
+/// The output is shorter than the input by the kernel length - 1 along each axis.
+pub fn convolve_nd_valid(
+    kernel: &ndarray::ArrayD<f32>,
+    input: &ndarray::ArrayD<f32>,
+) -> ndarray::ArrayD<f32> {
+    assert!(kernel.shape().iter().all(|len| len % 2 == 1));
+    let shape = std::iter::zip(input.shape(), kernel.shape())
+        .map(|(len, kernel_len)| len + 1 - kernel_len)
+        .collect::<Vec<_>>();
+    let mut output = ndarray::ArrayD::<f32>::zeros(shape.clone());
+    for (indices, weight) in kernel.indexed_iter() {
+        if *weight == 0.0 {
+            continue;
+        }
+        // Convolution flips the kernel
+        let slices = itertools::izip!(indices.slice(), kernel.shape(), &shape)
+            .map(|(&index, &kernel_len, &len)| {
+                let start = kernel_len - 1 - index;
+                ndarray::SliceInfoElem::from(start..start + len)
+            })
+            .collect::<Vec<_>>();
+        output.scaled_add(
+            *weight,
+            &input.slice(
+                ndarray::SliceInfo::<_, ndarray::IxDyn, ndarray::IxDyn>::try_from(slices).unwrap(),
+            ),
+        );
+    }
+    output
+}
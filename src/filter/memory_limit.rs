@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sysinfo::{MemoryRefreshKind, RefreshKind, System};
+
+/// The process-wide memory limit in bytes, or zero if unset.
+static MEMORY_LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the process-wide memory limit in bytes shared by every filter, or remove it with [`None`].
+pub fn set_memory_limit(memory_limit: Option<usize>) {
+    MEMORY_LIMIT.store(memory_limit.unwrap_or(0), Ordering::SeqCst);
+}
+
+/// Returns the process-wide memory limit in bytes, if set.
+pub fn memory_limit() -> Option<usize> {
+    match MEMORY_LIMIT.load(Ordering::SeqCst) {
+        0 => None,
+        memory_limit => Some(memory_limit),
+    }
+}
+
+/// Returns the memory in bytes available to the chunks being processed.
+///
+/// This is 80% of the available memory, constrained by the [`memory_limit`] if set.
+pub fn memory_budget() -> usize {
+    let system = System::new_with_specifics(
+        RefreshKind::new().with_memory(MemoryRefreshKind::new().with_ram()),
+    );
+    let available_memory = usize::try_from(system.available_memory()).unwrap();
+    let available_memory_target = available_memory * 8 / 10; // 80%
+    if let Some(memory_limit) = memory_limit() {
+        std::cmp::min(available_memory_target, memory_limit)
+    } else {
+        available_memory_target
+    }
+}
+
+/// Parse a memory size in bytes with an optional binary unit suffix, e.g. `32G`, `512MiB`, `1.5T`, or `1024`.
+///
+/// # Errors
+/// Returns an error if the size is not a non-negative number or the unit is unknown.
+pub fn parse_memory_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid memory size {size}"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "invalid memory size unit {unit}, expected K, M, G, or T"
+            ))
+        }
+    };
+    Ok((number * multiplier as f64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_size() {
+        assert_eq!(parse_memory_size("1024"), Ok(1024));
+        assert_eq!(parse_memory_size("32G"), Ok(32 << 30));
+        assert_eq!(parse_memory_size("512MiB"), Ok(512 << 20));
+        assert_eq!(parse_memory_size("1.5k"), Ok(1536));
+        assert!(parse_memory_size("G").is_err());
+        assert!(parse_memory_size("32X").is_err());
+    }
+}
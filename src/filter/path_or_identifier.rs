@@ -21,3 +21,12 @@ impl<'de> serde::Deserialize<'de> for PathOrIdentifier {
             .map_err(|_| serde::de::Error::custom("could not create temporary directory"))
     }
 }
+
+impl serde::Serialize for PathOrIdentifier {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Path(path) => s.serialize_str(&path.to_string_lossy()),
+            Self::Identifier(identifier) => s.serialize_str(identifier),
+        }
+    }
+}
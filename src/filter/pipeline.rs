@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+
+use zarrs::metadata::Metadata;
+
+use crate::storage::{is_http_url, is_uri, is_zip_path};
+
+use super::{FilterCommand, FilterCommandTraits, FilterError, PathOrIdentifier};
+
+/// Read a pipeline of filters from a run configuration.
+///
+/// The run configuration is a YAML file (.yaml or .yml) or otherwise a JSON file, holding an array of filters.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or is malformed.
+pub fn read_pipeline(path: &std::path::Path) -> Result<Vec<FilterCommand>, FilterError> {
+    let contents = std::fs::read_to_string(path)?;
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "yaml" || extension == "yml")
+    {
+        Ok(serde_yaml::from_str(&contents)?)
+    } else {
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Validate the inputs and outputs of a pipeline of filters before it is run.
+///
+/// Checks that
+///  - the first filter has an input,
+///  - every identifier input (`$name`) is the output of a preceding filter, and
+///  - every path input is the output of a preceding filter or exists (remote URIs are not checked),
+///  - no output is a read-only HTTP(S) URL or zip archive,
+///  - the subset offset and shape of each filter are set together, and
+///  - the codec overrides of each filter are valid codec metadata.
+///
+/// # Errors
+/// Returns [`FilterError::InvalidParameters`] identifying the first invalid filter.
+pub fn validate_pipeline(filters: &[FilterCommand]) -> Result<(), FilterError> {
+    if filters.is_empty() {
+        return Err(FilterError::InvalidParameters(
+            "the pipeline has no filters".to_string(),
+        ));
+    }
+
+    let mut identifiers = HashSet::<String>::new();
+    let mut paths = HashSet::<std::path::PathBuf>::new();
+    for (i, filter) in filters.iter().enumerate() {
+        let invalid = |message: String| {
+            FilterError::InvalidParameters(format!("filter {i} ({}): {message}", filter.name()))
+        };
+
+        if let Err(FilterError::InvalidParameters(message)) = filter.common_args().subset() {
+            return Err(invalid(message));
+        }
+
+        let reencode = filter.common_args().reencode();
+        for (name, codecs) in [
+            ("array_to_array_codecs", &reencode.array_to_array_codecs),
+            ("bytes_to_bytes_codecs", &reencode.bytes_to_bytes_codecs),
+        ] {
+            if let Some(codecs) = codecs {
+                if serde_json::from_str::<Vec<Metadata>>(codecs).is_err() {
+                    return Err(invalid(format!(
+                        "{name} {codecs} is not an array of codec metadata"
+                    )));
+                }
+            }
+        }
+        if let Some(codec) = &reencode.array_to_bytes_codec {
+            if Metadata::try_from(codec.as_str()).is_err() {
+                return Err(invalid(format!(
+                    "array_to_bytes_codec {codec} is not codec metadata"
+                )));
+            }
+        }
+
+        let input = filter.io_args().input();
+        if i == 0 && input.is_none() {
+            return Err(invalid("the first filter must have an input".to_string()));
+        }
+        for input in input.iter().chain(filter.additional_inputs().iter()) {
+            match input {
+                PathOrIdentifier::Identifier(identifier) => {
+                    if !identifiers.contains(identifier) {
+                        return Err(invalid(format!(
+                            "{identifier} is not the output of a preceding filter"
+                        )));
+                    }
+                }
+                PathOrIdentifier::Path(path) => {
+                    let path_str = path.to_string_lossy();
+                    if !paths.contains(path) && !is_uri(&path_str) && !path.exists() {
+                        return Err(invalid(format!("{path:?} does not exist")));
+                    }
+                }
+            }
+        }
+
+        match filter.io_args().output() {
+            Some(PathOrIdentifier::Identifier(identifier)) => {
+                identifiers.insert(identifier.clone());
+            }
+            Some(PathOrIdentifier::Path(path)) => {
+                if is_http_url(&path.to_string_lossy()) {
+                    return Err(invalid(format!("{path:?} is a read-only HTTP(S) URL")));
+                }
+                if is_zip_path(&path.to_string_lossy()) {
+                    return Err(invalid(format!("{path:?} is a read-only zip archive")));
+                }
+                paths.insert(path.clone());
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use zarrs::{
+        array::{ArrayBuilder, DataType},
+        storage::store::MemoryStore,
+    };
+
+    use crate::filter::FilterStorage;
+
+    use super::*;
+
+    #[test]
+    fn pipeline_yaml() {
+        let filters: Vec<FilterCommand> = serde_yaml::from_str(
+            r#"
+- filter: reencode
+  input: array.zarr
+  output: $reencode0
+  chunk_shape: [32, 32, 32]
+  shard_shape: [256, 256, 256]
+- filter: crop
+  input: $reencode0
+  output: array_crop.zarr
+  offset: [256, 256, 256]
+  shape: [768, 768, 768]
+"#,
+        )
+        .unwrap();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[1].name(), "crop");
+    }
+
+    #[test]
+    fn pipeline_encoding() {
+        let filters: Vec<FilterCommand> = serde_yaml::from_str(
+            r#"
+- filter: downsample
+  input: array.zarr
+  output: array_2.zarr
+  stride: [2, 2]
+  chunk_shape: [0, 2]
+  bytes_to_bytes_codecs: [{"name": "gzip", "configuration": {"level": 1}}]
+"#,
+        )
+        .unwrap();
+        let reencode = filters[0].common_args().reencode();
+        let bytes_to_bytes_codecs: serde_json::Value =
+            serde_json::from_str(reencode.bytes_to_bytes_codecs.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            bytes_to_bytes_codecs,
+            serde_json::json!([{"name": "gzip", "configuration": {"level": 1}}])
+        );
+
+        let store: Arc<FilterStorage> = Arc::new(MemoryStore::new());
+        let array_input = ArrayBuilder::new(
+            vec![16, 16],
+            DataType::UInt8,
+            vec![8, 8].try_into().unwrap(),
+            0u8.into(),
+        )
+        .build(store.clone(), "/input")
+        .unwrap();
+        let filter = filters[0].init().unwrap();
+        let array_output = filter
+            .output_array_builder(&array_input, reencode)
+            .build(store, "/output")
+            .unwrap();
+        // The zero sized chunk dimension is resolved against the downsampled shape
+        assert_eq!(array_output.shape(), &[8, 8]);
+        assert_eq!(array_output.chunk_grid_shape().unwrap(), vec![1, 4]);
+        assert_eq!(array_output.codecs().bytes_to_bytes_codecs().len(), 1);
+
+        // Invalid codec metadata is rejected before the pipeline is run
+        let filters: Vec<FilterCommand> = serde_json::from_str(
+            r#"[{"filter": "reencode", "input": "https://example.com/a.zarr", "output": "b.zarr", "array_to_bytes_codec": "bytes"}]"#,
+        )
+        .unwrap();
+        assert!(validate_pipeline(&filters).is_err());
+    }
+
+    #[test]
+    fn pipeline_validate() {
+        let path = tempfile::TempDir::new().unwrap();
+        let input = path.path().to_string_lossy();
+        let pipeline = |filters: &str| -> Vec<FilterCommand> {
+            serde_json::from_str(&filters.replace("INPUT", &input)).unwrap()
+        };
+
+        assert!(validate_pipeline(&pipeline(
+            r#"[
+                {"filter": "reencode", "input": "INPUT", "output": "$a"},
+                {"filter": "reencode", "input": "$a", "output": "b.zarr"},
+                {"filter": "binary_op", "input": "b.zarr", "output": "c.zarr", "operator": "add", "other": "$a"},
+                {"filter": "reencode", "output": "d.zarr"}
+            ]"#
+        ))
+        .is_ok());
+
+        // An identifier must be the output of a preceding filter
+        assert!(validate_pipeline(&pipeline(
+            r#"[
+                {"filter": "reencode", "input": "$a", "output": "b.zarr"},
+                {"filter": "reencode", "input": "INPUT", "output": "$a"}
+            ]"#
+        ))
+        .is_err());
+
+        // Paths must exist or be the output of a preceding filter
+        assert!(validate_pipeline(&pipeline(
+            r#"[{"filter": "reencode", "input": "INPUT/missing.zarr", "output": "b.zarr"}]"#
+        ))
+        .is_err());
+
+        // HTTP(S) URLs are read-only
+        assert!(validate_pipeline(&pipeline(
+            r#"[{"filter": "reencode", "input": "https://example.com/a.zarr", "output": "b.zarr"}]"#
+        ))
+        .is_ok());
+        assert!(validate_pipeline(&pipeline(
+            r#"[{"filter": "reencode", "input": "INPUT", "output": "https://example.com/b.zarr"}]"#
+        ))
+        .is_err());
+
+        // The first filter must have an input
+        assert!(
+            validate_pipeline(&pipeline(r#"[{"filter": "reencode", "output": "b.zarr"}]"#))
+                .is_err()
+        );
+    }
+}
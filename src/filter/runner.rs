@@ -0,0 +1,1784 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use itertools::Itertools;
+use tempfile::TempDir;
+use zarrs::{
+    array::{Array, ArrayBuilder, ArrayCreateError, ArrayMetadata, DataType},
+    array_subset::ArraySubset,
+    storage::{store::MemoryStore, ListableStorageTraits, StorePrefix},
+};
+
+use crate::{
+    checkpoint::Checkpoint,
+    interrupt::InterruptStorageAdapter,
+    progress::{ProgressCallback, ProgressStage, ProgressStats, StageReport},
+    queue::{QueueCoordinator, QueueWorker},
+    storage::{is_uri, is_zip_path, RemoteStorageArgs},
+    task::{finalize_tasks, Task},
+    threads::tokio_runtime,
+    ZarrReencodingArgs,
+};
+
+use super::{
+    calculate_chunk_limit, copy_array_subset, element_size, validate_pipeline, ArraySubsetOverlap,
+    AsyncFilterStorage, FilterCommand, FilterCommandTraits, FilterError, FilterStorage,
+    FilterSubset, FilterTraits, FusedFilter, FusedStage, PathOrIdentifier, PathOrTempPath,
+};
+
+/// The seed of stochastic filters without a seed in a [deterministic](PipelineOptions::deterministic) run.
+pub const DETERMINISTIC_SEED: u64 = 0;
+
+/// Behaviour of a [`Pipeline`] if the output of a filter exists.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputExists {
+    /// Erase the output
+    #[default]
+    Erase,
+    /// Exit if the output already exists
+    Exit,
+}
+
+/// Options of a [`Pipeline`].
+///
+/// These match the global options of `zarrs_filter`.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineOptions {
+    /// Behaviour if the output of a filter exists.
+    pub exists: OutputExists,
+    /// Directory for temporary arrays, or the platform-specific temporary directory if [`None`].
+    pub tmp: Option<PathBuf>,
+    /// The maximum number of chunks concurrently processed by filters without their own chunk limit.
+    pub chunk_limit: Option<usize>,
+    /// Fuse consecutive element-wise filters connected by a temporary array.
+    pub fuse: bool,
+    /// Run filters with the same input and output in place.
+    pub in_place: bool,
+    /// Read and write the chunks of element-wise filters (and fused groups) asynchronously.
+    pub run_async: bool,
+    /// The maximum number of chunks in flight with [`run_async`](PipelineOptions::run_async).
+    pub concurrent_chunks: Option<usize>,
+    /// Read ahead this many chunks of element-wise filters (and fused groups) while the current chunks are processed and written.
+    ///
+    /// This has no effect with [`run_async`](PipelineOptions::run_async).
+    pub prefetch: Option<usize>,
+    /// Process the chunks of element-wise filters (and fused groups) in blocks of at most this many bytes, see [`FusedFilter::with_max_block_bytes`].
+    ///
+    /// This has no effect with [`run_async`](PipelineOptions::run_async) or [`prefetch`](PipelineOptions::prefetch).
+    pub max_block_bytes: Option<usize>,
+    /// Adapt the number of chunks of element-wise filters (and fused groups) processed concurrently to the observed throughput and memory headroom, see [`AdaptiveConcurrency`](super::AdaptiveConcurrency).
+    ///
+    /// The chunk limit is the maximum concurrency.
+    /// This has no effect with [`run_async`](PipelineOptions::run_async) or [`prefetch`](PipelineOptions::prefetch).
+    pub adaptive: bool,
+    /// Process the chunks of each filter one at a time in order, and seed stochastic filters without a seed with [`DETERMINISTIC_SEED`], so outputs and checkpoints are reproducible.
+    ///
+    /// This cannot be combined with a [`chunk_limit`](PipelineOptions::chunk_limit) (or a chunk limit of a filter), [`run_async`](PipelineOptions::run_async), [`prefetch`](PipelineOptions::prefetch), [`adaptive`](PipelineOptions::adaptive), or [`worker`](PipelineOptions::worker).
+    pub deterministic: bool,
+    /// Resume an interrupted run.
+    pub resume: bool,
+    /// Only process the chunks of this task of an array job, see [`crate::task`].
+    ///
+    /// Every filter (or fused group) must be element-wise with an output path, and the outputs are not erased.
+    pub task: Option<Task>,
+    /// Only process the chunks leased from the coordinator at this address (e.g. `node01:7878`), see [`crate::queue`] and [`Pipeline::coordinate`].
+    ///
+    /// The pipeline must have one stage, which is element-wise with an output path, and the output is not erased.
+    pub worker: Option<String>,
+    /// Options for remote stores.
+    pub remote: RemoteStorageArgs,
+}
+
+impl PipelineOptions {
+    /// Returns true if the outputs are shared with other processes (the tasks of an array job or workers), so they are not erased.
+    fn is_shared(&self) -> bool {
+        self.task.is_some() || self.worker.is_some()
+    }
+}
+
+/// Observes the progress of a [`Pipeline`] run.
+///
+/// A stage is a filter, or a group of fused filters, identified by the range of its filter indices.
+/// Every method does nothing by default.
+pub trait PipelineObserver: Sync {
+    /// Called when the arrays of the filter at `index` are opened, with a description of its arguments, inputs, and output.
+    fn opened(&self, _index: usize, _description: &str) {}
+
+    /// Called when the stage `filters` named `name` is skipped, since its `output` is complete.
+    fn skipped(&self, _filters: Range<usize>, _name: &str, _output: &Path) {}
+
+    /// Called when the stage `filters` starts.
+    fn started(&self, _filters: Range<usize>) {}
+
+    /// Called with the progress `stats` of the stage `filters`, which have been recorded in `stage`.
+    fn progress(&self, _filters: Range<usize>, _stage: &ProgressStage, _stats: ProgressStats) {}
+
+    /// Called when the stage `filters` has written its `output`.
+    fn finished(&self, _filters: Range<usize>, _report: &StageReport, _output: &Path) {}
+}
+
+impl PipelineObserver for () {}
+
+/// The planned output of a filter of a [`Pipeline`] dry run.
+#[derive(Debug, Clone)]
+pub struct FilterPlan {
+    /// The filter name.
+    pub name: String,
+    /// The filter arguments.
+    pub args: String,
+    /// The reencoding arguments of the output.
+    pub encode: String,
+    /// The input path.
+    pub input: PathBuf,
+    /// The input data type.
+    pub input_data_type: DataType,
+    /// The input shape.
+    pub input_shape: Vec<u64>,
+    /// The output path.
+    pub output: PathBuf,
+    /// Whether the output is a temporary array.
+    pub output_temporary: bool,
+    /// The output data type.
+    pub output_data_type: DataType,
+    /// The output shape.
+    pub output_shape: Vec<u64>,
+    /// The memory per chunk in bytes.
+    pub memory_per_chunk: usize,
+    /// The chunk limit, or why it cannot be calculated.
+    pub chunk_limit: Result<usize, String>,
+    /// The number of output chunks.
+    pub output_chunks: u64,
+    /// The shape of the output chunk grid.
+    pub output_chunk_grid_shape: Vec<u64>,
+    /// The uncompressed output size in bytes.
+    pub output_size: u64,
+}
+
+impl std::fmt::Display for FilterPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n\targs:   {}\n\tencode: {}\n\tinput:  {} {:?} {:?}\n\toutput: {} {:?} {:?}\n\tmemory per chunk: ~{:.2}MB\n\tchunk limit: {}\n\toutput chunks: {}\n\toutput size: ~{:.2}MB uncompressed",
+            self.name,
+            self.args,
+            self.encode,
+            self.input_data_type,
+            self.input_shape,
+            self.input,
+            self.output_data_type,
+            self.output_shape,
+            self.output,
+            self.memory_per_chunk as f32 / 1e6, // MB
+            match &self.chunk_limit {
+                Ok(chunk_limit) => chunk_limit.to_string(),
+                Err(err) => err.clone(),
+            },
+            self.output_chunks,
+            self.output_size as f32 / 1e6, // MB
+        )
+    }
+}
+
+/// Load an array to be read by a filter, reads fail after an interrupt so no new chunks are processed.
+///
+/// # Errors
+/// Returns an error if the store cannot be opened or the array does not exist.
+pub fn load_array<P: Into<PathBuf>>(
+    path: P,
+    remote: &RemoteStorageArgs,
+) -> Result<Array<FilterStorage>, ArrayCreateError> {
+    let store = remote.storage_from_uri(&path.into().to_string_lossy())?;
+    let store: Arc<FilterStorage> = Arc::new(InterruptStorageAdapter::new(store));
+    Array::open(store, "/")
+}
+
+/// Load an existing array to be written by a filter.
+///
+/// Unlike [`load_array`], the store is not wrapped in an [`InterruptStorageAdapter`], so in-flight chunk writes finish after an interrupt.
+///
+/// # Errors
+/// Returns an error if the store cannot be opened or the array does not exist.
+pub fn load_output_array<P: Into<PathBuf>>(
+    path: P,
+    remote: &RemoteStorageArgs,
+) -> Result<Array<FilterStorage>, ArrayCreateError> {
+    let store: Arc<FilterStorage> = remote.storage_from_uri(&path.into().to_string_lossy())?;
+    Array::open(store, "/")
+}
+
+/// Create an array to be written by a filter, removing the array at `path` if it exists.
+///
+/// # Errors
+/// Returns an error if the store cannot be opened or erased, or the array cannot be created.
+pub fn create_array<P: Into<PathBuf>>(
+    path: P,
+    builder: &ArrayBuilder,
+    remote: &RemoteStorageArgs,
+) -> Result<Array<FilterStorage>, ArrayCreateError> {
+    let store: Arc<FilterStorage> = remote.storage_from_uri(&path.into().to_string_lossy())?;
+    store.erase_prefix(&StorePrefix::root())?;
+    builder.build(store, "/")
+}
+
+/// Open an array being resumed without removing the chunks written by the interrupted run.
+///
+/// # Errors
+/// Returns an error if the store cannot be opened or the array cannot be created.
+pub fn resume_array<P: Into<PathBuf>>(
+    path: P,
+    builder: &ArrayBuilder,
+    remote: &RemoteStorageArgs,
+) -> Result<Array<FilterStorage>, ArrayCreateError> {
+    let store: Arc<FilterStorage> = remote.storage_from_uri(&path.into().to_string_lossy())?;
+    builder.build(store, "/")
+}
+
+/// Open an asynchronous view of `array` at `path` to read and write its chunks.
+///
+/// The metadata is taken from `array`, since the metadata of an output is erased while its filter runs.
+/// A remote store is opened with the retries, rate limit, and cache of `remote`.
+///
+/// # Errors
+/// Returns an error if the store cannot be opened.
+pub fn async_array(
+    path: &Path,
+    array: &Array<FilterStorage>,
+    remote: &RemoteStorageArgs,
+) -> Result<Array<AsyncFilterStorage>, ArrayCreateError> {
+    let store: Arc<AsyncFilterStorage> = remote.async_storage_from_uri(&path.to_string_lossy())?;
+    Array::new_with_metadata(store, "/", array.metadata().clone())
+}
+
+fn get_array_input_output(
+    filter: &dyn FilterTraits,
+    input: &Path,
+    output: &Path,
+    reencode: &ZarrReencodingArgs,
+    remote: &RemoteStorageArgs,
+) -> Result<(Array<FilterStorage>, Array<FilterStorage>), ArrayCreateError> {
+    let array_input = load_array(input, remote)?;
+    let array_output = create_array(
+        output,
+        &filter.output_array_builder(&array_input, reencode),
+        remote,
+    )?;
+    Ok((array_input, array_output))
+}
+
+/// Open an array twice to be read and written by a filter in place.
+fn get_array_input_output_in_place(
+    filter: &dyn FilterTraits,
+    path: &Path,
+    reencode: &ZarrReencodingArgs,
+    remote: &RemoteStorageArgs,
+) -> Result<(Array<FilterStorage>, Array<FilterStorage>), FilterError> {
+    if !filter.supports_in_place() {
+        return Err(FilterError::InvalidParameters(format!(
+            "the filter cannot be run in place on {path:?}"
+        )));
+    }
+    let array_input = load_array(path, remote)?;
+    let array_output = load_output_array(path, remote)?;
+    let array_output_expected = filter
+        .output_array_builder(&array_input, reencode)
+        .build(Arc::new(MemoryStore::new()), "/")?;
+    if array_output_expected.metadata() != array_input.metadata() {
+        return Err(FilterError::InvalidParameters(format!(
+            "the filter changes the metadata of {path:?} and cannot be run in place"
+        )));
+    }
+    Ok((array_input, array_output))
+}
+
+/// The cropped arrays of a filter restricted to a subset of its input.
+struct SubsetArrays {
+    /// The input subset, including the halo of a neighbourhood filter, and the subset within it.
+    subset_overlap: ArraySubsetOverlap,
+    /// The subset of the output that is written.
+    subset_output: ArraySubset,
+    input: Array<FilterStorage>,
+    /// The cropped additional inputs, if they match the input shape.
+    additional_inputs: Vec<Array<FilterStorage>>,
+    /// The cropped output, if it is filled into an output with the shape of the input or has a halo to remove.
+    output: Option<Array<FilterStorage>>,
+    _tmp_dirs: Vec<TempDir>,
+}
+
+impl SubsetArrays {
+    /// Create the cropped arrays of `filter` restricted to `subset` in `tmp_dir`.
+    ///
+    /// If the filter has a [halo](FilterTraits::halo), the inputs are cropped to the subset extended by the halo so the subset matches a filter of the whole input.
+    /// `additional_arrays_input` are the additional inputs to crop, which must match the input shape.
+    /// Returns the output of the filter, which is cropped unless [`FilterSubset::fill`] is set.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        filter: &dyn FilterTraits,
+        subset: &FilterSubset,
+        array_input: &Array<FilterStorage>,
+        additional_arrays_input: &[Array<FilterStorage>],
+        output: &Path,
+        reencode: &ZarrReencodingArgs,
+        tmp_dir: &Path,
+        remote: &RemoteStorageArgs,
+    ) -> Result<(Self, Array<FilterStorage>), FilterError> {
+        let array_subset = subset.array_subset(array_input.shape())?;
+        let halo = filter
+            .halo(array_input.dimensionality())
+            .filter(|_| filter.output_shape(array_input).is_none());
+        let subset_overlap = subset.overlap(array_input.shape(), halo.as_deref())?;
+        let subset_input = subset_overlap.subset_input();
+        let mut tmp_dirs = vec![];
+        let mut create_subset_array = |builder: &ArrayBuilder| {
+            let tmp = TempDir::new_in(tmp_dir)?;
+            let array = create_array(tmp.path(), builder, remote)?;
+            tmp_dirs.push(tmp);
+            Ok::<_, FilterError>(array)
+        };
+        let input = create_subset_array(&FilterSubset::array_builder(array_input, subset_input))?;
+        let additional_inputs = additional_arrays_input
+            .iter()
+            .map(|additional_input| {
+                create_subset_array(&FilterSubset::array_builder(additional_input, subset_input))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let builder_output = filter.output_array_builder(&input, reencode);
+        let (array_output, subset_output, output_cropped) = if subset.fill {
+            if filter.output_shape(&input).is_some() {
+                return Err(FilterError::InvalidParameters(
+                    "the filter changes the array shape, so its subset cannot be filled"
+                        .to_string(),
+                ));
+            }
+            let array_output = create_array(
+                output,
+                &filter.output_array_builder(array_input, reencode),
+                remote,
+            )?;
+            let output_cropped = create_subset_array(&builder_output)?;
+            (array_output, array_subset, Some(output_cropped))
+        } else if subset_input != &array_subset {
+            let mut builder_output_subset = filter.output_array_builder(&input, reencode);
+            builder_output_subset.shape(array_subset.shape().to_vec());
+            let array_output = create_array(output, &builder_output_subset, remote)?;
+            let output_cropped = create_subset_array(&builder_output)?;
+            let subset_output = ArraySubset::new_with_shape(array_subset.shape().to_vec());
+            (array_output, subset_output, Some(output_cropped))
+        } else {
+            let array_output = create_array(output, &builder_output, remote)?;
+            let subset_output = ArraySubset::new_with_shape(array_output.shape().to_vec());
+            (array_output, subset_output, None)
+        };
+        Ok((
+            Self {
+                subset_overlap,
+                subset_output,
+                input,
+                additional_inputs,
+                output: output_cropped,
+                _tmp_dirs: tmp_dirs,
+            },
+            array_output,
+        ))
+    }
+
+    /// Crop the inputs, apply the filter, and write the cropped output into `array_output` if filling or removing a halo.
+    fn apply(
+        &mut self,
+        filter: &dyn FilterTraits,
+        array_input: &Array<FilterStorage>,
+        additional_arrays_input: &[Array<FilterStorage>],
+        array_output: &mut Array<FilterStorage>,
+        chunk_limit: Option<usize>,
+        progress_callback: &ProgressCallback,
+    ) -> Result<(), FilterError> {
+        let subset_input = self.subset_overlap.subset_input();
+        let subset_all = ArraySubset::new_with_shape(subset_input.shape().to_vec());
+        copy_array_subset(
+            array_input,
+            subset_input,
+            &self.input,
+            &subset_all,
+            chunk_limit,
+            progress_callback,
+        )?;
+        let additional_arrays_input = if self.additional_inputs.is_empty() {
+            additional_arrays_input
+        } else {
+            for (additional_input, cropped_input) in
+                std::iter::zip(additional_arrays_input, &self.additional_inputs)
+            {
+                copy_array_subset(
+                    additional_input,
+                    subset_input,
+                    cropped_input,
+                    &subset_all,
+                    chunk_limit,
+                    progress_callback,
+                )?;
+            }
+            &self.additional_inputs
+        };
+        let arrays_input = std::iter::once(&self.input)
+            .chain(additional_arrays_input)
+            .collect_vec();
+        if let Some(output_cropped) = &mut self.output {
+            filter.apply_inputs(&arrays_input, output_cropped, progress_callback)?;
+            copy_array_subset(
+                output_cropped,
+                self.subset_overlap.subset_output_in_input(),
+                array_output,
+                &self.subset_output,
+                chunk_limit,
+                progress_callback,
+            )
+        } else {
+            filter.apply_inputs(&arrays_input, array_output, progress_callback)
+        }
+    }
+}
+
+fn get_path(
+    path_or_id: &Option<PathOrIdentifier>,
+    tmp_dir: &Path,
+    id_to_path: &mut HashMap<String, Arc<TempDir>>,
+    last_output: &Option<PathBuf>,
+) -> std::io::Result<PathOrTempPath> {
+    if let Some(path_or_id) = path_or_id {
+        match path_or_id {
+            PathOrIdentifier::Identifier(id) => {
+                // Named temporary output
+                let entry = id_to_path.entry(id.clone()).or_insert_with(|| {
+                    tempfile::TempDir::with_prefix_in(id, tmp_dir)
+                        .unwrap()
+                        .into()
+                });
+                Ok(PathOrTempPath::TempPath(entry.clone()))
+            }
+            PathOrIdentifier::Path(path) => {
+                // Long lived output
+                Ok(PathOrTempPath::Path(path.clone()))
+            }
+        }
+    } else {
+        // Unnamed temporary
+        if let Some(last_output) = last_output {
+            Ok(PathOrTempPath::Path(last_output.clone()))
+        } else {
+            Ok(PathOrTempPath::TempPath(
+                tempfile::TempDir::new_in(tmp_dir)?.into(),
+            ))
+        }
+    }
+}
+
+struct InputsOutputsExists {
+    input_paths: Vec<PathOrTempPath>,
+    additional_input_paths: Vec<Vec<PathOrTempPath>>,
+    output_paths: Vec<PathOrTempPath>,
+    exists: Vec<bool>,
+}
+
+/// Returns true if the output at `path` exists.
+///
+/// A remote output exists if its store has any keys, so it is checked through the store rather than the local file system.
+fn output_exists(path: &Path, remote: &RemoteStorageArgs) -> Result<bool, FilterError> {
+    let path_str = path.to_string_lossy();
+    if is_uri(&path_str) {
+        let store = remote.storage_from_uri(&path_str)?;
+        let keys_prefixes = store.list_dir(&StorePrefix::root())?;
+        Ok(!keys_prefixes.keys().is_empty() || !keys_prefixes.prefixes().is_empty())
+    } else {
+        Ok(path.exists())
+    }
+}
+
+fn get_input_output_paths(
+    filters: &[FilterCommand],
+    tmp_dir: &Path,
+    remote: &RemoteStorageArgs,
+) -> Result<InputsOutputsExists, FilterError> {
+    let mut id_to_path = HashMap::<String, Arc<TempDir>>::new();
+    let mut input_paths = Vec::<PathOrTempPath>::with_capacity(filters.len());
+    let mut additional_input_paths = Vec::<Vec<PathOrTempPath>>::with_capacity(filters.len());
+    let mut output_paths = Vec::<PathOrTempPath>::with_capacity(filters.len());
+    let mut last_output: Option<PathBuf> = None;
+    let mut exists = Vec::<bool>::with_capacity(filters.len());
+    for filter in filters {
+        if let Some(PathOrIdentifier::Path(output_path)) = filter.io_args().output() {
+            exists.push(output_exists(output_path, remote)?);
+        } else {
+            exists.push(false);
+        }
+
+        let input_path = get_path(
+            filter.io_args().input(),
+            tmp_dir,
+            &mut id_to_path,
+            &last_output,
+        )?;
+        let additional_input_path = filter
+            .additional_inputs()
+            .into_iter()
+            .map(|additional_input| {
+                get_path(&Some(additional_input), tmp_dir, &mut id_to_path, &None)
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let output_path = get_path(filter.io_args().output(), tmp_dir, &mut id_to_path, &None)?;
+        if input_paths.is_empty() {
+            if let PathOrTempPath::TempPath(_) = input_path {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "the first filter must have a valid input path",
+                ))?
+            }
+        }
+        last_output = Some(output_path.path().to_path_buf());
+
+        input_paths.push(input_path);
+        additional_input_paths.push(additional_input_path);
+        output_paths.push(output_path);
+    }
+
+    Ok(InputsOutputsExists {
+        input_paths,
+        additional_input_paths,
+        output_paths,
+        exists,
+    })
+}
+
+/// A pipeline of filters, run in order with the outputs of earlier filters as the inputs of later filters.
+///
+/// This is the runner of `zarrs_filter`, so a pipeline read with [`read_pipeline`](super::read_pipeline) or built from [`FilterCommand`]s runs as it would on the command line.
+///
+/// ```no_run
+/// # use zarrs_tools::filter::{read_pipeline, Pipeline, PipelineOptions};
+/// let filter_commands = read_pipeline("pipeline.yaml".as_ref())?;
+/// let pipeline = Pipeline::new(filter_commands, PipelineOptions::default())?;
+/// let reports = pipeline.run(&())?;
+/// # Ok::<_, zarrs_tools::filter::FilterError>(())
+/// ```
+pub struct Pipeline {
+    filter_commands: Vec<FilterCommand>,
+    filters: Vec<Box<dyn FilterTraits>>,
+    input_paths: Vec<PathOrTempPath>,
+    additional_input_paths: Vec<Vec<PathOrTempPath>>,
+    output_paths: Vec<PathOrTempPath>,
+    exists: Vec<bool>,
+    in_place: Vec<bool>,
+    subsets: Vec<Option<FilterSubset>>,
+    options: PipelineOptions,
+    tmp_dir: PathOrTempPath,
+}
+
+impl Pipeline {
+    /// Create a pipeline of `filter_commands` with `options`.
+    ///
+    /// The inputs and outputs of the pipeline are validated and the filters are instantiated, but no outputs are created.
+    ///
+    /// # Errors
+    /// Returns an error if the pipeline is invalid, an output exists with [`OutputExists::Exit`], or a filter cannot be instantiated.
+    pub fn new(
+        mut filter_commands: Vec<FilterCommand>,
+        options: PipelineOptions,
+    ) -> Result<Self, FilterError> {
+        // Validate the inputs and outputs of the pipeline before creating any outputs
+        validate_pipeline(&filter_commands)?;
+        if options.deterministic
+            && (options.chunk_limit.is_some()
+                || options.run_async
+                || options.prefetch.is_some()
+                || options.adaptive
+                || options.worker.is_some())
+        {
+            return Err(FilterError::InvalidParameters(
+                "a deterministic run cannot have a chunk limit, be asynchronous, prefetch chunks, adapt its concurrency, or be a worker".to_string(),
+            ));
+        }
+
+        // Create temporary directory
+        let tmp_dir = if let Some(tmp) = &options.tmp {
+            PathOrTempPath::Path(tmp.clone())
+        } else {
+            PathOrTempPath::TempPath(tempfile::tempdir()?.into())
+        };
+
+        // Propagate global settings to filters
+        for filter in &mut filter_commands {
+            if let Some(global_chunk_limit) = options.chunk_limit {
+                let filter_chunk_limit = filter.common_args_mut().chunk_limit_mut();
+                if filter_chunk_limit.is_none() {
+                    *filter_chunk_limit = Some(global_chunk_limit);
+                }
+            }
+            if options.deterministic {
+                // Chunks are processed in order with a chunk limit of one
+                if filter.common_args().chunk_limit().is_some() {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "a deterministic run processes one chunk at a time, so the {} filter cannot have a chunk limit",
+                        filter.name()
+                    )));
+                }
+                *filter.common_args_mut().chunk_limit_mut() = Some(1);
+                if let Some(seed) = filter.seed_mut() {
+                    seed.get_or_insert(DETERMINISTIC_SEED);
+                }
+            }
+        }
+
+        // Get the input and output paths
+        let InputsOutputsExists {
+            input_paths,
+            additional_input_paths,
+            output_paths,
+            exists,
+        } = get_input_output_paths(&filter_commands, tmp_dir.path(), &options.remote)?;
+
+        // Filters with the same input and output are run in place
+        let in_place = std::iter::zip(&input_paths, &output_paths)
+            .map(|(input, output)| input.path() == output.path())
+            .collect_vec();
+        if !options.in_place && in_place.iter().any(|i| *i) {
+            return Err(FilterError::InvalidParameters(
+                "the input and output of a filter are the same, use --in-place to write to the input"
+                    .to_string(),
+            ));
+        }
+
+        // A memory-mapped chunk must not be rewritten while it is read
+        if options.remote.mmap
+            && itertools::izip!(&input_paths, &additional_input_paths, &output_paths).any(
+                |(input, additional_inputs, output)| {
+                    std::iter::once(input)
+                        .chain(additional_inputs)
+                        .any(|input| input.path() == output.path())
+                },
+            )
+        {
+            return Err(FilterError::InvalidParameters(
+                "memory maps cannot be used by a filter that writes to one of its inputs"
+                    .to_string(),
+            ));
+        }
+
+        // Filters restricted to a subset of their input
+        let subsets: Vec<Option<FilterSubset>> = filter_commands
+            .iter()
+            .map(|filter| filter.common_args().subset())
+            .try_collect()?;
+        if std::iter::zip(&subsets, &in_place)
+            .any(|(subset, in_place)| subset.is_some() && *in_place)
+        {
+            return Err(FilterError::InvalidParameters(
+                "a filter restricted to a subset cannot be run in place".to_string(),
+            ));
+        }
+
+        // Handle an existing output
+        match options.exists {
+            OutputExists::Exit => {
+                if !options.resume
+                    && !options.is_shared()
+                    && std::iter::zip(&exists, &in_place)
+                        .any(|(exists, in_place)| *exists && !in_place)
+                {
+                    return Err(FilterError::Other("Output exists, exiting".to_string()));
+                }
+            }
+            OutputExists::Erase => {}
+        }
+
+        // Instantiate the filters
+        let filters: Vec<Box<dyn FilterTraits>> = filter_commands
+            .iter()
+            .map(|filter| filter.init())
+            .try_collect()?;
+
+        let pipeline = Self {
+            filter_commands,
+            filters,
+            input_paths,
+            additional_input_paths,
+            output_paths,
+            exists,
+            in_place,
+            subsets,
+            options,
+            tmp_dir,
+        };
+        if pipeline.options.is_shared() {
+            let outputs = pipeline.task_outputs()?;
+            if pipeline.options.worker.is_some() && outputs.len() != 1 {
+                return Err(FilterError::InvalidParameters(
+                    "a worker must run a pipeline with one stage".to_string(),
+                ));
+            }
+        }
+        if pipeline.options.task.is_some() && pipeline.options.worker.is_some() {
+            return Err(FilterError::InvalidParameters(
+                "a worker cannot be a task of an array job".to_string(),
+            ));
+        }
+        Ok(pipeline)
+    }
+
+    /// Returns the filter commands, with the global chunk limit applied.
+    #[must_use]
+    pub fn filter_commands(&self) -> &[FilterCommand] {
+        &self.filter_commands
+    }
+
+    /// Returns the input path of each filter.
+    #[must_use]
+    pub fn input_paths(&self) -> &[PathOrTempPath] {
+        &self.input_paths
+    }
+
+    /// Returns the output path of each filter.
+    #[must_use]
+    pub fn output_paths(&self) -> &[PathOrTempPath] {
+        &self.output_paths
+    }
+
+    /// Returns the stages that are run, as ranges of filter indices.
+    ///
+    /// Each filter is a stage, unless [fused](PipelineOptions::fuse) with its neighbours.
+    #[must_use]
+    pub fn stages(&self) -> Vec<Range<usize>> {
+        if self.options.fuse {
+            self.fused_groups()
+        } else {
+            (0..self.filters.len()).map(|i| i..i + 1).collect_vec()
+        }
+    }
+
+    /// Returns the output path of each stage, which are written chunk by chunk by the tasks of an array job or workers.
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if a stage is not element-wise (without additional inputs or a subset) or its output is temporary.
+    fn task_outputs(&self) -> Result<Vec<&Path>, FilterError> {
+        self.stages()
+            .into_iter()
+            .map(|group| {
+                let last = group.end - 1;
+                let chunkwise = group.clone().all(|i| {
+                    self.filters[i].is_elementwise()
+                        && self.additional_input_paths[i].is_empty()
+                        && self.subsets[i].is_none()
+                });
+                match &self.output_paths[last] {
+                    PathOrTempPath::Path(output) if chunkwise => Ok(output.as_path()),
+                    _ => Err(FilterError::InvalidParameters(format!(
+                        "the filter {} is not element-wise with an output path, so it cannot be run as a task of an array job or by workers",
+                        self.filter_commands[last].name()
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    /// Verify that each of `task_count` tasks of an array job has written its chunks of every output, then remove their checkpoints.
+    ///
+    /// # Errors
+    /// Returns an error if a stage cannot be run as a task, an output cannot be opened, or a task is incomplete.
+    pub fn finalize_tasks(&self, task_count: usize) -> Result<(), FilterError> {
+        for output in self.task_outputs()? {
+            let array_output = load_output_array(output, &self.options.remote)?;
+            finalize_tasks(
+                &output.to_string_lossy(),
+                task_count,
+                &array_output.chunk_grid_shape().unwrap(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Hand out the chunks of the output to workers connecting to `address` (e.g. `0.0.0.0:7878`) in ranges of up to `batch` chunks, until every chunk has been written.
+    ///
+    /// The checkpoint of the output is the completion ledger, so an interrupted coordinator can be resumed with [`PipelineOptions::resume`].
+    /// The workers run the pipeline with [`PipelineOptions::worker`], and can join or leave at any time.
+    ///
+    /// # Errors
+    /// Returns an error if the pipeline does not have one stage that is element-wise with an output path, the address cannot be bound, or the coordinator is interrupted.
+    pub fn coordinate(&self, address: &str, batch: u64) -> Result<(), FilterError> {
+        let [output] = self.task_outputs()?[..] else {
+            return Err(FilterError::InvalidParameters(
+                "a coordinator must run a pipeline with one stage".to_string(),
+            ));
+        };
+        let chunk_grid_shape = self.dry_run()?.pop().unwrap().output_chunk_grid_shape;
+        let manifest_path = Checkpoint::manifest_path(&output.to_string_lossy());
+        let ledger = if self.options.resume {
+            Checkpoint::resume(&manifest_path)?
+        } else {
+            None
+        };
+        let ledger = match ledger {
+            Some(ledger) => ledger,
+            None => Checkpoint::create(&manifest_path)?,
+        };
+        let listener = std::net::TcpListener::bind(address)?;
+        QueueCoordinator::new(ledger, chunk_grid_shape, batch)
+            .serve(&listener)?
+            .finish()?;
+        Ok(())
+    }
+
+    /// Group consecutive filters that can be fused.
+    ///
+    /// A filter is fused with the next filter if both are element-wise without additional inputs or a subset, and the output of the filter is a temporary array which is only the input of the next filter.
+    fn fused_groups(&self) -> Vec<Range<usize>> {
+        let num_uses = |path: &Path| {
+            self.input_paths
+                .iter()
+                .chain(self.additional_input_paths.iter().flatten())
+                .filter(|input_path| input_path.path() == path)
+                .count()
+        };
+        let fusable = |i: usize| {
+            self.filters[i].is_elementwise()
+                && self.additional_input_paths[i].is_empty()
+                && self.subsets[i].is_none()
+        };
+
+        let mut groups = Vec::<Range<usize>>::new();
+        for i in 0..self.filters.len() {
+            if let Some(group) = groups.last_mut() {
+                let previous = i - 1;
+                if fusable(previous)
+                    && fusable(i)
+                    && matches!(self.output_paths[previous], PathOrTempPath::TempPath(_))
+                    && self.input_paths[i].path() == self.output_paths[previous].path()
+                    && num_uses(self.output_paths[previous].path()) == 1
+                {
+                    group.end = i + 1;
+                    continue;
+                }
+            }
+            groups.push(i..i + 1);
+        }
+        groups
+    }
+
+    /// Plan the memory per chunk, chunk limit, and output size of each filter without reading or writing any chunks.
+    ///
+    /// The outputs are created in memory, so the inputs of later filters are the planned outputs of earlier filters.
+    ///
+    /// # Errors
+    /// Returns an error if an input cannot be opened, or a filter is incompatible with its inputs and output.
+    pub fn dry_run(&self) -> Result<Vec<FilterPlan>, FilterError> {
+        let remote = &self.options.remote;
+        let mut planned_outputs = HashMap::<PathBuf, ArrayMetadata>::new();
+        let open_input = |path: &Path, planned_outputs: &HashMap<PathBuf, ArrayMetadata>| {
+            if let Some(metadata) = planned_outputs.get(path) {
+                let store: Arc<FilterStorage> = Arc::new(MemoryStore::new());
+                Array::new_with_metadata(store, "/", metadata.clone())
+            } else {
+                load_array(path, remote)
+            }
+        };
+        let mut plans = Vec::with_capacity(self.filters.len());
+        for (filter_command, filter, input, additional_inputs, output, subset) in itertools::izip!(
+            &self.filter_commands,
+            &self.filters,
+            &self.input_paths,
+            &self.additional_input_paths,
+            &self.output_paths,
+            &self.subsets
+        ) {
+            let array_input = open_input(input.path(), &planned_outputs)?;
+            let additional_arrays_input = additional_inputs
+                .iter()
+                .map(|additional_input| open_input(additional_input.path(), &planned_outputs))
+                .collect::<Result<Vec<_>, _>>()?;
+            let store: Arc<FilterStorage> = Arc::new(MemoryStore::new());
+            let array_output =
+                if let Some(subset) = subset.as_ref().filter(|subset| !subset.fill) {
+                    let array_input_subset = FilterSubset::array_builder(
+                        &array_input,
+                        &subset.array_subset(array_input.shape())?,
+                    )
+                    .build(store.clone(), "/")?;
+                    filter.output_array_builder(
+                        &array_input_subset,
+                        filter_command.common_args().reencode(),
+                    )
+                } else {
+                    if let Some(subset) = subset {
+                        subset.array_subset(array_input.shape())?;
+                    }
+                    filter
+                        .output_array_builder(&array_input, filter_command.common_args().reencode())
+                }
+                .build(store, "/")?;
+            let arrays_input = std::iter::once(&array_input)
+                .chain(&additional_arrays_input)
+                .collect_vec();
+            filter.is_compatible_arrays(&arrays_input, &array_output)?;
+
+            let chunk_input =
+                array_input.chunk_array_representation(&vec![0; array_input.dimensionality()])?;
+            let chunk_output =
+                array_output.chunk_array_representation(&vec![0; array_output.dimensionality()])?;
+            let memory_per_chunk = filter.memory_per_chunk(&chunk_input, &chunk_output);
+            let chunk_limit = if let Some(chunk_limit) = filter_command.common_args().chunk_limit()
+            {
+                Ok(*chunk_limit)
+            } else {
+                calculate_chunk_limit(memory_per_chunk).map_err(|err| err.to_string())
+            };
+            let output_chunk_grid_shape = array_output.chunk_grid_shape().unwrap();
+            let output_chunks = output_chunk_grid_shape.iter().product::<u64>();
+            let output_size =
+                array_output.shape().iter().product::<u64>() * element_size(&chunk_output) as u64;
+            plans.push(FilterPlan {
+                name: filter_command.name(),
+                args: filter_command.args_str(),
+                encode: filter_command.reencode_str(),
+                input: input.path().to_path_buf(),
+                input_data_type: array_input.data_type().clone(),
+                input_shape: array_input.shape().to_vec(),
+                output: output.path().to_path_buf(),
+                output_temporary: matches!(output, PathOrTempPath::TempPath(_)),
+                output_data_type: array_output.data_type().clone(),
+                output_shape: array_output.shape().to_vec(),
+                memory_per_chunk,
+                chunk_limit,
+                output_chunks,
+                output_chunk_grid_shape,
+                output_size,
+            });
+            planned_outputs.insert(output.path().to_path_buf(), array_output.metadata().clone());
+        }
+        Ok(plans)
+    }
+
+    /// Run the pipeline, reporting its progress to `observer`.
+    ///
+    /// Returns the timing and throughput report of each stage that is run.
+    ///
+    /// # Errors
+    /// Returns an error if an array cannot be opened or created, a filter is incompatible with its inputs and output, or a filter fails.
+    pub fn run(&self, observer: &dyn PipelineObserver) -> Result<Vec<StageReport>, FilterError> {
+        let options = &self.options;
+        let remote = &options.remote;
+        let filter_commands = &self.filter_commands;
+        let filters = &self.filters;
+        let input_paths = &self.input_paths;
+        let output_paths = &self.output_paths;
+        let subsets = &self.subsets;
+        let in_place = &self.in_place;
+
+        // Group filters that are run together
+        let groups = self.stages();
+
+        // Checkpoint element-wise filters (and fused groups) with an output path, so an interrupted run can be resumed
+        // The output metadata is only written when a filter finishes, so other filters (except those run in place) are complete if their output exists
+        let group_of = (0..filters.len())
+            .map(|i| groups.iter().position(|group| group.contains(&i)).unwrap())
+            .collect_vec();
+        let mut checkpoints = Vec::<Option<Checkpoint>>::with_capacity(groups.len());
+        let mut complete = Vec::<bool>::with_capacity(groups.len());
+        let mut resumed = Vec::<bool>::with_capacity(groups.len());
+        for group in &groups {
+            let last = group.end - 1;
+            let PathOrTempPath::Path(output) = &output_paths[last] else {
+                checkpoints.push(None);
+                complete.push(false);
+                resumed.push(false);
+                continue;
+            };
+            let chunkwise = group.clone().all(|i| {
+                filters[i].is_elementwise()
+                    && self.additional_input_paths[i].is_empty()
+                    && subsets[i].is_none()
+            });
+            // An in-place output always exists, so it is only complete if its checkpoint records every chunk
+            let output_complete =
+                options.resume && !in_place[last] && load_array(output, remote).is_ok();
+            if !chunkwise {
+                if options.resume && in_place[last] {
+                    return Err(FilterError::InvalidParameters(format!(
+                        "the in place filter {} is not element-wise, so it cannot be resumed",
+                        filter_commands[last].name()
+                    )));
+                }
+                checkpoints.push(None);
+                complete.push(output_complete);
+                resumed.push(false);
+                continue;
+            }
+            if options.worker.is_some() {
+                // The output is shared by the workers, and each leased range is checkpointed by the coordinator
+                checkpoints.push(None);
+                complete.push(false);
+                resumed.push(true);
+                continue;
+            }
+            if let Some(task) = options.task {
+                // The output is shared by the tasks, so it is not erased
+                let manifest_path =
+                    Checkpoint::task_manifest_path(&output.to_string_lossy(), &task);
+                let checkpoint = if options.resume {
+                    Checkpoint::resume(&manifest_path)?
+                } else {
+                    None
+                };
+                checkpoints.push(Some(match checkpoint {
+                    Some(checkpoint) => checkpoint,
+                    None => Checkpoint::create(&manifest_path)?,
+                }));
+                complete.push(false);
+                resumed.push(true);
+                continue;
+            }
+            let manifest_path = if in_place[last] {
+                // Each in-place filter of an array has its own checkpoint
+                let output = output.to_string_lossy();
+                Checkpoint::manifest_path(&format!(
+                    "{}.{}",
+                    output.trim_end_matches('/'),
+                    group.start
+                ))
+            } else {
+                Checkpoint::manifest_path(&output.to_string_lossy())
+            };
+            let checkpoint = if options.resume {
+                Checkpoint::resume(&manifest_path)?
+            } else {
+                None
+            };
+            if let Some(checkpoint) = checkpoint {
+                checkpoints.push(Some(checkpoint));
+                complete.push(false);
+                resumed.push(true);
+            } else {
+                checkpoints.push(if output_complete {
+                    None
+                } else {
+                    Some(Checkpoint::create(&manifest_path)?)
+                });
+                complete.push(output_complete);
+                resumed.push(false);
+            }
+        }
+
+        // Collect filters/input/outputs and check compatibility
+        let mut filter_input_output: Vec<_> = itertools::izip!(
+            filter_commands,
+            filters,
+            input_paths,
+            &self.additional_input_paths,
+            output_paths,
+            &self.exists,
+            in_place
+        )
+        .enumerate()
+        .map(
+            |(i, (filter_command, filter, input, additional_inputs, output, exists, in_place))| {
+                let group = group_of[i];
+                let last_in_group = i + 1 == groups[group].end;
+                let additional_arrays_input = additional_inputs
+                    .iter()
+                    .map(|additional_input| load_array(additional_input.path(), remote))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let (array_input, array_output, subset_arrays) = if *in_place {
+                    let (array_input, array_output) = get_array_input_output_in_place(
+                        filter,
+                        input.path(),
+                        filter_command.common_args().reencode(),
+                        remote,
+                    )?;
+                    (array_input, array_output, None)
+                } else if last_in_group && complete[group] {
+                    (
+                        load_array(input.path(), remote)?,
+                        load_output_array(output.path(), remote)?,
+                        None,
+                    )
+                } else if last_in_group && resumed[group] {
+                    let array_input = load_array(input.path(), remote)?;
+                    let array_output = resume_array(
+                        output.path(),
+                        &filter.output_array_builder(
+                            &array_input,
+                            filter_command.common_args().reencode(),
+                        ),
+                        remote,
+                    )?;
+                    (array_input, array_output, None)
+                } else if let Some(subset) = &subsets[i] {
+                    let array_input = load_array(input.path(), remote)?;
+                    let (subset_arrays, array_output) = SubsetArrays::new(
+                        filter,
+                        subset,
+                        &array_input,
+                        if filter_command.additional_inputs_match_input_shape() {
+                            &additional_arrays_input
+                        } else {
+                            &[]
+                        },
+                        output.path(),
+                        filter_command.common_args().reencode(),
+                        self.tmp_dir.path(),
+                        remote,
+                    )?;
+                    (array_input, array_output, Some(subset_arrays))
+                } else {
+                    let (array_input, array_output) = get_array_input_output(
+                        filter,
+                        input.path(),
+                        output.path(),
+                        filter_command.common_args().reencode(),
+                        remote,
+                    )?;
+                    (array_input, array_output, None)
+                };
+                for (additional_input, array) in
+                    std::iter::zip(additional_inputs, &additional_arrays_input)
+                {
+                    if filter_command.additional_inputs_match_input_shape()
+                        && array.shape() != array_input.shape()
+                    {
+                        return Err(FilterError::InvalidParameters(format!(
+                            "the shape of {:?} {:?} does not match the input shape {:?}",
+                            additional_input.path(),
+                            array.shape(),
+                            array_input.shape()
+                        )));
+                    }
+                }
+                observer.opened(
+                    i,
+                    &format!(
+                        "{}\n\targs:   {}\n\tencode: {}\n\tinput:  {} {:?} {:?}{}{}\n\toutput: {} {:?} {:?}{}",
+                        filter_command.name(),
+                        filter_command.args_str(),
+                        filter_command.reencode_str(),
+                        array_input.data_type(),
+                        array_input.shape(),
+                        input.path(),
+                        std::iter::zip(additional_inputs, &additional_arrays_input)
+                            .map(|(additional_input, array)| format!(
+                                "\n\tinput:  {} {:?} {:?}",
+                                array.data_type(),
+                                array.shape(),
+                                additional_input.path()
+                            ))
+                            .collect::<String>(),
+                        subsets[i]
+                            .as_ref()
+                            .map(|subset| format!(
+                                "\n\tsubset: {:?} {:?}{}",
+                                subset.offset,
+                                subset.shape,
+                                if subset.fill { " (fill)" } else { "" }
+                            ))
+                            .unwrap_or_default(),
+                        array_output.data_type(),
+                        array_output.shape(),
+                        output.path(),
+                        if complete[group] {
+                            " (complete)".to_string()
+                        } else if let Some(task) = options.task {
+                            format!(" (task {} of {})", task.index(), task.count())
+                        } else if options.worker.is_some() {
+                            " (worker)".to_string()
+                        } else if resumed[group] {
+                            " (resume)".to_string()
+                        } else if *in_place {
+                            " (in place)".to_string()
+                        } else if *exists {
+                            " (overwrite)".to_string()
+                        } else {
+                            String::new()
+                        },
+                    ),
+                );
+                if !in_place && !complete[group] {
+                    array_output.store_metadata()?; // erased before filter run
+                }
+
+                let arrays_input = std::iter::once(&array_input)
+                    .chain(&additional_arrays_input)
+                    .collect_vec();
+                filter.is_compatible_arrays(&arrays_input, &array_output)?;
+                Ok::<_, FilterError>((
+                    filter_command.name(),
+                    filter,
+                    array_input,
+                    additional_arrays_input,
+                    array_output,
+                    output.path(),
+                    *in_place,
+                    subset_arrays,
+                ))
+            },
+        )
+        .try_collect()?;
+
+        // Erase output metadata to imply indicating that filter has not run
+        filter_input_output
+            .iter()
+            .enumerate()
+            .filter(|(i, (_, _, _, _, _, _, in_place, _))| {
+                !in_place && !complete[group_of[*i]] && !options.is_shared()
+            })
+            .try_for_each(|(_, (_, _, _, _, array_output, _, _, _))| {
+                array_output.erase_metadata()
+            })?;
+
+        // Run the filters
+        let runtime = if options.run_async {
+            Some(tokio_runtime()?)
+        } else {
+            None
+        };
+        let mut reports = vec![];
+        let mut checkpoints_in_place = vec![];
+        for (group, checkpoint, complete) in itertools::izip!(groups, checkpoints, complete) {
+            let name = filter_commands[group.clone()]
+                .iter()
+                .map(|filter| filter.name())
+                .join("+");
+            if complete {
+                observer.skipped(group.clone(), &name, output_paths[group.end - 1].path());
+                continue;
+            }
+            observer.started(group.clone());
+            let progress_stage = {
+                let (_, _, _, _, array_output, _, _, _) = &filter_input_output[group.end - 1];
+                let chunk_output =
+                    array_output
+                        .chunk_array_representation(&vec![0; array_output.dimensionality()])?;
+                ProgressStage::new(
+                    name,
+                    (chunk_output.num_elements_usize() * element_size(&chunk_output)) as u64,
+                )
+            };
+            let progress_callback = |stats: ProgressStats| {
+                progress_stage.update(&stats);
+                observer.progress(group.clone(), &progress_stage, stats);
+            };
+            let progress_callback = ProgressCallback::new(&progress_callback);
+
+            let filter_input_output = &mut filter_input_output[group.clone()];
+            let checkpoint = match (checkpoint, options.task) {
+                (Some(checkpoint), Some(task)) => {
+                    let (_, _, _, _, array_output, _, _, _) = filter_input_output.last().unwrap();
+                    Some(checkpoint.with_task(task, array_output.chunk_grid_shape().unwrap()))
+                }
+                (checkpoint, _) => checkpoint,
+            };
+            let input_path = input_paths[group.start].path();
+            // Element-wise filters are run as a fused filter, which skips chunks of the fill value
+            let elementwise = filter_input_output.iter().all(
+                |(_, filter, _, additional_arrays_input, _, _, _, subset_arrays)| {
+                    filter.is_elementwise()
+                        && subset_arrays.is_none()
+                        && additional_arrays_input.is_empty()
+                },
+            );
+            let run_async = runtime.is_some()
+                && elementwise
+                && filter_input_output
+                    .iter()
+                    .all(|(_, _, _, _, _, output_path, _, _)| {
+                        !is_zip_path(&output_path.to_string_lossy())
+                    })
+                && !is_zip_path(&input_path.to_string_lossy());
+            let output_path = if let (
+                [(
+                    _name,
+                    filter,
+                    array_input,
+                    additional_arrays_input,
+                    array_output,
+                    output_path,
+                    _in_place,
+                    subset_arrays,
+                )],
+                false,
+            ) = (&mut *filter_input_output, elementwise)
+            {
+                // Run the filter
+                let arrays_input = std::iter::once(&*array_input)
+                    .chain(additional_arrays_input.iter())
+                    .collect_vec();
+                let chunk_limit = *filter_commands[group.start].common_args().chunk_limit();
+                if let Some(subset_arrays) = subset_arrays {
+                    subset_arrays.apply(
+                        *filter,
+                        array_input,
+                        additional_arrays_input,
+                        array_output,
+                        chunk_limit,
+                        &progress_callback,
+                    )?;
+                } else {
+                    filter.apply_inputs(&arrays_input, array_output, &progress_callback)?;
+                }
+
+                // Write metadata to indicate that filter is finished
+                array_output.store_metadata()?;
+                *output_path
+            } else {
+                // Run the fused filters, only the output of the last filter is written
+                let stages = || {
+                    filter_input_output
+                        .iter()
+                        .map(
+                            |(_, filter, array_input, _, array_output, _, _, _)| FusedStage {
+                                filter: *filter,
+                                input: array_input,
+                                output: array_output,
+                            },
+                        )
+                        .collect_vec()
+                };
+                let chunk_limit = *filter_commands[group.end - 1].common_args().chunk_limit();
+                let apply = |checkpoint: Option<&Checkpoint>| {
+                    let fused_filter = FusedFilter::new(stages(), chunk_limit)?
+                        .with_checkpoint(checkpoint)
+                        .with_max_block_bytes(options.max_block_bytes)?
+                        .with_adaptive_concurrency(options.adaptive);
+                    if run_async {
+                        let (_, _, array_input, _, _, _, _, _) =
+                            filter_input_output.first().unwrap();
+                        let (_, _, _, _, array_output, output_path, _, _) =
+                            filter_input_output.last().unwrap();
+                        fused_filter.apply_async(
+                            &async_array(input_path, array_input, remote)?,
+                            &async_array(output_path, array_output, remote)?,
+                            options.concurrent_chunks,
+                            runtime.as_ref().unwrap(),
+                            &progress_callback,
+                        )
+                    } else if let Some(prefetch) = options.prefetch {
+                        fused_filter.apply_prefetch(prefetch, &progress_callback)
+                    } else {
+                        fused_filter.apply(&progress_callback)
+                    }
+                };
+                if let Some(address) = &options.worker {
+                    // Process the ranges of chunks leased from the coordinator, which records them as complete
+                    let (_, _, _, _, array_output, _, _, _) = filter_input_output.last().unwrap();
+                    let chunk_grid_shape = array_output.chunk_grid_shape().unwrap();
+                    let worker_dir = TempDir::new_in(self.tmp_dir.path())?;
+                    let manifest_path = worker_dir.path().join("worker.checkpoint");
+                    let mut worker = QueueWorker::connect(address.as_str())?;
+                    while let Some(range) = worker.lease()? {
+                        let checkpoint = Checkpoint::create(&manifest_path)?
+                            .with_chunks(range.clone(), chunk_grid_shape.clone());
+                        apply(Some(&checkpoint))?;
+                        worker.complete(range)?;
+                    }
+                } else {
+                    apply(checkpoint.as_ref())?;
+                }
+
+                let (_, _, _, _, array_output, output_path, _, _) =
+                    filter_input_output.last().unwrap();
+                array_output.store_metadata()?;
+                *output_path
+            };
+            // The checkpoint of a task is kept until every task has finished
+            // The checkpoint of an in-place filter is kept until the run has finished, so it is not run again on its output if the run is resumed
+            if let (Some(checkpoint), false) = (checkpoint, options.is_shared()) {
+                if in_place[group.end - 1] {
+                    checkpoints_in_place.push(checkpoint);
+                } else {
+                    checkpoint.finish()?;
+                }
+            }
+
+            let report = progress_stage.report();
+            observer.finished(group.clone(), &report, output_path);
+            reports.push(report);
+        }
+        for checkpoint in checkpoints_in_place {
+            checkpoint.finish()?;
+        }
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use zarrs::array::{Element, ElementOwned};
+
+    use super::*;
+
+    /// Create the array built by `builder` at `path` and store `elements` in it.
+    pub(crate) fn create_test_array<T: Element>(
+        path: &Path,
+        builder: &ArrayBuilder,
+        elements: &[T],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let array = create_array(path, builder, &RemoteStorageArgs::default())?;
+        array.store_metadata()?;
+        let subset = ArraySubset::new_with_shape(array.shape().to_vec());
+        array.store_array_subset_elements(&subset, elements)?;
+        Ok(())
+    }
+
+    /// Run `filter` with the YAML `args` from `input` to `output`, returning the output array.
+    ///
+    /// Each line of `args` is a field of the filter command.
+    pub(crate) fn run_test_filter(
+        filter: &str,
+        input: &Path,
+        output: &Path,
+        args: &str,
+    ) -> Result<Array<FilterStorage>, Box<dyn std::error::Error>> {
+        let args = args
+            .lines()
+            .map(|line| format!("\n  {line}"))
+            .collect::<String>();
+        let filter_commands: Vec<FilterCommand> = serde_yaml::from_str(&format!(
+            r#"
+- filter: {filter}
+  input: {input:?}
+  output: {output:?}{args}
+"#
+        ))?;
+        Pipeline::new(filter_commands, PipelineOptions::default())?.run(&())?;
+        Ok(load_array(output, &RemoteStorageArgs::default())?)
+    }
+
+    /// Retrieve the elements of `array`.
+    pub(crate) fn retrieve_test_elements<T: ElementOwned>(
+        array: &Array<FilterStorage>,
+    ) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+        let subset = ArraySubset::new_with_shape(array.shape().to_vec());
+        Ok(array.retrieve_array_subset_elements::<T>(&subset)?)
+    }
+
+    #[derive(Default)]
+    struct Stages(Mutex<Vec<String>>);
+
+    impl PipelineObserver for Stages {
+        fn finished(&self, _filters: Range<usize>, report: &StageReport, _output: &Path) {
+            self.0.lock().unwrap().push(report.stage.clone());
+        }
+    }
+
+    #[test]
+    fn pipeline() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &(0..16).collect::<Vec<u8>>(),
+        )?;
+
+        let filter_commands: Vec<FilterCommand> = serde_yaml::from_str(&format!(
+            r#"
+- filter: clamp
+  input: {input:?}
+  min: 2
+  max: 10
+- filter: cast
+  output: {output:?}
+  data_type: uint16
+"#
+        ))?;
+        let options = PipelineOptions {
+            fuse: true,
+            ..Default::default()
+        };
+        let pipeline = Pipeline::new(filter_commands, options)?;
+        assert_eq!(pipeline.stages(), vec![0..2]);
+
+        let plans = pipeline.dry_run()?;
+        assert_eq!(plans.len(), 2);
+        assert!(plans[0].output_temporary);
+        assert_eq!(plans[1].output_data_type, DataType::UInt16);
+        assert_eq!(plans[1].output_size, 32);
+        assert!(!output.exists());
+
+        let stages = Stages::default();
+        let reports = pipeline.run(&stages)?;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(*stages.0.lock().unwrap(), vec!["clamp+cast".to_string()]);
+        let elements = load_array(&output, &RemoteStorageArgs::default())?
+            .retrieve_array_subset_elements::<u16>(&ArraySubset::new_with_shape(vec![4, 4]))?;
+        assert_eq!(
+            elements,
+            (0..16u16).map(|e| e.clamp(2, 10)).collect::<Vec<_>>()
+        );
+
+        assert!(Pipeline::new(vec![], PipelineOptions::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_tasks() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &(0..16).collect::<Vec<u8>>(),
+        )?;
+
+        let filter_commands: Vec<FilterCommand> = serde_yaml::from_str(&format!(
+            r#"
+- filter: clamp
+  input: {input:?}
+  output: {output:?}
+  min: 2
+  max: 10
+"#
+        ))?;
+        let pipeline = |task: Option<Task>| {
+            Pipeline::new(
+                filter_commands.clone(),
+                PipelineOptions {
+                    task,
+                    ..Default::default()
+                },
+            )
+        };
+        pipeline(Some(Task::new(1, 2)?))?.run(&Stages::default())?;
+        assert!(pipeline(None)?.finalize_tasks(2).is_err());
+        pipeline(Some(Task::new(0, 2)?))?.run(&Stages::default())?;
+        pipeline(None)?.finalize_tasks(2)?;
+        assert!(
+            !Checkpoint::task_manifest_path(&output.to_string_lossy(), &Task::new(0, 2)?).exists()
+        );
+
+        let elements = load_array(&output, &RemoteStorageArgs::default())?
+            .retrieve_array_subset_elements::<u8>(&ArraySubset::new_with_shape(vec![4, 4]))?;
+        assert_eq!(
+            elements,
+            (0..16u8).map(|e| e.clamp(2, 10)).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_output_exists() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        let builder = ArrayBuilder::new(
+            vec![4, 4],
+            DataType::UInt8,
+            vec![2, 2].try_into().unwrap(),
+            0u8.into(),
+        );
+        create_array(&input, &builder, &RemoteStorageArgs::default())?.store_metadata()?;
+        let pipeline = |output: &str| -> Result<Pipeline, Box<dyn std::error::Error>> {
+            let filter_commands: Vec<FilterCommand> = serde_yaml::from_str(&format!(
+                r#"
+- filter: clamp
+  input: {input:?}
+  output: {output:?}
+  min: 2
+  max: 10
+"#
+            ))?;
+            Ok(Pipeline::new(
+                filter_commands,
+                PipelineOptions {
+                    exists: OutputExists::Exit,
+                    ..Default::default()
+                },
+            )?)
+        };
+        let output_uri = format!("fs://{}", output.to_string_lossy());
+        pipeline(&output_uri)?;
+
+        // A remote output is checked through its store
+        create_array(&output, &builder, &RemoteStorageArgs::default())?.store_metadata()?;
+        assert!(pipeline(&output_uri).is_err());
+        assert!(pipeline(&output.to_string_lossy()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_deterministic() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &RemoteStorageArgs::default(),
+        )?
+        .store_metadata()?;
+        let filter_commands = |chunk_limit: &str| -> Result<Vec<FilterCommand>, serde_yaml::Error> {
+            serde_yaml::from_str(&format!(
+                r#"
+- filter: clamp
+  input: {input:?}
+  output: {output:?}
+  min: 2
+  max: 10{chunk_limit}
+"#
+            ))
+        };
+        let deterministic = PipelineOptions {
+            deterministic: true,
+            ..Default::default()
+        };
+
+        // The chunk limit is not overridden
+        assert!(Pipeline::new(
+            filter_commands("")?,
+            PipelineOptions {
+                chunk_limit: Some(4),
+                ..deterministic.clone()
+            }
+        )
+        .is_err());
+        assert!(Pipeline::new(
+            filter_commands("\n  chunk_limit: 4")?,
+            deterministic.clone()
+        )
+        .is_err());
+
+        let pipeline = Pipeline::new(filter_commands("")?, deterministic)?;
+        assert_eq!(
+            *pipeline.filter_commands[0].common_args().chunk_limit(),
+            Some(1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_resume_in_place() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &(0..16).collect::<Vec<u8>>(),
+        )?;
+
+        let filter_commands: Vec<FilterCommand> = serde_yaml::from_str(&format!(
+            r#"
+- filter: rescale
+  input: {input:?}
+  output: {input:?}
+  multiply: 1
+  add: 1
+"#
+        ))?;
+        let pipeline = || {
+            Pipeline::new(
+                filter_commands.clone(),
+                PipelineOptions {
+                    in_place: true,
+                    resume: true,
+                    ..Default::default()
+                },
+            )
+        };
+        let elements = || -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            Ok(load_array(&input, &RemoteStorageArgs::default())?
+                .retrieve_array_subset_elements::<u8>(&ArraySubset::new_with_shape(vec![4, 4]))?)
+        };
+
+        // The checkpoint of an interrupted run records every chunk, so the filter is not applied again
+        let manifest_path = Checkpoint::manifest_path(&format!("{}.0", input.to_string_lossy()));
+        let checkpoint = Checkpoint::create(&manifest_path)?;
+        for chunk_indices in ArraySubset::new_with_shape(vec![2, 2])
+            .indices()
+            .into_iter()
+        {
+            checkpoint.complete(&chunk_indices)?;
+        }
+        drop(checkpoint);
+        pipeline()?.run(&Stages::default())?;
+        assert_eq!(elements()?, (0..16).collect::<Vec<u8>>());
+        assert!(!manifest_path.exists());
+
+        // Without a checkpoint, the existing output of an in-place filter is not complete
+        pipeline()?.run(&Stages::default())?;
+        assert_eq!(elements()?, (1..17).collect::<Vec<u8>>());
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_mmap() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.zarr");
+        let output = dir.path().join("output.zarr");
+        create_test_array(
+            &input,
+            &ArrayBuilder::new(
+                vec![4, 4],
+                DataType::UInt8,
+                vec![2, 2].try_into().unwrap(),
+                0u8.into(),
+            ),
+            &(0..16).collect::<Vec<u8>>(),
+        )?;
+
+        let mut remote = RemoteStorageArgs::default();
+        remote.mmap = true;
+        let pipeline = |output: &Path| -> Result<Pipeline, Box<dyn std::error::Error>> {
+            let filter_commands: Vec<FilterCommand> = serde_yaml::from_str(&format!(
+                r#"
+- filter: clamp
+  input: {input:?}
+  output: {output:?}
+  min: 2
+  max: 10
+"#
+            ))?;
+            Ok(Pipeline::new(
+                filter_commands,
+                PipelineOptions {
+                    in_place: true,
+                    remote: remote.clone(),
+                    ..Default::default()
+                },
+            )?)
+        };
+
+        // The input of an in-place filter would be rewritten while it is mapped
+        assert!(pipeline(&input).is_err());
+
+        pipeline(&output)?.run(&Stages::default())?;
+        let elements = load_array(&output, &RemoteStorageArgs::default())?
+            .retrieve_array_subset_elements::<u8>(&ArraySubset::new_with_shape(vec![4, 4]))?;
+        assert_eq!(
+            elements,
+            (0..16u8).map(|e| e.clamp(2, 10)).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Autovectorised per-element kernels.
+//!
+//! Elements are split into blocks that are processed in parallel with rayon.
+//! Each block is processed by a branch-free loop over fixed-size lanes, which gives the compiler a constant trip count to unroll and vectorise.
+//! The kernels are plain scalar Rust without intrinsics, so whether they are vectorised is up to LLVM.
+//! The loops are compiled for the baseline instruction set and, on x86-64, in a `target_feature(enable = "avx2,fma")` function so LLVM may use 256-bit vectors.
+//! The AVX2 build is selected at runtime if the CPU supports it.
+//!
+//! `zarrs_benchmark_simd` compares the kernels with plain rayon iterators on the CPU it runs on (see `docs/benchmarks.md`).
+
+use std::mem::MaybeUninit;
+
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
+};
+
+/// The number of elements in a block processed by a rayon task.
+const BLOCK: usize = 1 << 16;
+
+/// The number of elements in a lane.
+///
+/// 32 8-bit elements fill a 256-bit AVX2 vector, and wider elements span several vectors of an unrolled loop.
+const LANES: usize = 32;
+
+/// Returns true if the vectorised kernels use AVX2.
+///
+/// The CPU features are detected at runtime.
+pub fn simd_avx2() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        std::arch::is_x86_feature_detected!("avx2") && std::arch::is_x86_feature_detected!("fma")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+/// Run `kernel` compiled for the widest instruction set supported by the CPU.
+#[inline(always)]
+fn dispatch<R>(kernel: impl FnOnce() -> R) -> R {
+    #[cfg(target_arch = "x86_64")]
+    {
+        #[target_feature(enable = "avx2,fma")]
+        unsafe fn avx2<R>(kernel: impl FnOnce() -> R) -> R {
+            kernel()
+        }
+        if simd_avx2() {
+            // SAFETY: AVX2 and FMA are supported by the CPU
+            return unsafe { avx2(kernel) };
+        }
+    }
+    kernel()
+}
+
+/// Map `input` to `output` lane by lane.
+#[inline(always)]
+fn map_block<TIn: Copy, TOut>(
+    input: &[TIn],
+    output: &mut [MaybeUninit<TOut>],
+    f: &impl Fn(TIn) -> TOut,
+) {
+    let mut input_lanes = input.chunks_exact(LANES);
+    let mut output_lanes = output.chunks_exact_mut(LANES);
+    for (input, output) in (&mut input_lanes).zip(&mut output_lanes) {
+        let input: &[TIn; LANES] = input.try_into().unwrap();
+        let output: &mut [MaybeUninit<TOut>; LANES] = output.try_into().unwrap();
+        for (output, input) in std::iter::zip(output, input) {
+            output.write(f(*input));
+        }
+    }
+    for (input, output) in std::iter::zip(input_lanes.remainder(), output_lanes.into_remainder()) {
+        output.write(f(*input));
+    }
+}
+
+/// Map each element of `input` with `f`.
+///
+/// `f` should be branch-free (e.g. use comparisons and selects rather than early returns) so that it is vectorised.
+pub fn simd_map<TIn, TOut, F>(input: &[TIn], f: F) -> Vec<TOut>
+where
+    TIn: Copy + Sync,
+    TOut: Send,
+    F: Fn(TIn) -> TOut + Sync,
+{
+    let mut output: Vec<TOut> = Vec::with_capacity(input.len());
+    output.spare_capacity_mut()[..input.len()]
+        .par_chunks_mut(BLOCK)
+        .zip(input.par_chunks(BLOCK))
+        .for_each(|(output, input)| dispatch(|| map_block(input, output, &f)));
+    // SAFETY: every element up to the length of the input has been initialised
+    unsafe { output.set_len(input.len()) };
+    output
+}
+
+/// Clamp each element of `elements` to `[min, max]` in place.
+///
+/// This matches [`num_traits::clamp`], NaN elements are unchanged.
+pub fn simd_clamp<T>(elements: &mut [T], min: T, max: T)
+where
+    T: Copy + Send + Sync + PartialOrd,
+{
+    #[inline(always)]
+    fn clamp<T: Copy + PartialOrd>(value: T, min: T, max: T) -> T {
+        let value = if value < min { min } else { value };
+        if value > max {
+            max
+        } else {
+            value
+        }
+    }
+
+    elements.par_chunks_mut(BLOCK).for_each(|block| {
+        dispatch(|| {
+            let mut lanes = block.chunks_exact_mut(LANES);
+            for lane in &mut lanes {
+                let lane: &mut [T; LANES] = lane.try_into().unwrap();
+                for value in lane.iter_mut() {
+                    *value = clamp(*value, min, max);
+                }
+            }
+            for value in lanes.into_remainder() {
+                *value = clamp(*value, min, max);
+            }
+        });
+    });
+}
+
+/// Returns the minimum and maximum of each element of `input` mapped with `f`.
+///
+/// Returns [`None`] if `input` is empty or a mapped element is NaN.
+pub fn simd_min_max<TIn, F>(input: &[TIn], f: F) -> Option<(f64, f64)>
+where
+    TIn: Copy + Sync,
+    F: Fn(TIn) -> f64 + Sync,
+{
+    #[inline(always)]
+    fn reduce(
+        (min, max, nan): (f64, f64, bool),
+        (value_min, value_max, value_nan): (f64, f64, bool),
+    ) -> (f64, f64, bool) {
+        (
+            if value_min < min { value_min } else { min },
+            if value_max > max { value_max } else { max },
+            nan | value_nan,
+        )
+    }
+
+    const IDENTITY: (f64, f64, bool) = (f64::INFINITY, f64::NEG_INFINITY, false);
+    if input.is_empty() {
+        return None;
+    }
+    let (min, max, nan) = input
+        .par_chunks(BLOCK)
+        .map(|block| {
+            dispatch(|| {
+                let map = |value: &TIn| {
+                    let value = f(*value);
+                    (value, value, value.is_nan())
+                };
+                let mut lanes_min_max = [IDENTITY; LANES];
+                let mut lanes = block.chunks_exact(LANES);
+                for lane in &mut lanes {
+                    let lane: &[TIn; LANES] = lane.try_into().unwrap();
+                    for (min_max, value) in std::iter::zip(&mut lanes_min_max, lane) {
+                        *min_max = reduce(*min_max, map(value));
+                    }
+                }
+                let remainder = lanes.remainder().iter().map(map).fold(IDENTITY, reduce);
+                lanes_min_max.into_iter().fold(remainder, reduce)
+            })
+        })
+        .reduce(|| IDENTITY, reduce);
+    (!nan).then_some((min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simd_kernels() {
+        // Enough elements for multiple blocks and a remainder
+        let elements: Vec<f32> = (0..BLOCK * 2 + LANES + 3)
+            .map(|i| (i % 1000) as f32 - 500.0)
+            .chain([f32::NAN])
+            .collect();
+
+        let mut clamped = elements.clone();
+        simd_clamp(&mut clamped, -10.0, 10.0);
+        for (clamped, element) in std::iter::zip(&clamped, &elements) {
+            let expected = num_traits::clamp(*element, -10.0, 10.0);
+            assert!(clamped == &expected || (clamped.is_nan() && expected.is_nan()));
+        }
+
+        let greater = simd_map(&elements, |element| u8::from(element > 0.0));
+        assert_eq!(
+            greater,
+            elements
+                .iter()
+                .map(|element| u8::from(*element > 0.0))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(simd_min_max(&elements, f64::from), None);
+        assert_eq!(
+            simd_min_max(&elements[..elements.len() - 1], f64::from),
+            Some((-500.0, 499.0))
+        );
+        assert_eq!(simd_min_max(&elements[..0], f64::from), None);
+        assert_eq!(
+            simd_min_max(&elements[..3], f64::from),
+            Some((-500.0, -498.0))
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use zarrs::array::{Array, DataType, FillValueMetadata};
+
+use super::{FilterError, FilterStorage};
+
+/// The array attribute holding the units of a datetime or timedelta array.
+pub const UNITS_ATTRIBUTE: &str = "units";
+
+/// The "not a time" value of a datetime or timedelta array.
+pub const NOT_A_TIME: i64 = i64::MIN;
+
+/// The unit of a datetime or timedelta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnit {
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Millisecond,
+    Microsecond,
+    Nanosecond,
+}
+
+impl TimeUnit {
+    /// Parse a unit from its CF name (e.g. `seconds`) or numpy code (e.g. `s`).
+    pub fn parse(unit: &str) -> Option<Self> {
+        match unit {
+            "W" | "week" | "weeks" => Some(Self::Week),
+            "D" | "d" | "day" | "days" => Some(Self::Day),
+            "h" | "hr" | "hour" | "hours" => Some(Self::Hour),
+            "m" | "min" | "minute" | "minutes" => Some(Self::Minute),
+            "s" | "sec" | "second" | "seconds" => Some(Self::Second),
+            "ms" | "millisecond" | "milliseconds" => Some(Self::Millisecond),
+            "us" | "microsecond" | "microseconds" => Some(Self::Microsecond),
+            "ns" | "nanosecond" | "nanoseconds" => Some(Self::Nanosecond),
+            _ => None,
+        }
+    }
+
+    /// The number of nanoseconds in the unit.
+    pub fn nanoseconds(&self) -> i128 {
+        match self {
+            Self::Week => 7 * 86_400_000_000_000,
+            Self::Day => 86_400_000_000_000,
+            Self::Hour => 3_600_000_000_000,
+            Self::Minute => 60_000_000_000,
+            Self::Second => 1_000_000_000,
+            Self::Millisecond => 1_000_000,
+            Self::Microsecond => 1_000,
+            Self::Nanosecond => 1,
+        }
+    }
+
+    /// Convert a number of nanoseconds to a whole number of this unit.
+    fn whole_units(&self, nanoseconds: i128) -> Option<i64> {
+        if nanoseconds % self.nanoseconds() == 0 {
+            i64::try_from(nanoseconds / self.nanoseconds()).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// The units of an `int64` array holding datetimes or timedeltas.
+///
+/// These are read from the [`UNITS_ATTRIBUTE`] of an array, which is either
+///  - `<unit> since <epoch>` (CF conventions) or `datetime64[<unit>]` (numpy, since 1970-01-01) for datetimes, or
+///  - `<unit>` or `timedelta64[<unit>]` for timedeltas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUnits {
+    /// A number of `unit` since `epoch`.
+    Datetime {
+        unit: TimeUnit,
+        epoch: NaiveDateTime,
+    },
+    /// A number of `unit`.
+    Timedelta { unit: TimeUnit },
+}
+
+impl TimeUnits {
+    /// Parse the units of a datetime or timedelta array.
+    pub fn parse(units: &str) -> Option<Self> {
+        let units = units.trim();
+        if let Some((unit, epoch)) = units.split_once(" since ") {
+            Some(Self::Datetime {
+                unit: TimeUnit::parse(unit.trim())?,
+                epoch: parse_datetime(epoch.trim())?,
+            })
+        } else if let Some(unit) = numpy_unit(units, "datetime64") {
+            Some(Self::Datetime {
+                unit: TimeUnit::parse(unit)?,
+                epoch: DateTime::UNIX_EPOCH.naive_utc(),
+            })
+        } else {
+            let unit = numpy_unit(units, "timedelta64").unwrap_or(units);
+            Some(Self::Timedelta {
+                unit: TimeUnit::parse(unit)?,
+            })
+        }
+    }
+
+    /// Returns the time units of `array` if it is an `int64` array with a valid [`UNITS_ATTRIBUTE`].
+    pub fn from_array(array: &Array<FilterStorage>) -> Option<Self> {
+        if array.data_type() != &DataType::Int64 {
+            return None;
+        }
+        array
+            .attributes()
+            .get(UNITS_ATTRIBUTE)
+            .and_then(|units| units.as_str())
+            .and_then(Self::parse)
+    }
+
+    /// Convert a datetime (e.g. `2020-01-01T12:00:00`) or timedelta (e.g. `90 s`) to a number of the unit.
+    ///
+    /// `NaT` is converted to [`NOT_A_TIME`].
+    ///
+    /// # Errors
+    /// Returns [`FilterError::InvalidParameters`] if `value` cannot be parsed or is not a whole number of the unit.
+    pub fn parse_value(&self, value: &str) -> Result<i64, FilterError> {
+        let value = value.trim();
+        if value == "NaT" {
+            return Ok(NOT_A_TIME);
+        }
+        let (unit, nanoseconds) = match self {
+            Self::Datetime { unit, epoch } => {
+                let datetime = parse_datetime(value).ok_or_else(|| {
+                    FilterError::InvalidParameters(format!("{value} is not a valid datetime"))
+                })?;
+                let duration = datetime.signed_duration_since(*epoch);
+                (
+                    unit,
+                    i128::from(duration.num_seconds()) * 1_000_000_000
+                        + i128::from(duration.subsec_nanos()),
+                )
+            }
+            Self::Timedelta { unit } => (
+                unit,
+                parse_timedelta_nanoseconds(value).ok_or_else(|| {
+                    FilterError::InvalidParameters(format!("{value} is not a valid timedelta"))
+                })?,
+            ),
+        };
+        unit.whole_units(nanoseconds).ok_or_else(|| {
+            FilterError::InvalidParameters(format!(
+                "{value} is not representable as a whole number of {unit:?}"
+            ))
+        })
+    }
+}
+
+/// Returns `<unit>` if `units` is `<prefix>[<unit>]`.
+fn numpy_unit<'a>(units: &'a str, prefix: &str) -> Option<&'a str> {
+    units
+        .strip_prefix(prefix)?
+        .strip_prefix('[')?
+        .strip_suffix(']')
+}
+
+/// Parse an ISO 8601 datetime or date, converting to UTC if it has an offset.
+fn parse_datetime(datetime: &str) -> Option<NaiveDateTime> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(datetime) {
+        return Some(datetime.naive_utc());
+    }
+    let datetime = datetime.strip_suffix('Z').unwrap_or(datetime);
+    [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(datetime, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(datetime, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+}
+
+/// Parse a timedelta of the form `<integer> <unit>` (e.g. `90 s`, `-2days`) to nanoseconds.
+fn parse_timedelta_nanoseconds(timedelta: &str) -> Option<i128> {
+    let split = timedelta
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || (*i == 0 && (*c == '-' || *c == '+'))))
+        .map_or(timedelta.len(), |(i, _)| i);
+    let (count, unit) = timedelta.split_at(split);
+    let count = count.parse::<i128>().ok()?;
+    let unit = TimeUnit::parse(unit.trim())?;
+    count.checked_mul(unit.nanoseconds())
+}
+
+/// Resolve a datetime or timedelta string `value` to a number of the units of `array`.
+///
+/// `value` is returned unchanged if it is not a string or `array` does not have [`TimeUnits`].
+///
+/// # Errors
+/// Returns [`FilterError::InvalidParameters`] if `value` is not a valid datetime or timedelta for the units of `array`.
+pub fn resolve_time_value(
+    array: &Array<FilterStorage>,
+    value: &FillValueMetadata,
+) -> Result<FillValueMetadata, FilterError> {
+    match (value, TimeUnits::from_array(array)) {
+        (FillValueMetadata::String(string), Some(units)) => {
+            Ok(FillValueMetadata::Int(units.parse_value(string)?))
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_units() {
+        let units = TimeUnits::parse("hours since 2000-01-01 00:00:00").unwrap();
+        assert_eq!(units.parse_value("2000-01-02T06:00:00").unwrap(), 30);
+        assert_eq!(units.parse_value("1999-12-31").unwrap(), -24);
+        assert_eq!(units.parse_value("NaT").unwrap(), NOT_A_TIME);
+        assert!(units.parse_value("2000-01-01T00:30:00").is_err());
+        assert!(units.parse_value("not a datetime").is_err());
+
+        let units = TimeUnits::parse("datetime64[s]").unwrap();
+        assert_eq!(units.parse_value("1970-01-01T00:01:00Z").unwrap(), 60);
+        assert_eq!(units.parse_value("1970-01-01T01:00:00+01:00").unwrap(), 0);
+
+        let units = TimeUnits::parse("timedelta64[ms]").unwrap();
+        assert_eq!(units.parse_value("90 s").unwrap(), 90_000);
+        assert_eq!(units.parse_value("-2days").unwrap(), -172_800_000);
+        assert_eq!(
+            TimeUnits::parse("minutes"),
+            Some(TimeUnits::Timedelta {
+                unit: TimeUnit::Minute
+            })
+        );
+        assert_eq!(TimeUnits::parse("metres"), None);
+    }
+}
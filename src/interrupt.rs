@@ -0,0 +1,143 @@
+//! Graceful handling of interrupt signals.
+//!
+//! After the first `SIGINT` or `SIGTERM`, no new chunks are read, so in-flight chunk writes finish and are recorded in their checkpoint before the run exits with [`EXIT_CODE_INTERRUPTED`].
+//! A second signal exits immediately.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use zarrs::{
+    byte_range::ByteRange,
+    storage::{
+        Bytes, ListableStorageTraits, MaybeBytes, ReadableStorageTraits,
+        ReadableWritableStorageTraits, StorageError, StoreKey, StoreKeyStartValue, StoreKeys,
+        StoreKeysPrefixes, StorePrefix, WritableStorageTraits,
+    },
+};
+
+/// The exit code of an interrupted run.
+pub const EXIT_CODE_INTERRUPTED: u8 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Handle `SIGINT` and `SIGTERM` by requesting an interrupt, see [`is_interrupted`].
+///
+/// # Errors
+/// Returns an error if the signal handler cannot be set.
+pub fn set_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_CODE_INTERRUPTED.into());
+        }
+        eprintln!("Interrupted, finishing in-flight chunks (interrupt again to exit immediately)");
+    })
+}
+
+/// Returns true if an interrupt has been requested.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// The error of an operation that was not started due to an interrupt.
+fn interrupted_error() -> StorageError {
+    StorageError::Other("interrupted".to_string())
+}
+
+/// Fails reads after an interrupt, so no new chunks are processed.
+///
+/// Wrap the inputs of a run, rather than the outputs, so in-flight chunk writes finish.
+pub struct InterruptStorageAdapter<TStorage: ?Sized> {
+    storage: Arc<TStorage>,
+}
+
+impl<TStorage: ?Sized> InterruptStorageAdapter<TStorage> {
+    /// Create a new interrupt storage adapter.
+    #[must_use]
+    pub fn new(storage: Arc<TStorage>) -> Self {
+        Self { storage }
+    }
+
+    fn check(&self) -> Result<(), StorageError> {
+        if is_interrupted() {
+            Err(interrupted_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<TStorage: ?Sized + ReadableStorageTraits> ReadableStorageTraits
+    for InterruptStorageAdapter<TStorage>
+{
+    fn get(&self, key: &StoreKey) -> Result<MaybeBytes, StorageError> {
+        self.check()?;
+        self.storage.get(key)
+    }
+
+    fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        self.check()?;
+        self.storage.get_partial_values_key(key, byte_ranges)
+    }
+
+    fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        self.storage.size_key(key)
+    }
+}
+
+impl<TStorage: ?Sized + ListableStorageTraits> ListableStorageTraits
+    for InterruptStorageAdapter<TStorage>
+{
+    fn list(&self) -> Result<StoreKeys, StorageError> {
+        self.storage.list()
+    }
+
+    fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        self.storage.list_prefix(prefix)
+    }
+
+    fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        self.storage.list_dir(prefix)
+    }
+
+    fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        self.storage.size_prefix(prefix)
+    }
+}
+
+impl<TStorage: ?Sized + ReadableWritableStorageTraits> WritableStorageTraits
+    for InterruptStorageAdapter<TStorage>
+{
+    fn set(&self, key: &StoreKey, value: Bytes) -> Result<(), StorageError> {
+        self.storage.set(key, value)
+    }
+
+    fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        self.storage.set_partial_values(key_start_values)
+    }
+
+    fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        self.storage.erase(key)
+    }
+
+    fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        self.storage.erase_values(keys)
+    }
+
+    fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        self.storage.erase_prefix(prefix)
+    }
+}
+
+impl<TStorage: ?Sized + ReadableWritableStorageTraits> ReadableWritableStorageTraits
+    for InterruptStorageAdapter<TStorage>
+{
+}
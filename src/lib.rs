@@ -1,8 +1,8 @@
 #![doc = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/README.md"))]
-#![doc(hidden)]
 
 use std::{sync::Mutex, time::SystemTime};
 
+use checkpoint::Checkpoint;
 use clap::Parser;
 use progress::{Progress, ProgressCallback};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -24,9 +24,15 @@ use zarrs::{
     storage::{ReadableStorageTraits, ReadableWritableStorageTraits},
 };
 
+pub mod checkpoint;
 pub mod filter;
 pub mod info;
+pub mod interrupt;
 pub mod progress;
+pub mod queue;
+pub mod storage;
+pub mod task;
+pub mod threads;
 
 #[derive(Parser)]
 #[allow(rustdoc::bare_urls)]
@@ -293,7 +299,11 @@ pub struct ZarrReencodingArgs {
     /// Examples:
     ///   '[ { "name": "transpose", "configuration": { "order": [0, 2, 1] } } ]'
     ///   '[ { "name": "bitround", "configuration": { "keepbits": 9 } } ]'
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_json_string"
+    )]
     #[arg(long, verbatim_doc_comment)]
     pub array_to_array_codecs: Option<String>,
 
@@ -305,7 +315,11 @@ pub struct ZarrReencodingArgs {
     ///   '{ "name": "bytes", "configuration": { "endian": "little" } }'
     ///   '{ "name": "pcodec", "configuration": { "level": 12 } }'
     ///   '{ "name": "zfp", "configuration": { "mode": "fixedprecision", "precision": 19 } }'
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_json_string"
+    )]
     #[arg(long, verbatim_doc_comment)]
     pub array_to_bytes_codec: Option<String>,
 
@@ -319,7 +333,11 @@ pub struct ZarrReencodingArgs {
     ///   '[ { "name": "crc32c" } ]'
     ///   '[ { "name": "gzip", "configuration": { "level": 9 } } ]'
     ///   '[ { "name": "zstd", "configuration": { "level": 22, "checksum": false } } ]'
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_json_string"
+    )]
     #[arg(long, verbatim_doc_comment)]
     pub bytes_to_bytes_codecs: Option<String>,
 
@@ -342,6 +360,20 @@ pub struct ZarrReencodingArgs {
     pub attributes_append: Option<String>,
 }
 
+/// Deserialize a JSON string, or any other JSON value as its string representation.
+///
+/// This permits codec metadata in a run configuration to be written as JSON rather than an escaped string.
+fn deserialize_json_string<'de, D: serde::Deserializer<'de>>(
+    d: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(
+        Option::<serde_json::Value>::deserialize(d)?.map(|value| match value {
+            serde_json::Value::String(string) => string,
+            value => value.to_string(),
+        }),
+    )
+}
+
 pub enum ZarrReEncodingChangeType {
     None,
     Metadata,
@@ -413,7 +445,12 @@ pub fn get_array_builder_reencode<TStorage: ?Sized>(
             bytes_to_bytes_codecs,
         )
     } else {
-        let chunk_shape = array.chunk_grid_shape().unwrap().to_vec();
+        let chunk_shape = array
+            .chunk_shape(&vec![0; array.dimensionality()])
+            .unwrap()
+            .iter()
+            .map(|i| i.get())
+            .collect::<Vec<_>>();
         let shard_shape = None;
         let array_to_array_codecs = array.codecs().array_to_array_codecs().to_vec();
         let array_to_bytes_codec = array.codecs().array_to_bytes_codec().clone();
@@ -427,12 +464,15 @@ pub fn get_array_builder_reencode<TStorage: ?Sized>(
         )
     };
 
+    // Zero sized dimensions of the chunk and shard shape overrides are resolved against the output shape
+    let output_shape = array_shape.as_deref().unwrap_or(array.shape());
+
     // Chunk shape override
     let chunk_shape = encoding_args
         .chunk_shape
         .as_ref()
         .map(|chunk_shape| {
-            std::iter::zip(chunk_shape.as_slice(), array.shape())
+            std::iter::zip(chunk_shape.as_slice(), output_shape)
                 .map(|(&c, &a)| if c == 0 { a } else { c })
                 .collect::<Vec<_>>()
         })
@@ -445,7 +485,7 @@ pub fn get_array_builder_reencode<TStorage: ?Sized>(
             .shard_shape
             .as_ref()
             .map_or(shard_shape, |shard_shape| {
-                let shard_shape = std::iter::zip(shard_shape, array.shape())
+                let shard_shape = std::iter::zip(shard_shape, output_shape)
                     .map(|(&s, &a)| if s == 0 { a } else { std::cmp::min(s, a) })
                     .collect::<Vec<_>>();
                 Some(shard_shape)
@@ -578,6 +618,9 @@ pub fn get_array_builder_reencode<TStorage: ?Sized>(
         )));
         array_builder.bytes_to_bytes_codecs(vec![]);
     } else {
+        if encoding_args.chunk_shape.is_some() {
+            array_builder.chunk_grid(chunk_shape.try_into().unwrap());
+        }
         array_builder.array_to_array_codecs(array_to_array_codecs);
         array_builder.array_to_bytes_codec(array_to_bytes_codec);
         array_builder.bytes_to_bytes_codecs(bytes_to_bytes_codecs);
@@ -586,6 +629,41 @@ pub fn get_array_builder_reencode<TStorage: ?Sized>(
     array_builder
 }
 
+/// Returns the number of concurrent chunks and the codec concurrent target of reencoding to `array_out`.
+///
+/// `concurrent_chunks` overrides the number of concurrent chunks, otherwise it is the minimum recommended by the global configuration.
+pub fn calculate_reencode_concurrency<TStorage: ?Sized>(
+    array_out: &Array<TStorage>,
+    concurrent_chunks: Option<usize>,
+) -> (usize, usize) {
+    let chunk_representation = array_out
+        .chunk_array_representation(&vec![0; array_out.chunk_grid().dimensionality()])
+        .unwrap();
+    let chunks = ArraySubset::new_with_shape(array_out.chunk_grid_shape().unwrap());
+
+    let concurrent_target = threads::num_threads();
+    zarrs::array::concurrency::calc_concurrency_outer_inner(
+        concurrent_target,
+        &if let Some(concurrent_chunks) = concurrent_chunks {
+            let concurrent_chunks = std::cmp::min(chunks.num_elements_usize(), concurrent_chunks);
+            RecommendedConcurrency::new(concurrent_chunks..concurrent_chunks)
+        } else {
+            let concurrent_chunks = std::cmp::min(
+                chunks.num_elements_usize(),
+                global_config().chunk_concurrent_minimum(),
+            );
+            RecommendedConcurrency::new_minimum(concurrent_chunks)
+        },
+        &array_out
+            .codecs()
+            .recommended_concurrency(&chunk_representation)
+            .unwrap(),
+    )
+}
+
+/// Reencode `array_in` to `array_out` chunk by chunk.
+///
+/// Chunks completed by a previous run in the `checkpoint` (or of other tasks of an array job) are skipped, and each written chunk is recorded in it.
 pub fn do_reencode<
     TStorageIn: ReadableStorageTraits + ?Sized + 'static,
     TStorageOut: ReadableWritableStorageTraits + ?Sized + 'static,
@@ -594,36 +672,15 @@ pub fn do_reencode<
     array_out: &Array<TStorageOut>,
     validate: bool,
     concurrent_chunks: Option<usize>,
+    checkpoint: Option<&Checkpoint>,
     progress_callback: &ProgressCallback,
 ) -> Result<(f32, f32, f32, usize), ArrayError> {
     let start = SystemTime::now();
     let bytes_decoded = Mutex::new(0);
 
-    let chunk_representation = array_out
-        .chunk_array_representation(&vec![0; array_out.chunk_grid().dimensionality()])
-        .unwrap();
     let chunks = ArraySubset::new_with_shape(array_out.chunk_grid_shape().unwrap());
-
-    let concurrent_target = std::thread::available_parallelism().unwrap().get();
     let (chunks_concurrent_limit, codec_concurrent_target) =
-        zarrs::array::concurrency::calc_concurrency_outer_inner(
-            concurrent_target,
-            &if let Some(concurrent_chunks) = concurrent_chunks {
-                let concurrent_chunks =
-                    std::cmp::min(chunks.num_elements_usize(), concurrent_chunks);
-                RecommendedConcurrency::new(concurrent_chunks..concurrent_chunks)
-            } else {
-                let concurrent_chunks = std::cmp::min(
-                    chunks.num_elements_usize(),
-                    global_config().chunk_concurrent_minimum(),
-                );
-                RecommendedConcurrency::new_minimum(concurrent_chunks)
-            },
-            &array_out
-                .codecs()
-                .recommended_concurrency(&chunk_representation)
-                .unwrap(),
-        );
+        calculate_reencode_concurrency(array_out, concurrent_chunks);
     let codec_options = CodecOptionsBuilder::new()
         .concurrent_target(codec_concurrent_target)
         .build();
@@ -636,6 +693,10 @@ pub fn do_reencode<
             indices,
             try_for_each,
             |chunk_indices: Vec<u64>| {
+                if checkpoint.is_some_and(|checkpoint| checkpoint.is_skipped(&chunk_indices)) {
+                    progress.next();
+                    return Ok(());
+                }
                 let chunk_subset = array_out.chunk_subset(&chunk_indices).unwrap();
                 let bytes = progress
                     .read(|| array_in.retrieve_array_subset_opt(&chunk_subset, &codec_options))?;
@@ -654,6 +715,11 @@ pub fn do_reencode<
                         array_out.store_chunk_opt(&chunk_indices, bytes, &codec_options)
                     })?;
                 }
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint
+                        .complete(&chunk_indices)
+                        .map_err(|err| ArrayError::StorageError(err.into()))?;
+                }
                 progress.next();
                 Ok::<_, ArrayError>(())
             }
@@ -685,6 +751,43 @@ fn convert_fill_value(
     fill_value_in: &FillValue,
     data_type_out: &DataType,
 ) -> FillValue {
+    // Complex fill values are converted through their real and imaginary parts
+    let complex_parts = |data_type: &DataType, fill_value: &FillValue| -> (f64, f64) {
+        let bytes = fill_value.as_ne_bytes();
+        match data_type {
+            DataType::Complex64 => (
+                f32::from_ne_bytes(bytes[..4].try_into().unwrap()).into(),
+                f32::from_ne_bytes(bytes[4..].try_into().unwrap()).into(),
+            ),
+            DataType::Complex128 => (
+                f64::from_ne_bytes(bytes[..8].try_into().unwrap()),
+                f64::from_ne_bytes(bytes[8..].try_into().unwrap()),
+            ),
+            _ => {
+                let fill_value = convert_fill_value(data_type, fill_value, &DataType::Float64);
+                (
+                    f64::from_ne_bytes(fill_value.as_ne_bytes().try_into().unwrap()),
+                    0.0,
+                )
+            }
+        }
+    };
+    match (data_type_in, data_type_out) {
+        (_, DataType::Complex64) => {
+            let (re, im) = complex_parts(data_type_in, fill_value_in);
+            return FillValue::from(num_complex::Complex32::new(re as f32, im as f32));
+        }
+        (_, DataType::Complex128) => {
+            let (re, im) = complex_parts(data_type_in, fill_value_in);
+            return FillValue::from(num_complex::Complex64::new(re, im));
+        }
+        (DataType::Complex64 | DataType::Complex128, _) => {
+            let (re, _im) = complex_parts(data_type_in, fill_value_in);
+            return convert_fill_value(&DataType::Float64, &FillValue::from(re), data_type_out);
+        }
+        _ => {}
+    }
+
     macro_rules! convert {
         ( $t_in:ty, $t_out:ty) => {{
             let input_fill_value =
@@ -1,8 +1,20 @@
+//! Progress reporting of filters and pipeline stages.
+//!
+//! A filter reports the [`ProgressStats`] of a [`Progress`] to a [`ProgressCallback`] as each chunk is read, processed, and written.
+//! A [`ProgressStage`] accumulates the progress of a stage into [`ProgressEvent`]s and a [`StageReport`].
+
 use std::{
+    io::Write,
+    path::Path,
     sync::{atomic::AtomicUsize, Mutex},
     time::{Duration, Instant},
 };
 
+use serde::Serialize;
+
+use crate::storage::{bytes_read, bytes_written};
+
+#[derive(Debug, Clone)]
 pub struct ProgressStats {
     pub step: usize,
     pub num_steps: usize,
@@ -47,6 +59,14 @@ impl<'a> Progress<'a> {
         result
     }
 
+    pub async fn read_async<F: std::future::Future<Output = T>, T>(&self, f: F) -> T {
+        let start = Instant::now();
+        let result = f.await;
+        let elapsed = start.elapsed();
+        *self.duration_read.lock().unwrap() += elapsed;
+        result
+    }
+
     pub fn process<F: FnOnce() -> T, T>(&self, f: F) -> T {
         let start = Instant::now();
         let result = f();
@@ -77,6 +97,14 @@ impl<'a> Progress<'a> {
         result
     }
 
+    pub async fn write_async<F: std::future::Future<Output = T>, T>(&self, f: F) -> T {
+        let start = Instant::now();
+        let result = f.await;
+        let elapsed = start.elapsed();
+        *self.duration_write.lock().unwrap() += elapsed;
+        result
+    }
+
     pub fn stats(&self) -> ProgressStats {
         let step = self.step.load(std::sync::atomic::Ordering::SeqCst);
         let read = *self.duration_read.lock().unwrap();
@@ -117,3 +145,226 @@ impl<'a> ProgressCallback<'a> {
         (self.callback)(stats);
     }
 }
+
+/// The format of progress reporting.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// Progress bars
+    #[default]
+    Bar,
+    /// Newline-delimited JSON [`ProgressEvent`]s
+    Json,
+}
+
+/// A progress event of a stage of a run, written as a line of JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    /// The name of the stage, e.g. a filter.
+    pub stage: String,
+    /// The number of chunks done.
+    pub chunks_done: usize,
+    /// The total number of chunks.
+    pub chunks_total: usize,
+    /// The bytes read from stores during the stage.
+    pub bytes_read: u64,
+    /// The decoded bytes of the chunks done.
+    pub bytes_processed: u64,
+    /// The bytes written to stores during the stage.
+    pub bytes_written: u64,
+    /// The elapsed time of the stage in seconds.
+    pub elapsed_s: f64,
+    /// The estimated time remaining in the stage in seconds, once a chunk is done.
+    pub eta_s: Option<f64>,
+}
+
+/// Writes [`ProgressEvent`]s as newline-delimited JSON to stderr or a file.
+pub struct ProgressEventWriter {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl ProgressEventWriter {
+    /// Create a progress event writer to `path`, or stderr if [`None`].
+    ///
+    /// # Errors
+    /// Returns an error if the file at `path` cannot be created.
+    pub fn new(path: Option<&Path>) -> std::io::Result<Self> {
+        let writer: Box<dyn Write + Send> = if let Some(path) = path {
+            Box::new(std::fs::File::create(path)?)
+        } else {
+            Box::new(std::io::stderr())
+        };
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Write a progress event.
+    ///
+    /// Progress reporting is best effort, so write errors are ignored.
+    pub fn write(&self, event: &ProgressEvent) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Ok(line) = serde_json::to_string(event) {
+            let _ = writeln!(writer, "{line}");
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// The timing and throughput report of a stage of a run.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    /// The name of the stage, e.g. a filter.
+    pub stage: String,
+    /// The wall time of the stage in seconds.
+    pub wall_s: f64,
+    /// The time reading chunks in seconds, summed over threads.
+    pub read_s: f64,
+    /// The time processing chunks in seconds, summed over threads.
+    pub process_s: f64,
+    /// The time writing chunks in seconds, summed over threads.
+    pub write_s: f64,
+    /// The number of chunks done.
+    pub chunks: usize,
+    /// The bytes read from stores.
+    pub bytes_read: u64,
+    /// The decoded bytes of the chunks done.
+    pub bytes_processed: u64,
+    /// The bytes written to stores.
+    pub bytes_written: u64,
+    /// The decoded bytes of the chunks done per second of wall time.
+    pub throughput_bytes_per_s: f64,
+}
+
+/// A stage of a run, which reports [`ProgressEvent`]s and a [`StageReport`] from its [`ProgressStats`].
+///
+/// A stage may have several passes, each with its own [`Progress`] starting from zero.
+/// Bytes read and written are those of every [`Storage`](crate::storage::Storage) since the stage started.
+pub struct ProgressStage {
+    stage: String,
+    bytes_per_chunk: u64,
+    start: Instant,
+    bytes_read_start: u64,
+    bytes_written_start: u64,
+    passes: Mutex<Vec<ProgressStats>>,
+}
+
+impl ProgressStage {
+    /// Start a stage named `stage` with `bytes_per_chunk` decoded bytes in each chunk.
+    #[must_use]
+    pub fn new(stage: String, bytes_per_chunk: u64) -> Self {
+        Self {
+            stage,
+            bytes_per_chunk,
+            start: Instant::now(),
+            bytes_read_start: bytes_read(),
+            bytes_written_start: bytes_written(),
+            passes: Mutex::new(vec![]),
+        }
+    }
+
+    /// Record `stats`, the progress of the current pass.
+    ///
+    /// A new pass starts when a [`Progress`] is created, with no steps done or time spent.
+    pub fn update(&self, stats: &ProgressStats) {
+        let mut passes = self.passes.lock().unwrap();
+        let new_pass = stats.step == 0
+            && stats.read.is_zero()
+            && stats.process.is_zero()
+            && stats.write.is_zero();
+        match passes.last_mut() {
+            Some(pass) if !new_pass => {
+                // Updates from concurrent chunks can arrive out of order
+                if stats.step >= pass.step {
+                    *pass = stats.clone();
+                }
+            }
+            _ => passes.push(stats.clone()),
+        }
+    }
+
+    /// Returns the progress event of `stats`.
+    #[must_use]
+    pub fn event(&self, stats: &ProgressStats) -> ProgressEvent {
+        let elapsed_s = self.start.elapsed().as_secs_f64();
+        let eta_s = (stats.step > 0).then(|| {
+            elapsed_s / stats.step as f64 * stats.num_steps.saturating_sub(stats.step) as f64
+        });
+        ProgressEvent {
+            stage: self.stage.clone(),
+            chunks_done: stats.step,
+            chunks_total: stats.num_steps,
+            bytes_read: bytes_read() - self.bytes_read_start,
+            bytes_processed: stats.step as u64 * self.bytes_per_chunk,
+            bytes_written: bytes_written() - self.bytes_written_start,
+            elapsed_s,
+            eta_s,
+        }
+    }
+
+    /// Returns the report of the stage so far, summed over its passes.
+    #[must_use]
+    pub fn report(&self) -> StageReport {
+        let wall_s = self.start.elapsed().as_secs_f64();
+        let passes = self.passes.lock().unwrap();
+        let sum_s = |duration: fn(&ProgressStats) -> Duration| {
+            passes.iter().map(duration).sum::<Duration>().as_secs_f64()
+        };
+        let chunks = passes.iter().map(|pass| pass.step).sum::<usize>();
+        let bytes_processed = chunks as u64 * self.bytes_per_chunk;
+        StageReport {
+            stage: self.stage.clone(),
+            wall_s,
+            read_s: sum_s(|pass| pass.read),
+            process_s: sum_s(|pass| pass.process),
+            write_s: sum_s(|pass| pass.write),
+            chunks,
+            bytes_read: bytes_read() - self.bytes_read_start,
+            bytes_processed,
+            bytes_written: bytes_written() - self.bytes_written_start,
+            throughput_bytes_per_s: if wall_s > 0.0 {
+                bytes_processed as f64 / wall_s
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_stage() {
+        let stage = ProgressStage::new("clamp".to_string(), 100);
+        let stats = |step: usize, read_ms: u64| ProgressStats {
+            step,
+            num_steps: 4,
+            read: Duration::from_millis(read_ms),
+            process: Duration::ZERO,
+            process_steps: vec![],
+            write: Duration::ZERO,
+        };
+        let event = stage.event(&stats(0, 0));
+        assert_eq!(event.eta_s, None);
+        let event = stage.event(&stats(3, 3));
+        assert_eq!(event.chunks_done, 3);
+        assert_eq!(event.bytes_processed, 300);
+        assert!(event.eta_s.unwrap() <= event.elapsed_s);
+        let line = serde_json::to_value(&event).unwrap();
+        assert_eq!(line["stage"], "clamp");
+        assert_eq!(line["chunks_total"], 4);
+
+        // Two passes, with an out of order update in the first
+        stage.update(&stats(0, 0));
+        stage.update(&stats(2, 2));
+        stage.update(&stats(1, 1));
+        stage.update(&stats(4, 4));
+        stage.update(&stats(0, 0));
+        stage.update(&stats(4, 6));
+        let report = stage.report();
+        assert_eq!(report.chunks, 8);
+        assert_eq!(report.bytes_processed, 800);
+        assert!((report.read_s - 0.010).abs() < 1e-9);
+    }
+}
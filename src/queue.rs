@@ -0,0 +1,294 @@
+//! A work queue of the chunks of an output, handed out over TCP by a coordinator to workers on other nodes.
+//!
+//! The coordinator hands out ranges of the linearised indices of the incomplete chunks, and records the chunks of each range in its checkpoint (the completion ledger) as a worker completes it.
+//! A range leased to a worker that disconnects before completing it is handed out again, and a coordinator that is interrupted resumes from its checkpoint.
+//!
+//! The protocol is line-based, a worker sends `next` and receives `range <start> <end>`, `wait` (every range is leased), `done`, or `stop` (the coordinator was interrupted), and sends `complete <start> <end>` and receives `ok`.
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    ops::Range,
+    sync::Mutex,
+    time::Duration,
+};
+
+use zarrs::array::unravel_index;
+
+use crate::{checkpoint::Checkpoint, interrupt::is_interrupted};
+
+/// The interval that a coordinator polls for new workers, and a worker polls for a range while every range is leased.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn protocol_error(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+/// The ranges of chunks that are pending or leased to workers.
+struct QueueState {
+    pending: VecDeque<Range<u64>>,
+    leased: usize,
+}
+
+/// Hands out the chunks of an output to [`QueueWorker`]s.
+pub struct QueueCoordinator {
+    ledger: Checkpoint,
+    chunk_grid_shape: Vec<u64>,
+    state: Mutex<QueueState>,
+}
+
+impl QueueCoordinator {
+    /// Create a coordinator of the chunks of an output with a chunk grid of `chunk_grid_shape`, handed out in ranges of up to `batch` chunks.
+    ///
+    /// Chunks completed in the `ledger` (e.g. by a previous run) are not handed out.
+    #[must_use]
+    pub fn new(ledger: Checkpoint, chunk_grid_shape: Vec<u64>, batch: u64) -> Self {
+        let batch = batch.max(1);
+        let num_chunks = chunk_grid_shape.iter().product::<u64>();
+        let mut pending = VecDeque::new();
+        let mut start = None;
+        for chunk in 0..=num_chunks {
+            let incomplete =
+                chunk < num_chunks && !ledger.is_complete(&unravel_index(chunk, &chunk_grid_shape));
+            match (start, incomplete) {
+                (None, true) => start = Some(chunk),
+                (Some(range_start), _) if !incomplete || chunk - range_start == batch => {
+                    pending.push_back(range_start..chunk);
+                    start = incomplete.then_some(chunk);
+                }
+                _ => {}
+            }
+        }
+        Self {
+            ledger,
+            chunk_grid_shape,
+            state: Mutex::new(QueueState { pending, leased: 0 }),
+        }
+    }
+
+    /// Returns the number of ranges of chunks that have not been completed.
+    pub fn num_incomplete(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.pending.len() + state.leased
+    }
+
+    /// Serve workers connecting to `listener` until every chunk has been completed, then return the ledger.
+    ///
+    /// After an interrupt, no new ranges are handed out and this returns once the connected workers disconnect.
+    ///
+    /// # Errors
+    /// Returns an error if the listener fails or the coordinator is interrupted.
+    pub fn serve(self, listener: &TcpListener) -> std::io::Result<Checkpoint> {
+        listener.set_nonblocking(true)?;
+        std::thread::scope(|scope| {
+            while self.num_incomplete() > 0 {
+                if is_interrupted() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Interrupted,
+                        "the coordinator was interrupted",
+                    ));
+                }
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(false)?;
+                        let coordinator = &self;
+                        scope.spawn(move || {
+                            if let Err(err) = coordinator.serve_worker(stream) {
+                                eprintln!("Worker disconnected: {err}");
+                            }
+                        });
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok(())
+        })?;
+        Ok(self.ledger)
+    }
+
+    /// Serve a worker, returning the ranges leased to it to the queue if it disconnects.
+    fn serve_worker(&self, stream: TcpStream) -> std::io::Result<()> {
+        let mut leases = vec![];
+        let result = self.serve_requests(&stream, &mut leases);
+        let mut state = self.state.lock().unwrap();
+        state.leased -= leases.len();
+        for range in leases {
+            state.pending.push_front(range);
+        }
+        result
+    }
+
+    fn serve_requests(
+        &self,
+        stream: &TcpStream,
+        leases: &mut Vec<Range<u64>>,
+    ) -> std::io::Result<()> {
+        let mut writer = stream;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let request = line.split_whitespace().collect::<Vec<_>>();
+            let response = match request.as_slice() {
+                // Workers complete their leased ranges, but no new ranges are handed out
+                ["next"] if is_interrupted() => "stop".to_string(),
+                ["next"] => {
+                    let mut state = self.state.lock().unwrap();
+                    if let Some(range) = state.pending.pop_front() {
+                        state.leased += 1;
+                        leases.push(range.clone());
+                        format!("range {} {}", range.start, range.end)
+                    } else if state.leased > 0 {
+                        "wait".to_string()
+                    } else {
+                        "done".to_string()
+                    }
+                }
+                ["complete", start, end] => {
+                    let range = parse_range(start, end)?;
+                    let Some(lease) = leases.iter().position(|lease| *lease == range) else {
+                        return Err(protocol_error(format!(
+                            "the range {range:?} is not leased to the worker"
+                        )));
+                    };
+                    for chunk in range {
+                        self.ledger
+                            .complete(&unravel_index(chunk, &self.chunk_grid_shape))?;
+                    }
+                    leases.swap_remove(lease);
+                    self.state.lock().unwrap().leased -= 1;
+                    "ok".to_string()
+                }
+                _ => return Err(protocol_error(format!("invalid request {line}"))),
+            };
+            writeln!(writer, "{response}")?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_range(start: &str, end: &str) -> std::io::Result<Range<u64>> {
+    let parse = |index: &str| {
+        index
+            .parse::<u64>()
+            .map_err(|err| protocol_error(err.to_string()))
+    };
+    Ok(parse(start)?..parse(end)?)
+}
+
+/// Requests ranges of chunks from a [`QueueCoordinator`].
+pub struct QueueWorker {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl QueueWorker {
+    /// Connect to the coordinator at `address` (e.g. `node01:7878`).
+    ///
+    /// # Errors
+    /// Returns an error if the coordinator cannot be connected to.
+    pub fn connect(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let writer = TcpStream::connect(address)?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    fn request(&mut self, request: &str) -> std::io::Result<String> {
+        writeln!(self.writer, "{request}")?;
+        let mut response = String::new();
+        if self.reader.read_line(&mut response)? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the coordinator disconnected",
+            ));
+        }
+        Ok(response.trim_end().to_string())
+    }
+
+    /// Lease the next range of linearised chunk indices to process, or return [`None`] if every chunk has been completed.
+    ///
+    /// Waits while the remaining ranges are leased to other workers, since they are handed out again if a worker disconnects.
+    ///
+    /// # Errors
+    /// Returns an error if the coordinator disconnects or is interrupted, or the response is invalid.
+    pub fn lease(&mut self) -> std::io::Result<Option<Range<u64>>> {
+        loop {
+            let response = self.request("next")?;
+            match response.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["range", start, end] => return parse_range(start, end).map(Some),
+                ["wait"] => std::thread::sleep(POLL_INTERVAL),
+                ["done"] => return Ok(None),
+                ["stop"] => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Interrupted,
+                        "the coordinator was interrupted",
+                    ))
+                }
+                _ => return Err(protocol_error(format!("invalid response {response}"))),
+            }
+        }
+    }
+
+    /// Report that the chunks of `range` have been written.
+    ///
+    /// # Errors
+    /// Returns an error if the coordinator disconnects or rejects the range.
+    pub fn complete(&mut self, range: Range<u64>) -> std::io::Result<()> {
+        let response = self.request(&format!("complete {} {}", range.start, range.end))?;
+        if response == "ok" {
+            Ok(())
+        } else {
+            Err(protocol_error(format!("invalid response {response}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let manifest = path.path().join("array.zarr.checkpoint");
+        let ledger = Checkpoint::create(&manifest)?;
+        ledger.complete(&[0, 2])?;
+        drop(ledger);
+
+        // Chunk 2 of the 3x3 chunk grid is complete
+        let ledger = Checkpoint::resume(&manifest)?.unwrap();
+        let coordinator = QueueCoordinator::new(ledger, vec![3, 3], 4);
+        assert_eq!(
+            coordinator.state.lock().unwrap().pending,
+            VecDeque::from([0..2, 3..7, 7..9])
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let workers = std::thread::spawn(move || {
+            // A worker that disconnects without completing its range
+            let mut worker = QueueWorker::connect(address)?;
+            assert_eq!(worker.lease()?, Some(0..2));
+            drop(worker);
+
+            let mut worker = QueueWorker::connect(address)?;
+            let mut chunks = vec![];
+            while let Some(range) = worker.lease()? {
+                chunks.extend(range.clone());
+                worker.complete(range)?;
+            }
+            chunks.sort_unstable();
+            Ok::<_, std::io::Error>(chunks)
+        });
+        let ledger = coordinator.serve(&listener)?;
+        assert_eq!(workers.join().unwrap()?, vec![0, 1, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Checkpoint::resume(&manifest)?.unwrap().num_completed(), 9);
+        ledger.finish()?;
+        assert!(!manifest.exists());
+        Ok(())
+    }
+}
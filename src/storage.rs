@@ -0,0 +1,1083 @@
+//! Storage for local paths and remote URIs.
+//!
+//! A zip archive (e.g. `array.zarr.zip`) at a local path or URI is supported as a read-only store.
+//! Outputs are written to a zip archive through a [`ZipOutput`].
+//!
+//! A `http://` or `https://` URL is supported as a read-only store.
+//!
+//! A profile URI (`profile://name/path`) references a named store profile with a base URI and backend options, see [`StoreProfiles`].
+//!
+//! A remote store can retry failed operations, be rate limited, and be cached locally with [`RemoteStorageArgs`].
+//! A local store can be read with direct I/O and/or io_uring, or memory maps, with [`RemoteStorageArgs`], see [`DirectReadStore`].
+//!
+//! Any other `<scheme>://` URI is opened with the [OpenDAL](https://opendal.apache.org) service of that scheme, see [`opendal_operator`].
+//! The `webdav`, `sftp`, and `hdfs` services are supported with the `opendal-webdav`, `opendal-sftp`, and `opendal-hdfs` features.
+//!
+//! An `s3://bucket/prefix` URI is supported with the `s3` feature.
+//! The region, endpoint, and credentials of an S3 store are read from the environment:
+//!  - `AWS_REGION` or `AWS_DEFAULT_REGION`,
+//!  - `AWS_ENDPOINT` (e.g. for S3 compatible object storage), and `AWS_ALLOW_HTTP` to allow a HTTP endpoint,
+//!  - `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and `AWS_SESSION_TOKEN`, or otherwise a web identity token or instance metadata.
+//!
+//! A `gs://bucket/prefix` URI is supported with the `gcs` feature.
+//! Credentials are read from `GOOGLE_SERVICE_ACCOUNT` or `GOOGLE_APPLICATION_CREDENTIALS`, or otherwise the application default credentials or instance metadata.
+//!
+//! An `az://container/prefix` URI is supported with the `azure` feature.
+//! The account is read from `AZURE_STORAGE_ACCOUNT_NAME`, and credentials from `AZURE_STORAGE_ACCOUNT_KEY`, a service principal (`AZURE_CLIENT_ID`, `AZURE_CLIENT_SECRET`, and `AZURE_TENANT_ID`), or otherwise a workload identity or managed identity.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use zarrs::{
+    byte_range::ByteRange,
+    storage::{
+        storage_adapter::{
+            async_to_sync::{AsyncToSyncBlockOn, AsyncToSyncStorageAdapter},
+            zip::ZipStorageAdapter,
+        },
+        store::{AsyncOpendalStore, FilesystemStore, MemoryStore},
+        AsyncReadableWritableListableStorage, Bytes, ListableStorageTraits, MaybeBytes,
+        ReadableStorage, ReadableStorageTraits, ReadableWritableStorageTraits, StorageError,
+        StoreKey, StoreKeyStartValue, StoreKeys, StoreKeysPrefixes, StorePrefix,
+        WritableStorageTraits,
+    },
+};
+
+use crate::threads::tokio_runtime;
+
+#[cfg(feature = "async")]
+use zarrs::storage::{
+    store::AsyncObjectStore, AsyncListableStorageTraits, AsyncReadableStorageTraits,
+    AsyncWritableStorageTraits,
+};
+
+mod cache;
+mod direct;
+mod profile;
+mod retry;
+pub use cache::CacheStorageAdapter;
+pub use direct::{DirectReadOptions, DirectReadStore};
+pub use profile::{is_profile_uri, StoreOptions, StoreProfile, StoreProfiles};
+pub use retry::{RateLimiter, RetryStorageAdapter};
+
+/// Returns true if `path` is a URI with a `<scheme>://` prefix rather than a local path.
+pub fn is_uri(path: &str) -> bool {
+    path.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+    })
+}
+
+/// Returns true if `path` is a zip archive (`.zip`), e.g. `array.zarr.zip`.
+pub fn is_zip_path(path: &str) -> bool {
+    path.ends_with(".zip")
+}
+
+/// Returns true if `path` is a HTTP(S) URL.
+pub fn is_http_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Returns true if `path` is an S3 URI (`s3://bucket/prefix`).
+pub fn is_s3_uri(path: &str) -> bool {
+    path.starts_with("s3://")
+}
+
+/// Returns true if `path` is a Google Cloud Storage URI (`gs://bucket/prefix`).
+pub fn is_gcs_uri(path: &str) -> bool {
+    path.starts_with("gs://")
+}
+
+/// Returns true if `path` is an Azure Blob Storage URI (`az://container/prefix`).
+pub fn is_azure_uri(path: &str) -> bool {
+    path.starts_with("az://")
+}
+
+/// Returns true if `path` is an S3, Google Cloud Storage, or Azure Blob Storage URI.
+pub fn is_object_store_uri(path: &str) -> bool {
+    is_s3_uri(path) || is_gcs_uri(path) || is_azure_uri(path)
+}
+
+fn split_bucket_uri<'a>(uri: &'a str, scheme: &str) -> Option<(&'a str, &'a str)> {
+    let path = uri.strip_prefix(scheme)?;
+    let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+    if bucket.is_empty() {
+        None
+    } else {
+        Some((bucket, prefix.trim_matches('/')))
+    }
+}
+
+/// Split an S3 URI (`s3://bucket/prefix`) into its bucket and prefix.
+///
+/// Returns [`None`] if `uri` is not an S3 URI or has no bucket.
+pub fn parse_s3_uri(uri: &str) -> Option<(&str, &str)> {
+    split_bucket_uri(uri, "s3://")
+}
+
+/// Split a Google Cloud Storage URI (`gs://bucket/prefix`) into its bucket and prefix.
+///
+/// Returns [`None`] if `uri` is not a Google Cloud Storage URI or has no bucket.
+pub fn parse_gcs_uri(uri: &str) -> Option<(&str, &str)> {
+    split_bucket_uri(uri, "gs://")
+}
+
+/// Split an Azure Blob Storage URI (`az://container/prefix`) into its container and prefix.
+///
+/// Returns [`None`] if `uri` is not an Azure Blob Storage URI or has no container.
+pub fn parse_azure_uri(uri: &str) -> Option<(&str, &str)> {
+    split_bucket_uri(uri, "az://")
+}
+
+/// An asynchronous S3 object store, rooted at a prefix of a bucket.
+#[cfg(feature = "s3")]
+pub type S3ObjectStore = object_store::prefix::PrefixStore<object_store::aws::AmazonS3>;
+
+/// An asynchronous Google Cloud Storage object store, rooted at a prefix of a bucket.
+#[cfg(feature = "gcs")]
+pub type GcsObjectStore = object_store::prefix::PrefixStore<object_store::gcp::GoogleCloudStorage>;
+
+/// An asynchronous Azure Blob Storage object store, rooted at a prefix of a container.
+#[cfg(feature = "azure")]
+pub type AzureObjectStore = object_store::prefix::PrefixStore<object_store::azure::MicrosoftAzure>;
+
+/// Create an asynchronous store for an S3 URI (`s3://bucket/prefix`).
+///
+/// # Errors
+/// Returns a [`StorageError`] if `uri` is not a valid S3 URI or the S3 configuration or `options` are invalid.
+#[cfg(feature = "s3")]
+pub fn s3_store(
+    uri: &str,
+    options: &StoreOptions,
+) -> Result<AsyncObjectStore<S3ObjectStore>, StorageError> {
+    let (bucket, prefix) =
+        parse_s3_uri(uri).ok_or_else(|| StorageError::Other(format!("invalid S3 URI {uri}")))?;
+    let mut builder = object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket);
+    for (key, value) in options {
+        let key: object_store::aws::AmazonS3ConfigKey = key
+            .parse()
+            .map_err(|err: object_store::Error| StorageError::Other(err.to_string()))?;
+        builder = builder.with_config(key, value);
+    }
+    let store = builder
+        .build()
+        .map_err(|err| StorageError::Other(err.to_string()))?;
+    Ok(AsyncObjectStore::new(
+        object_store::prefix::PrefixStore::new(store, prefix),
+    ))
+}
+
+/// Create an asynchronous store for a Google Cloud Storage URI (`gs://bucket/prefix`).
+///
+/// # Errors
+/// Returns a [`StorageError`] if `uri` is not a valid Google Cloud Storage URI or the configuration or `options` are invalid.
+#[cfg(feature = "gcs")]
+pub fn gcs_store(
+    uri: &str,
+    options: &StoreOptions,
+) -> Result<AsyncObjectStore<GcsObjectStore>, StorageError> {
+    let (bucket, prefix) = parse_gcs_uri(uri)
+        .ok_or_else(|| StorageError::Other(format!("invalid Google Cloud Storage URI {uri}")))?;
+    let mut builder =
+        object_store::gcp::GoogleCloudStorageBuilder::from_env().with_bucket_name(bucket);
+    for (key, value) in options {
+        let key: object_store::gcp::GoogleConfigKey = key
+            .parse()
+            .map_err(|err: object_store::Error| StorageError::Other(err.to_string()))?;
+        builder = builder.with_config(key, value);
+    }
+    let store = builder
+        .build()
+        .map_err(|err| StorageError::Other(err.to_string()))?;
+    Ok(AsyncObjectStore::new(
+        object_store::prefix::PrefixStore::new(store, prefix),
+    ))
+}
+
+/// Create an asynchronous store for an Azure Blob Storage URI (`az://container/prefix`).
+///
+/// # Errors
+/// Returns a [`StorageError`] if `uri` is not a valid Azure Blob Storage URI or the configuration or `options` are invalid.
+#[cfg(feature = "azure")]
+pub fn azure_store(
+    uri: &str,
+    options: &StoreOptions,
+) -> Result<AsyncObjectStore<AzureObjectStore>, StorageError> {
+    let (container, prefix) = parse_azure_uri(uri)
+        .ok_or_else(|| StorageError::Other(format!("invalid Azure Blob Storage URI {uri}")))?;
+    let mut builder =
+        object_store::azure::MicrosoftAzureBuilder::from_env().with_container_name(container);
+    for (key, value) in options {
+        let key: object_store::azure::AzureConfigKey = key
+            .parse()
+            .map_err(|err: object_store::Error| StorageError::Other(err.to_string()))?;
+        builder = builder.with_config(key, value);
+    }
+    let store = builder
+        .build()
+        .map_err(|err| StorageError::Other(err.to_string()))?;
+    Ok(AsyncObjectStore::new(
+        object_store::prefix::PrefixStore::new(store, prefix),
+    ))
+}
+
+/// Open an asynchronous store for an S3 (`s3://`), Google Cloud Storage (`gs://`), or Azure Blob Storage (`az://`) URI.
+///
+/// # Errors
+/// Returns a [`StorageError`] if `uri` is not a valid object store URI, the configuration is invalid, or the feature for its scheme is not enabled.
+#[cfg_attr(
+    not(any(feature = "s3", feature = "gcs", feature = "azure")),
+    allow(unused_variables)
+)]
+pub fn get_async_object_storage(
+    uri: &str,
+    options: &StoreOptions,
+) -> Result<AsyncReadableWritableListableStorage, StorageError> {
+    if is_s3_uri(uri) {
+        #[cfg(feature = "s3")]
+        return Ok(Arc::new(s3_store(uri, options)?));
+        #[cfg(not(feature = "s3"))]
+        return Err(feature_required(uri, "s3"));
+    }
+    if is_gcs_uri(uri) {
+        #[cfg(feature = "gcs")]
+        return Ok(Arc::new(gcs_store(uri, options)?));
+        #[cfg(not(feature = "gcs"))]
+        return Err(feature_required(uri, "gcs"));
+    }
+    if is_azure_uri(uri) {
+        #[cfg(feature = "azure")]
+        return Ok(Arc::new(azure_store(uri, options)?));
+        #[cfg(not(feature = "azure"))]
+        return Err(feature_required(uri, "azure"));
+    }
+    Err(StorageError::Other(format!(
+        "{uri} is not an object store URI"
+    )))
+}
+
+#[cfg(not(all(feature = "s3", feature = "gcs", feature = "azure")))]
+fn feature_required(uri: &str, feature: &str) -> StorageError {
+    StorageError::Other(format!("{uri} requires the {feature} feature"))
+}
+
+/// Create an [`opendal::Operator`] for a local path or a URI (`<scheme>://<authority>/<path>?<key>=<value>&...`).
+///
+/// The scheme selects the OpenDAL service and the path is the `root` of the service.
+/// The authority is the `endpoint` of the service, except
+///  - `webdav://host/path` has the endpoint `https://host`,
+///  - `sftp://user@host/path` has the endpoint `host` and the user `user`, and
+///  - `hdfs://host:port/path` has the name node `hdfs://host:port`.
+///
+/// A `http://` or `https://` URL is the endpoint of the `http` service.
+/// `options` and then query parameters are passed through as service configuration, e.g. `webdav://host/path?username=user&password=pass`.
+///
+/// # Errors
+/// Returns a [`StorageError`] if the service is unknown, not enabled, or its configuration is invalid.
+pub fn opendal_operator(
+    uri: &str,
+    options: &StoreOptions,
+) -> Result<opendal::Operator, StorageError> {
+    let to_storage_error = |err: opendal::Error| StorageError::Other(err.to_string());
+    if !is_uri(uri) {
+        let builder = opendal::services::Fs::default().root(uri);
+        return Ok(opendal::Operator::new(builder)
+            .map_err(to_storage_error)?
+            .finish());
+    }
+
+    let (uri, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let (scheme, path) = uri.split_once("://").unwrap();
+    let scheme: opendal::Scheme = scheme.parse().map_err(to_storage_error)?;
+    let mut config = HashMap::<String, String>::new();
+    if is_http_url(uri) {
+        config.insert("endpoint".to_string(), uri.to_string());
+    } else {
+        let (authority, root) = path.split_once('/').unwrap_or((path, ""));
+        config.insert("root".to_string(), format!("/{root}"));
+        match scheme {
+            opendal::Scheme::Webdav => {
+                config.insert("endpoint".to_string(), format!("https://{authority}"));
+            }
+            opendal::Scheme::Sftp => {
+                if let Some((user, host)) = authority.split_once('@') {
+                    config.insert("user".to_string(), user.to_string());
+                    config.insert("endpoint".to_string(), host.to_string());
+                } else {
+                    config.insert("endpoint".to_string(), authority.to_string());
+                }
+            }
+            opendal::Scheme::Hdfs => {
+                config.insert("name_node".to_string(), format!("hdfs://{authority}"));
+            }
+            _ => {
+                config.insert("endpoint".to_string(), authority.to_string());
+            }
+        }
+    }
+    config.extend(options.clone());
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        config.insert(key.to_string(), value.to_string());
+    }
+    opendal::Operator::via_iter(scheme, config).map_err(to_storage_error)
+}
+
+/// Open an asynchronous store for a local path or a URI.
+///
+/// A profile URI (`profile://name/path`) is resolved with the [`StoreProfiles`] from [`StoreProfiles::load`].
+/// Object store URIs (`s3://`, `gs://`, `az://`) are opened with [`get_async_object_storage`], and anything else with [`opendal_operator`].
+///
+/// # Errors
+/// Returns a [`StorageError`] if the store cannot be opened.
+pub fn async_storage_from_uri(
+    uri: &str,
+) -> Result<AsyncReadableWritableListableStorage, StorageError> {
+    let (uri, options) = resolve_profile(uri)?;
+    if is_object_store_uri(&uri) {
+        get_async_object_storage(&uri, &options)
+    } else {
+        Ok(Arc::new(AsyncOpendalStore::new(opendal_operator(
+            &uri, &options,
+        )?)))
+    }
+}
+
+/// Resolve a profile URI, or return any other URI with no options.
+fn resolve_profile(uri: &str) -> Result<(String, StoreOptions), StorageError> {
+    if is_profile_uri(uri) {
+        StoreProfiles::load()?.resolve(uri)
+    } else {
+        Ok((uri.to_string(), StoreOptions::new()))
+    }
+}
+
+/// Blocks on futures with a `tokio` runtime.
+pub struct TokioBlockOn(pub tokio::runtime::Runtime);
+
+impl AsyncToSyncBlockOn for TokioBlockOn {
+    fn block_on<F: core::future::Future>(&self, future: F) -> F::Output {
+        self.0.block_on(future)
+    }
+}
+
+/// A synchronous store for a local path or a remote URI.
+pub enum Storage {
+    /// A local filesystem store.
+    Filesystem(FilesystemStore),
+    /// A local filesystem store read with direct I/O and/or io_uring.
+    Direct(DirectReadStore),
+    /// A read-only HTTP(S) store.
+    Http(AsyncToSyncStorageAdapter<AsyncOpendalStore, TokioBlockOn>),
+    /// An OpenDAL store.
+    Opendal(AsyncToSyncStorageAdapter<AsyncOpendalStore, TokioBlockOn>),
+    /// A read-only zip archive.
+    Zip(ZipStorageAdapter<Storage>),
+    /// A store with a persistent local cache.
+    Cached(CacheStorageAdapter<Storage>),
+    /// A store that retries failed operations.
+    Retry(RetryStorageAdapter<Storage>),
+    /// An S3 store.
+    #[cfg(feature = "s3")]
+    S3(AsyncToSyncStorageAdapter<AsyncObjectStore<S3ObjectStore>, TokioBlockOn>),
+    /// A Google Cloud Storage store.
+    #[cfg(feature = "gcs")]
+    Gcs(AsyncToSyncStorageAdapter<AsyncObjectStore<GcsObjectStore>, TokioBlockOn>),
+    /// An Azure Blob Storage store.
+    #[cfg(feature = "azure")]
+    Azure(AsyncToSyncStorageAdapter<AsyncObjectStore<AzureObjectStore>, TokioBlockOn>),
+}
+
+/// Open the store at a local path or a remote URI.
+///
+/// A profile URI (`profile://name/path`) is resolved with the [`StoreProfiles`] from [`StoreProfiles::load`].
+/// Local paths are opened as a [`FilesystemStore`], object store URIs (`s3://`, `gs://`, `az://`) with `object_store`, and any other URI with [`opendal_operator`].
+/// A zip archive (`.zip`) is opened from the store of its parent.
+/// HTTP(S) and zip stores are read-only, writing to them returns [`StorageError::ReadOnly`].
+///
+/// # Errors
+/// Returns a [`StorageError`] if the store cannot be opened, or `path` is a URI and the feature for its scheme is not enabled.
+pub fn storage_from_uri(path: &str) -> Result<Arc<Storage>, StorageError> {
+    let (path, options) = resolve_profile(path)?;
+    storage_from_uri_with_options(&path, &options)
+}
+
+/// Open the store at a local path or a remote URI with backend `options`.
+///
+/// # Errors
+/// Returns a [`StorageError`] if the store cannot be opened or `options` are invalid.
+pub fn storage_from_uri_with_options(
+    path: &str,
+    options: &StoreOptions,
+) -> Result<Arc<Storage>, StorageError> {
+    if is_zip_path(path) {
+        let (parent, name) = path.rsplit_once('/').unwrap_or((".", path));
+        let parent = if parent.is_empty() { "/" } else { parent };
+        let zip = ZipStorageAdapter::new(
+            storage_from_uri_with_options(parent, options)?,
+            StoreKey::new(name)?,
+        )
+        .map_err(|err| StorageError::Other(format!("{path}: {err}")))?;
+        return Ok(Arc::new(Storage::Zip(zip)));
+    }
+    if is_uri(path) && !is_object_store_uri(path) {
+        let storage = AsyncToSyncStorageAdapter::new(
+            Arc::new(AsyncOpendalStore::new(opendal_operator(path, options)?)),
+            TokioBlockOn(tokio_runtime()?),
+        );
+        return Ok(Arc::new(if is_http_url(path) {
+            Storage::Http(storage)
+        } else {
+            Storage::Opendal(storage)
+        }));
+    }
+    if is_s3_uri(path) {
+        #[cfg(feature = "s3")]
+        return Ok(Arc::new(Storage::S3(AsyncToSyncStorageAdapter::new(
+            Arc::new(s3_store(path, options)?),
+            TokioBlockOn(tokio_runtime()?),
+        ))));
+        #[cfg(not(feature = "s3"))]
+        return Err(feature_required(path, "s3"));
+    }
+    if is_gcs_uri(path) {
+        #[cfg(feature = "gcs")]
+        return Ok(Arc::new(Storage::Gcs(AsyncToSyncStorageAdapter::new(
+            Arc::new(gcs_store(path, options)?),
+            TokioBlockOn(tokio_runtime()?),
+        ))));
+        #[cfg(not(feature = "gcs"))]
+        return Err(feature_required(path, "gcs"));
+    }
+    if is_azure_uri(path) {
+        #[cfg(feature = "azure")]
+        return Ok(Arc::new(Storage::Azure(AsyncToSyncStorageAdapter::new(
+            Arc::new(azure_store(path, options)?),
+            TokioBlockOn(tokio_runtime()?),
+        ))));
+        #[cfg(not(feature = "azure"))]
+        return Err(feature_required(path, "azure"));
+    }
+    Ok(Arc::new(Storage::Filesystem(
+        FilesystemStore::new(path).map_err(|err| StorageError::Other(err.to_string()))?,
+    )))
+}
+
+/// The subdirectory of `cache_dir` that caches the store at `uri`.
+pub fn uri_cache_dir(cache_dir: &Path, uri: &str) -> PathBuf {
+    let name: String = uri
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    cache_dir.join(name)
+}
+
+/// Options for remote stores, and for reading local stores.
+#[derive(clap::Parser, Debug, Clone)]
+pub struct RemoteStorageArgs {
+    /// Directory for a persistent cache of remote inputs (e.g. `s3://`, `https://`).
+    ///
+    /// Each chunk of a remote input is fetched once, and read from the cache by later filters and runs.
+    /// The cache is not invalidated if the remote data changes.
+    #[arg(long)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// The number of retries of a failed remote storage operation.
+    #[arg(long, default_value_t = 3)]
+    pub retries: usize,
+
+    /// The backoff before the first retry of a failed remote storage operation in seconds.
+    ///
+    /// The backoff is doubled for each subsequent retry.
+    #[arg(long, default_value_t = 0.5, value_parser = parse_backoff)]
+    pub backoff: f64,
+
+    /// The maximum number of remote storage requests per second.
+    ///
+    /// The limit is shared by all remote stores.
+    #[arg(long, value_parser = parse_max_requests_per_second)]
+    pub max_requests_per_second: Option<f64>,
+
+    /// Read local stores with direct I/O (`O_DIRECT`), bypassing the page cache.
+    ///
+    /// Reading a large array does not evict other data from the page cache, and benchmarks measure the storage rather than memory.
+    /// Linux only.
+    #[arg(long)]
+    pub direct_io: bool,
+
+    /// Read local stores with io_uring, submitting the reads of the byte ranges of a chunk (e.g. the inner chunks of a shard) at once.
+    ///
+    /// Requires the `io_uring` feature on Linux.
+    #[arg(long)]
+    pub io_uring: bool,
+
+    /// Memory-map the chunk files of local stores for decoding, avoiding a copy of large uncompressed chunks.
+    ///
+    /// A chunk file must not be modified by another process while it is read, so a filter cannot write to one of its inputs (e.g. in place).
+    #[arg(long, conflicts_with_all = ["direct_io", "io_uring"])]
+    pub mmap: bool,
+
+    #[arg(skip)]
+    rate_limiter: std::sync::OnceLock<Option<Arc<RateLimiter>>>,
+}
+
+impl Default for RemoteStorageArgs {
+    fn default() -> Self {
+        Self {
+            cache_dir: None,
+            retries: 3,
+            backoff: 0.5,
+            max_requests_per_second: None,
+            direct_io: false,
+            io_uring: false,
+            mmap: false,
+            rate_limiter: std::sync::OnceLock::new(),
+        }
+    }
+}
+
+/// Parse a backoff in seconds, which must be a non-negative finite number.
+fn parse_backoff(backoff: &str) -> Result<f64, String> {
+    let backoff: f64 = backoff.parse().map_err(|err| format!("{err}"))?;
+    backoff_duration(backoff)
+        .map(|_| backoff)
+        .map_err(|err| err.to_string())
+}
+
+/// Parse a maximum number of requests per second, which must be a positive finite number.
+fn parse_max_requests_per_second(max_requests_per_second: &str) -> Result<f64, String> {
+    let max_requests_per_second: f64 = max_requests_per_second
+        .parse()
+        .map_err(|err| format!("{err}"))?;
+    RateLimiter::new(max_requests_per_second)
+        .map(|_| max_requests_per_second)
+        .map_err(|err| err.to_string())
+}
+
+/// The duration of a `backoff` in seconds.
+fn backoff_duration(backoff: f64) -> Result<Duration, StorageError> {
+    Duration::try_from_secs_f64(backoff).map_err(|_| {
+        StorageError::Other(format!(
+            "the backoff must be a non-negative number of seconds, not {backoff}"
+        ))
+    })
+}
+
+impl RemoteStorageArgs {
+    fn rate_limiter(&self) -> Result<Option<Arc<RateLimiter>>, StorageError> {
+        if let Some(rate_limiter) = self.rate_limiter.get() {
+            return Ok(rate_limiter.clone());
+        }
+        let rate_limiter = self
+            .max_requests_per_second
+            .map(RateLimiter::new)
+            .transpose()?
+            .map(Arc::new);
+        Ok(self.rate_limiter.get_or_init(|| rate_limiter).clone())
+    }
+
+    /// Open the store at a local path or a remote URI.
+    ///
+    /// A remote store retries failed operations, is rate limited, and is cached in a subdirectory of the cache directory named after its URI.
+    /// Local paths are opened as with [`storage_from_uri`], or as a [`DirectReadStore`] with [`direct_io`](RemoteStorageArgs::direct_io), [`io_uring`](RemoteStorageArgs::io_uring), or [`mmap`](RemoteStorageArgs::mmap).
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the store cannot be opened, the backoff or rate limit is invalid, or the cache directory cannot be created.
+    pub fn storage_from_uri(&self, path: &str) -> Result<Arc<Storage>, StorageError> {
+        if !is_uri(path) && !is_zip_path(path) && (self.direct_io || self.io_uring || self.mmap) {
+            return Ok(Arc::new(Storage::Direct(DirectReadStore::new(
+                path,
+                DirectReadOptions {
+                    direct: self.direct_io,
+                    io_uring: self.io_uring,
+                    mmap: self.mmap,
+                },
+            )?)));
+        }
+        let storage = storage_from_uri(path)?;
+        if !is_uri(path) {
+            return Ok(storage);
+        }
+        let storage = Arc::new(Storage::Retry(RetryStorageAdapter::new(
+            storage,
+            self.retries,
+            backoff_duration(self.backoff)?,
+            self.rate_limiter()?,
+        )));
+        if let Some(cache_dir) = &self.cache_dir {
+            Ok(Arc::new(Storage::Cached(CacheStorageAdapter::new(
+                storage,
+                &uri_cache_dir(cache_dir, path),
+            )?)))
+        } else {
+            Ok(storage)
+        }
+    }
+
+    /// Open an asynchronous store at a local path or a remote URI with [`async_storage_from_uri`].
+    ///
+    /// A remote store retries failed operations, is rate limited, and is cached as with [`RemoteStorageArgs::storage_from_uri`].
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the store cannot be opened, the backoff or rate limit is invalid, or the cache directory cannot be created.
+    pub fn async_storage_from_uri(
+        &self,
+        path: &str,
+    ) -> Result<AsyncReadableWritableListableStorage, StorageError> {
+        let storage = async_storage_from_uri(path)?;
+        if !is_uri(path) {
+            return Ok(storage);
+        }
+        let storage = Arc::new(RetryStorageAdapter::new(
+            storage,
+            self.retries,
+            backoff_duration(self.backoff)?,
+            self.rate_limiter()?,
+        ));
+        if let Some(cache_dir) = &self.cache_dir {
+            Ok(Arc::new(CacheStorageAdapter::new(
+                storage,
+                &uri_cache_dir(cache_dir, path),
+            )?))
+        } else {
+            Ok(storage)
+        }
+    }
+
+    /// Apply the retries, rate limit, and cache of remote stores to a readable `storage` opened from `uri`.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the backoff or rate limit is invalid, or the cache directory cannot be created.
+    pub fn readable_storage(
+        &self,
+        uri: &str,
+        storage: ReadableStorage,
+    ) -> Result<ReadableStorage, StorageError> {
+        if !is_uri(uri) {
+            return Ok(storage);
+        }
+        let storage: ReadableStorage = Arc::new(RetryStorageAdapter::new(
+            storage,
+            self.retries,
+            backoff_duration(self.backoff)?,
+            self.rate_limiter()?,
+        ));
+        if let Some(cache_dir) = &self.cache_dir {
+            Ok(Arc::new(CacheStorageAdapter::new(
+                storage,
+                &uri_cache_dir(cache_dir, uri),
+            )?))
+        } else {
+            Ok(storage)
+        }
+    }
+}
+
+/// A local staging directory for an output that is written to a zip archive when finished.
+///
+/// Chunks are written concurrently to the directory, then [`ZipOutput::finish`] writes the archive.
+pub struct ZipOutput {
+    directory: tempfile::TempDir,
+    path: PathBuf,
+}
+
+impl ZipOutput {
+    /// Create a staging directory for a zip archive at a local `path`.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if `path` is a URI or the staging directory cannot be created.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, StorageError> {
+        let path = path.into();
+        if is_uri(&path.to_string_lossy()) {
+            return Err(StorageError::Other(format!(
+                "{path:?}: a zip output must be a local path"
+            )));
+        }
+        Ok(Self {
+            directory: tempfile::tempdir()?,
+            path,
+        })
+    }
+
+    /// The staging directory.
+    pub fn directory(&self) -> &Path {
+        self.directory.path()
+    }
+
+    /// The path of the zip archive.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the staging directory to the zip archive and remove it.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the staging directory cannot be read or the archive cannot be written.
+    pub fn finish(self) -> Result<(), StorageError> {
+        let storage = FilesystemStore::new(self.directory())
+            .map_err(|err| StorageError::Other(err.to_string()))?;
+        write_zip(&storage, &self.path)
+    }
+}
+
+/// Write every value in `storage` to a zip archive at a local `path`.
+///
+/// Values are stored uncompressed, since chunks are generally already compressed and this allows partial reads.
+///
+/// # Errors
+/// Returns a [`StorageError`] if `storage` cannot be read or the archive cannot be written.
+pub fn write_zip<TStorage: ?Sized + ReadableStorageTraits + ListableStorageTraits>(
+    storage: &TStorage,
+    path: &Path,
+) -> Result<(), StorageError> {
+    let to_storage_error = |err: zip::result::ZipError| StorageError::Other(err.to_string());
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(path)?);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(true);
+    for key in storage.list()? {
+        if let Some(value) = storage.get(&key)? {
+            writer
+                .start_file(key.as_str(), options)
+                .map_err(to_storage_error)?;
+            writer.write_all(&value)?;
+        }
+    }
+    writer.finish().map_err(to_storage_error)?;
+    Ok(())
+}
+
+/// Copy every value in `storage` into a [`MemoryStore`].
+///
+/// # Errors
+/// Returns a [`StorageError`] if `storage` cannot be listed or read.
+pub fn preload_storage<TStorage: ?Sized + ReadableStorageTraits + ListableStorageTraits>(
+    storage: &TStorage,
+) -> Result<Arc<MemoryStore>, StorageError> {
+    let memory_store = MemoryStore::new();
+    for key in storage.list()? {
+        if let Some(value) = storage.get(&key)? {
+            memory_store.set(&key, value)?;
+        }
+    }
+    Ok(Arc::new(memory_store))
+}
+
+/// Copy every value in an asynchronous `storage` into an in-memory store.
+///
+/// # Errors
+/// Returns a [`StorageError`] if `storage` cannot be listed or read.
+#[cfg(feature = "async")]
+pub async fn async_preload_storage<
+    TStorage: ?Sized + AsyncReadableStorageTraits + AsyncListableStorageTraits,
+>(
+    storage: &TStorage,
+) -> Result<Arc<AsyncObjectStore<object_store::memory::InMemory>>, StorageError> {
+    let memory_store = AsyncObjectStore::new(object_store::memory::InMemory::new());
+    for key in storage.list().await? {
+        if let Some(value) = storage.get(&key).await? {
+            memory_store.set(&key, value).await?;
+        }
+    }
+    Ok(Arc::new(memory_store))
+}
+
+/// The number of bytes read from and written to the underlying stores of every [`Storage`].
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of bytes read from every [`Storage`] by this process.
+///
+/// Bytes are counted once by the underlying store, so reads served by a [`CacheStorageAdapter`] are not counted.
+pub fn bytes_read() -> u64 {
+    BYTES_READ.load(Ordering::Relaxed)
+}
+
+/// Returns the number of bytes written to every [`Storage`] by this process.
+///
+/// Partial writes are not counted.
+pub fn bytes_written() -> u64 {
+    BYTES_WRITTEN.load(Ordering::Relaxed)
+}
+
+impl Storage {
+    /// Returns true if the store is an adapter of another [`Storage`], which counts its bytes read and written.
+    fn is_adapter(&self) -> bool {
+        matches!(
+            self,
+            Storage::Zip(_) | Storage::Cached(_) | Storage::Retry(_)
+        )
+    }
+
+    fn count_read(&self, bytes: usize) {
+        if !self.is_adapter() {
+            BYTES_READ.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn count_written(&self, bytes: usize) {
+        if !self.is_adapter() {
+            BYTES_WRITTEN.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+macro_rules! storage_dispatch {
+    ($self:ident, $storage:ident => $expr:expr) => {
+        match $self {
+            Storage::Filesystem($storage) => $expr,
+            Storage::Direct($storage) => $expr,
+            Storage::Http($storage) | Storage::Opendal($storage) => $expr,
+            Storage::Zip($storage) => $expr,
+            Storage::Cached($storage) => $expr,
+            Storage::Retry($storage) => $expr,
+            #[cfg(feature = "s3")]
+            Storage::S3($storage) => $expr,
+            #[cfg(feature = "gcs")]
+            Storage::Gcs($storage) => $expr,
+            #[cfg(feature = "azure")]
+            Storage::Azure($storage) => $expr,
+        }
+    };
+}
+
+macro_rules! storage_dispatch_writable {
+    ($self:ident, $storage:ident => $expr:expr) => {
+        match $self {
+            Storage::Filesystem($storage) => $expr,
+            Storage::Direct($storage) => $expr,
+            Storage::Http(_) | Storage::Zip(_) => Err(StorageError::ReadOnly),
+            Storage::Opendal($storage) => $expr,
+            Storage::Cached($storage) => $expr,
+            Storage::Retry($storage) => $expr,
+            #[cfg(feature = "s3")]
+            Storage::S3($storage) => $expr,
+            #[cfg(feature = "gcs")]
+            Storage::Gcs($storage) => $expr,
+            #[cfg(feature = "azure")]
+            Storage::Azure($storage) => $expr,
+        }
+    };
+}
+
+impl ReadableStorageTraits for Storage {
+    fn get(&self, key: &StoreKey) -> Result<MaybeBytes, StorageError> {
+        let bytes = storage_dispatch!(self, storage => storage.get(key))?;
+        self.count_read(bytes.as_ref().map_or(0, |bytes| bytes.len()));
+        Ok(bytes)
+    }
+
+    fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        let bytes =
+            storage_dispatch!(self, storage => storage.get_partial_values_key(key, byte_ranges))?;
+        self.count_read(bytes.iter().flatten().map(|bytes| bytes.len()).sum());
+        Ok(bytes)
+    }
+
+    fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        storage_dispatch!(self, storage => storage.size_key(key))
+    }
+}
+
+impl ListableStorageTraits for Storage {
+    fn list(&self) -> Result<StoreKeys, StorageError> {
+        storage_dispatch!(self, storage => storage.list())
+    }
+
+    fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        storage_dispatch!(self, storage => storage.list_prefix(prefix))
+    }
+
+    fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        storage_dispatch!(self, storage => storage.list_dir(prefix))
+    }
+
+    fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        storage_dispatch!(self, storage => storage.size_prefix(prefix))
+    }
+}
+
+impl WritableStorageTraits for Storage {
+    fn set(&self, key: &StoreKey, value: Bytes) -> Result<(), StorageError> {
+        let len = value.len();
+        storage_dispatch_writable!(self, storage => storage.set(key, value))?;
+        self.count_written(len);
+        Ok(())
+    }
+
+    fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        storage_dispatch_writable!(self, storage => storage.set_partial_values(key_start_values))
+    }
+
+    fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        storage_dispatch_writable!(self, storage => storage.erase(key))
+    }
+
+    fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        storage_dispatch_writable!(self, storage => storage.erase_values(keys))
+    }
+
+    fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        storage_dispatch_writable!(self, storage => storage.erase_prefix(prefix))
+    }
+}
+
+impl ReadableWritableStorageTraits for Storage {}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn s3_uri() {
+        assert!(is_s3_uri("s3://bucket/prefix"));
+        assert!(!is_s3_uri("/tmp/array.zarr"));
+        assert_eq!(
+            parse_s3_uri("s3://bucket/path/to/array.zarr/"),
+            Some(("bucket", "path/to/array.zarr"))
+        );
+        assert_eq!(parse_s3_uri("s3://bucket"), Some(("bucket", "")));
+        assert_eq!(parse_s3_uri("s3:///array.zarr"), None);
+        assert_eq!(parse_s3_uri("array.zarr"), None);
+    }
+
+    #[test]
+    fn preload() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let storage = storage_from_uri(&path.path().to_string_lossy())?;
+        storage.set(&StoreKey::new("zarr.json")?, Bytes::from_static(b"{}"))?;
+        storage.set(&StoreKey::new("c/0/0")?, Bytes::from_static(&[1, 2, 3]))?;
+
+        let memory_store = preload_storage(&*storage)?;
+        storage.erase_prefix(&StorePrefix::root())?;
+        assert_eq!(memory_store.list()?.len(), 2);
+        assert_eq!(
+            memory_store.get(&StoreKey::new("c/0/0")?)?,
+            Some(Bytes::from_static(&[1, 2, 3]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn zip() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let zip_path = path.path().join("array.zarr.zip");
+        let zip_output = ZipOutput::new(&zip_path)?;
+        let storage = storage_from_uri(&zip_output.directory().to_string_lossy())?;
+        storage.set(&StoreKey::new("zarr.json")?, Bytes::from_static(b"{}"))?;
+        storage.set(&StoreKey::new("c/0/0")?, Bytes::from_static(&[1, 2, 3]))?;
+        zip_output.finish()?;
+
+        let storage = storage_from_uri(&zip_path.to_string_lossy())?;
+        assert_eq!(
+            storage.get(&StoreKey::new("c/0/0")?)?,
+            Some(Bytes::from_static(&[1, 2, 3]))
+        );
+        assert_eq!(storage.list()?.len(), 2);
+        assert!(matches!(
+            storage.erase(&StoreKey::new("zarr.json")?),
+            Err(StorageError::ReadOnly)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn remote_storage_args() {
+        let parse = |args: &[&str]| {
+            RemoteStorageArgs::try_parse_from(std::iter::once("remote").chain(args.iter().copied()))
+        };
+        assert!(parse(&["--max-requests-per-second", "10"]).is_ok());
+        assert!(parse(&["--backoff", "0"]).is_ok());
+        for args in [
+            ["--max-requests-per-second", "0"],
+            ["--max-requests-per-second", "-1"],
+            ["--max-requests-per-second", "NaN"],
+            ["--max-requests-per-second", "inf"],
+            ["--backoff", "-1"],
+            ["--backoff", "NaN"],
+        ] {
+            assert!(parse(&args).is_err());
+        }
+
+        // Arguments that are not parsed are validated when a store is opened
+        let args = RemoteStorageArgs {
+            max_requests_per_second: Some(0.0),
+            ..Default::default()
+        };
+        assert!(args
+            .storage_from_uri("https://example.com/array.zarr")
+            .is_err());
+    }
+
+    #[test]
+    fn cache_dir() {
+        assert_eq!(
+            uri_cache_dir(Path::new("/cache"), "s3://bucket/array.zarr"),
+            Path::new("/cache/s3___bucket_array.zarr")
+        );
+    }
+
+    #[test]
+    fn uri() {
+        assert!(is_uri("webdav://example.com/array.zarr"));
+        assert!(is_uri("s3://bucket/prefix"));
+        assert!(!is_uri("/tmp/array.zarr"));
+        assert!(!is_uri("array.zarr"));
+        assert!(
+            opendal_operator("unknown://example.com/array.zarr", &StoreOptions::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn object_store_uri() {
+        assert!(is_object_store_uri("gs://bucket/prefix"));
+        assert!(is_object_store_uri("az://container/prefix"));
+        assert!(!is_object_store_uri("https://example.com/array.zarr"));
+        assert_eq!(
+            parse_gcs_uri("gs://bucket/array.zarr"),
+            Some(("bucket", "array.zarr"))
+        );
+        assert_eq!(
+            parse_azure_uri("az://container/path/to/array.zarr"),
+            Some(("container", "path/to/array.zarr"))
+        );
+        assert_eq!(parse_azure_uri("gs://bucket/array.zarr"), None);
+    }
+
+    #[test]
+    fn http_read_only() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(is_http_url("https://example.com/array.zarr"));
+        assert!(!is_http_url("s3://bucket/prefix"));
+        let storage = storage_from_uri("https://example.com/array.zarr")?;
+        assert!(matches!(
+            storage.set(&StoreKey::new("zarr.json")?, Bytes::new()),
+            Err(StorageError::ReadOnly)
+        ));
+        Ok(())
+    }
+}
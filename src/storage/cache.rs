@@ -0,0 +1,336 @@
+use std::{path::Path, sync::Arc};
+
+use zarrs::{
+    byte_range::ByteRange,
+    storage::{
+        store::FilesystemStore, AsyncBytes, AsyncListableStorageTraits, AsyncReadableStorageTraits,
+        AsyncReadableWritableStorageTraits, AsyncWritableStorageTraits, Bytes,
+        ListableStorageTraits, MaybeAsyncBytes, MaybeBytes, ReadableStorageTraits,
+        ReadableWritableStorageTraits, StorageError, StoreKey, StoreKeyStartValue, StoreKeys,
+        StoreKeysPrefixes, StorePrefix, WritableStorageTraits,
+    },
+};
+
+/// A persistent cache of the values of a store in a local directory.
+///
+/// Each value is fetched from the store once and then read from the cache, including in later runs.
+/// Writes go to the store and invalidate the cached value.
+/// The cache is not invalidated if the store is changed by something else, so it should be removed if the store changes.
+/// The adapter implements the sync storage traits for a sync store, and the async storage traits for an async store.
+pub struct CacheStorageAdapter<TStorage: ?Sized> {
+    storage: Arc<TStorage>,
+    cache: FilesystemStore,
+}
+
+impl<TStorage: ?Sized> CacheStorageAdapter<TStorage> {
+    /// Create a cache of `storage` in `cache_dir`.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the cache directory cannot be created.
+    pub fn new(storage: Arc<TStorage>, cache_dir: &Path) -> Result<Self, StorageError> {
+        std::fs::create_dir_all(cache_dir)?;
+        let cache =
+            FilesystemStore::new(cache_dir).map_err(|err| StorageError::Other(err.to_string()))?;
+        Ok(Self { storage, cache })
+    }
+
+    /// Write a value to the cache.
+    ///
+    /// The value is written to a temporary file which is then renamed, so a concurrent reader never sees a partial value.
+    fn cache_value(&self, key: &StoreKey, value: &[u8]) -> Result<(), StorageError> {
+        let path = self.cache.key_to_fspath(key);
+        let parent = path.parent().unwrap();
+        std::fs::create_dir_all(parent)?;
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        std::io::Write::write_all(&mut file, value)?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+}
+
+impl<TStorage: ?Sized + ReadableStorageTraits> CacheStorageAdapter<TStorage> {
+    /// Fetch a value from the store into the cache if it is not cached.
+    ///
+    /// Returns false if the value does not exist.
+    fn fetch(&self, key: &StoreKey) -> Result<bool, StorageError> {
+        if self.cache.size_key(key)?.is_some() {
+            return Ok(true);
+        }
+        if let Some(value) = self.storage.get(key)? {
+            self.cache_value(key, &value)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+impl<TStorage: ?Sized + ReadableStorageTraits> ReadableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+    fn get(&self, key: &StoreKey) -> Result<MaybeBytes, StorageError> {
+        if let Some(value) = self.cache.get(key)? {
+            return Ok(Some(value));
+        }
+        let value = self.storage.get(key)?;
+        if let Some(value) = &value {
+            self.cache_value(key, value)?;
+        }
+        Ok(value)
+    }
+
+    fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        // Partial reads (e.g. of shards) fetch the whole value once
+        if self.fetch(key)? {
+            self.cache.get_partial_values_key(key, byte_ranges)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        if let Some(size) = self.cache.size_key(key)? {
+            Ok(Some(size))
+        } else {
+            self.storage.size_key(key)
+        }
+    }
+}
+
+impl<TStorage: ?Sized + ListableStorageTraits> ListableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+    fn list(&self) -> Result<StoreKeys, StorageError> {
+        self.storage.list()
+    }
+
+    fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        self.storage.list_prefix(prefix)
+    }
+
+    fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        self.storage.list_dir(prefix)
+    }
+
+    fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        self.storage.size_prefix(prefix)
+    }
+}
+
+impl<TStorage: ?Sized + ReadableWritableStorageTraits> WritableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+    fn set(&self, key: &StoreKey, value: Bytes) -> Result<(), StorageError> {
+        self.cache.erase(key)?;
+        self.storage.set(key, value)
+    }
+
+    fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        // The keys of key_start_values are not accessible, so the whole cache is invalidated
+        self.cache.erase_prefix(&StorePrefix::root())?;
+        self.storage.set_partial_values(key_start_values)
+    }
+
+    fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        self.cache.erase(key)?;
+        self.storage.erase(key)
+    }
+
+    fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        self.cache.erase_values(keys)?;
+        self.storage.erase_values(keys)
+    }
+
+    fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        self.cache.erase_prefix(prefix)?;
+        self.storage.erase_prefix(prefix)
+    }
+}
+
+impl<TStorage: ?Sized + ReadableWritableStorageTraits> ReadableWritableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+}
+
+impl<TStorage: ?Sized + AsyncReadableStorageTraits> CacheStorageAdapter<TStorage> {
+    /// Fetch a value from the async store into the cache if it is not cached.
+    ///
+    /// Returns false if the value does not exist.
+    async fn fetch_async(&self, key: &StoreKey) -> Result<bool, StorageError> {
+        if self.cache.size_key(key)?.is_some() {
+            return Ok(true);
+        }
+        if let Some(value) = self.storage.get(key).await? {
+            self.cache_value(key, &value)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncReadableStorageTraits> AsyncReadableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+    async fn get(&self, key: &StoreKey) -> Result<MaybeAsyncBytes, StorageError> {
+        if let Some(value) = self.cache.get(key)? {
+            return Ok(Some(value));
+        }
+        let value = self.storage.get(key).await?;
+        if let Some(value) = &value {
+            self.cache_value(key, value)?;
+        }
+        Ok(value)
+    }
+
+    async fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<AsyncBytes>>, StorageError> {
+        // Partial reads (e.g. of shards) fetch the whole value once
+        if self.fetch_async(key).await? {
+            self.cache.get_partial_values_key(key, byte_ranges)
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        if let Some(size) = self.cache.size_key(key)? {
+            Ok(Some(size))
+        } else {
+            self.storage.size_key(key).await
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncListableStorageTraits> AsyncListableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+    async fn list(&self) -> Result<StoreKeys, StorageError> {
+        self.storage.list().await
+    }
+
+    async fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        self.storage.list_prefix(prefix).await
+    }
+
+    async fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        self.storage.list_dir(prefix).await
+    }
+
+    async fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        self.storage.size_prefix(prefix).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncReadableWritableStorageTraits> AsyncWritableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+    async fn set(&self, key: &StoreKey, value: AsyncBytes) -> Result<(), StorageError> {
+        self.cache.erase(key)?;
+        self.storage.set(key, value).await
+    }
+
+    async fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        // The keys of key_start_values are not accessible, so the whole cache is invalidated
+        self.cache.erase_prefix(&StorePrefix::root())?;
+        self.storage.set_partial_values(key_start_values).await
+    }
+
+    async fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        self.cache.erase(key)?;
+        self.storage.erase(key).await
+    }
+
+    async fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        self.cache.erase_values(keys)?;
+        self.storage.erase_values(keys).await
+    }
+
+    async fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        self.cache.erase_prefix(prefix)?;
+        self.storage.erase_prefix(prefix).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncReadableWritableStorageTraits> AsyncReadableWritableStorageTraits
+    for CacheStorageAdapter<TStorage>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let store = Arc::new(FilesystemStore::new(path.path().join("store"))?);
+        let cache_dir = path.path().join("cache");
+        let key = StoreKey::new("c/0/0")?;
+        store.set(&key, Bytes::from_static(&[1, 2, 3, 4]))?;
+
+        let cached = CacheStorageAdapter::new(store.clone(), &cache_dir)?;
+        assert_eq!(
+            cached.get_partial_values_key(&key, &[ByteRange::FromStart(1, Some(2))])?,
+            Some(vec![Bytes::from_static(&[2, 3])])
+        );
+        assert!(cache_dir.join("c/0/0").exists());
+
+        // Values are read from the cache
+        store.erase(&key)?;
+        assert_eq!(cached.get(&key)?, Some(Bytes::from_static(&[1, 2, 3, 4])));
+
+        // Writes invalidate the cache
+        cached.set(&key, Bytes::from_static(&[5]))?;
+        assert!(!cache_dir.join("c/0/0").exists());
+        assert_eq!(cached.get(&key)?, Some(Bytes::from_static(&[5])));
+        Ok(())
+    }
+
+    #[test]
+    fn cache_async() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let store =
+            crate::storage::async_storage_from_uri(&path.path().join("store").to_string_lossy())?;
+        let cache_dir = path.path().join("cache");
+        let key = StoreKey::new("c/0/0")?;
+        tokio::runtime::Runtime::new()?.block_on(async {
+            store.set(&key, Bytes::from_static(&[1, 2, 3, 4])).await?;
+            let cached = CacheStorageAdapter::new(store.clone(), &cache_dir)?;
+            assert_eq!(
+                cached.get(&key).await?,
+                Some(Bytes::from_static(&[1, 2, 3, 4]))
+            );
+            assert!(cache_dir.join("c/0/0").exists());
+
+            // Values are read from the cache, and writes invalidate it
+            store.erase(&key).await?;
+            assert_eq!(
+                cached.get(&key).await?,
+                Some(Bytes::from_static(&[1, 2, 3, 4]))
+            );
+            cached.set(&key, Bytes::from_static(&[5])).await?;
+            assert!(!cache_dir.join("c/0/0").exists());
+            assert_eq!(cached.get(&key).await?, Some(Bytes::from_static(&[5])));
+            Ok::<_, StorageError>(())
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,470 @@
+use std::{alloc::Layout, fs::File, ops::Range, path::Path, ptr::NonNull};
+
+use zarrs::{
+    byte_range::ByteRange,
+    storage::{
+        store::FilesystemStore, Bytes, ListableStorageTraits, MaybeBytes, ReadableStorageTraits,
+        ReadableWritableStorageTraits, StorageError, StoreKey, StoreKeyStartValue, StoreKeys,
+        StoreKeysPrefixes, StorePrefix, WritableStorageTraits,
+    },
+};
+
+/// The alignment of the offset, length, and buffer of a direct I/O read.
+///
+/// This is the logical block size of most devices, or a multiple of it.
+const ALIGNMENT: u64 = 4096;
+
+/// How a [`DirectReadStore`] reads its files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirectReadOptions {
+    /// Open files with `O_DIRECT`, bypassing the page cache.
+    pub direct: bool,
+    /// Submit the reads of the byte ranges of a file concurrently with io_uring.
+    pub io_uring: bool,
+    /// Memory-map files, so their bytes are decoded from the page cache without a copy.
+    ///
+    /// A file must not be modified by another process while it is mapped.
+    pub mmap: bool,
+}
+
+/// A [`FilesystemStore`] that reads its files with direct I/O and/or io_uring, or memory maps.
+///
+/// Direct I/O (`O_DIRECT`) bypasses the page cache, so reading a large array does not evict other data and benchmarks measure the storage rather than memory.
+/// Reads are aligned to 4096 bytes, and a file system that does not support direct I/O (e.g. `tmpfs`) is read through the page cache.
+/// io_uring submits the reads of every byte range of a file (e.g. the inner chunks of a shard) at once, rather than one after another.
+/// A memory-mapped file is not copied into memory, so a large uncompressed chunk is decoded directly from the page cache.
+///
+/// Listing and writing are those of the [`FilesystemStore`].
+pub struct DirectReadStore {
+    store: FilesystemStore,
+    options: DirectReadOptions,
+}
+
+impl DirectReadStore {
+    /// Create a new store at `base_path` read with `options`.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the store cannot be created, `options` are not supported on this platform or without the `io_uring` feature, or memory maps are combined with direct I/O or io_uring.
+    pub fn new(
+        base_path: impl AsRef<Path>,
+        options: DirectReadOptions,
+    ) -> Result<Self, StorageError> {
+        if options.direct && !cfg!(target_os = "linux") {
+            return Err(StorageError::Other(
+                "direct I/O is only supported on Linux".to_string(),
+            ));
+        }
+        if options.io_uring && !cfg!(all(target_os = "linux", feature = "io_uring")) {
+            return Err(StorageError::Other(
+                "io_uring requires the io_uring feature on Linux".to_string(),
+            ));
+        }
+        if options.mmap && (options.direct || options.io_uring) {
+            return Err(StorageError::Other(
+                "memory maps cannot be combined with direct I/O or io_uring".to_string(),
+            ));
+        }
+        let store =
+            FilesystemStore::new(base_path).map_err(|err| StorageError::Other(err.to_string()))?;
+        Ok(Self { store, options })
+    }
+
+    /// Open the file of `key`, or return [`None`] if it does not exist.
+    fn open(&self, key: &StoreKey) -> std::io::Result<Option<File>> {
+        let path = self.store.key_to_fspath(key);
+        let file = if self.options.direct {
+            match open_direct(&path) {
+                // The file system does not support direct I/O
+                Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => File::open(&path),
+                result => result,
+            }
+        } else {
+            File::open(&path)
+        };
+        match file {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Read the byte `ranges` of `file`.
+    fn read_ranges(&self, file: &File, ranges: &[Range<u64>]) -> std::io::Result<Vec<Bytes>> {
+        if self.options.mmap {
+            return map_ranges(file, ranges);
+        }
+        // Direct I/O reads whole aligned blocks, which are trimmed to the range
+        let reads = ranges
+            .iter()
+            .map(|range| {
+                if self.options.direct {
+                    let start = range.start / ALIGNMENT * ALIGNMENT;
+                    start..range.end.div_ceil(ALIGNMENT) * ALIGNMENT
+                } else {
+                    range.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut buffers = reads
+            .iter()
+            .map(|read| AlignedBuffer::new(usize::try_from(read.end - read.start).unwrap()))
+            .collect::<Vec<_>>();
+        let lengths = self.read(file, &reads, &mut buffers)?;
+        itertools::izip!(ranges, &reads, &buffers, lengths)
+            .map(|(range, read, buffer, length)| {
+                let start = usize::try_from(range.start - read.start).unwrap();
+                let end = usize::try_from(range.end - read.start).unwrap();
+                if length < end {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("the byte range {range:?} is beyond the end of the file"),
+                    ));
+                }
+                Ok(Bytes::copy_from_slice(&buffer.as_slice()[start..end]))
+            })
+            .collect()
+    }
+
+    /// Read each of `reads` of `file` into its buffer, returning the number of bytes read.
+    fn read(
+        &self,
+        file: &File,
+        reads: &[Range<u64>],
+        buffers: &mut [AlignedBuffer],
+    ) -> std::io::Result<Vec<usize>> {
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        if self.options.io_uring {
+            return uring::read(file, reads, buffers);
+        }
+        std::iter::zip(reads, buffers)
+            .map(|(read, buffer)| read_at(file, read.start, buffer.as_mut_slice()))
+            .collect()
+    }
+}
+
+/// Memory-map `file`, returning its byte `ranges` without copying them.
+fn map_ranges(file: &File, ranges: &[Range<u64>]) -> std::io::Result<Vec<Bytes>> {
+    let bytes = if file.metadata()?.len() == 0 {
+        Bytes::new()
+    } else {
+        // SAFETY: the file is not modified while it is mapped, see `DirectReadOptions::mmap`
+        Bytes::from_owner(unsafe { memmap2::Mmap::map(file)? })
+    };
+    ranges
+        .iter()
+        .map(|range| {
+            if range.end > bytes.len() as u64 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("the byte range {range:?} is beyond the end of the file"),
+                ));
+            }
+            Ok(bytes
+                .slice(usize::try_from(range.start).unwrap()..usize::try_from(range.end).unwrap()))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn open_direct(path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_direct(path: &Path) -> std::io::Result<File> {
+    File::open(path)
+}
+
+/// Read into `buffer` from `offset` of `file` until it is full or the end of the file, returning the number of bytes read.
+fn read_at(file: &File, offset: u64, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut length = 0;
+    while length < buffer.len() {
+        #[cfg(unix)]
+        let read = std::os::unix::fs::FileExt::read_at(
+            file,
+            &mut buffer[length..],
+            offset + length as u64,
+        );
+        #[cfg(windows)]
+        let read = std::os::windows::fs::FileExt::seek_read(
+            file,
+            &mut buffer[length..],
+            offset + length as u64,
+        );
+        match read {
+            Ok(0) => break,
+            Ok(read) => length += read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(length)
+}
+
+/// A zeroed buffer aligned to [`ALIGNMENT`], as required by direct I/O.
+struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+}
+
+// SAFETY: the buffer is uniquely owned
+unsafe impl Send for AlignedBuffer {}
+
+impl AlignedBuffer {
+    fn new(len: usize) -> Self {
+        let layout = Layout::from_size_align(len.max(1), ALIGNMENT as usize).unwrap();
+        // SAFETY: the layout has a non-zero size
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout)
+        };
+        Self { ptr, len, layout }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        // SAFETY: the buffer holds `len` initialised bytes
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the buffer holds `len` initialised bytes
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the buffer was allocated with `layout`
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod uring {
+    use std::{cell::RefCell, fs::File, ops::Range, os::fd::AsRawFd};
+
+    use io_uring::{opcode, types, IoUring};
+
+    use super::{read_at, AlignedBuffer};
+
+    /// The number of reads submitted at a time.
+    const ENTRIES: u32 = 64;
+
+    thread_local! {
+        /// The ring of each thread, created on its first read.
+        static RING: RefCell<Option<IoUring>> = const { RefCell::new(None) };
+    }
+
+    /// Read each of `reads` of `file` into its buffer, returning the number of bytes read.
+    ///
+    /// A short read (e.g. an interrupted read) is completed with a synchronous read.
+    pub(super) fn read(
+        file: &File,
+        reads: &[Range<u64>],
+        buffers: &mut [AlignedBuffer],
+    ) -> std::io::Result<Vec<usize>> {
+        let mut lengths = vec![0; reads.len()];
+        RING.with(|ring_cell| {
+            let mut ring_cell = ring_cell.borrow_mut();
+            if ring_cell.is_none() {
+                *ring_cell = Some(IoUring::new(ENTRIES)?);
+            }
+            let ring = ring_cell.as_mut().unwrap();
+            let fd = types::Fd(file.as_raw_fd());
+            let indices = (0..reads.len()).collect::<Vec<_>>();
+            let mut error = None;
+            for batch in indices.chunks(ENTRIES as usize) {
+                for &i in batch {
+                    let buffer = buffers[i].as_mut_slice();
+                    let entry = opcode::Read::new(
+                        fd,
+                        buffer.as_mut_ptr(),
+                        u32::try_from(buffer.len()).unwrap(),
+                    )
+                    .offset(reads[i].start)
+                    .build()
+                    .user_data(i as u64);
+                    // SAFETY: the buffer outlives the read, which completes before returning
+                    unsafe { ring.submission().push(&entry) }
+                        .expect("the submission queue has space for a batch");
+                }
+                // Every completion of the batch is drained (even after a failed read), so none are left in the ring for the next read of the thread
+                let mut completed = 0;
+                while completed < batch.len() {
+                    if let Err(err) = ring.submit_and_wait(batch.len() - completed) {
+                        if err.kind() == std::io::ErrorKind::Interrupted {
+                            continue;
+                        }
+                        // The state of the ring is unknown, so it is recreated by the next read
+                        *ring_cell = None;
+                        return Err(err);
+                    }
+                    for entry in ring.completion() {
+                        completed += 1;
+                        let i = usize::try_from(entry.user_data()).unwrap();
+                        let result = entry.result();
+                        if result < 0 {
+                            error.get_or_insert_with(|| std::io::Error::from_raw_os_error(-result));
+                        } else {
+                            lengths[i] = usize::try_from(result).unwrap();
+                        }
+                    }
+                }
+                if error.is_some() {
+                    break;
+                }
+            }
+            if let Some(err) = error {
+                return Err(err);
+            }
+            Ok::<_, std::io::Error>(())
+        })?;
+        for ((read, buffer), length) in std::iter::zip(std::iter::zip(reads, buffers), &mut lengths)
+        {
+            let buffer = buffer.as_mut_slice();
+            if *length > 0 && *length < buffer.len() {
+                *length += read_at(file, read.start + *length as u64, &mut buffer[*length..])?;
+            }
+        }
+        Ok(lengths)
+    }
+}
+
+impl ReadableStorageTraits for DirectReadStore {
+    fn get(&self, key: &StoreKey) -> Result<MaybeBytes, StorageError> {
+        let Some(file) = self.open(key)? else {
+            return Ok(None);
+        };
+        let size = file.metadata()?.len();
+        let mut bytes = self.read_ranges(&file, std::slice::from_ref(&(0..size)))?;
+        Ok(bytes.pop())
+    }
+
+    fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        let Some(file) = self.open(key)? else {
+            return Ok(None);
+        };
+        let size = file.metadata()?.len();
+        let ranges = byte_ranges
+            .iter()
+            .map(|byte_range| byte_range.to_range(size))
+            .collect::<Vec<_>>();
+        Ok(Some(self.read_ranges(&file, &ranges)?))
+    }
+
+    fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        self.store.size_key(key)
+    }
+}
+
+impl ListableStorageTraits for DirectReadStore {
+    fn list(&self) -> Result<StoreKeys, StorageError> {
+        self.store.list()
+    }
+
+    fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        self.store.list_prefix(prefix)
+    }
+
+    fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        self.store.list_dir(prefix)
+    }
+
+    fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        self.store.size_prefix(prefix)
+    }
+}
+
+impl WritableStorageTraits for DirectReadStore {
+    fn set(&self, key: &StoreKey, value: Bytes) -> Result<(), StorageError> {
+        self.store.set(key, value)
+    }
+
+    fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        self.store.set_partial_values(key_start_values)
+    }
+
+    fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        self.store.erase(key)
+    }
+
+    fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        self.store.erase_values(keys)
+    }
+
+    fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        self.store.erase_prefix(prefix)
+    }
+}
+
+impl ReadableWritableStorageTraits for DirectReadStore {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_read_store() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let key = StoreKey::new("c/0/0")?;
+        let value = (0..10_000u32).map(|i| i as u8).collect::<Vec<u8>>();
+        FilesystemStore::new(path.path())?.set(&key, Bytes::from(value.clone()))?;
+
+        let mut options = vec![
+            DirectReadOptions::default(),
+            DirectReadOptions {
+                direct: cfg!(target_os = "linux"),
+                ..Default::default()
+            },
+            DirectReadOptions {
+                mmap: true,
+                ..Default::default()
+            },
+        ];
+        if cfg!(all(target_os = "linux", feature = "io_uring")) {
+            options.push(DirectReadOptions {
+                direct: true,
+                io_uring: true,
+                ..Default::default()
+            });
+        }
+        for options in options {
+            let store = DirectReadStore::new(path.path(), options)?;
+            assert_eq!(store.get(&key)?.unwrap(), value);
+            assert_eq!(
+                store
+                    .get_partial_values_key(
+                        &key,
+                        &[
+                            ByteRange::FromStart(4090, Some(10)),
+                            ByteRange::FromEnd(0, Some(5)),
+                            ByteRange::FromStart(9000, None),
+                        ],
+                    )?
+                    .unwrap(),
+                vec![
+                    Bytes::copy_from_slice(&value[4090..4100]),
+                    Bytes::copy_from_slice(&value[9995..]),
+                    Bytes::copy_from_slice(&value[9000..]),
+                ]
+            );
+            assert!(store.get(&StoreKey::new("c/0/1")?)?.is_none());
+            assert!(store
+                .get_partial_values_key(&key, &[ByteRange::FromStart(9000, Some(2000))])
+                .is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use zarrs::storage::StorageError;
+
+/// Backend options of a store, e.g. `endpoint`, `region`, or credentials.
+pub type StoreOptions = HashMap<String, String>;
+
+/// A named store profile.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreProfile {
+    /// The base URI of the profile, e.g. `s3://bucket/prefix`.
+    pub uri: String,
+    /// Backend options, e.g. `endpoint`, `region`, or credentials.
+    #[serde(default)]
+    pub options: StoreOptions,
+}
+
+/// Named store profiles, referenced as `profile://name/path`.
+///
+/// Profiles are read from a TOML file, e.g.
+/// ```toml
+/// [profiles.data]
+/// uri = "s3://bucket/prefix"
+/// options = { endpoint = "https://s3.example.com", region = "us-east-1" }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoreProfiles {
+    #[serde(default)]
+    profiles: HashMap<String, StoreProfile>,
+}
+
+/// Returns true if `path` references a store profile (`profile://name/path`).
+pub fn is_profile_uri(path: &str) -> bool {
+    path.starts_with("profile://")
+}
+
+impl StoreProfiles {
+    /// The path of the store profiles file.
+    ///
+    /// This is `$ZARRS_TOOLS_STORES` if set, otherwise `zarrs_tools/stores.toml` in `$XDG_CONFIG_HOME` or `~/.config`.
+    #[must_use]
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("ZARRS_TOOLS_STORES") {
+            return Some(path.into());
+        }
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_dir.join("zarrs_tools").join("stores.toml"))
+    }
+
+    /// Read store profiles from a TOML file.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the file cannot be read or is malformed.
+    pub fn read(path: &Path) -> Result<Self, StorageError> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|err| StorageError::Other(format!("{}: {err}", path.display())))
+    }
+
+    /// Load the store profiles from the file at [`StoreProfiles::path`], or no profiles if it does not exist.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if the file exists but cannot be read or is malformed.
+    pub fn load() -> Result<Self, StorageError> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::read(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Resolve a profile URI (`profile://name/path`) to the URI of `path` relative to the profile URI, and the profile options.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if `uri` is not a profile URI or the profile does not exist.
+    pub fn resolve(&self, uri: &str) -> Result<(String, StoreOptions), StorageError> {
+        let path = uri
+            .strip_prefix("profile://")
+            .ok_or_else(|| StorageError::Other(format!("{uri} is not a profile URI")))?;
+        let (name, path) = path.split_once('/').unwrap_or((path, ""));
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            StorageError::Other(format!("the store profile {name} does not exist"))
+        })?;
+        let path = path.trim_matches('/');
+        let uri = if path.is_empty() {
+            profile.uri.clone()
+        } else {
+            format!("{}/{path}", profile.uri.trim_end_matches('/'))
+        };
+        Ok((uri, profile.options.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        let profiles: StoreProfiles = toml::from_str(
+            r#"
+[profiles.data]
+uri = "s3://bucket/prefix/"
+options = { endpoint = "https://s3.example.com", access_key_id = "key" }
+
+[profiles.web]
+uri = "webdav://example.com/data"
+"#,
+        )
+        .unwrap();
+        let (uri, options) = profiles.resolve("profile://data/array.zarr").unwrap();
+        assert_eq!(uri, "s3://bucket/prefix/array.zarr");
+        assert_eq!(options["endpoint"], "https://s3.example.com");
+        let (uri, options) = profiles.resolve("profile://web").unwrap();
+        assert_eq!(uri, "webdav://example.com/data");
+        assert!(options.is_empty());
+        assert!(profiles.resolve("profile://missing/array.zarr").is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use zarrs::{
+    byte_range::ByteRange,
+    storage::{
+        AsyncBytes, AsyncListableStorageTraits, AsyncReadableStorageTraits,
+        AsyncReadableWritableStorageTraits, AsyncWritableStorageTraits, Bytes,
+        ListableStorageTraits, MaybeAsyncBytes, MaybeBytes, ReadableStorageTraits,
+        ReadableWritableStorageTraits, StorageError, StoreKey, StoreKeyStartValue, StoreKeys,
+        StoreKeysPrefixes, StorePrefix, WritableStorageTraits,
+    },
+};
+
+/// Limits the rate of requests, shared by every store it is passed to.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter allowing `max_requests_per_second`.
+    ///
+    /// # Errors
+    /// Returns a [`StorageError`] if `max_requests_per_second` is not a positive finite number.
+    pub fn new(max_requests_per_second: f64) -> Result<Self, StorageError> {
+        let interval = (max_requests_per_second.is_finite() && max_requests_per_second > 0.0)
+            .then(|| Duration::try_from_secs_f64(1.0 / max_requests_per_second).ok())
+            .flatten()
+            .ok_or_else(|| {
+                StorageError::Other(format!(
+                    "the maximum requests per second must be a positive number, not {max_requests_per_second}"
+                ))
+            })?;
+        Ok(Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Reserve the next request, returning the time until it is allowed.
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap();
+        let start = std::cmp::max(*next, now);
+        *next = start + self.interval;
+        start - now
+    }
+
+    /// Block until the next request is allowed.
+    pub fn wait(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Wait asynchronously until the next request is allowed.
+    pub async fn wait_async(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Retries failed operations of a store with exponential backoff, and optionally limits the rate of requests.
+///
+/// Only errors that may be transient (IO and store errors) are retried.
+/// The adapter implements the sync storage traits for a sync store, and the async storage traits for an async store.
+pub struct RetryStorageAdapter<TStorage: ?Sized> {
+    storage: Arc<TStorage>,
+    retries: usize,
+    backoff: Duration,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<TStorage: ?Sized> RetryStorageAdapter<TStorage> {
+    /// Create a new retry storage adapter.
+    ///
+    /// A failed operation is retried up to `retries` times, waiting `backoff` before the first retry and doubling it for each subsequent retry.
+    #[must_use]
+    pub fn new(
+        storage: Arc<TStorage>,
+        retries: usize,
+        backoff: Duration,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Self {
+        Self {
+            storage,
+            retries,
+            backoff,
+            rate_limiter,
+        }
+    }
+
+    fn retry<T>(
+        &self,
+        operation: impl Fn(&TStorage) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let mut backoff = self.backoff;
+        let mut retry = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.wait();
+            }
+            match operation(&self.storage) {
+                Err(StorageError::IOError(_) | StorageError::Other(_)) if retry < self.retries => {
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn retry_async<'a, T, F>(
+        &'a self,
+        operation: impl Fn(&'a TStorage) -> F,
+    ) -> Result<T, StorageError>
+    where
+        F: Future<Output = Result<T, StorageError>>,
+    {
+        let mut backoff = self.backoff;
+        let mut retry = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.wait_async().await;
+            }
+            match operation(&self.storage).await {
+                Err(StorageError::IOError(_) | StorageError::Other(_)) if retry < self.retries => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<TStorage: ?Sized + ReadableStorageTraits> ReadableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+    fn get(&self, key: &StoreKey) -> Result<MaybeBytes, StorageError> {
+        self.retry(|storage| storage.get(key))
+    }
+
+    fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<Bytes>>, StorageError> {
+        self.retry(|storage| storage.get_partial_values_key(key, byte_ranges))
+    }
+
+    fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        self.retry(|storage| storage.size_key(key))
+    }
+}
+
+impl<TStorage: ?Sized + ListableStorageTraits> ListableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+    fn list(&self) -> Result<StoreKeys, StorageError> {
+        self.retry(|storage| storage.list())
+    }
+
+    fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        self.retry(|storage| storage.list_prefix(prefix))
+    }
+
+    fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        self.retry(|storage| storage.list_dir(prefix))
+    }
+
+    fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        self.retry(|storage| storage.size_prefix(prefix))
+    }
+}
+
+impl<TStorage: ?Sized + ReadableWritableStorageTraits> WritableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+    fn set(&self, key: &StoreKey, value: Bytes) -> Result<(), StorageError> {
+        self.retry(|storage| storage.set(key, value.clone()))
+    }
+
+    fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        self.retry(|storage| storage.set_partial_values(key_start_values))
+    }
+
+    fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        self.retry(|storage| storage.erase(key))
+    }
+
+    fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        self.retry(|storage| storage.erase_values(keys))
+    }
+
+    fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        self.retry(|storage| storage.erase_prefix(prefix))
+    }
+}
+
+impl<TStorage: ?Sized + ReadableWritableStorageTraits> ReadableWritableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncReadableStorageTraits> AsyncReadableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+    async fn get(&self, key: &StoreKey) -> Result<MaybeAsyncBytes, StorageError> {
+        self.retry_async(|storage| storage.get(key)).await
+    }
+
+    async fn get_partial_values_key(
+        &self,
+        key: &StoreKey,
+        byte_ranges: &[ByteRange],
+    ) -> Result<Option<Vec<AsyncBytes>>, StorageError> {
+        self.retry_async(|storage| storage.get_partial_values_key(key, byte_ranges))
+            .await
+    }
+
+    async fn size_key(&self, key: &StoreKey) -> Result<Option<u64>, StorageError> {
+        self.retry_async(|storage| storage.size_key(key)).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncListableStorageTraits> AsyncListableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+    async fn list(&self) -> Result<StoreKeys, StorageError> {
+        self.retry_async(|storage| storage.list()).await
+    }
+
+    async fn list_prefix(&self, prefix: &StorePrefix) -> Result<StoreKeys, StorageError> {
+        self.retry_async(|storage| storage.list_prefix(prefix))
+            .await
+    }
+
+    async fn list_dir(&self, prefix: &StorePrefix) -> Result<StoreKeysPrefixes, StorageError> {
+        self.retry_async(|storage| storage.list_dir(prefix)).await
+    }
+
+    async fn size_prefix(&self, prefix: &StorePrefix) -> Result<u64, StorageError> {
+        self.retry_async(|storage| storage.size_prefix(prefix))
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncReadableWritableStorageTraits> AsyncWritableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+    async fn set(&self, key: &StoreKey, value: AsyncBytes) -> Result<(), StorageError> {
+        self.retry_async(|storage| storage.set(key, value.clone()))
+            .await
+    }
+
+    async fn set_partial_values(
+        &self,
+        key_start_values: &[StoreKeyStartValue],
+    ) -> Result<(), StorageError> {
+        self.retry_async(|storage| storage.set_partial_values(key_start_values))
+            .await
+    }
+
+    async fn erase(&self, key: &StoreKey) -> Result<(), StorageError> {
+        self.retry_async(|storage| storage.erase(key)).await
+    }
+
+    async fn erase_values(&self, keys: &[StoreKey]) -> Result<(), StorageError> {
+        self.retry_async(|storage| storage.erase_values(keys)).await
+    }
+
+    async fn erase_prefix(&self, prefix: &StorePrefix) -> Result<(), StorageError> {
+        self.retry_async(|storage| storage.erase_prefix(prefix))
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<TStorage: ?Sized + AsyncReadableWritableStorageTraits> AsyncReadableWritableStorageTraits
+    for RetryStorageAdapter<TStorage>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A store that fails a number of times before each successful read.
+    struct FlakyStore {
+        failures: usize,
+        attempts: AtomicUsize,
+    }
+
+    impl FlakyStore {
+        fn attempt(&self) -> Result<MaybeBytes, StorageError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                Err(StorageError::Other("503 Service Unavailable".to_string()))
+            } else {
+                Ok(Some(Bytes::from_static(&[1])))
+            }
+        }
+    }
+
+    impl ReadableStorageTraits for FlakyStore {
+        fn get(&self, _key: &StoreKey) -> Result<MaybeBytes, StorageError> {
+            self.attempt()
+        }
+
+        fn get_partial_values_key(
+            &self,
+            _key: &StoreKey,
+            _byte_ranges: &[ByteRange],
+        ) -> Result<Option<Vec<Bytes>>, StorageError> {
+            unimplemented!()
+        }
+
+        fn size_key(&self, _key: &StoreKey) -> Result<Option<u64>, StorageError> {
+            unimplemented!()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AsyncReadableStorageTraits for FlakyStore {
+        async fn get(&self, _key: &StoreKey) -> Result<MaybeAsyncBytes, StorageError> {
+            self.attempt()
+        }
+
+        async fn get_partial_values_key(
+            &self,
+            _key: &StoreKey,
+            _byte_ranges: &[ByteRange],
+        ) -> Result<Option<Vec<AsyncBytes>>, StorageError> {
+            unimplemented!()
+        }
+
+        async fn size_key(&self, _key: &StoreKey) -> Result<Option<u64>, StorageError> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn retry() -> Result<(), Box<dyn std::error::Error>> {
+        let key = StoreKey::new("zarr.json")?;
+        let flaky = |failures| {
+            Arc::new(FlakyStore {
+                failures,
+                attempts: AtomicUsize::new(0),
+            })
+        };
+
+        let storage = RetryStorageAdapter::new(flaky(2), 2, Duration::from_millis(1), None);
+        assert_eq!(
+            ReadableStorageTraits::get(&storage, &key)?,
+            Some(Bytes::from_static(&[1]))
+        );
+
+        let storage = RetryStorageAdapter::new(flaky(3), 2, Duration::from_millis(1), None);
+        assert!(ReadableStorageTraits::get(&storage, &key).is_err());
+
+        // Async operations are retried and rate limited
+        let runtime = tokio::runtime::Runtime::new()?;
+        let rate_limiter = Arc::new(RateLimiter::new(100.0)?);
+        let storage =
+            RetryStorageAdapter::new(flaky(2), 2, Duration::from_millis(1), Some(rate_limiter));
+        let start = Instant::now();
+        assert_eq!(
+            runtime.block_on(AsyncReadableStorageTraits::get(&storage, &key))?,
+            Some(Bytes::from_static(&[1]))
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let storage = RetryStorageAdapter::new(flaky(3), 2, Duration::from_millis(1), None);
+        assert!(runtime
+            .block_on(AsyncReadableStorageTraits::get(&storage, &key))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limiter() -> Result<(), StorageError> {
+        let rate_limiter = RateLimiter::new(100.0)?;
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        for max_requests_per_second in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::MIN_POSITIVE] {
+            assert!(RateLimiter::new(max_requests_per_second).is_err());
+        }
+        Ok(())
+    }
+}
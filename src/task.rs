@@ -0,0 +1,190 @@
+//! Tasks of an array job (e.g. a SLURM job array), which each process a partition of the chunks of an output.
+//!
+//! The chunks of an output are partitioned into `count` contiguous ranges of their linearised indices, so a task is deterministic and the chunks of a task are close together.
+//! Each task records its completed chunks in a checkpoint (e.g. `<output>.task-3-of-8.checkpoint`), which is kept when the task finishes.
+//! Once every task has finished, [`finalize_tasks`] verifies that every chunk has been written and removes the checkpoints.
+
+use std::ops::Range;
+
+use zarrs::array::{ravel_indices, unravel_index};
+
+use crate::checkpoint::Checkpoint;
+
+/// A task of an array job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Task {
+    index: usize,
+    count: usize,
+}
+
+impl Task {
+    /// Create task `index` of `count` tasks.
+    ///
+    /// # Errors
+    /// Returns an error if `index` is not less than `count`.
+    pub fn new(index: usize, count: usize) -> std::io::Result<Self> {
+        if index < count {
+            Ok(Self { index, count })
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("the task index {index} must be less than the task count {count}"),
+            ))
+        }
+    }
+
+    /// Returns the index of the task.
+    #[must_use]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the number of tasks.
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the linearised indices of the chunks of the task, of `num_chunks` chunks.
+    #[must_use]
+    pub fn chunks(&self, num_chunks: u64) -> Range<u64> {
+        let bound = |index: usize| {
+            u64::try_from(u128::from(num_chunks) * index as u128 / self.count as u128).unwrap()
+        };
+        bound(self.index)..bound(self.index + 1)
+    }
+
+    /// Returns true if the chunk at `chunk_indices` of a chunk grid of `chunk_grid_shape` is processed by the task.
+    #[must_use]
+    pub fn contains(&self, chunk_indices: &[u64], chunk_grid_shape: &[u64]) -> bool {
+        let num_chunks = chunk_grid_shape.iter().product();
+        self.chunks(num_chunks)
+            .contains(&ravel_indices(chunk_indices, chunk_grid_shape))
+    }
+}
+
+/// Options for running a task of an array job.
+#[derive(clap::Parser, Debug, Clone, Default)]
+pub struct TaskArgs {
+    /// The index of this task of an array job, from 0 to `--task-count` - 1.
+    ///
+    /// Only the chunks of the outputs assigned to this task are processed, so a run can be spread over the tasks of an array job (e.g. `--task-index $SLURM_ARRAY_TASK_ID` with `--array=0-7`).
+    /// Each output must be written chunk by chunk (e.g. element-wise filters with an output path), and is not erased.
+    #[arg(long, requires = "task_count")]
+    pub task_index: Option<usize>,
+
+    /// The number of tasks of an array job.
+    #[arg(long)]
+    pub task_count: Option<usize>,
+
+    /// Verify that every task of an array job of `--task-count` tasks has written its chunks, then remove their checkpoints.
+    #[arg(long, requires = "task_count", conflicts_with = "task_index")]
+    pub finalize_tasks: bool,
+}
+
+impl TaskArgs {
+    /// Returns the task of this run, or [`None`] if it is not a task of an array job.
+    ///
+    /// # Errors
+    /// Returns an error if the task index is not less than the task count, or the task count is set without a task index or [`finalize_tasks`](TaskArgs::finalize_tasks).
+    pub fn task(&self) -> std::io::Result<Option<Task>> {
+        match (self.task_index, self.task_count) {
+            (Some(index), Some(count)) => Task::new(index, count).map(Some),
+            (None, Some(_)) if !self.finalize_tasks => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "--task-count requires --task-index or --finalize-tasks",
+            )),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Verify that each of `task_count` tasks has written its chunks of `output`, with a chunk grid of `chunk_grid_shape`, then remove their checkpoints.
+///
+/// # Errors
+/// Returns an error listing the incomplete tasks if the checkpoint of a task is missing or does not record one of its chunks, or if a checkpoint cannot be read or removed.
+pub fn finalize_tasks(
+    output: &str,
+    task_count: usize,
+    chunk_grid_shape: &[u64],
+) -> std::io::Result<()> {
+    let num_chunks = chunk_grid_shape.iter().product();
+    let mut checkpoints = Vec::with_capacity(task_count);
+    let mut incomplete = vec![];
+    for index in 0..task_count {
+        let task = Task::new(index, task_count)?;
+        let checkpoint = Checkpoint::resume(&Checkpoint::task_manifest_path(output, &task))?;
+        match checkpoint {
+            Some(checkpoint)
+                if task.chunks(num_chunks).all(|chunk| {
+                    checkpoint.is_complete(&unravel_index(chunk, chunk_grid_shape))
+                }) =>
+            {
+                checkpoints.push(checkpoint);
+            }
+            _ => incomplete.push(index),
+        }
+    }
+    if !incomplete.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "the tasks {incomplete:?} of {task_count} tasks have not written every chunk of {output}"
+        )));
+    }
+    for checkpoint in checkpoints {
+        checkpoint.finish()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_chunks() {
+        let tasks = (0..3)
+            .map(|index| Task::new(index, 3).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(tasks[0].chunks(10), 0..3);
+        assert_eq!(tasks[1].chunks(10), 3..6);
+        assert_eq!(tasks[2].chunks(10), 6..10);
+        assert_eq!(tasks[2].chunks(2), 1..2);
+        assert!(Task::new(3, 3).is_err());
+
+        // Every chunk is processed by exactly one task
+        let chunk_grid_shape = [3, 4];
+        for chunk in 0..12 {
+            let chunk_indices = unravel_index(chunk, &chunk_grid_shape);
+            assert_eq!(
+                tasks
+                    .iter()
+                    .filter(|task| task.contains(&chunk_indices, &chunk_grid_shape))
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn task_finalize() -> Result<(), Box<dyn std::error::Error>> {
+        let path = tempfile::TempDir::new()?;
+        let output = path.path().join("array.zarr");
+        let output = output.to_str().unwrap();
+        let chunk_grid_shape = [2, 2];
+        let tasks = [Task::new(0, 2)?, Task::new(1, 2)?];
+
+        let checkpoint = Checkpoint::create(&Checkpoint::task_manifest_path(output, &tasks[0]))?;
+        checkpoint.complete(&[0, 0])?;
+        checkpoint.complete(&[0, 1])?;
+        assert!(finalize_tasks(output, 2, &chunk_grid_shape).is_err());
+
+        let checkpoint = Checkpoint::create(&Checkpoint::task_manifest_path(output, &tasks[1]))?;
+        checkpoint.complete(&[1, 0])?;
+        assert!(finalize_tasks(output, 2, &chunk_grid_shape).is_err());
+        checkpoint.complete(&[1, 1])?;
+        finalize_tasks(output, 2, &chunk_grid_shape)?;
+        assert!(!Checkpoint::task_manifest_path(output, &tasks[0]).exists());
+        assert!(!Checkpoint::task_manifest_path(output, &tasks[1]).exists());
+        Ok(())
+    }
+}
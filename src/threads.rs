@@ -0,0 +1,135 @@
+//! The threads of the tools, shared by the global rayon thread pool and tokio runtimes.
+//!
+//! [`ThreadArgs::init`] configures the global rayon thread pool, optionally pinning its threads to CPUs.
+//! Afterwards, [`num_threads`] is the target concurrency of the tools and [`tokio_runtime`] creates a runtime with as many worker threads.
+
+/// Options for the threads of a tool.
+#[derive(clap::Parser, Debug, Clone, Default)]
+pub struct ThreadArgs {
+    /// The number of threads.
+    ///
+    /// Defaults to the RAYON_NUM_THREADS environment variable or the number of logical CPUs.
+    /// This is the number of threads processing chunks, the number of worker threads of async stores, and the default concurrency.
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// Pin each thread processing chunks to a CPU (Linux only).
+    ///
+    /// The threads are assigned to the CPUs available to the process in turn.
+    #[arg(long)]
+    pub pin_threads: bool,
+}
+
+impl ThreadArgs {
+    /// Build the global rayon thread pool.
+    ///
+    /// This must be called before any parallel work, since the global thread pool is otherwise built with the default number of threads.
+    ///
+    /// # Errors
+    /// Returns an error if the global thread pool has already been built, or the threads cannot be pinned on this platform.
+    pub fn init(&self) -> std::io::Result<()> {
+        let mut builder =
+            rayon::ThreadPoolBuilder::new().num_threads(self.threads.unwrap_or_default());
+        if self.pin_threads {
+            let cpus = affinity::available_cpus()?;
+            builder = builder.start_handler(move |index| {
+                if let Err(err) = affinity::pin(cpus[index % cpus.len()]) {
+                    eprintln!("Failed to pin thread {index}: {err}");
+                }
+            });
+        }
+        builder.build_global().map_err(std::io::Error::other)
+    }
+}
+
+/// Returns the number of threads of the global rayon thread pool.
+#[must_use]
+pub fn num_threads() -> usize {
+    rayon::current_num_threads()
+}
+
+/// Create a multi-threaded tokio runtime with [`num_threads`] worker threads.
+///
+/// # Errors
+/// Returns an error if the runtime cannot be created.
+pub fn tokio_runtime() -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(num_threads())
+        .enable_all()
+        .build()
+}
+
+#[cfg(target_os = "linux")]
+mod affinity {
+    /// Returns the CPUs that the process can run on.
+    pub fn available_cpus() -> std::io::Result<Vec<usize>> {
+        // SAFETY: the CPU set is zeroed and sized for sched_getaffinity
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok((0..libc::CPU_SETSIZE as usize)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect())
+        }
+    }
+
+    /// Pin the calling thread to `cpu`.
+    pub fn pin(cpu: usize) -> std::io::Result<()> {
+        // SAFETY: the CPU set is zeroed and sized for sched_setaffinity
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(cpu, &mut set);
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod affinity {
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "pinning threads is only supported on Linux",
+        )
+    }
+
+    pub fn available_cpus() -> std::io::Result<Vec<usize>> {
+        Err(unsupported())
+    }
+
+    pub fn pin(_cpu: usize) -> std::io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pin_threads() -> std::io::Result<()> {
+        let cpus = affinity::available_cpus()?;
+        assert!(!cpus.is_empty());
+        let cpu = cpus[0];
+        std::thread::spawn(move || {
+            affinity::pin(cpu)?;
+            assert_eq!(affinity::available_cpus()?, vec![cpu]);
+            Ok(())
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn tokio_runtime_threads() -> std::io::Result<()> {
+        let runtime = tokio_runtime()?;
+        assert_eq!(runtime.metrics().num_workers(), num_threads());
+        Ok(())
+    }
+}